@@ -0,0 +1,23 @@
+use home_automation_common::protobuf::client_api_command::CommandType;
+
+/// Returns the name of the command for logs and statistics.
+pub fn command_name(command: Option<&CommandType>) -> &'static str {
+    match command {
+        Some(CommandType::Query(_)) => "Query",
+        Some(CommandType::Action(_)) => "Action",
+        Some(CommandType::ExportConfiguration(_)) => "ExportConfiguration",
+        Some(CommandType::ImportConfiguration(_)) => "ImportConfiguration",
+        Some(CommandType::DryRun(_)) => "DryRun",
+        Some(CommandType::Admin(_)) => "Admin",
+        Some(CommandType::SetTags(_)) => "SetTags",
+        Some(CommandType::TaggedAction(_)) => "TaggedAction",
+        Some(CommandType::Hello(_)) => "Hello",
+        Some(CommandType::Tombstones(_)) => "Tombstones",
+        Some(CommandType::NextChunk(_)) => "NextChunk",
+        Some(CommandType::CaptureSnapshot(_)) => "CaptureSnapshot",
+        Some(CommandType::RestoreSnapshot(_)) => "RestoreSnapshot",
+        Some(CommandType::Rules(_)) => "Rules",
+        Some(CommandType::Ghosts(_)) => "Ghosts",
+        None => "Missing",
+    }
+}
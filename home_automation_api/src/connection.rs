@@ -0,0 +1,227 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use home_automation_common::{
+    chunk::ChunkAssembler,
+    load_env,
+    protobuf::{ClientApiCommand, PayloadEnvelope, ResponseChunk, ResponseCode, Welcome},
+    zmq_sockets::{self, markers::Linked, Context, Requester},
+    ErrorKind, ErrorKindExt as _, ShutdownToken, ENV_CLIENT_API_ENDPOINT,
+};
+
+/// Time the controller has to answer a request that does not wait for a change.
+pub const MESSAGE_EXCHANGE_TIMEOUT: Duration = Duration::from_millis(800);
+/// Interval in which a long poll checks whether the client shuts down.
+const LONG_POLL_SHUTDOWN_CHECK: Duration = Duration::from_millis(100);
+/// Number of consecutive unanswered requests after which the controller is considered offline.
+const OFFLINE_THRESHOLD: u32 = 3;
+/// Larger responses are split into chunks by the controller and reassembled.
+pub const MAX_RESPONSE_SIZE: u32 = 64 * 1024;
+
+/// REQ socket to the client API of the controller that survives controller restarts.
+///
+/// A REQ socket whose request was never answered refuses to send further requests, so the
+/// socket is recreated after every timeout (lazy pirate pattern). Once multiple requests in a row
+/// timed out, the connection is reported as offline until the controller answers again.
+#[derive(Debug)]
+pub struct ControllerConnection {
+    context: Context,
+    endpoint: String,
+    requester: Requester<Linked>,
+    consecutive_timeouts: u32,
+    online: Arc<AtomicBool>,
+}
+
+impl ControllerConnection {
+    pub fn new(context: &Context) -> Result<Self> {
+        let endpoint = load_env(ENV_CLIENT_API_ENDPOINT)?;
+        Ok(Self {
+            requester: Self::connect(context, &endpoint)?,
+            context: context.clone(),
+            endpoint,
+            consecutive_timeouts: 0,
+            online: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    fn connect(context: &Context, endpoint: &str) -> Result<Requester<Linked>> {
+        let mut requester = Requester::new(context)?.connect(endpoint)?;
+        requester.set_message_exchange_timeout(Some(MESSAGE_EXCHANGE_TIMEOUT))?;
+        requester.discard_pending_on_close()?;
+        Ok(requester)
+    }
+
+    /// Returns whether the controller answered recently.
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::SeqCst)
+    }
+
+    /// Flag whether the controller answered recently, for threads without the connection.
+    pub fn online(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.online)
+    }
+
+    /// Announces the protocol version of the client and returns the version of the controller.
+    ///
+    /// Controllers that predate the handshake reply with an error code instead of a [`Welcome`]
+    /// and are treated as [legacy][Welcome::legacy]. An unreachable controller is assumed to be
+    /// up to date.
+    pub fn handshake(&mut self) -> Result<Welcome> {
+        match self.request::<_, Welcome>(ClientApiCommand::hello()) {
+            Ok(welcome) => {
+                tracing::info!(
+                    ?welcome,
+                    "Controller uses protocol version {}",
+                    welcome.protocol_version
+                );
+                Ok(welcome)
+            }
+            Err(e) if e.error_kind() == Some(ErrorKind::Decode) => {
+                tracing::warn!("Controller does not support the handshake: {e:#}");
+                Ok(Welcome::legacy())
+            }
+            Err(e) if e.is_timeout() => {
+                tracing::warn!("Controller did not answer the handshake, assuming current version");
+                Ok(Welcome::current())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sends the request and blocks until the reply is received or the request timed out.
+    pub fn request<Req, Resp>(&mut self, request: Req) -> Result<Resp>
+    where
+        Req: Into<ClientApiCommand>,
+        Resp: prost::Message + prost::Name + Default,
+    {
+        let request = ClientApiCommand {
+            max_response_size: MAX_RESPONSE_SIZE,
+            ..request.into()
+        };
+        let result = self
+            .requester
+            .send(request)
+            .and_then(|()| self.requester.receive_envelope());
+        let reply = self.finish_request(result)?;
+        self.reassemble(reply)
+    }
+
+    /// Like [`request`][Self::request], but waits up to `timeout` for the reply.
+    ///
+    /// Returns `None` without waiting for the reply once the shutdown is requested.
+    pub fn request_until<Req, Resp>(
+        &mut self,
+        request: Req,
+        timeout: Duration,
+        shutdown: &ShutdownToken,
+    ) -> Result<Option<Resp>>
+    where
+        Req: Into<ClientApiCommand>,
+        Resp: prost::Message + prost::Name + Default,
+    {
+        let request = ClientApiCommand {
+            max_response_size: MAX_RESPONSE_SIZE,
+            ..request.into()
+        };
+        self.requester.send(request)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if shutdown.is_requested() {
+                // the REQ socket still expects the reply and cannot be reused
+                self.requester = Self::connect(&self.context, &self.endpoint)?;
+                return Ok(None);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                let timeout = home_automation_common::Error::Timeout {
+                    context: format!("No reply within {timeout:?}"),
+                };
+                return self.finish_request(Err(timeout)).map(Some);
+            }
+            let readable = {
+                let mut items = [self.requester.poll_item()];
+                zmq_sockets::poll(&mut items, Some(remaining.min(LONG_POLL_SHUTDOWN_CHECK)))?;
+                items[0].is_readable()
+            };
+            if readable {
+                let result = self.requester.receive_envelope();
+                let reply = self.finish_request(result)?;
+                return self.reassemble(reply).map(Some);
+            }
+        }
+    }
+
+    /// Unpacks the reply, after requesting the remaining chunks if the response was split.
+    fn reassemble<Resp>(&mut self, reply: PayloadEnvelope) -> Result<Resp>
+    where
+        Resp: prost::Message + prost::Name + Default,
+    {
+        if !reply.contains::<ResponseChunk>() {
+            return Ok(unpack(reply)?);
+        }
+
+        let mut assembler = ChunkAssembler::default();
+        assembler.push(unpack(reply)?)?;
+        while let Some((response_id, sequence)) = assembler.next_chunk() {
+            tracing::debug!(response_id, sequence, "Requesting next chunk of response");
+            let result = self
+                .requester
+                .send(ClientApiCommand::next_chunk(response_id, sequence))
+                .and_then(|()| self.requester.receive_envelope());
+            let reply = self.finish_request(result)?;
+            if reply.contains::<ResponseCode>() {
+                let response: ResponseCode = unpack(reply)?;
+                anyhow::bail!("Controller rejected chunk request: {}", response.message);
+            }
+            assembler.push(unpack(reply)?)?;
+        }
+        Ok(unpack(assembler.finish()?)?)
+    }
+
+    /// Updates the online state and recreates the socket if the request timed out.
+    fn finish_request<Resp>(
+        &mut self,
+        result: home_automation_common::Result<Resp>,
+    ) -> Result<Resp> {
+        match result {
+            Ok(response) => {
+                if !self.online.swap(true, Ordering::SeqCst) {
+                    tracing::info!("Controller is online again");
+                }
+                self.consecutive_timeouts = 0;
+                Ok(response)
+            }
+            Err(e) if e.is_timeout() => {
+                self.consecutive_timeouts += 1;
+                tracing::warn!(
+                    consecutive_timeouts = self.consecutive_timeouts,
+                    "Request timed out, recreating socket"
+                );
+                if self.consecutive_timeouts >= OFFLINE_THRESHOLD
+                    && self.online.swap(false, Ordering::SeqCst)
+                {
+                    tracing::warn!("Controller is offline, reconnecting until it answers");
+                }
+                self.requester = Self::connect(&self.context, &self.endpoint)?;
+                Err(e.into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Unpacks the reply, a decode error like the ones of the sockets if it has another type.
+fn unpack<M>(reply: PayloadEnvelope) -> home_automation_common::Result<M>
+where
+    M: prost::Name + Default,
+{
+    reply
+        .unpack()
+        .map_err(|e| home_automation_common::Error::decode("Failed to unpack reply", e))
+}
@@ -0,0 +1,34 @@
+//! Client API of the controller for Rust tools.
+//!
+//! The messages are generated in `home_automation_protocol` together with their builders, e.g.
+//! [`ClientApiCommand::system_state_query`][protobuf::ClientApiCommand::system_state_query], and
+//! re-exported here. [`ControllerConnection`] sends them to the controller and survives its
+//! restarts, [`entities`] parses the reply of a state query.
+//!
+//! ```no_run
+//! use home_automation_api::{
+//!     protobuf::{ClientApiCommand, SystemState},
+//!     ControllerConnection,
+//! };
+//!
+//! let context = home_automation_api::Context::new();
+//! let mut connection = ControllerConnection::new(&context)?;
+//! let welcome = connection.handshake()?;
+//! let state: SystemState = connection.request(ClientApiCommand::system_state_query())?;
+//! for (name, state) in home_automation_api::entities(state) {
+//!     println!("{name}: {state:?} (protocol version {})", welcome.protocol_version);
+//! }
+//! # anyhow::Ok(())
+//! ```
+
+mod command;
+mod connection;
+mod system_state;
+
+pub use command::command_name;
+pub use connection::{ControllerConnection, MAX_RESPONSE_SIZE, MESSAGE_EXCHANGE_TIMEOUT};
+pub use home_automation_common::{
+    features, protobuf, zmq_sockets::Context, EntityState, ENV_ADMIN_TOKEN,
+    ENV_CLIENT_API_ENDPOINT, PROTOCOL_VERSION,
+};
+pub use system_state::{entities, SystemStateBuilder, MAX_WAIT_FOR_CHANGE};
@@ -0,0 +1,88 @@
+use std::{collections::HashMap, time::Duration};
+
+use home_automation_common::{
+    protobuf::{entity_discovery_command::EntityType, EntityMetadata, SystemState},
+    EntityState,
+};
+
+/// Upper bound the controller applies to the wait timeout of a state query.
+pub const MAX_WAIT_FOR_CHANGE: Duration = Duration::from_secs(30);
+
+/// Collects the state of the entities into the reply of a state query.
+#[derive(Debug, Default)]
+pub struct SystemStateBuilder {
+    state: SystemState,
+}
+
+impl SystemStateBuilder {
+    pub fn new(generation: u64) -> Self {
+        Self {
+            state: SystemState {
+                generation,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Adds the entity, an unknown publish time (`0`) is omitted.
+    pub fn add(&mut self, name: &str, state: &EntityState, published_at_ms: u64) {
+        let name = name.to_owned();
+        if published_at_ms != 0 {
+            self.state
+                .published_at_ms
+                .insert(name.clone(), published_at_ms);
+        }
+        match state {
+            EntityState::Sensor(measurement) => {
+                self.state.sensors.insert(name, measurement.clone());
+            }
+            EntityState::Actuator(state) => {
+                self.state.actuators.insert(name, state.clone());
+            }
+            EntityState::New(EntityType::Sensor) => self.state.new_sensors.push(name),
+            EntityState::New(EntityType::Actuator) => self.state.new_actuators.push(name),
+        }
+    }
+
+    /// Adds the time since the last heartbeat of the entity.
+    pub fn heartbeat(&mut self, name: &str, age: Duration) {
+        self.state
+            .heartbeat_age_seconds
+            .insert(name.to_owned(), age.as_secs_f32());
+    }
+
+    /// Adds the rooms, tags and back-channel status of the entity.
+    pub fn metadata(&mut self, name: &str, metadata: EntityMetadata) {
+        self.state.metadata.insert(name.to_owned(), metadata);
+    }
+
+    pub fn build(self) -> SystemState {
+        self.state
+    }
+}
+
+/// State of every entity in the reply, entities that did not publish yet are
+/// [`EntityState::New`].
+pub fn entities(state: SystemState) -> HashMap<String, EntityState> {
+    let sensors = state
+        .sensors
+        .into_iter()
+        .map(|(name, measurement)| (name, EntityState::Sensor(measurement)));
+    let actuators = state
+        .actuators
+        .into_iter()
+        .map(|(name, state)| (name, EntityState::Actuator(state)));
+    let new_sensors = state
+        .new_sensors
+        .into_iter()
+        .map(|name| (name, EntityState::New(EntityType::Sensor)));
+    let new_actuators = state
+        .new_actuators
+        .into_iter()
+        .map(|name| (name, EntityState::New(EntityType::Actuator)));
+    sensors
+        .chain(actuators)
+        .chain(new_sensors)
+        .chain(new_actuators)
+        .collect()
+}
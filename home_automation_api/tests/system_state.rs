@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use home_automation_api::{
+    entities,
+    protobuf::{
+        entity_discovery_command::EntityType, tombstone::LastState, ActuatorState, EntityMetadata,
+        SensorMeasurement,
+    },
+    EntityState, SystemStateBuilder,
+};
+
+#[test]
+fn parses_the_built_state() {
+    let mut builder = SystemStateBuilder::new(4);
+    builder.add(
+        "sen_a",
+        &EntityState::Sensor(SensorMeasurement::default()),
+        1_700_000_000_000,
+    );
+    builder.add(
+        "act_b",
+        &EntityState::Actuator(ActuatorState::light(5.0)),
+        0,
+    );
+    builder.add("sen_c", &EntityState::New(EntityType::Sensor), 0);
+    builder.heartbeat("act_b", Duration::from_millis(2500));
+    builder.metadata(
+        "act_b",
+        EntityMetadata {
+            rooms: vec!["kitchen".to_owned()],
+            tags: vec![],
+            back_channel_healthy: true,
+            heartbeat_interval_ms: 30_000,
+            description: None,
+        },
+    );
+    let state = builder.build();
+
+    assert_eq!(state.generation, 4);
+    assert_eq!(state.published_at_ms.len(), 1);
+    assert_eq!(state.heartbeat_age_seconds["act_b"], 2.5);
+    assert_eq!(state.metadata["act_b"].rooms, ["kitchen"]);
+    assert_eq!(state.metadata["act_b"].heartbeat_interval_ms, 30_000);
+    assert_eq!(state.new_sensors, ["sen_c"]);
+
+    let entities = entities(state);
+    assert_eq!(entities.len(), 3);
+    assert!(matches!(entities["sen_a"], EntityState::Sensor(_)));
+    assert!(matches!(
+        &entities["act_b"],
+        EntityState::Actuator(state) if *state == ActuatorState::light(5.0)
+    ));
+    assert_eq!(entities["sen_c"].entity_type(), EntityType::Sensor);
+}
+
+#[test]
+fn converts_the_published_state() {
+    let published =
+        |state: EntityState| state.published(LastState::Measurement, LastState::ActuatorState);
+
+    assert_eq!(
+        published(EntityState::Actuator(ActuatorState::light(5.0))),
+        Some(LastState::ActuatorState(ActuatorState::light(5.0)))
+    );
+    assert_eq!(
+        published(EntityState::Sensor(SensorMeasurement::default())),
+        Some(LastState::Measurement(SensorMeasurement::default()))
+    );
+    assert_eq!(published(EntityState::New(EntityType::Actuator)), None);
+}
@@ -1,43 +1,98 @@
-use std::time::Duration;
-
-use anyhow::{Context, Result};
+use anyhow::Result;
+use home_automation_api::ControllerConnection;
 use home_automation_common::{
-    load_env, zmq_sockets, OpenTelemetryConfiguration, ENV_CLIENT_API_ENDPOINT,
+    doctor::{self, Report},
+    encryption::PayloadCipher,
+    load_env,
+    log_file::{LogFileConfiguration, RotatingLogFile},
+    signing::CommandSigner,
+    zmq_sockets, OpenTelemetryConfiguration, ShutdownToken, STATISTICS_LOG_INTERVAL,
 };
 
-use crate::{network::SystemStateRefresher, ui::BackgroundTaskState};
+use crate::{
+    mqtt_bridge::MqttBridge,
+    network::{
+        offline::{self, OfflineSystem},
+        SystemStateRefresher,
+    },
+    ui::BackgroundTaskState,
+};
 
+mod mqtt_bridge;
 mod network;
+mod rules;
+mod snapshot;
 mod ui;
 mod utility;
 
+/// Optional directory of the log files, the working directory by default.
+const ENV_CLIENT_LOG_DIR: &str = "HOME_AUTOMATION_CLIENT_LOG_DIR";
+/// Optional name of the log file, `{time}` is replaced by the start time of the client.
+const ENV_CLIENT_LOG_FILE: &str = "HOME_AUTOMATION_CLIENT_LOG_FILE";
+/// Optional number of log files that are kept, including the rotated ones.
+const ENV_CLIENT_LOG_MAX_FILES: &str = "HOME_AUTOMATION_CLIENT_LOG_MAX_FILES";
+/// Optional size in MiB after which the log file is rotated, `0` disables the rotation.
+const ENV_CLIENT_LOG_MAX_SIZE_MIB: &str = "HOME_AUTOMATION_CLIENT_LOG_MAX_SIZE_MIB";
+
 fn main() -> Result<()> {
-    let log_file = create_log_file()?;
-    let _config = OpenTelemetryConfiguration::with_writer("client", log_file)?;
+    if doctor::requested() {
+        return run_doctor();
+    }
+    if let Some(command) = snapshot::requested() {
+        return snapshot::run(command?);
+    }
+    if let Some(command) = rules::requested() {
+        return rules::run(command?);
+    }
+    let log_configuration = log_file_configuration()?;
+    let log_file = RotatingLogFile::create(&log_configuration)?;
+    let _config =
+        OpenTelemetryConfiguration::with_writer("client", std::sync::Mutex::new(log_file))?;
+    log_configuration.remove_old_files();
+    // fails on an invalid key instead of on the first message
+    PayloadCipher::global()?;
     let context = zmq_sockets::Context::new();
     let result = tracing::info_span!("main").in_scope(|| {
         tracing::info!("Starting client");
-        let (sender, receiver) = std::sync::mpsc::channel();
-        let refresher = SystemStateRefresher::new(&context, sender)?;
-        let mut requester =
-            zmq_sockets::Requester::new(&context)?.connect(&load_env(ENV_CLIENT_API_ENDPOINT)?)?;
-        requester.set_message_exchange_timeout(Some(Duration::from_millis(800)))?;
-
-        let handle = refresher.run()?;
+        let shutdown = ShutdownToken::new();
+        // the UI reads CTRL-C as key, but a redirected client can only be stopped by a signal.
+        // The client has nothing to reload, so the other signals only log the socket statistics.
+        let _ = home_automation_common::install_signal_handler(context.clone(), shutdown.clone())?;
+        if mqtt_bridge::requested() {
+            return MqttBridge::new(&context, shutdown)?.run();
+        }
+        let (sender, events) = std::sync::mpsc::channel();
+        let (network, commands, connection, controller) = if offline::requested() {
+            tracing::info!("Simulating the system instead of connecting to the controller");
+            let (network, commands) = OfflineSystem::new(sender, shutdown.clone()).run();
+            (network, commands, None, offline::welcome())
+        } else {
+            let refresher = SystemStateRefresher::new(&context, sender, shutdown.clone())?;
+            let mut connection = ControllerConnection::new(&context)?;
+            let controller = connection.handshake()?;
+            let (network, commands) = refresher.run();
+            (network, commands, Some(connection), controller)
+        };
+        let statistics = std::thread::spawn({
+            let shutdown = shutdown.clone();
+            move || zmq_sockets::log_statistics_periodically(STATISTICS_LOG_INTERVAL, &shutdown)
+        });
 
         let result = ui::run(BackgroundTaskState {
-            refresher: &refresher,
-            receiver,
-            requester,
+            events,
+            commands,
+            connection,
+            controller,
+            command_signer: CommandSigner::from_env()?,
+            shutdown: shutdown.clone(),
         });
 
-        tracing::debug!("Unparking refresher thread");
-        handle.thread().unpark();
-
-        handle
+        // also stop the background threads if the UI failed
+        shutdown.request();
+        network.join()?;
+        statistics
             .join()
-            .map_err(|e| anyhow::anyhow!("Refresher task panicked: {e:?}"))?
-            .context("Refresher task failed")?;
+            .map_err(|e| anyhow::anyhow!("Statistics task panicked: {e:?}"))?;
 
         tracing::debug!("All threads finished");
         result
@@ -49,15 +104,66 @@ fn main() -> Result<()> {
     result
 }
 
-fn create_log_file() -> Result<std::fs::File> {
-    use time::format_description::well_known::Iso8601;
-    let time = time::OffsetDateTime::now_utc()
-        .format(&Iso8601::DEFAULT)
-        .context("Failed to format timestamp")?;
-    let log_file_name = format!("client-{time}.log");
-    std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_file_name)
-        .with_context(|| anyhow::anyhow!("Failed to open log file: {log_file_name}"))
+fn log_file_configuration() -> Result<LogFileConfiguration> {
+    fn parse_env<T: std::str::FromStr>(var: &str) -> Result<Option<T>>
+    where
+        T::Err: std::fmt::Display,
+    {
+        let Ok(value) = load_env(var) else {
+            return Ok(None);
+        };
+        let parsed = value
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid {var} {value:?}: {e}"))?;
+        Ok(Some(parsed))
+    }
+
+    let mut configuration = LogFileConfiguration::new("client");
+    if let Ok(directory) = load_env(ENV_CLIENT_LOG_DIR) {
+        configuration.directory = directory.into();
+    }
+    if let Ok(template) = load_env(ENV_CLIENT_LOG_FILE) {
+        configuration.template = template;
+    }
+    if let Some(max_files) = parse_env(ENV_CLIENT_LOG_MAX_FILES)? {
+        configuration.max_files = max_files;
+    }
+    if let Some(max_size_mib) = parse_env::<u64>(ENV_CLIENT_LOG_MAX_SIZE_MIB)? {
+        configuration.max_size = (max_size_mib > 0).then(|| max_size_mib * 1024 * 1024);
+    }
+    Ok(configuration)
+}
+
+/// Checks the environment of the client instead of starting it.
+fn run_doctor() -> Result<()> {
+    use home_automation_common::{
+        ENV_ADMIN_TOKEN, ENV_CLIENT_API_ENDPOINT, ENV_DATA_STREAM_ENDPOINT, ENV_LAST_VALUE_ENDPOINT,
+    };
+    let mut report = Report::new("home_automation_client");
+    report.env_var(ENV_CLIENT_API_ENDPOINT, true);
+    for var in [
+        ENV_ADMIN_TOKEN,
+        ENV_DATA_STREAM_ENDPOINT,
+        ENV_LAST_VALUE_ENDPOINT,
+        mqtt_bridge::ENV_MQTT_BROKER,
+        mqtt_bridge::ENV_MQTT_TOPIC_PREFIX,
+        mqtt_bridge::ENV_MQTT_USERNAME,
+        ENV_CLIENT_LOG_DIR,
+        ENV_CLIENT_LOG_FILE,
+        ENV_CLIENT_LOG_MAX_FILES,
+        ENV_CLIENT_LOG_MAX_SIZE_MIB,
+    ] {
+        report.env_var(var, false);
+    }
+    ui::doctor(&mut report);
+    report.add_result(
+        "log file",
+        log_file_configuration().map(|c| format!("writing to {}", c.directory.display())),
+    );
+    report.connect_probe(ENV_CLIENT_API_ENDPOINT);
+    report.payload_encryption();
+    report.command_signing(true, false);
+    report.zipkin();
+    report.protocol_handshake(ENV_CLIENT_API_ENDPOINT);
+    report.finish()
 }
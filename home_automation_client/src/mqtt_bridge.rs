@@ -0,0 +1,285 @@
+//! `mqtt-bridge` subcommand that connects the system to an MQTT broker instead of starting the
+//! UI, e.g. for Home Assistant.
+//!
+//! The bridge subscribes to the data stream of the controller (see [`ENV_DATA_STREAM_ENDPOINT`])
+//! and publishes every state retained as JSON on `<prefix>/measurement/<name>` or
+//! `<prefix>/actuator_state/<name>`. Commands like `{ "brightness": 50.0 }` published on
+//! `<prefix>/command/<name>` are sent to the entity via the client API.
+//!
+//! If the controller serves its cached last values (see [`ENV_LAST_VALUE_ENDPOINT`]), the bridge
+//! mirrors them whenever it connects to the broker.
+
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+use home_automation_api::{
+    protobuf::{
+        actuator_state, publish_data, response_code::Code, sensor_measurement, ActuatorState,
+        ClientApiCommand, LastValueQuery, LastValueSnapshot, NamedEntityState, PayloadEnvelope,
+        PublishData, ResponseCode, SensorMeasurement,
+    },
+    ControllerConnection,
+};
+use home_automation_common::{
+    encryption,
+    envelope::Headers,
+    load_env,
+    mqtt::{Connect, MqttClient, Publish},
+    signing::CommandSigner,
+    topic_entity_type, topic_prefix,
+    transport::{Channel, Pattern, Transport as _, ZmqTransport, TOPIC_HEADER},
+    zmq_sockets::Context,
+    ErrorKindExt as _, ShutdownToken, UpdateFrequency, ENV_DATA_STREAM_ENDPOINT,
+    ENV_LAST_VALUE_ENDPOINT,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+/// First argument that selects the subcommand.
+pub const MQTT_BRIDGE_COMMAND: &str = "mqtt-bridge";
+/// Address of the MQTT broker, e.g. `127.0.0.1:1883`.
+pub const ENV_MQTT_BROKER: &str = "HOME_AUTOMATION_MQTT_BROKER";
+/// Optional first level of the MQTT topics, `home_automation` by default.
+pub const ENV_MQTT_TOPIC_PREFIX: &str = "HOME_AUTOMATION_MQTT_TOPIC_PREFIX";
+/// Optional user name for the broker.
+pub const ENV_MQTT_USERNAME: &str = "HOME_AUTOMATION_MQTT_USERNAME";
+/// Optional password for the broker.
+pub const ENV_MQTT_PASSWORD: &str = "HOME_AUTOMATION_MQTT_PASSWORD";
+
+const DEFAULT_TOPIC_PREFIX: &str = "home_automation";
+const CLIENT_ID: &str = "home_automation_bridge";
+const COMMAND_CLASS: &str = "command";
+const KEEP_ALIVE_SECS: u16 = 30;
+/// Time waited for entity data and for commands in turn while both are idle.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Time waited for commands while entity data arrives, so the data stream is not throttled.
+const BUSY_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const LAST_VALUE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Returns whether the client was started with [`MQTT_BRIDGE_COMMAND`].
+pub fn requested() -> bool {
+    std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == MQTT_BRIDGE_COMMAND)
+}
+
+/// Payload of a command, e.g. `{ "air_conditioning": true }`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Command {
+    Brightness(f32),
+    AirConditioning(bool),
+    /// In Hz.
+    UpdateFrequency(f32),
+}
+
+impl Command {
+    fn entity_state(self, name: &str) -> Result<NamedEntityState> {
+        Ok(match self {
+            Self::Brightness(brightness) => {
+                NamedEntityState::actuator(name, ActuatorState::light(brightness))
+            }
+            Self::AirConditioning(on) => {
+                NamedEntityState::actuator(name, ActuatorState::air_conditioning(on))
+            }
+            Self::UpdateFrequency(hz) => {
+                NamedEntityState::frequency(name, UpdateFrequency::from_hz(hz)?)
+            }
+        })
+    }
+}
+
+/// Forwards the entity data to the broker and the commands of the broker to the controller.
+pub struct MqttBridge {
+    broker: String,
+    prefix: String,
+    connect: Connect,
+    data_stream: Box<dyn Channel>,
+    context: Context,
+    last_value_endpoint: Option<String>,
+    connection: ControllerConnection,
+    command_signer: Option<CommandSigner>,
+    shutdown: ShutdownToken,
+}
+
+impl MqttBridge {
+    pub fn new(context: &Context, shutdown: ShutdownToken) -> Result<Self> {
+        let data_stream = ZmqTransport::new(context.clone())
+            .connect(Pattern::Subscribe, &load_env(ENV_DATA_STREAM_ENDPOINT)?)?;
+        Ok(Self {
+            broker: load_env(ENV_MQTT_BROKER)?,
+            prefix: std::env::var(ENV_MQTT_TOPIC_PREFIX)
+                .map(|prefix| prefix.trim_end_matches('/').to_owned())
+                .unwrap_or_else(|_| DEFAULT_TOPIC_PREFIX.to_owned()),
+            connect: Connect {
+                client_id: CLIENT_ID.to_owned(),
+                username: std::env::var(ENV_MQTT_USERNAME).ok(),
+                password: std::env::var(ENV_MQTT_PASSWORD).ok(),
+                keep_alive_secs: KEEP_ALIVE_SECS,
+            },
+            data_stream,
+            context: context.clone(),
+            last_value_endpoint: std::env::var(ENV_LAST_VALUE_ENDPOINT).ok(),
+            connection: ControllerConnection::new(context)?,
+            command_signer: CommandSigner::from_env()?,
+            shutdown,
+        })
+    }
+
+    /// Bridges until the shutdown and connects to the broker again whenever the connection failed.
+    pub fn run(mut self) -> Result<()> {
+        tracing::info!(broker = self.broker, "Starting MQTT bridge");
+        while !self.shutdown.is_requested() {
+            match self.bridge() {
+                Ok(()) => {}
+                Err(e) if e.is_termination() => break,
+                Err(e) => {
+                    tracing::error!(
+                        "MQTT bridge failed, reconnecting in {RECONNECT_DELAY:?}: {e:#}"
+                    );
+                    self.shutdown.wait_timeout(RECONNECT_DELAY);
+                }
+            }
+        }
+        tracing::info!("Shutdown of MQTT bridge");
+        Ok(())
+    }
+
+    /// Forwards in both directions over a single connection to the broker.
+    fn bridge(&mut self) -> Result<()> {
+        let mut mqtt = MqttClient::connect(&self.broker, self.connect.clone())?;
+        mqtt.subscribe(&format!("{}/{COMMAND_CLASS}/+", self.prefix))?;
+        tracing::info!("Connected to MQTT broker {}", self.broker);
+        if let Err(e) = self.mirror_last_values(&mut mqtt) {
+            tracing::warn!("Failed to mirror the last values of the controller: {e:#}");
+        }
+        while !self.shutdown.is_requested() {
+            let mut timeout = POLL_INTERVAL;
+            match self
+                .data_stream
+                .receive_message::<PublishData>(Some(POLL_INTERVAL))
+            {
+                Ok(Some((data, headers))) => {
+                    self.mirror(&mut mqtt, data, &headers)?;
+                    timeout = BUSY_POLL_INTERVAL;
+                }
+                Ok(None) => {}
+                Err(e) if e.is_termination() => return Err(e),
+                Err(e) => tracing::error!("Failed to receive entity data: {e:#}"),
+            }
+            if let Some(command) = mqtt.receive(timeout)? {
+                match self.forward(&command) {
+                    Ok(()) => {}
+                    Err(e) if e.is_termination() => return Err(e),
+                    Err(e) => tracing::warn!(
+                        topic = command.topic,
+                        "Failed to forward MQTT command: {e:#}"
+                    ),
+                }
+            }
+        }
+        mqtt.disconnect()
+    }
+
+    /// Publishes the cached last values of the controller, so the broker does not wait for the
+    /// next publications of the entities.
+    fn mirror_last_values(&self, mqtt: &mut MqttClient) -> Result<()> {
+        let Some(endpoint) = &self.last_value_endpoint else {
+            return Ok(());
+        };
+        let channel =
+            ZmqTransport::new(self.context.clone()).connect(Pattern::Request, endpoint)?;
+        let query = LastValueQuery {
+            topic_prefix: topic_prefix().to_owned(),
+        };
+        let snapshot: LastValueSnapshot = channel.request(&query, Some(LAST_VALUE_TIMEOUT))?;
+        tracing::info!("Mirroring {} last values", snapshot.values.len());
+        for value in snapshot.values {
+            let mut envelope = PayloadEnvelope::from_bytes(&value.envelope)?;
+            encryption::open(&mut envelope)?;
+            let mut headers = std::mem::take(&mut envelope.headers);
+            headers.insert(TOPIC_HEADER.to_owned(), value.topic);
+            self.mirror(mqtt, envelope.unpack()?, &headers)?;
+        }
+        Ok(())
+    }
+
+    /// Publishes the state on the MQTT topic of the same class and entity.
+    fn mirror(&self, mqtt: &mut MqttClient, data: PublishData, headers: &Headers) -> Result<()> {
+        let Some(topic) = headers.get(TOPIC_HEADER) else {
+            tracing::warn!("Ignoring entity data without topic");
+            return Ok(());
+        };
+        // e.g. the descriptions on the meta topics
+        if topic_entity_type(topic).is_none() {
+            return Ok(());
+        }
+        let Some(state) = state_json(data) else {
+            tracing::warn!(topic, "Ignoring entity data without state");
+            return Ok(());
+        };
+        let entity_topic = topic.strip_prefix(topic_prefix()).unwrap_or(topic);
+        mqtt.publish(Publish {
+            topic: format!("{}{entity_topic}", self.prefix),
+            payload: state.to_string().into_bytes(),
+            retain: true,
+        })
+    }
+
+    /// Sends the command published on `<prefix>/command/<name>` to the entity.
+    fn forward(&mut self, command: &Publish) -> Result<()> {
+        let name = command
+            .topic
+            .strip_prefix(&format!("{}/{COMMAND_CLASS}/", self.prefix))
+            .with_context(|| format!("Unexpected topic {}", command.topic))?;
+        let payload: Command = serde_json::from_slice(&command.payload).with_context(|| {
+            format!(
+                "Invalid command {:?}, expected e.g. {{ \"brightness\": 50.0 }}",
+                String::from_utf8_lossy(&command.payload)
+            )
+        })?;
+        let mut entity_state = payload.entity_state(name)?;
+        if let Some(signer) = &self.command_signer {
+            signer.sign(&mut entity_state);
+        }
+        let response: ResponseCode = self
+            .connection
+            .request(ClientApiCommand::named_entity_state(entity_state))?;
+        anyhow::ensure!(
+            response.code() == Code::Ok,
+            "Command for {name} failed: {}",
+            response.message
+        );
+        tracing::info!("Forwarded MQTT command to {name}");
+        Ok(())
+    }
+}
+
+/// State of the publication as JSON, e.g. `{ "temperature": 21.5, "unit": "°C" }`.
+///
+/// Batches are represented by their latest measurement.
+fn state_json(data: PublishData) -> Option<serde_json::Value> {
+    match data.value? {
+        publish_data::Value::Measurement(measurement) => measurement_json(&measurement),
+        publish_data::Value::Batch(batch) => {
+            measurement_json(batch.measurements.last()?.measurement.as_ref()?)
+        }
+        publish_data::Value::ActuatorState(state) => Some(match state.state? {
+            actuator_state::State::Light(light) => json!({ "brightness": light.brightness }),
+            actuator_state::State::AirConditioning(ac) => json!({ "air_conditioning": ac.on }),
+        }),
+        publish_data::Value::Description(_) => None,
+    }
+}
+
+fn measurement_json(measurement: &SensorMeasurement) -> Option<serde_json::Value> {
+    Some(match measurement.value.as_ref()? {
+        sensor_measurement::Value::Temperature(t) => {
+            json!({ "temperature": t.temperature, "unit": measurement.unit })
+        }
+        sensor_measurement::Value::Humidity(h) => {
+            json!({ "humidity": h.humidity, "unit": measurement.unit })
+        }
+    })
+}
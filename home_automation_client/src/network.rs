@@ -1,140 +1,445 @@
 use std::{
-    collections::HashMap,
-    sync::{atomic::AtomicBool, mpsc::Sender, Arc, Mutex},
-    thread::JoinHandle,
-    time::Duration,
+    collections::{BTreeMap, HashMap},
+    sync::mpsc::{Receiver, Sender},
+    thread::{JoinHandle, Thread},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use home_automation_api::{ControllerConnection, MESSAGE_EXCHANGE_TIMEOUT};
 use home_automation_common::{
-    load_env,
-    zmq_sockets::{invalid_state_is_ok, markers::Linked, timeout_is_ok, Context, Requester},
-    EntityState, ENV_CLIENT_API_ENDPOINT,
+    latency::LatencyWindow,
+    log_summary::Summarize as _,
+    protobuf::{ClientApiCommand, EntityMetadata, Latency, ResponseCode, SystemState},
+    zmq_sockets::Context,
+    EntityState, ErrorKindExt as _, ShutdownToken,
 };
 
+pub mod offline;
+
 type State = HashMap<String, EntityState>;
 pub const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// Time the controller may hold a state query during auto-refresh until the state changes.
+const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Event of the network layer for the UI.
+#[derive(Debug)]
+pub enum UiEvent {
+    StateUpdate {
+        entities: State,
+        /// Latency from the publication by each entity until the client received the state.
+        ///
+        /// Only measured while refreshing, so the samples are at most as frequent as the
+        /// refreshes.
+        end_to_end_latency: HashMap<String, Latency>,
+        /// Estimated time of the last heartbeat of each entity the controller reported it for.
+        heartbeats: HashMap<String, Instant>,
+        /// Rooms, tags, back-channel status and description of each entity the controller reported
+        /// them for.
+        metadata: BTreeMap<String, EntityMetadata>,
+        received_at: Instant,
+    },
+    /// Whether the controller answered the recent state queries, only sent on changes.
+    ConnectionStatus(bool),
+    CommandResult(CommandResult),
+    Notification(Notification),
+}
+
+/// Outcome of a [`UiCommand`].
+#[derive(Debug)]
+pub enum CommandResult {
+    AutoRefresh(bool),
+    /// Replies to the requests of [`UiCommand::Send`] in the same order.
+    Sent {
+        id: RequestId,
+        replies: Vec<Reply>,
+    },
+}
+
+/// Correlates a [`UiCommand::Send`] with its [`CommandResult::Sent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId(pub u64);
+
+pub type Reply = Result<ResponseCode, RequestError>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    /// The controller did not answer in time, the request may still have been executed.
+    Timeout,
+    Failed(String),
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("No reply from the controller"),
+            Self::Failed(error) => f.write_str(error),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Notification {
+    /// The refresh stopped because of the error until it is retried.
+    TaskFailed(String),
+}
+
+/// Command of the UI for the network layer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UiCommand {
+    Refresh,
+    SetAutoRefresh(bool),
+    ToggleAutoRefresh,
+    /// Restarts the refresh after a [`Notification::TaskFailed`].
+    Retry,
+    /// Sends the requests one after another to the controller without blocking the UI.
+    Send {
+        id: RequestId,
+        requests: Vec<ClientApiCommand>,
+    },
+}
+
+/// Sends the commands of the UI to the network threads and wakes the refresher up.
+#[derive(Debug, Clone)]
+pub struct UiCommands {
+    sender: Sender<UiCommand>,
+    thread: Thread,
+    requests: Sender<(RequestId, Vec<ClientApiCommand>)>,
+}
+
+impl UiCommands {
+    pub fn send(&self, command: UiCommand) {
+        match command {
+            UiCommand::Send { id, requests } => {
+                if self.requests.send((id, requests)).is_err() {
+                    tracing::debug!(?id, "Request thread ended, dropping requests");
+                }
+            }
+            command => {
+                if self.sender.send(command.clone()).is_err() {
+                    tracing::debug!(?command, "Refresher thread ended, dropping command");
+                }
+                self.thread.unpark();
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 struct InnerRefresher {
-    sender: Sender<State>,
-    requester: Requester<Linked>,
+    events: Sender<UiEvent>,
+    commands: Receiver<UiCommand>,
+    connection: ControllerConnection,
+    shutdown: ShutdownToken,
+    auto_refresh: bool,
+    /// Connection status last sent to the UI.
+    online: bool,
+    /// Generation of the last received state, unknown before the first query.
+    generation: Option<u64>,
+    /// Publish timestamps of the last received state to only measure new publications.
+    published_at_ms: BTreeMap<String, u64>,
+    /// Time between the publication by the entity and the reception by the client.
+    end_to_end_latency: HashMap<String, LatencyWindow>,
 }
 
 impl InnerRefresher {
+    /// Queries the state and forwards it to the UI, returns whether the state changed.
+    ///
+    /// If `wait` is set, the controller holds the query until the state changed.
     #[tracing::instrument(name = "refresh system state", skip(self))]
-    fn refresh_once(&mut self) -> Result<()> {
-        use home_automation_common::protobuf::{
-            entity_discovery_command::EntityType, ClientApiCommand, SystemState,
+    fn refresh_once(&mut self, wait: bool) -> Result<bool> {
+        let response: SystemState = match self.generation.filter(|_| wait) {
+            Some(generation) => {
+                let request = ClientApiCommand::wait_for_change(generation, LONG_POLL_TIMEOUT);
+                let timeout = LONG_POLL_TIMEOUT + MESSAGE_EXCHANGE_TIMEOUT;
+                let response = self
+                    .connection
+                    .request_until(request, timeout, &self.shutdown)?;
+                let Some(response) = response else {
+                    return Ok(false);
+                };
+                response
+            }
+            None => self
+                .connection
+                .request(ClientApiCommand::system_state_query())?,
         };
-
-        let sensor = |(name, measurement)| (name, EntityState::Sensor(measurement));
-        let actuator = |(name, state)| (name, EntityState::Actuator(state));
-        let new_sensor = |name| (name, EntityState::New(EntityType::Sensor));
-        let new_actuator = |name| (name, EntityState::New(EntityType::Actuator));
-
-        let request = ClientApiCommand::system_state_query();
-        self.requester.send(request).or_else(invalid_state_is_ok)?;
-        let response: SystemState = self.requester.receive()?;
-        tracing::info!("Constructing local system state");
-        let sensors = response.sensors.into_iter().map(sensor);
-        let actuators = response.actuators.into_iter().map(actuator);
-        let new_sensors = response.new_sensors.into_iter().map(new_sensor);
-        let new_actuators = response.new_actuators.into_iter().map(new_actuator);
-        let state = sensors
-            .chain(actuators)
-            .chain(new_sensors)
-            .chain(new_actuators)
+        let changed = self.generation != Some(response.generation);
+        self.generation = Some(response.generation);
+        self.record_latency(&response.published_at_ms);
+        let end_to_end_latency = self
+            .end_to_end_latency
+            .iter()
+            .map(|(name, window)| (name.clone(), window.summary()))
             .collect();
-        tracing::info!(?state, "Sending new state to UI");
-        self.sender.send(state)?;
+        self.events
+            .send(state_update(response, end_to_end_latency))?;
+        Ok(changed)
+    }
+
+    fn record_latency(&mut self, published_at_ms: &BTreeMap<String, u64>) {
+        let latencies = &mut self.end_to_end_latency;
+        latencies.retain(|name, _| published_at_ms.contains_key(name));
+        for (name, &published_at) in published_at_ms {
+            if self.published_at_ms.get(name) != Some(&published_at) {
+                latencies
+                    .entry(name.clone())
+                    .or_default()
+                    .record_since(published_at);
+            }
+        }
+        self.published_at_ms.clone_from(published_at_ms);
+    }
+
+    /// Sends the connection status to the UI if it changed.
+    fn report_connection_status(&mut self) -> Result<()> {
+        let online = self.connection.is_online();
+        if online != self.online {
+            self.online = online;
+            self.events.send(UiEvent::ConnectionStatus(online))?;
+        }
+        Ok(())
+    }
+
+    /// Handles the pending commands of the UI, returns whether a retry was requested.
+    fn handle_commands(&mut self) -> Result<bool> {
+        let mut retry = false;
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                // the command already woke the thread up
+                UiCommand::Refresh => {}
+                UiCommand::SetAutoRefresh(enabled) => self.set_auto_refresh(enabled)?,
+                UiCommand::ToggleAutoRefresh => self.set_auto_refresh(!self.auto_refresh)?,
+                UiCommand::Retry => retry = true,
+                UiCommand::Send { .. } => {
+                    unreachable!("requests are sent to the request thread")
+                }
+            }
+        }
+        Ok(retry)
+    }
+
+    fn set_auto_refresh(&mut self, enabled: bool) -> Result<()> {
+        if enabled != self.auto_refresh {
+            self.auto_refresh = enabled;
+            if enabled {
+                tracing::info!("Auto-refresh activated");
+            } else {
+                tracing::info!("Auto-refresh deactivated");
+            }
+        }
+        self.events
+            .send(UiEvent::CommandResult(CommandResult::AutoRefresh(enabled)))?;
         Ok(())
     }
 
-    fn task(mut self, auto_refresh: Arc<AtomicBool>) -> Result<()> {
+    fn task(mut self) -> Result<()> {
         tracing::info!("Starting refresh task");
-        while !home_automation_common::shutdown_requested() {
-            self.refresh_once().or_else(timeout_is_ok)?;
+        while !self.shutdown.is_requested() {
+            if let Err(e) = self.refresh_until_shutdown() {
+                tracing::error!(error = %e, "Refresh task failed: {e:#}");
+                // a retry requested before the failure does not count
+                self.handle_commands()?;
+                self.events
+                    .send(UiEvent::Notification(Notification::TaskFailed(format!(
+                        "{e:#}"
+                    ))))?;
+                self.wait_for_retry()?;
+            }
+        }
+
+        tracing::info!("Shutdown of refresher thread");
 
-            if home_automation_common::shutdown_requested() {
+        Ok(())
+    }
+
+    /// Parks the thread until the user retries or the client shuts down.
+    fn wait_for_retry(&mut self) -> Result<()> {
+        while !self.handle_commands()? {
+            if self.shutdown.is_requested() {
+                return Ok(());
+            }
+            std::thread::park();
+        }
+        tracing::info!("Retrying refresh task");
+        // starts over with a full query
+        self.generation = None;
+        Ok(())
+    }
+
+    fn refresh_until_shutdown(&mut self) -> Result<()> {
+        while !self.shutdown.is_requested() {
+            self.handle_commands()?;
+            let wait = self.auto_refresh && self.connection.is_online();
+            let result = self.refresh_once(wait);
+            self.report_connection_status()?;
+            let changed = match result {
+                Ok(changed) => changed,
+                Err(e) if e.is_timeout() => false,
+                Err(e) => return Err(e),
+            };
+
+            if self.shutdown.is_requested() {
                 break;
             }
+            // a controller without long polling answers immediately without a change
+            if wait && changed {
+                continue;
+            }
             tracing::debug!("Parking refresh thread");
-            if auto_refresh.load(std::sync::atomic::Ordering::SeqCst) {
+            if self.auto_refresh || !self.connection.is_online() {
                 std::thread::park_timeout(REFRESH_INTERVAL);
             } else {
                 std::thread::park();
             }
             tracing::debug!("Unparked refresh thread");
         }
-
-        tracing::info!("Shutdown of refresher thread");
-
         Ok(())
     }
 }
 
-#[derive(Debug)]
-enum ThreadState {
-    StartPending(InnerRefresher),
-    Running(std::thread::Thread),
+/// Converts the reply of a state query into the event for the UI.
+fn state_update(
+    mut response: SystemState,
+    end_to_end_latency: HashMap<String, Latency>,
+) -> UiEvent {
+    let received_at = Instant::now();
+    let heartbeats = response
+        .heartbeat_age_seconds
+        .iter()
+        .filter_map(|(name, &age)| {
+            let age = Duration::try_from_secs_f32(age).ok()?;
+            Some((name.clone(), received_at.checked_sub(age)?))
+        })
+        .collect();
+    let metadata = std::mem::take(&mut response.metadata);
+    tracing::info!("Constructing local system state");
+    let entities = home_automation_api::entities(response);
+    tracing::info!(state = %entities.summary(), "Sending new state to UI");
+    UiEvent::StateUpdate {
+        entities,
+        end_to_end_latency,
+        heartbeats,
+        metadata,
+        received_at,
+    }
 }
 
+/// Sends the requests of the UI, so a slow controller does not block the render loop.
 #[derive(Debug)]
-pub struct SystemStateRefresher {
-    inner: Mutex<ThreadState>,
-    auto_refresh: Arc<AtomicBool>,
+struct RequestSender {
+    events: Sender<UiEvent>,
+    requests: Receiver<(RequestId, Vec<ClientApiCommand>)>,
+    connection: ControllerConnection,
 }
 
-impl SystemStateRefresher {
-    pub fn new(context: &Context, sender: Sender<State>) -> Result<Self> {
-        let mut requester =
-            Requester::new(context)?.connect(&load_env(ENV_CLIENT_API_ENDPOINT)?)?;
-        requester.set_message_exchange_timeout(Some(Duration::from_millis(800)))?;
-        Ok(Self {
-            inner: Mutex::new(ThreadState::StartPending(InnerRefresher {
-                sender,
-                requester,
-            })),
-            auto_refresh: Arc::new(AtomicBool::new(false)),
-        })
+impl RequestSender {
+    /// Runs until the UI dropped its [`UiCommands`].
+    fn task(mut self) -> Result<()> {
+        tracing::info!("Starting request task");
+        while let Ok((id, requests)) = self.requests.recv() {
+            let replies = requests
+                .into_iter()
+                .map(|request| self.send(request))
+                .collect::<Result<_>>()?;
+            self.events
+                .send(UiEvent::CommandResult(CommandResult::Sent { id, replies }))?;
+        }
+        tracing::info!("Shutdown of request thread");
+        Ok(())
     }
 
-    pub fn toggle_auto_refresh(&self) {
-        use std::sync::atomic::Ordering;
-        // invert the value by using value XOR true
-        let current_value = !self.auto_refresh.fetch_xor(true, Ordering::SeqCst);
-        if current_value {
-            tracing::info!("Auto-refresh activated");
-            self.refresh();
-        } else {
-            tracing::info!("Auto-refresh deactivated");
+    #[tracing::instrument(skip(self), parent = None)]
+    fn send(&mut self, request: ClientApiCommand) -> Result<Reply> {
+        match self.connection.request::<_, ResponseCode>(request) {
+            Ok(reply) => Ok(Ok(reply)),
+            Err(e) if e.is_termination() => Err(e),
+            Err(e) if e.is_timeout() => Ok(Err(RequestError::Timeout)),
+            Err(e) => Ok(Err(RequestError::Failed(format!("{e:#}")))),
         }
     }
+}
 
-    pub fn refresh(&self) {
-        let mut guard = self.inner.lock().expect("non-poisoned Mutex");
-        if let ThreadState::Running(thread) = &mut *guard {
-            thread.unpark();
-        }
+/// Threads of the network layer, see [`SystemStateRefresher::run`].
+#[derive(Debug)]
+pub struct NetworkThreads {
+    refresher: JoinHandle<Result<()>>,
+    requests: JoinHandle<Result<()>>,
+}
+
+impl NetworkThreads {
+    /// Waits for the threads after the shutdown was requested and the [`UiCommands`] dropped.
+    pub fn join(self) -> Result<()> {
+        tracing::debug!("Unparking refresher thread");
+        self.refresher.thread().unpark();
+        self.refresher
+            .join()
+            .map_err(|e| anyhow::anyhow!("Refresher task panicked: {e:?}"))?
+            .context("Refresher task failed")?;
+        self.requests
+            .join()
+            .map_err(|e| anyhow::anyhow!("Request task panicked: {e:?}"))?
+            .context("Request task failed")
     }
+}
 
-    pub fn run(&self) -> Result<JoinHandle<Result<()>>> {
-        let auto_refresh = self.auto_refresh.clone();
-        let mut guard = self.inner.lock().expect("non-poisoned mutex");
+/// Background threads that query the system state of the controller for the UI and send its
+/// requests.
+#[derive(Debug)]
+pub struct SystemStateRefresher {
+    inner: InnerRefresher,
+    commands: Sender<UiCommand>,
+    request_sender: RequestSender,
+    requests: Sender<(RequestId, Vec<ClientApiCommand>)>,
+}
 
-        // get ownership and replace with dummy value until done
-        match std::mem::replace(&mut *guard, ThreadState::Running(std::thread::current())) {
-            ThreadState::Running(thread) => {
-                *guard = ThreadState::Running(thread);
-                Err(anyhow::anyhow!("Thread already started"))
-            }
-            ThreadState::StartPending(inner) => {
-                let handle = std::thread::spawn(move || inner.task(auto_refresh));
-                *guard = ThreadState::Running(handle.thread().clone());
+impl SystemStateRefresher {
+    pub fn new(
+        context: &Context,
+        events: Sender<UiEvent>,
+        shutdown: ShutdownToken,
+    ) -> Result<Self> {
+        let (commands, receiver) = std::sync::mpsc::channel();
+        let (requests, pending) = std::sync::mpsc::channel();
+        Ok(Self {
+            request_sender: RequestSender {
+                events: events.clone(),
+                requests: pending,
+                connection: ControllerConnection::new(context)?,
+            },
+            requests,
+            inner: InnerRefresher {
+                events,
+                commands: receiver,
+                connection: ControllerConnection::new(context)?,
+                shutdown,
+                auto_refresh: false,
+                online: true,
+                generation: None,
+                published_at_ms: BTreeMap::new(),
+                end_to_end_latency: HashMap::new(),
+            },
+            commands,
+        })
+    }
 
-                Ok(handle)
-            }
-        }
+    /// Starts the threads, they are controlled with the returned [`UiCommands`].
+    pub fn run(self) -> (NetworkThreads, UiCommands) {
+        let inner = self.inner;
+        let refresher = std::thread::spawn(move || inner.task());
+        let request_sender = self.request_sender;
+        let requests = std::thread::spawn(move || request_sender.task());
+        let commands = UiCommands {
+            sender: self.commands,
+            thread: refresher.thread().clone(),
+            requests: self.requests,
+        };
+        (
+            NetworkThreads {
+                refresher,
+                requests,
+            },
+            commands,
+        )
     }
 }
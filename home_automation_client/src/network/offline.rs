@@ -0,0 +1,296 @@
+//! `--offline` mode that replaces the controller by a local simulation, so the UI can be
+//! demonstrated on a machine without the rest of the system.
+//!
+//! The simulation answers the [`UiCommand`]s with the same [`UiEvent`]s as the network threads:
+//! it produces plausible [`SystemState`]s and applies the messages sent to the actuators.
+
+use std::{
+    collections::HashMap,
+    f64::consts::TAU,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::Thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use home_automation_api::{command_name, SystemStateBuilder};
+use home_automation_common::{
+    latency,
+    protobuf::{
+        actuator_state, client_api_command::CommandType, named_entity_state, ActuatorState,
+        ClientApiCommand, EntityMetadata, NamedEntityState, SensorMeasurement, SystemState,
+        Welcome,
+    },
+    EntityState, ShutdownToken, DEFAULT_HEARTBEAT_INTERVAL, PROTOCOL_VERSION,
+};
+
+use super::{
+    state_update, CommandResult, NetworkThreads, Reply, RequestError, RequestId, UiCommand,
+    UiCommands, UiEvent, REFRESH_INTERVAL,
+};
+
+/// Flag that starts the client with the simulation instead of connecting to the controller.
+pub const OFFLINE_FLAG: &str = "--offline";
+
+/// Period of the oscillation of the simulated measurements.
+const WAVE_PERIOD: Duration = Duration::from_secs(300);
+/// Tag of the simulated entities.
+const TAG: &str = "offline";
+
+#[derive(Debug, Clone, Copy)]
+enum SensorKind {
+    Temperature,
+    Humidity,
+}
+
+/// Simulated sensors with their room.
+const SENSORS: [(&str, &str, SensorKind); 3] = [
+    (
+        "sen_kitchen_temperature",
+        "kitchen",
+        SensorKind::Temperature,
+    ),
+    ("sen_bathroom_humidity", "bathroom", SensorKind::Humidity),
+    (
+        "sen_living_room_temperature",
+        "living room",
+        SensorKind::Temperature,
+    ),
+];
+
+/// Simulated actuators with their room.
+const ACTUATORS: [(&str, &str); 2] = [
+    ("act_kitchen_light", "kitchen"),
+    ("act_living_room_ac", "living room"),
+];
+
+/// Returns whether the client was started with [`OFFLINE_FLAG`].
+pub fn requested() -> bool {
+    std::env::args().skip(1).any(|arg| arg == OFFLINE_FLAG)
+}
+
+/// Protocol version and features of the simulated controller, which has no optional parts.
+pub fn welcome() -> Welcome {
+    Welcome {
+        protocol_version: PROTOCOL_VERSION,
+        features: Vec::new(),
+    }
+}
+
+#[derive(Debug)]
+struct Simulation {
+    started: Instant,
+    generation: u64,
+    actuators: HashMap<&'static str, ActuatorState>,
+}
+
+impl Simulation {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            generation: 0,
+            actuators: HashMap::from([
+                (ACTUATORS[0].0, ActuatorState::light(60.)),
+                (ACTUATORS[1].0, ActuatorState::air_conditioning(false)),
+            ]),
+        }
+    }
+
+    /// Value that oscillates around a plausible level, shifted by the index of the sensor so the
+    /// sensors of the same kind differ.
+    fn measurement(&self, kind: SensorKind, index: usize) -> SensorMeasurement {
+        let phase = TAU * self.started.elapsed().as_secs_f64() / WAVE_PERIOD.as_secs_f64();
+        let wave = (phase + index as f64).sin();
+        match kind {
+            SensorKind::Temperature => SensorMeasurement::temperature((21. + 3. * wave) as f32),
+            SensorKind::Humidity => SensorMeasurement::humidity((45. + 10. * wave) as f32),
+        }
+    }
+
+    /// Current state as the controller would reply to a state query.
+    fn state(&mut self) -> SystemState {
+        self.generation += 1;
+        let mut builder = SystemStateBuilder::new(self.generation);
+        let now_ms = latency::unix_time_ms();
+        // the entities seem to send their heartbeats in the default interval
+        let heartbeat_age = Duration::from_millis(
+            (self.started.elapsed().as_millis() % DEFAULT_HEARTBEAT_INTERVAL.as_millis()) as u64,
+        );
+        let sensors = SENSORS.iter().enumerate().map(|(i, &(name, room, kind))| {
+            (name, room, EntityState::Sensor(self.measurement(kind, i)))
+        });
+        let actuators = ACTUATORS.iter().map(|&(name, room)| {
+            (
+                name,
+                room,
+                EntityState::Actuator(self.actuators[name].clone()),
+            )
+        });
+        for (name, room, state) in sensors.chain(actuators) {
+            builder.add(name, &state, now_ms);
+            builder.heartbeat(name, heartbeat_age);
+            builder.metadata(
+                name,
+                EntityMetadata {
+                    rooms: vec![room.to_owned()],
+                    tags: vec![TAG.to_owned()],
+                    back_channel_healthy: true,
+                    heartbeat_interval_ms: DEFAULT_HEARTBEAT_INTERVAL.as_millis() as u32,
+                    description: None,
+                },
+            );
+        }
+        builder.build()
+    }
+
+    /// Answers the request like the controller and the entity would.
+    fn apply(&mut self, request: ClientApiCommand) -> Reply {
+        match request.command_type {
+            Some(CommandType::Action(action)) => Ok(self.apply_action(action).into()),
+            command => Err(RequestError::Failed(format!(
+                "{} is not available offline",
+                command_name(command.as_ref())
+            ))),
+        }
+    }
+
+    fn apply_action(&mut self, action: NamedEntityState) -> Result<()> {
+        use named_entity_state::State;
+        let name = action.entity_name.as_str();
+        let is_sensor = SENSORS.iter().any(|&(sensor, ..)| sensor == name);
+        match action.state {
+            // pings and the update frequency change nothing that is simulated
+            None | Some(State::SensorConfiguration(_)) if is_sensor => Ok(()),
+            None if self.actuators.contains_key(name) => Ok(()),
+            Some(State::ActuatorState(state)) => {
+                let current = self
+                    .actuators
+                    .get_mut(name)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown actuator {name}"))?;
+                let same_kind = matches!(
+                    (&current.state, &state.state),
+                    (
+                        Some(actuator_state::State::Light(_)),
+                        Some(actuator_state::State::Light(_))
+                    ) | (
+                        Some(actuator_state::State::AirConditioning(_)),
+                        Some(actuator_state::State::AirConditioning(_))
+                    )
+                );
+                anyhow::ensure!(same_kind, "Invalid state {state:?} for {name}");
+                *current = state;
+                Ok(())
+            }
+            Some(state) => anyhow::bail!("Entity {name} does not accept {state:?}"),
+            None => anyhow::bail!("Unknown entity {name}"),
+        }
+    }
+}
+
+/// Local replacement of the network threads, see [`SystemStateRefresher`][super::SystemStateRefresher].
+#[derive(Debug)]
+pub struct OfflineSystem {
+    simulation: Arc<Mutex<Simulation>>,
+    events: Sender<UiEvent>,
+    shutdown: ShutdownToken,
+}
+
+impl OfflineSystem {
+    pub fn new(events: Sender<UiEvent>, shutdown: ShutdownToken) -> Self {
+        Self {
+            simulation: Arc::new(Mutex::new(Simulation::new())),
+            events,
+            shutdown,
+        }
+    }
+
+    /// Starts the threads, they are controlled with the returned [`UiCommands`].
+    pub fn run(self) -> (NetworkThreads, UiCommands) {
+        let Self {
+            simulation,
+            events,
+            shutdown,
+        } = self;
+        let (commands, receiver) = std::sync::mpsc::channel();
+        let (requests, pending) = std::sync::mpsc::channel();
+        let refresher = std::thread::spawn({
+            let simulation = simulation.clone();
+            let events = events.clone();
+            move || generate(&simulation, &events, &receiver, &shutdown)
+        });
+        let requests_thread = std::thread::spawn({
+            let generator = refresher.thread().clone();
+            move || answer(&simulation, &events, &pending, &generator)
+        });
+        let commands = UiCommands {
+            sender: commands,
+            thread: refresher.thread().clone(),
+            requests,
+        };
+        (
+            NetworkThreads {
+                refresher,
+                requests: requests_thread,
+            },
+            commands,
+        )
+    }
+}
+
+/// Sends a new state whenever woken up by the UI, and every [`REFRESH_INTERVAL`] during
+/// auto-refresh.
+fn generate(
+    simulation: &Mutex<Simulation>,
+    events: &Sender<UiEvent>,
+    commands: &Receiver<UiCommand>,
+    shutdown: &ShutdownToken,
+) -> Result<()> {
+    tracing::info!("Starting offline simulation");
+    let mut auto_refresh = false;
+    while !shutdown.is_requested() {
+        while let Ok(command) = commands.try_recv() {
+            let enabled = match command {
+                UiCommand::Refresh | UiCommand::Retry => continue,
+                UiCommand::SetAutoRefresh(enabled) => enabled,
+                UiCommand::ToggleAutoRefresh => !auto_refresh,
+                UiCommand::Send { .. } => unreachable!("requests are sent to the request thread"),
+            };
+            auto_refresh = enabled;
+            events.send(UiEvent::CommandResult(CommandResult::AutoRefresh(enabled)))?;
+        }
+        let state = simulation.lock().expect("non-poisoned Mutex").state();
+        events.send(state_update(state, HashMap::new()))?;
+        if auto_refresh {
+            std::thread::park_timeout(REFRESH_INTERVAL);
+        } else {
+            std::thread::park();
+        }
+    }
+    tracing::info!("Shutdown of offline simulation");
+    Ok(())
+}
+
+/// Applies the requests of the UI until it dropped its [`UiCommands`], then wakes the generator
+/// up so the UI shows the changed actuators at once.
+fn answer(
+    simulation: &Mutex<Simulation>,
+    events: &Sender<UiEvent>,
+    requests: &Receiver<(RequestId, Vec<ClientApiCommand>)>,
+    generator: &Thread,
+) -> Result<()> {
+    while let Ok((id, requests)) = requests.recv() {
+        let replies = {
+            let mut simulation = simulation.lock().expect("non-poisoned Mutex");
+            requests
+                .into_iter()
+                .map(|request| simulation.apply(request))
+                .collect()
+        };
+        events.send(UiEvent::CommandResult(CommandResult::Sent { id, replies }))?;
+        generator.unpark();
+    }
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! `rules list`, `rules add` and `rules remove` subcommands that change the automation rules of
+//! the controller at runtime without starting the UI.
+
+use anyhow::{Context as _, Result};
+use home_automation_api::{
+    protobuf::{
+        actuator_state::State, automation_rule::Comparison, named_entity_state, ActuatorState,
+        AutomationRule, ClientApiCommand, NamedEntityState, RuleList,
+    },
+    ControllerConnection,
+};
+use home_automation_common::zmq_sockets;
+
+/// First argument that selects the subcommand.
+pub const RULES_COMMAND: &str = "rules";
+const USAGE: &str = "Usage: rules list | rules remove <name> | \
+    rules add <name> <entity> above|below <value> <actuator>=on|off|<brightness>...";
+
+pub enum Command {
+    List,
+    Add(AutomationRule),
+    Remove(String),
+}
+
+/// Returns the subcommand if the client was started with [`RULES_COMMAND`].
+pub fn requested() -> Option<Result<Command>> {
+    let mut args = std::env::args_os().skip(1);
+    if args.next()? != RULES_COMMAND {
+        return None;
+    }
+    let args: Vec<String> = args.map(|arg| arg.to_string_lossy().into_owned()).collect();
+    Some(parse(&args))
+}
+
+fn parse(args: &[String]) -> Result<Command> {
+    match args {
+        [list] if list == "list" => Ok(Command::List),
+        [remove, name] if remove == "remove" => Ok(Command::Remove(name.clone())),
+        [add, name, entity, comparison, value, actions @ ..] if add == "add" => {
+            anyhow::ensure!(!actions.is_empty(), "{USAGE}");
+            let comparison = match comparison.as_str() {
+                "above" => Comparison::Above,
+                "below" => Comparison::Below,
+                _ => anyhow::bail!("Invalid comparison {comparison:?}, expected above or below"),
+            };
+            Ok(Command::Add(AutomationRule {
+                name: name.clone(),
+                entity: entity.clone(),
+                comparison: comparison.into(),
+                value: value
+                    .parse()
+                    .with_context(|| format!("Invalid value {value:?}"))?,
+                actions: actions
+                    .iter()
+                    .map(|action| parse_action(action))
+                    .collect::<Result<_>>()?,
+            }))
+        }
+        _ => Err(anyhow::anyhow!(USAGE)),
+    }
+}
+
+/// Parses `act_ac=on`, `act_ac=off` or `act_light=50`.
+fn parse_action(action: &str) -> Result<NamedEntityState> {
+    let (entity, value) = action.split_once('=').with_context(|| {
+        format!("Invalid action {action:?}, expected <actuator>=on|off|<brightness>")
+    })?;
+    let state = match value {
+        "on" => ActuatorState::air_conditioning(true),
+        "off" => ActuatorState::air_conditioning(false),
+        brightness => ActuatorState::light(
+            brightness
+                .parse()
+                .with_context(|| format!("Invalid brightness {brightness:?} in {action:?}"))?,
+        ),
+    };
+    Ok(NamedEntityState::actuator(entity, state))
+}
+
+pub fn run(command: Command) -> Result<()> {
+    let context = zmq_sockets::Context::new();
+    let mut connection = ControllerConnection::new(&context)?;
+    let request = match command {
+        Command::List => ClientApiCommand::list_rules(),
+        Command::Add(rule) => ClientApiCommand::add_rule(rule),
+        Command::Remove(name) => ClientApiCommand::remove_rule(name),
+    };
+    let result = connection
+        .request::<_, RuleList>(request)
+        .context("Failed to send rule command");
+    // Workaround: For some reason, the destructor of context keeps blocking.
+    std::mem::forget(context);
+    let list = result?;
+    print(&list);
+    anyhow::ensure!(list.error.is_empty(), "{}", list.error);
+    Ok(())
+}
+
+fn print(list: &RuleList) {
+    if list.rules.is_empty() {
+        println!("No rules configured");
+    }
+    for rule in &list.rules {
+        let actions: Vec<_> = rule.actions.iter().map(describe_action).collect();
+        println!(
+            "{}: if {} {} {} then {}",
+            rule.name,
+            rule.entity,
+            rule.comparison().as_str_name().to_lowercase(),
+            rule.value,
+            actions.join(", ")
+        );
+    }
+}
+
+fn describe_action(action: &NamedEntityState) -> String {
+    let value = match &action.state {
+        Some(named_entity_state::State::ActuatorState(ActuatorState {
+            state: Some(State::Light(light)),
+        })) => light.brightness.to_string(),
+        Some(named_entity_state::State::ActuatorState(ActuatorState {
+            state: Some(State::AirConditioning(ac)),
+        })) => if ac.on { "on" } else { "off" }.to_owned(),
+        Some(named_entity_state::State::SensorConfiguration(configuration)) => {
+            format!("{} Hz", configuration.update_frequency_hz)
+        }
+        _ => "?".to_owned(),
+    };
+    format!("{}={value}", action.entity_name)
+}
@@ -0,0 +1,92 @@
+//! `snapshot save <file>` and `snapshot restore <file>` subcommands that capture the whole system
+//! in a file and restore it later without starting the UI, e.g. before a demo.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use home_automation_api::{
+    protobuf::{ClientApiCommand, SnapshotDocument, SnapshotRestoreReport},
+    ControllerConnection,
+};
+use home_automation_common::{zmq_sockets, ShutdownToken, ENV_ADMIN_TOKEN};
+
+/// First argument that selects the subcommand.
+pub const SNAPSHOT_COMMAND: &str = "snapshot";
+/// The controller forwards the states to every entity before it replies.
+const RESTORE_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum Command {
+    Save(PathBuf),
+    Restore(PathBuf),
+}
+
+/// Returns the subcommand if the client was started with [`SNAPSHOT_COMMAND`].
+pub fn requested() -> Option<Result<Command>> {
+    let mut args = std::env::args_os().skip(1);
+    if args.next()? != SNAPSHOT_COMMAND {
+        return None;
+    }
+    let usage = || format!("Usage: {SNAPSHOT_COMMAND} save|restore <file>");
+    let action = args.next();
+    let path = args.next().map(PathBuf::from);
+    Some(match (action.as_ref().and_then(|a| a.to_str()), path) {
+        (Some("save"), Some(path)) => Ok(Command::Save(path)),
+        (Some("restore"), Some(path)) => Ok(Command::Restore(path)),
+        _ => Err(anyhow::anyhow!(usage())),
+    })
+}
+
+pub fn run(command: Command) -> Result<()> {
+    let context = zmq_sockets::Context::new();
+    let mut connection = ControllerConnection::new(&context)?;
+    let result = match command {
+        Command::Save(path) => save(&mut connection, &path),
+        Command::Restore(path) => restore(&mut connection, &path),
+    };
+    // Workaround: For some reason, the destructor of context keeps blocking.
+    std::mem::forget(context);
+    result
+}
+
+fn save(connection: &mut ControllerConnection, path: &Path) -> Result<()> {
+    let document: SnapshotDocument = connection
+        .request(ClientApiCommand::capture_snapshot(admin_token()))
+        .context("Failed to capture snapshot")?;
+    std::fs::write(path, document.snapshot_json)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("Saved snapshot to {}", path.display());
+    Ok(())
+}
+
+fn restore(connection: &mut ControllerConnection, path: &Path) -> Result<()> {
+    let snapshot_json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let report: SnapshotRestoreReport = connection
+        .request_until(
+            ClientApiCommand::restore_snapshot(snapshot_json, admin_token()),
+            RESTORE_TIMEOUT,
+            &ShutdownToken::new(),
+        )?
+        .context("Restore was cancelled")?;
+    anyhow::ensure!(
+        report.error.is_empty(),
+        "Failed to restore snapshot: {}",
+        report.error
+    );
+    println!("Restored {} entities", report.restored.len());
+    for name in &report.missing {
+        println!("  {name}: not registered");
+    }
+    for (name, reason) in &report.failed {
+        println!("  {name}: {reason}");
+    }
+    Ok(())
+}
+
+/// Snapshots require the admin token if the controller has one.
+fn admin_token() -> String {
+    std::env::var(ENV_ADMIN_TOKEN).unwrap_or_default()
+}
@@ -1,8 +1,18 @@
+use std::io::IsTerminal as _;
+
 use anyhow::{Context as _, Result};
 use crossterm::{event, terminal};
 use ratatui::{backend::CrosstermBackend, Terminal};
 
 mod app;
+mod frame_time;
+mod headless;
+mod i18n;
+mod macros;
+mod model;
+mod persistence;
+mod registry;
+mod sensor_history;
 mod view;
 
 pub use app::BackgroundTaskState;
@@ -22,6 +32,22 @@ fn init_raw_tty() -> Result<Tui> {
     Terminal::new(CrosstermBackend::new(stdout)).context("Failed to create terminal")
 }
 
+/// Returns whether the input event should be handled by the UI.
+///
+/// Windows reports the release of a key in addition to its press, which must not trigger the
+/// action twice. The other platforms only report releases if enabled explicitly.
+fn is_relevant(event: &event::Event) -> bool {
+    use event::{Event, KeyEvent, KeyEventKind};
+    let release = matches!(
+        event,
+        Event::Key(KeyEvent {
+            kind: KeyEventKind::Release,
+            ..
+        })
+    );
+    !(cfg!(windows) && release)
+}
+
 /// Restore the terminal to its original state
 fn restore_normal_tty() -> Result<()> {
     crossterm::execute!(
@@ -33,7 +59,25 @@ fn restore_normal_tty() -> Result<()> {
     terminal::disable_raw_mode().context("Failed to disable raw_mode")
 }
 
+/// Adds the settings of the user interface to the report of `--doctor`.
+pub fn doctor(report: &mut home_automation_common::doctor::Report) {
+    for var in [
+        app::ENV_CLIENT_MAX_FPS,
+        i18n::ENV_CLIENT_LANGUAGE,
+        macros::ENV_CLIENT_MACROS_FILE,
+        persistence::ENV_CLIENT_STATE_FILE,
+        view::ENV_CLIENT_APPEARANCE,
+    ] {
+        report.env_var(var, false);
+    }
+}
+
 pub fn run(task_state: BackgroundTaskState) -> Result<()> {
+    if !std::io::stdout().is_terminal() {
+        tracing::info!("Stdout is not a terminal, printing the system state instead of the UI");
+        return headless::run(task_state);
+    }
+
     let default_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |info| {
         restore_normal_tty().unwrap();
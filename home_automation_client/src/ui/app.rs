@@ -1,24 +1,55 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::mpsc::{Receiver, TryRecvError},
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context as _, Result};
 use crossterm::event;
 use home_automation_common::{
-    protobuf::{NamedEntityState, ResponseCode},
-    zmq_sockets::{self, markers::Linked},
-    AnyhowZmq as _, EntityState,
+    protobuf::{
+        admin_command, response_code::Code, ClientApiCommand, Latency, NamedEntityState, Welcome,
+    },
+    signing::CommandSigner,
+    ErrorKindExt as _, ShutdownToken, ENV_ADMIN_TOKEN,
 };
 
-use crate::network::SystemStateRefresher;
+use crate::network::{
+    CommandResult, Notification, Reply, RequestError, RequestId, UiCommand, UiCommands, UiEvent,
+    REFRESH_INTERVAL,
+};
 
 use super::{
-    view::{PayloadTab, SendStage, UiView, View},
+    frame_time::{FrameTimes, Stage},
+    i18n::strings,
+    macros::{self, Macro},
+    model::{AppModel, Update},
+    persistence::PersistedState,
+    view::{AdminData, Appearance, PayloadTab, View},
     Tui,
 };
 
+use command::{Command, Effect, History, Undo};
+
+/// Optional maximum number of frames per second the UI is drawn with, default 30.
+pub const ENV_CLIENT_MAX_FPS: &str = "HOME_AUTOMATION_CLIENT_MAX_FPS";
+
+const DEFAULT_MAX_FPS: u32 = 30;
+/// How long the main loop waits for input if nothing needs to be drawn.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// The heartbeat ages in the monitor and topology views and the time axis of the chart advance
+/// without a new state, so these views are redrawn anyway.
+const CLOCK_REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+mod command;
+
+#[derive(Clone)]
 pub enum Action {
     ChangeView(View),
     Refresh,
     ToggleAutoRefresh,
+    /// Restarts the background refresh after it failed.
+    RetryRefresh,
     Exit,
     SetMessageRecipient(String),
     SetRecipientSelection(Option<usize>),
@@ -27,136 +58,505 @@ pub enum Action {
     ChangePayloadTab(PayloadTab),
     ToggleAirConditioning,
     SetLightBrightness(f32),
+    RefreshAdmin,
+    SetAdminSelection(Option<usize>),
+    SendAdminCommand(admin_command::Command),
+    /// Shows or hides the performance counters in the admin view.
+    TogglePerformance,
+    /// Shows or hides the frame times on top of the view, only offered in debug builds.
+    ToggleFrameTimes,
+    OpenPalette,
+    ClosePalette,
+    PaletteInput(tui_textarea::Input),
+    SetPaletteSelection(Option<usize>),
+    /// Action chosen in the command palette, which closes the palette.
+    FromPalette(Box<Action>),
+    /// Starts the recording of a macro or stops it and saves the macro.
+    ToggleRecording,
+    /// Replays the macro bound to the function key.
+    RunMacro(u8),
+    SetAppearance(Appearance),
+    SetChartSensor(String),
+    SetChartWindow(Duration),
+    SetTopologyScroll(u16),
 }
 
+/// Boundary to the network layer, its results arrive as [`UiEvent`]s and the UI controls it
+/// with [`UiCommand`]s.
 #[derive(Debug)]
-pub struct BackgroundTaskState<'a> {
-    pub refresher: &'a SystemStateRefresher,
-    pub receiver: std::sync::mpsc::Receiver<HashMap<String, EntityState>>,
-    pub requester: zmq_sockets::Requester<Linked>,
+pub struct BackgroundTaskState {
+    pub events: Receiver<UiEvent>,
+    pub commands: UiCommands,
+    /// Connection for the admin queries, `None` in the offline mode.
+    pub connection: Option<home_automation_api::ControllerConnection>,
+    /// Protocol version and features of the controller learned during the handshake.
+    pub controller: Welcome,
+    /// Signs the entity commands so the entities can verify the client.
+    pub command_signer: Option<CommandSigner>,
+    pub shutdown: ShutdownToken,
 }
 
+/// What the UI shows once the replies to a [`UiCommand::Send`] arrived.
 #[derive(Debug)]
-pub struct App<'a> {
-    state: HashMap<String, EntityState>,
-    view: View,
-    background_task_state: BackgroundTaskState<'a>,
+enum PendingRequest {
+    Message {
+        recipient: String,
+    },
+    Admin {
+        description: String,
+    },
+    /// Every step with the reason why it was skipped, the other steps were sent in order.
+    Macro {
+        name: String,
+        steps: Vec<(String, Option<String>)>,
+    },
 }
 
-impl<'a> App<'a> {
-    pub fn new(background_task_state: BackgroundTaskState<'a>) -> Self {
+#[derive(Debug)]
+pub struct App {
+    model: AppModel,
+    background_task_state: BackgroundTaskState,
+    admin_token: String,
+    /// Requests sent by the network layer whose replies did not arrive yet.
+    pending: HashMap<RequestId, PendingRequest>,
+    next_request_id: u64,
+    last_admin_refresh: Option<Instant>,
+    /// Latency measured by the refresher, shown with the next refresh of the admin view.
+    end_to_end_latency: HashMap<String, Latency>,
+    history: History,
+    frame_times: FrameTimes,
+    /// Whether the model changed since it was drawn.
+    dirty: bool,
+    last_draw: Option<Instant>,
+    frame_interval: Duration,
+}
+
+/// Reads the minimum time between two frames from [`ENV_CLIENT_MAX_FPS`].
+fn frame_interval() -> Duration {
+    let max_fps = match std::env::var(ENV_CLIENT_MAX_FPS) {
+        Ok(fps) => match fps.parse::<u32>() {
+            Ok(fps) if fps > 0 => fps,
+            _ => {
+                tracing::warn!("Ignoring invalid {ENV_CLIENT_MAX_FPS} {fps:?}");
+                DEFAULT_MAX_FPS
+            }
+        },
+        Err(_) => DEFAULT_MAX_FPS,
+    };
+    Duration::from_secs(1) / max_fps
+}
+
+impl App {
+    pub fn new(background_task_state: BackgroundTaskState) -> Self {
+        let mut model = AppModel::new(background_task_state.controller.clone());
+        let persisted = PersistedState::load();
+        tracing::debug!(?persisted, "Restoring UI state");
+        model.restore(&persisted);
+        model.macros = macros::load();
+        if let Some(appearance) = Appearance::from_env() {
+            model.appearance = appearance;
+        }
+        background_task_state
+            .commands
+            .send(UiCommand::SetAutoRefresh(persisted.auto_refresh));
         Self {
-            view: View::default(),
-            state: HashMap::default(),
+            model,
             background_task_state,
+            admin_token: std::env::var(ENV_ADMIN_TOKEN).unwrap_or_default(),
+            pending: HashMap::new(),
+            next_request_id: 0,
+            last_admin_refresh: None,
+            end_to_end_latency: HashMap::new(),
+            history: History::default(),
+            frame_times: FrameTimes::default(),
+            dirty: true,
+            last_draw: None,
+            frame_interval: frame_interval(),
         }
     }
 
     /// runs the application's main loop until the user quits
     pub fn run(&mut self, terminal: &mut Tui) -> Result<()> {
-        while !home_automation_common::shutdown_requested() {
-            terminal.draw(|frame| self.view.active(&self.state).render(frame))?;
-            self.handle_events().context("Failed to handle events")?;
-            if let Some(new_state) = self.background_task_state.receiver.try_iter().last() {
-                self.state = new_state;
+        while !self.background_task_state.shutdown.is_requested() {
+            let next_frame = self
+                .last_draw
+                .map_or_else(Instant::now, |last| last + self.frame_interval);
+            let now = Instant::now();
+            if matches!(
+                self.model.view,
+                View::Monitor | View::Chart(_) | View::Topology(_)
+            ) && self
+                .last_draw
+                .is_some_and(|last| now - last >= CLOCK_REDRAW_INTERVAL)
+            {
+                self.dirty = true;
+            }
+            if self.dirty && now >= next_frame {
+                self.draw(terminal)?;
             }
+            // wakes up in time for the next frame if a change is pending
+            let timeout = if self.dirty {
+                next_frame.saturating_duration_since(now)
+            } else {
+                IDLE_POLL_INTERVAL
+            };
+            self.handle_events(timeout)
+                .context("Failed to handle events")?;
+            let timer = Stage::Receive.start();
+            self.receive_updates();
+            self.frame_times.finish(timer);
+            let admin_refresh_due = self
+                .last_admin_refresh
+                .map_or(true, |last| last.elapsed() >= REFRESH_INTERVAL);
+            if matches!(self.model.view, View::Admin(_)) && admin_refresh_due {
+                self.refresh_admin_state()?;
+            }
+        }
+
+        if let Err(e) = self.model.persisted_state().save() {
+            tracing::warn!(%e, "Failed to save UI state: {e:#}");
+        }
+        Ok(())
+    }
+
+    /// Draws the model if it changed, at most with the configured frame rate.
+    fn draw(&mut self, terminal: &mut Tui) -> Result<()> {
+        if self.model.frame_times.is_some() {
+            self.model
+                .reduce(Update::FrameTimesMeasured(self.frame_times.summary()));
         }
+        let timer = Stage::Draw.start();
+        terminal.draw(|frame| self.model.render(frame))?;
+        self.frame_times.finish(timer);
+        self.dirty = false;
+        self.last_draw = Some(Instant::now());
         Ok(())
     }
 
+    /// Reduces the events of the network layer, of multiple states only the last one.
+    fn receive_updates(&mut self) {
+        let mut refreshed = None;
+        loop {
+            match self.background_task_state.events.try_recv() {
+                Ok(UiEvent::StateUpdate {
+                    entities,
+                    end_to_end_latency,
+                    heartbeats,
+                    metadata,
+                    received_at,
+                }) => {
+                    refreshed = Some(Update::EntitiesRefreshed {
+                        entities,
+                        heartbeats,
+                        metadata,
+                        received_at,
+                    });
+                    self.end_to_end_latency = end_to_end_latency;
+                }
+                Ok(UiEvent::ConnectionStatus(online)) => {
+                    self.reduce(Update::ConnectivityChanged(online));
+                }
+                Ok(UiEvent::CommandResult(CommandResult::AutoRefresh(enabled))) => {
+                    self.reduce(Update::AutoRefreshChanged(enabled));
+                }
+                Ok(UiEvent::CommandResult(CommandResult::Sent { id, replies })) => {
+                    self.finish_request(id, replies);
+                }
+                Ok(UiEvent::Notification(Notification::TaskFailed(error))) => {
+                    if let Some(update) = refreshed.take() {
+                        self.reduce(update);
+                    }
+                    self.reduce(Update::TaskFailed(error));
+                }
+                Err(TryRecvError::Empty) => break,
+                // the refresher thread ended, e.g. it panicked
+                Err(TryRecvError::Disconnected) => {
+                    if self.model.task_error.is_none() {
+                        let error = strings().refresh_task_stopped.to_owned();
+                        self.reduce(Update::TaskFailed(error));
+                    }
+                    break;
+                }
+            }
+        }
+        if let Some(update) = refreshed {
+            self.reduce(update);
+        }
+    }
+
+    /// Applies the update and marks the UI for redrawing.
+    fn reduce(&mut self, update: Update) {
+        self.model.reduce(update);
+        self.dirty = true;
+    }
+
     /// updates the application's state based on user input
-    fn handle_events(&mut self) -> Result<()> {
+    fn handle_events(&mut self, timeout: Duration) -> Result<()> {
+        use event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
         let event = {
             let context = "Failed to read input event";
-            if !event::poll(Duration::from_millis(500)).context(context)? {
+            if !event::poll(timeout).context(context)? {
                 return Ok(());
             }
             event::read().context(context)?
         };
-        let action = self.view.active(&self.state).handle_events(event);
-        match action {
-            Some(Action::Exit) => home_automation_common::request_shutdown(),
-            Some(Action::ChangeView(v)) => self.view = v,
-            Some(Action::Refresh) => self.background_task_state.refresher.refresh(),
-            Some(Action::ToggleAutoRefresh) => {
-                self.background_task_state.refresher.toggle_auto_refresh();
-            }
-            Some(Action::SetMessageRecipient(recipient)) => {
-                let send_data = self.view.ensure_send_mut();
-                send_data.input.cancel_selection();
-                send_data.input.select_all();
-                send_data.input.insert_str(recipient);
-                send_data.list.select(None);
-                send_data.stage = SendStage::PayloadSelect {};
+        if !super::is_relevant(&event) {
+            return Ok(());
+        }
+        // every input may change the view, e.g. a resize of the terminal
+        self.dirty = true;
+        let timer = Stage::Events.start();
+        let result = match event {
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('z'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: KeyEventKind::Press,
+                ..
+            }) => self.undo(),
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('y'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: KeyEventKind::Press,
+                ..
+            }) => self.redo(),
+            event => match self.model.handle_event(event) {
+                Some(action) => {
+                    self.model.record(&action);
+                    self.execute(action.into_command())
+                }
+                None => Ok(()),
+            },
+        };
+        self.frame_times.finish(timer);
+        result
+    }
+
+    fn execute(&mut self, mut command: Box<dyn Command>) -> Result<()> {
+        let effect = command.apply(&mut self.model);
+        match command.undo() {
+            Undo::Revertible => self.history.record(command),
+            Undo::Ignored => {}
+            Undo::Irreversible => self.history.clear(),
+        }
+        self.perform(effect)
+    }
+
+    fn undo(&mut self) -> Result<()> {
+        let Some(mut command) = self.history.take_undo() else {
+            return Ok(());
+        };
+        tracing::debug!(?command, "Undoing command");
+        let effect = command.revert(&mut self.model);
+        self.history.undone(command);
+        self.perform(effect)
+    }
+
+    fn redo(&mut self) -> Result<()> {
+        let Some(mut command) = self.history.take_redo() else {
+            return Ok(());
+        };
+        tracing::debug!(?command, "Redoing command");
+        let effect = command.apply(&mut self.model);
+        self.history.redone(command);
+        self.perform(effect)
+    }
+
+    /// Performs the side effect of a command and reduces its outcome into the model.
+    fn perform(&mut self, effect: Option<Effect>) -> Result<()> {
+        let Some(effect) = effect else {
+            return Ok(());
+        };
+        match effect {
+            Effect::Refresh => self.background_task_state.commands.send(UiCommand::Refresh),
+            Effect::ToggleAutoRefresh => {
+                self.background_task_state
+                    .commands
+                    .send(UiCommand::ToggleAutoRefresh);
             }
-            Some(Action::SetRecipientSelection(index)) => {
-                let send_data = self.view.ensure_send_mut();
-                send_data.list.select(index);
+            Effect::RetryRefresh => self.background_task_state.commands.send(UiCommand::Retry),
+            Effect::Exit => self.background_task_state.shutdown.request(),
+            Effect::SendMessage(msg) => {
+                let recipient = msg.entity_name.clone();
+                let request = self.entity_command(msg);
+                self.send(vec![request], PendingRequest::Message { recipient });
             }
-            Some(Action::TextInput(input)) => {
-                let send_data = self.view.ensure_send_mut();
-                send_data.list.select(None);
-                if matches!(send_data.stage, SendStage::EntitySelect) {
-                    send_data.input.input(input);
-                } else if let PayloadTab::UpdateFrequency(freq_input) = &mut send_data.tab {
-                    freq_input.input(input);
+            Effect::RefreshAdmin => self.refresh_admin_state()?,
+            Effect::SendAdminCommand(command) => self.send_admin_command(command),
+            Effect::SaveMacros => {
+                if let Err(e) = macros::save(&self.model.macros) {
+                    tracing::warn!(%e, "Failed to save macros: {e:#}");
                 }
             }
-            Some(Action::SendMessage(msg)) => {
-                let popup_text = self.send_message(msg)?;
-                self.view = View::PopUp(popup_text);
+            Effect::RunMacro(recorded) => self.run_macro(recorded),
+        }
+        Ok(())
+    }
+
+    /// Hands the requests to the network layer, the replies are shown once they arrive.
+    fn send(&mut self, requests: Vec<ClientApiCommand>, pending: PendingRequest) {
+        let id = RequestId(self.next_request_id);
+        self.next_request_id += 1;
+        self.pending.insert(id, pending);
+        self.background_task_state
+            .commands
+            .send(UiCommand::Send { id, requests });
+    }
+
+    /// Shows the outcome of the requests sent with [`Self::send`].
+    fn finish_request(&mut self, id: RequestId, replies: Vec<Reply>) {
+        let Some(pending) = self.pending.remove(&id) else {
+            tracing::warn!(?id, "Ignoring replies to an unknown request");
+            return;
+        };
+        let mut replies = replies.into_iter();
+        match pending {
+            PendingRequest::Message { recipient } => {
+                let text = replies
+                    .next()
+                    .map_or_else(|| strings().update_unknown_error.to_owned(), message_text);
+                self.reduce(Update::MessageSent { recipient, text });
             }
-            Some(Action::ChangePayloadTab(tab)) => {
-                let send_data = self.view.ensure_send_mut();
-                send_data.tab = tab;
+            PendingRequest::Admin { description } => {
+                let t = strings();
+                let text = match replies.next() {
+                    Some(Ok(reply)) if matches!(reply.code(), Code::Ok) => {
+                        format!("{description}: {}", t.admin_succeeded)
+                    }
+                    Some(Ok(reply)) => {
+                        format!("{description}: {}: {}", t.admin_rejected, reply.message)
+                    }
+                    Some(Err(e)) => format!("{description}: {e}"),
+                    None => format!("{description}: {}", RequestError::Timeout),
+                };
+                self.reduce(Update::AdminStatus(text));
+                // shows the effect of the command right away
+                self.last_admin_refresh = None;
             }
-            Some(Action::ToggleAirConditioning) => {
-                use crate::utility::Wrapping;
-                let send_data = self.view.ensure_send_mut();
-                if let PayloadTab::AirConditioning(list) = &mut send_data.tab {
-                    let current = Wrapping::new(list.selected().unwrap_or_default(), 1);
-                    list.select(Some(current.inc().current()));
+            PendingRequest::Macro { name, steps } => {
+                let mut lines = vec![format!("{name}:")];
+                for (step, skipped) in steps {
+                    let text = match skipped {
+                        Some(reason) => reason,
+                        None => replies.next().map_or_else(
+                            || strings().update_unknown_error.to_owned(),
+                            message_text,
+                        ),
+                    };
+                    lines.push(format!("{step}: {text}"));
                 }
+                self.reduce(Update::MacroFinished(lines.join("\n")));
             }
-            Some(Action::SetLightBrightness(desired_brightness)) => {
-                let send_data = self.view.ensure_send_mut();
-                if let PayloadTab::Light { brightness } = &mut send_data.tab {
-                    *brightness = desired_brightness;
+        }
+    }
+
+    /// Sends the messages of the macro one after another.
+    ///
+    /// Invalid steps, e.g. from an edited macros file, are skipped and reported like failed ones.
+    fn run_macro(&mut self, recorded: Macro) {
+        let mut requests = Vec::new();
+        let mut steps = Vec::new();
+        for step in &recorded.steps {
+            let skipped = match step.message() {
+                Ok(msg) => {
+                    requests.push(self.entity_command(msg));
+                    None
                 }
-            }
-            None => {}
+                Err(e) => Some(format!("{}: {e:#}", strings().macro_step_skipped)),
+            };
+            steps.push((step.to_string(), skipped));
         }
-        Ok(())
+        let name = recorded.name;
+        self.send(requests, PendingRequest::Macro { name, steps });
     }
 
     #[tracing::instrument(skip(self), parent=None)]
-    fn send_message(&mut self, msg: NamedEntityState) -> Result<String> {
-        use home_automation_common::protobuf::{response_code::Code, ClientApiCommand};
-        let msg = ClientApiCommand::named_entity_state(msg);
-        let inner = || {
-            self.background_task_state.requester.send(msg)?;
-            let reply: ResponseCode = self.background_task_state.requester.receive()?;
-            Ok(reply)
+    fn refresh_admin_state(&mut self) -> Result<()> {
+        use home_automation_common::protobuf::{AdminQuery, AdminState};
+        self.last_admin_refresh = Some(Instant::now());
+        self.reduce(Update::LatencyMeasured(self.end_to_end_latency.clone()));
+        let request = ClientApiCommand::admin(
+            &self.admin_token,
+            admin_command::Command::Query(AdminQuery {}),
+        );
+        let Some(connection) = &mut self.background_task_state.connection else {
+            self.reduce(Update::AdminStatus(strings().admin_unsupported.to_owned()));
+            return Ok(());
         };
+        let result = connection.request::<_, AdminState>(request);
 
-        let success = inner().map_or_else(
-            |e: anyhow::Error| {
-                if e.is_zmq_timeout() {
-                    Ok(false)
-                } else {
-                    Err(e)
-                }
-            },
-            |r| Ok(matches!(r.code(), Code::Ok)),
-        )?;
+        let update = match result {
+            Ok(state) => Update::AdminStateRefreshed(state),
+            Err(e) if e.is_termination() => return Err(e),
+            Err(e) => Update::AdminStatus(format!("{}: {e:#}", strings().admin_query_failed)),
+        };
+        self.reduce(update);
+        if matches!(
+            self.model.view,
+            View::Admin(AdminData {
+                performance: Some(_),
+                ..
+            })
+        ) {
+            self.refresh_performance()?;
+        }
+        Ok(())
+    }
+
+    fn refresh_performance(&mut self) -> Result<()> {
+        use home_automation_common::protobuf::{PerformanceCounters, PerformanceQuery};
+        let request = ClientApiCommand::admin(
+            &self.admin_token,
+            admin_command::Command::Performance(PerformanceQuery {}),
+        );
+        let Some(connection) = &mut self.background_task_state.connection else {
+            self.reduce(Update::AdminStatus(
+                strings().performance_unsupported.to_owned(),
+            ));
+            return Ok(());
+        };
+        let result = connection.request::<_, PerformanceCounters>(request);
 
-        let text = if success {
-            "Successfully updated entity configuration"
-        } else {
-            "Unknown error occurred during entity configuration"
+        let update = match result {
+            Ok(counters) => Update::PerformanceRefreshed(counters),
+            Err(e) if e.is_termination() => return Err(e),
+            Err(e) => Update::AdminStatus(format!("{}: {e:#}", strings().admin_performance_failed)),
+        };
+        self.reduce(update);
+        Ok(())
+    }
+
+    fn send_admin_command(&mut self, command: admin_command::Command) {
+        let t = strings();
+        let description = match &command {
+            admin_command::Command::Query(_) => t.admin_query.to_owned(),
+            admin_command::Command::ForceUnregister(name) => {
+                format!("{} {name}", t.admin_force_unregister)
+            }
+            admin_command::Command::Ping(name) => format!("{} {name}", t.admin_ping),
+            admin_command::Command::Shutdown(name) => format!("{} {name}", t.admin_shutdown),
+            admin_command::Command::Restart(name) => format!("{} {name}", t.admin_restart),
+            admin_command::Command::Performance(_) => t.admin_performance.to_owned(),
+        };
+        let request = ClientApiCommand::admin(&self.admin_token, command);
+        self.send(vec![request], PendingRequest::Admin { description });
+    }
+
+    /// Signs the message so the entity can verify the client.
+    fn entity_command(&self, mut msg: NamedEntityState) -> ClientApiCommand {
+        if let Some(signer) = &self.background_task_state.command_signer {
+            signer.sign(&mut msg);
         }
-        .to_owned();
+        ClientApiCommand::named_entity_state(msg)
+    }
+}
 
-        Ok(text)
+/// Text shown for the reply of an entity to a message.
+fn message_text(reply: Reply) -> String {
+    let t = strings();
+    match reply {
+        Ok(r) if matches!(r.code(), Code::Ok) => t.update_succeeded.to_owned(),
+        Ok(r) if !r.message.is_empty() => format!("{}: {}", t.update_failed, r.message),
+        Ok(_) | Err(RequestError::Timeout) => t.update_unknown_error.to_owned(),
+        Err(RequestError::Failed(e)) => format!("{}: {e}", t.update_failed),
     }
 }
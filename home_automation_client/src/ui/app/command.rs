@@ -0,0 +1,657 @@
+use std::{collections::VecDeque, time::Duration};
+
+use home_automation_common::{
+    features,
+    protobuf::{admin_command, NamedEntityState},
+};
+
+use crate::ui::{
+    frame_time::FrameTimeSummary,
+    i18n::strings,
+    macros::{Macro, MacroStep, MACRO_KEYS},
+    model::AppModel,
+    view::{Appearance, PaletteData, PayloadTab, PerformanceData, SendData, SendStage, View},
+};
+
+use super::Action;
+
+/// Maximum number of commands that can be undone.
+const HISTORY_CAPACITY: usize = 100;
+
+/// How a command interacts with the undo history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Undo {
+    /// The command can be reverted and is recorded in the history.
+    Revertible,
+    /// The command did not change the state of the UI, e.g. a refresh, and is not recorded.
+    Ignored,
+    /// The command has effects outside of the UI, e.g. sending a message, that cannot be reverted.
+    /// The earlier commands cannot be reverted either because the UI moved on.
+    Irreversible,
+}
+
+/// Side effect of a command that the [`App`][super::App] performs outside of the model.
+#[derive(Debug, Clone)]
+pub enum Effect {
+    Refresh,
+    ToggleAutoRefresh,
+    RetryRefresh,
+    Exit,
+    SendMessage(NamedEntityState),
+    RefreshAdmin,
+    SendAdminCommand(admin_command::Command),
+    SaveMacros,
+    RunMacro(Macro),
+}
+
+/// State transition of the [`AppModel`] triggered by an [`Action`] of the user.
+///
+/// Commands only change the model, everything else is returned as [`Effect`].
+pub trait Command: std::fmt::Debug {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect>;
+
+    /// Reverts the last [`apply`][Command::apply], only called for [revertible][Undo::Revertible]
+    /// commands.
+    fn revert(&mut self, _model: &mut AppModel) -> Option<Effect> {
+        None
+    }
+
+    /// Determines how the command is recorded in the history after it was applied.
+    fn undo(&self) -> Undo;
+}
+
+impl Action {
+    pub fn into_command(self) -> Box<dyn Command> {
+        match self {
+            Action::ChangeView(view) => Box::new(ChangeView(view)),
+            Action::Refresh => Box::new(Perform(Effect::Refresh)),
+            Action::ToggleAutoRefresh => Box::new(Perform(Effect::ToggleAutoRefresh)),
+            Action::RetryRefresh => Box::new(Perform(Effect::RetryRefresh)),
+            Action::Exit => Box::new(Perform(Effect::Exit)),
+            Action::SetMessageRecipient(recipient) => Box::new(SetMessageRecipient {
+                recipient,
+                previous: None,
+            }),
+            Action::SetRecipientSelection(index) => Box::new(SetRecipientSelection {
+                index,
+                previous: None,
+            }),
+            Action::TextInput(input) => Box::new(TextInput {
+                input,
+                previous: None,
+            }),
+            Action::SendMessage(message) => Box::new(Perform(Effect::SendMessage(message))),
+            Action::ChangePayloadTab(tab) => Box::new(ChangePayloadTab(tab)),
+            Action::ToggleAirConditioning => Box::new(ToggleAirConditioning),
+            Action::SetLightBrightness(brightness) => Box::new(SetLightBrightness {
+                brightness,
+                previous: None,
+            }),
+            Action::RefreshAdmin => Box::new(Perform(Effect::RefreshAdmin)),
+            Action::SetAdminSelection(index) => Box::new(SetAdminSelection {
+                index,
+                previous: None,
+            }),
+            Action::SendAdminCommand(command) => {
+                Box::new(Perform(Effect::SendAdminCommand(command)))
+            }
+            Action::TogglePerformance => Box::new(TogglePerformance),
+            Action::ToggleFrameTimes => Box::new(ToggleFrameTimes),
+            Action::OpenPalette => Box::new(SetPalette(Some(PaletteData::default()))),
+            Action::ClosePalette => Box::new(SetPalette(None)),
+            Action::PaletteInput(input) => Box::new(PaletteInput(input)),
+            Action::SetPaletteSelection(index) => Box::new(SetPaletteSelection(index)),
+            Action::FromPalette(action) => Box::new(FromPalette(action.into_command())),
+            Action::ToggleRecording => Box::new(ToggleRecording),
+            Action::RunMacro(key) => Box::new(RunMacro(key)),
+            Action::SetAppearance(appearance) => Box::new(SetAppearance(appearance)),
+            Action::SetChartSensor(sensor) => Box::new(SetChartSensor(Some(sensor))),
+            Action::SetChartWindow(window) => Box::new(SetChartWindow(window)),
+            Action::SetTopologyScroll(scroll) => Box::new(SetTopologyScroll(scroll)),
+        }
+    }
+}
+
+/// Applied commands that can be undone and undone commands that can be redone.
+#[derive(Debug, Default)]
+pub struct History {
+    done: VecDeque<Box<dyn Command>>,
+    undone: Vec<Box<dyn Command>>,
+}
+
+impl History {
+    /// Records a newly applied command, which discards the commands that could be redone.
+    pub fn record(&mut self, command: Box<dyn Command>) {
+        self.undone.clear();
+        if self.done.len() == HISTORY_CAPACITY {
+            self.done.pop_front();
+        }
+        self.done.push_back(command);
+    }
+
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+
+    pub fn take_undo(&mut self) -> Option<Box<dyn Command>> {
+        self.done.pop_back()
+    }
+
+    pub fn take_redo(&mut self) -> Option<Box<dyn Command>> {
+        self.undone.pop()
+    }
+
+    pub fn undone(&mut self, command: Box<dyn Command>) {
+        self.undone.push(command);
+    }
+
+    pub fn redone(&mut self, command: Box<dyn Command>) {
+        self.done.push_back(command);
+    }
+}
+
+/// Switches to the view, the command keeps the previous view to switch back.
+#[derive(Debug)]
+struct ChangeView(View);
+
+impl Command for ChangeView {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if matches!(self.0, View::Admin(_)) && !model.controller.supports(features::ADMIN) {
+            self.0 = View::PopUp(strings().admin_unsupported.to_owned());
+        }
+        std::mem::swap(&mut model.view, &mut self.0);
+        matches!(model.view, View::Admin(_)).then_some(Effect::RefreshAdmin)
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.apply(model)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+/// Selects the recipient and continues with the payload selection.
+#[derive(Debug)]
+struct SetMessageRecipient {
+    recipient: String,
+    previous: Option<SendData>,
+}
+
+impl Command for SetMessageRecipient {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        let send_data = model.view.ensure_send_mut();
+        self.previous = Some(send_data.clone());
+        send_data.input.cancel_selection();
+        send_data.input.select_all();
+        send_data.input.insert_str(self.recipient.clone());
+        send_data.list.select(None);
+        send_data.stage = SendStage::PayloadSelect {};
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let Some(previous) = self.previous.take() {
+            *model.view.ensure_send_mut() = previous;
+        }
+        None
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+#[derive(Debug)]
+struct SetRecipientSelection {
+    index: Option<usize>,
+    previous: Option<Option<usize>>,
+}
+
+impl Command for SetRecipientSelection {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        let send_data = model.view.ensure_send_mut();
+        self.previous = Some(send_data.list.selected());
+        send_data.list.select(self.index);
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let Some(previous) = self.previous.take() {
+            model.view.ensure_send_mut().list.select(previous);
+        }
+        None
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+/// Edits the recipient or the update frequency, depending on the stage.
+#[derive(Debug)]
+struct TextInput {
+    input: tui_textarea::Input,
+    /// state before the input if the input modified it
+    previous: Option<SendData>,
+}
+
+impl Command for TextInput {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        let send_data = model.view.ensure_send_mut();
+        let previous = send_data.clone();
+        send_data.list.select(None);
+        let modified = if matches!(send_data.stage, SendStage::EntitySelect) {
+            send_data.input.input(self.input.clone())
+        } else if let PayloadTab::UpdateFrequency(freq_input) = &mut send_data.tab {
+            freq_input.input(self.input.clone())
+        } else {
+            false
+        };
+        self.previous = modified.then_some(previous);
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let Some(previous) = self.previous.take() {
+            *model.view.ensure_send_mut() = previous;
+        }
+        None
+    }
+
+    fn undo(&self) -> Undo {
+        // cursor movements and other events without modification are not worth an undo step
+        if self.previous.is_some() {
+            Undo::Revertible
+        } else {
+            Undo::Ignored
+        }
+    }
+}
+
+/// Switches the payload tab, the command keeps the previous tab to switch back.
+#[derive(Debug)]
+struct ChangePayloadTab(PayloadTab);
+
+impl Command for ChangePayloadTab {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        std::mem::swap(&mut model.view.ensure_send_mut().tab, &mut self.0);
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.apply(model)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+#[derive(Debug)]
+struct ToggleAirConditioning;
+
+impl Command for ToggleAirConditioning {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        use crate::utility::Wrapping;
+        let send_data = model.view.ensure_send_mut();
+        if let PayloadTab::AirConditioning(list) = &mut send_data.tab {
+            let current = Wrapping::new(list.selected().unwrap_or_default(), 1);
+            list.select(Some(current.inc().current()));
+        }
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.apply(model)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+#[derive(Debug)]
+struct SetLightBrightness {
+    brightness: f32,
+    previous: Option<f32>,
+}
+
+impl Command for SetLightBrightness {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        let send_data = model.view.ensure_send_mut();
+        if let PayloadTab::Light { brightness } = &mut send_data.tab {
+            self.previous = Some(std::mem::replace(brightness, self.brightness));
+        }
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        let send_data = model.view.ensure_send_mut();
+        if let (PayloadTab::Light { brightness }, Some(previous)) =
+            (&mut send_data.tab, self.previous.take())
+        {
+            *brightness = previous;
+        }
+        None
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+/// Shows or hides the performance counters in the admin view.
+#[derive(Debug)]
+struct TogglePerformance;
+
+impl Command for TogglePerformance {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        let View::Admin(data) = &mut model.view else {
+            return None;
+        };
+        if !model.controller.supports(features::PERFORMANCE) {
+            data.status = strings().performance_unsupported.to_owned();
+            return None;
+        }
+        match data.performance {
+            Some(_) => {
+                data.performance = None;
+                None
+            }
+            None => {
+                data.performance = Some(PerformanceData::default());
+                Some(Effect::RefreshAdmin)
+            }
+        }
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.apply(model)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+/// Shows or hides the frame times on top of the view.
+#[derive(Debug)]
+struct ToggleFrameTimes;
+
+impl Command for ToggleFrameTimes {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        model.frame_times = match model.frame_times {
+            Some(_) => None,
+            None => Some(FrameTimeSummary::default()),
+        };
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.apply(model)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+#[derive(Debug)]
+struct SetAdminSelection {
+    index: Option<usize>,
+    previous: Option<Option<usize>>,
+}
+
+impl Command for SetAdminSelection {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let View::Admin(data) = &mut model.view {
+            self.previous = Some(data.table.selected());
+            data.table.select(self.index);
+        }
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let (View::Admin(data), Some(previous)) = (&mut model.view, self.previous.take()) {
+            // the entities may have changed in the meantime
+            let entity_count = data.state.entities.len();
+            data.table
+                .select(previous.filter(|&index| index < entity_count));
+        }
+        None
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+/// Only performs the effect, the model is changed once the effect finished.
+#[derive(Debug)]
+struct Perform(Effect);
+
+impl Command for Perform {
+    fn apply(&mut self, _model: &mut AppModel) -> Option<Effect> {
+        Some(self.0.clone())
+    }
+
+    fn revert(&mut self, _model: &mut AppModel) -> Option<Effect> {
+        // only toggling the auto-refresh is revertible, by toggling it again
+        matches!(self.0, Effect::ToggleAutoRefresh).then_some(Effect::ToggleAutoRefresh)
+    }
+
+    fn undo(&self) -> Undo {
+        match self.0 {
+            Effect::ToggleAutoRefresh => Undo::Revertible,
+            Effect::Refresh
+            | Effect::RetryRefresh
+            | Effect::Exit
+            | Effect::RefreshAdmin
+            | Effect::SaveMacros => Undo::Ignored,
+            Effect::SendMessage(_) | Effect::SendAdminCommand(_) | Effect::RunMacro(_) => {
+                Undo::Irreversible
+            }
+        }
+    }
+}
+
+/// Opens or closes the command palette.
+///
+/// The palette is a transient overlay, so its commands are not recorded in the history.
+#[derive(Debug)]
+struct SetPalette(Option<PaletteData>);
+
+impl Command for SetPalette {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        model.palette = self.0.take();
+        None
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Ignored
+    }
+}
+
+#[derive(Debug)]
+struct PaletteInput(tui_textarea::Input);
+
+impl Command for PaletteInput {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let Some(palette) = &mut model.palette {
+            if palette.input.input(self.0.clone()) {
+                // the matching actions changed, so start again with the best match
+                palette.list.select(Some(0));
+            }
+        }
+        None
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Ignored
+    }
+}
+
+#[derive(Debug)]
+struct SetPaletteSelection(Option<usize>);
+
+impl Command for SetPaletteSelection {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let Some(palette) = &mut model.palette {
+            palette.list.select(self.0);
+        }
+        None
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Ignored
+    }
+}
+
+/// Closes the palette and runs the chosen command, which determines the history entry.
+#[derive(Debug)]
+struct FromPalette(Box<dyn Command>);
+
+impl Command for FromPalette {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        model.palette = None;
+        self.0.apply(model)
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.0.revert(model)
+    }
+
+    fn undo(&self) -> Undo {
+        self.0.undo()
+    }
+}
+
+/// Starts a recording or stops it and binds the recorded steps to the first free function key.
+#[derive(Debug)]
+struct ToggleRecording;
+
+impl Command for ToggleRecording {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        let Some(steps) = model.recording.take() else {
+            model.recording = Some(Vec::new());
+            return None;
+        };
+        if steps.is_empty() {
+            return None;
+        }
+        let Some(key) = MACRO_KEYS.find(|key| model.macros.iter().all(|m| m.key != *key)) else {
+            model.view = View::PopUp(strings().macro_keys_exhausted.to_owned());
+            return None;
+        };
+        let mut entities: Vec<_> = steps.iter().map(MacroStep::entity).collect();
+        entities.dedup();
+        model.macros.push(Macro {
+            name: (strings().macro_name)(&entities.join(", ")),
+            key,
+            steps,
+        });
+        model.view = View::PopUp((strings().macro_recorded)(key));
+        Some(Effect::SaveMacros)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Ignored
+    }
+}
+
+/// Replays the macro bound to the function key, unbound keys are ignored.
+#[derive(Debug)]
+struct RunMacro(u8);
+
+impl Command for RunMacro {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        let recorded = model.macros.iter().find(|m| m.key == self.0)?;
+        Some(Effect::RunMacro(recorded.clone()))
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Irreversible
+    }
+}
+
+/// Switches the appearance, the command keeps the previous appearance to switch back.
+#[derive(Debug)]
+struct SetAppearance(Appearance);
+
+impl Command for SetAppearance {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        std::mem::swap(&mut model.appearance, &mut self.0);
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.apply(model)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+#[derive(Debug)]
+struct SetChartSensor(Option<String>);
+
+impl Command for SetChartSensor {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let View::Chart(data) = &mut model.view {
+            std::mem::swap(&mut data.sensor, &mut self.0);
+        }
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.apply(model)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+#[derive(Debug)]
+struct SetChartWindow(Duration);
+
+impl Command for SetChartWindow {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let View::Chart(data) = &mut model.view {
+            std::mem::swap(&mut data.window, &mut self.0);
+        }
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.apply(model)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
+
+#[derive(Debug)]
+struct SetTopologyScroll(u16);
+
+impl Command for SetTopologyScroll {
+    fn apply(&mut self, model: &mut AppModel) -> Option<Effect> {
+        if let View::Topology(scroll) = &mut model.view {
+            std::mem::swap(scroll, &mut self.0);
+        }
+        None
+    }
+
+    fn revert(&mut self, model: &mut AppModel) -> Option<Effect> {
+        self.apply(model)
+    }
+
+    fn undo(&self) -> Undo {
+        Undo::Revertible
+    }
+}
@@ -0,0 +1,127 @@
+//! Durations of the stages of the main loop, so that UI jank, e.g. slow renders of large tables,
+//! can be profiled.
+//!
+//! Every stage runs in a `trace` span, e.g. enabled with `RUST_LOG=home_automation_client=trace`.
+
+use std::time::{Duration, Instant};
+
+use home_automation_common::{latency::LatencyWindow, STATISTICS_LOG_INTERVAL};
+use tracing::span::EnteredSpan;
+
+/// Frames that take longer are logged.
+const SLOW_FRAME: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Rendering the model to the terminal.
+    Draw,
+    /// Handling an input event, including the requests it triggers.
+    Events,
+    /// Taking the system state from the background refresher.
+    Receive,
+}
+
+impl Stage {
+    pub fn start(self) -> StageTimer {
+        let span = match self {
+            Self::Draw => tracing::trace_span!(parent: None, "draw"),
+            Self::Events => tracing::trace_span!(parent: None, "handle event"),
+            Self::Receive => tracing::trace_span!(parent: None, "receive state"),
+        };
+        StageTimer {
+            stage: self,
+            start: Instant::now(),
+            _span: span.entered(),
+        }
+    }
+}
+
+/// Measures a stage until it is passed to [`FrameTimes::finish`].
+pub struct StageTimer {
+    stage: Stage,
+    start: Instant,
+    _span: EnteredSpan,
+}
+
+#[derive(Debug)]
+pub struct FrameTimes {
+    draw: LatencyWindow,
+    events: LatencyWindow,
+    receive: LatencyWindow,
+    frames: u32,
+    frames_since: Instant,
+    fps: f32,
+    next_log: Instant,
+}
+
+impl Default for FrameTimes {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            draw: LatencyWindow::default(),
+            events: LatencyWindow::default(),
+            receive: LatencyWindow::default(),
+            frames: 0,
+            frames_since: now,
+            fps: 0.0,
+            next_log: now + STATISTICS_LOG_INTERVAL,
+        }
+    }
+}
+
+/// Percentiles of the stages in milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameTimeSummary {
+    pub draw_p50_ms: f32,
+    pub draw_p99_ms: f32,
+    pub events_p99_ms: f32,
+    pub receive_p99_ms: f32,
+    pub fps: f32,
+}
+
+impl FrameTimes {
+    pub fn finish(&mut self, timer: StageTimer) {
+        let elapsed = timer.start.elapsed();
+        match timer.stage {
+            Stage::Draw => {
+                self.draw.record(elapsed);
+                self.count_frame();
+            }
+            Stage::Events => self.events.record(elapsed),
+            Stage::Receive => self.receive.record(elapsed),
+        }
+        if elapsed >= SLOW_FRAME {
+            tracing::debug!(stage = ?timer.stage, ?elapsed, "Slow {:?} stage took {elapsed:?}", timer.stage);
+        }
+        if Instant::now() >= self.next_log {
+            let summary = self.summary();
+            tracing::debug!(?summary, "Frame times: {summary:?}");
+            self.next_log += STATISTICS_LOG_INTERVAL;
+        }
+    }
+
+    fn count_frame(&mut self) {
+        self.frames += 1;
+        let elapsed = self.frames_since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frames as f32 / elapsed.as_secs_f32();
+            self.frames = 0;
+            self.frames_since = Instant::now();
+        }
+    }
+
+    pub fn summary(&self) -> FrameTimeSummary {
+        let milliseconds = |window: &LatencyWindow, fraction| {
+            window
+                .percentile(fraction)
+                .map_or(0.0, |duration| duration.as_secs_f32() * 1000.0)
+        };
+        FrameTimeSummary {
+            draw_p50_ms: milliseconds(&self.draw, 0.5),
+            draw_p99_ms: milliseconds(&self.draw, 0.99),
+            events_p99_ms: milliseconds(&self.events, 0.99),
+            receive_p99_ms: milliseconds(&self.receive, 0.99),
+            fps: self.fps,
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write as _,
+    sync::mpsc::RecvTimeoutError,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context as _, Result};
+use home_automation_common::{protobuf::EntityMetadata, EntityState};
+
+use crate::{
+    network::{Notification, UiCommand, UiEvent},
+    utility::HashMapExt as _,
+};
+
+use super::{i18n::strings, view::DisplayEntityState, BackgroundTaskState};
+
+/// Interval in which the system state is printed.
+const DUMP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Prints the system state periodically instead of running the interactive UI, e.g. when the
+/// output is redirected to a file or the client runs in CI.
+pub fn run(task_state: BackgroundTaskState) -> Result<()> {
+    let BackgroundTaskState {
+        events,
+        commands,
+        shutdown,
+        ..
+    } = task_state;
+    let mut next_dump = Instant::now();
+    while !shutdown.sleep_until_or_shutdown(next_dump) {
+        next_dump += DUMP_INTERVAL;
+        commands.send(UiCommand::Refresh);
+        let first = match events.recv_timeout(DUMP_INTERVAL) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        let mut entities = None;
+        for event in first.into_iter().chain(events.try_iter()) {
+            match event {
+                UiEvent::StateUpdate {
+                    entities: state,
+                    metadata,
+                    ..
+                } => entities = Some((state, metadata)),
+                UiEvent::ConnectionStatus(false) => {
+                    writeln!(stdout, "{}", strings().banner_unreachable)
+                        .context("Failed to print system state")?;
+                }
+                UiEvent::ConnectionStatus(true) | UiEvent::CommandResult(_) => {}
+                // there is nobody to retry, so it is retried with the next dump
+                UiEvent::Notification(Notification::TaskFailed(error)) => {
+                    writeln!(stdout, "{}: {error}", strings().refresh_task_retrying)
+                        .context("Failed to print system state")?;
+                    commands.send(UiCommand::Retry);
+                }
+            }
+        }
+        if let Some((entities, metadata)) = entities {
+            print_state(&mut stdout, &entities, &metadata)
+                .context("Failed to print system state")?;
+        }
+    }
+    Ok(())
+}
+
+fn print_state(
+    out: &mut impl std::io::Write,
+    entities: &HashMap<String, EntityState>,
+    metadata: &BTreeMap<String, EntityMetadata>,
+) -> std::io::Result<()> {
+    use time::format_description::well_known::Iso8601;
+    let now = time::OffsetDateTime::now_utc()
+        .format(&Iso8601::DEFAULT)
+        .unwrap_or_default();
+    writeln!(out, "--- {now} ---")?;
+    for (name, state) in entities.iter_stable() {
+        writeln!(
+            out,
+            "{name}\t{}\t{}",
+            state.entity_type(),
+            DisplayEntityState::with_metadata(state, metadata.get(name))
+        )?;
+    }
+    out.flush()
+}
@@ -0,0 +1,503 @@
+use std::sync::OnceLock;
+
+use super::frame_time::FrameTimeSummary;
+
+/// Optional language of the user interface, `en` (default) or `de`.
+pub const ENV_CLIENT_LANGUAGE: &str = "HOME_AUTOMATION_CLIENT_LANGUAGE";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+}
+
+impl std::str::FromStr for Language {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "en" | "english" => Ok(Self::English),
+            "de" | "german" | "deutsch" => Ok(Self::German),
+            _ => anyhow::bail!("Unknown language {s}, expected en or de"),
+        }
+    }
+}
+
+impl Language {
+    /// Reads the language from the environment, falls back to English if it is missing or unknown.
+    fn from_env() -> Self {
+        let Ok(language) = std::env::var(ENV_CLIENT_LANGUAGE) else {
+            return Self::default();
+        };
+        language
+            .parse()
+            .inspect_err(|e| tracing::warn!(%e, "Ignoring {ENV_CLIENT_LANGUAGE}: {e}"))
+            .unwrap_or_default()
+    }
+
+    fn strings(self) -> &'static Strings {
+        match self {
+            Self::English => &ENGLISH,
+            Self::German => &GERMAN,
+        }
+    }
+}
+
+/// Returns the user-facing texts in the configured language.
+pub fn strings() -> &'static Strings {
+    static LANGUAGE: OnceLock<Language> = OnceLock::new();
+    LANGUAGE.get_or_init(Language::from_env).strings()
+}
+
+/// Bundle of all user-facing texts of the client.
+///
+/// The labels of the key instructions keep their surrounding spaces because they are rendered
+/// next to the highlighted key.
+pub struct Strings {
+    pub app_title: &'static str,
+
+    pub key_send_message: &'static str,
+    pub key_refresh: &'static str,
+    pub key_auto_refresh: &'static str,
+    pub key_admin: &'static str,
+    pub key_chart: &'static str,
+    pub key_topology: &'static str,
+    pub key_palette: &'static str,
+    pub key_record_macro: &'static str,
+    pub key_quit: &'static str,
+    pub key_accept_input: &'static str,
+    pub key_switch_focus: &'static str,
+    pub key_select: &'static str,
+    pub key_undo: &'static str,
+    pub key_redo: &'static str,
+    pub key_abort: &'static str,
+    pub key_run: &'static str,
+    pub key_close: &'static str,
+    pub key_ping: &'static str,
+    pub key_force_unregister: &'static str,
+    pub key_shutdown: &'static str,
+    pub key_restart: &'static str,
+    pub key_performance: &'static str,
+    pub key_back: &'static str,
+    pub key_select_sensor: &'static str,
+    pub key_zoom: &'static str,
+    pub key_scroll: &'static str,
+    pub key_press: &'static str,
+    pub key_close_dialog: &'static str,
+
+    pub title_info: &'static str,
+    pub title_palette: &'static str,
+    pub title_send_message: &'static str,
+    pub title_entity: &'static str,
+    pub title_payload: &'static str,
+    pub title_tasks: &'static str,
+    pub title_entities: &'static str,
+    pub title_recent_errors: fn(u64, u64) -> String,
+    pub title_performance: fn(u32, u32) -> String,
+    pub title_chart: fn(&str, &str) -> String,
+    pub title_topology: &'static str,
+
+    pub header_entities: [&'static str; 4],
+    pub header_tasks: [&'static str; 3],
+    pub header_admin_entities: [&'static str; 5],
+    pub header_sockets: [&'static str; 6],
+    pub header_commands: [&'static str; 4],
+
+    pub tab_update_frequency: &'static str,
+    pub tab_light: &'static str,
+    pub tab_air_conditioning: &'static str,
+    pub on: &'static str,
+    pub off: &'static str,
+    pub humidity: &'static str,
+    pub temperature: &'static str,
+    pub brightness: &'static str,
+    pub liveness_ok: &'static str,
+    pub liveness_stale: fn(u64) -> String,
+    pub liveness_missing: &'static str,
+    pub chart_time_axis: &'static str,
+    pub chart_now: &'static str,
+    pub chart_no_samples: &'static str,
+
+    pub task_running: &'static str,
+    pub task_stopped: &'static str,
+    pub task_failed: &'static str,
+    pub back_channel_healthy: &'static str,
+    pub back_channel_broken: &'static str,
+    pub back_channel: &'static str,
+    pub topology_controller: fn(usize) -> String,
+    pub topology_sensors: &'static str,
+    pub topology_actuators: &'static str,
+    pub topology_no_room: &'static str,
+    pub flapping: &'static str,
+    pub suspected: &'static str,
+    pub clock_offset: fn(i64) -> String,
+    pub seconds_ago: fn(f32) -> String,
+
+    pub banner_unreachable: &'static str,
+    pub banner_incompatible: fn(u32, u32) -> String,
+    pub banner_recording: fn(usize) -> String,
+    pub banner_task_failed: fn(&str) -> String,
+    pub refresh_task_stopped: &'static str,
+    pub refresh_task_retrying: &'static str,
+    pub frame_times: fn(&FrameTimeSummary) -> String,
+
+    pub update_succeeded: &'static str,
+    pub update_failed: &'static str,
+    pub update_unknown_error: &'static str,
+    pub admin_unsupported: &'static str,
+    pub performance_unsupported: &'static str,
+    pub admin_query_failed: &'static str,
+    pub admin_query: &'static str,
+    pub admin_force_unregister: &'static str,
+    pub admin_ping: &'static str,
+    pub admin_shutdown: &'static str,
+    pub admin_restart: &'static str,
+    pub admin_performance: &'static str,
+    pub admin_performance_failed: &'static str,
+    pub admin_succeeded: &'static str,
+    pub admin_rejected: &'static str,
+
+    pub macro_keys_exhausted: &'static str,
+    pub macro_recorded: fn(u8) -> String,
+    pub macro_name: fn(&str) -> String,
+    pub macro_step_skipped: &'static str,
+
+    pub action_refresh: &'static str,
+    pub action_toggle_auto_refresh: &'static str,
+    pub action_retry_refresh: &'static str,
+    pub action_show_monitor: &'static str,
+    pub action_send_message: &'static str,
+    pub action_show_admin: &'static str,
+    pub action_show_chart: &'static str,
+    pub action_show_topology: &'static str,
+    pub action_toggle_performance: &'static str,
+    pub action_toggle_recording: &'static str,
+    pub action_toggle_high_contrast: &'static str,
+    pub action_toggle_ascii: &'static str,
+    pub action_toggle_frame_times: &'static str,
+    pub action_run_macro: fn(&str, u8) -> String,
+    pub action_quit: &'static str,
+    pub action_send_to_entity: fn(&str) -> String,
+}
+
+impl Strings {
+    /// Translates the quantities the client knows, e.g. the `kind` of an entity description, and
+    /// keeps the others.
+    pub fn quantity<'a>(&self, kind: &'a str) -> &'a str {
+        match kind {
+            "humidity" => self.humidity,
+            "temperature" => self.temperature,
+            "brightness" => self.brightness,
+            other => other,
+        }
+    }
+}
+
+static ENGLISH: Strings = Strings {
+    app_title: " Home Automation Client ",
+
+    key_send_message: " Send Message ",
+    key_refresh: " Refresh ",
+    key_auto_refresh: " Auto-Refresh ",
+    key_admin: " Admin ",
+    key_chart: " Chart ",
+    key_topology: " Topology ",
+    key_palette: " Palette ",
+    key_record_macro: " Record Macro ",
+    key_quit: " Quit ",
+    key_accept_input: " Accept input ",
+    key_switch_focus: " Switch focus ",
+    key_select: " Select ",
+    key_undo: " Undo ",
+    key_redo: " Redo ",
+    key_abort: " Abort ",
+    key_run: " Run ",
+    key_close: " Close ",
+    key_ping: " Ping ",
+    key_force_unregister: " Force unregister ",
+    key_shutdown: " Shutdown ",
+    key_restart: " Restart ",
+    key_performance: " Performance ",
+    key_back: " Back ",
+    key_select_sensor: " Select sensor ",
+    key_zoom: " Zoom ",
+    key_scroll: " Scroll ",
+    key_press: " Press ",
+    key_close_dialog: " to close dialog ",
+
+    title_info: "Info",
+    title_palette: "Command palette",
+    title_send_message: "Send Message",
+    title_entity: "Entity",
+    title_payload: "Payload",
+    title_tasks: "Tasks",
+    title_entities: "Entities",
+    title_recent_errors: |rejected, mismatches| {
+        format!("Recent errors ({rejected} rejected requests, {mismatches} payload mismatches)")
+    },
+    title_performance: |pending, chunked| {
+        format!("Performance ({pending} waiting queries, {chunked} chunked responses)")
+    },
+    title_chart: |sensor, current| format!("{sensor}: {current}"),
+    title_topology: "Topology",
+
+    header_entities: ["Entity", "Type", "Value", "Liveness"],
+    header_tasks: ["Task", "Status", "Error"],
+    header_admin_entities: [
+        "Entity",
+        "Type",
+        "Last heartbeat",
+        "Back-channel",
+        "Latency p50/p99 (ingest | end-to-end)",
+    ],
+    header_sockets: [
+        "Socket",
+        "Sent msg/s",
+        "Sent B/s",
+        "Received msg/s",
+        "Received B/s",
+        "Dropped",
+    ],
+    header_commands: ["Command", "Requests", "Failed", "Latency p50/p90/p99"],
+
+    tab_update_frequency: "Update frequency (Hz)",
+    tab_light: "Light (%)",
+    tab_air_conditioning: "Air conditioning (On/Off)",
+    on: "On",
+    off: "Off",
+    humidity: "humidity",
+    temperature: "temperature",
+    brightness: "brightness",
+    liveness_ok: "OK",
+    liveness_stale: |seconds| format!("stale {seconds}s"),
+    liveness_missing: "missing",
+    chart_time_axis: "Time",
+    chart_now: "now",
+    chart_no_samples: "No sensor values received yet, refresh to collect them",
+
+    task_running: "Running",
+    task_stopped: "Stopped",
+    task_failed: "Failed",
+    back_channel_healthy: "Healthy",
+    back_channel_broken: "Broken",
+    back_channel: "back-channel",
+    topology_controller: |entities| format!("Controller ({entities} entities)"),
+    topology_sensors: "Sensors",
+    topology_actuators: "Actuators",
+    topology_no_room: "no room",
+    flapping: "flapping",
+    suspected: "suspected",
+    clock_offset: |ms| format!("clock {:+.1}s", ms as f32 / 1000.0),
+    seconds_ago: |seconds| format!("{seconds:.1}s ago"),
+
+    banner_unreachable: "Controller unreachable, reconnecting...",
+    banner_incompatible: |controller, client| {
+        format!("Controller uses protocol version {controller} instead of {client}, some features are unavailable")
+    },
+    banner_recording: |steps| format!("Recording macro ({steps} steps), press <CTRL-K> to stop"),
+    banner_task_failed: |error| format!("Refreshing failed: {error}, press <CTRL-T> to retry"),
+    refresh_task_stopped: "Refresh task stopped, restart the client",
+    refresh_task_retrying: "Refreshing failed, retrying",
+    frame_times: |t| {
+        format!(
+            " draw {:.1}/{:.1} ms, input {:.1} ms, receive {:.1} ms, {:.0} fps ",
+            t.draw_p50_ms, t.draw_p99_ms, t.events_p99_ms, t.receive_p99_ms, t.fps
+        )
+    },
+
+    update_succeeded: "Successfully updated entity configuration",
+    update_failed: "Failed to update entity configuration",
+    update_unknown_error: "Unknown error occurred during entity configuration",
+    admin_unsupported: "The controller does not support the admin API",
+    performance_unsupported: "The controller does not report performance counters",
+    admin_query_failed: "Failed to query admin state",
+    admin_query: "Query",
+    admin_force_unregister: "Force unregister",
+    admin_ping: "Ping",
+    admin_shutdown: "Shutdown",
+    admin_restart: "Restart",
+    admin_performance: "Performance counters",
+    admin_performance_failed: "Failed to query performance counters",
+    admin_succeeded: "succeeded",
+    admin_rejected: "rejected by controller",
+
+    macro_keys_exhausted:
+        "All function keys are bound to macros, remove one from the macros file first",
+    macro_recorded: |key| format!("Recorded macro, press <F{key}> to replay it"),
+    macro_name: |entities| format!("Configure {entities}"),
+    macro_step_skipped: "Skipped invalid step",
+
+    action_refresh: "Refresh",
+    action_toggle_auto_refresh: "Toggle auto refresh",
+    action_retry_refresh: "Retry refreshing",
+    action_show_monitor: "Show monitor",
+    action_send_message: "Send message",
+    action_show_admin: "Show admin view",
+    action_show_chart: "Show sensor chart",
+    action_show_topology: "Show system topology",
+    action_toggle_performance: "Toggle performance counters",
+    action_toggle_recording: "Start/stop macro recording",
+    action_toggle_high_contrast: "Toggle high contrast colors",
+    action_toggle_ascii: "Toggle ASCII-only rendering",
+    action_toggle_frame_times: "Toggle frame times",
+    action_run_macro: |name, key| format!("Run macro {name} (F{key})"),
+    action_quit: "Quit",
+    action_send_to_entity: |name| format!("Send to entity {name}"),
+};
+
+static GERMAN: Strings = Strings {
+    app_title: " Hausautomatisierung ",
+
+    key_send_message: " Nachricht senden ",
+    key_refresh: " Aktualisieren ",
+    key_auto_refresh: " Auto-Aktualisierung ",
+    key_admin: " Verwaltung ",
+    key_chart: " Verlauf ",
+    key_topology: " Topologie ",
+    key_palette: " Befehle ",
+    key_record_macro: " Makro aufnehmen ",
+    key_quit: " Beenden ",
+    key_accept_input: " Eingabe übernehmen ",
+    key_switch_focus: " Fokus wechseln ",
+    key_select: " Auswählen ",
+    key_undo: " Rückgängig ",
+    key_redo: " Wiederholen ",
+    key_abort: " Abbrechen ",
+    key_run: " Ausführen ",
+    key_close: " Schließen ",
+    key_ping: " Ping ",
+    key_force_unregister: " Zwangsabmelden ",
+    key_shutdown: " Herunterfahren ",
+    key_restart: " Neustarten ",
+    key_performance: " Leistung ",
+    key_back: " Zurück ",
+    key_select_sensor: " Sensor wählen ",
+    key_zoom: " Zoom ",
+    key_scroll: " Blättern ",
+    key_press: " Mit ",
+    key_close_dialog: " Dialog schließen ",
+
+    title_info: "Info",
+    title_palette: "Befehlspalette",
+    title_send_message: "Nachricht senden",
+    title_entity: "Gerät",
+    title_payload: "Inhalt",
+    title_tasks: "Tasks",
+    title_entities: "Geräte",
+    title_recent_errors: |rejected, mismatches| {
+        format!("Letzte Fehler ({rejected} abgelehnte Anfragen, {mismatches} unpassende Nutzdaten)")
+    },
+    title_performance: |pending, chunked| {
+        format!("Leistung ({pending} wartende Abfragen, {chunked} geteilte Antworten)")
+    },
+    title_chart: |sensor, current| format!("{sensor}: {current}"),
+    title_topology: "Topologie",
+
+    header_entities: ["Gerät", "Typ", "Wert", "Lebenszeichen"],
+    header_tasks: ["Task", "Status", "Fehler"],
+    header_admin_entities: [
+        "Gerät",
+        "Typ",
+        "Letzter Heartbeat",
+        "Rückkanal",
+        "Latenz p50/p99 (Eingang | Ende-zu-Ende)",
+    ],
+    header_sockets: [
+        "Socket",
+        "Gesendet Nachr./s",
+        "Gesendet B/s",
+        "Empfangen Nachr./s",
+        "Empfangen B/s",
+        "Verloren",
+    ],
+    header_commands: ["Befehl", "Anfragen", "Fehlgeschlagen", "Latenz p50/p90/p99"],
+
+    tab_update_frequency: "Aktualisierungsrate (Hz)",
+    tab_light: "Licht (%)",
+    tab_air_conditioning: "Klimaanlage (An/Aus)",
+    on: "An",
+    off: "Aus",
+    humidity: "Luftfeuchtigkeit",
+    temperature: "Temperatur",
+    brightness: "Helligkeit",
+    liveness_ok: "OK",
+    liveness_stale: |seconds| format!("veraltet {seconds}s"),
+    liveness_missing: "fehlt",
+    chart_time_axis: "Zeit",
+    chart_now: "jetzt",
+    chart_no_samples: "Noch keine Sensorwerte empfangen, zum Sammeln aktualisieren",
+
+    task_running: "Läuft",
+    task_stopped: "Gestoppt",
+    task_failed: "Fehler",
+    back_channel_healthy: "Intakt",
+    back_channel_broken: "Gestört",
+    back_channel: "Rückkanal",
+    topology_controller: |entities| format!("Controller ({entities} Geräte)"),
+    topology_sensors: "Sensoren",
+    topology_actuators: "Aktoren",
+    topology_no_room: "kein Raum",
+    flapping: "instabil",
+    suspected: "unbestätigt",
+    clock_offset: |ms| format!("Uhr {:+.1}s", ms as f32 / 1000.0),
+    seconds_ago: |seconds| format!("vor {seconds:.1}s"),
+
+    banner_unreachable: "Controller nicht erreichbar, verbinde neu...",
+    banner_incompatible: |controller, client| {
+        format!("Controller nutzt Protokollversion {controller} statt {client}, einige Funktionen sind nicht verfügbar")
+    },
+    banner_recording: |steps| {
+        format!("Makro wird aufgenommen ({steps} Schritte), <CTRL-K> zum Beenden")
+    },
+    banner_task_failed: |error| {
+        format!("Aktualisierung fehlgeschlagen: {error}, <CTRL-T> für neuen Versuch")
+    },
+    refresh_task_stopped: "Aktualisierung beendet, Client neu starten",
+    refresh_task_retrying: "Aktualisierung fehlgeschlagen, neuer Versuch",
+    frame_times: |t| {
+        format!(
+            " Zeichnen {:.1}/{:.1} ms, Eingabe {:.1} ms, Empfang {:.1} ms, {:.0} fps ",
+            t.draw_p50_ms, t.draw_p99_ms, t.events_p99_ms, t.receive_p99_ms, t.fps
+        )
+    },
+
+    update_succeeded: "Gerätekonfiguration erfolgreich geändert",
+    update_failed: "Gerätekonfiguration konnte nicht geändert werden",
+    update_unknown_error: "Unbekannter Fehler beim Ändern der Gerätekonfiguration",
+    admin_unsupported: "Der Controller unterstützt die Verwaltungs-API nicht",
+    performance_unsupported: "Der Controller meldet keine Leistungszähler",
+    admin_query_failed: "Verwaltungsstatus konnte nicht abgefragt werden",
+    admin_query: "Abfrage",
+    admin_force_unregister: "Zwangsabmelden",
+    admin_ping: "Ping",
+    admin_shutdown: "Herunterfahren",
+    admin_restart: "Neustarten",
+    admin_performance: "Leistungszähler",
+    admin_performance_failed: "Leistungszähler konnten nicht abgefragt werden",
+    admin_succeeded: "erfolgreich",
+    admin_rejected: "vom Controller abgelehnt",
+
+    macro_keys_exhausted:
+        "Alle Funktionstasten sind belegt, entferne zuerst ein Makro aus der Makrodatei",
+    macro_recorded: |key| format!("Makro aufgenommen, <F{key}> spielt es ab"),
+    macro_name: |entities| format!("Konfiguriere {entities}"),
+    macro_step_skipped: "Ungültiger Schritt übersprungen",
+
+    action_refresh: "Aktualisieren",
+    action_toggle_auto_refresh: "Auto-Aktualisierung umschalten",
+    action_retry_refresh: "Aktualisierung erneut versuchen",
+    action_show_monitor: "Übersicht anzeigen",
+    action_send_message: "Nachricht senden",
+    action_show_admin: "Verwaltung anzeigen",
+    action_show_chart: "Sensorverlauf anzeigen",
+    action_show_topology: "Systemtopologie anzeigen",
+    action_toggle_performance: "Leistungszähler ein-/ausblenden",
+    action_toggle_recording: "Makroaufnahme starten/beenden",
+    action_toggle_high_contrast: "Hohen Kontrast umschalten",
+    action_toggle_ascii: "Darstellung nur mit ASCII umschalten",
+    action_toggle_frame_times: "Bildzeiten ein-/ausblenden",
+    action_run_macro: |name, key| format!("Makro {name} ausführen (F{key})"),
+    action_quit: "Beenden",
+    action_send_to_entity: |name| format!("Nachricht an {name} senden"),
+};
@@ -0,0 +1,126 @@
+use anyhow::{Context as _, Result};
+use home_automation_common::{
+    protobuf::{actuator_state, named_entity_state, ActuatorState, NamedEntityState},
+    value_range, UpdateFrequency,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    app::Action,
+    i18n::strings,
+    persistence::{load_json, path_from_env, save_json},
+};
+
+/// Optional path of the file the client keeps the recorded macros in.
+pub const ENV_CLIENT_MACROS_FILE: &str = "HOME_AUTOMATION_CLIENT_MACROS_FILE";
+
+const DEFAULT_MACROS_FILE: &str = "client-macros.json";
+
+/// Macros are bound to the function keys F1 to F12.
+pub const MACRO_KEYS: std::ops::RangeInclusive<u8> = 1..=12;
+
+/// Recorded sequence of messages that is replayed with a single function key.
+///
+/// The name defaults to the configured entities and can be edited in the macros file, it is shown
+/// in the command palette.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Macro {
+    pub name: String,
+    /// number of the function key, e.g. `1` for F1
+    pub key: u8,
+    pub steps: Vec<MacroStep>,
+}
+
+/// Message sent during the recording of a macro.
+///
+/// Only actions with an effect on the entities are recorded, the navigation and inputs in the
+/// views are not, so a macro replays the same way regardless of the current view.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum MacroStep {
+    SetUpdateFrequency { entity: String, hz: f32 },
+    SetBrightness { entity: String, brightness: f32 },
+    SetAirConditioning { entity: String, on: bool },
+}
+
+impl MacroStep {
+    /// Returns the step to record for the action, `None` for actions that only change the UI.
+    pub fn record(action: &Action) -> Option<Self> {
+        use actuator_state::State as Actuator;
+        use named_entity_state::State;
+        let message = match action {
+            Action::SendMessage(message) => message,
+            Action::FromPalette(action) => return Self::record(action),
+            _ => return None,
+        };
+        let entity = message.entity_name.clone();
+        match message.state.as_ref()? {
+            State::SensorConfiguration(configuration) => Some(Self::SetUpdateFrequency {
+                entity,
+                hz: configuration.update_frequency_hz,
+            }),
+            State::ActuatorState(ActuatorState {
+                state: Some(Actuator::Light(light)),
+            }) => Some(Self::SetBrightness {
+                entity,
+                brightness: light.brightness,
+            }),
+            State::ActuatorState(ActuatorState {
+                state: Some(Actuator::AirConditioning(ac)),
+            }) => Some(Self::SetAirConditioning { entity, on: ac.on }),
+            State::ActuatorState(ActuatorState { state: None })
+            | State::Lifecycle(_)
+            | State::Measurement(_) => None,
+        }
+    }
+
+    pub fn entity(&self) -> &str {
+        match self {
+            Self::SetUpdateFrequency { entity, .. }
+            | Self::SetBrightness { entity, .. }
+            | Self::SetAirConditioning { entity, .. } => entity,
+        }
+    }
+
+    /// Returns the message to replay, the values are validated because the file can be edited.
+    pub fn message(&self) -> Result<NamedEntityState> {
+        let message = match self {
+            Self::SetUpdateFrequency { entity, hz } => {
+                NamedEntityState::frequency(entity, UpdateFrequency::from_hz(*hz)?)
+            }
+            Self::SetBrightness { entity, brightness } => {
+                NamedEntityState::actuator(entity, ActuatorState::light(*brightness))
+            }
+            Self::SetAirConditioning { entity, on } => {
+                NamedEntityState::actuator(entity, ActuatorState::air_conditioning(*on))
+            }
+        };
+        value_range::validate(&message)
+            .with_context(|| anyhow::anyhow!("Invalid step for {}", self.entity()))?;
+        Ok(message)
+    }
+}
+
+impl std::fmt::Display for MacroStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SetUpdateFrequency { entity, hz } => write!(f, "{entity}: {hz} Hz"),
+            Self::SetBrightness { entity, brightness } => write!(f, "{entity}: {brightness}%"),
+            Self::SetAirConditioning { entity, on } => {
+                let t = strings();
+                write!(f, "{entity}: {}", if *on { t.on } else { t.off })
+            }
+        }
+    }
+}
+
+pub fn load() -> Vec<Macro> {
+    load_json(&path_from_env(ENV_CLIENT_MACROS_FILE, DEFAULT_MACROS_FILE))
+}
+
+pub fn save(macros: &[Macro]) -> Result<()> {
+    save_json(
+        &path_from_env(ENV_CLIENT_MACROS_FILE, DEFAULT_MACROS_FILE),
+        macros,
+    )
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use home_automation_common::{
+    features,
+    protobuf::{AdminState, EntityMetadata, Latency, PerformanceCounters, Welcome},
+    EntityState, PROTOCOL_VERSION,
+};
+use ratatui::Frame;
+
+use super::{
+    app::Action,
+    frame_time::FrameTimeSummary,
+    i18n::strings,
+    macros::{Macro, MacroStep},
+    persistence::{PersistedState, ViewKind},
+    registry::{self, NamedAction},
+    sensor_history::SensorHistory,
+    view::{
+        render_banner, render_frame_times, AdminData, Appearance, ChartData, PaletteData,
+        PaletteView, SendData, TextAreaExt as _, UiView, View,
+    },
+};
+
+/// Complete state of the user interface, the views are derived from it.
+///
+/// The model is only changed by reducing [`Update`]s and by the commands of the user, so every
+/// state transition is explicit and a sequence of them can be replayed.
+#[derive(Debug)]
+pub struct AppModel {
+    pub entities: HashMap<String, EntityState>,
+    /// Estimated time of the last heartbeat of each entity.
+    pub heartbeats: HashMap<String, Instant>,
+    /// Rooms, tags and back-channel status of each entity.
+    pub metadata: BTreeMap<String, EntityMetadata>,
+    /// Recent values of the sensors for the chart view.
+    pub history: SensorHistory,
+    pub view: View,
+    pub online: bool,
+    /// Whether the refresher keeps the entities up to date, as confirmed by it.
+    pub auto_refresh: bool,
+    /// Protocol version and features of the controller learned during the handshake.
+    pub controller: Welcome,
+    /// Entity that received the last message.
+    pub last_recipient: Option<String>,
+    /// Command palette shown on top of the view if open.
+    pub palette: Option<PaletteData>,
+    /// Steps of the macro that is currently being recorded.
+    pub recording: Option<Vec<MacroStep>>,
+    pub macros: Vec<Macro>,
+    pub appearance: Appearance,
+    /// Frame times shown on top of the view if enabled.
+    pub frame_times: Option<FrameTimeSummary>,
+    /// Error of the background refresh, shown until a state is received again.
+    pub task_error: Option<String>,
+}
+
+/// Results of the background tasks and requests that change the model.
+#[derive(Debug)]
+pub enum Update {
+    EntitiesRefreshed {
+        entities: HashMap<String, EntityState>,
+        heartbeats: HashMap<String, Instant>,
+        metadata: BTreeMap<String, EntityMetadata>,
+        received_at: Instant,
+    },
+    ConnectivityChanged(bool),
+    AutoRefreshChanged(bool),
+    AdminStateRefreshed(AdminState),
+    PerformanceRefreshed(PerformanceCounters),
+    /// End-to-end latency of the entities measured by the client.
+    LatencyMeasured(HashMap<String, Latency>),
+    /// Outcome of the last admin request.
+    AdminStatus(String),
+    /// Outcome of sending a message to an entity.
+    MessageSent {
+        recipient: String,
+        text: String,
+    },
+    /// Outcome of replaying a macro, one line per step.
+    MacroFinished(String),
+    FrameTimesMeasured(FrameTimeSummary),
+    /// The background refresh stopped because of the error.
+    TaskFailed(String),
+}
+
+impl AppModel {
+    pub fn new(controller: Welcome) -> Self {
+        Self {
+            entities: HashMap::new(),
+            heartbeats: HashMap::new(),
+            metadata: BTreeMap::new(),
+            history: SensorHistory::default(),
+            view: View::default(),
+            online: true,
+            auto_refresh: false,
+            controller,
+            last_recipient: None,
+            palette: None,
+            recording: None,
+            macros: Vec::new(),
+            appearance: Appearance::default(),
+            frame_times: None,
+            task_error: None,
+        }
+    }
+
+    /// Restores the UI state of the last session, the send view starts with the last recipient.
+    pub fn restore(&mut self, state: &PersistedState) {
+        self.last_recipient = state.last_recipient.clone();
+        self.auto_refresh = state.auto_refresh;
+        self.appearance = state.appearance;
+        self.view = match state.view {
+            ViewKind::Monitor => View::Monitor,
+            ViewKind::Send => {
+                let mut data = SendData::default();
+                if let Some(recipient) = &self.last_recipient {
+                    data.input.insert_str(recipient.clone());
+                }
+                View::Send(data)
+            }
+            ViewKind::Admin if self.controller.supports(features::ADMIN) => {
+                View::Admin(AdminData::default())
+            }
+            ViewKind::Admin => View::Monitor,
+            ViewKind::Chart => View::Chart(ChartData::default()),
+            ViewKind::Topology => View::Topology(0),
+        };
+    }
+
+    pub fn persisted_state(&self) -> PersistedState {
+        let view = match self.view {
+            View::Monitor | View::PopUp(_) => ViewKind::Monitor,
+            View::Send(_) => ViewKind::Send,
+            View::Admin(_) => ViewKind::Admin,
+            View::Chart(_) => ViewKind::Chart,
+            View::Topology(_) => ViewKind::Topology,
+        };
+        PersistedState {
+            view,
+            auto_refresh: self.auto_refresh,
+            last_recipient: self.last_recipient.clone(),
+            appearance: self.appearance,
+        }
+    }
+
+    /// Applies the state transition of the update.
+    pub fn reduce(&mut self, update: Update) {
+        match update {
+            Update::EntitiesRefreshed {
+                entities,
+                heartbeats,
+                metadata,
+                received_at,
+            } => {
+                self.history.record(&entities, received_at);
+                self.entities = entities;
+                self.heartbeats = heartbeats;
+                self.metadata = metadata;
+                self.task_error = None;
+            }
+            Update::ConnectivityChanged(online) => self.online = online,
+            Update::AutoRefreshChanged(enabled) => self.auto_refresh = enabled,
+            Update::AdminStateRefreshed(state) => {
+                if let View::Admin(data) = &mut self.view {
+                    let entity_count = state.entities.len();
+                    data.state = state;
+                    if data.table.selected().is_some_and(|i| i >= entity_count) {
+                        data.table.select(entity_count.checked_sub(1));
+                    }
+                }
+            }
+            Update::PerformanceRefreshed(counters) => {
+                if let View::Admin(AdminData {
+                    performance: Some(performance),
+                    ..
+                }) = &mut self.view
+                {
+                    performance.previous = performance.current.replace(counters);
+                }
+            }
+            Update::LatencyMeasured(latency) => {
+                if let View::Admin(data) = &mut self.view {
+                    data.end_to_end_latency = latency;
+                }
+            }
+            Update::AdminStatus(status) => {
+                if let View::Admin(data) = &mut self.view {
+                    data.status = status;
+                }
+            }
+            Update::MessageSent { recipient, text } => {
+                self.last_recipient = Some(recipient);
+                self.view = View::PopUp(text);
+            }
+            Update::MacroFinished(text) => self.view = View::PopUp(text),
+            Update::FrameTimesMeasured(summary) => {
+                if let Some(frame_times) = &mut self.frame_times {
+                    *frame_times = summary;
+                }
+            }
+            Update::TaskFailed(error) => self.task_error = Some(error),
+        }
+    }
+
+    /// Records the action as step of the macro if a recording is active.
+    pub fn record(&mut self, action: &Action) {
+        if let (Some(steps), Some(step)) = (&mut self.recording, MacroStep::record(action)) {
+            steps.push(step);
+        }
+    }
+
+    /// Translates the input event into an action of the palette if open or the active view.
+    pub fn handle_event(&mut self, event: Event) -> Option<Action> {
+        let actions = self.palette_actions();
+        if let Some(data) = &mut self.palette {
+            return PaletteView { data, actions }.handle_events(event);
+        }
+        match event {
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('p'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(Action::OpenPalette),
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('k'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(Action::ToggleRecording),
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('t'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: KeyEventKind::Press,
+                ..
+            }) if self.task_error.is_some() => Some(Action::RetryRefresh),
+            Event::Key(KeyEvent {
+                code: KeyCode::F(key),
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(Action::RunMacro(key)),
+            event => self
+                .view
+                .active(
+                    &self.entities,
+                    &self.heartbeats,
+                    &self.history,
+                    &self.metadata,
+                )
+                .handle_events(event),
+        }
+    }
+
+    /// Returns the named actions matching the search of the palette.
+    fn palette_actions(&self) -> Vec<NamedAction> {
+        let Some(palette) = &self.palette else {
+            return Vec::new();
+        };
+        registry::search(palette.input.text(), registry::named_actions(self))
+    }
+
+    pub fn render(&mut self, frame: &mut Frame) {
+        self.appearance.make_current();
+        self.view
+            .active(
+                &self.entities,
+                &self.heartbeats,
+                &self.history,
+                &self.metadata,
+            )
+            .render(frame);
+        let actions = self.palette_actions();
+        if let Some(data) = &mut self.palette {
+            PaletteView { data, actions }.render(frame);
+        }
+        if let Some(error) = &self.task_error {
+            render_banner(frame, &(strings().banner_task_failed)(error));
+        } else if !self.online {
+            render_banner(frame, strings().banner_unreachable);
+        } else if !self.controller.is_compatible() {
+            render_banner(
+                frame,
+                &(strings().banner_incompatible)(
+                    self.controller.protocol_version,
+                    PROTOCOL_VERSION,
+                ),
+            );
+        } else if let Some(steps) = &self.recording {
+            render_banner(frame, &(strings().banner_recording)(steps.len()));
+        }
+        if let Some(frame_times) = &self.frame_times {
+            render_frame_times(frame, frame_times);
+        }
+    }
+}
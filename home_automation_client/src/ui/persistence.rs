@@ -0,0 +1,73 @@
+use anyhow::{Context as _, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::view::Appearance;
+
+/// Optional path of the file the client keeps its UI state in across restarts.
+pub const ENV_CLIENT_STATE_FILE: &str = "HOME_AUTOMATION_CLIENT_STATE_FILE";
+
+const DEFAULT_STATE_FILE: &str = "client-state.json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewKind {
+    #[default]
+    Monitor,
+    Send,
+    Admin,
+    Chart,
+    Topology,
+}
+
+/// UI state that is saved on exit and restored on launch.
+///
+/// Unknown or missing fields are ignored so that state files of other client versions still load.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistedState {
+    pub view: ViewKind,
+    pub auto_refresh: bool,
+    /// Entity that received the last message.
+    pub last_recipient: Option<String>,
+    pub appearance: Appearance,
+}
+
+impl PersistedState {
+    /// Loads the state of the last session, a missing or broken file results in the default state.
+    pub fn load() -> Self {
+        load_json(&path_from_env(ENV_CLIENT_STATE_FILE, DEFAULT_STATE_FILE))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        save_json(
+            &path_from_env(ENV_CLIENT_STATE_FILE, DEFAULT_STATE_FILE),
+            self,
+        )
+    }
+}
+
+/// Returns the path given in the environment variable or the default path.
+pub fn path_from_env(var: &str, default: &str) -> String {
+    std::env::var(var).unwrap_or_else(|_| default.to_owned())
+}
+
+/// Reads the JSON file, a missing or broken file results in the default value.
+pub fn load_json<T: DeserializeOwned + Default>(path: &str) -> T {
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return T::default(),
+        Err(e) => {
+            tracing::warn!(%e, "Failed to read {path}: {e}");
+            return T::default();
+        }
+    };
+    serde_json::from_str(&json)
+        .inspect_err(|e| tracing::warn!(%e, "Ignoring invalid content of {path}: {e}"))
+        .unwrap_or_default()
+}
+
+pub fn save_json<T: Serialize + ?Sized>(path: &str, value: &T) -> Result<()> {
+    let json = serde_json::to_string_pretty(value)
+        .with_context(|| anyhow::anyhow!("Failed to serialize content of {path}"))?;
+    std::fs::write(path, json).with_context(|| anyhow::anyhow!("Failed to write {path}"))
+}
@@ -0,0 +1,139 @@
+use home_automation_common::features;
+
+use crate::utility::HashMapExt as _;
+
+use super::{
+    app::Action,
+    i18n::strings,
+    model::AppModel,
+    view::{Appearance, SendData, View},
+};
+
+/// Action that can be searched by its name in the command palette.
+#[derive(Clone)]
+pub struct NamedAction {
+    pub name: String,
+    pub action: Action,
+}
+
+impl NamedAction {
+    fn new(name: impl Into<String>, action: Action) -> Self {
+        Self {
+            name: name.into(),
+            action,
+        }
+    }
+}
+
+/// Returns all actions that are currently available, including one per known entity.
+pub fn named_actions(model: &AppModel) -> Vec<NamedAction> {
+    let t = strings();
+    let mut actions = vec![
+        NamedAction::new(t.action_refresh, Action::Refresh),
+        NamedAction::new(t.action_toggle_auto_refresh, Action::ToggleAutoRefresh),
+        NamedAction::new(t.action_show_monitor, Action::ChangeView(View::Monitor)),
+        NamedAction::new(
+            t.action_send_message,
+            Action::ChangeView(View::Send(Default::default())),
+        ),
+        NamedAction::new(
+            t.action_show_chart,
+            Action::ChangeView(View::Chart(Default::default())),
+        ),
+        NamedAction::new(
+            t.action_show_topology,
+            Action::ChangeView(View::Topology(0)),
+        ),
+    ];
+    if model.task_error.is_some() {
+        actions.insert(
+            0,
+            NamedAction::new(t.action_retry_refresh, Action::RetryRefresh),
+        );
+    }
+    if model.controller.supports(features::ADMIN) {
+        actions.push(NamedAction::new(
+            t.action_show_admin,
+            Action::ChangeView(View::Admin(Default::default())),
+        ));
+    }
+    if matches!(model.view, View::Admin(_)) && model.controller.supports(features::PERFORMANCE) {
+        actions.push(NamedAction::new(
+            t.action_toggle_performance,
+            Action::TogglePerformance,
+        ));
+    }
+    actions.push(NamedAction::new(
+        t.action_toggle_recording,
+        Action::ToggleRecording,
+    ));
+    actions.extend(model.macros.iter().map(|m| {
+        NamedAction::new(
+            (t.action_run_macro)(&m.name, m.key),
+            Action::RunMacro(m.key),
+        )
+    }));
+    let appearance = model.appearance;
+    actions.push(NamedAction::new(
+        t.action_toggle_high_contrast,
+        Action::SetAppearance(Appearance {
+            high_contrast: !appearance.high_contrast,
+            ..appearance
+        }),
+    ));
+    actions.push(NamedAction::new(
+        t.action_toggle_ascii,
+        Action::SetAppearance(Appearance {
+            ascii: !appearance.ascii,
+            ..appearance
+        }),
+    ));
+    if cfg!(debug_assertions) {
+        actions.push(NamedAction::new(
+            t.action_toggle_frame_times,
+            Action::ToggleFrameTimes,
+        ));
+    }
+    actions.push(NamedAction::new(t.action_quit, Action::Exit));
+    actions.extend(model.entities.keys_stable().map(|name| {
+        NamedAction::new(
+            (t.action_send_to_entity)(name),
+            Action::ChangeView(View::Send(SendData::for_recipient(name))),
+        )
+    }));
+    actions
+}
+
+/// Returns the actions matching the query, best match first.
+pub fn search(query: &str, actions: Vec<NamedAction>) -> Vec<NamedAction> {
+    let mut matches: Vec<_> = actions
+        .into_iter()
+        .filter_map(|action| Some((fuzzy_score(query, &action.name)?, action)))
+        .collect();
+    // stable sort keeps the registry order for equally good matches
+    matches.sort_by_key(|(score, _)| *score);
+    matches.into_iter().map(|(_, action)| action).collect()
+}
+
+/// Scores how well the query matches the name, lower is better.
+///
+/// Returns `None` unless all characters of the query appear in the name in the same order.
+/// Matches at the start of the name and without gaps score best.
+fn fuzzy_score(query: &str, name: &str) -> Option<usize> {
+    let mut candidates = name.chars().map(|c| c.to_ascii_lowercase()).enumerate();
+    let mut score = 0;
+    let mut previous = None;
+    for wanted in query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+    {
+        let (index, _) = candidates.find(|&(_, c)| c == wanted)?;
+        score += match previous {
+            Some(previous) => index - previous - 1,
+            None => index,
+        };
+        previous = Some(index);
+    }
+    Some(score)
+}
@@ -0,0 +1,72 @@
+//! Recent values of the sensors, collected by the client from the refreshed states because the
+//! controller only keeps the last state of every entity.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use home_automation_common::{
+    protobuf::{sensor_measurement::Value, SensorMeasurement},
+    EntityState,
+};
+
+use crate::utility::HashMapExt as _;
+
+/// Samples older than this are dropped, it is also the widest window of the chart.
+pub const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Upper bound of the samples per sensor, in case the states arrive faster than expected.
+const MAX_SAMPLES: usize = 4096;
+
+#[derive(Debug, Clone, Default)]
+pub struct Series {
+    pub unit: String,
+    /// Oldest sample first.
+    pub samples: VecDeque<(Instant, f64)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SensorHistory(HashMap<String, Series>);
+
+impl SensorHistory {
+    /// Appends the values of the sensors in the state received at the given time.
+    pub fn record(&mut self, entities: &HashMap<String, EntityState>, received_at: Instant) {
+        for (name, state) in entities {
+            let EntityState::Sensor(SensorMeasurement {
+                unit,
+                value: Some(value),
+            }) = state
+            else {
+                continue;
+            };
+            let value = match value {
+                Value::Temperature(t) => t.temperature,
+                Value::Humidity(h) => h.humidity,
+            };
+            let series = self.0.entry(name.clone()).or_default();
+            series.unit.clone_from(unit);
+            series.samples.push_back((received_at, f64::from(value)));
+        }
+        for series in self.0.values_mut() {
+            while series.samples.len() > MAX_SAMPLES
+                || series
+                    .samples
+                    .front()
+                    .is_some_and(|&(at, _)| received_at.saturating_duration_since(at) > MAX_AGE)
+            {
+                series.samples.pop_front();
+            }
+        }
+        self.0.retain(|_, series| !series.samples.is_empty());
+    }
+
+    /// Names of the sensors with samples, sorted.
+    pub fn sensors(&self) -> Vec<&String> {
+        self.0.keys_stable().collect()
+    }
+
+    pub fn get(&self, sensor: &str) -> Option<&Series> {
+        self.0.get(sensor)
+    }
+}
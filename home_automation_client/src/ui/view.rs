@@ -1,36 +1,94 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
 
 use crossterm::event::Event;
-use home_automation_common::EntityState;
+use home_automation_common::{
+    protobuf::{AdminState, EntityMetadata, Latency, PerformanceCounters},
+    EntityState,
+};
 use ratatui::{
     layout::Alignment,
     style::{Color, Modifier, Stylize as _},
-    symbols::border,
+    text::Span,
     widgets::{
         block::{Position, Title},
-        Block, Borders, ListState,
+        Block, Borders, ListState, TableState,
     },
     Frame,
 };
 use tui_textarea::TextArea;
 
-use super::app::Action;
+use super::{
+    app::Action, frame_time::FrameTimeSummary, i18n::strings, sensor_history::SensorHistory,
+};
 
+mod admin;
+mod chart;
 mod monitor;
+mod palette;
 mod popup;
 mod send;
+mod theme;
+mod topology;
 
-pub use monitor::MonitorView;
+pub use admin::AdminView;
+pub use chart::ChartView;
+pub use monitor::{DisplayEntityState, MonitorView};
+pub use palette::{PaletteData, PaletteView};
 pub use popup::PopUp;
 pub use send::SendView;
+pub use theme::{color, Appearance, ENV_CLIENT_APPEARANCE};
+pub use topology::TopologyView;
 
 pub trait UiView {
     fn handle_events(&self, event: Event) -> Option<Action>;
     fn render(&mut self, frame: &mut Frame);
 }
 
+/// Renders a highlighted single line message over the top border of the screen.
+pub fn render_banner(frame: &mut Frame, text: &str) {
+    use ratatui::{
+        layout::Rect,
+        widgets::{Clear, Paragraph},
+    };
+    let size = frame.size();
+    let area = Rect {
+        height: size.height.min(1),
+        ..size
+    };
+    let background = if Appearance::current().high_contrast {
+        Color::Black
+    } else {
+        Color::Red
+    };
+    let banner = Paragraph::new(text.white().bg(background).bold()).centered();
+    frame.render_widget(Clear, area);
+    frame.render_widget(banner, area);
+}
+
+/// Renders the frame times in the bottom right corner over the border of the screen.
+pub fn render_frame_times(frame: &mut Frame, frame_times: &FrameTimeSummary) {
+    use ratatui::{
+        layout::Rect,
+        widgets::{Clear, Paragraph},
+    };
+    let text = (strings().frame_times)(frame_times);
+    let size = frame.size();
+    let width = (text.chars().count() as u16).min(size.width);
+    let area = Rect {
+        x: size.right() - width,
+        y: size.bottom().saturating_sub(1),
+        width,
+        height: size.height.min(1),
+    };
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(text.black().on_yellow()), area);
+}
+
 fn prepare_scaffolding(instructions: Title) -> Block {
-    let title = Title::from(" Home Automation Client ".bold());
+    let title = Title::from(strings().app_title.bold());
     Block::default()
         .title(title.alignment(Alignment::Center))
         .title(
@@ -39,7 +97,12 @@ fn prepare_scaffolding(instructions: Title) -> Block {
                 .position(Position::Bottom),
         )
         .borders(Borders::ALL)
-        .border_set(border::THICK)
+        .border_set(Appearance::current().border_set(true))
+}
+
+/// Highlighted key of the instructions.
+fn key_hint(key: &str) -> Span<'_> {
+    key.fg(color(Color::Blue)).bold()
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -57,8 +120,8 @@ impl Border {
     fn color(self) -> Color {
         match self {
             Border::NoHighlight => Color::default(),
-            Border::Blue => Color::Blue,
-            Border::Magenta => Color::Magenta,
+            Border::Blue => color(Color::Blue),
+            Border::Magenta => color(Color::Magenta),
         }
     }
 
@@ -77,12 +140,12 @@ impl Border {
     pub fn titled(self, title: &str) -> Block<'_> {
         use crate::utility::ApplyIf;
         use ratatui::style::Style;
-        use ratatui::widgets::BorderType;
+        let highlighted = self.is_highlighted();
         Block::bordered()
             .title(title)
-            .apply_if(self.is_highlighted(), |b| {
+            .border_set(Appearance::current().border_set(highlighted))
+            .apply_if(highlighted, |b| {
                 b.border_style(self.color())
-                    .border_type(BorderType::Thick)
                     .title_style(Style::from(self.color()).bold())
             })
     }
@@ -144,10 +207,11 @@ impl PayloadTabKind {
 
 impl std::fmt::Display for PayloadTabKind {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let t = strings();
         let text = match self {
-            Self::UpdateFrequency => "Update frequency (Hz)",
-            Self::Light => "Light (%)",
-            Self::AirConditioning => "Air conditioning (On/Off)",
+            Self::UpdateFrequency => t.tab_update_frequency,
+            Self::Light => t.tab_light,
+            Self::AirConditioning => t.tab_air_conditioning,
         };
         f.write_str(text)
     }
@@ -193,7 +257,7 @@ impl From<PayloadTabKind> for PayloadTab {
 pub enum PayloadTab {
     UpdateFrequency(TextArea<'static>),
     Light {
-        /// brightness as percentage from 0.0 to 100.0
+        /// brightness as percentage within the range of `ValueKind::Brightness`
         brightness: f32,
     },
     AirConditioning(ListState),
@@ -239,12 +303,62 @@ impl Default for SendData {
     }
 }
 
+impl SendData {
+    /// Starts with the payload selection for the recipient.
+    pub fn for_recipient(recipient: &str) -> Self {
+        let mut data = Self::default();
+        data.input.insert_str(recipient);
+        data.stage = SendStage::PayloadSelect {};
+        data
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AdminData {
+    pub state: AdminState,
+    /// Measured by the client, the ingest latency is part of the state.
+    pub end_to_end_latency: HashMap<String, Latency>,
+    pub table: TableState,
+    /// outcome of the last admin request
+    pub status: String,
+    /// `None` while the performance counters are hidden.
+    pub performance: Option<PerformanceData>,
+}
+
+/// Last two samples of the performance counters, the rates are derived from their difference.
+#[derive(Debug, Clone, Default)]
+pub struct PerformanceData {
+    pub current: Option<PerformanceCounters>,
+    pub previous: Option<PerformanceCounters>,
+}
+
+/// Sensor and time window shown in the chart view.
+#[derive(Debug, Clone)]
+pub struct ChartData {
+    /// `None` shows the first sensor with samples.
+    pub sensor: Option<String>,
+    pub window: Duration,
+}
+
+impl Default for ChartData {
+    fn default() -> Self {
+        Self {
+            sensor: None,
+            window: chart::DEFAULT_WINDOW,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub enum View {
     #[default]
     Monitor,
     Send(SendData),
     PopUp(String),
+    Admin(AdminData),
+    Chart(ChartData),
+    /// Tree of the controller and the entities, scrolled to the line.
+    Topology(u16),
 }
 
 impl View {
@@ -259,7 +373,13 @@ impl View {
         }
     }
 
-    pub fn active<'a>(&'a mut self, state: &'a HashMap<String, EntityState>) -> impl UiView + 'a {
+    pub fn active<'a>(
+        &'a mut self,
+        state: &'a HashMap<String, EntityState>,
+        heartbeats: &'a HashMap<String, Instant>,
+        history: &'a SensorHistory,
+        metadata: &'a BTreeMap<String, EntityMetadata>,
+    ) -> impl UiView + 'a {
         macro_rules! all_views {
             ($($view:ident),+) => {
                 enum Views<'b> {
@@ -280,18 +400,43 @@ impl View {
                 }
             };
         }
-        all_views!(MonitorView, SendView, PopUp);
+        all_views!(
+            MonitorView,
+            SendView,
+            PopUp,
+            AdminView,
+            ChartView,
+            TopologyView
+        );
 
         match self {
-            Self::Monitor => Views::MonitorView(MonitorView(state)),
+            Self::Monitor => Views::MonitorView(MonitorView {
+                entities: state,
+                heartbeats,
+                metadata,
+            }),
             Self::Send(data) => Views::SendView(SendView {
                 state,
                 entity_input: &mut data.input,
                 list: &mut data.list,
                 stage: &data.stage,
                 tab: &mut data.tab,
+                metadata,
             }),
             Self::PopUp(text) => Views::PopUp(PopUp(&*text)),
+            Self::Admin(data) => Views::AdminView(AdminView(data)),
+            Self::Chart(data) => Views::ChartView(ChartView {
+                data,
+                history,
+                entities: state,
+                metadata,
+            }),
+            Self::Topology(scroll) => Views::TopologyView(TopologyView {
+                entities: state,
+                heartbeats,
+                metadata,
+                scroll: *scroll,
+            }),
         }
     }
 }
@@ -0,0 +1,339 @@
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use home_automation_common::{
+    latency::SUSPICIOUS_CLOCK_OFFSET_MS,
+    protobuf::{admin_command::Command, task_health::Status, Latency, SocketStatistics},
+};
+use ratatui::{
+    prelude::*,
+    widgets::{block::Title, Cell, List, Paragraph, Row, Table},
+};
+
+use crate::{
+    ui::{app::Action, i18n::strings},
+    utility::Wrapping,
+};
+
+use super::{
+    color, key_hint, prepare_scaffolding, AdminData, Border, PerformanceData, UiView, View,
+};
+
+pub struct AdminView<'a>(pub &'a mut AdminData);
+
+impl<'a> AdminView<'a> {
+    fn render_tasks(&self, frame: &mut Frame, area: Rect) {
+        let t = strings();
+        let table = Table::default()
+            .header(
+                Row::new(t.header_tasks)
+                    .bold()
+                    .underlined()
+                    .fg(color(Color::Blue)),
+            )
+            .widths([
+                Constraint::Min(20),
+                Constraint::Length(8),
+                Constraint::Percentage(80),
+            ])
+            .rows(self.0.state.tasks.iter().map(|task| {
+                let status = match task.status() {
+                    Status::Running => t.task_running.fg(color(Color::Green)),
+                    Status::Stopped => t.task_stopped.fg(color(Color::Yellow)),
+                    Status::Failed => t.task_failed.fg(color(Color::Red)),
+                };
+                Row::new([
+                    task.name.as_str().into(),
+                    status,
+                    task.error.as_str().into(),
+                ])
+            }))
+            .block(Border::NoHighlight.titled(t.title_tasks));
+
+        frame.render_widget(table, area);
+    }
+
+    fn render_entities(&mut self, frame: &mut Frame, area: Rect) {
+        let t = strings();
+        let table = Table::default()
+            .header(
+                Row::new(t.header_admin_entities)
+                    .bold()
+                    .underlined()
+                    .fg(color(Color::Blue)),
+            )
+            .widths([
+                Constraint::Min(20),
+                Constraint::Length(8),
+                Constraint::Length(16),
+                Constraint::Length(12),
+                Constraint::Min(28),
+            ])
+            .rows(self.0.state.entities.iter().map(|entity| {
+                let back_channel = if entity.back_channel_healthy {
+                    t.back_channel_healthy.fg(color(Color::Green))
+                } else {
+                    t.back_channel_broken.fg(color(Color::Red))
+                };
+                let mut name = vec![entity.name.as_str().into()];
+                if entity.flapping {
+                    name.push(format!(" [{}]", t.flapping).fg(color(Color::Yellow)));
+                }
+                if entity.suspected {
+                    name.push(format!(" [{}]", t.suspected).fg(color(Color::Yellow)));
+                }
+                if entity.clock_offset_ms.abs() >= SUSPICIOUS_CLOCK_OFFSET_MS {
+                    let offset = (t.clock_offset)(entity.clock_offset_ms);
+                    name.push(format!(" [{offset}]").fg(color(Color::Yellow)));
+                }
+                Row::new([
+                    Cell::from(Line::from(name)),
+                    entity
+                        .entity_type()
+                        .to_string()
+                        .fg(color(Color::Blue))
+                        .into(),
+                    (t.seconds_ago)(entity.heartbeat_age_seconds).into(),
+                    back_channel.into(),
+                    format!(
+                        "{} | {}",
+                        format_latency(entity.ingest_latency.as_ref()),
+                        format_latency(self.0.end_to_end_latency.get(&entity.name))
+                    )
+                    .into(),
+                ])
+            }))
+            .block(Border::Blue.titled(t.title_entities))
+            // invert color scheme for selected line
+            .highlight_style(Modifier::REVERSED);
+
+        frame.render_stateful_widget(table, area, &mut self.0.table);
+    }
+
+    fn render_errors(&self, frame: &mut Frame, area: Rect) {
+        let t = strings();
+        let title = (t.title_recent_errors)(
+            self.0.state.rejected_requests,
+            self.0.state.payload_mismatches,
+        );
+        let list = List::new(self.0.state.recent_errors.iter().map(|error| {
+            Line::from(vec![
+                format!("{:>10} ", (t.seconds_ago)(error.age_seconds)).fg(color(Color::DarkGray)),
+                error.message.as_str().into(),
+            ])
+        }))
+        .block(Border::NoHighlight.titled(&title));
+
+        frame.render_widget(list, area);
+    }
+
+    fn render_performance(performance: &PerformanceData, frame: &mut Frame, area: Rect) {
+        let t = strings();
+        let Some(current) = &performance.current else {
+            frame.render_widget(
+                Border::NoHighlight.titled(&(t.title_performance)(0, 0)),
+                area,
+            );
+            return;
+        };
+        let title = (t.title_performance)(current.pending_queries, current.chunked_responses);
+        let block = Border::NoHighlight.titled(&title);
+        let [socket_area, command_area] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(block.inner(area));
+        frame.render_widget(block, area);
+
+        let rates = |socket: &SocketStatistics| {
+            let previous = performance.previous.as_ref()?;
+            let seconds = current.uptime_ms.checked_sub(previous.uptime_ms)? as f64 / 1000.;
+            let before = previous
+                .sockets
+                .iter()
+                .find(|s| s.socket == socket.socket)?;
+            let rate = |now: u64, before: u64| {
+                now.checked_sub(before)
+                    .filter(|_| seconds > 0.)
+                    .map(|delta| delta as f64 / seconds)
+            };
+            Some([
+                rate(socket.messages_sent, before.messages_sent),
+                rate(socket.bytes_sent, before.bytes_sent),
+                rate(socket.messages_received, before.messages_received),
+                rate(socket.bytes_received, before.bytes_received),
+            ])
+        };
+        let sockets = Table::default()
+            .header(
+                Row::new(t.header_sockets)
+                    .bold()
+                    .underlined()
+                    .fg(color(Color::Blue)),
+            )
+            .widths([
+                Constraint::Min(20),
+                Constraint::Length(12),
+                Constraint::Length(12),
+                Constraint::Length(14),
+                Constraint::Length(14),
+                Constraint::Length(8),
+            ])
+            .rows(current.sockets.iter().map(|socket| {
+                let rates = rates(socket).unwrap_or_default();
+                let mut cells = vec![Cell::from(socket.socket.as_str())];
+                cells.extend(rates.map(|rate| Cell::from(format_rate(rate))));
+                let dropped = socket.messages_dropped.to_string();
+                cells.push(if socket.messages_dropped > 0 {
+                    dropped.fg(color(Color::Red)).into()
+                } else {
+                    dropped.into()
+                });
+                Row::new(cells)
+            }));
+        frame.render_widget(sockets, socket_area);
+
+        let commands = Table::default()
+            .header(
+                Row::new(t.header_commands)
+                    .bold()
+                    .underlined()
+                    .fg(color(Color::Blue)),
+            )
+            .widths([
+                Constraint::Min(16),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Length(20),
+            ])
+            .rows(current.commands.iter().map(|command| {
+                let failures = if command.failures > 0 {
+                    command.failures.to_string().fg(color(Color::Red))
+                } else {
+                    command.failures.to_string().into()
+                };
+                Row::new([
+                    command.command.as_str().into(),
+                    command.requests.to_string().into(),
+                    failures,
+                    format_percentiles(command.latency.as_ref()).into(),
+                ])
+            }));
+        frame.render_widget(commands, command_area);
+    }
+
+    fn selected_entity(&self) -> Option<String> {
+        let index = self.0.table.selected()?;
+        let entity = self.0.state.entities.get(index)?;
+        Some(entity.name.clone())
+    }
+}
+
+/// Per second with one decimal, `-` until two samples were taken.
+fn format_rate(rate: Option<f64>) -> String {
+    rate.map_or_else(|| "-".to_owned(), |rate| format!("{rate:.1}"))
+}
+
+/// Median, 90th and 99th percentile, `-` without samples.
+fn format_percentiles(latency: Option<&Latency>) -> String {
+    match latency {
+        Some(latency) if latency.samples > 0 => format!(
+            "{:.1}/{:.1}/{:.1} ms",
+            latency.p50_ms, latency.p90_ms, latency.p99_ms
+        ),
+        _ => "-".to_owned(),
+    }
+}
+
+/// Median and 99th percentile, `-` without samples.
+fn format_latency(latency: Option<&Latency>) -> String {
+    match latency {
+        Some(latency) if latency.samples > 0 => {
+            format!("{:.0}/{:.0} ms", latency.p50_ms, latency.p99_ms)
+        }
+        _ => "-".to_owned(),
+    }
+}
+
+impl<'a> UiView for AdminView<'a> {
+    fn render(&mut self, frame: &mut Frame) {
+        let t = strings();
+        let instructions = Title::from(Line::from(vec![
+            t.key_ping.into(),
+            key_hint("<P>"),
+            t.key_force_unregister.into(),
+            key_hint("<U>"),
+            t.key_shutdown.into(),
+            key_hint("<X>"),
+            t.key_restart.into(),
+            key_hint("<T>"),
+            t.key_performance.into(),
+            key_hint("<C>"),
+            t.key_refresh.into(),
+            key_hint("<R>"),
+            t.key_back.into(),
+            key_hint("<ESC> "),
+        ]));
+        let block = prepare_scaffolding(instructions);
+        let area = block.inner(frame.size());
+        frame.render_widget(&block, frame.size());
+
+        let task_rows = u16::try_from(self.0.state.tasks.len()).unwrap_or(u16::MAX);
+        let performance_rows = self.0.performance.as_ref().map_or(0, |performance| {
+            let counters = performance.current.as_ref();
+            let rows = counters.map_or(0, |counters| {
+                counters.sockets.len().max(counters.commands.len())
+            });
+            u16::try_from(rows).unwrap_or(u16::MAX).saturating_add(3)
+        });
+        let layout = Layout::vertical([
+            Constraint::Length(task_rows.saturating_add(3)),
+            Constraint::Min(5),
+            Constraint::Length(performance_rows),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ]);
+        let [task_area, entity_area, performance_area, error_area, status_area] =
+            layout.areas(area);
+
+        self.render_tasks(frame, task_area);
+        self.render_entities(frame, entity_area);
+        if let Some(performance) = &self.0.performance {
+            Self::render_performance(performance, frame, performance_area);
+        }
+        self.render_errors(frame, error_area);
+        frame.render_widget(Paragraph::new(self.0.status.as_str()), status_area);
+    }
+
+    fn handle_events(&self, event: Event) -> Option<Action> {
+        let update_index = |increase: fn(Wrapping) -> Wrapping| {
+            let max = self.0.state.entities.len().checked_sub(1)?;
+            let current = self.0.table.selected().unwrap_or_default();
+            Some(increase(Wrapping::new(current, max)).current())
+        };
+        let Event::Key(KeyEvent {
+            code,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event
+        else {
+            return None;
+        };
+        match code {
+            KeyCode::Esc => Some(Action::ChangeView(View::Monitor)),
+            KeyCode::Char('r') => Some(Action::RefreshAdmin),
+            KeyCode::Char('c') => Some(Action::TogglePerformance),
+            KeyCode::Up => Some(Action::SetAdminSelection(update_index(Wrapping::dec))),
+            KeyCode::Down => Some(Action::SetAdminSelection(update_index(Wrapping::inc))),
+            KeyCode::Char('p') => Some(Action::SendAdminCommand(Command::Ping(
+                self.selected_entity()?,
+            ))),
+            KeyCode::Char('u') => Some(Action::SendAdminCommand(Command::ForceUnregister(
+                self.selected_entity()?,
+            ))),
+            KeyCode::Char('x') => Some(Action::SendAdminCommand(Command::Shutdown(
+                self.selected_entity()?,
+            ))),
+            KeyCode::Char('t') => Some(Action::SendAdminCommand(Command::Restart(
+                self.selected_entity()?,
+            ))),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use home_automation_common::{protobuf::EntityMetadata, EntityState};
+use ratatui::{
+    prelude::*,
+    symbols::Marker,
+    widgets::{block::Title, Axis, Chart, Dataset, GraphType, Paragraph},
+};
+
+use crate::{
+    ui::{app::Action, i18n::strings, sensor_history::SensorHistory},
+    utility::Wrapping,
+};
+
+use super::{
+    color, key_hint, prepare_scaffolding, Border, ChartData, DisplayEntityState, UiView, View,
+};
+
+/// Zoom levels of the time window, the widest one is limited by the history.
+const WINDOWS: [Duration; 5] = [
+    Duration::from_secs(60),
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(15 * 60),
+    Duration::from_secs(30 * 60),
+    crate::ui::sensor_history::MAX_AGE,
+];
+
+pub const DEFAULT_WINDOW: Duration = WINDOWS[1];
+
+pub struct ChartView<'a> {
+    pub data: &'a ChartData,
+    pub history: &'a SensorHistory,
+    pub entities: &'a HashMap<String, EntityState>,
+    pub metadata: &'a BTreeMap<String, EntityMetadata>,
+}
+
+impl<'a> ChartView<'a> {
+    /// Selected sensor, the first one if none was selected yet.
+    fn sensor(&self) -> Option<&'a str> {
+        let sensors = self.history.sensors();
+        let selected = self
+            .data
+            .sensor
+            .as_deref()
+            .and_then(|sensor| sensors.iter().copied().find(|s| s.as_str() == sensor));
+        selected.or(sensors.first().copied()).map(String::as_str)
+    }
+
+    /// Next or previous sensor in the sorted list of sensors with samples.
+    fn cycle_sensor(&self, next: fn(Wrapping) -> Wrapping) -> Option<String> {
+        let sensors = self.history.sensors();
+        let max = sensors.len().checked_sub(1)?;
+        let current = self
+            .sensor()
+            .and_then(|sensor| sensors.iter().position(|s| s.as_str() == sensor))
+            .unwrap_or_default();
+        Some(sensors[next(Wrapping::new(current, max)).current()].clone())
+    }
+
+    fn zoom(&self, wider: bool) -> Option<Duration> {
+        let current = WINDOWS.iter().position(|&w| w >= self.data.window)?;
+        let index = if wider {
+            current + 1
+        } else {
+            current.checked_sub(1)?
+        };
+        WINDOWS.get(index).copied()
+    }
+
+    fn render_chart(&self, frame: &mut Frame, area: Rect, sensor: &str) {
+        let t = strings();
+        let window = self.data.window.as_secs_f64();
+        let now = Instant::now();
+        let series = self.history.get(sensor);
+        let points: Vec<(f64, f64)> = series
+            .into_iter()
+            .flat_map(|series| &series.samples)
+            .map(|&(at, value)| (-now.saturating_duration_since(at).as_secs_f64(), value))
+            .filter(|&(x, _)| x >= -window)
+            .collect();
+        let (min, max) = points
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &(_, y)| {
+                (min.min(y), max.max(y))
+            });
+        // keeps a flat line off the border of the chart
+        let margin = ((max - min) * 0.1).max(1.0);
+        let (low, high) = if points.is_empty() {
+            (0.0, 1.0)
+        } else {
+            (min - margin, max + margin)
+        };
+
+        let current = self
+            .entities
+            .get(sensor)
+            .map(|state| {
+                DisplayEntityState::with_metadata(state, self.metadata.get(sensor)).to_string()
+            })
+            .unwrap_or_default();
+        let title = (t.title_chart)(sensor, &current);
+        let unit = series.map_or("", |series| series.unit.as_str());
+        let dataset = Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(color(Color::Cyan))
+            .data(&points);
+        let chart = Chart::new(vec![dataset])
+            .block(Border::Blue.titled(&title))
+            .x_axis(
+                Axis::default()
+                    .title(t.chart_time_axis)
+                    .bounds([-window, 0.0])
+                    .labels(vec![
+                        format!("-{}", format_window(self.data.window)).into(),
+                        format!("-{}", format_window(self.data.window / 2)).into(),
+                        t.chart_now.into(),
+                    ]),
+            )
+            .y_axis(Axis::default().title(unit).bounds([low, high]).labels(vec![
+                format!("{low:.1}").into(),
+                format!("{:.1}", (low + high) / 2.0).into(),
+                format!("{high:.1}").into(),
+            ]));
+        frame.render_widget(chart, area);
+    }
+}
+
+/// Formats the window in the largest unit that fits, e.g. `5m` or `30s`.
+fn format_window(window: Duration) -> String {
+    let seconds = window.as_secs();
+    if seconds >= 60 && seconds % 60 == 0 {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+impl<'a> UiView for ChartView<'a> {
+    fn render(&mut self, frame: &mut Frame) {
+        let t = strings();
+        let instructions = Title::from(Line::from(vec![
+            t.key_select_sensor.into(),
+            key_hint("<UP/DOWN>"),
+            t.key_zoom.into(),
+            key_hint("<+/->"),
+            t.key_back.into(),
+            key_hint("<ESC> "),
+        ]));
+        let block = prepare_scaffolding(instructions);
+        let area = block.inner(frame.size());
+        frame.render_widget(&block, frame.size());
+
+        match self.sensor() {
+            Some(sensor) => self.render_chart(frame, area, sensor),
+            None => frame.render_widget(
+                Paragraph::new(t.chart_no_samples)
+                    .centered()
+                    .block(Border::Blue.untitled()),
+                area,
+            ),
+        }
+    }
+
+    fn handle_events(&self, event: Event) -> Option<Action> {
+        let Event::Key(KeyEvent {
+            code,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event
+        else {
+            return None;
+        };
+        match code {
+            KeyCode::Esc => Some(Action::ChangeView(View::Monitor)),
+            KeyCode::Up => Some(Action::SetChartSensor(self.cycle_sensor(Wrapping::dec)?)),
+            KeyCode::Down => Some(Action::SetChartSensor(self.cycle_sensor(Wrapping::inc)?)),
+            KeyCode::Char('+') => Some(Action::SetChartWindow(self.zoom(false)?)),
+            KeyCode::Char('-') => Some(Action::SetChartWindow(self.zoom(true)?)),
+            _ => None,
+        }
+    }
+}
@@ -1,70 +1,150 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
 
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
-use home_automation_common::EntityState;
+use home_automation_common::{
+    protobuf::{EntityDescription, EntityMetadata},
+    EntityState, DEFAULT_HEARTBEAT_INTERVAL,
+};
 use ratatui::{
     layout::{Constraint, Rect},
-    style::Stylize as _,
-    text::Line,
+    style::{Color, Stylize as _},
+    text::{Line, Span},
     widgets::block::Title,
     Frame,
 };
 
-use crate::{ui::app::Action, utility::HashMapExt};
+use crate::{
+    ui::{app::Action, i18n::strings},
+    utility::HashMapExt,
+};
+
+use super::{color, key_hint, prepare_scaffolding, UiView, View};
+
+/// Value of the entity state in the configured language, e.g. `temperature = 21.5°C`.
+///
+/// The quantity and unit are taken from the description of the entity if it published one.
+pub struct DisplayEntityState<'a>(pub &'a EntityState, pub Option<&'a EntityDescription>);
+
+impl<'a> DisplayEntityState<'a> {
+    /// Looks the description of the entity up in the metadata.
+    pub fn with_metadata(state: &'a EntityState, metadata: Option<&'a EntityMetadata>) -> Self {
+        Self(
+            state,
+            metadata.and_then(|metadata| metadata.description.as_ref()),
+        )
+    }
+}
+
+impl<'a> std::fmt::Display for DisplayEntityState<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use home_automation_common::protobuf::{
+            actuator_state::State, sensor_measurement::Value, ActuatorState, SensorMeasurement,
+        };
+        let t = strings();
+        let (quantity, value, unit) = match self.0 {
+            EntityState::Sensor(SensorMeasurement {
+                unit,
+                value: Some(Value::Humidity(h)),
+            }) => (t.humidity, h.humidity, unit.as_str()),
+            EntityState::Sensor(SensorMeasurement {
+                unit,
+                value: Some(Value::Temperature(m)),
+            }) => (t.temperature, m.temperature, unit.as_str()),
+            EntityState::Actuator(ActuatorState {
+                state: Some(State::Light(l)),
+            }) => (t.brightness, l.brightness, "%"),
+            EntityState::Actuator(ActuatorState {
+                state: Some(State::AirConditioning(ac)),
+            }) => return f.write_str(if ac.on { t.on } else { t.off }),
+            _ => return Ok(()),
+        };
+        match self.1 {
+            Some(description) => write!(
+                f,
+                "{} = {value}{}",
+                t.quantity(&description.kind),
+                description.unit
+            ),
+            None => write!(f, "{quantity} = {value}{unit}"),
+        }
+    }
+}
+
+/// Heartbeats that are overdue by more than this are shown as stale, the controller unregisters
+/// the entity after two missed heartbeats.
+const STALE_GRACE: Duration = Duration::from_secs(5);
+
+/// Whether the entity is alive, derived from the age of its last heartbeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    Ok,
+    Stale(Duration),
+    /// The controller did not report a heartbeat.
+    Missing,
+}
+
+impl Liveness {
+    /// The heartbeat interval of the entity is taken from its metadata, controllers that do not
+    /// negotiate it use the default.
+    pub fn of(last_heartbeat: Option<Instant>, metadata: Option<&EntityMetadata>) -> Self {
+        let interval = match metadata.map(|metadata| metadata.heartbeat_interval_ms) {
+            Some(ms) if ms > 0 => Duration::from_millis(ms.into()),
+            _ => DEFAULT_HEARTBEAT_INTERVAL,
+        };
+        match last_heartbeat.map(|last| last.elapsed()) {
+            Some(age) if age < interval + STALE_GRACE => Self::Ok,
+            Some(age) => Self::Stale(age),
+            None => Self::Missing,
+        }
+    }
 
-use super::{prepare_scaffolding, UiView, View};
+    pub(super) fn to_span(self) -> Span<'static> {
+        let t = strings();
+        match self {
+            Self::Ok => t.liveness_ok.fg(color(Color::Green)),
+            Self::Stale(age) => (t.liveness_stale)(age.as_secs()).fg(color(Color::Yellow)),
+            Self::Missing => t.liveness_missing.fg(color(Color::Red)),
+        }
+    }
+}
 
-pub struct MonitorView<'a>(pub &'a HashMap<String, EntityState>);
+pub struct MonitorView<'a> {
+    pub entities: &'a HashMap<String, EntityState>,
+    /// Estimated time of the last heartbeat of each entity.
+    pub heartbeats: &'a HashMap<String, Instant>,
+    pub metadata: &'a BTreeMap<String, EntityMetadata>,
+}
 
 impl<'a> MonitorView<'a> {
     fn render_table(&self, frame: &mut Frame, area: Rect) {
         use ratatui::widgets::{Row, Table};
 
-        struct DisplayEntityState<'a>(&'a EntityState);
-
-        impl<'a> std::fmt::Display for DisplayEntityState<'a> {
-            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-                use home_automation_common::protobuf::{
-                    actuator_state::State, sensor_measurement::Value, ActuatorState,
-                    SensorMeasurement,
-                };
-                match self.0 {
-                    EntityState::Sensor(SensorMeasurement {
-                        unit,
-                        value: Some(Value::Humidity(h)),
-                    }) => write!(f, "humidity = {}{unit}", h.humidity),
-                    EntityState::Sensor(SensorMeasurement {
-                        unit,
-                        value: Some(Value::Temperature(t)),
-                    }) => write!(f, "temperature = {}{unit}", t.temperature),
-                    EntityState::Actuator(ActuatorState {
-                        state: Some(State::Light(l)),
-                    }) => write!(f, "brightness = {}%", l.brightness),
-                    EntityState::Actuator(ActuatorState {
-                        state: Some(State::AirConditioning(ac)),
-                    }) => write!(f, "on = {}", ac.on),
-                    _ => Ok(()),
-                }
-            }
-        }
-
         let table = Table::default()
             .header(
-                Row::new(["Entity", "Type", "Value"])
+                Row::new(strings().header_entities)
                     .bold()
                     .underlined()
-                    .blue(),
+                    .fg(color(Color::Blue)),
             )
             .widths([
                 Constraint::Min(20),
                 Constraint::Length(8),
-                Constraint::Percentage(80),
+                Constraint::Percentage(70),
+                Constraint::Length(14),
             ])
-            .rows(self.0.iter_stable().map(|(name, state)| {
+            .rows(self.entities.iter_stable().map(|(name, state)| {
+                let metadata = self.metadata.get(name);
+                let liveness = Liveness::of(self.heartbeats.get(name).copied(), metadata);
                 Row::new([
                     name.into(),
-                    state.entity_type().to_string().blue(),
-                    DisplayEntityState(state).to_string().into(),
+                    state.entity_type().to_string().fg(color(Color::Blue)),
+                    DisplayEntityState::with_metadata(state, metadata)
+                        .to_string()
+                        .into(),
+                    liveness.to_span(),
                 ])
             }));
 
@@ -74,15 +154,26 @@ impl<'a> MonitorView<'a> {
 
 impl<'a> UiView for MonitorView<'a> {
     fn render(&mut self, frame: &mut Frame) {
+        let t = strings();
         let instructions = Title::from(Line::from(vec![
-            " Send Message ".into(),
-            "<S>".blue().bold(),
-            " Refresh ".into(),
-            "<R>".blue().bold(),
-            " Auto-Refresh ".into(),
-            "<CTRL-R>".blue().bold(),
-            " Quit ".into(),
-            "<ESC> ".blue().bold(),
+            t.key_send_message.into(),
+            key_hint("<S>"),
+            t.key_refresh.into(),
+            key_hint("<R>"),
+            t.key_auto_refresh.into(),
+            key_hint("<CTRL-R>"),
+            t.key_admin.into(),
+            key_hint("<A>"),
+            t.key_chart.into(),
+            key_hint("<C>"),
+            t.key_topology.into(),
+            key_hint("<T>"),
+            t.key_palette.into(),
+            key_hint("<CTRL-P>"),
+            t.key_record_macro.into(),
+            key_hint("<CTRL-K>"),
+            t.key_quit.into(),
+            key_hint("<ESC> "),
         ]));
         let block = prepare_scaffolding(instructions);
 
@@ -97,6 +188,22 @@ impl<'a> UiView for MonitorView<'a> {
                 kind: KeyEventKind::Press,
                 ..
             }) => Some(Action::ChangeView(View::Send(Default::default()))),
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('a'),
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(Action::ChangeView(View::Admin(Default::default()))),
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('c'),
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(Action::ChangeView(View::Chart(Default::default()))),
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('t'),
+                modifiers: KeyModifiers::NONE,
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(Action::ChangeView(View::Topology(0))),
             Event::Key(KeyEvent {
                 code: KeyCode::Esc, ..
             }) => Some(Action::Exit),
@@ -0,0 +1,119 @@
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::Modifier,
+    text::{Line, Span},
+    widgets::{
+        block::{Position, Title},
+        Clear, List, ListState,
+    },
+    Frame,
+};
+use tui_textarea::TextArea;
+
+use crate::{
+    ui::{app::Action, i18n::strings, registry::NamedAction},
+    utility::Wrapping,
+};
+
+use super::{key_hint, popup::centered_rect, Border, TextAreaExt, UiView};
+
+/// Search state of the command palette.
+#[derive(Debug, Clone)]
+pub struct PaletteData {
+    pub input: TextArea<'static>,
+    pub list: ListState,
+}
+
+impl Default for PaletteData {
+    fn default() -> Self {
+        let mut input = TextArea::initial();
+        input.set_block(Border::Magenta.untitled());
+        Self {
+            input,
+            list: ListState::default().with_selected(Some(0)),
+        }
+    }
+}
+
+/// Overlay to search and run the named actions.
+pub struct PaletteView<'a> {
+    pub data: &'a mut PaletteData,
+    /// actions matching the search, best match first
+    pub actions: Vec<NamedAction>,
+}
+
+impl<'a> UiView for PaletteView<'a> {
+    fn handle_events(&self, event: Event) -> Option<Action> {
+        let update_index = |increase: fn(Wrapping) -> Wrapping| {
+            let max = self.actions.len().checked_sub(1)?;
+            let current = self.data.list.selected().unwrap_or_default();
+            Some(increase(Wrapping::new(current, max)).current())
+        };
+        match event {
+            Event::Key(KeyEvent {
+                code: KeyCode::Esc, ..
+            })
+            | Event::Key(KeyEvent {
+                code: KeyCode::Char('p'),
+                modifiers: KeyModifiers::CONTROL,
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(Action::ClosePalette),
+            Event::Key(KeyEvent {
+                code: KeyCode::Enter,
+                kind: KeyEventKind::Press,
+                ..
+            }) => {
+                let index = self.data.list.selected().unwrap_or_default();
+                let action = self.actions.get(index)?.action.clone();
+                Some(Action::FromPalette(Box::new(action)))
+            }
+            Event::Key(KeyEvent {
+                code: KeyCode::Up,
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(Action::SetPaletteSelection(update_index(Wrapping::dec))),
+            Event::Key(KeyEvent {
+                code: KeyCode::Down,
+                kind: KeyEventKind::Press,
+                ..
+            }) => Some(Action::SetPaletteSelection(update_index(Wrapping::inc))),
+            event => Some(Action::PaletteInput(event.into())),
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let t = strings();
+        let instructions = Title::from(Line::from(vec![
+            t.key_run.into(),
+            key_hint("<ENTER>"),
+            t.key_select.into(),
+            key_hint("<UP>/<DOWN>"),
+            t.key_close.into(),
+            key_hint("<ESC> "),
+        ]));
+        let block = Border::Blue
+            .titled(t.title_palette)
+            .title(instructions.position(Position::Bottom));
+
+        let area = centered_rect(60, 50, frame.size());
+        frame.render_widget(Clear, area);
+        frame.render_widget(&block, area);
+
+        let layout = Layout::vertical([Constraint::Length(3), Constraint::Min(1)]);
+        let [input_area, list_area] = layout.areas(block.inner(area));
+
+        self.data.input.toggle_focus(true);
+        frame.render_widget(self.data.input.widget(), input_area);
+
+        let list = List::new(
+            self.actions
+                .iter()
+                .map(|action| Span::raw(action.name.as_str())),
+        )
+        // invert color scheme for selected line
+        .highlight_style(Modifier::REVERSED);
+        frame.render_stateful_widget(list, list_area, &mut self.data.list);
+    }
+}
@@ -1,9 +1,9 @@
 use crossterm::event::{Event, KeyCode, KeyEvent};
-use ratatui::{layout::Rect, style::Stylize};
+use ratatui::layout::Rect;
 
-use crate::ui::{app::Action, view::Border};
+use crate::ui::{app::Action, i18n::strings, view::Border};
 
-use super::{UiView, View::Monitor};
+use super::{key_hint, UiView, View::Monitor};
 
 pub struct PopUp<'a>(pub &'a str);
 
@@ -26,14 +26,15 @@ impl<'a> UiView for PopUp<'a> {
                 Clear, Paragraph, Wrap,
             },
         };
+        let t = strings();
         let instructions = Title::from(Line::from(vec![
-            " Press ".into(),
-            "<Enter>".blue().bold(),
-            " to close dialog ".into(),
+            t.key_press.into(),
+            key_hint("<Enter>"),
+            t.key_close_dialog.into(),
         ]));
 
         let block = Border::NoHighlight
-            .titled("Info")
+            .titled(t.title_info)
             .title(instructions.position(Position::Bottom));
 
         let content = Paragraph::new(self.0)
@@ -48,7 +49,7 @@ impl<'a> UiView for PopUp<'a> {
 }
 
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
-fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
+pub(super) fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     use ratatui::layout::{Constraint, Layout};
     let popup_layout = Layout::vertical([
         Constraint::Percentage((100 - percent_y) / 2),
@@ -1,9 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
 use home_automation_common::{
-    protobuf::{ActuatorState, NamedEntityState},
-    EntityState,
+    capabilities,
+    protobuf::{ActuatorState, EntityDescription, EntityMetadata, NamedEntityState},
+    value_range::{ValueKind, ValueRange},
+    EntityState, UpdateFrequency,
 };
 use ratatui::{
     prelude::*,
@@ -14,12 +16,15 @@ use tui_textarea::TextArea;
 use crate::{
     ui::{
         app::Action,
+        i18n::strings,
         view::{PayloadTab, PayloadTabKind},
     },
     utility::{ApplyIf as _, HashMapExt, Wrapping},
 };
 
-use super::{prepare_scaffolding, Border, SendStage, TextAreaExt, UiView, View};
+use super::{
+    color, key_hint, prepare_scaffolding, Appearance, Border, SendStage, TextAreaExt, UiView, View,
+};
 
 pub struct SendView<'a> {
     pub(super) state: &'a HashMap<String, EntityState>,
@@ -27,14 +32,37 @@ pub struct SendView<'a> {
     pub(super) list: &'a mut ListState,
     pub(super) stage: &'a SendStage,
     pub(super) tab: &'a mut PayloadTab,
+    pub(super) metadata: &'a BTreeMap<String, EntityMetadata>,
 }
 
 impl<'a> SendView<'a> {
+    /// Description of the selected entity, if it published one.
+    fn description(&self) -> Option<&'a EntityDescription> {
+        self.metadata
+            .get(self.entity_input.text())
+            .and_then(|metadata| metadata.description.as_ref())
+    }
+
+    /// Brightness range of the selected light, narrowed by its description.
+    fn brightness_range(&self) -> ValueRange {
+        let range = ValueKind::Brightness.range();
+        match self.description().and_then(EntityDescription::range) {
+            Some((min, max)) => ValueRange {
+                min: range.clamp(min),
+                max: range.clamp(max),
+                ..range
+            },
+            None => range,
+        }
+    }
+
     fn render_name_select(&mut self, frame: &mut Frame, area: Rect) {
         let entity_focused = matches!(self.stage, SendStage::EntitySelect);
         let list_focused = entity_focused && self.list.selected().is_some();
 
-        let container = Border::Blue.highlighted(entity_focused).titled("Entity");
+        let container = Border::Blue
+            .highlighted(entity_focused)
+            .titled(strings().title_entity);
         frame.render_widget(&container, area);
 
         let layout = Layout::vertical([Constraint::Length(3), Constraint::Min(5)]);
@@ -61,19 +89,21 @@ impl<'a> SendView<'a> {
 
         let container = Border::Blue
             .highlighted(payload_selection_active)
-            .titled("Payload");
+            .titled(strings().title_payload);
         frame.render_widget(&container, area);
 
         let layout = Layout::vertical([Constraint::Length(1), Constraint::Min(0)]);
         let [tab_header_area, tab_content_area] = layout.areas(container.inner(area));
 
         let allowed_payloads = self.determine_allowed_payload_tabs();
+        let brightness_range = self.brightness_range();
         let tabs = Tabs::new(PayloadTabKind::all().map(|t| {
             Span::raw(t.to_string()).apply_if(allowed_payloads.contains(&t), |s| {
                 s.style(Modifier::UNDERLINED)
             })
         }))
-        .highlight_style(Style::from(Color::Magenta).bold())
+        .highlight_style(Style::from(color(Color::Magenta)).bold())
+        .divider(Appearance::current().tab_divider())
         .select(self.tab.index());
 
         match self.tab {
@@ -86,19 +116,20 @@ impl<'a> SendView<'a> {
             PayloadTab::Light { brightness } => {
                 let layout = Layout::vertical([Constraint::Length(5)]);
                 let [area] = layout.areas(tab_content_area);
+                let ratio = brightness_range.ratio(*brightness);
                 let brightness = f64::from(*brightness);
                 let gauge = Gauge::default()
                     .block(Border::Magenta.untitled())
-                    .gauge_style(Color::Magenta)
-                    .ratio(brightness / 100.0)
+                    .gauge_style(color(Color::Magenta))
+                    .ratio(f64::from(ratio))
                     .label(format!("{brightness:.1}%"))
-                    .use_unicode(true);
+                    .use_unicode(!Appearance::current().ascii);
                 frame.render_widget(gauge, area);
             }
             PayloadTab::AirConditioning(state) => {
                 let layout = Layout::vertical([Constraint::Length(4)]);
                 let [area] = layout.areas(tab_content_area);
-                let list = List::new(["On", "Off"])
+                let list = List::new([strings().on, strings().off])
                     .block(Border::Magenta.untitled())
                     // invert color scheme for selected line
                     .highlight_style(Modifier::REVERSED);
@@ -111,6 +142,23 @@ impl<'a> SendView<'a> {
 
     fn determine_allowed_payload_tabs(&self) -> Vec<PayloadTabKind> {
         use home_automation_common::protobuf::{actuator_state::State, ActuatorState};
+        if let Some(description) = self.description() {
+            return [
+                (
+                    capabilities::UPDATE_FREQUENCY,
+                    PayloadTabKind::UpdateFrequency,
+                ),
+                (capabilities::LIGHT, PayloadTabKind::Light),
+                (
+                    capabilities::AIR_CONDITIONING,
+                    PayloadTabKind::AirConditioning,
+                ),
+            ]
+            .into_iter()
+            .filter(|(capability, _)| description.accepts(capability))
+            .map(|(_, tab)| tab)
+            .collect();
+        }
         let entity_name = self.entity_input.text();
         match self.state.get(entity_name) {
             Some(EntityState::Actuator(ActuatorState {
@@ -187,13 +235,19 @@ impl<'a> SendView<'a> {
                 ..
             }) => Some(Action::SendMessage(match &self.tab {
                 PayloadTab::UpdateFrequency(text) => {
-                    let freq: f32 = text.text().parse().ok()?;
-                    NamedEntityState::frequency(self.entity_input.text(), freq)
+                    let frequency: UpdateFrequency = text.text().parse().ok()?;
+                    ValueKind::UpdateFrequency.check(frequency.hz()).ok()?;
+                    NamedEntityState::frequency(self.entity_input.text(), frequency)
+                }
+                PayloadTab::Light { brightness } => {
+                    if !self.brightness_range().contains(*brightness) {
+                        return None;
+                    }
+                    NamedEntityState::actuator(
+                        self.entity_input.text(),
+                        ActuatorState::light(*brightness),
+                    )
                 }
-                PayloadTab::Light { brightness } => NamedEntityState::actuator(
-                    self.entity_input.text(),
-                    ActuatorState::light(*brightness),
-                ),
                 PayloadTab::AirConditioning(list) => {
                     let on = match list.selected()? {
                         0 => true,
@@ -252,7 +306,7 @@ impl<'a> SendView<'a> {
                     (false, false) => -1.0,
                 };
                 Some(Action::SetLightBrightness(
-                    (brightness + delta).clamp(0.0, 100.0),
+                    self.brightness_range().clamp(brightness + delta),
                 ))
             }
             _ => None,
@@ -262,18 +316,23 @@ impl<'a> SendView<'a> {
 
 impl<'a> UiView for SendView<'a> {
     fn render(&mut self, frame: &mut Frame) {
+        let t = strings();
         let instructions = Title::from(Line::from(vec![
-            " Accept input".into(),
-            "<ENTER>".blue().bold(),
-            " Switch focus ".into(),
-            "<TAB>".blue().bold(),
-            " Select ".into(),
-            "<UP>/<DOWN>/<LEFT>/<RIGHT>".blue().bold(),
-            " Abort ".into(),
-            "<ESC> ".blue().bold(),
+            t.key_accept_input.into(),
+            key_hint("<ENTER>"),
+            t.key_switch_focus.into(),
+            key_hint("<TAB>"),
+            t.key_select.into(),
+            key_hint("<UP>/<DOWN>/<LEFT>/<RIGHT>"),
+            t.key_undo.into(),
+            key_hint("<CTRL-Z>"),
+            t.key_redo.into(),
+            key_hint("<CTRL-Y>"),
+            t.key_abort.into(),
+            key_hint("<ESC> "),
         ]));
         let block = prepare_scaffolding(instructions)
-            .title(Title::from("Send Message".bold()).alignment(Alignment::Left));
+            .title(Title::from(t.title_send_message.bold()).alignment(Alignment::Left));
         frame.render_widget(&block, frame.size());
 
         let outer_layout = Layout::vertical([Constraint::Min(10), Constraint::Min(10)]);
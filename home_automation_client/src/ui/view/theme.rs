@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use ratatui::{
+    style::Color,
+    symbols::{self, border},
+};
+use serde::{Deserialize, Serialize};
+
+/// Optional comma separated rendering options at startup, e.g. `high-contrast,ascii`.
+pub const ENV_CLIENT_APPEARANCE: &str = "HOME_AUTOMATION_CLIENT_APPEARANCE";
+
+/// Border drawn only with ASCII characters for terminals without unicode box drawing glyphs.
+const ASCII_BORDER: border::Set = border::Set {
+    top_left: "+",
+    top_right: "+",
+    bottom_left: "+",
+    bottom_right: "+",
+    vertical_left: "|",
+    vertical_right: "|",
+    horizontal_top: "-",
+    horizontal_bottom: "-",
+};
+
+/// Rendering options for limited terminals and users who need more contrast.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Appearance {
+    /// Brighter colors that stand out on dark and light backgrounds.
+    pub high_contrast: bool,
+    /// Avoids unicode border and gauge glyphs.
+    pub ascii: bool,
+}
+
+/// Appearance of the current frame, set by the model before the views are rendered.
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+impl Appearance {
+    /// Reads the appearance from the environment, returns `None` if it is not set.
+    pub fn from_env() -> Option<Self> {
+        let options = std::env::var(ENV_CLIENT_APPEARANCE).ok()?;
+        let mut appearance = Self::default();
+        for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            match option {
+                "high-contrast" => appearance.high_contrast = true,
+                "ascii" => appearance.ascii = true,
+                _ => tracing::warn!("Ignoring unknown option {option} in {ENV_CLIENT_APPEARANCE}"),
+            }
+        }
+        Some(appearance)
+    }
+
+    pub fn current() -> Self {
+        let bits = CURRENT.load(Ordering::Relaxed);
+        Self {
+            high_contrast: bits & 1 != 0,
+            ascii: bits & 2 != 0,
+        }
+    }
+
+    pub fn make_current(self) {
+        let bits = u8::from(self.high_contrast) | u8::from(self.ascii) << 1;
+        CURRENT.store(bits, Ordering::Relaxed);
+    }
+
+    /// Replaces the color by a brighter one in high contrast mode.
+    pub fn color(self, color: Color) -> Color {
+        if !self.high_contrast {
+            return color;
+        }
+        match color {
+            Color::Blue => Color::LightCyan,
+            Color::Magenta => Color::LightMagenta,
+            Color::Green => Color::LightGreen,
+            Color::Yellow => Color::LightYellow,
+            Color::Red => Color::LightRed,
+            Color::DarkGray => Color::White,
+            color => color,
+        }
+    }
+
+    /// Thick borders mark the outermost and the highlighted blocks.
+    pub fn border_set(self, thick: bool) -> border::Set {
+        match (self.ascii, thick) {
+            (true, _) => ASCII_BORDER,
+            (false, true) => border::THICK,
+            (false, false) => border::PLAIN,
+        }
+    }
+
+    pub fn tab_divider(self) -> &'static str {
+        if self.ascii {
+            "|"
+        } else {
+            symbols::line::VERTICAL
+        }
+    }
+}
+
+/// Replaces the color by a brighter one if the current frame uses high contrast.
+pub fn color(color: Color) -> Color {
+    Appearance::current().color(color)
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use home_automation_common::{
+    protobuf::{entity_discovery_command::EntityType, EntityMetadata},
+    EntityState,
+};
+use ratatui::{
+    prelude::*,
+    widgets::{block::Title, Paragraph},
+};
+
+use crate::ui::{app::Action, i18n::strings};
+
+use super::{
+    color, key_hint, monitor::Liveness, prepare_scaffolding, Appearance, Border,
+    DisplayEntityState, UiView, View,
+};
+
+/// Glyphs that connect the nodes of the tree.
+struct Branches {
+    middle: &'static str,
+    last: &'static str,
+    /// Continues the line of a node with further children below.
+    continued: &'static str,
+    ended: &'static str,
+}
+
+impl Branches {
+    fn current() -> Self {
+        if Appearance::current().ascii {
+            Self {
+                middle: "|-- ",
+                last: "`-- ",
+                continued: "|   ",
+                ended: "    ",
+            }
+        } else {
+            Self {
+                middle: "├── ",
+                last: "└── ",
+                continued: "│   ",
+                ended: "    ",
+            }
+        }
+    }
+
+    fn branch(&self, last: bool) -> &'static str {
+        if last {
+            self.last
+        } else {
+            self.middle
+        }
+    }
+
+    fn indent(&self, last: bool) -> &'static str {
+        if last {
+            self.ended
+        } else {
+            self.continued
+        }
+    }
+}
+
+pub struct TopologyView<'a> {
+    pub entities: &'a HashMap<String, EntityState>,
+    /// Estimated time of the last heartbeat of each entity.
+    pub heartbeats: &'a HashMap<String, Instant>,
+    pub metadata: &'a BTreeMap<String, EntityMetadata>,
+    /// First line of the tree that is shown.
+    pub scroll: u16,
+}
+
+impl<'a> TopologyView<'a> {
+    /// Entities of the type by room, an entity in several rooms is listed in each of them.
+    fn rooms(&self, entity_type: EntityType) -> BTreeMap<&'a str, Vec<&'a str>> {
+        let mut rooms: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (name, state) in self.entities {
+            if state.entity_type() != entity_type {
+                continue;
+            }
+            let metadata = self.metadata.get(name);
+            match metadata.map(|m| m.rooms.as_slice()) {
+                Some(names @ [_, ..]) => {
+                    for room in names {
+                        rooms.entry(room.as_str()).or_default().push(name.as_str());
+                    }
+                }
+                _ => rooms
+                    .entry(strings().topology_no_room)
+                    .or_default()
+                    .push(name.as_str()),
+            }
+        }
+        for entities in rooms.values_mut() {
+            entities.sort_unstable();
+        }
+        rooms
+    }
+
+    fn entity_line(&self, prefix: String, name: &'a str) -> Line<'a> {
+        let t = strings();
+        let mut spans = vec![prefix.into(), name.bold()];
+        let metadata = self.metadata.get(name);
+        if let Some(state) = self.entities.get(name) {
+            spans.push(format!("  {}", DisplayEntityState::with_metadata(state, metadata)).into());
+        }
+        spans.push("  ".into());
+        spans.push(Liveness::of(self.heartbeats.get(name).copied(), metadata).to_span());
+        if let Some(metadata) = metadata {
+            let back_channel = if metadata.back_channel_healthy {
+                format!("  {} {}", t.back_channel, t.back_channel_healthy).fg(color(Color::Green))
+            } else {
+                format!("  {} {}", t.back_channel, t.back_channel_broken).fg(color(Color::Red))
+            };
+            spans.push(back_channel);
+            for tag in &metadata.tags {
+                spans.push(format!("  #{tag}").fg(color(Color::DarkGray)));
+            }
+        }
+        Line::from(spans)
+    }
+
+    /// Controller as root, then the entity types, the rooms and the entities.
+    fn lines(&self) -> Vec<Line<'a>> {
+        let t = strings();
+        let branches = Branches::current();
+        let mut lines = vec![Line::from(
+            (t.topology_controller)(self.entities.len()).bold(),
+        )];
+        let types = [
+            (EntityType::Sensor, t.topology_sensors),
+            (EntityType::Actuator, t.topology_actuators),
+        ];
+        for (i, (entity_type, label)) in types.into_iter().enumerate() {
+            let last_type = i + 1 == types.len();
+            lines.push(Line::from(vec![
+                branches.branch(last_type).into(),
+                label.fg(color(Color::Blue)).bold(),
+            ]));
+            let rooms = self.rooms(entity_type);
+            for (j, (&room, entities)) in rooms.iter().enumerate() {
+                let last_room = j + 1 == rooms.len();
+                lines.push(Line::from(vec![
+                    branches.indent(last_type).into(),
+                    branches.branch(last_room).into(),
+                    room.fg(color(Color::Magenta)),
+                ]));
+                for (k, &name) in entities.iter().enumerate() {
+                    let prefix = [
+                        branches.indent(last_type),
+                        branches.indent(last_room),
+                        branches.branch(k + 1 == entities.len()),
+                    ]
+                    .concat();
+                    lines.push(self.entity_line(prefix, name));
+                }
+            }
+        }
+        lines
+    }
+}
+
+impl<'a> UiView for TopologyView<'a> {
+    fn render(&mut self, frame: &mut Frame) {
+        let t = strings();
+        let instructions = Title::from(Line::from(vec![
+            t.key_scroll.into(),
+            key_hint("<UP/DOWN>"),
+            t.key_back.into(),
+            key_hint("<ESC> "),
+        ]));
+        let block = prepare_scaffolding(instructions);
+        let area = block.inner(frame.size());
+        frame.render_widget(&block, frame.size());
+
+        let tree = Paragraph::new(self.lines())
+            .scroll((self.scroll, 0))
+            .block(Border::Blue.titled(t.title_topology));
+        frame.render_widget(tree, area);
+    }
+
+    fn handle_events(&self, event: Event) -> Option<Action> {
+        let Event::Key(KeyEvent {
+            code,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event
+        else {
+            return None;
+        };
+        match code {
+            KeyCode::Esc => Some(Action::ChangeView(View::Monitor)),
+            KeyCode::Up => Some(Action::SetTopologyScroll(self.scroll.checked_sub(1)?)),
+            KeyCode::Down => {
+                let last = self.lines().len().saturating_sub(1);
+                (usize::from(self.scroll) < last)
+                    .then(|| Action::SetTopologyScroll(self.scroll + 1))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Compares answering state queries of many concurrent clients with a state that is encoded for
+//! every reply against a state that is encoded once, as the controller does until the state
+//! changes.
+//!
+//! Run with `cargo bench -p home_automation_common --bench system_state_replies`.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use home_automation_common::{
+    envelope::PackedMessage,
+    protobuf::{
+        sensor_measurement::Value, ClientApiCommand, SensorMeasurement, SystemState,
+        TemperatureSensorMeasurement,
+    },
+    zmq_sockets::{Context, Requester, Router},
+};
+
+const CLIENTS: usize = 50;
+const REQUESTS_PER_CLIENT: usize = 200;
+const SENSORS: usize = 200;
+
+fn system_state() -> SystemState {
+    let measurement = SensorMeasurement {
+        value: Some(Value::Temperature(TemperatureSensorMeasurement {
+            temperature: 21.5,
+        })),
+        unit: "°C".to_owned(),
+    };
+    SystemState {
+        sensors: (0..SENSORS)
+            .map(|i| (format!("sen_{i}"), measurement.clone()))
+            .collect::<BTreeMap<_, _>>(),
+        generation: 1,
+        ..Default::default()
+    }
+}
+
+fn run(name: &str, endpoint: &str, cached: bool) {
+    let context = Context::new();
+    let server = Router::new(&context)
+        .and_then(|router| router.bind(endpoint))
+        .expect("failed to bind router");
+    let state = system_state();
+    let packed = PackedMessage::new(&state).expect("failed to encode state");
+
+    let start = Instant::now();
+    std::thread::scope(|s| {
+        for _ in 0..CLIENTS {
+            let context = context.clone();
+            s.spawn(move || {
+                let client = Requester::new(&context)
+                    .and_then(|requester| requester.connect(endpoint))
+                    .expect("failed to connect requester");
+                for _ in 0..REQUESTS_PER_CLIENT {
+                    client
+                        .send(ClientApiCommand::system_state_query())
+                        .expect("failed to send query");
+                    client
+                        .receive::<SystemState>()
+                        .expect("failed to receive state");
+                }
+            });
+        }
+
+        for _ in 0..CLIENTS * REQUESTS_PER_CLIENT {
+            let (client, _): (_, ClientApiCommand) = server.receive().expect("failed to receive");
+            if cached {
+                server.send_packed(&client, &packed)
+            } else {
+                server.send(&client, state.clone())
+            }
+            .expect("failed to reply");
+        }
+    });
+    report(name, start.elapsed());
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let requests = CLIENTS * REQUESTS_PER_CLIENT;
+    println!(
+        "{name:>10}: {requests} replies to {CLIENTS} clients in {elapsed:?} ({:.0} replies/s)",
+        requests as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    run("encoded", "inproc://bench-encoded", false);
+    run("cached", "inproc://bench-cached", true);
+}
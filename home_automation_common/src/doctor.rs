@@ -0,0 +1,248 @@
+//! Self-test of the environment that every binary runs instead of its normal operation when it
+//! is started with [`DOCTOR_FLAG`].
+
+use std::{fmt, net::ToSocketAddrs, time::Duration};
+
+use anyhow::{Context as _, Result};
+
+use crate::{
+    encryption::{PayloadCipher, ENV_PAYLOAD_KEY},
+    protobuf::{ClientApiCommand, Welcome},
+    signing::{CommandSigner, CommandVerifier},
+    zipkin, zmq_sockets, PROTOCOL_VERSION,
+};
+
+pub const DOCTOR_FLAG: &str = "--doctor";
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Returns whether the binary was started with [`DOCTOR_FLAG`].
+pub fn requested() -> bool {
+    std::env::args().skip(1).any(|arg| arg == DOCTOR_FLAG)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARN",
+            Status::Error => "FAIL",
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Check {
+    name: String,
+    status: Status,
+    detail: String,
+}
+
+/// Results of the checks of one binary, printed as a table.
+#[derive(Debug)]
+pub struct Report {
+    program: String,
+    context: zmq_sockets::Context,
+    checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            context: zmq_sockets::Context::new(),
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, status: Status, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        });
+    }
+
+    /// Adds the outcome of a check that fails with an error.
+    pub fn add_result(&mut self, name: impl Into<String>, result: Result<String>) {
+        match result {
+            Ok(detail) => self.add(name, Status::Ok, detail),
+            Err(e) => self.add(name, Status::Error, format!("{e:#}")),
+        }
+    }
+
+    /// Checks that the variable is set, a missing optional variable is only reported.
+    pub fn env_var(&mut self, var: &str, required: bool) -> Option<String> {
+        match std::env::var(var) {
+            Ok(value) => {
+                self.add(var, Status::Ok, format!("{value:?}"));
+                Some(value)
+            }
+            Err(_) if required => {
+                self.add(var, Status::Error, "required but not set");
+                None
+            }
+            Err(_) => {
+                self.add(var, Status::Ok, "not set, using default");
+                None
+            }
+        }
+    }
+
+    /// Checks that the endpoint of the variable can be bound, i.e. it is valid and not in use.
+    pub fn bind_probe(&mut self, var: &str) {
+        let Ok(endpoint) = std::env::var(var) else {
+            return;
+        };
+        let result = zmq_sockets::Replier::new(&self.context)
+            .and_then(|socket| socket.bind(&endpoint))
+            .map(|_| "can be bound".to_owned())
+            .with_context(|| anyhow::anyhow!("Failed to bind {endpoint}"));
+        self.add_result(format!("bind {var}"), result);
+    }
+
+    /// Checks that a TCP connection to the endpoint of the variable can be established.
+    ///
+    /// ZeroMQ connects in the background, so the probe uses a plain TCP connection instead.
+    pub fn connect_probe(&mut self, var: &str) {
+        let Ok(endpoint) = std::env::var(var) else {
+            return;
+        };
+        let result =
+            tcp_connect(&endpoint).with_context(|| anyhow::anyhow!("No answer from {endpoint}"));
+        self.add_result(format!("connect {var}"), result);
+    }
+
+    /// Checks the key of the payload encryption without revealing it.
+    pub fn payload_encryption(&mut self) {
+        let result = PayloadCipher::from_env().map(|cipher| match cipher {
+            Some(_) => "enabled".to_owned(),
+            None => format!("disabled, set {ENV_PAYLOAD_KEY} to encrypt the payloads"),
+        });
+        self.add_result("payload encryption", result.map_err(Into::into));
+    }
+
+    /// Checks the keys of the command signatures without revealing them.
+    pub fn command_signing(&mut self, sign: bool, verify: bool) {
+        if sign {
+            let result = CommandSigner::from_env().map(|signer| match signer {
+                Some(signer) => format!("signs commands as {}", signer.key_id()),
+                None => "commands are not signed".to_owned(),
+            });
+            self.add_result("command signing", result.map_err(Into::into));
+        }
+        if verify {
+            let result = CommandVerifier::from_env().map(|verifier| {
+                if verifier.is_enabled() {
+                    format!(
+                        "accepts commands signed by {}",
+                        verifier.key_ids().join(", ")
+                    )
+                } else {
+                    "accepts unsigned commands".to_owned()
+                }
+            });
+            self.add_result("command verification", result.map_err(Into::into));
+        }
+    }
+
+    /// Checks that the Zipkin trace collector accepts spans.
+    pub fn zipkin(&mut self) {
+        let endpoint = zipkin::endpoint();
+        let result = zipkin::probe(PROBE_TIMEOUT);
+        match result {
+            Ok(response) => self.add(
+                "zipkin",
+                Status::Ok,
+                format!("{endpoint} answered with {}", response.status()),
+            ),
+            // tracing is optional, the programs work without it
+            Err(e) => self.add(
+                "zipkin",
+                Status::Warning,
+                format!("{endpoint} unavailable, traces are lost: {e}"),
+            ),
+        }
+    }
+
+    /// Checks that the controller behind the client API of the variable speaks the same protocol.
+    pub fn protocol_handshake(&mut self, var: &str) {
+        let Ok(endpoint) = std::env::var(var) else {
+            return;
+        };
+        let handshake = || -> Result<Welcome> {
+            let mut requester = zmq_sockets::Requester::new(&self.context)?.connect(&endpoint)?;
+            requester.set_message_exchange_timeout(Some(PROBE_TIMEOUT))?;
+            requester.send(ClientApiCommand::hello())?;
+            Ok(requester.receive()?)
+        };
+        match handshake() {
+            Ok(welcome) if welcome.protocol_version == PROTOCOL_VERSION => self.add(
+                "protocol",
+                Status::Ok,
+                format!("controller speaks protocol version {PROTOCOL_VERSION}"),
+            ),
+            Ok(welcome) => self.add(
+                "protocol",
+                Status::Warning,
+                format!(
+                    "controller speaks protocol version {} instead of {PROTOCOL_VERSION}, some features are unavailable",
+                    welcome.protocol_version
+                ),
+            ),
+            Err(e) => self.add(
+                "protocol",
+                Status::Error,
+                format!("handshake with {endpoint} failed: {e:#}"),
+            ),
+        }
+    }
+
+    /// Prints the report and fails if any check failed.
+    pub fn finish(self) -> Result<()> {
+        println!("{} {DOCTOR_FLAG}", self.program);
+        let width = self.checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        for check in &self.checks {
+            println!(
+                "  [{:<4}] {:<width$}  {}",
+                check.status, check.name, check.detail
+            );
+        }
+        let worst = self
+            .checks
+            .iter()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(Status::Ok);
+        println!("Result: {worst}");
+        // Workaround: the destructor of the context may block.
+        std::mem::forget(self.context);
+        anyhow::ensure!(worst != Status::Error, "Some checks failed");
+        Ok(())
+    }
+}
+
+fn tcp_connect(endpoint: &str) -> Result<String> {
+    let address = endpoint
+        .strip_prefix("tcp://")
+        .with_context(|| anyhow::anyhow!("Only tcp:// endpoints can be probed"))?;
+    let addresses: Vec<_> = address
+        .to_socket_addrs()
+        .with_context(|| anyhow::anyhow!("Failed to resolve {address}"))?
+        .collect();
+    let mut error = None;
+    for address in addresses {
+        match std::net::TcpStream::connect_timeout(&address, PROBE_TIMEOUT) {
+            Ok(_) => return Ok(format!("{address} is reachable")),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.map_or_else(|| anyhow::anyhow!("Address did not resolve"), Into::into))
+}
@@ -0,0 +1,187 @@
+//! Encryption of the payloads inside the [`PayloadEnvelope`]s with a pre-shared key per
+//! deployment, so measurements and commands are not readable on the wire of any transport.
+//!
+//! The headers stay readable because they are needed for routing and tracing, and the type URL
+//! is authenticated but not encrypted so envelopes can still be inspected with
+//! [`PayloadEnvelope::contains`].
+
+use std::{borrow::Cow, sync::OnceLock};
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+
+use crate::protobuf::PayloadEnvelope;
+
+/// Pre-shared key of the deployment as 64 hex digits, payloads are sent in plain text if unset.
+pub const ENV_PAYLOAD_KEY: &str = "HOME_AUTOMATION_PAYLOAD_KEY";
+/// Header naming the algorithm the payload is encrypted with.
+pub const ENCRYPTION_HEADER: &str = "encryption";
+
+const ALGORITHM: &str = "chacha20poly1305";
+pub(crate) const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+static GLOBAL: OnceLock<Result<Option<PayloadCipher>, EncryptionError>> = OnceLock::new();
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum EncryptionError {
+    #[error("invalid {ENV_PAYLOAD_KEY}: {0}")]
+    InvalidKey(String),
+    #[error("payload is encrypted, but {ENV_PAYLOAD_KEY} is not set")]
+    MissingKey,
+    #[error("unencrypted payload rejected because {ENV_PAYLOAD_KEY} is set")]
+    Unencrypted,
+    #[error("unsupported encryption {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("payload cannot be decrypted with the configured key")]
+    Decrypt,
+    #[error("failed to encrypt payload")]
+    Encrypt,
+}
+
+pub struct PayloadCipher(ChaCha20Poly1305);
+
+impl std::fmt::Debug for PayloadCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never print the key
+        f.write_str("PayloadCipher")
+    }
+}
+
+impl PayloadCipher {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        Self(ChaCha20Poly1305::new(Key::from_slice(key)))
+    }
+
+    /// Parses a key of 64 hex digits.
+    pub fn from_hex(key: &str) -> Result<Self, EncryptionError> {
+        parse_hex_key(key)
+            .map(|key| Self::new(&key))
+            .map_err(EncryptionError::InvalidKey)
+    }
+
+    /// Reads the key from [`ENV_PAYLOAD_KEY`], `None` if encryption is disabled.
+    pub fn from_env() -> Result<Option<Self>, EncryptionError> {
+        std::env::var(ENV_PAYLOAD_KEY)
+            .ok()
+            .map(|key| Self::from_hex(&key))
+            .transpose()
+    }
+
+    /// Uses the given cipher for all envelopes of this process.
+    ///
+    /// Fails if the cipher was already configured, e.g. because an envelope was sent before.
+    pub fn install(cipher: Option<Self>) -> Result<(), Option<Self>> {
+        GLOBAL
+            .set(Ok(cipher))
+            .map_err(|cipher| cipher.ok().flatten())
+    }
+
+    /// Returns the cipher of this process, reading the key from the environment on first use.
+    ///
+    /// An invalid key fails every send and receive instead of falling back to plain text.
+    pub fn global() -> Result<Option<&'static Self>, EncryptionError> {
+        GLOBAL
+            .get_or_init(Self::from_env)
+            .as_ref()
+            .map(Option::as_ref)
+            .map_err(Clone::clone)
+    }
+
+    /// Replaces the payload with its encryption and marks the envelope in its headers.
+    pub fn encrypt(&self, envelope: &mut PayloadEnvelope) -> Result<(), EncryptionError> {
+        let Some(payload) = &mut envelope.payload else {
+            return Ok(());
+        };
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &payload.value,
+                    aad: payload.type_url.as_bytes(),
+                },
+            )
+            .map_err(|_| EncryptionError::Encrypt)?;
+        payload.value = [nonce.as_slice(), &ciphertext].concat();
+        envelope
+            .headers
+            .insert(ENCRYPTION_HEADER.to_owned(), ALGORITHM.to_owned());
+        Ok(())
+    }
+
+    /// Restores the payload of an envelope marked as encrypted.
+    pub fn decrypt(&self, envelope: &mut PayloadEnvelope) -> Result<(), EncryptionError> {
+        let Some(algorithm) = envelope.headers.remove(ENCRYPTION_HEADER) else {
+            return Err(EncryptionError::Unencrypted);
+        };
+        if algorithm != ALGORITHM {
+            return Err(EncryptionError::UnsupportedAlgorithm(algorithm));
+        }
+        let Some(payload) = &mut envelope.payload else {
+            return Ok(());
+        };
+        if payload.value.len() < NONCE_LEN {
+            return Err(EncryptionError::Decrypt);
+        }
+        let (nonce, ciphertext) = payload.value.split_at(NONCE_LEN);
+        payload.value = self
+            .0
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: payload.type_url.as_bytes(),
+                },
+            )
+            .map_err(|_| EncryptionError::Decrypt)?;
+        Ok(())
+    }
+}
+
+/// Parses a key of 64 hex digits, also used for the keys of the command signatures.
+pub(crate) fn parse_hex_key(key: &str) -> Result<[u8; KEY_LEN], String> {
+    let key = key.trim();
+    if key.len() != 2 * KEY_LEN {
+        return Err(format!(
+            "expected {} hex digits, got {}",
+            2 * KEY_LEN,
+            key.len()
+        ));
+    }
+    if !key.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err("not a hex number".to_owned());
+    }
+    let mut bytes = [0; KEY_LEN];
+    for (byte, digits) in bytes.iter_mut().zip(key.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).expect("ASCII hex digits");
+        *byte = u8::from_str_radix(digits, 16).expect("valid hex digits");
+    }
+    Ok(bytes)
+}
+
+/// Returns the envelope to send, with an encrypted copy of the payload if a key is configured.
+pub fn seal(envelope: &PayloadEnvelope) -> Result<Cow<'_, PayloadEnvelope>, EncryptionError> {
+    let Some(cipher) = PayloadCipher::global()? else {
+        return Ok(Cow::Borrowed(envelope));
+    };
+    let mut envelope = envelope.clone();
+    cipher.encrypt(&mut envelope)?;
+    Ok(Cow::Owned(envelope))
+}
+
+/// Decrypts the payload after it was received.
+///
+/// With a key, unencrypted payloads are rejected so nobody can inject messages without it.
+pub fn open(envelope: &mut PayloadEnvelope) -> Result<(), EncryptionError> {
+    match PayloadCipher::global()? {
+        Some(cipher) => cipher.decrypt(envelope),
+        None if envelope.headers.contains_key(ENCRYPTION_HEADER) => {
+            Err(EncryptionError::MissingKey)
+        }
+        None => Ok(()),
+    }
+}
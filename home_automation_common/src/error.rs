@@ -0,0 +1,143 @@
+use crate::{encryption::EncryptionError, envelope::EnvelopeError};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors returned by the socket wrappers in [`zmq_sockets`][crate::zmq_sockets].
+///
+/// Every variant carries a human readable context describing the failed operation.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{context}")]
+    Zmq {
+        context: String,
+        #[source]
+        source: zmq::Error,
+    },
+    #[error("{context}")]
+    Decode {
+        context: String,
+        #[source]
+        source: EnvelopeError,
+    },
+    #[error("{context}")]
+    Encryption {
+        context: String,
+        #[source]
+        source: EncryptionError,
+    },
+    #[error("{context}: operation timed out")]
+    Timeout { context: String },
+    #[error("{context}: context was terminated")]
+    Termination { context: String },
+    #[error("{context}: operation cannot be accomplished in the current socket state")]
+    InvalidState { context: String },
+    #[error("{context}: {reason}")]
+    InvalidArgument { context: String, reason: String },
+}
+
+/// Coarse classification of an [`Error`] for matching without inspecting the details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Zmq,
+    Decode,
+    Encryption,
+    Timeout,
+    Termination,
+    InvalidState,
+    InvalidArgument,
+}
+
+impl Error {
+    /// Wraps the ØMQ error and maps the error codes with dedicated meaning to their variant.
+    pub fn zmq(context: impl Into<String>, source: zmq::Error) -> Self {
+        let context = context.into();
+        match source {
+            zmq::Error::EAGAIN => Self::Timeout { context },
+            zmq::Error::ETERM => Self::Termination { context },
+            zmq::Error::EFSM => Self::InvalidState { context },
+            source => Self::Zmq { context, source },
+        }
+    }
+
+    pub fn decode(context: impl Into<String>, source: EnvelopeError) -> Self {
+        Self::Decode {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn encryption(context: impl Into<String>, source: EncryptionError) -> Self {
+        Self::Encryption {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn invalid_argument(context: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::InvalidArgument {
+            context: context.into(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Zmq { .. } => ErrorKind::Zmq,
+            Self::Decode { .. } => ErrorKind::Decode,
+            Self::Encryption { .. } => ErrorKind::Encryption,
+            Self::Timeout { .. } => ErrorKind::Timeout,
+            Self::Termination { .. } => ErrorKind::Termination,
+            Self::InvalidState { .. } => ErrorKind::InvalidState,
+            Self::InvalidArgument { .. } => ErrorKind::InvalidArgument,
+        }
+    }
+}
+
+/// Access to the [`ErrorKind`] of errors that may contain an [`Error`].
+///
+/// Implemented for `anyhow::Error` as well so the binaries can keep using `anyhow` while still
+/// reacting to e.g. a terminated context.
+pub trait ErrorKindExt {
+    fn error_kind(&self) -> Option<ErrorKind>;
+
+    fn is_termination(&self) -> bool {
+        self.error_kind() == Some(ErrorKind::Termination)
+    }
+
+    fn is_timeout(&self) -> bool {
+        self.error_kind() == Some(ErrorKind::Timeout)
+    }
+
+    fn is_invalid_state(&self) -> bool {
+        self.error_kind() == Some(ErrorKind::InvalidState)
+    }
+}
+
+impl ErrorKindExt for Error {
+    fn error_kind(&self) -> Option<ErrorKind> {
+        Some(self.kind())
+    }
+}
+
+impl ErrorKindExt for anyhow::Error {
+    fn error_kind(&self) -> Option<ErrorKind> {
+        self.downcast_ref::<Error>().map(Error::kind)
+    }
+}
+
+pub(crate) trait ZmqResultExt<T> {
+    fn zmq_context<C, F>(self, context: F) -> Result<T>
+    where
+        C: Into<String>,
+        F: FnOnce() -> C;
+}
+
+impl<T> ZmqResultExt<T> for std::result::Result<T, zmq::Error> {
+    fn zmq_context<C, F>(self, context: F) -> Result<T>
+    where
+        C: Into<String>,
+        F: FnOnce() -> C,
+    {
+        self.map_err(|e| Error::zmq(context(), e))
+    }
+}
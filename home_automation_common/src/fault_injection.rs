@@ -0,0 +1,125 @@
+//! Fault injection for chaos testing of the socket wrappers in [`zmq_sockets`][crate::zmq_sockets].
+//!
+//! Only compiled with the `fault-injection` feature. The faults are configured once per process
+//! via environment variables or explicitly with [`FaultInjection::install`]:
+//! - publications are dropped with the probability [`ENV_FAULT_DROP_PERCENT`],
+//! - every send is delayed by [`ENV_FAULT_SEND_DELAY_MS`],
+//! - receiving on `REQ` and `SUB` sockets fails with a timeout with the probability
+//!   [`ENV_FAULT_RECEIVE_ERROR_PERCENT`].
+//!
+//! Requests and replies are never dropped because the REQ-REP state machine cannot recover from
+//! a lost message on the sending side; a failing receive emulates a lost reply instead.
+//! The random decisions are drawn from a generator seeded with [`ENV_FAULT_SEED`], so a run can be
+//! reproduced as long as the sockets are used in the same order.
+
+use std::{
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+pub const ENV_FAULT_DROP_PERCENT: &str = "HOME_AUTOMATION_FAULT_DROP_PERCENT";
+pub const ENV_FAULT_SEND_DELAY_MS: &str = "HOME_AUTOMATION_FAULT_SEND_DELAY_MS";
+pub const ENV_FAULT_RECEIVE_ERROR_PERCENT: &str = "HOME_AUTOMATION_FAULT_RECEIVE_ERROR_PERCENT";
+pub const ENV_FAULT_SEED: &str = "HOME_AUTOMATION_FAULT_SEED";
+
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+static GLOBAL: OnceLock<FaultInjection> = OnceLock::new();
+
+/// Probabilities and delays of the injected faults.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FaultConfiguration {
+    pub drop_percent: f64,
+    pub send_delay: Duration,
+    pub receive_error_percent: f64,
+    pub seed: u64,
+}
+
+impl FaultConfiguration {
+    /// Reads the configuration from the environment, unset or invalid values disable the fault.
+    pub fn from_env() -> Self {
+        fn parse<T: std::str::FromStr>(var: &str) -> Option<T> {
+            let value = std::env::var(var).ok()?;
+            let parsed = value.parse().ok();
+            if parsed.is_none() {
+                tracing::warn!("Ignoring invalid value {value} of {var}");
+            }
+            parsed
+        }
+
+        Self {
+            drop_percent: parse(ENV_FAULT_DROP_PERCENT).unwrap_or_default(),
+            send_delay: Duration::from_millis(parse(ENV_FAULT_SEND_DELAY_MS).unwrap_or_default()),
+            receive_error_percent: parse(ENV_FAULT_RECEIVE_ERROR_PERCENT).unwrap_or_default(),
+            seed: parse(ENV_FAULT_SEED).unwrap_or(DEFAULT_SEED),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FaultInjection {
+    configuration: FaultConfiguration,
+    /// state of the xorshift64* generator
+    state: Mutex<u64>,
+}
+
+impl FaultInjection {
+    pub fn new(configuration: FaultConfiguration) -> Self {
+        Self {
+            configuration,
+            // xorshift gets stuck at zero
+            state: Mutex::new(configuration.seed.max(1)),
+        }
+    }
+
+    /// Uses the given configuration for all sockets of this process.
+    ///
+    /// Fails if the faults were already configured, e.g. because a socket was used before.
+    pub fn install(configuration: FaultConfiguration) -> Result<(), FaultConfiguration> {
+        GLOBAL
+            .set(Self::new(configuration))
+            .map_err(|injection| injection.configuration)
+    }
+
+    /// Returns the faults of this process, reading them from the environment on first use.
+    pub fn global() -> &'static Self {
+        GLOBAL.get_or_init(|| {
+            let configuration = FaultConfiguration::from_env();
+            tracing::warn!(?configuration, "Fault injection is active");
+            Self::new(configuration)
+        })
+    }
+
+    pub fn configuration(&self) -> FaultConfiguration {
+        self.configuration
+    }
+
+    pub fn drop_publication(&self) -> bool {
+        self.roll(self.configuration.drop_percent)
+    }
+
+    pub fn fail_receive(&self) -> bool {
+        self.roll(self.configuration.receive_error_percent)
+    }
+
+    pub fn delay_send(&self) {
+        if !self.configuration.send_delay.is_zero() {
+            std::thread::sleep(self.configuration.send_delay);
+        }
+    }
+
+    /// Returns `true` with the given probability in percent.
+    fn roll(&self, percent: f64) -> bool {
+        if percent <= 0.0 {
+            return false;
+        }
+        let mut state = self.state.lock().expect("non-poisoned Mutex");
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        let random = state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+        // 53 bits fit exactly into the mantissa of a f64
+        let sample = random as f64 / (1u64 << 53) as f64 * 100.0;
+        sample < percent
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use crate::protobuf::SensorConfiguration;
+
+/// Rate at which an entity publishes its data.
+///
+/// The protocol transmits the frequency in Hz while the entities sleep for the period, this type
+/// converts between both and guarantees that the frequency is positive, finite and has a period
+/// that a [`Duration`] can represent and that is not zero.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct UpdateFrequency {
+    hz: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum InvalidFrequency {
+    #[error("update frequency must be a positive finite number of Hz with a representable non-zero period but is {0}")]
+    OutOfRange(f32),
+    #[error("update frequency {0:?} is not a number")]
+    Parse(String),
+}
+
+impl UpdateFrequency {
+    pub fn from_hz(hz: f32) -> Result<Self, InvalidFrequency> {
+        let period = Duration::try_from_secs_f32(1. / hz);
+        if hz.is_finite() && hz > 0.0 && period.is_ok_and(|period| !period.is_zero()) {
+            Ok(Self { hz })
+        } else {
+            Err(InvalidFrequency::OutOfRange(hz))
+        }
+    }
+
+    pub fn from_period(period: Duration) -> Result<Self, InvalidFrequency> {
+        Self::from_hz(1. / period.as_secs_f32())
+    }
+
+    pub fn hz(self) -> f32 {
+        self.hz
+    }
+
+    /// Returns the time between two publications.
+    pub fn period(self) -> Duration {
+        Duration::try_from_secs_f32(1. / self.hz).expect("period checked by from_hz")
+    }
+}
+
+impl fmt::Display for UpdateFrequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz", self.hz)
+    }
+}
+
+/// Parses a number of Hz with an optional `Hz` unit, e.g. `2.5` or `2.5 Hz`.
+impl FromStr for UpdateFrequency {
+    type Err = InvalidFrequency;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let number = trimmed
+            .strip_suffix("Hz")
+            .or_else(|| trimmed.strip_suffix("hz"))
+            .unwrap_or(trimmed)
+            .trim_end();
+        let hz = number
+            .parse()
+            .map_err(|_| InvalidFrequency::Parse(s.to_owned()))?;
+        Self::from_hz(hz)
+    }
+}
+
+impl TryFrom<&SensorConfiguration> for UpdateFrequency {
+    type Error = InvalidFrequency;
+
+    fn try_from(configuration: &SensorConfiguration) -> Result<Self, Self::Error> {
+        Self::from_hz(configuration.update_frequency_hz)
+    }
+}
+
+impl From<UpdateFrequency> for SensorConfiguration {
+    fn from(frequency: UpdateFrequency) -> Self {
+        Self {
+            update_frequency_hz: frequency.hz,
+        }
+    }
+}
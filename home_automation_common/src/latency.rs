@@ -0,0 +1,90 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::protobuf::Latency;
+
+/// Number of the most recent samples the percentiles are computed from.
+const WINDOW_CAPACITY: usize = 1000;
+/// Clock offsets of at least this size are reported as suspicious.
+pub const SUSPICIOUS_CLOCK_OFFSET_MS: i64 = 1000;
+
+/// Milliseconds since the Unix epoch, the format of the publish timestamps.
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+/// Estimates how far the local clock is ahead of the remote clock in milliseconds, NTP style.
+///
+/// The remote time is assumed to be taken halfway between sending the request and receiving the
+/// reply, so the estimate is off by at most half of the round trip.
+pub fn estimate_clock_offset_ms(sent_at_ms: u64, remote_time_ms: u64, received_at_ms: u64) -> i64 {
+    let midpoint = (i128::from(sent_at_ms) + i128::from(received_at_ms)) / 2;
+    let offset = midpoint - i128::from(remote_time_ms);
+    offset.clamp(i64::MIN.into(), i64::MAX.into()) as i64
+}
+
+/// Converts a timestamp of a clock that is `clock_offset_ms` ahead to the local clock.
+///
+/// Unknown timestamps (`0`) stay unknown.
+pub fn correct_timestamp_ms(timestamp_ms: u64, clock_offset_ms: i64) -> u64 {
+    if timestamp_ms == 0 {
+        return 0;
+    }
+    timestamp_ms
+        .saturating_add_signed(clock_offset_ms.saturating_neg())
+        .max(1)
+}
+
+/// Latencies of the most recent samples of the publish pipeline.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyWindow {
+    samples: VecDeque<Duration>,
+}
+
+impl LatencyWindow {
+    /// Records the time since the publication, timestamps of `0` are unknown and ignored.
+    ///
+    /// A timestamp in the future because of clock differences counts as zero latency.
+    pub fn record_since(&mut self, published_at_ms: u64) {
+        if published_at_ms == 0 {
+            return;
+        }
+        let elapsed = unix_time_ms().saturating_sub(published_at_ms);
+        self.record(Duration::from_millis(elapsed));
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        if self.samples.len() == WINDOW_CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+    }
+
+    /// Nearest-rank percentile, e.g. `0.99` for the 99th percentile.
+    pub fn percentile(&self, fraction: f32) -> Option<Duration> {
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (fraction.clamp(0.0, 1.0) * sorted.len() as f32).ceil() as usize;
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+
+    pub fn summary(&self) -> Latency {
+        let milliseconds = |fraction| {
+            self.percentile(fraction)
+                .map_or(0.0, |latency| latency.as_secs_f32() * 1000.0)
+        };
+        Latency {
+            samples: self.samples.len().try_into().unwrap_or(u32::MAX),
+            p50_ms: milliseconds(0.5),
+            p90_ms: milliseconds(0.9),
+            p99_ms: milliseconds(0.99),
+        }
+    }
+}
@@ -1,11 +1,7 @@
-use std::{
-    sync::atomic::{AtomicBool, Ordering},
-    time::Duration,
-};
+use std::{sync::OnceLock, time::Duration};
 
 use anyhow::Context;
-use bytes::Bytes;
-use opentelemetry_http::{HttpError, Request, Response};
+use home_automation_protocol::topic;
 use protobuf::entity_discovery_command::EntityType;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
@@ -22,130 +18,48 @@ where
     }
 }
 
-pub trait AnyhowZmq {
-    fn is_zmq_termination(&self) -> bool;
-    fn is_zmq_timeout(&self) -> bool;
-    fn is_zmq_invalid_state(&self) -> bool;
-}
-
-impl AnyhowZmq for anyhow::Error {
-    fn is_zmq_termination(&self) -> bool {
-        self.downcast_ref()
-            .is_some_and(|e: &zmq::Error| matches!(e, zmq::Error::ETERM))
-    }
-
-    fn is_zmq_timeout(&self) -> bool {
-        self.downcast_ref()
-            .is_some_and(|e: &zmq::Error| matches!(e, zmq::Error::EAGAIN))
-    }
-
-    fn is_zmq_invalid_state(&self) -> bool {
-        self.downcast_ref()
-            .is_some_and(|e: &zmq::Error| matches!(e, zmq::Error::EFSM))
-    }
-}
-
+pub mod doctor;
+pub mod encryption;
+pub mod error;
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection;
+pub mod frequency;
+pub mod latency;
+pub mod log_event;
+pub mod log_file;
+pub mod log_summary;
+pub mod mqtt;
+#[cfg(feature = "nng")]
+pub mod nng_transport;
+pub mod registration_auth;
+pub mod schedule;
+pub mod serial;
+pub mod shutdown;
+pub mod signals;
+pub mod signing;
+pub mod transport;
+pub mod udp;
+pub mod value_range;
+pub mod zipkin;
 pub mod zmq_sockets;
 
-pub mod protobuf {
-    include!(concat!(env!("OUT_DIR"), "/wipmate.rs"));
-
-    impl<T, E> From<Result<T, E>> for ResponseCode {
-        fn from(value: Result<T, E>) -> Self {
-            ResponseCode {
-                code: match value {
-                    Ok(_) => response_code::Code::Ok,
-                    Err(_) => response_code::Code::Error,
-                }
-                .into(),
-            }
-        }
-    }
-
-    impl From<SensorMeasurement> for PublishData {
-        fn from(m: SensorMeasurement) -> Self {
-            Self {
-                value: Some(publish_data::Value::Measurement(m)),
-            }
-        }
-    }
-
-    impl From<ActuatorState> for PublishData {
-        fn from(m: ActuatorState) -> Self {
-            Self {
-                value: Some(publish_data::Value::ActuatorState(m)),
-            }
-        }
-    }
-
-    impl ActuatorState {
-        pub fn light(brightness: f32) -> Self {
-            Self {
-                state: Some(actuator_state::State::Light(LightActuatorState {
-                    brightness,
-                })),
-            }
-        }
-
-        pub fn air_conditioning(on: bool) -> Self {
-            Self {
-                state: Some(actuator_state::State::AirConditioning(
-                    AirConditioningActuatorState { on },
-                )),
-            }
-        }
-    }
-
-    impl NamedEntityState {
-        pub fn actuator(entity_name: impl Into<String>, value: ActuatorState) -> Self {
-            Self {
-                entity_name: entity_name.into(),
-                state: Some(named_entity_state::State::ActuatorState(value)),
-            }
-        }
-
-        pub fn frequency(entity_name: impl Into<String>, update_frequency_hz: f32) -> Self {
-            Self {
-                entity_name: entity_name.into(),
-                state: Some(named_entity_state::State::SensorConfiguration(
-                    SensorConfiguration {
-                        update_frequency_hz,
-                    },
-                )),
-            }
-        }
-    }
-
-    impl std::fmt::Display for entity_discovery_command::EntityType {
-        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            f.write_str(match self {
-                Self::Actuator => "Actuator",
-                Self::Sensor => "Sensor",
-            })
-        }
-    }
-
-    impl ClientApiCommand {
-        pub fn system_state_query() -> Self {
-            use client_api_command::CommandType;
-            ClientApiCommand {
-                command_type: Some(CommandType::Query(SystemStateQuery::default())),
-            }
-        }
-
-        pub fn named_entity_state(named_entity_state: NamedEntityState) -> Self {
-            use client_api_command::CommandType;
-            ClientApiCommand {
-                command_type: Some(CommandType::Action(named_entity_state)),
-            }
-        }
-    }
-}
+pub use error::{Error, ErrorKind, ErrorKindExt, Result};
+pub use frequency::{InvalidFrequency, UpdateFrequency};
+pub use home_automation_protocol::{
+    capabilities, chunk, envelope, features, protobuf, topic::META as META_TOPIC_CLASS,
+    PROTOCOL_VERSION,
+};
+pub use shutdown::ShutdownToken;
+pub use signals::{install_graceful_signal_handler, install_signal_handler};
 
+/// Last state of an entity, shared by the controller and the clients.
+///
+/// See `home_automation_api::entities` for the conversion from and to a `SystemState`.
 #[derive(Debug, Clone)]
 pub enum EntityState {
     Sensor(protobuf::SensorMeasurement),
     Actuator(protobuf::ActuatorState),
+    /// Registered, but did not publish yet.
     New(EntityType),
 }
 
@@ -157,76 +71,122 @@ impl EntityState {
             Self::New(t) => *t,
         }
     }
+
+    /// Converts the published state into a `oneof` of a message, e.g.
+    /// `state.published(LastState::Measurement, LastState::ActuatorState)`.
+    ///
+    /// Returns `None` for [`EntityState::New`].
+    pub fn published<T>(
+        &self,
+        measurement: impl FnOnce(protobuf::SensorMeasurement) -> T,
+        actuator_state: impl FnOnce(protobuf::ActuatorState) -> T,
+    ) -> Option<T> {
+        match self {
+            Self::Sensor(m) => Some(measurement(m.clone())),
+            Self::Actuator(s) => Some(actuator_state(s.clone())),
+            Self::New(_) => None,
+        }
+    }
 }
 
 pub const ENV_DISCOVERY_ENDPOINT: &str = "HOME_AUTOMATION_DISCOVERY_ENDPOINT";
 pub const ENV_ENTITY_DATA_ENDPOINT: &str = "HOME_AUTOMATION_ENTITY_DATA_ENDPOINT";
 pub const ENV_CLIENT_API_ENDPOINT: &str = "HOME_AUTOMATION_CLIENT_API_ENDPOINT";
+/// Optional endpoint where the controller republishes the entity data for other subscribers.
+pub const ENV_DATA_STREAM_ENDPOINT: &str = "HOME_AUTOMATION_DATA_STREAM_ENDPOINT";
+/// Optional endpoint where the controller serves the cached last values of the data stream.
+pub const ENV_LAST_VALUE_ENDPOINT: &str = "HOME_AUTOMATION_LAST_VALUE_ENDPOINT";
+/// Token that clients must present to use the admin commands of the client API, without it the
+/// admin commands are disabled.
+pub const ENV_ADMIN_TOKEN: &str = "HOME_AUTOMATION_ADMIN_TOKEN";
+/// Optional comma separated list of tags an entity registers with, e.g. `outdoor,garden`.
+pub const ENV_ENTITY_TAGS: &str = "HOME_AUTOMATION_ENTITY_TAGS";
+/// Optional initial publish frequency of an entity in Hz.
+pub const ENV_UPDATE_FREQUENCY: &str = "HOME_AUTOMATION_UPDATE_FREQUENCY_HZ";
+pub const ENV_TOPIC_PREFIX: &str = "HOME_AUTOMATION_TOPIC_PREFIX";
 
 pub fn load_env(var: &str) -> anyhow::Result<String> {
     std::env::var(var).with_context(|| anyhow::anyhow!("Failed to read env var {var}"))
 }
 
-pub const HEARTBEAT_FREQUENCY: Duration = Duration::from_secs(10);
+/// Checks whether the presented token is the configured one, e.g. the [`ENV_ADMIN_TOKEN`].
+///
+/// The tokens are compared by their HMAC in constant time, so the time taken reveals neither the
+/// correct prefix nor the length. An empty configured token matches nothing.
+pub fn token_matches(configured: &str, presented: &str) -> bool {
+    use hmac::{Hmac, Mac};
+    let mac = |token: &str| {
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(configured.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(token.as_bytes());
+        mac
+    };
+    !configured.is_empty()
+        && mac(presented)
+            .verify_slice(&mac(configured).finalize().into_bytes())
+            .is_ok()
+}
+
+/// Interval between the heartbeats of entities that do not propose one at their registration.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Interval in which every binary logs the traffic statistics of its sockets.
+pub const STATISTICS_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Returns the deployment specific prefix of all topics, e.g. `/lab42`.
+///
+/// It is read once from [`ENV_TOPIC_PREFIX`] and empty if the variable is not set.
+pub fn topic_prefix() -> &'static str {
+    static PREFIX: OnceLock<String> = OnceLock::new();
+    PREFIX.get_or_init(|| {
+        std::env::var(ENV_TOPIC_PREFIX)
+            .map(|prefix| prefix.trim_end_matches('/').to_owned())
+            .unwrap_or_default()
+    })
+}
+
+/// Returns the class of the topic, e.g. `measurement` for `/lab42/measurement/sen_a`.
+pub fn topic_class(topic: &str) -> Option<&str> {
+    topic::topic_class(topic_prefix(), topic)
+}
+
+/// Returns the type of the entities that publish on the topic, `None` for unknown classes.
+pub fn topic_entity_type(topic: &str) -> Option<EntityType> {
+    topic::topic_entity_type(topic_prefix(), topic)
+}
 
 pub fn actuator_name(topic: &str) -> anyhow::Result<String> {
-    Ok(topic
-        .strip_prefix("/actuator_state/")
-        .with_context(|| anyhow::anyhow!("Failed to parse topic {topic} as actuator topic"))?
-        .to_owned())
+    topic::actuator_name(topic_prefix(), topic)
+        .map(ToOwned::to_owned)
+        .with_context(|| anyhow::anyhow!("Failed to parse topic {topic} as actuator topic"))
 }
 
 pub fn actuator_state_topic(name: &str) -> String {
-    format!("/actuator_state/{name}")
+    topic::actuator_state_topic(topic_prefix(), name)
 }
 
 pub fn sensor_name(topic: &str) -> anyhow::Result<String> {
-    Ok(topic
-        .strip_prefix("/measurement/")
-        .with_context(|| anyhow::anyhow!("Failed to parse topic {topic} as sensor topic"))?
-        .to_owned())
-}
-pub fn sensor_measurement_topic(name: &str) -> String {
-    format!("/measurement/{name}")
+    topic::sensor_name(topic_prefix(), topic)
+        .map(ToOwned::to_owned)
+        .with_context(|| anyhow::anyhow!("Failed to parse topic {topic} as sensor topic"))
 }
 
-pub fn entity_topic(name: &str, entity_type: EntityType) -> String {
-    match entity_type {
-        EntityType::Actuator => actuator_state_topic(name),
-        EntityType::Sensor => sensor_measurement_topic(name),
-    }
+pub fn sensor_measurement_topic(name: &str) -> String {
+    topic::sensor_measurement_topic(topic_prefix(), name)
 }
 
-static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
-
-#[inline]
-pub fn shutdown_requested() -> bool {
-    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+pub fn meta_name(topic: &str) -> anyhow::Result<String> {
+    topic::meta_name(topic_prefix(), topic)
+        .map(ToOwned::to_owned)
+        .with_context(|| anyhow::anyhow!("Failed to parse topic {topic} as meta topic"))
 }
 
-#[inline]
-pub fn request_shutdown() {
-    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+pub fn meta_topic(name: &str) -> String {
+    topic::meta_topic(topic_prefix(), name)
 }
 
-pub fn install_signal_handler(context: zmq_sockets::Context) -> anyhow::Result<()> {
-    ctrlc::set_handler(move || {
-        tracing::info!("Shutdown signal received");
-        if shutdown_requested() {
-            tracing::warn!("Shutdown was already requested previously. Forcing shutdown now.");
-            std::process::abort();
-        }
-        // Workaround: context.destroy() seems to block forever and prevent a second signal from
-        // getting to the ctrlc thread.
-        std::thread::spawn({
-            let mut context = context.clone();
-            move || {
-                request_shutdown();
-                context.destroy().expect("Failed to destroy context");
-            }
-        });
-    })
-    .context("Failed to install signal handler")
+pub fn entity_topic(name: &str, entity_type: EntityType) -> String {
+    topic::entity_topic(topic_prefix(), name, entity_type)
 }
 
 pub struct OpenTelemetryConfiguration(());
@@ -241,27 +201,55 @@ impl OpenTelemetryConfiguration {
         }
         opentelemetry::global::set_text_map_propagator(opentelemetry_zipkin::Propagator::new());
 
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer().with_writer(writer))
+            .with(EnvFilter::from_default_env());
+        let service_name = service_name.into();
         let tracer = opentelemetry_zipkin::new_pipeline()
-            .with_service_name(service_name)
-            .with_http_client(UReqHttpClient)
-            .install_simple()
-            .context("Failed to install opentelemetry_zipkin tracer")?;
-
-        let tracer = tracing_opentelemetry::layer().with_tracer(tracer);
-
-        let subscriber = tracing_subscriber::fmt::layer().with_writer(writer);
-
-        tracing_subscriber::registry()
-            .with(subscriber)
-            .with(EnvFilter::from_default_env())
-            .with(tracer)
-            .init();
+            .with_service_name(service_name.clone())
+            .with_http_client(zipkin::UReqHttpClient)
+            .install_simple();
+        match tracer {
+            Ok(tracer) => {
+                subscriber
+                    .with(tracing_opentelemetry::layer().with_tracer(tracer))
+                    // after the OpenTelemetry layer, which creates the span data
+                    .with(zipkin::ResourceLayer::new())
+                    .init();
+                tracing::info!(
+                    instance = zipkin::instance_id(),
+                    "Tracing as {service_name} instance {}",
+                    zipkin::instance_id()
+                );
+                zipkin::check_collector();
+            }
+            // tracing is optional, the programs work with logs only
+            Err(e) => {
+                subscriber.init();
+                tracing::warn!("Failed to install the Zipkin exporter, logging only: {e}");
+            }
+        }
 
         Ok(OpenTelemetryConfiguration(()))
     }
     pub fn new(service_name: impl Into<String>) -> anyhow::Result<Self> {
         Self::with_writer(service_name, std::io::stderr)
     }
+
+    /// Logs to stderr and additionally to the log file if given.
+    pub fn with_optional_log_file(
+        service_name: impl Into<String>,
+        log_file: Option<log_file::RotatingLogFile>,
+    ) -> anyhow::Result<Self> {
+        use tracing_subscriber::fmt::writer::MakeWriterExt as _;
+        match log_file {
+            Some(log_file) => Self::with_writer(
+                service_name,
+                std::io::stderr.and(std::sync::Mutex::new(log_file)),
+            ),
+            None => Self::new(service_name),
+        }
+    }
 }
 
 impl Drop for OpenTelemetryConfiguration {
@@ -269,17 +257,3 @@ impl Drop for OpenTelemetryConfiguration {
         opentelemetry::global::shutdown_tracer_provider();
     }
 }
-
-#[derive(Debug)]
-struct UReqHttpClient;
-
-#[async_trait::async_trait]
-impl opentelemetry_http::HttpClient for UReqHttpClient {
-    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
-        let (http_parts, body) = request.into_parts();
-        let ureq_request: ureq::Request = http_parts.into();
-        let ureq_response = ureq_request.send_bytes(&body)?;
-        let response: opentelemetry_http::Response<Vec<u8>> = ureq_response.into();
-        Ok(response.map(bytes::Bytes::from))
-    }
-}
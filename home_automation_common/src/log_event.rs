@@ -0,0 +1,192 @@
+//! Typed events of the message exchange and of the command handling.
+//!
+//! Every event is logged with a fixed `event` name and fixed fields, so the logs can be queried
+//! and counted, e.g. the `message_received` events of one `entity` or the `bytes` sent per
+//! socket `kind`.
+
+use std::fmt;
+
+use home_automation_protocol::topic;
+
+use crate::{protobuf::PayloadEnvelope, topic_prefix, Error, ErrorKindExt as _};
+
+pub const MESSAGE_SENT: &str = "message_sent";
+pub const MESSAGE_RECEIVED: &str = "message_received";
+pub const MESSAGE_FAILED: &str = "message_failed";
+pub const COMMAND_HANDLED: &str = "command_handled";
+pub const COMMAND_FAILED: &str = "command_failed";
+
+pub enum LogEvent<'a> {
+    MessageSent {
+        /// Socket type, e.g. `PUB`.
+        kind: &'a str,
+        topic: Option<&'a str>,
+        envelope: &'a PayloadEnvelope,
+        bytes: usize,
+    },
+    MessageReceived {
+        kind: &'a str,
+        topic: Option<&'a str>,
+        envelope: &'a PayloadEnvelope,
+        bytes: usize,
+    },
+    MessageFailed {
+        kind: &'a str,
+        /// `send` or `receive`.
+        operation: &'a str,
+        error: &'a Error,
+    },
+    CommandHandled {
+        /// Type of the command, e.g. `Action`.
+        kind: &'a str,
+        entity: Option<&'a str>,
+    },
+    CommandFailed {
+        kind: &'a str,
+        entity: Option<&'a str>,
+        error: &'a dyn fmt::Display,
+    },
+}
+
+impl<'a> LogEvent<'a> {
+    /// [`CommandHandled`][Self::CommandHandled] or [`CommandFailed`][Self::CommandFailed]
+    /// depending on the result.
+    pub fn command<T, E: fmt::Display>(
+        kind: &'a str,
+        entity: Option<&'a str>,
+        result: &'a Result<T, E>,
+    ) -> Self {
+        match result {
+            Ok(_) => Self::CommandHandled { kind, entity },
+            Err(error) => Self::CommandFailed {
+                kind,
+                entity,
+                error,
+            },
+        }
+    }
+
+    pub fn emit(&self) {
+        match *self {
+            Self::MessageSent {
+                kind,
+                topic,
+                envelope,
+                bytes,
+            } => {
+                let message_type = message_type(envelope);
+                tracing::info!(
+                    event = MESSAGE_SENT,
+                    kind,
+                    topic,
+                    entity = topic.and_then(entity),
+                    message_type,
+                    bytes,
+                    "Sent {message_type} ({bytes} bytes)"
+                );
+            }
+            Self::MessageReceived {
+                kind,
+                topic,
+                envelope,
+                bytes,
+            } => {
+                let message_type = message_type(envelope);
+                tracing::info!(
+                    event = MESSAGE_RECEIVED,
+                    kind,
+                    topic,
+                    entity = topic.and_then(entity),
+                    message_type,
+                    bytes,
+                    "Received {message_type} ({bytes} bytes)"
+                );
+            }
+            Self::MessageFailed {
+                kind,
+                operation,
+                error,
+            } if error.is_termination() => {
+                tracing::info!(
+                    event = MESSAGE_FAILED,
+                    kind,
+                    operation,
+                    error = %Chain(error),
+                    "Failed to {operation} message: {}",
+                    Chain(error)
+                );
+            }
+            Self::MessageFailed {
+                kind,
+                operation,
+                error,
+            } => {
+                tracing::error!(
+                    event = MESSAGE_FAILED,
+                    kind,
+                    operation,
+                    error = %Chain(error),
+                    "Failed to {operation} message: {}",
+                    Chain(error)
+                );
+            }
+            Self::CommandHandled { kind, entity } => {
+                tracing::info!(
+                    event = COMMAND_HANDLED,
+                    kind,
+                    entity,
+                    "Handled {kind} command"
+                );
+            }
+            Self::CommandFailed {
+                kind,
+                entity,
+                error,
+            } => {
+                let error = format!("{error:#}");
+                tracing::warn!(
+                    event = COMMAND_FAILED,
+                    kind,
+                    entity,
+                    error = %error,
+                    "Failed to handle {kind} command: {error}"
+                );
+            }
+        }
+    }
+}
+
+/// Name of the contained message without the type URL prefix, e.g. `wipmate.SystemState`.
+fn message_type(envelope: &PayloadEnvelope) -> &str {
+    envelope
+        .payload
+        .as_ref()
+        .map_or("empty envelope", |payload| {
+            payload
+                .type_url
+                .rsplit_once('/')
+                .map_or(&*payload.type_url, |(_, name)| name)
+        })
+}
+
+/// Name of the entity the topic belongs to.
+fn entity(topic: &str) -> Option<&str> {
+    topic::sensor_name(topic_prefix(), topic)
+        .or_else(|| topic::actuator_name(topic_prefix(), topic))
+}
+
+/// Displays the error followed by all of its sources, like the alternate format of `anyhow`.
+struct Chain<'a>(&'a Error);
+
+impl fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use std::error::Error as _;
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(s) = source {
+            write!(f, ": {s}")?;
+            source = s.source();
+        }
+        Ok(())
+    }
+}
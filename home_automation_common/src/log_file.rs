@@ -0,0 +1,201 @@
+//! Log files that are rotated by size and cleaned up on startup.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+/// Placeholder in the file name that is replaced by the start time of the program.
+pub const TIME_PLACEHOLDER: &str = "{time}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFileConfiguration {
+    pub directory: PathBuf,
+    /// File name with an optional [`TIME_PLACEHOLDER`].
+    ///
+    /// A name without the placeholder results in a single log file for all runs that is only
+    /// rotated once it is full.
+    pub template: String,
+    /// Number of log files that are kept, including the current and the rotated ones.
+    pub max_files: usize,
+    /// Size in bytes after which the file is rotated, `None` if it is never rotated.
+    pub max_size: Option<u64>,
+}
+
+impl LogFileConfiguration {
+    /// Configuration with timestamped files named after the program, e.g. `client-{time}.log`.
+    pub fn new(program: &str) -> Self {
+        Self {
+            directory: ".".into(),
+            template: format!("{program}-{TIME_PLACEHOLDER}.log"),
+            max_files: 10,
+            max_size: Some(10 * 1024 * 1024),
+        }
+    }
+
+    fn path(&self) -> Result<PathBuf> {
+        use time::format_description::well_known::Iso8601;
+        let time = time::OffsetDateTime::now_utc()
+            .format(&Iso8601::DEFAULT)
+            .context("Failed to format timestamp")?
+            // colons are not allowed in file names on Windows
+            .replace(':', "-");
+        Ok(self
+            .directory
+            .join(self.template.replace(TIME_PLACEHOLDER, &time)))
+    }
+
+    /// Returns whether the file was created with this configuration, including rotated files.
+    fn is_log_file(&self, name: &str) -> bool {
+        let (prefix, suffix) = self
+            .template
+            .split_once(TIME_PLACEHOLDER)
+            .unwrap_or((&self.template, ""));
+        let Some(rest) = name.strip_prefix(prefix) else {
+            return false;
+        };
+        // rotated files end with the number of the rotation, e.g. `client.log.1`
+        let rest = match rest.rsplit_once('.') {
+            Some((rest, number))
+                if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                rest
+            }
+            _ => rest,
+        };
+        if self.template.contains(TIME_PLACEHOLDER) {
+            rest.ends_with(suffix)
+        } else {
+            rest.is_empty()
+        }
+    }
+
+    /// Deletes the oldest log files so that at most the configured number of files remain.
+    ///
+    /// Call it after the logger was set up, the outcome is logged.
+    pub fn remove_old_files(&self) {
+        match self.try_remove_old_files() {
+            Ok(removed) if !removed.is_empty() => tracing::info!(?removed, "Removed old log files"),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(%e, "Failed to remove old log files: {e:#}"),
+        }
+    }
+
+    fn try_remove_old_files(&self) -> Result<Vec<PathBuf>> {
+        let entries = std::fs::read_dir(&self.directory).with_context(|| {
+            anyhow::anyhow!("Failed to list log directory {}", self.directory.display())
+        })?;
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name();
+                if !self.is_log_file(name.to_str()?) {
+                    return None;
+                }
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                Some((modified, entry.path()))
+            })
+            .collect();
+        // newest first
+        files.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        let mut removed = Vec::new();
+        for (_, path) in files.into_iter().skip(self.max_files.max(1)) {
+            std::fs::remove_file(&path)
+                .with_context(|| anyhow::anyhow!("Failed to remove {}", path.display()))?;
+            removed.push(path);
+        }
+        Ok(removed)
+    }
+}
+
+/// Log file that is renamed to `<name>.1` once it exceeds the maximum size.
+///
+/// Earlier rotations are shifted to `<name>.2` and so on, the oldest beyond the maximum number
+/// of files is overwritten.
+#[derive(Debug)]
+pub struct RotatingLogFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_files: usize,
+    max_size: Option<u64>,
+}
+
+impl RotatingLogFile {
+    pub fn create(configuration: &LogFileConfiguration) -> Result<Self> {
+        std::fs::create_dir_all(&configuration.directory).with_context(|| {
+            anyhow::anyhow!(
+                "Failed to create log directory {}",
+                configuration.directory.display()
+            )
+        })?;
+        let path = configuration.path()?;
+        let file = Self::open(&path)?;
+        let written = file.metadata().map_or(0, |m| m.len());
+        Ok(Self {
+            path,
+            file,
+            written,
+            // the current file is always kept
+            max_files: configuration.max_files.max(1),
+            max_size: configuration.max_size,
+        })
+    }
+
+    fn open(path: &Path) -> Result<File> {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| anyhow::anyhow!("Failed to open log file: {}", path.display()))
+    }
+
+    fn rotated_path(&self, rotation: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{rotation}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        // the rotations and the current file share the maximum number of files
+        for rotation in (1..self.max_files).rev() {
+            let from = if rotation == 1 {
+                self.path.clone()
+            } else {
+                self.rotated_path(rotation - 1)
+            };
+            match std::fs::rename(&from, self.rotated_path(rotation)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        self.file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingLogFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // cannot log anything here because the logger is currently writing
+        if self.max_size.is_some_and(|max| self.written >= max) {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
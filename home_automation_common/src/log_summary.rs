@@ -0,0 +1,162 @@
+//! Short forms of the messages for the logs, because the full `Debug` output of a large
+//! [`SystemState`] is unreadable.
+//!
+//! [`Summarize`] gives counts and the first names of the large messages, [`Abbreviated`]
+//! truncates the `Debug` output of any other value. Set [`ENV_LOG_FULL_MESSAGES`] to log the
+//! messages in full.
+
+use std::{collections::HashMap, fmt, sync::OnceLock};
+
+use crate::{
+    protobuf::{
+        entity_discovery_command::EntityType, AdminState, DryRunReport, SystemState, TombstoneList,
+    },
+    EntityState,
+};
+
+/// Logs the full `Debug` output of the messages if set to anything but `0`.
+pub const ENV_LOG_FULL_MESSAGES: &str = "HOME_AUTOMATION_LOG_FULL_MESSAGES";
+/// Longer `Debug` outputs are truncated.
+pub const MAX_DEBUG_LEN: usize = 300;
+/// Number of names listed in a summary.
+const MAX_NAMES: usize = 5;
+
+/// Whether the messages are logged in full, read from [`ENV_LOG_FULL_MESSAGES`] once.
+pub fn full_messages() -> bool {
+    static FULL: OnceLock<bool> = OnceLock::new();
+    *FULL.get_or_init(|| std::env::var(ENV_LOG_FULL_MESSAGES).is_ok_and(|value| value != "0"))
+}
+
+/// Displays the `Debug` output of the value, truncated to [`MAX_DEBUG_LEN`] characters.
+pub struct Abbreviated<'a, T: ?Sized>(pub &'a T);
+
+impl<T: fmt::Debug + ?Sized> fmt::Display for Abbreviated<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let debug = format!("{:?}", self.0);
+        match debug.char_indices().nth(MAX_DEBUG_LEN) {
+            Some((end, _)) if !full_messages() => {
+                write!(
+                    f,
+                    "{}… ({} bytes omitted)",
+                    &debug[..end],
+                    debug.len() - end
+                )
+            }
+            _ => f.write_str(&debug),
+        }
+    }
+}
+
+/// Values with a short form for the logs.
+pub trait Summarize: fmt::Debug {
+    fn summarize(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+
+    fn summary(&self) -> Summary<'_, Self> {
+        Summary(self)
+    }
+}
+
+/// Displays the short form of the value, or its `Debug` output if full messages are logged.
+pub struct Summary<'a, T: ?Sized>(&'a T);
+
+impl<T: Summarize + ?Sized> fmt::Display for Summary<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if full_messages() {
+            write!(f, "{:?}", self.0)
+        } else {
+            self.0.summarize(f)
+        }
+    }
+}
+
+/// Writes the count and the first names in alphabetical order, e.g. `3 sensors (a, b, c)`.
+fn names<'a>(
+    f: &mut fmt::Formatter<'_>,
+    what: &str,
+    names: impl IntoIterator<Item = &'a String>,
+) -> fmt::Result {
+    let mut names: Vec<_> = names.into_iter().collect();
+    names.sort_unstable();
+    write!(f, "{} {what}", names.len())?;
+    if names.is_empty() {
+        return Ok(());
+    }
+    let listed: Vec<_> = names
+        .iter()
+        .take(MAX_NAMES)
+        .map(|name| name.as_str())
+        .collect();
+    write!(f, " ({}", listed.join(", "))?;
+    if names.len() > MAX_NAMES {
+        write!(f, ", … {} more", names.len() - MAX_NAMES)?;
+    }
+    f.write_str(")")
+}
+
+impl Summarize for SystemState {
+    fn summarize(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SystemState generation {}: ", self.generation)?;
+        names(f, "sensors", self.sensors.keys())?;
+        f.write_str(", ")?;
+        names(f, "actuators", self.actuators.keys())?;
+        f.write_str(", ")?;
+        names(f, "new", self.new_sensors.iter().chain(&self.new_actuators))
+    }
+}
+
+impl Summarize for AdminState {
+    fn summarize(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AdminState: ")?;
+        names(f, "tasks", self.tasks.iter().map(|task| &task.name))?;
+        f.write_str(", ")?;
+        names(
+            f,
+            "entities",
+            self.entities.iter().map(|entity| &entity.name),
+        )?;
+        write!(
+            f,
+            ", {} recent errors, {} rejected requests, {} payload mismatches",
+            self.recent_errors.len(),
+            self.rejected_requests,
+            self.payload_mismatches
+        )
+    }
+}
+
+impl Summarize for TombstoneList {
+    fn summarize(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TombstoneList: ")?;
+        names(f, "tombstones", self.tombstones.iter().map(|t| &t.name))?;
+        let history: usize = self.tombstones.iter().map(|t| t.history.len()).sum();
+        write!(f, ", {history} archived states")
+    }
+}
+
+impl Summarize for DryRunReport {
+    fn summarize(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entities = self
+            .commands
+            .iter()
+            .filter_map(|planned| planned.command.as_ref())
+            .map(|command| &command.entity_name);
+        f.write_str("DryRunReport: ")?;
+        names(f, "commands", entities)?;
+        if !self.error.is_empty() {
+            write!(f, ", error: {}", self.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The state of all entities as known by the client.
+impl Summarize for HashMap<String, EntityState> {
+    fn summarize(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (sensors, actuators): (Vec<_>, Vec<_>) = self
+            .iter()
+            .partition(|(_, state)| state.entity_type() == EntityType::Sensor);
+        names(f, "sensors", sensors.into_iter().map(|(name, _)| name))?;
+        f.write_str(", ")?;
+        names(f, "actuators", actuators.into_iter().map(|(name, _)| name))
+    }
+}
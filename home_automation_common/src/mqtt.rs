@@ -0,0 +1,423 @@
+//! Minimal MQTT 3.1.1 client to connect the system to brokers like Mosquitto, e.g. for Home
+//! Assistant.
+//!
+//! Only QoS 0 is supported: publications are sent at most once and subscriptions request QoS 0,
+//! which suffices for states that are published again with every change anyway.
+
+use std::{
+    io::{ErrorKind, Read as _, Write as _},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+const PROTOCOL_NAME: &str = "MQTT";
+const PROTOCOL_LEVEL: u8 = 4;
+const CLEAN_SESSION: u8 = 0x02;
+const PASSWORD_FLAG: u8 = 0x40;
+const USERNAME_FLAG: u8 = 0x80;
+/// Return code of a [`Packet::SubAck`] for a rejected topic filter.
+pub const SUBSCRIPTION_FAILURE: u8 = 0x80;
+/// Longest remaining length of a packet, limited by its variable length encoding.
+pub const MAX_REMAINING_LEN: usize = 268_435_455;
+
+/// Time the broker has to acknowledge the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum MqttError {
+    #[error("MQTT field of {0} bytes exceeds the maximum length")]
+    TooLong(usize),
+    #[error("malformed MQTT packet: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported MQTT packet type {0}")]
+    UnsupportedType(u8),
+}
+
+/// The packets of the client and the broker without the QoS 1 and 2 handshakes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Connect(Connect),
+    ConnAck {
+        session_present: bool,
+        return_code: u8,
+    },
+    Publish(Publish),
+    Subscribe {
+        packet_id: u16,
+        filters: Vec<String>,
+    },
+    SubAck {
+        packet_id: u16,
+        return_codes: Vec<u8>,
+    },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+/// Connection request of the client, always with a clean session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Connect {
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Maximum interval between two packets of the client, `0` disables the keep-alive.
+    pub keep_alive_secs: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// Whether the broker keeps the publication for future subscribers of the topic.
+    pub retain: bool,
+}
+
+fn put_str(body: &mut Vec<u8>, s: &str) -> Result<(), MqttError> {
+    let len = u16::try_from(s.len()).map_err(|_| MqttError::TooLong(s.len()))?;
+    body.extend_from_slice(&len.to_be_bytes());
+    body.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+impl Packet {
+    pub fn encode(&self) -> Result<Vec<u8>, MqttError> {
+        let mut body = Vec::new();
+        let header = match self {
+            Self::Connect(connect) => {
+                put_str(&mut body, PROTOCOL_NAME)?;
+                body.push(PROTOCOL_LEVEL);
+                let mut flags = CLEAN_SESSION;
+                if connect.username.is_some() {
+                    flags |= USERNAME_FLAG;
+                }
+                if connect.password.is_some() {
+                    flags |= PASSWORD_FLAG;
+                }
+                body.push(flags);
+                body.extend_from_slice(&connect.keep_alive_secs.to_be_bytes());
+                put_str(&mut body, &connect.client_id)?;
+                for credential in [&connect.username, &connect.password].into_iter().flatten() {
+                    put_str(&mut body, credential)?;
+                }
+                CONNECT << 4
+            }
+            Self::ConnAck {
+                session_present,
+                return_code,
+            } => {
+                body.extend_from_slice(&[u8::from(*session_present), *return_code]);
+                CONNACK << 4
+            }
+            Self::Publish(publish) => {
+                put_str(&mut body, &publish.topic)?;
+                body.extend_from_slice(&publish.payload);
+                (PUBLISH << 4) | u8::from(publish.retain)
+            }
+            Self::Subscribe { packet_id, filters } => {
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                for filter in filters {
+                    put_str(&mut body, filter)?;
+                    // requested QoS
+                    body.push(0);
+                }
+                (SUBSCRIBE << 4) | 0b0010
+            }
+            Self::SubAck {
+                packet_id,
+                return_codes,
+            } => {
+                body.extend_from_slice(&packet_id.to_be_bytes());
+                body.extend_from_slice(return_codes);
+                SUBACK << 4
+            }
+            Self::PingReq => PINGREQ << 4,
+            Self::PingResp => PINGRESP << 4,
+            Self::Disconnect => DISCONNECT << 4,
+        };
+        if body.len() > MAX_REMAINING_LEN {
+            return Err(MqttError::TooLong(body.len()));
+        }
+        let mut packet = Vec::with_capacity(body.len() + 5);
+        packet.push(header);
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            if len == 0 {
+                packet.push(byte);
+                break;
+            }
+            packet.push(byte | 0x80);
+        }
+        packet.extend_from_slice(&body);
+        Ok(packet)
+    }
+}
+
+/// Reads the fields of a packet body.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MqttError> {
+        if self.0.len() < len {
+            return Err(MqttError::Malformed("packet ends within a field"));
+        }
+        let (field, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(field)
+    }
+
+    fn u8(&mut self) -> Result<u8, MqttError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MqttError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn str(&mut self) -> Result<String, MqttError> {
+        let len = self.u16()?;
+        let bytes = self.take(usize::from(len))?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| MqttError::Malformed("string is not UTF-8"))
+    }
+}
+
+fn decode(header: u8, body: &[u8]) -> Result<Packet, MqttError> {
+    let mut fields = Fields(body);
+    let packet = match header >> 4 {
+        CONNECT => {
+            if fields.str()? != PROTOCOL_NAME {
+                return Err(MqttError::Malformed("unknown protocol name"));
+            }
+            let _level = fields.u8()?;
+            let flags = fields.u8()?;
+            let keep_alive_secs = fields.u16()?;
+            let client_id = fields.str()?;
+            let username = (flags & USERNAME_FLAG != 0)
+                .then(|| fields.str())
+                .transpose()?;
+            let password = (flags & PASSWORD_FLAG != 0)
+                .then(|| fields.str())
+                .transpose()?;
+            Packet::Connect(Connect {
+                client_id,
+                username,
+                password,
+                keep_alive_secs,
+            })
+        }
+        CONNACK => Packet::ConnAck {
+            session_present: fields.u8()? & 1 != 0,
+            return_code: fields.u8()?,
+        },
+        PUBLISH => {
+            let topic = fields.str()?;
+            // publications with QoS 1 and 2 carry a packet identifier
+            if (header >> 1) & 0b11 != 0 {
+                fields.u16()?;
+            }
+            Packet::Publish(Publish {
+                topic,
+                payload: fields.0.to_vec(),
+                retain: header & 1 != 0,
+            })
+        }
+        SUBSCRIBE => {
+            let packet_id = fields.u16()?;
+            let mut filters = Vec::new();
+            while !fields.0.is_empty() {
+                filters.push(fields.str()?);
+                let _qos = fields.u8()?;
+            }
+            Packet::Subscribe { packet_id, filters }
+        }
+        SUBACK => Packet::SubAck {
+            packet_id: fields.u16()?,
+            return_codes: fields.0.to_vec(),
+        },
+        PINGREQ => Packet::PingReq,
+        PINGRESP => Packet::PingResp,
+        DISCONNECT => Packet::Disconnect,
+        other => return Err(MqttError::UnsupportedType(other)),
+    };
+    Ok(packet)
+}
+
+/// Collects the received bytes and splits them into packets.
+#[derive(Debug, Default)]
+pub struct PacketDecoder {
+    buffer: Vec<u8>,
+}
+
+impl PacketDecoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete packet, or `None` if more bytes are needed.
+    ///
+    /// A packet that cannot be decoded is skipped, so it can be called again after an error.
+    pub fn next_packet(&mut self) -> Option<Result<Packet, MqttError>> {
+        let &header = self.buffer.first()?;
+        let mut len = 0;
+        let mut header_len = 1;
+        loop {
+            if header_len > 4 {
+                // the stream cannot be resynchronized
+                self.buffer.clear();
+                return Some(Err(MqttError::Malformed(
+                    "remaining length exceeds 4 bytes",
+                )));
+            }
+            let &byte = self.buffer.get(header_len)?;
+            len |= usize::from(byte & 0x7F) << (7 * (header_len - 1));
+            header_len += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let body = self.buffer.get(header_len..header_len + len)?.to_vec();
+        self.buffer.drain(..header_len + len);
+        Some(decode(header, &body))
+    }
+}
+
+fn refusal_reason(return_code: u8) -> &'static str {
+    match return_code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown return code",
+    }
+}
+
+/// Connection to a broker that publishes and subscribes with QoS 0.
+#[derive(Debug)]
+pub struct MqttClient {
+    stream: TcpStream,
+    decoder: PacketDecoder,
+    keep_alive: Duration,
+    last_sent: Instant,
+    next_packet_id: u16,
+}
+
+impl MqttClient {
+    /// Connects to the broker at the address, e.g. `127.0.0.1:1883`, and waits until it accepted
+    /// the connection.
+    pub fn connect(address: &str, connect: Connect) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect(address)
+            .with_context(|| format!("Failed to connect to MQTT broker {address}"))?;
+        stream.set_nodelay(true)?;
+        let mut client = Self {
+            stream,
+            decoder: PacketDecoder::default(),
+            keep_alive: Duration::from_secs(connect.keep_alive_secs.into()),
+            last_sent: Instant::now(),
+            next_packet_id: 1,
+        };
+        client.send(&Packet::Connect(connect))?;
+        match client.read_packet(CONNECT_TIMEOUT)? {
+            Some(Packet::ConnAck { return_code: 0, .. }) => Ok(client),
+            Some(Packet::ConnAck { return_code, .. }) => anyhow::bail!(
+                "MQTT broker {address} refused the connection: {}",
+                refusal_reason(return_code)
+            ),
+            Some(packet) => {
+                anyhow::bail!("Expected CONNACK from MQTT broker {address}: {packet:?}")
+            }
+            None => anyhow::bail!(
+                "MQTT broker {address} did not accept the connection within {CONNECT_TIMEOUT:?}"
+            ),
+        }
+    }
+
+    pub fn publish(&mut self, publish: Publish) -> anyhow::Result<()> {
+        self.send(&Packet::Publish(publish))
+    }
+
+    /// Subscribes to the topic filter, e.g. `home/+/state`.
+    ///
+    /// A rejection of the broker is reported by the next [`receive`][Self::receive].
+    pub fn subscribe(&mut self, filter: &str) -> anyhow::Result<()> {
+        let packet_id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        self.send(&Packet::Subscribe {
+            packet_id,
+            filters: vec![filter.to_owned()],
+        })
+    }
+
+    /// Waits up to `timeout` for the next publication on a subscribed topic and keeps the
+    /// connection alive meanwhile.
+    pub fn receive(&mut self, timeout: Duration) -> anyhow::Result<Option<Publish>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !self.keep_alive.is_zero() && self.last_sent.elapsed() >= self.keep_alive / 2 {
+                self.send(&Packet::PingReq)?;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.read_packet(remaining)? {
+                Some(Packet::Publish(publish)) => return Ok(Some(publish)),
+                Some(Packet::SubAck { return_codes, .. }) => anyhow::ensure!(
+                    !return_codes.contains(&SUBSCRIPTION_FAILURE),
+                    "MQTT broker rejected the subscription"
+                ),
+                Some(Packet::PingResp) => {}
+                Some(packet) => tracing::debug!(?packet, "Ignoring unexpected MQTT packet"),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Closes the connection gracefully.
+    pub fn disconnect(mut self) -> anyhow::Result<()> {
+        self.send(&Packet::Disconnect)
+    }
+
+    fn send(&mut self, packet: &Packet) -> anyhow::Result<()> {
+        self.stream
+            .write_all(&packet.encode()?)
+            .context("Failed to send MQTT packet")?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Returns the next packet of the broker, `None` if none arrived within the timeout.
+    fn read_packet(&mut self, timeout: Duration) -> anyhow::Result<Option<Packet>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(packet) = self.decoder.next_packet() {
+                return Ok(Some(packet?));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+            let mut buffer = [0; 1024];
+            match self.stream.read(&mut buffer) {
+                Ok(0) => anyhow::bail!("MQTT broker closed the connection"),
+                Ok(read) => self.decoder.push(&buffer[..read]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e).context("Failed to receive from MQTT broker"),
+            }
+        }
+    }
+}
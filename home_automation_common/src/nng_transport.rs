@@ -0,0 +1,174 @@
+//! Channels on top of nanomsg-next-gen sockets to compare nng with ZMQ.
+//!
+//! nng messages have a single part, so publications start with their topic and a
+//! [`TOPIC_SEPARATOR`] because the subscriptions filter by the prefix of the message.
+
+use std::{fmt, time::Duration};
+
+use anyhow::Context as _;
+use nng::{
+    options::{protocol::pubsub::Subscribe, LocalAddr, Options, RecvTimeout, RemAddr},
+    Listener, Message, Protocol, Socket, SocketAddr,
+};
+use prost::Message as _;
+
+use crate::{
+    encryption,
+    protobuf::PayloadEnvelope,
+    transport::{Channel, Pattern, Transport, PEER_ADDRESS_HEADER, TOPIC_HEADER},
+};
+
+/// Ends the topic of a publication.
+const TOPIC_SEPARATOR: u8 = 0;
+
+/// Endpoints are the same as for ZMQ, e.g. `tcp://*:5556`.
+///
+/// Like ZMQ, connecting succeeds before the endpoint is bound and the connection is established
+/// in the background. Publications sent before are lost.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NngTransport;
+
+/// nng binds an ephemeral port for port 0 instead of `*`.
+fn nng_url(endpoint: &str) -> String {
+    match endpoint.strip_suffix(":*") {
+        Some(address) => format!("{address}:0"),
+        None => endpoint.to_owned(),
+    }
+}
+
+fn open(pattern: Pattern) -> anyhow::Result<Socket> {
+    let protocol = match pattern {
+        Pattern::Publish => Protocol::Pub0,
+        Pattern::Subscribe => Protocol::Sub0,
+        Pattern::Request => Protocol::Req0,
+        Pattern::Reply => Protocol::Rep0,
+    };
+    let socket = Socket::new(protocol).context("Failed to create nng socket")?;
+    if pattern == Pattern::Subscribe {
+        socket
+            .set_opt::<Subscribe>(crate::topic_prefix().as_bytes().to_vec())
+            .context("Failed to subscribe")?;
+    }
+    Ok(socket)
+}
+
+fn port(address: SocketAddr) -> Option<u16> {
+    match address {
+        SocketAddr::Inet(address) => Some(address.port()),
+        SocketAddr::Inet6(address) => Some(address.port()),
+        _ => None,
+    }
+}
+
+/// IP address of the peer that sent the message.
+fn peer_ip(message: &Message) -> Option<String> {
+    match message.pipe()?.get_opt::<RemAddr>().ok()? {
+        SocketAddr::Inet(address) => Some(address.ip().to_string()),
+        SocketAddr::Inet6(address) => Some(address.ip().to_string()),
+        _ => None,
+    }
+}
+
+impl Transport for NngTransport {
+    fn bind(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>> {
+        let socket = open(pattern)?;
+        let listener = Listener::new(&socket, &nng_url(endpoint), false)
+            .with_context(|| format!("Failed to bind to {endpoint}"))?;
+        let port = port(listener.get_opt::<LocalAddr>()?);
+        Ok(Box::new(NngChannel {
+            pattern,
+            socket,
+            port,
+        }))
+    }
+
+    fn connect(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>> {
+        let socket = open(pattern)?;
+        socket
+            .dial_async(&nng_url(endpoint))
+            .with_context(|| format!("Failed to connect to {endpoint}"))?;
+        Ok(Box::new(NngChannel {
+            pattern,
+            socket,
+            port: None,
+        }))
+    }
+}
+
+struct NngChannel {
+    pattern: Pattern,
+    socket: Socket,
+    /// Port of bound channels.
+    port: Option<u16>,
+}
+
+impl fmt::Debug for NngChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NngChannel")
+            .field("pattern", &self.pattern)
+            .field("port", &self.port)
+            .finish()
+    }
+}
+
+impl Channel for NngChannel {
+    fn send(&self, mut envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        let mut data = Vec::new();
+        match self.pattern {
+            Pattern::Publish => {
+                let topic = envelope
+                    .headers
+                    .remove(TOPIC_HEADER)
+                    .context("Missing topic of publication")?;
+                data.extend_from_slice(topic.as_bytes());
+                data.push(TOPIC_SEPARATOR);
+            }
+            Pattern::Subscribe => anyhow::bail!("Cannot send through a subscriber"),
+            Pattern::Request | Pattern::Reply => {}
+        }
+        encryption::seal(&envelope)?.encode(&mut data)?;
+        self.socket
+            .send(Message::from(data.as_slice()))
+            .map_err(|(_, e)| e)
+            .with_context(|| format!("Failed to send through {self:?}"))
+    }
+
+    fn receive(&self, timeout: Option<Duration>) -> anyhow::Result<Option<PayloadEnvelope>> {
+        anyhow::ensure!(
+            self.pattern != Pattern::Publish,
+            "Cannot receive through a publisher"
+        );
+        self.socket.set_opt::<RecvTimeout>(timeout)?;
+        let message = match self.socket.recv() {
+            Ok(message) => message,
+            Err(nng::Error::TimedOut) => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to receive through {self:?}")),
+        };
+        let (topic, payload) = if self.pattern == Pattern::Subscribe {
+            let separator = message
+                .iter()
+                .position(|&byte| byte == TOPIC_SEPARATOR)
+                .context("Missing topic of publication")?;
+            let topic = String::from_utf8(message[..separator].to_vec())?;
+            (Some(topic), &message[separator + 1..])
+        } else {
+            (None, &message[..])
+        };
+        let mut envelope = PayloadEnvelope::from_bytes(payload)?;
+        encryption::open(&mut envelope)?;
+        if let Some(topic) = topic {
+            envelope.headers.insert(TOPIC_HEADER.to_owned(), topic);
+        }
+        // a header sent by the peer itself must not pass for its address
+        envelope.headers.remove(PEER_ADDRESS_HEADER);
+        if let Some(ip) = peer_ip(&message) {
+            envelope.headers.insert(PEER_ADDRESS_HEADER.to_owned(), ip);
+        }
+        Ok(Some(envelope))
+    }
+
+    fn local_port(&self) -> anyhow::Result<u16> {
+        self.port
+            .with_context(|| format!("{self:?} is not bound to a TCP port"))
+    }
+}
@@ -0,0 +1,71 @@
+//! Tokens the entities send with their discovery commands, so rogue processes cannot register
+//! fake entities or take over the name of a registered one.
+//!
+//! The controller either accepts a static list of tokens from [`ENV_REGISTRATION_TOKENS`] or
+//! the HMAC of the entity name with the key in [`ENV_REGISTRATION_KEY`]. An entity sends the HMAC
+//! of its name if it knows the key, otherwise the token in [`ENV_REGISTRATION_TOKEN`].
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::encryption::{parse_hex_key, KEY_LEN};
+
+/// Key of the HMAC of the entity names as 64 hex digits, shared by the controller and the
+/// entities.
+pub const ENV_REGISTRATION_KEY: &str = "HOME_AUTOMATION_REGISTRATION_KEY";
+/// Static token an entity sends if no [`ENV_REGISTRATION_KEY`] is configured.
+pub const ENV_REGISTRATION_TOKEN: &str = "HOME_AUTOMATION_REGISTRATION_TOKEN";
+/// Comma separated tokens the controller accepts if no [`ENV_REGISTRATION_KEY`] is configured.
+pub const ENV_REGISTRATION_TOKENS: &str = "HOME_AUTOMATION_REGISTRATION_TOKENS";
+
+type HmacSha256 = Hmac<Sha256>;
+pub type Key = [u8; KEY_LEN];
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RegistrationAuthError {
+    #[error("invalid {ENV_REGISTRATION_KEY}: {0}")]
+    InvalidKey(String),
+}
+
+/// Reads the key from [`ENV_REGISTRATION_KEY`], `None` if it is not set.
+pub fn key_from_env() -> Result<Option<Key>, RegistrationAuthError> {
+    let Ok(key) = std::env::var(ENV_REGISTRATION_KEY) else {
+        return Ok(None);
+    };
+    parse_hex_key(&key)
+        .map(Some)
+        .map_err(RegistrationAuthError::InvalidKey)
+}
+
+fn hmac(key: &Key, entity_name: &str) -> HmacSha256 {
+    let mut hmac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    hmac.update(entity_name.as_bytes());
+    hmac
+}
+
+/// Token of the entity for the key, the HMAC of its name as hex digits.
+pub fn entity_token(key: &Key, entity_name: &str) -> String {
+    hmac(key, entity_name)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Checks in constant time whether the token is the HMAC of the entity name.
+pub fn verify_entity_token(key: &Key, entity_name: &str, token: &str) -> bool {
+    let Ok(mac) = parse_hex_key(token) else {
+        return false;
+    };
+    hmac(key, entity_name).verify_slice(&mac).is_ok()
+}
+
+/// Token the entity authenticates with according to the environment, empty if none is
+/// configured.
+pub fn token_from_env(entity_name: &str) -> Result<String, RegistrationAuthError> {
+    if let Some(key) = key_from_env()? {
+        return Ok(entity_token(&key, entity_name));
+    }
+    Ok(std::env::var(ENV_REGISTRATION_TOKEN).unwrap_or_default())
+}
@@ -0,0 +1,99 @@
+//! Fixed-rate scheduling of the publications of an entity.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+/// Behavior of the publish loop if it fell behind its schedule, e.g. because sending blocked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissedTickPolicy {
+    /// Publish once for all missed ticks and continue with the next tick in the future.
+    #[default]
+    Skip,
+    /// Publish every missed tick back to back until the schedule caught up.
+    Burst,
+}
+
+/// Fixed-rate schedule of the publications of an entity, with its own period for every topic.
+///
+/// The deadlines are derived from the previous tick instead of the time the publication finished,
+/// so the time spent publishing does not make the schedule drift.
+#[derive(Debug, Clone)]
+pub struct PublishSchedule {
+    policy: MissedTickPolicy,
+    topics: BTreeMap<String, TopicSchedule>,
+}
+
+#[derive(Debug, Clone)]
+struct TopicSchedule {
+    period: Duration,
+    previous: Option<Instant>,
+}
+
+impl TopicSchedule {
+    fn deadline(&self, now: Instant) -> Instant {
+        self.previous.map_or(now, |previous| previous + self.period)
+    }
+}
+
+impl PublishSchedule {
+    pub fn new(policy: MissedTickPolicy) -> Self {
+        Self {
+            policy,
+            topics: BTreeMap::new(),
+        }
+    }
+
+    /// Schedules the topic, or changes its period if it is already scheduled. The next deadline
+    /// of the topic is recomputed from its previous tick.
+    pub fn set_period(&mut self, topic: &str, period: Duration) {
+        match self.topics.get_mut(topic) {
+            Some(schedule) => schedule.period = period,
+            None => {
+                self.topics.insert(
+                    topic.to_owned(),
+                    TopicSchedule {
+                        period,
+                        previous: None,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Stops publishing on the topic.
+    pub fn remove(&mut self, topic: &str) {
+        self.topics.remove(topic);
+    }
+
+    /// Returns the topic that is due next with its deadline, the first tick of a topic is due
+    /// `now`. `None` if no topic is scheduled.
+    pub fn next(&self, now: Instant) -> Option<(&str, Instant)> {
+        self.topics
+            .iter()
+            .map(|(topic, schedule)| (topic.as_str(), schedule.deadline(now)))
+            .min_by_key(|(_, deadline)| *deadline)
+    }
+
+    /// Marks the current tick of the topic as done, must be called once its deadline was
+    /// reached. Unknown topics are ignored.
+    pub fn advance(&mut self, topic: &str, now: Instant) {
+        let Some(schedule) = self.topics.get_mut(topic) else {
+            return;
+        };
+        let tick = schedule.deadline(now);
+        let tick = match self.policy {
+            MissedTickPolicy::Burst => tick,
+            MissedTickPolicy::Skip => {
+                let behind = now.saturating_duration_since(tick);
+                let missed = behind.as_nanos() / schedule.period.as_nanos().max(1);
+                if missed > 0 {
+                    tracing::debug!(topic, missed, "Skipping missed publish ticks");
+                }
+                tick + schedule.period * u32::try_from(missed).unwrap_or(u32::MAX)
+            }
+        };
+        schedule.previous = Some(tick);
+    }
+}
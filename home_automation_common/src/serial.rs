@@ -0,0 +1,173 @@
+//! Messages over a serial port, framed by [`framing`][home_automation_protocol::framing].
+//!
+//! The link carries the same [`PayloadEnvelope`]s as the sockets but multiplexes all channels
+//! of an entity, so the receivers pick the messages by their type.
+
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use home_automation_protocol::framing::{encode_frame, FrameDecoder};
+use prost::Message as _;
+use serialport::SerialPort;
+
+use crate::{envelope::Headers, protobuf::PayloadEnvelope};
+
+/// Serial port an entity talks to the controller through instead of the sockets.
+pub const ENV_SERIAL_PORT: &str = "HOME_AUTOMATION_SERIAL_PORT";
+/// Optional baud rate of the serial ports, 115200 by default.
+pub const ENV_SERIAL_BAUD_RATE: &str = "HOME_AUTOMATION_SERIAL_BAUD_RATE";
+const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// How long a single read blocks, bounds the delay until another receiver gets its turn.
+const READ_TIMEOUT: Duration = Duration::from_millis(50);
+/// Received messages nobody asked for yet, older ones are dropped.
+const MAX_PENDING: usize = 64;
+
+/// Reads the baud rate from [`ENV_SERIAL_BAUD_RATE`].
+pub fn baud_rate() -> anyhow::Result<u32> {
+    let Ok(baud_rate) = std::env::var(ENV_SERIAL_BAUD_RATE) else {
+        return Ok(DEFAULT_BAUD_RATE);
+    };
+    baud_rate
+        .parse()
+        .with_context(|| anyhow::anyhow!("Invalid {ENV_SERIAL_BAUD_RATE} value {baud_rate}"))
+}
+
+struct Reader {
+    port: Box<dyn SerialPort>,
+    decoder: FrameDecoder,
+    pending: VecDeque<PayloadEnvelope>,
+}
+
+impl Reader {
+    fn take<M: prost::Name + Default>(&mut self) -> Option<anyhow::Result<M>> {
+        let index = self
+            .pending
+            .iter()
+            .position(PayloadEnvelope::contains::<M>)?;
+        let envelope = self.pending.remove(index)?;
+        Some(envelope.unpack().map_err(Into::into))
+    }
+
+    /// Reads the available bytes and queues the messages of the completed frames.
+    fn read(&mut self, path: &str) -> anyhow::Result<()> {
+        let mut buffer = [0; 256];
+        let read = match self.port.read(&mut buffer) {
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::TimedOut => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read from {path}")),
+        };
+        self.decoder.push(&buffer[..read]);
+        while let Some(frame) = self.decoder.next_frame() {
+            let envelope = frame
+                .map_err(anyhow::Error::from)
+                .and_then(|frame| PayloadEnvelope::from_bytes(&frame).map_err(Into::into));
+            match envelope {
+                Ok(envelope) => {
+                    if self.pending.len() == MAX_PENDING {
+                        let dropped = self.pending.pop_front();
+                        tracing::warn!(?dropped, "Dropping unhandled message from {path}");
+                    }
+                    self.pending.push_back(envelope);
+                }
+                Err(e) => tracing::warn!("Skipping corrupted frame from {path}: {e:#}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serial port that sends and receives protobuf messages, usable from several threads.
+pub struct SerialLink {
+    path: String,
+    writer: Mutex<Box<dyn SerialPort>>,
+    reader: Mutex<Reader>,
+}
+
+impl std::fmt::Debug for SerialLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SerialLink")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SerialLink {
+    pub fn open(path: &str, baud_rate: u32) -> anyhow::Result<Self> {
+        let port = serialport::new(path, baud_rate)
+            .timeout(READ_TIMEOUT)
+            .open()
+            .with_context(|| format!("Failed to open serial port {path}"))?;
+        let writer = port
+            .try_clone()
+            .with_context(|| format!("Failed to clone serial port {path}"))?;
+        Ok(Self {
+            path: path.to_owned(),
+            writer: Mutex::new(writer),
+            reader: Mutex::new(Reader {
+                port,
+                decoder: FrameDecoder::default(),
+                pending: VecDeque::new(),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn send<M>(&self, message: &M) -> anyhow::Result<()>
+    where
+        M: prost::Name + std::fmt::Debug,
+    {
+        let payload = PayloadEnvelope::pack(message, Headers::default())?.encode_to_vec();
+        let frame = encode_frame(&payload)?;
+        let mut writer = self.writer.lock().expect("non-poisoned Mutex");
+        writer
+            .write_all(&frame)
+            .and_then(|()| writer.flush())
+            .with_context(|| format!("Failed to write to {}", self.path))
+    }
+
+    /// Waits up to `timeout` for a message of the given type, reads at least once.
+    ///
+    /// Messages of other types are kept for their receivers.
+    pub fn receive<M>(&self, timeout: Duration) -> anyhow::Result<Option<M>>
+    where
+        M: prost::Name + Default,
+    {
+        self.receive_with(timeout, Reader::take::<M>)?.transpose()
+    }
+
+    /// Waits up to `timeout` for the oldest message of any type, reads at least once.
+    pub fn receive_envelope(&self, timeout: Duration) -> anyhow::Result<Option<PayloadEnvelope>> {
+        self.receive_with(timeout, |reader| reader.pending.pop_front())
+    }
+
+    fn receive_with<T>(
+        &self,
+        timeout: Duration,
+        mut take: impl FnMut(&mut Reader) -> Option<T>,
+    ) -> anyhow::Result<Option<T>> {
+        let deadline = Instant::now() + timeout;
+        let mut timed_out = false;
+        loop {
+            // released after every read so the other receivers are not blocked
+            let mut reader = self.reader.lock().expect("non-poisoned Mutex");
+            if let Some(message) = take(&mut reader) {
+                return Ok(Some(message));
+            }
+            if timed_out {
+                return Ok(None);
+            }
+            reader.read(&self.path)?;
+            timed_out = Instant::now() >= deadline;
+        }
+    }
+}
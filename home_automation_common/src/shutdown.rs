@@ -0,0 +1,98 @@
+use std::{
+    sync::{Arc, Condvar, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+/// Cooperative shutdown signal shared by the tasks of an application.
+///
+/// Clones refer to the same signal. A [child][ShutdownToken::child] is shut down together with
+/// its parent but can also be shut down on its own, e.g. to stop a single subsystem.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    requested: Mutex<bool>,
+    condvar: Condvar,
+    children: Mutex<Vec<Weak<Inner>>>,
+}
+
+impl Inner {
+    fn request(&self) {
+        *self.requested.lock().expect("non-poisoned Mutex") = true;
+        self.condvar.notify_all();
+        let children = std::mem::take(&mut *self.children.lock().expect("non-poisoned Mutex"));
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.request();
+        }
+    }
+}
+
+impl ShutdownToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a token that is shut down when this token is shut down, but not vice versa.
+    pub fn child(&self) -> Self {
+        let child = Self::new();
+        // holding the lock prevents a concurrent request from missing the new child
+        let mut children = self.inner.children.lock().expect("non-poisoned Mutex");
+        if self.is_requested() {
+            child.request();
+        } else {
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.inner));
+        }
+        child
+    }
+
+    /// Requests the shutdown of this token and all of its children and wakes up all waiters.
+    pub fn request(&self) {
+        self.inner.request();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.inner.requested.lock().expect("non-poisoned Mutex")
+    }
+
+    /// Blocks until the shutdown is requested.
+    pub fn wait(&self) {
+        let requested = self.inner.requested.lock().expect("non-poisoned Mutex");
+        let _requested = self
+            .inner
+            .condvar
+            .wait_while(requested, |requested| !*requested)
+            .expect("non-poisoned Mutex");
+    }
+
+    /// Blocks until the shutdown is requested or the timeout expires.
+    ///
+    /// Returns whether the shutdown was requested.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.sleep_until_or_shutdown(Instant::now() + timeout)
+    }
+
+    /// Blocks until the deadline is reached or the shutdown is requested.
+    ///
+    /// Returns whether the shutdown was requested. Loops should advance their deadline by a fixed
+    /// interval instead of sleeping for it so the time spent working does not accumulate.
+    pub fn sleep_until_or_shutdown(&self, deadline: Instant) -> bool {
+        let mut requested = self.inner.requested.lock().expect("non-poisoned Mutex");
+        while !*requested {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            // the condvar may wake up spuriously, so re-check the deadline
+            (requested, _) = self
+                .inner
+                .condvar
+                .wait_timeout(requested, remaining)
+                .expect("non-poisoned Mutex");
+        }
+        *requested
+    }
+}
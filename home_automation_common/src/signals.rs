@@ -0,0 +1,149 @@
+//! Dispatch of the signals of the process to the binaries.
+//!
+//! SIGINT and SIGTERM request an orderly shutdown. On unix, SIGHUP asks the binary to reload its
+//! configuration and SIGUSR1 to dump its state to the log. The socket statistics are always
+//! logged on SIGUSR1, everything else is up to the binary receiving the [`Signal`].
+
+use std::{
+    sync::{mpsc, Mutex},
+    time::Duration,
+};
+
+use crate::{zmq_sockets, ShutdownToken};
+
+/// Interval in which [`SignalReceiver::run`] checks whether shutdown was requested.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Signals that a binary handles itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Reload the configuration (SIGHUP).
+    Reload,
+    /// Log the current state (SIGUSR1).
+    DumpState,
+}
+
+/// Receives the signals forwarded by [`install_signal_handler`] or
+/// [`install_graceful_signal_handler`].
+///
+/// Programs without anything to reload or dump can drop it.
+#[derive(Debug)]
+pub struct SignalReceiver(Mutex<mpsc::Receiver<Signal>>);
+
+impl SignalReceiver {
+    /// Handles the received signals until shutdown is requested.
+    pub fn run(&self, shutdown: &ShutdownToken, mut handle: impl FnMut(Signal)) {
+        let receiver = self.0.lock().expect("non-poisoned Mutex");
+        while !shutdown.is_requested() {
+            match receiver.recv_timeout(SHUTDOWN_CHECK_INTERVAL) {
+                Ok(signal) => handle(signal),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
+
+/// Requests the shutdown of the token and terminates the context on SIGINT/SIGTERM, and forwards
+/// the other signals to the returned receiver.
+pub fn install_signal_handler(
+    context: zmq_sockets::Context,
+    shutdown: ShutdownToken,
+) -> anyhow::Result<SignalReceiver> {
+    install(move || request_shutdown(&context, &shutdown))
+}
+
+/// Like [`install_signal_handler`], but SIGINT/SIGTERM only request the shutdown of the token.
+///
+/// The sockets keep working until the program tears them down itself, e.g. to send a final
+/// request before it destroys the context.
+pub fn install_graceful_signal_handler(shutdown: ShutdownToken) -> anyhow::Result<SignalReceiver> {
+    install(move || {
+        tracing::info!("Shutdown signal received");
+        if shutdown.is_requested() {
+            tracing::warn!("Shutdown was already requested previously. Forcing shutdown now.");
+            std::process::abort();
+        }
+        shutdown.request();
+    })
+}
+
+fn install(on_shutdown: impl Fn() + Send + 'static) -> anyhow::Result<SignalReceiver> {
+    let (sender, receiver) = mpsc::channel();
+    let dispatch = move |signal: Option<Signal>| match signal {
+        None => on_shutdown(),
+        Some(signal) => {
+            tracing::info!(?signal, "Signal {signal:?} received");
+            if signal == Signal::DumpState {
+                zmq_sockets::log_statistics();
+            }
+            if sender.send(signal).is_err() && signal == Signal::Reload {
+                tracing::warn!(
+                    "Ignoring reload signal, this program has no configuration to reload"
+                );
+            }
+        }
+    };
+    platform::install(dispatch)?;
+    Ok(SignalReceiver(Mutex::new(receiver)))
+}
+
+fn request_shutdown(context: &zmq_sockets::Context, shutdown: &ShutdownToken) {
+    tracing::info!("Shutdown signal received");
+    if shutdown.is_requested() {
+        tracing::warn!("Shutdown was already requested previously. Forcing shutdown now.");
+        std::process::abort();
+    }
+    // Workaround: context.destroy() seems to block forever and prevent a second signal from
+    // getting to the dispatching thread.
+    std::thread::spawn({
+        let mut context = context.clone();
+        let shutdown = shutdown.clone();
+        move || {
+            shutdown.request();
+            context.destroy().expect("Failed to destroy context");
+        }
+    });
+}
+
+#[cfg(unix)]
+mod platform {
+    use anyhow::Context as _;
+    use signal_hook::{
+        consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1},
+        iterator::Signals,
+    };
+
+    use super::Signal;
+
+    /// Calls `dispatch` with `None` for termination signals on a dedicated thread.
+    pub fn install(dispatch: impl Fn(Option<Signal>) + Send + 'static) -> anyhow::Result<()> {
+        let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGUSR1])
+            .context("Failed to install signal handler")?;
+        std::thread::Builder::new()
+            .name("signals".to_owned())
+            .spawn(move || {
+                for signal in signals.forever() {
+                    dispatch(match signal {
+                        SIGHUP => Some(Signal::Reload),
+                        SIGUSR1 => Some(Signal::DumpState),
+                        _ => None,
+                    });
+                }
+            })
+            .context("Failed to spawn signal handler thread")?;
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    use anyhow::Context as _;
+
+    use super::Signal;
+
+    /// Only termination is supported without unix signals.
+    pub fn install(dispatch: impl Fn(Option<Signal>) + Send + 'static) -> anyhow::Result<()> {
+        ctrlc::set_handler(move || dispatch(None)).context("Failed to install signal handler")
+    }
+}
@@ -0,0 +1,226 @@
+//! Signatures of the commands sent to the entities, so commands injected on the network are
+//! rejected by the entities.
+//!
+//! Every client and the controller sign with their own key from [`ENV_COMMAND_KEY`], the
+//! entities verify with the keys of all signers from [`ENV_COMMAND_KEYS`]. The strictly
+//! increasing nonce of each signer protects against replays of recorded commands.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use prost::Message as _;
+use sha2::Sha256;
+
+use crate::{
+    encryption::{parse_hex_key, KEY_LEN},
+    protobuf::{CommandSignature, NamedEntityState},
+};
+
+/// Key the program signs its commands with, `<key id>:<64 hex digits>`.
+pub const ENV_COMMAND_KEY: &str = "HOME_AUTOMATION_COMMAND_KEY";
+/// Comma separated keys of all signers the entity accepts commands from, in the format of
+/// [`ENV_COMMAND_KEY`]. Commands are not verified if it is unset.
+pub const ENV_COMMAND_KEYS: &str = "HOME_AUTOMATION_COMMAND_KEYS";
+
+/// Older commands are rejected even if the signer is not known yet, e.g. after a restart.
+pub const MAX_COMMAND_AGE: Duration = Duration::from_secs(300);
+
+type HmacSha256 = Hmac<Sha256>;
+type Key = [u8; KEY_LEN];
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SignatureError {
+    #[error("invalid command key: {0}")]
+    InvalidKey(String),
+    #[error("unsigned command rejected")]
+    Unsigned,
+    #[error("command signed with unknown key {0}")]
+    UnknownKey(String),
+    #[error("invalid signature of key {0}")]
+    InvalidSignature(String),
+    #[error("replayed or outdated command of key {key_id} with nonce {nonce}")]
+    Replay { key_id: String, nonce: u64 },
+}
+
+/// Parses `<key id>:<64 hex digits>`.
+fn parse_key(entry: &str) -> Result<(String, Key), SignatureError> {
+    let (key_id, key) = entry
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| SignatureError::InvalidKey("expected <key id>:<key>".to_owned()))?;
+    if key_id.is_empty() {
+        return Err(SignatureError::InvalidKey("missing key id".to_owned()));
+    }
+    let key = parse_hex_key(key)
+        .map_err(|reason| SignatureError::InvalidKey(format!("key {key_id}: {reason}")))?;
+    Ok((key_id.to_owned(), key))
+}
+
+fn unix_time_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros()
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+/// Keyed hash of the command with the signature but without its mac.
+fn hmac(key: &Key, command: &NamedEntityState, key_id: &str, nonce: u64) -> HmacSha256 {
+    let unsigned = NamedEntityState {
+        signature: Some(CommandSignature {
+            key_id: key_id.to_owned(),
+            nonce,
+            mac: Vec::new(),
+        }),
+        ..command.clone()
+    };
+    let mut hmac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    hmac.update(&unsigned.encode_to_vec());
+    hmac
+}
+
+pub struct CommandSigner {
+    key_id: String,
+    key: Key,
+    last_nonce: AtomicU64,
+}
+
+impl fmt::Debug for CommandSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // never print the key
+        f.debug_struct("CommandSigner")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CommandSigner {
+    pub fn new(key_id: impl Into<String>, key: Key) -> Self {
+        Self {
+            key_id: key_id.into(),
+            key,
+            last_nonce: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads the key from [`ENV_COMMAND_KEY`], `None` if commands are not signed.
+    pub fn from_env() -> Result<Option<Self>, SignatureError> {
+        let Ok(entry) = std::env::var(ENV_COMMAND_KEY) else {
+            return Ok(None);
+        };
+        let (key_id, key) = parse_key(&entry)?;
+        Ok(Some(Self::new(key_id, key)))
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Replaces the signature of the command with a new one.
+    pub fn sign(&self, command: &mut NamedEntityState) {
+        let nonce = self.next_nonce();
+        let mac = hmac(&self.key, command, &self.key_id, nonce)
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        command.signature = Some(CommandSignature {
+            key_id: self.key_id.clone(),
+            nonce,
+            mac,
+        });
+    }
+
+    /// The current time, but strictly increasing even if the clock did not advance.
+    fn next_nonce(&self) -> u64 {
+        let now = unix_time_us();
+        let previous = self
+            .last_nonce
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .expect("nonce is always updated");
+        now.max(previous + 1)
+    }
+}
+
+#[derive(Default)]
+pub struct CommandVerifier {
+    keys: HashMap<String, Key>,
+    /// Nonce of the last accepted command per key id.
+    last_nonces: Mutex<HashMap<String, u64>>,
+}
+
+impl fmt::Debug for CommandVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandVerifier")
+            .field("key_ids", &self.key_ids())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CommandVerifier {
+    pub fn new(keys: impl IntoIterator<Item = (String, Key)>) -> Self {
+        Self {
+            keys: keys.into_iter().collect(),
+            last_nonces: Mutex::default(),
+        }
+    }
+
+    /// Reads the keys from [`ENV_COMMAND_KEYS`], accepts all commands if it is unset.
+    pub fn from_env() -> Result<Self, SignatureError> {
+        let keys = std::env::var(ENV_COMMAND_KEYS).unwrap_or_default();
+        let keys = keys
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(parse_key)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(keys))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    pub fn key_ids(&self) -> Vec<&str> {
+        let mut key_ids: Vec<_> = self.keys.keys().map(String::as_str).collect();
+        key_ids.sort_unstable();
+        key_ids
+    }
+
+    /// Checks the signature and that the nonce is newer than the last one of the signer.
+    pub fn verify(&self, command: &NamedEntityState) -> Result<(), SignatureError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let signature = command.signature.as_ref().ok_or(SignatureError::Unsigned)?;
+        let key_id = &signature.key_id;
+        let key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| SignatureError::UnknownKey(key_id.clone()))?;
+        hmac(key, command, key_id, signature.nonce)
+            .verify_slice(&signature.mac)
+            .map_err(|_| SignatureError::InvalidSignature(key_id.clone()))?;
+
+        let oldest = unix_time_us().saturating_sub(MAX_COMMAND_AGE.as_micros() as u64);
+        let mut last_nonces = self.last_nonces.lock().expect("non-poisoned Mutex");
+        let last_nonce = last_nonces.get(key_id).copied().unwrap_or_default();
+        if signature.nonce <= last_nonce.max(oldest) {
+            return Err(SignatureError::Replay {
+                key_id: key_id.clone(),
+                nonce: signature.nonce,
+            });
+        }
+        last_nonces.insert(key_id.clone(), signature.nonce);
+        Ok(())
+    }
+}
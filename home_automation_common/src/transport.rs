@@ -0,0 +1,837 @@
+//! Transports that carry the [`PayloadEnvelope`]s between the controller and the entities.
+//!
+//! A [`Transport`] creates the [`Channel`]s of the messaging patterns, so the same logic works
+//! over all of them. ZMQ is the default, TCP with the [`framing`] of the serial links avoids
+//! libzmq on the entities, and the in-memory transport connects the parts of a single process,
+//! e.g. in tests. With the `nng` feature, [`NngTransport`][crate::nng_transport::NngTransport]
+//! compares nanomsg-next-gen with ZMQ. The UDP multicast data plane in [`udp`][crate::udp]
+//! provides channels as well.
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt,
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use home_automation_protocol::framing::{self, FrameDecoder};
+use prost::Message as _;
+
+use crate::{
+    encryption,
+    envelope::Headers,
+    protobuf::PayloadEnvelope,
+    zmq_sockets::{self, markers::Linked, BindRetry},
+};
+
+/// Transport of the controller and the entities, `zmq` (default), `tcp`, `memory` or `nng`.
+pub const ENV_TRANSPORT: &str = "HOME_AUTOMATION_TRANSPORT";
+/// Header with the topic of a published envelope.
+pub const TOPIC_HEADER: &str = "topic";
+/// Header with the IP address of the peer, added to the envelopes received by bound channels.
+pub const PEER_ADDRESS_HEADER: &str = "peer-address";
+
+/// Interval in which the TCP listeners check whether their channel was dropped.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// First port handed out for in-memory endpoints bound to `*`.
+const FIRST_MEMORY_PORT: u16 = 49152;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Publish,
+    Subscribe,
+    Request,
+    Reply,
+}
+
+/// Sends and receives envelopes according to its [`Pattern`].
+pub trait Channel: Send + fmt::Debug {
+    /// Sends the envelope, publish channels take the topic from its [`TOPIC_HEADER`].
+    fn send(&self, envelope: PayloadEnvelope) -> anyhow::Result<()>;
+
+    /// Waits up to `timeout`, or forever without one, for the next envelope.
+    ///
+    /// Returns `None` if none arrived in time.
+    fn receive(&self, timeout: Option<Duration>) -> anyhow::Result<Option<PayloadEnvelope>>;
+
+    /// Port the channel is bound to, e.g. to announce a port chosen by the system.
+    fn local_port(&self) -> anyhow::Result<u16> {
+        anyhow::bail!("{self:?} is not bound to a port")
+    }
+}
+
+impl<C: Channel + ?Sized> Channel for Box<C> {
+    fn send(&self, envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        (**self).send(envelope)
+    }
+
+    fn receive(&self, timeout: Option<Duration>) -> anyhow::Result<Option<PayloadEnvelope>> {
+        (**self).receive(timeout)
+    }
+
+    fn local_port(&self) -> anyhow::Result<u16> {
+        (**self).local_port()
+    }
+}
+
+impl dyn Channel + '_ {
+    /// Packs the message together with the current span, on the topic for publish channels.
+    pub fn send_message<M>(&self, topic: Option<&str>, message: &M) -> anyhow::Result<()>
+    where
+        M: prost::Name,
+    {
+        let mut headers = zmq_sockets::trace_headers();
+        if let Some(topic) = topic {
+            headers.insert(TOPIC_HEADER.to_owned(), topic.to_owned());
+        }
+        self.send(PayloadEnvelope::pack(message, headers)?)
+    }
+
+    /// Unpacks the next message, see [`Channel::receive`].
+    ///
+    /// The headers of the envelope are returned with it, pass them to [`follow_remote_span`]
+    /// in the span that handles the message.
+    pub fn receive_message<M>(
+        &self,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Option<(M, Headers)>>
+    where
+        M: prost::Name + Default,
+    {
+        let Some(mut envelope) = self.receive(timeout)? else {
+            return Ok(None);
+        };
+        let headers = std::mem::take(&mut envelope.headers);
+        Ok(Some((envelope.unpack()?, headers)))
+    }
+
+    /// Sends the request and waits up to `timeout`, or forever without one, for the reply.
+    pub fn request<M, R>(&self, request: &M, timeout: Option<Duration>) -> anyhow::Result<R>
+    where
+        M: prost::Name,
+        R: prost::Name + Default,
+    {
+        self.send_message(None, request)?;
+        let (reply, headers) = self
+            .receive_message(timeout)?
+            .with_context(|| format!("No reply from {self:?} within {timeout:?}"))?;
+        follow_remote_span(&headers);
+        Ok(reply)
+    }
+}
+
+/// Makes the span of the sender, as propagated in the headers, the parent of the current span.
+pub fn follow_remote_span(headers: &Headers) {
+    zmq_sockets::link_remote_span(headers);
+}
+
+/// Creates channels by binding or connecting to endpoints like `tcp://127.0.0.1:5556`.
+pub trait Transport: Send + Sync + fmt::Debug {
+    fn bind(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>>;
+    fn connect(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>>;
+}
+
+/// The transports that can be selected with [`ENV_TRANSPORT`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    #[default]
+    Zmq,
+    Tcp,
+    Memory,
+    #[cfg(feature = "nng")]
+    Nng,
+}
+
+impl FromStr for TransportKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "zmq" => Ok(Self::Zmq),
+            "tcp" => Ok(Self::Tcp),
+            "memory" => Ok(Self::Memory),
+            #[cfg(feature = "nng")]
+            "nng" => Ok(Self::Nng),
+            #[cfg(not(feature = "nng"))]
+            "nng" => anyhow::bail!("The nng transport requires the nng feature"),
+            _ => anyhow::bail!("Unknown transport {s}, expected zmq, tcp, memory or nng"),
+        }
+    }
+}
+
+impl fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Zmq => "zmq",
+            Self::Tcp => "tcp",
+            Self::Memory => "memory",
+            #[cfg(feature = "nng")]
+            Self::Nng => "nng",
+        };
+        f.write_str(name)
+    }
+}
+
+impl TransportKind {
+    /// Reads the transport from [`ENV_TRANSPORT`], ZMQ if it is not set.
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var(ENV_TRANSPORT) {
+            Ok(kind) => kind
+                .parse()
+                .with_context(|| format!("Invalid {ENV_TRANSPORT} value {kind}")),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Creates the transport, the ZMQ sockets are created from the given context.
+    pub fn transport(self, context: &zmq_sockets::Context) -> Box<dyn Transport> {
+        match self {
+            Self::Zmq => Box::new(ZmqTransport::new(context.clone())),
+            Self::Tcp => Box::new(TcpTransport),
+            Self::Memory => Box::new(MemoryTransport),
+            #[cfg(feature = "nng")]
+            Self::Nng => Box::new(crate::nng_transport::NngTransport),
+        }
+    }
+}
+
+/// Channels on top of the ZMQ sockets, publications are received for the own
+/// [`topic_prefix`][crate::topic_prefix] only.
+#[derive(Debug, Clone)]
+pub struct ZmqTransport {
+    context: zmq_sockets::Context,
+    bind_retry: BindRetry,
+}
+
+impl ZmqTransport {
+    pub fn new(context: zmq_sockets::Context) -> Self {
+        Self {
+            context,
+            bind_retry: BindRetry::default(),
+        }
+    }
+
+    /// Retries binding endpoints that are still in use.
+    pub fn with_bind_retry(self, bind_retry: BindRetry) -> Self {
+        Self { bind_retry, ..self }
+    }
+}
+
+impl Transport for ZmqTransport {
+    fn bind(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>> {
+        let retry = &self.bind_retry;
+        let socket = match pattern {
+            Pattern::Publish => ZmqSocket::Publisher(
+                zmq_sockets::Publisher::new(&self.context)?
+                    .bind_with_retry(endpoint, retry)?
+                    .0,
+            ),
+            Pattern::Subscribe => ZmqSocket::subscriber(
+                zmq_sockets::Subscriber::new(&self.context)?
+                    .bind_with_retry(endpoint, retry)?
+                    .0,
+            )?,
+            Pattern::Request => ZmqSocket::Requester(
+                zmq_sockets::Requester::new(&self.context)?
+                    .bind_with_retry(endpoint, retry)?
+                    .0,
+            ),
+            Pattern::Reply => ZmqSocket::Replier(
+                zmq_sockets::Replier::new(&self.context)?
+                    .bind_with_retry(endpoint, retry)?
+                    .0,
+            ),
+        };
+        Ok(Box::new(ZmqChannel(socket)))
+    }
+
+    fn connect(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>> {
+        let socket = match pattern {
+            Pattern::Publish => {
+                ZmqSocket::Publisher(zmq_sockets::Publisher::new(&self.context)?.connect(endpoint)?)
+            }
+            Pattern::Subscribe => ZmqSocket::subscriber(
+                zmq_sockets::Subscriber::new(&self.context)?.connect(endpoint)?,
+            )?,
+            Pattern::Request => {
+                ZmqSocket::Requester(zmq_sockets::Requester::new(&self.context)?.connect(endpoint)?)
+            }
+            Pattern::Reply => {
+                ZmqSocket::Replier(zmq_sockets::Replier::new(&self.context)?.connect(endpoint)?)
+            }
+        };
+        Ok(Box::new(ZmqChannel(socket)))
+    }
+}
+
+#[derive(Debug)]
+enum ZmqSocket {
+    Publisher(zmq_sockets::Publisher<Linked>),
+    Subscriber(zmq_sockets::Subscriber<Linked>),
+    Requester(zmq_sockets::Requester<Linked>),
+    Replier(zmq_sockets::Replier<Linked>),
+}
+
+impl ZmqSocket {
+    fn subscriber(subscriber: zmq_sockets::Subscriber<Linked>) -> anyhow::Result<Self> {
+        subscriber.subscribe(crate::topic_prefix())?;
+        Ok(Self::Subscriber(subscriber))
+    }
+}
+
+#[derive(Debug)]
+struct ZmqChannel(ZmqSocket);
+
+impl Channel for ZmqChannel {
+    fn send(&self, mut envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        match &self.0 {
+            ZmqSocket::Publisher(publisher) => {
+                // the topic is sent in its own frame for the subscription filters
+                let topic = envelope
+                    .headers
+                    .remove(TOPIC_HEADER)
+                    .context("Missing topic of publication")?;
+                Ok(publisher.send_envelope(&topic, envelope)?)
+            }
+            ZmqSocket::Subscriber(_) => anyhow::bail!("Cannot send through a subscriber"),
+            ZmqSocket::Requester(requester) => Ok(requester.send_envelope(&envelope)?),
+            ZmqSocket::Replier(replier) => Ok(replier.send_envelope(&envelope)?),
+        }
+    }
+
+    fn receive(&self, timeout: Option<Duration>) -> anyhow::Result<Option<PayloadEnvelope>> {
+        let mut items = [match &self.0 {
+            ZmqSocket::Publisher(_) => anyhow::bail!("Cannot receive through a publisher"),
+            ZmqSocket::Subscriber(subscriber) => subscriber.poll_item(),
+            ZmqSocket::Requester(requester) => requester.poll_item(),
+            ZmqSocket::Replier(replier) => replier.poll_item(),
+        }];
+        if timeout.is_some() && zmq_sockets::poll(&mut items, timeout)? == 0 {
+            return Ok(None);
+        }
+        let envelope = match &self.0 {
+            ZmqSocket::Publisher(_) => unreachable!("publishers cannot be polled"),
+            ZmqSocket::Subscriber(subscriber) => {
+                let (topic, mut envelope) = subscriber.receive_envelope()?;
+                envelope.headers.insert(TOPIC_HEADER.to_owned(), topic);
+                // only bound channels know the address of their peer
+                envelope.headers.remove(PEER_ADDRESS_HEADER);
+                envelope
+            }
+            ZmqSocket::Requester(requester) => {
+                let mut envelope = requester.receive_envelope()?;
+                envelope.headers.remove(PEER_ADDRESS_HEADER);
+                envelope
+            }
+            ZmqSocket::Replier(replier) => {
+                let (mut envelope, ip) = replier.receive_envelope()?;
+                envelope.headers.insert(PEER_ADDRESS_HEADER.to_owned(), ip);
+                envelope
+            }
+        };
+        Ok(Some(envelope))
+    }
+
+    fn local_port(&self) -> anyhow::Result<u16> {
+        let address = match &self.0 {
+            ZmqSocket::Publisher(publisher) => publisher.get_last_endpoint(),
+            ZmqSocket::Subscriber(subscriber) => subscriber.get_last_endpoint(),
+            ZmqSocket::Requester(requester) => requester.get_last_endpoint(),
+            ZmqSocket::Replier(replier) => replier.get_last_endpoint(),
+        }?;
+        Ok(address.port())
+    }
+}
+
+/// Envelopes in [`framing`] frames over TCP connections.
+///
+/// Unlike ZMQ, connecting fails if nobody is bound to the endpoint yet, and bound publish
+/// channels send to all connected peers while bound reply channels answer the peer of the last
+/// request.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TcpTransport;
+
+/// Turns `tcp://*:*` into `0.0.0.0:0`.
+fn tcp_address(endpoint: &str) -> anyhow::Result<String> {
+    let address = endpoint
+        .strip_prefix("tcp://")
+        .with_context(|| format!("TCP endpoint {endpoint} does not start with tcp://"))?;
+    let (host, port) = address
+        .rsplit_once(':')
+        .with_context(|| format!("TCP endpoint {endpoint} is missing the port"))?;
+    let host = if host == "*" { "0.0.0.0" } else { host };
+    let port = if port == "*" { "0" } else { port };
+    Ok(format!("{host}:{port}"))
+}
+
+impl Transport for TcpTransport {
+    fn bind(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>> {
+        anyhow::ensure!(
+            pattern != Pattern::Request,
+            "Requests can only be sent through connected TCP channels"
+        );
+        let listener = TcpListener::bind(tcp_address(endpoint)?)
+            .with_context(|| format!("Failed to bind to {endpoint}"))?;
+        Ok(Box::new(TcpServer::listen(pattern, listener)?))
+    }
+
+    fn connect(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>> {
+        let stream = TcpStream::connect(tcp_address(endpoint)?)
+            .with_context(|| format!("Failed to connect to {endpoint}"))?;
+        let peer = stream.peer_addr()?;
+        let (sender, received) = mpsc::channel();
+        spawn_reader(stream.try_clone()?, peer, sender, || {});
+        Ok(Box::new(TcpClient {
+            pattern,
+            stream,
+            received,
+        }))
+    }
+}
+
+type Received = (SocketAddr, PayloadEnvelope);
+
+/// Forwards the envelopes received from the peer until the connection is closed.
+fn spawn_reader(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    received: Sender<Received>,
+    on_close: impl FnOnce() + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        let mut decoder = FrameDecoder::default();
+        let mut buffer = [0; 1024];
+        loop {
+            let read = match stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    tracing::debug!("Closing connection to {peer}: {e}");
+                    break;
+                }
+            };
+            decoder.push(&buffer[..read]);
+            while let Some(frame) = decoder.next_frame() {
+                let envelope = frame
+                    .map_err(anyhow::Error::from)
+                    .and_then(|frame| PayloadEnvelope::from_bytes(&frame).map_err(Into::into))
+                    .and_then(|mut envelope| {
+                        encryption::open(&mut envelope)?;
+                        Ok(envelope)
+                    });
+                match envelope {
+                    Ok(envelope) => {
+                        if received.send((peer, envelope)).is_err() {
+                            // the channel was dropped
+                            return on_close();
+                        }
+                    }
+                    Err(e) => tracing::warn!("Skipping invalid frame from {peer}: {e:#}"),
+                }
+            }
+        }
+        on_close();
+    });
+}
+
+fn write_frame(mut stream: &TcpStream, envelope: &PayloadEnvelope) -> anyhow::Result<()> {
+    let frame = framing::encode_frame(&encryption::seal(envelope)?.encode_to_vec())?;
+    stream
+        .write_all(&frame)
+        .with_context(|| format!("Failed to send to {:?}", stream.peer_addr().ok()))
+}
+
+/// Returns the next message from the peers, `None` after the timeout.
+fn receive_within<T>(
+    receiver: &Receiver<T>,
+    timeout: Option<Duration>,
+) -> anyhow::Result<Option<T>> {
+    let result = match timeout {
+        Some(timeout) => receiver.recv_timeout(timeout),
+        None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    };
+    match result {
+        Ok(message) => Ok(Some(message)),
+        Err(RecvTimeoutError::Timeout) => Ok(None),
+        Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Connection to the peer was closed"),
+    }
+}
+
+#[derive(Debug)]
+struct TcpClient {
+    pattern: Pattern,
+    stream: TcpStream,
+    received: Receiver<Received>,
+}
+
+impl Channel for TcpClient {
+    fn send(&self, envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.pattern != Pattern::Subscribe,
+            "Cannot send through a subscriber"
+        );
+        write_frame(&self.stream, &envelope)
+    }
+
+    fn receive(&self, timeout: Option<Duration>) -> anyhow::Result<Option<PayloadEnvelope>> {
+        anyhow::ensure!(
+            self.pattern != Pattern::Publish,
+            "Cannot receive through a publisher"
+        );
+        Ok(receive_within(&self.received, timeout)?.map(|(_, envelope)| envelope))
+    }
+}
+
+impl Drop for TcpClient {
+    fn drop(&mut self) {
+        // stops the reader
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+type Connections = Arc<Mutex<HashMap<SocketAddr, TcpStream>>>;
+
+#[derive(Debug)]
+struct TcpServer {
+    pattern: Pattern,
+    port: u16,
+    connections: Connections,
+    received: Receiver<Received>,
+    /// Peer of the last request, which the reply is sent to.
+    requester: Cell<Option<SocketAddr>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl TcpServer {
+    fn listen(pattern: Pattern, listener: TcpListener) -> anyhow::Result<Self> {
+        let port = listener.local_addr()?.port();
+        listener.set_nonblocking(true)?;
+        let connections = Connections::default();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (sender, received) = mpsc::channel();
+        std::thread::spawn({
+            let connections = connections.clone();
+            let stopped = stopped.clone();
+            move || {
+                while !stopped.load(Ordering::SeqCst) {
+                    let stream = match listener.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                            std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::warn!("Failed to accept connection on port {port}: {e}");
+                            continue;
+                        }
+                    };
+                    if let Err(e) = Self::accept(stream, &connections, &sender) {
+                        tracing::warn!("Failed to accept connection on port {port}: {e:#}");
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            pattern,
+            port,
+            connections,
+            received,
+            requester: Cell::new(None),
+            stopped,
+        })
+    }
+
+    fn accept(
+        stream: TcpStream,
+        connections: &Connections,
+        sender: &Sender<Received>,
+    ) -> anyhow::Result<()> {
+        stream.set_nonblocking(false)?;
+        let peer = stream.peer_addr()?;
+        tracing::debug!("Accepted connection from {peer}");
+        spawn_reader(stream.try_clone()?, peer, sender.clone(), {
+            let connections = connections.clone();
+            move || drop(lock(&connections).remove(&peer))
+        });
+        lock(connections).insert(peer, stream);
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().expect("non-poisoned Mutex")
+}
+
+impl Channel for TcpServer {
+    fn send(&self, envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        match self.pattern {
+            Pattern::Publish => {
+                // peers that cannot keep up are disconnected, like slow ZMQ subscribers drop data
+                lock(&self.connections).retain(|peer, stream| {
+                    write_frame(stream, &envelope)
+                        .inspect_err(|e| tracing::warn!("Disconnecting {peer}: {e:#}"))
+                        .is_ok()
+                });
+                Ok(())
+            }
+            Pattern::Reply => {
+                let peer = self
+                    .requester
+                    .take()
+                    .context("Cannot reply before a request was received")?;
+                let connections = lock(&self.connections);
+                let stream = connections
+                    .get(&peer)
+                    .with_context(|| format!("Requester {peer} disconnected before the reply"))?;
+                write_frame(stream, &envelope)
+            }
+            Pattern::Subscribe | Pattern::Request => {
+                anyhow::bail!("Cannot send through a bound {:?} channel", self.pattern)
+            }
+        }
+    }
+
+    fn receive(&self, timeout: Option<Duration>) -> anyhow::Result<Option<PayloadEnvelope>> {
+        anyhow::ensure!(
+            self.pattern != Pattern::Publish,
+            "Cannot receive through a publisher"
+        );
+        let Some((peer, mut envelope)) = receive_within(&self.received, timeout)? else {
+            return Ok(None);
+        };
+        if self.pattern == Pattern::Reply {
+            self.requester.set(Some(peer));
+        }
+        envelope
+            .headers
+            .insert(PEER_ADDRESS_HEADER.to_owned(), peer.ip().to_string());
+        Ok(Some(envelope))
+    }
+
+    fn local_port(&self) -> anyhow::Result<u16> {
+        Ok(self.port)
+    }
+}
+
+impl Drop for TcpServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for stream in lock(&self.connections).values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Channels between the parts of a single process.
+///
+/// Endpoints are addresses like `tcp://127.0.0.1:5556`, but only their port is relevant.
+/// The envelopes never leave the process, so their payloads are not encrypted.
+/// Connecting succeeds before the endpoint is bound, but requests fail while nobody replies.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryTransport;
+
+/// Request together with the sender for its reply.
+type MemoryRequest = (PayloadEnvelope, Sender<PayloadEnvelope>);
+
+#[derive(Debug, Default)]
+struct MemoryEndpoint {
+    bound: bool,
+    subscribers: Vec<Sender<PayloadEnvelope>>,
+    replier: Option<Sender<MemoryRequest>>,
+}
+
+fn memory_endpoints() -> MutexGuard<'static, HashMap<u16, MemoryEndpoint>> {
+    static ENDPOINTS: OnceLock<Mutex<HashMap<u16, MemoryEndpoint>>> = OnceLock::new();
+    lock(ENDPOINTS.get_or_init(Mutex::default))
+}
+
+/// Returns the port of the endpoint, `None` for `*`.
+fn memory_port(endpoint: &str) -> anyhow::Result<Option<u16>> {
+    let (_, port) = endpoint
+        .rsplit_once(':')
+        .with_context(|| format!("Endpoint {endpoint} is missing the port"))?;
+    if port == "*" {
+        return Ok(None);
+    }
+    let port = port
+        .parse()
+        .with_context(|| format!("Invalid port in endpoint {endpoint}"))?;
+    Ok(Some(port))
+}
+
+impl MemoryTransport {
+    fn open(&self, pattern: Pattern, port: u16, bound: bool) -> MemoryChannel {
+        let mut endpoints = memory_endpoints();
+        let endpoint = endpoints.entry(port).or_default();
+        endpoint.bound |= bound;
+        let inner = match pattern {
+            Pattern::Publish => MemoryInner::Publish,
+            Pattern::Subscribe => {
+                let (sender, receiver) = mpsc::channel();
+                endpoint.subscribers.push(sender);
+                MemoryInner::Subscribe(receiver)
+            }
+            Pattern::Request => MemoryInner::Request(Cell::new(None)),
+            Pattern::Reply => {
+                let (sender, receiver) = mpsc::channel();
+                endpoint.replier = Some(sender);
+                MemoryInner::Reply {
+                    requests: receiver,
+                    requester: Cell::new(None),
+                }
+            }
+        };
+        MemoryChannel { port, bound, inner }
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn bind(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>> {
+        let port = match memory_port(endpoint)? {
+            Some(port) => {
+                anyhow::ensure!(
+                    !memory_endpoints().get(&port).is_some_and(|e| e.bound),
+                    "In-memory endpoint {endpoint} is already bound"
+                );
+                port
+            }
+            None => {
+                static NEXT_PORT: AtomicU16 = AtomicU16::new(FIRST_MEMORY_PORT);
+                NEXT_PORT.fetch_add(1, Ordering::SeqCst)
+            }
+        };
+        Ok(Box::new(self.open(pattern, port, true)))
+    }
+
+    fn connect(&self, pattern: Pattern, endpoint: &str) -> anyhow::Result<Box<dyn Channel>> {
+        let port = memory_port(endpoint)?
+            .with_context(|| format!("Cannot connect to endpoint {endpoint} without port"))?;
+        Ok(Box::new(self.open(pattern, port, false)))
+    }
+}
+
+enum MemoryInner {
+    Publish,
+    Subscribe(Receiver<PayloadEnvelope>),
+    /// Receiver of the reply to the pending request.
+    Request(Cell<Option<Receiver<PayloadEnvelope>>>),
+    Reply {
+        requests: Receiver<MemoryRequest>,
+        /// Sender of the reply to the last request.
+        requester: Cell<Option<Sender<PayloadEnvelope>>>,
+    },
+}
+
+struct MemoryChannel {
+    port: u16,
+    bound: bool,
+    inner: MemoryInner,
+}
+
+impl fmt::Debug for MemoryChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pattern = match self.inner {
+            MemoryInner::Publish => Pattern::Publish,
+            MemoryInner::Subscribe(_) => Pattern::Subscribe,
+            MemoryInner::Request(_) => Pattern::Request,
+            MemoryInner::Reply { .. } => Pattern::Reply,
+        };
+        f.debug_struct("MemoryChannel")
+            .field("port", &self.port)
+            .field("pattern", &pattern)
+            .finish()
+    }
+}
+
+impl Channel for MemoryChannel {
+    fn send(&self, envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        match &self.inner {
+            MemoryInner::Publish => {
+                if let Some(endpoint) = memory_endpoints().get_mut(&self.port) {
+                    endpoint
+                        .subscribers
+                        .retain(|subscriber| subscriber.send(envelope.clone()).is_ok());
+                }
+                Ok(())
+            }
+            MemoryInner::Subscribe(_) => anyhow::bail!("Cannot send through a subscriber"),
+            MemoryInner::Request(reply) => {
+                let replier = memory_endpoints()
+                    .get(&self.port)
+                    .and_then(|endpoint| endpoint.replier.clone())
+                    .with_context(|| format!("Nobody replies on in-memory port {}", self.port))?;
+                let (sender, receiver) = mpsc::channel();
+                replier
+                    .send((envelope, sender))
+                    .map_err(|_| anyhow::anyhow!("Replier on port {} is gone", self.port))?;
+                reply.set(Some(receiver));
+                Ok(())
+            }
+            MemoryInner::Reply { requester, .. } => requester
+                .take()
+                .context("Cannot reply before a request was received")?
+                .send(envelope)
+                .map_err(|_| anyhow::anyhow!("Requester is gone before the reply")),
+        }
+    }
+
+    fn receive(&self, timeout: Option<Duration>) -> anyhow::Result<Option<PayloadEnvelope>> {
+        match &self.inner {
+            MemoryInner::Publish => anyhow::bail!("Cannot receive through a publisher"),
+            MemoryInner::Subscribe(receiver) => receive_within(receiver, timeout),
+            MemoryInner::Request(reply) => {
+                let receiver = reply
+                    .take()
+                    .context("Cannot receive a reply before sending a request")?;
+                let result = receive_within(&receiver, timeout);
+                if matches!(result, Ok(None)) {
+                    // the reply may still arrive
+                    reply.set(Some(receiver));
+                }
+                result
+            }
+            MemoryInner::Reply {
+                requests,
+                requester,
+            } => {
+                let Some((mut envelope, sender)) = receive_within(requests, timeout)? else {
+                    return Ok(None);
+                };
+                requester.set(Some(sender));
+                envelope
+                    .headers
+                    .insert(PEER_ADDRESS_HEADER.to_owned(), "127.0.0.1".to_owned());
+                Ok(Some(envelope))
+            }
+        }
+    }
+
+    fn local_port(&self) -> anyhow::Result<u16> {
+        Ok(self.port)
+    }
+}
+
+impl Drop for MemoryChannel {
+    fn drop(&mut self) {
+        let mut endpoints = memory_endpoints();
+        let Some(endpoint) = endpoints.get_mut(&self.port) else {
+            return;
+        };
+        if self.bound {
+            endpoint.bound = false;
+        }
+        if let MemoryInner::Reply { .. } = self.inner {
+            endpoint.replier = None;
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Data plane over UDP multicast as an alternative to the PUB/SUB sockets.
+//!
+//! Every datagram is a [`PayloadEnvelope`] carrying the topic and a sequence number per sender in
+//! its headers, so the receiver can count lost datagrams.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use prost::Message as _;
+
+use crate::{
+    encryption,
+    envelope::Headers,
+    protobuf::PayloadEnvelope,
+    transport::{Channel, TOPIC_HEADER},
+};
+
+/// Multicast group and port entities publish their data to instead of the data endpoint,
+/// e.g. `239.255.42.1:5560`.
+pub const ENV_UDP_MULTICAST_GROUP: &str = "HOME_AUTOMATION_UDP_MULTICAST_GROUP";
+
+const SEQUENCE_HEADER: &str = "sequence";
+/// Datagrams are limited to the usual MTU to avoid fragmentation.
+const MAX_DATAGRAM_LEN: usize = 1400;
+
+/// Reads the multicast group from [`ENV_UDP_MULTICAST_GROUP`], `None` if UDP is not used.
+pub fn multicast_group() -> anyhow::Result<Option<SocketAddrV4>> {
+    let Ok(group) = std::env::var(ENV_UDP_MULTICAST_GROUP) else {
+        return Ok(None);
+    };
+    let group: SocketAddrV4 = group
+        .parse()
+        .with_context(|| format!("Invalid {ENV_UDP_MULTICAST_GROUP} value {group}"))?;
+    anyhow::ensure!(
+        group.ip().is_multicast(),
+        "{ENV_UDP_MULTICAST_GROUP} value {group} is not a multicast address"
+    );
+    Ok(Some(group))
+}
+
+#[derive(Debug)]
+pub struct UdpPublisher {
+    socket: UdpSocket,
+    group: SocketAddrV4,
+    sequence: AtomicU64,
+}
+
+impl UdpPublisher {
+    pub fn new(group: SocketAddrV4) -> anyhow::Result<Self> {
+        let socket =
+            UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).context("Failed to create UDP socket")?;
+        Ok(Self {
+            socket,
+            group,
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    pub fn send<M>(&self, topic: &str, message: &M) -> anyhow::Result<()>
+    where
+        M: prost::Name,
+    {
+        let headers = Headers::from_iter([(TOPIC_HEADER.to_owned(), topic.to_owned())]);
+        self.send_envelope(PayloadEnvelope::pack(message, headers)?)
+    }
+
+    /// Sends the envelope, which must carry its topic in the [`TOPIC_HEADER`].
+    pub fn send_envelope(&self, mut envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            envelope.headers.contains_key(TOPIC_HEADER),
+            "Missing topic of datagram"
+        );
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        envelope
+            .headers
+            .insert(SEQUENCE_HEADER.to_owned(), sequence.to_string());
+        let datagram = encryption::seal(&envelope)?.encode_to_vec();
+        anyhow::ensure!(
+            datagram.len() <= MAX_DATAGRAM_LEN,
+            "Datagram of {} bytes exceeds the maximum of {MAX_DATAGRAM_LEN} bytes",
+            datagram.len()
+        );
+        self.socket
+            .send_to(&datagram, self.group)
+            .with_context(|| format!("Failed to send datagram to {}", self.group))?;
+        Ok(())
+    }
+}
+
+/// Received, lost and malformed datagrams since the start.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UdpStatistics {
+    pub received: u64,
+    pub lost: u64,
+    pub malformed: u64,
+}
+
+impl fmt::Display for UdpStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "received {} datagrams, lost {}, malformed {}",
+            self.received, self.lost, self.malformed
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct UdpSubscriber {
+    socket: UdpSocket,
+    statistics: Mutex<UdpStatistics>,
+    /// Next expected sequence number per sender.
+    sequences: Mutex<HashMap<SocketAddr, u64>>,
+}
+
+impl UdpSubscriber {
+    /// Joins the multicast group, receives time out after `timeout` to check for shutdowns.
+    pub fn join(group: SocketAddrV4, timeout: Duration) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, group.port()))
+            .with_context(|| format!("Failed to bind UDP port {}", group.port()))?;
+        socket
+            .join_multicast_v4(group.ip(), &Ipv4Addr::UNSPECIFIED)
+            .with_context(|| format!("Failed to join multicast group {}", group.ip()))?;
+        socket.set_read_timeout(Some(timeout))?;
+        Ok(Self {
+            socket,
+            statistics: Mutex::default(),
+            sequences: Mutex::default(),
+        })
+    }
+
+    /// Waits for the next datagram, `None` if none arrived in time.
+    pub fn receive<M>(&self) -> anyhow::Result<Option<(String, M)>>
+    where
+        M: prost::Name + Default,
+    {
+        let Some(mut envelope) = self.receive_envelope()? else {
+            return Ok(None);
+        };
+        let topic = envelope
+            .headers
+            .remove(TOPIC_HEADER)
+            .context("Missing topic")?;
+        Ok(Some((topic, envelope.unpack()?)))
+    }
+
+    /// Waits for the next datagram without unpacking it, the topic stays in the [`TOPIC_HEADER`].
+    pub fn receive_envelope(&self) -> anyhow::Result<Option<PayloadEnvelope>> {
+        let mut buffer = [0; MAX_DATAGRAM_LEN];
+        let (len, sender) = match self.socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e).context("Failed to receive datagram"),
+        };
+        let result = self.decode(&buffer[..len], sender);
+        let mut statistics = self.statistics.lock().expect("non-poisoned Mutex");
+        match &result {
+            Ok((_, lost)) => {
+                statistics.received += 1;
+                statistics.lost += lost;
+            }
+            Err(_) => statistics.malformed += 1,
+        }
+        result
+            .map(|(envelope, _)| Some(envelope))
+            .with_context(|| format!("Malformed datagram from {sender}"))
+    }
+
+    /// Returns the envelope and the number of datagrams lost before it.
+    fn decode(
+        &self,
+        datagram: &[u8],
+        sender: SocketAddr,
+    ) -> anyhow::Result<(PayloadEnvelope, u64)> {
+        let mut envelope = PayloadEnvelope::from_bytes(datagram)?;
+        anyhow::ensure!(envelope.headers.contains_key(TOPIC_HEADER), "Missing topic");
+        encryption::open(&mut envelope)?;
+        let sequence: u64 = envelope
+            .headers
+            .remove(SEQUENCE_HEADER)
+            .context("Missing sequence number")?
+            .parse()
+            .context("Invalid sequence number")?;
+
+        let mut sequences = self.sequences.lock().expect("non-poisoned Mutex");
+        let expected = sequences.insert(sender, sequence + 1).unwrap_or(sequence);
+        // a smaller number means the sender restarted or the datagrams were reordered
+        let lost = sequence.saturating_sub(expected);
+        Ok((envelope, lost))
+    }
+
+    pub fn statistics(&self) -> UdpStatistics {
+        *self.statistics.lock().expect("non-poisoned Mutex")
+    }
+}
+
+impl Channel for UdpPublisher {
+    fn send(&self, envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        self.send_envelope(envelope)
+    }
+
+    fn receive(&self, _timeout: Option<Duration>) -> anyhow::Result<Option<PayloadEnvelope>> {
+        anyhow::bail!("Cannot receive through the UDP publisher")
+    }
+}
+
+impl Channel for UdpSubscriber {
+    fn send(&self, _envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        anyhow::bail!("Cannot send through the UDP subscriber")
+    }
+
+    /// Receives in steps of the timeout given to [`UdpSubscriber::join`].
+    fn receive(&self, timeout: Option<Duration>) -> anyhow::Result<Option<PayloadEnvelope>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(envelope) = self.receive_envelope()? {
+                return Ok(Some(envelope));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+        }
+    }
+}
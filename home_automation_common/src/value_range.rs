@@ -0,0 +1,116 @@
+//! Allowed ranges of the values that can be sent to the entities.
+//!
+//! The controller, the entities and the client check against the same table, so a value that is
+//! accepted by one of them is accepted by all.
+
+use std::fmt;
+
+use crate::protobuf::{actuator_state, named_entity_state::State, NamedEntityState};
+
+/// Generates [`ValueKind`] with its name, range and unit from one row per kind.
+macro_rules! value_ranges {
+    ($($(#[doc = $doc:literal])* $kind:ident ($name:literal): $min:literal ..= $max:literal $unit:literal,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum ValueKind {
+            $($(#[doc = $doc])* $kind,)*
+        }
+
+        impl ValueKind {
+            pub const ALL: &'static [Self] = &[$(Self::$kind,)*];
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Self::$kind => $name,)*
+                }
+            }
+
+            pub fn range(self) -> ValueRange {
+                match self {
+                    $(Self::$kind => ValueRange { min: $min, max: $max, unit: $unit },)*
+                }
+            }
+        }
+    };
+}
+
+value_ranges! {
+    /// Brightness of a light.
+    Brightness("brightness"): 0.0..=100.0 "%",
+    /// Publish frequency of an entity.
+    UpdateFrequency("update frequency"): 0.1..=10.0 "Hz",
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueRange {
+    pub min: f32,
+    pub max: f32,
+    pub unit: &'static str,
+}
+
+impl ValueRange {
+    /// `NaN` is never contained.
+    pub fn contains(&self, value: f32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+
+    pub fn clamp(&self, value: f32) -> f32 {
+        value.clamp(self.min, self.max)
+    }
+
+    /// Position of the value in the range from 0.0 to 1.0, e.g. for gauges.
+    pub fn ratio(&self, value: f32) -> f32 {
+        (self.clamp(value) - self.min) / (self.max - self.min)
+    }
+}
+
+impl fmt::Display for ValueRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} to {} {}", self.min, self.max, self.unit)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{kind} {value} is not in the allowed range from {range}")]
+pub struct OutOfRange {
+    pub kind: ValueKind,
+    pub value: f32,
+    pub range: ValueRange,
+}
+
+impl ValueKind {
+    pub fn check(self, value: f32) -> Result<f32, OutOfRange> {
+        let range = self.range();
+        if range.contains(value) {
+            Ok(value)
+        } else {
+            Err(OutOfRange {
+                kind: self,
+                value,
+                range,
+            })
+        }
+    }
+}
+
+/// Checks all values of the command, commands without values like pings are always valid.
+pub fn validate(command: &NamedEntityState) -> Result<(), OutOfRange> {
+    match &command.state {
+        Some(State::SensorConfiguration(configuration)) => {
+            ValueKind::UpdateFrequency.check(configuration.update_frequency_hz)?;
+        }
+        Some(State::ActuatorState(actuator)) => match &actuator.state {
+            Some(actuator_state::State::Light(light)) => {
+                ValueKind::Brightness.check(light.brightness)?;
+            }
+            Some(actuator_state::State::AirConditioning(_)) | None => {}
+        },
+        Some(State::Lifecycle(_) | State::Measurement(_)) | None => {}
+    }
+    Ok(())
+}
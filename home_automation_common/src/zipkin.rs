@@ -0,0 +1,169 @@
+//! Export of the spans to the Zipkin trace collector. While the collector is unreachable, the
+//! spans are dropped instead of stalling the exporter, and the programs continue with logs only.
+//!
+//! The root spans of every process are tagged with the host, the process ID, the version and a
+//! random instance ID, so the traces of entities with the same name are told apart.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use opentelemetry::KeyValue;
+use opentelemetry_http::{HttpError, Request, Response};
+use tracing::{span, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Zipkin endpoint of the trace exporter if [`ENV_ZIPKIN_ENDPOINT`] is not set.
+const DEFAULT_ZIPKIN_ENDPOINT: &str = "http://127.0.0.1:9411/api/v2/spans";
+pub const ENV_ZIPKIN_ENDPOINT: &str = "OTEL_EXPORTER_ZIPKIN_ENDPOINT";
+
+/// Upper bound of a single export, so that a stalled collector cannot pile up spans.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(2);
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `false` while the collector is unreachable.
+static EXPORTING: AtomicBool = AtomicBool::new(true);
+
+pub fn endpoint() -> String {
+    std::env::var(ENV_ZIPKIN_ENDPOINT).unwrap_or_else(|_| DEFAULT_ZIPKIN_ENDPOINT.to_owned())
+}
+
+/// Sends an empty list of spans to check that the collector accepts spans.
+pub fn probe(timeout: Duration) -> Result<ureq::Response, ureq::Error> {
+    ureq::post(&endpoint())
+        .timeout(timeout)
+        .set("Content-Type", "application/json")
+        .send_string("[]")
+}
+
+/// Checks the collector before the first span is exported.
+pub(crate) fn check_collector() {
+    if let Err(e) = probe(EXPORT_TIMEOUT) {
+        stop_exporting(&e);
+    }
+}
+
+/// Continues with logs only and retries the collector in the background until it answers.
+fn stop_exporting(error: &dyn fmt::Display) {
+    if !EXPORTING.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    tracing::warn!(
+        "Zipkin collector at {} is unreachable, continuing with logs only: {error}",
+        endpoint()
+    );
+    let retry = std::thread::Builder::new()
+        .name("zipkin-retry".to_owned())
+        .spawn(|| loop {
+            std::thread::sleep(RETRY_INTERVAL);
+            if probe(EXPORT_TIMEOUT).is_ok() {
+                EXPORTING.store(true, Ordering::SeqCst);
+                tracing::info!(
+                    "Zipkin collector at {} is reachable again, exporting traces",
+                    endpoint()
+                );
+                return;
+            }
+        });
+    if let Err(e) = retry {
+        tracing::error!("Failed to retry the Zipkin collector, traces stay disabled: {e}");
+    }
+}
+
+/// Answer for the spans that are dropped while the collector is unreachable.
+fn dropped() -> Result<Response<Bytes>, HttpError> {
+    Ok(Response::builder().status(503).body(Bytes::new())?)
+}
+
+#[derive(Debug)]
+pub(crate) struct UReqHttpClient;
+
+#[async_trait::async_trait]
+impl opentelemetry_http::HttpClient for UReqHttpClient {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        if !EXPORTING.load(Ordering::SeqCst) {
+            return dropped();
+        }
+        let (http_parts, body) = request.into_parts();
+        let ureq_request: ureq::Request = http_parts.into();
+        match ureq_request.timeout(EXPORT_TIMEOUT).send_bytes(&body) {
+            Ok(ureq_response) => {
+                let response: Response<Vec<u8>> = ureq_response.into();
+                Ok(response.map(Bytes::from))
+            }
+            // the exporter would print the error to stderr for every span
+            Err(ureq::Error::Transport(e)) => {
+                stop_exporting(&e);
+                dropped()
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Random ID of this process in the traces.
+pub fn instance_id() -> &'static str {
+    static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+    INSTANCE_ID.get_or_init(|| format!("{:016x}", RandomState::new().hash_one(std::process::id())))
+}
+
+fn host_name() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_owned())
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
+
+/// Tags the root spans of the process with its resource attributes, which the Zipkin exporter
+/// does not export itself.
+pub(crate) struct ResourceLayer {
+    attributes: Vec<KeyValue>,
+}
+
+impl ResourceLayer {
+    pub(crate) fn new() -> Self {
+        let mut attributes = vec![
+            KeyValue::new("host.name", host_name()),
+            KeyValue::new("process.pid", i64::from(std::process::id())),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+            KeyValue::new("service.instance.id", instance_id()),
+        ];
+        // parallel deployments, e.g. of several lab groups, differ in their prefix
+        let prefix = crate::topic_prefix();
+        if !prefix.is_empty() {
+            attributes.push(KeyValue::new("deployment", prefix));
+        }
+        Self { attributes }
+    }
+}
+
+impl<S> Layer<S> for ResourceLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if span.parent().is_some() {
+            return;
+        }
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<OtelData>() {
+            data.builder
+                .attributes
+                .get_or_insert_with(Vec::new)
+                .extend(self.attributes.iter().cloned());
+        }
+    }
+}
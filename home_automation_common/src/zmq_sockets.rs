@@ -1,10 +1,61 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    encryption,
+    envelope::{Headers, PackedMessage},
+    error::{ErrorKindExt, ZmqResultExt as _},
+    log_event::LogEvent,
+    log_summary::Abbreviated,
+    protobuf::PayloadEnvelope,
+    Error, Result,
+};
+
+pub mod r#async;
+
+/// Header with the number of the publication on its topic, so subscribers can count the
+/// publications dropped in between, e.g. because a queue reached its high water mark.
+const SEQUENCE_HEADER: &str = "sequence";
+
+/// Peer address of the messages received over `inproc` endpoints, which have no remote address.
+pub const INPROC_PEER_ADDRESS: &str = "inproc";
+
+/// Traffic class of a socket. Each lane has its own I/O thread, so that commands are not queued
+/// behind bulk data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    /// Publications of the entity data.
+    Bulk,
+    /// Requests and replies, e.g. actuator commands and the client API.
+    Command,
+}
 
-use anyhow::{anyhow, Context as _, Result};
+impl Lane {
+    /// I/O threads of a context, one per lane.
+    const IO_THREADS: i32 = 2;
 
-pub use zmq::Error;
+    /// Bit mask of the I/O thread that handles the lane.
+    fn affinity(self) -> u64 {
+        match self {
+            Self::Bulk => 1 << 0,
+            Self::Command => 1 << 1,
+        }
+    }
 
-use crate::{AnyhowExt, AnyhowZmq};
+    /// Type of service of the IP packets, commands are marked for expedited forwarding.
+    fn type_of_service(self) -> i32 {
+        match self {
+            Self::Bulk => 0,
+            Self::Command => 0xb8,
+        }
+    }
+}
 
 /// Handle for a ØMQ context, used to create sockets.
 ///
@@ -25,7 +76,7 @@ use crate::{AnyhowExt, AnyhowZmq};
 /// You can still deadlock yourself (or intentionally close sockets in
 /// other threads, see `zmq_ctx_destroy`(3)) by explicitly calling
 /// `Context::destroy`.
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct Context(zmq::Context);
 
 impl std::fmt::Debug for Context {
@@ -38,29 +89,41 @@ impl std::fmt::Debug for Context {
 }
 
 impl Context {
-    /// Create a new reference-counted context handle.
+    /// Create a new reference-counted context handle with an I/O thread per [`Lane`].
     pub fn new() -> Context {
-        Self(zmq::Context::new())
+        let context = Self(zmq::Context::new());
+        if let Err(e) = context.set_io_threads(Lane::IO_THREADS) {
+            tracing::warn!("Commands share the I/O thread with bulk data: {e}");
+        }
+        context
     }
 
     /// Get the size of the ØMQ thread pool to handle I/O operations.
     pub fn get_io_threads(&self) -> Result<i32> {
         self.0
             .get_io_threads()
-            .context("Failed to read I/O thread count")
+            .zmq_context(|| "Failed to read I/O thread count")
     }
 
     /// Set the size of the ØMQ thread pool to handle I/O operations.
     pub fn set_io_threads(&self, value: i32) -> Result<()> {
         self.0
             .set_io_threads(value)
-            .context("Failed to set I/O thread count")
+            .zmq_context(|| "Failed to set I/O thread count")
     }
 
     /// Try to destroy the context. This is different than the destructor; the
     /// destructor will loop when zmq_ctx_term returns EINTR.
     pub fn destroy(&mut self) -> Result<()> {
-        self.0.destroy().context("Failed to destroy ZMQ context")
+        self.0
+            .destroy()
+            .zmq_context(|| "Failed to destroy ZMQ context")
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -71,6 +134,8 @@ impl Context {
 /// - [`Subscriber`][markers::Subscriber] = `SUB`
 /// - [`Requester`][markers::Requester] = `REQ`
 /// - [`Replier`][markers::Replier] = `REP`
+/// - [`XPublisher`][markers::XPublisher] = `XPUB`
+/// - [`XSubscriber`][markers::XSubscriber] = `XSUB`
 ///
 /// The generic parameter `LinkState` is either [`Detached`][markers::Detached] or
 /// [`Linked`][markers::Linked] to represent a socket that is bound or connected to
@@ -79,12 +144,20 @@ pub struct Socket<Kind, LinkState> {
     inner: zmq::Socket,
     kind: Kind,
     link_state: LinkState,
+    counters: Arc<Counters>,
+    /// Whether the socket is linked to an `inproc` endpoint.
+    inproc: AtomicBool,
 }
 
 pub type Publisher<LinkState = markers::Detached> = Socket<markers::Publisher, LinkState>;
 pub type Subscriber<LinkState = markers::Detached> = Socket<markers::Subscriber, LinkState>;
 pub type Requester<LinkState = markers::Detached> = Socket<markers::Requester, LinkState>;
 pub type Replier<LinkState = markers::Detached> = Socket<markers::Replier, LinkState>;
+pub type XPublisher<LinkState = markers::Detached> = Socket<markers::XPublisher, LinkState>;
+pub type XSubscriber<LinkState = markers::Detached> = Socket<markers::XSubscriber, LinkState>;
+pub type Router<LinkState = markers::Detached> = Socket<markers::Router, LinkState>;
+
+pub use zmq::PollItem;
 
 impl<Kind, LinkState> std::fmt::Debug for Socket<Kind, LinkState>
 where
@@ -99,6 +172,15 @@ where
     }
 }
 
+impl<Kind, LinkState> Socket<Kind, LinkState> {
+    /// Remembers that messages without peer address may be received from the endpoint.
+    fn mark_inproc(&self, endpoint: &str) {
+        if endpoint.starts_with("inproc://") {
+            self.inproc.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
 impl<Kind> Socket<Kind, markers::Detached>
 where
     Kind: markers::SocketKind,
@@ -108,41 +190,158 @@ where
     /// Note that the returned socket keeps a an `Arc` reference to
     /// the context it was created from, and will keep that context
     /// from being dropped while being live.
+    ///
+    /// The socket is handled by the I/O thread of its [`Lane`].
     pub fn new(ctx: &Context) -> Result<Self> {
-        ctx.0
+        let inner = ctx
+            .0
             .socket(Kind::KIND)
-            .map(|inner| Self {
-                inner,
-                kind: Kind::default(),
-                link_state: markers::Detached,
-            })
-            .with_context(|| format!("Failed to create {:?} socket", Kind::default()))
+            .zmq_context(|| format!("Failed to create {:?} socket", Kind::default()))?;
+        // an affinity without matching I/O thread fails when linking the socket
+        if ctx.get_io_threads()? >= Lane::IO_THREADS {
+            inner
+                .set_affinity(Kind::LANE.affinity())
+                .zmq_context(|| format!("Failed to assign {:?} lane", Kind::LANE))?;
+        }
+        inner
+            .set_tos(Kind::LANE.type_of_service())
+            .zmq_context(|| "Failed to set type of service")?;
+        Ok(Self {
+            inner,
+            kind: Kind::default(),
+            link_state: markers::Detached,
+            counters: Arc::default(),
+            inproc: AtomicBool::new(false),
+        })
     }
 }
 
-impl<Kind> Socket<Kind, markers::Detached> {
+impl<Kind: std::fmt::Debug> Socket<Kind, markers::Detached> {
     /// Connect a socket.
     pub fn connect(self, endpoint: &str) -> Result<Socket<Kind, markers::Linked>> {
         self.inner
             .connect(endpoint)
-            .with_context(|| format!("Failed to connect to {endpoint}"))?;
-        Ok(Socket {
-            inner: self.inner,
-            link_state: markers::Linked,
-            kind: self.kind,
-        })
+            .zmq_context(|| format!("Failed to connect to {endpoint}"))?;
+        self.mark_inproc(endpoint);
+        let description = format!("{:?} connected to {endpoint}", self.kind);
+        Ok(self.link(description))
     }
 
     /// Accept connections on a socket.
     pub fn bind(self, endpoint: &str) -> Result<Socket<Kind, markers::Linked>> {
         self.inner
             .bind(endpoint)
-            .with_context(|| format!("Failed to bind to {endpoint}"))?;
-        Ok(Socket {
+            .zmq_context(|| format!("Failed to bind to {endpoint}"))?;
+        self.mark_inproc(endpoint);
+        let description = format!("{:?} bound to {endpoint}", self.kind);
+        Ok(self.link(description))
+    }
+
+    /// Accept connections on a socket, retrying while the address is in use.
+    ///
+    /// Returns the socket together with the actually bound endpoint, which differs from the given
+    /// one if a fallback port was used.
+    pub fn bind_with_retry(
+        self,
+        endpoint: &str,
+        retry: &BindRetry,
+    ) -> Result<(Socket<Kind, markers::Linked>, String)> {
+        let candidates = retry.candidates(endpoint);
+        let mut backoff = retry.initial_backoff;
+        for attempt in 0..=retry.retries {
+            for candidate in &candidates {
+                match self.inner.bind(candidate) {
+                    Ok(()) => {
+                        let bound = match self.inner.get_last_endpoint() {
+                            Ok(Ok(bound)) => bound,
+                            _ => candidate.clone(),
+                        };
+                        if candidate.as_str() != endpoint {
+                            tracing::warn!(
+                                %endpoint, %bound,
+                                "{endpoint} is in use, bound to fallback endpoint {bound} instead"
+                            );
+                        }
+                        self.mark_inproc(candidate);
+                        let description = format!("{:?} bound to {bound}", self.kind);
+                        return Ok((self.link(description), bound));
+                    }
+                    Err(zmq::Error::EADDRINUSE) => {
+                        tracing::debug!(%candidate, "Address of {candidate} is in use");
+                    }
+                    Err(e) => {
+                        return Err(e).zmq_context(|| format!("Failed to bind to {candidate}"))
+                    }
+                }
+            }
+            if attempt < retry.retries {
+                tracing::warn!(
+                    %endpoint,
+                    "Address of {endpoint} is in use, retrying in {backoff:?}"
+                );
+                std::thread::sleep(backoff);
+                backoff = backoff.saturating_mul(2);
+            }
+        }
+        Err(zmq::Error::EADDRINUSE).zmq_context(|| {
+            format!(
+                "Failed to bind to {} after {} attempts",
+                candidates.join(", "),
+                retry.retries + 1
+            )
+        })
+    }
+
+    fn link(self, description: String) -> Socket<Kind, markers::Linked> {
+        register_counters(description, &self.counters);
+        Socket {
             inner: self.inner,
             link_state: markers::Linked,
             kind: self.kind,
-        })
+            counters: self.counters,
+            inproc: self.inproc,
+        }
+    }
+}
+
+/// Retry policy of [`Socket::bind_with_retry`] for endpoints that may still be in use, e.g. by a
+/// previous instance that is shutting down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindRetry {
+    /// Number of additional attempts after the first one failed.
+    pub retries: u32,
+    /// Wait time before the first retry, doubled for every further retry.
+    pub initial_backoff: Duration,
+    /// Number of ports following the port of the endpoint that are tried if it is in use.
+    pub port_fallback: u16,
+}
+
+impl Default for BindRetry {
+    /// Fails immediately like [`Socket::bind`].
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            initial_backoff: Duration::from_millis(200),
+            port_fallback: 0,
+        }
+    }
+}
+
+impl BindRetry {
+    /// Returns the endpoint followed by its fallback endpoints.
+    ///
+    /// Endpoints without a numeric port, e.g. `tcp://*:*` or `ipc://...`, have no fallback.
+    fn candidates(&self, endpoint: &str) -> Vec<String> {
+        let Some((address, port)) = endpoint.rsplit_once(':') else {
+            return vec![endpoint.to_owned()];
+        };
+        let Ok(port) = port.parse::<u16>() else {
+            return vec![endpoint.to_owned()];
+        };
+        (0..=self.port_fallback)
+            .map_while(|offset| port.checked_add(offset))
+            .map(|port| format!("{address}:{port}"))
+            .collect()
     }
 }
 
@@ -153,20 +352,51 @@ impl Publisher<markers::Linked> {
     where
         M: prost::Message + prost::Name + Default + std::fmt::Debug,
     {
-        self.inner
-            .send(topic.as_ref(), zmq::SNDMORE)
-            .with_context(|| {
-                let topic = String::from_utf8_lossy(topic.as_ref());
-                format!("Failed to send message {message:?} on topic {topic}")
-            })
-            .trace(Direction::Send)?;
+        #[cfg(feature = "fault-injection")]
+        if crate::fault_injection::FaultInjection::global().drop_publication() {
+            tracing::debug!("Dropping publication because of fault injection");
+            return Ok(());
+        }
 
-        self.tracing_send(message)
-            .with_context(|| {
-                let topic = String::from_utf8_lossy(topic.as_ref());
-                format!("Failed to send on topic {topic}")
-            })
-            .trace(Direction::Send)
+        self.send_topic(topic.as_ref(), || {
+            let topic = String::from_utf8_lossy(topic.as_ref());
+            format!("Failed to send message {message:?} on topic {topic}")
+        })?;
+
+        let topic = String::from_utf8_lossy(topic.as_ref());
+        self.tracing_send(message, Some(&topic))
+            .trace(self.kind_name(), Direction::Send)
+    }
+
+    /// Publish an already packed envelope on the given topic, e.g. one that is forwarded.
+    ///
+    /// The envelope is numbered per topic, see [`Subscriber::receive_envelope`].
+    #[tracing::instrument(skip(self, envelope))]
+    pub fn send_envelope(&self, topic: &str, mut envelope: PayloadEnvelope) -> Result<()> {
+        // numbered before an injected drop, so the subscribers notice it
+        let sequence = self.counters.next_sequence(topic);
+        #[cfg(feature = "fault-injection")]
+        if crate::fault_injection::FaultInjection::global().drop_publication() {
+            tracing::debug!("Dropping publication because of fault injection");
+            return Ok(());
+        }
+
+        envelope
+            .headers
+            .insert(SEQUENCE_HEADER.to_owned(), sequence.to_string());
+        let context = || format!("Failed to send envelope on topic {topic}");
+        self.send_topic(topic.as_bytes(), context)?;
+        self.send_raw_envelope(&envelope, Some(topic), context)
+            .trace(self.kind_name(), Direction::Send)
+    }
+
+    fn send_topic(&self, topic: &[u8], context: impl FnOnce() -> String) -> Result<()> {
+        self.inner
+            .send(topic, zmq::SNDMORE)
+            .zmq_context(context)
+            .trace(self.kind_name(), Direction::Send)?;
+        self.counters.add_bytes_sent(topic.len());
+        Ok(())
     }
 }
 
@@ -176,27 +406,64 @@ impl Subscriber<markers::Linked> {
     where
         M: prost::Message + prost::Name + Default,
     {
-        let topic = self
-            .inner
-            .recv_msg(0)
-            .erase_err()
-            .and_then(|msg| std::str::from_utf8(&msg).map(ToOwned::to_owned).erase_err())
-            .context("Failed to receive topic")
-            .trace(Direction::Receive)?;
+        #[cfg(feature = "fault-injection")]
+        fail_receive_on_injected_fault()?;
 
+        let topic = self.receive_topic()?;
         let payload = self
-            .tracing_receive()
-            .context("Failed to receive payload")
-            .trace(Direction::Receive)?;
+            .tracing_receive(Some(&topic))
+            .trace(self.kind_name(), Direction::Receive)?;
 
         Ok((topic, payload.0))
     }
+
+    /// Block until a message is received and return it without unpacking the envelope.
+    ///
+    /// Gaps in the numbers of the publications on a topic are counted as dropped messages.
+    pub fn receive_envelope(&self) -> Result<(String, PayloadEnvelope)> {
+        #[cfg(feature = "fault-injection")]
+        fail_receive_on_injected_fault()?;
+
+        let topic = self.receive_topic()?;
+        let (mut envelope, _) = self
+            .receive_raw_envelope(Some(&topic), || {
+                format!("Failed to receive envelope on topic {topic}")
+            })
+            .trace(self.kind_name(), Direction::Receive)?;
+
+        // publishers of older versions do not number their publications
+        if let Some(sequence) = envelope
+            .headers
+            .remove(SEQUENCE_HEADER)
+            .and_then(|sequence| sequence.parse().ok())
+        {
+            let dropped = self.counters.track_sequence(&topic, sequence);
+            if dropped > 0 {
+                tracing::warn!(%topic, dropped, "Dropped {dropped} messages on topic {topic}");
+            }
+        }
+
+        Ok((topic, envelope))
+    }
+
+    fn receive_topic(&self) -> Result<String> {
+        self.inner
+            .recv_msg(0)
+            .zmq_context(|| "Failed to receive topic")
+            .and_then(|msg| {
+                self.counters.add_bytes_received(msg.len());
+                msg.as_str().map(ToOwned::to_owned).ok_or_else(|| {
+                    Error::invalid_argument("Failed to receive topic", "topic is not valid UTF-8")
+                })
+            })
+            .trace(self.kind_name(), Direction::Receive)
+    }
 }
 
 impl<LinkState> Subscriber<LinkState> {
     /// Subscribe to the given topic.
     pub fn subscribe(&self, topic: impl AsRef<[u8]>) -> Result<()> {
-        self.inner.set_subscribe(topic.as_ref()).with_context(|| {
+        self.inner.set_subscribe(topic.as_ref()).zmq_context(|| {
             let topic = String::from_utf8_lossy(topic.as_ref());
             format!("Failed to subscribe to {topic}")
         })
@@ -204,9 +471,9 @@ impl<LinkState> Subscriber<LinkState> {
 
     /// Unsubscribe from the given topic.
     pub fn unsubscribe(&self, topic: impl AsRef<[u8]>) -> Result<()> {
-        self.inner.set_unsubscribe(topic.as_ref()).with_context(|| {
+        self.inner.set_unsubscribe(topic.as_ref()).zmq_context(|| {
             let topic = String::from_utf8_lossy(topic.as_ref());
-            format!("Failed to subscribe to {topic}")
+            format!("Failed to unsubscribe from {topic}")
         })
     }
 }
@@ -218,7 +485,8 @@ impl Requester<markers::Linked> {
     where
         M: prost::Message + prost::Name + std::fmt::Debug,
     {
-        self.tracing_send(message).trace(Direction::Send)
+        self.tracing_send(message, None)
+            .trace(self.kind_name(), Direction::Send)
     }
 
     /// Block until a message is received with the REQ-REP pattern.
@@ -227,9 +495,32 @@ impl Requester<markers::Linked> {
     where
         M: prost::Message + prost::Name + Default,
     {
-        self.tracing_receive()
+        #[cfg(feature = "fault-injection")]
+        fail_receive_on_injected_fault()?;
+
+        self.tracing_receive(None)
             .map(|(m, _)| m)
-            .trace(Direction::Receive)
+            .trace(self.kind_name(), Direction::Receive)
+    }
+
+    /// Send an already packed envelope with the REQ-REP pattern.
+    #[tracing::instrument(skip_all)]
+    pub fn send_envelope(&self, envelope: &PayloadEnvelope) -> Result<()> {
+        self.send_raw_envelope(envelope, None, || {
+            "Failed to send request envelope".to_owned()
+        })
+        .trace(self.kind_name(), Direction::Send)
+    }
+
+    /// Block until a reply is received and return it without unpacking the envelope.
+    #[tracing::instrument(skip(self))]
+    pub fn receive_envelope(&self) -> Result<PayloadEnvelope> {
+        #[cfg(feature = "fault-injection")]
+        fail_receive_on_injected_fault()?;
+
+        self.receive_raw_envelope(None, || "Failed to receive reply envelope".to_owned())
+            .map(|(envelope, _)| envelope)
+            .trace(self.kind_name(), Direction::Receive)
     }
 }
 
@@ -240,7 +531,8 @@ impl Replier<markers::Linked> {
     where
         M: prost::Message + prost::Name + std::fmt::Debug,
     {
-        self.tracing_send(message).trace(Direction::Send)
+        self.tracing_send(message, None)
+            .trace(self.kind_name(), Direction::Send)
     }
 
     /// Block until a message is received with the REQ-REP pattern.
@@ -249,9 +541,9 @@ impl Replier<markers::Linked> {
     where
         M: prost::Message + prost::Name + Default,
     {
-        let result = self.tracing_receive().map(|(m, _)| m);
+        let result = self.tracing_receive(None).map(|(m, _)| m);
         let _span = tracing::info_span!("receive").entered();
-        result.trace(Direction::Receive)
+        result.trace(self.kind_name(), Direction::Receive)
     }
 
     /// Block until a message is received with the REQ-REP pattern.
@@ -260,66 +552,308 @@ impl Replier<markers::Linked> {
     where
         M: prost::Message + prost::Name + Default,
     {
-        let result = self.tracing_receive();
+        let result = self.tracing_receive(None);
+        let _span = tracing::info_span!("receive").entered();
+        result.trace(self.kind_name(), Direction::Receive)
+    }
+
+    /// Send an already packed envelope with the REQ-REP pattern.
+    #[tracing::instrument(skip_all)]
+    pub fn send_envelope(&self, envelope: &PayloadEnvelope) -> Result<()> {
+        self.send_raw_envelope(envelope, None, || {
+            "Failed to send reply envelope".to_owned()
+        })
+        .trace(self.kind_name(), Direction::Send)
+    }
+
+    /// Block until a request is received and return it without unpacking the envelope,
+    /// together with the address of the peer.
+    pub fn receive_envelope(&self) -> Result<(PayloadEnvelope, String)> {
+        self.receive_raw_envelope(None, || "Failed to receive request envelope".to_owned())
+            .trace(self.kind_name(), Direction::Receive)
+    }
+}
+
+/// Routing frames of a request received by a [`Router`], needed to address the reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingEnvelope {
+    frames: Vec<Vec<u8>>,
+    peer_address: String,
+}
+
+impl RoutingEnvelope {
+    /// Address of the peer that sent the request.
+    pub fn peer_address(&self) -> &str {
+        &self.peer_address
+    }
+}
+
+impl Router<markers::Linked> {
+    /// Block until a request of a `REQ` socket is received.
+    ///
+    /// Unlike a [`Replier`], the router can receive further requests before replying, so
+    /// replies can be deferred.
+    // no tracing::instrument here to avoid cycles in span tree
+    pub fn receive<M>(&self) -> Result<(RoutingEnvelope, M)>
+    where
+        M: prost::Message + prost::Name + Default,
+    {
+        let result = self.receive_routing_frames().and_then(|frames| {
+            let (message, peer_address) = self.tracing_receive(None)?;
+            Ok((
+                RoutingEnvelope {
+                    frames,
+                    peer_address,
+                },
+                message,
+            ))
+        });
         let _span = tracing::info_span!("receive").entered();
-        result.trace(Direction::Receive)
+        result.trace(self.kind_name(), Direction::Receive)
+    }
+
+    /// Reads the identity frames up to the empty delimiter frame sent by `REQ` sockets.
+    fn receive_routing_frames(&self) -> Result<Vec<Vec<u8>>> {
+        let context = "Failed to receive routing frames";
+        let mut frames = Vec::new();
+        loop {
+            let frame = self.inner.recv_bytes(0).zmq_context(|| context)?;
+            self.counters.add_bytes_received(frame.len());
+            if frame.is_empty() {
+                return Ok(frames);
+            }
+            frames.push(frame);
+            if !self.inner.get_rcvmore().zmq_context(|| context)? {
+                return Err(Error::invalid_argument(context, "missing delimiter frame"));
+            }
+        }
+    }
+
+    /// Send the reply to the peer the routing envelope was received from.
+    #[tracing::instrument(skip(self, envelope))]
+    pub fn send<M>(&self, envelope: &RoutingEnvelope, message: M) -> Result<()>
+    where
+        M: prost::Message + prost::Name + std::fmt::Debug,
+    {
+        self.send_routing_frames(envelope)
+            .and_then(|()| self.tracing_send(message, None))
+            .trace(self.kind_name(), Direction::Send)
+    }
+
+    /// Send an already encoded reply, e.g. one that is shared by multiple peers.
+    #[tracing::instrument(skip_all, fields(type_url = message.type_url()))]
+    pub fn send_packed(&self, envelope: &RoutingEnvelope, message: &PackedMessage) -> Result<()> {
+        let context = || format!("Failed to send packed message {}", message.type_url());
+        self.send_routing_frames(envelope)
+            .and_then(|()| self.tracing_send_packed(message.clone(), None, context))
+            .trace(self.kind_name(), Direction::Send)
+    }
+
+    fn send_routing_frames(&self, envelope: &RoutingEnvelope) -> Result<()> {
+        let delimiter: &[u8] = &[];
+        for frame in envelope.frames.iter().map(Vec::as_slice).chain([delimiter]) {
+            self.inner
+                .send(frame, zmq::SNDMORE)
+                .zmq_context(|| "Failed to send routing frames")?;
+            self.counters.add_bytes_sent(frame.len());
+        }
+        Ok(())
+    }
+}
+
+/// A multipart message that is forwarded without decoding its payload, e.g. by a proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawMessage(pub Vec<Vec<u8>>);
+
+impl RawMessage {
+    /// The topic is the first frame of a published message.
+    pub fn topic(&self) -> &[u8] {
+        self.0.first().map_or(&[], Vec::as_slice)
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(Vec::len).sum()
+    }
+}
+
+/// (Un-)subscription of a subscriber as seen by an `XPUB` socket and forwarded to an `XSUB` socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscriptionEvent {
+    Subscribe(Vec<u8>),
+    Unsubscribe(Vec<u8>),
+}
+
+impl SubscriptionEvent {
+    fn from_frame(frame: &[u8]) -> Option<Self> {
+        match frame.split_first()? {
+            (1, topic) => Some(Self::Subscribe(topic.to_owned())),
+            (0, topic) => Some(Self::Unsubscribe(topic.to_owned())),
+            _ => None,
+        }
+    }
+
+    fn to_frame(&self) -> Vec<u8> {
+        let (flag, topic) = match self {
+            Self::Subscribe(topic) => (1, topic),
+            Self::Unsubscribe(topic) => (0, topic),
+        };
+        std::iter::once(flag).chain(topic.iter().copied()).collect()
+    }
+
+    pub fn topic(&self) -> &[u8] {
+        match self {
+            Self::Subscribe(topic) | Self::Unsubscribe(topic) => topic,
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriptionEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let action = match self {
+            Self::Subscribe(_) => "subscribe",
+            Self::Unsubscribe(_) => "unsubscribe",
+        };
+        write!(f, "{action} {}", String::from_utf8_lossy(self.topic()))
+    }
+}
+
+impl XPublisher<markers::Linked> {
+    /// Report every subscription instead of only new topics so that each joining subscriber
+    /// can be noticed.
+    pub fn set_verbose(&self, verbose: bool) -> Result<()> {
+        self.inner
+            .set_xpub_verbose(verbose)
+            .zmq_context(|| "Failed to set XPUB verbosity")
+    }
+
+    /// Publish a message as is.
+    pub fn send_raw(&self, message: &RawMessage) -> Result<()> {
+        self.inner
+            .send_multipart(&message.0, 0)
+            .zmq_context(|| "Failed to send raw message")?;
+        self.counters.add_bytes_sent(message.len());
+        self.counters.add_message_sent();
+        Ok(())
+    }
+
+    /// Block until a subscriber (un-)subscribes a topic.
+    pub fn receive_subscription(&self) -> Result<SubscriptionEvent> {
+        let context = "Failed to receive subscription";
+        let frame = self.inner.recv_bytes(0).zmq_context(|| context)?;
+        self.counters.add_bytes_received(frame.len());
+        self.counters.add_message_received();
+        SubscriptionEvent::from_frame(&frame)
+            .ok_or_else(|| Error::invalid_argument(context, "malformed subscription frame"))
     }
 }
 
-pub fn termination_is_ok(error: anyhow::Error) -> anyhow::Result<()> {
-    if error.is_zmq_termination() {
+impl XSubscriber<markers::Linked> {
+    /// Block until a message on any of the forwarded subscriptions is received.
+    pub fn receive_raw(&self) -> Result<RawMessage> {
+        let message = self
+            .inner
+            .recv_multipart(0)
+            .map(RawMessage)
+            .zmq_context(|| "Failed to receive raw message")?;
+        self.counters.add_bytes_received(message.len());
+        self.counters.add_message_received();
+        Ok(message)
+    }
+
+    /// Forward a subscription to the connected publishers.
+    pub fn send_subscription(&self, event: &SubscriptionEvent) -> Result<()> {
+        let frame = event.to_frame();
+        self.inner
+            .send(&frame, 0)
+            .zmq_context(|| format!("Failed to forward subscription {event}"))?;
+        self.counters.add_bytes_sent(frame.len());
+        self.counters.add_message_sent();
+        Ok(())
+    }
+}
+
+#[cfg(feature = "fault-injection")]
+fn fail_receive_on_injected_fault() -> Result<()> {
+    if crate::fault_injection::FaultInjection::global().fail_receive() {
+        tracing::debug!("Failing receive because of fault injection");
+        return Err(Error::Timeout {
+            context: "Injected receive fault".to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Wait until at least one of the items is ready or the timeout expires.
+///
+/// Returns the number of ready items.
+pub fn poll(items: &mut [PollItem], timeout: Option<Duration>) -> Result<usize> {
+    let ms = timeout.map_or(-1, |t| i64::try_from(t.as_millis()).unwrap_or(i64::MAX));
+    zmq::poll(items, ms)
+        .map(|ready| ready.try_into().unwrap_or_default())
+        .zmq_context(|| "Failed to poll sockets")
+}
+
+pub fn termination_is_ok<E: ErrorKindExt>(error: E) -> std::result::Result<(), E> {
+    if error.is_termination() {
         Ok(())
     } else {
         Err(error)
     }
 }
 
-pub fn timeout_is_ok(error: anyhow::Error) -> anyhow::Result<()> {
-    if error.is_zmq_timeout() {
+pub fn timeout_is_ok<E: ErrorKindExt>(error: E) -> std::result::Result<(), E> {
+    if error.is_timeout() {
         Ok(())
     } else {
         Err(error)
     }
 }
 
-pub fn invalid_state_is_ok(error: anyhow::Error) -> anyhow::Result<()> {
-    if error.is_zmq_invalid_state() {
+pub fn invalid_state_is_ok<E: ErrorKindExt>(error: E) -> std::result::Result<(), E> {
+    if error.is_invalid_state() {
         Ok(())
     } else {
         Err(error)
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 enum Direction {
     Send,
     Receive,
 }
 
+impl Direction {
+    fn operation(self) -> &'static str {
+        match self {
+            Self::Send => "send",
+            Self::Receive => "receive",
+        }
+    }
+}
+
 trait Trace {
-    fn trace(self, direction: Direction) -> Self;
+    fn trace(self, kind: &str, direction: Direction) -> Self;
 }
 
+/// Logs failures as [`LogEvent::MessageFailed`], the successful exchanges are logged by the
+/// sockets together with their size.
+///
+/// Received messages are abbreviated unless full messages are logged, see
+/// [`log_summary`][crate::log_summary].
 impl<T: std::fmt::Debug> Trace for Result<T> {
-    fn trace(self, direction: Direction) -> Self {
+    fn trace(self, kind: &str, direction: Direction) -> Self {
         match (direction, &self) {
-            (Direction::Receive, Err(e)) if e.is_zmq_termination() => {
-                tracing::info!(error=%e, "Failed to receive message: {e:#}");
-            }
-            (Direction::Receive, Err(e)) => {
-                tracing::error!(error=%e, "Failed to receive message: {e:#}");
+            (direction, Err(error)) => LogEvent::MessageFailed {
+                kind,
+                operation: direction.operation(),
+                error,
             }
+            .emit(),
             (Direction::Receive, Ok(m)) => {
-                tracing::info!(return=?m, "Received message: {m:?}");
-            }
-            (Direction::Send, Err(e)) if e.is_zmq_termination() => {
-                tracing::info!(error=%e, "Failed to send message: {e:#}");
-            }
-            (Direction::Send, Err(e)) => {
-                tracing::error!(error=%e, "Failed to send message: {e:#}");
-            }
-            (Direction::Send, Ok(_)) => {
-                tracing::info!("Successfully sent message");
+                tracing::debug!(message = %Abbreviated(m), "Received message content");
             }
+            (Direction::Send, Ok(_)) => {}
         }
         self
     }
@@ -332,78 +866,146 @@ where
     /// Receives a message envelope and its contained message of the given type.
     /// Based on the envelope information, the span id is correlated to the remote
     /// span for tracing. The second return value is the endpoint the message was received from.
-    fn tracing_receive<M>(&self) -> Result<(M, String)>
+    fn tracing_receive<M>(&self, topic: Option<&str>) -> Result<(M, String)>
     where
         M: prost::Message + prost::Name + Default,
     {
-        use crate::protobuf::PayloadEnvelope;
-        use prost::Message;
-        use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+        let context = || format!("Failed to receive {}", std::any::type_name::<M>());
+        let (envelope, ip) = self.receive_raw_envelope(topic, context)?;
+        link_remote_span(&envelope.headers);
 
-        let mut message = self
-            .inner
-            .recv_msg(0)
-            .context("Failed to receive message")?;
-        let ip = message
-            .gets("Peer-Address")
-            .ok_or_else(|| anyhow!("missing remote address"))?
-            .to_owned();
-
-        let envelope = PayloadEnvelope::decode(&*message).context("Failed to decode envelope")?;
+        let payload = envelope.unpack().map_err(|e| Error::decode(context(), e))?;
+        Ok((payload, ip))
+    }
 
-        let span = tracing::Span::current();
-        let parent_cx = opentelemetry::global::get_text_map_propagator(|propagator| {
-            propagator.extract(&TraceExtractor(&envelope.headers))
-        });
-        span.set_parent(parent_cx);
+    /// Receives a message envelope without unpacking it, together with the endpoint it was
+    /// received from.
+    fn receive_raw_envelope(
+        &self,
+        topic: Option<&str>,
+        context: impl Fn() -> String,
+    ) -> Result<(PayloadEnvelope, String)> {
+        let message = self.inner.recv_msg(0).zmq_context(&context)?;
+        self.counters.add_bytes_received(message.len());
+        self.counters.add_message_received();
+        let ip = match message.gets("Peer-Address") {
+            Some(ip) => ip.to_owned(),
+            None if self.inproc.load(Ordering::Relaxed) => INPROC_PEER_ADDRESS.to_owned(),
+            None => return Err(Error::invalid_argument(context(), "missing remote address")),
+        };
 
-        envelope
-            .payload
-            .ok_or_else(|| anyhow!("Missing payload"))?
-            .to_msg()
-            .with_context(|| format!("Failed to decode payload {}", std::any::type_name::<M>()))
-            .map(|e| (e, ip))
+        let mut envelope =
+            PayloadEnvelope::from_bytes(&message).map_err(|e| Error::decode(context(), e))?;
+        encryption::open(&mut envelope).map_err(|e| Error::encryption(context(), e))?;
+        LogEvent::MessageReceived {
+            kind: Kind::NAME,
+            topic,
+            envelope: &envelope,
+            bytes: message.len(),
+        }
+        .emit();
+        Ok((envelope, ip))
     }
 
     /// Sends a message envelope that contains the given message.
-    fn tracing_send<M>(&self, message: M) -> Result<()>
+    fn tracing_send<M>(&self, message: M, topic: Option<&str>) -> Result<()>
     where
         M: prost::Message + prost::Name + std::fmt::Debug,
     {
-        use crate::protobuf::PayloadEnvelope;
+        let context = || format!("Failed to send message {}", Abbreviated(&message));
+        let packed = PackedMessage::new(&message).map_err(|e| Error::decode(context(), e))?;
+        self.tracing_send_packed(packed, topic, context)
+    }
+
+    /// Sends a message envelope that contains the already encoded message.
+    fn tracing_send_packed(
+        &self,
+        message: PackedMessage,
+        topic: Option<&str>,
+        context: impl Fn() -> String,
+    ) -> Result<()> {
+        let envelope = PayloadEnvelope::pack_encoded(message, trace_headers());
+        self.send_raw_envelope(&envelope, topic, context)
+    }
+
+    /// Sends the message envelope as is.
+    fn send_raw_envelope(
+        &self,
+        envelope: &PayloadEnvelope,
+        topic: Option<&str>,
+        context: impl Fn() -> String,
+    ) -> Result<()> {
         use prost::Message;
-        use tracing_opentelemetry::OpenTelemetrySpanExt as _;
 
-        let span = tracing::Span::current();
-        let cx = span.context();
-        let mut headers = HashMap::default();
-        opentelemetry::global::get_text_map_propagator(|propagator| {
-            propagator.inject_context(&cx, &mut TraceInjector(&mut headers))
-        });
+        let buffer = encryption::seal(envelope)
+            .map_err(|e| Error::encryption(context(), e))?
+            .encode_to_vec();
+
+        #[cfg(feature = "fault-injection")]
+        crate::fault_injection::FaultInjection::global().delay_send();
+
+        let bytes = buffer.len();
+        self.inner.send(buffer, 0).zmq_context(context)?;
+        self.counters.add_bytes_sent(bytes);
+        self.counters.add_message_sent();
+        LogEvent::MessageSent {
+            kind: Kind::NAME,
+            topic,
+            envelope,
+            bytes,
+        }
+        .emit();
+        Ok(())
+    }
 
-        let envelope = PayloadEnvelope {
-            headers,
-            payload: Some(prost_types::Any::from_msg(&message).unwrap()),
-        };
-        let buffer = envelope.encode_to_vec();
+    fn kind_name(&self) -> &'static str {
+        Kind::NAME
+    }
 
+    /// Returns the number of messages and bytes exchanged over this socket so far.
+    pub fn statistics(&self) -> Statistics {
+        self.counters.snapshot()
+    }
+
+    /// Accept connections on an additional endpoint.
+    pub fn bind_additional(&self, endpoint: &str) -> Result<()> {
         self.inner
-            .send(buffer, 0)
-            .with_context(|| format!("Failed to send message {message:?}"))
+            .bind(endpoint)
+            .zmq_context(|| format!("Failed to bind to {endpoint}"))?;
+        self.mark_inproc(endpoint);
+        register_counters(
+            format!("{:?} bound to {endpoint}", self.kind),
+            &self.counters,
+        );
+        Ok(())
+    }
+
+    /// Returns an item to check whether a message can be received from this socket.
+    pub fn poll_item(&self) -> PollItem<'_> {
+        self.inner.as_poll_item(zmq::POLLIN)
     }
 
     pub fn get_last_endpoint(&self) -> Result<std::net::SocketAddr> {
+        let context = "Failed to get last endpoint";
         let result = self
             .inner
             .get_last_endpoint()
-            .context("Failed to get last endpoint")?
-            .map_err(|_| anyhow!("Invalid UTF-8"))?;
+            .zmq_context(|| context)?
+            .map_err(|_| Error::invalid_argument(context, "endpoint is not valid UTF-8"))?;
 
         result
             .split_once("//")
             .map_or(&*result, |r| r.1)
             .parse()
-            .context("Failed to parse endpoint")
+            .map_err(|e| Error::invalid_argument(context, format!("{e}: {result}")))
+    }
+
+    /// Discard pending outgoing messages when the socket is closed instead of blocking the
+    /// termination of the context until they are delivered.
+    pub fn discard_pending_on_close(&self) -> Result<()> {
+        self.inner
+            .set_linger(0)
+            .zmq_context(|| "Failed to set linger period")
     }
 
     pub fn set_message_exchange_timeout(
@@ -414,8 +1016,12 @@ where
         const INFINITE: TimeoutT = -1;
         let ms: TimeoutT = timeout
             .map(|t| t.as_millis().try_into())
-            .with_context(|| {
-                anyhow::anyhow!("timeout value too large, max value is {}ms", TimeoutT::MAX)
+            .transpose()
+            .map_err(|_| {
+                Error::invalid_argument(
+                    "Failed to set timeout",
+                    format!("timeout value too large, max value is {}ms", TimeoutT::MAX),
+                )
             })?
             .unwrap_or(INFINITE);
 
@@ -423,15 +1029,194 @@ where
 
         self.inner
             .set_rcvtimeo(ms)
-            .context("Failed to set receive timeout value")?;
+            .zmq_context(|| "Failed to set receive timeout value")?;
 
         self.inner
             .set_sndtimeo(ms)
-            .context("Failed to set send timeout value")
+            .zmq_context(|| "Failed to set send timeout value")
+    }
+}
+
+/// Traffic counters of a single socket.
+#[derive(Debug, Default)]
+struct Counters {
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_dropped: AtomicU64,
+    /// Next number per topic, the one to send for publishers and the expected one for
+    /// subscribers.
+    sequences: Mutex<HashMap<String, u64>>,
+}
+
+impl Counters {
+    fn add_message_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add_bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn add_message_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add_bytes_received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn next_sequence(&self, topic: &str) -> u64 {
+        let mut sequences = self.sequences.lock().expect("non-poisoned Mutex");
+        let next = sequences.entry(topic.to_owned()).or_default();
+        let sequence = *next;
+        *next += 1;
+        sequence
+    }
+
+    /// Returns the number of messages dropped before the one with the sequence number.
+    fn track_sequence(&self, topic: &str, sequence: u64) -> u64 {
+        let expected = self
+            .sequences
+            .lock()
+            .expect("non-poisoned Mutex")
+            .insert(topic.to_owned(), sequence + 1)
+            .unwrap_or(sequence);
+        // a smaller number means the publisher restarted
+        let dropped = sequence.saturating_sub(expected);
+        self.messages_dropped.fetch_add(dropped, Ordering::Relaxed);
+        dropped
+    }
+
+    fn snapshot(&self) -> Statistics {
+        Statistics {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            messages_dropped: self.messages_dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the traffic counters of a socket.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Statistics {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    /// Messages that never arrived, only detected by subscribers.
+    pub messages_dropped: u64,
+}
+
+impl std::ops::AddAssign for Statistics {
+    fn add_assign(&mut self, rhs: Self) {
+        self.messages_sent += rhs.messages_sent;
+        self.bytes_sent += rhs.bytes_sent;
+        self.messages_received += rhs.messages_received;
+        self.bytes_received += rhs.bytes_received;
+        self.messages_dropped += rhs.messages_dropped;
+    }
+}
+
+impl std::fmt::Display for Statistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "sent {} messages ({} bytes), received {} messages ({} bytes), dropped {} messages",
+            self.messages_sent,
+            self.bytes_sent,
+            self.messages_received,
+            self.bytes_received,
+            self.messages_dropped
+        )
     }
 }
 
-struct TraceInjector<'a>(&'a mut HashMap<String, String>);
+/// All linked sockets of this process. Dropped sockets are removed lazily.
+static REGISTERED_COUNTERS: Mutex<Vec<(String, Weak<Counters>)>> = Mutex::new(Vec::new());
+
+fn register_counters(description: String, counters: &Arc<Counters>) {
+    REGISTERED_COUNTERS
+        .lock()
+        .expect("non-poisoned Mutex")
+        .push((description, Arc::downgrade(counters)));
+}
+
+/// Returns the statistics of all sockets of this process that are still alive.
+pub fn all_statistics() -> Vec<(String, Statistics)> {
+    let mut registered = REGISTERED_COUNTERS.lock().expect("non-poisoned Mutex");
+    registered.retain(|(_, counters)| counters.strong_count() > 0);
+    registered
+        .iter()
+        .filter_map(|(description, counters)| {
+            Some((description.clone(), counters.upgrade()?.snapshot()))
+        })
+        .collect()
+}
+
+/// Logs the statistics of all live sockets.
+pub fn log_statistics() {
+    let mut total = Statistics::default();
+    for (socket, statistics) in all_statistics() {
+        tracing::info!(
+            %socket,
+            messages_sent = statistics.messages_sent,
+            bytes_sent = statistics.bytes_sent,
+            messages_received = statistics.messages_received,
+            bytes_received = statistics.bytes_received,
+            messages_dropped = statistics.messages_dropped,
+            "Socket statistics of {socket}: {statistics}"
+        );
+        total += statistics;
+    }
+    tracing::info!(
+        messages_sent = total.messages_sent,
+        bytes_sent = total.bytes_sent,
+        messages_received = total.messages_received,
+        bytes_received = total.bytes_received,
+        messages_dropped = total.messages_dropped,
+        "Total socket statistics: {total}"
+    );
+}
+
+/// Logs the socket statistics in the given interval until shutdown is requested.
+#[tracing::instrument(name = "Socket statistics", skip(shutdown))]
+pub fn log_statistics_periodically(interval: Duration, shutdown: &crate::ShutdownToken) {
+    let mut deadline = Instant::now() + interval;
+    while !shutdown.sleep_until_or_shutdown(deadline) {
+        log_statistics();
+        deadline += interval;
+    }
+    log_statistics();
+}
+
+/// Returns the headers that propagate the current span to the receiver of a message.
+pub(crate) fn trace_headers() -> Headers {
+    use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+
+    let cx = tracing::Span::current().context();
+    let mut headers = Headers::default();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut TraceInjector(&mut headers))
+    });
+    headers
+}
+
+/// Makes the span of the sender, as propagated in the headers, the parent of the current span.
+pub(crate) fn link_remote_span(headers: &Headers) {
+    use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+
+    let parent_cx = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&TraceExtractor(headers))
+    });
+    tracing::Span::current().set_parent(parent_cx);
+}
+
+struct TraceInjector<'a>(&'a mut Headers);
 
 impl<'a> opentelemetry::propagation::Injector for TraceInjector<'a> {
     fn set(&mut self, key: &str, value: String) {
@@ -439,7 +1224,7 @@ impl<'a> opentelemetry::propagation::Injector for TraceInjector<'a> {
     }
 }
 
-struct TraceExtractor<'a>(&'a HashMap<String, String>);
+struct TraceExtractor<'a>(&'a Headers);
 
 impl<'a> opentelemetry::propagation::Extractor for TraceExtractor<'a> {
     fn get(&self, key: &str) -> Option<&str> {
@@ -470,6 +1255,15 @@ pub mod markers {
     #[derive(Debug, Default, Clone, Copy)]
     pub struct Replier;
 
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct XPublisher;
+
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct XSubscriber;
+
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct Router;
+
     mod sealed {
         pub trait Seal {}
 
@@ -477,6 +1271,9 @@ pub mod markers {
         impl Seal for super::Publisher {}
         impl Seal for super::Requester {}
         impl Seal for super::Replier {}
+        impl Seal for super::XPublisher {}
+        impl Seal for super::XSubscriber {}
+        impl Seal for super::Router {}
     }
 
     #[doc(hidden)]
@@ -488,21 +1285,50 @@ pub mod markers {
     #[doc(hidden)]
     pub trait SocketKind: Default + std::fmt::Debug + sealed::Seal {
         const KIND: zmq::SocketType;
+        /// Name of the socket type in the logs.
+        const NAME: &'static str;
+        const LANE: super::Lane;
     }
 
     impl SocketKind for Publisher {
         const KIND: zmq::SocketType = zmq::SocketType::PUB;
+        const NAME: &'static str = "PUB";
+        const LANE: super::Lane = super::Lane::Bulk;
     }
 
     impl SocketKind for Subscriber {
         const KIND: zmq::SocketType = zmq::SocketType::SUB;
+        const NAME: &'static str = "SUB";
+        const LANE: super::Lane = super::Lane::Bulk;
     }
 
     impl SocketKind for Requester {
         const KIND: zmq::SocketType = zmq::SocketType::REQ;
+        const NAME: &'static str = "REQ";
+        const LANE: super::Lane = super::Lane::Command;
     }
 
     impl SocketKind for Replier {
         const KIND: zmq::SocketType = zmq::SocketType::REP;
+        const NAME: &'static str = "REP";
+        const LANE: super::Lane = super::Lane::Command;
+    }
+
+    impl SocketKind for XPublisher {
+        const KIND: zmq::SocketType = zmq::SocketType::XPUB;
+        const NAME: &'static str = "XPUB";
+        const LANE: super::Lane = super::Lane::Bulk;
+    }
+
+    impl SocketKind for XSubscriber {
+        const KIND: zmq::SocketType = zmq::SocketType::XSUB;
+        const NAME: &'static str = "XSUB";
+        const LANE: super::Lane = super::Lane::Bulk;
+    }
+
+    impl SocketKind for Router {
+        const KIND: zmq::SocketType = zmq::SocketType::ROUTER;
+        const NAME: &'static str = "ROUTER";
+        const LANE: super::Lane = super::Lane::Command;
     }
 }
@@ -0,0 +1,381 @@
+//! Asynchronous variant of the sockets, so that many sockets can be served by a single task
+//! instead of a thread per socket.
+//!
+//! The futures do not depend on a specific runtime. A reactor thread waits on the notification
+//! file descriptors of the sockets with waiting futures and wakes their tasks once the socket may
+//! be ready. The messages themselves are exchanged by the task, a socket is never touched by the
+//! reactor.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use super::markers::{self, Linked, SocketKind};
+use crate::{error::ZmqResultExt as _, protobuf::PayloadEnvelope, Result};
+
+#[cfg(unix)]
+type RawFd = std::os::unix::io::RawFd;
+#[cfg(windows)]
+type RawFd = std::os::windows::io::RawSocket;
+
+const WAKEUP_ENDPOINT: &str = "inproc://zmq-sockets-reactor";
+
+/// Linked socket whose messages are awaited instead of blocking the thread.
+///
+/// A timed out request leaves a [`Requester`] waiting for its reply, like the blocking one.
+pub struct Socket<Kind> {
+    inner: super::Socket<Kind, Linked>,
+}
+
+pub type Publisher = Socket<markers::Publisher>;
+pub type Subscriber = Socket<markers::Subscriber>;
+pub type Requester = Socket<markers::Requester>;
+pub type Replier = Socket<markers::Replier>;
+
+impl<Kind: std::fmt::Debug> std::fmt::Debug for Socket<Kind> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("AsyncSocket").field(&self.inner).finish()
+    }
+}
+
+impl<Kind> From<super::Socket<Kind, Linked>> for Socket<Kind> {
+    fn from(inner: super::Socket<Kind, Linked>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<Kind: SocketKind> Socket<Kind> {
+    /// Returns the blocking socket, e.g. for the parts that are not migrated yet.
+    pub fn into_inner(self) -> super::Socket<Kind, Linked> {
+        self.inner
+    }
+
+    /// Returns the number of messages and bytes exchanged over this socket so far.
+    pub fn statistics(&self) -> super::Statistics {
+        self.inner.statistics()
+    }
+
+    /// Waits until a message can be received, returns `false` if the deadline passed first.
+    async fn readable(&mut self, deadline: Option<Instant>) -> Result<bool> {
+        Readiness::new(&mut self.inner.inner, zmq::POLLIN, deadline).await
+    }
+
+    async fn writable(&mut self) -> Result<()> {
+        Readiness::new(&mut self.inner.inner, zmq::POLLOUT, None)
+            .await
+            .map(drop)
+    }
+}
+
+impl Publisher {
+    /// Publish the given message on the given topic.
+    pub async fn send<M>(&mut self, topic: impl AsRef<[u8]>, message: M) -> Result<()>
+    where
+        M: prost::Message + prost::Name + Default + std::fmt::Debug,
+    {
+        self.writable().await?;
+        self.inner.send(topic, message)
+    }
+
+    /// Publish an already packed envelope on the given topic, e.g. one that is forwarded.
+    pub async fn send_envelope(&mut self, topic: &str, envelope: PayloadEnvelope) -> Result<()> {
+        self.writable().await?;
+        self.inner.send_envelope(topic, envelope)
+    }
+}
+
+impl Subscriber {
+    /// Wait until a message is received on any of the subscribed topics.
+    pub async fn receive<M>(&mut self) -> Result<(String, M)>
+    where
+        M: prost::Message + prost::Name + Default,
+    {
+        self.readable(None).await?;
+        self.inner.receive()
+    }
+
+    /// Wait until a message is received or the timeout elapsed.
+    pub async fn receive_timeout<M>(&mut self, timeout: Duration) -> Result<Option<(String, M)>>
+    where
+        M: prost::Message + prost::Name + Default,
+    {
+        if !self.readable(Some(Instant::now() + timeout)).await? {
+            return Ok(None);
+        }
+        self.inner.receive().map(Some)
+    }
+
+    /// Wait until a message is received and return it without unpacking the envelope.
+    pub async fn receive_envelope(&mut self) -> Result<(String, PayloadEnvelope)> {
+        self.readable(None).await?;
+        self.inner.receive_envelope()
+    }
+
+    /// Subscribe to the given topic.
+    pub fn subscribe(&self, topic: impl AsRef<[u8]>) -> Result<()> {
+        self.inner.subscribe(topic)
+    }
+
+    /// Unsubscribe from the given topic.
+    pub fn unsubscribe(&self, topic: impl AsRef<[u8]>) -> Result<()> {
+        self.inner.unsubscribe(topic)
+    }
+}
+
+impl Requester {
+    /// Send a message with the REQ-REP pattern.
+    pub async fn send<M>(&mut self, message: M) -> Result<()>
+    where
+        M: prost::Message + prost::Name + std::fmt::Debug,
+    {
+        self.writable().await?;
+        self.inner.send(message)
+    }
+
+    /// Wait until a message is received with the REQ-REP pattern.
+    pub async fn receive<M>(&mut self) -> Result<M>
+    where
+        M: prost::Message + prost::Name + Default,
+    {
+        self.readable(None).await?;
+        self.inner.receive()
+    }
+
+    /// Wait until a message is received or the timeout elapsed.
+    pub async fn receive_timeout<M>(&mut self, timeout: Duration) -> Result<Option<M>>
+    where
+        M: prost::Message + prost::Name + Default,
+    {
+        if !self.readable(Some(Instant::now() + timeout)).await? {
+            return Ok(None);
+        }
+        self.inner.receive().map(Some)
+    }
+}
+
+impl Replier {
+    /// Send a message with the REQ-REP pattern.
+    pub async fn send<M>(&mut self, message: M) -> Result<()>
+    where
+        M: prost::Message + prost::Name + std::fmt::Debug,
+    {
+        self.writable().await?;
+        self.inner.send(message)
+    }
+
+    /// Wait until a message is received with the REQ-REP pattern.
+    pub async fn receive<M>(&mut self) -> Result<M>
+    where
+        M: prost::Message + prost::Name + Default,
+    {
+        self.readable(None).await?;
+        self.inner.receive()
+    }
+
+    /// Wait until a message is received with the REQ-REP pattern, together with the address of
+    /// the peer.
+    pub async fn receive_with_ip<M>(&mut self) -> Result<(M, String)>
+    where
+        M: prost::Message + prost::Name + Default,
+    {
+        self.readable(None).await?;
+        self.inner.receive_with_ip()
+    }
+
+    /// Wait until a message is received or the timeout elapsed.
+    pub async fn receive_timeout<M>(&mut self, timeout: Duration) -> Result<Option<M>>
+    where
+        M: prost::Message + prost::Name + Default,
+    {
+        if !self.readable(Some(Instant::now() + timeout)).await? {
+            return Ok(None);
+        }
+        self.inner.receive().map(Some)
+    }
+}
+
+/// Resolves once the socket signals one of the events or the deadline passed.
+///
+/// Holds the socket mutably, so the future can be sent to another thread.
+struct Readiness<'a> {
+    socket: &'a mut zmq::Socket,
+    events: zmq::PollEvents,
+    deadline: Option<Instant>,
+    id: u64,
+}
+
+impl<'a> Readiness<'a> {
+    fn new(
+        socket: &'a mut zmq::Socket,
+        events: zmq::PollEvents,
+        deadline: Option<Instant>,
+    ) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            socket,
+            events,
+            deadline,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl Future for Readiness<'_> {
+    type Output = Result<bool>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        // reading the events also resets the notification of the file descriptor, so a message
+        // arriving afterwards notifies the reactor again
+        let events = self
+            .socket
+            .get_events()
+            .zmq_context(|| "Failed to read socket events")?;
+        if events.intersects(self.events) {
+            return Poll::Ready(Ok(true));
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+        {
+            return Poll::Ready(Ok(false));
+        }
+        let fd = self
+            .socket
+            .get_fd()
+            .zmq_context(|| "Failed to get socket file descriptor")?;
+        Reactor::global().register(
+            self.id,
+            Waiting {
+                fd,
+                deadline: self.deadline,
+                waker: cx.waker().clone(),
+            },
+        );
+        Poll::Pending
+    }
+}
+
+impl Drop for Readiness<'_> {
+    fn drop(&mut self) {
+        Reactor::global().deregister(self.id);
+    }
+}
+
+struct Waiting {
+    fd: RawFd,
+    deadline: Option<Instant>,
+    waker: Waker,
+}
+
+struct Reactor {
+    waiting: Mutex<HashMap<u64, Waiting>>,
+    /// Interrupts the poll of the reactor thread when a future starts waiting.
+    wakeup: Mutex<zmq::Socket>,
+}
+
+impl Reactor {
+    fn global() -> &'static Self {
+        static REACTOR: OnceLock<Reactor> = OnceLock::new();
+        REACTOR.get_or_init(|| {
+            Self::start().expect("ZMQ reactor thread should start with inproc sockets")
+        })
+    }
+
+    fn start() -> Result<Self> {
+        let context = zmq::Context::new();
+        let receiver = context
+            .socket(zmq::PAIR)
+            .zmq_context(|| "Failed to create reactor wakeup socket")?;
+        receiver
+            .bind(WAKEUP_ENDPOINT)
+            .zmq_context(|| format!("Failed to bind to {WAKEUP_ENDPOINT}"))?;
+        let sender = context
+            .socket(zmq::PAIR)
+            .zmq_context(|| "Failed to create reactor wakeup socket")?;
+        sender
+            .connect(WAKEUP_ENDPOINT)
+            .zmq_context(|| format!("Failed to connect to {WAKEUP_ENDPOINT}"))?;
+        std::thread::Builder::new()
+            .name("zmq-reactor".to_owned())
+            .spawn(move || Self::global().run(&receiver))
+            .expect("Failed to spawn ZMQ reactor thread");
+        Ok(Self {
+            waiting: Mutex::default(),
+            wakeup: Mutex::new(sender),
+        })
+    }
+
+    fn register(&self, id: u64, waiting: Waiting) {
+        self.waiting
+            .lock()
+            .expect("non-poisoned Mutex")
+            .insert(id, waiting);
+        // a full queue already interrupts the reactor
+        let _ = self
+            .wakeup
+            .lock()
+            .expect("non-poisoned Mutex")
+            .send("", zmq::DONTWAIT);
+    }
+
+    fn deregister(&self, id: u64) {
+        self.waiting.lock().expect("non-poisoned Mutex").remove(&id);
+    }
+
+    fn run(&self, wakeup: &zmq::Socket) {
+        loop {
+            let (ids, fds, deadline): (Vec<_>, Vec<_>, _) = {
+                let waiting = self.waiting.lock().expect("non-poisoned Mutex");
+                let deadline = waiting.values().filter_map(|w| w.deadline).min();
+                let (ids, fds) = waiting.iter().map(|(id, w)| (*id, w.fd)).unzip();
+                (ids, fds, deadline)
+            };
+            let timeout = deadline.map_or(-1, |deadline| {
+                // rounded up, so the deadline has passed when the poll returns
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                remaining.as_micros().div_ceil(1000).min(i64::MAX as u128) as i64
+            });
+
+            let mut items = vec![wakeup.as_poll_item(zmq::POLLIN)];
+            items.extend(
+                fds.iter()
+                    .map(|&fd| zmq::PollItem::from_fd(fd, zmq::POLLIN)),
+            );
+            if let Err(e) = zmq::poll(&mut items, timeout) {
+                if e != zmq::Error::EINTR {
+                    tracing::warn!("Failed to poll ZMQ sockets of asynchronous tasks: {e}");
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                continue;
+            }
+            while wakeup.recv_bytes(zmq::DONTWAIT).is_ok() {}
+
+            let ready: Vec<u64> = ids
+                .into_iter()
+                .zip(&items[1..])
+                .filter(|(_, item)| item.is_readable() || item.is_error())
+                .map(|(id, _)| id)
+                .collect();
+            let now = Instant::now();
+            self.waiting
+                .lock()
+                .expect("non-poisoned Mutex")
+                .retain(|id, waiting| {
+                    let wake = ready.contains(id)
+                        || waiting.deadline.is_some_and(|deadline| deadline <= now);
+                    if wake {
+                        waiting.waker.wake_by_ref();
+                    }
+                    !wake
+                });
+        }
+    }
+}
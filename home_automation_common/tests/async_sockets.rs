@@ -0,0 +1,135 @@
+use std::{
+    future::Future,
+    sync::Arc,
+    task::{Context, Poll, Wake},
+    time::{Duration, Instant},
+};
+
+use home_automation_common::{
+    protobuf::{EntityDiscoveryCommand, ResponseCode},
+    zmq_sockets::{self, r#async},
+};
+
+/// The sockets do not need a specific runtime, parking the thread is enough.
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Arc::new(Unpark(std::thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+fn discovery_command(name: &str) -> EntityDiscoveryCommand {
+    EntityDiscoveryCommand {
+        entity_name: name.to_owned(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn request_and_reply_are_awaited() {
+    let context = zmq_sockets::Context::new();
+    let replier = zmq_sockets::Replier::new(&context)
+        .unwrap()
+        .bind("tcp://127.0.0.1:*")
+        .unwrap();
+    let endpoint = format!(
+        "tcp://127.0.0.1:{}",
+        replier.get_last_endpoint().unwrap().port()
+    );
+    let mut replier = r#async::Replier::from(replier);
+    let mut requester = r#async::Requester::from(
+        zmq_sockets::Requester::new(&context)
+            .unwrap()
+            .connect(&endpoint)
+            .unwrap(),
+    );
+
+    let server = std::thread::spawn(move || {
+        block_on(async {
+            let request: EntityDiscoveryCommand = replier.receive().await.unwrap();
+            assert_eq!(request, discovery_command("lamp"));
+            replier.send(ResponseCode::ok()).await.unwrap();
+        });
+    });
+
+    let response: ResponseCode = block_on(async {
+        requester.send(discovery_command("lamp")).await.unwrap();
+        requester.receive().await.unwrap()
+    });
+    assert_eq!(response, ResponseCode::ok());
+    server.join().unwrap();
+    std::mem::forget(context);
+}
+
+#[test]
+fn publications_are_awaited() {
+    let context = zmq_sockets::Context::new();
+    let publisher = zmq_sockets::Publisher::new(&context)
+        .unwrap()
+        .bind("tcp://127.0.0.1:*")
+        .unwrap();
+    let endpoint = format!(
+        "tcp://127.0.0.1:{}",
+        publisher.get_last_endpoint().unwrap().port()
+    );
+    let mut subscriber = r#async::Subscriber::from(
+        zmq_sockets::Subscriber::new(&context)
+            .unwrap()
+            .connect(&endpoint)
+            .unwrap(),
+    );
+    subscriber.subscribe("sen/").unwrap();
+
+    // the subscription takes a moment to reach the publisher
+    let (topic, command): (String, EntityDiscoveryCommand) = block_on(async {
+        loop {
+            publisher
+                .send("sen/lamp", discovery_command("lamp"))
+                .unwrap();
+            if let Some(received) = subscriber
+                .receive_timeout(Duration::from_millis(50))
+                .await
+                .unwrap()
+            {
+                break received;
+            }
+        }
+    });
+    assert_eq!(topic, "sen/lamp");
+    assert_eq!(command, discovery_command("lamp"));
+    std::mem::forget(context);
+}
+
+#[test]
+fn receive_times_out_without_message() {
+    let context = zmq_sockets::Context::new();
+    let mut replier = r#async::Replier::from(
+        zmq_sockets::Replier::new(&context)
+            .unwrap()
+            .bind("tcp://127.0.0.1:*")
+            .unwrap(),
+    );
+
+    let start = Instant::now();
+    let request: Option<EntityDiscoveryCommand> =
+        block_on(replier.receive_timeout(Duration::from_millis(100))).unwrap();
+
+    assert_eq!(request, None);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(100), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    std::mem::forget(context);
+}
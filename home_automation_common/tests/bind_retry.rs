@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use home_automation_common::zmq_sockets::{BindRetry, Context, Replier};
+
+#[test]
+fn falls_back_to_next_port_if_address_is_in_use() {
+    let context = Context::new();
+    let occupied = Replier::new(&context)
+        .unwrap()
+        .bind("tcp://127.0.0.1:*")
+        .unwrap();
+    let port = occupied.get_last_endpoint().unwrap().port();
+    let endpoint = format!("tcp://127.0.0.1:{port}");
+
+    let retry = BindRetry {
+        retries: 1,
+        initial_backoff: Duration::from_millis(1),
+        port_fallback: 1,
+    };
+    let (_socket, bound) = Replier::new(&context)
+        .unwrap()
+        .bind_with_retry(&endpoint, &retry)
+        .unwrap();
+
+    assert_eq!(bound, format!("tcp://127.0.0.1:{}", port + 1));
+    std::mem::forget(context);
+}
+
+#[test]
+fn fails_after_retries_without_fallback() {
+    let context = Context::new();
+    let occupied = Replier::new(&context)
+        .unwrap()
+        .bind("tcp://127.0.0.1:*")
+        .unwrap();
+    let port = occupied.get_last_endpoint().unwrap().port();
+
+    let retry = BindRetry {
+        retries: 2,
+        initial_backoff: Duration::from_millis(1),
+        port_fallback: 0,
+    };
+    let result = Replier::new(&context)
+        .unwrap()
+        .bind_with_retry(&format!("tcp://127.0.0.1:{port}"), &retry);
+
+    assert!(result.is_err());
+    std::mem::forget(context);
+}
@@ -0,0 +1,88 @@
+//! Runs in its own process, the flood of bulk data would make the drop counters of other tests
+//! nonzero.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use home_automation_common::{
+    latency::LatencyWindow,
+    protobuf::{EntityDiscoveryCommand, ResponseCode},
+    transport::{Pattern, Transport, ZmqTransport},
+    zmq_sockets,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Latency objective of the command lane while bulk data saturates the other one.
+const COMMAND_LATENCY_P99: Duration = Duration::from_millis(50);
+
+fn discovery_command(name: &str) -> EntityDiscoveryCommand {
+    EntityDiscoveryCommand {
+        entity_name: name.to_owned(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn zmq_commands_are_not_delayed_by_bulk_data() {
+    let transport = ZmqTransport::new(zmq_sockets::Context::new());
+    let subscriber = transport
+        .bind(Pattern::Subscribe, "tcp://127.0.0.1:*")
+        .unwrap();
+    let publisher = transport
+        .connect(
+            Pattern::Publish,
+            &format!("tcp://127.0.0.1:{}", subscriber.local_port().unwrap()),
+        )
+        .unwrap();
+    let server = transport.bind(Pattern::Reply, "tcp://127.0.0.1:*").unwrap();
+    let client = transport
+        .connect(
+            Pattern::Request,
+            &format!("tcp://127.0.0.1:{}", server.local_port().unwrap()),
+        )
+        .unwrap();
+    let bulk = &discovery_command(&"x".repeat(64 * 1024));
+    let done = &AtomicBool::new(false);
+
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                publisher.send_message(Some("sen/bulk"), bulk).unwrap();
+            }
+        });
+        s.spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                let _ = subscriber
+                    .receive_message::<EntityDiscoveryCommand>(Some(Duration::from_millis(10)));
+            }
+        });
+        s.spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                let request = server
+                    .receive_message::<EntityDiscoveryCommand>(Some(Duration::from_millis(10)));
+                if let Ok(Some(_)) = request {
+                    server.send_message(None, &ResponseCode::ok()).unwrap();
+                }
+            }
+        });
+
+        let mut latencies = LatencyWindow::default();
+        for _ in 0..200 {
+            let start = Instant::now();
+            let response: ResponseCode = client
+                .request(&discovery_command("lamp"), Some(TIMEOUT))
+                .unwrap();
+            assert_eq!(response, ResponseCode::ok());
+            latencies.record(start.elapsed());
+        }
+        done.store(true, Ordering::Relaxed);
+
+        let p99 = latencies.percentile(0.99).unwrap();
+        assert!(
+            p99 < COMMAND_LATENCY_P99,
+            "p99 of {p99:?} exceeds {COMMAND_LATENCY_P99:?}"
+        );
+    });
+}
@@ -0,0 +1,88 @@
+use home_automation_common::{
+    encryption::{EncryptionError, PayloadCipher, ENCRYPTION_HEADER},
+    envelope::Headers,
+    protobuf::{PayloadEnvelope, ResponseCode},
+};
+
+const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const OTHER_KEY: &str = "ff0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+fn envelope() -> PayloadEnvelope {
+    let response = ResponseCode {
+        message: "secret".to_owned(),
+        ..ResponseCode::ok()
+    };
+    let headers = Headers::from_iter([("traceparent".to_owned(), "00-abc".to_owned())]);
+    PayloadEnvelope::pack(&response, headers).unwrap()
+}
+
+#[test]
+fn encrypted_payload_roundtrips() {
+    let cipher = PayloadCipher::from_hex(KEY).unwrap();
+    let mut envelope = envelope();
+
+    cipher.encrypt(&mut envelope).unwrap();
+    assert_ne!(envelope.payload, self::envelope().payload);
+    assert!(envelope.headers.contains_key(ENCRYPTION_HEADER));
+    assert!(envelope.contains::<ResponseCode>());
+
+    cipher.decrypt(&mut envelope).unwrap();
+    assert_eq!(envelope, self::envelope());
+}
+
+#[test]
+fn every_encryption_uses_a_new_nonce() {
+    let cipher = PayloadCipher::from_hex(KEY).unwrap();
+    let mut first = envelope();
+    let mut second = envelope();
+
+    cipher.encrypt(&mut first).unwrap();
+    cipher.encrypt(&mut second).unwrap();
+
+    assert_ne!(first.payload, second.payload);
+}
+
+#[test]
+fn wrong_key_cannot_decrypt() {
+    let mut envelope = envelope();
+    PayloadCipher::from_hex(KEY)
+        .unwrap()
+        .encrypt(&mut envelope)
+        .unwrap();
+
+    let result = PayloadCipher::from_hex(OTHER_KEY)
+        .unwrap()
+        .decrypt(&mut envelope);
+
+    assert!(matches!(result, Err(EncryptionError::Decrypt)));
+}
+
+#[test]
+fn changed_payload_type_is_detected() {
+    let cipher = PayloadCipher::from_hex(KEY).unwrap();
+    let mut envelope = envelope();
+    cipher.encrypt(&mut envelope).unwrap();
+    envelope.payload.as_mut().unwrap().type_url = "type.googleapis.com/Other".to_owned();
+
+    assert!(matches!(
+        cipher.decrypt(&mut envelope),
+        Err(EncryptionError::Decrypt)
+    ));
+}
+
+#[test]
+fn unencrypted_payload_is_rejected() {
+    let cipher = PayloadCipher::from_hex(KEY).unwrap();
+
+    assert!(matches!(
+        cipher.decrypt(&mut envelope()),
+        Err(EncryptionError::Unencrypted)
+    ));
+}
+
+#[test]
+fn rejects_invalid_keys() {
+    assert!(PayloadCipher::from_hex("0011").is_err());
+    assert!(PayloadCipher::from_hex(&KEY.replace('0', "g")).is_err());
+    assert!(PayloadCipher::from_hex(&format!(" {KEY}\n")).is_ok());
+}
@@ -0,0 +1,72 @@
+//! Deterministic checks of the fault injection, run with `cargo test --features fault-injection`.
+#![cfg(feature = "fault-injection")]
+
+use home_automation_common::{
+    fault_injection::{FaultConfiguration, FaultInjection},
+    protobuf::{SystemState, SystemStateQuery},
+    zmq_sockets, ErrorKindExt as _,
+};
+
+fn drop_configuration(drop_percent: f64, seed: u64) -> FaultConfiguration {
+    FaultConfiguration {
+        drop_percent,
+        seed,
+        ..Default::default()
+    }
+}
+
+fn drops(injection: &FaultInjection, count: usize) -> Vec<bool> {
+    (0..count).map(|_| injection.drop_publication()).collect()
+}
+
+#[test]
+fn same_seed_injects_same_faults() {
+    let first = drops(&FaultInjection::new(drop_configuration(30.0, 42)), 1000);
+    let second = drops(&FaultInjection::new(drop_configuration(30.0, 42)), 1000);
+    assert_eq!(first, second);
+
+    let dropped = first.iter().filter(|&&dropped| dropped).count();
+    assert!((200..400).contains(&dropped), "dropped {dropped} of 1000");
+}
+
+#[test]
+fn different_seed_injects_different_faults() {
+    let first = drops(&FaultInjection::new(drop_configuration(50.0, 1)), 100);
+    let second = drops(&FaultInjection::new(drop_configuration(50.0, 2)), 100);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn probability_bounds_are_respected() {
+    let never = FaultInjection::new(drop_configuration(0.0, 7));
+    assert!(drops(&never, 1000).iter().all(|&dropped| !dropped));
+
+    let always = FaultInjection::new(drop_configuration(100.0, 7));
+    assert!(drops(&always, 1000).iter().all(|&dropped| dropped));
+    assert!(!always.fail_receive());
+}
+
+/// The only test that uses sockets because the faults of the sockets are installed process-wide.
+#[test]
+fn injected_receive_fault_is_reported_as_timeout() {
+    FaultInjection::install(FaultConfiguration {
+        receive_error_percent: 100.0,
+        ..Default::default()
+    })
+    .expect("faults were configured before");
+
+    let context = zmq_sockets::Context::new();
+    let endpoint = "inproc://fault-injection";
+    let _replier = zmq_sockets::Replier::new(&context)
+        .and_then(|replier| replier.bind(endpoint))
+        .expect("failed to bind replier");
+    let requester = zmq_sockets::Requester::new(&context)
+        .and_then(|requester| requester.connect(endpoint))
+        .expect("failed to connect requester");
+
+    requester
+        .send(SystemStateQuery::default())
+        .expect("failed to send request");
+    let error = requester.receive::<SystemState>().unwrap_err();
+    assert!(error.is_timeout(), "unexpected error {error:?}");
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use home_automation_common::{protobuf::SensorConfiguration, InvalidFrequency, UpdateFrequency};
+
+#[test]
+fn rejects_non_positive_and_non_finite_frequencies() {
+    for hz in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+        assert!(UpdateFrequency::from_hz(hz).is_err(), "{hz} was accepted");
+    }
+    assert!(UpdateFrequency::from_period(Duration::ZERO).is_err());
+}
+
+#[test]
+fn rejects_frequencies_without_representable_period() {
+    for hz in [1e-39, f32::MIN_POSITIVE, 1e-30, 1e10, f32::MAX] {
+        assert!(UpdateFrequency::from_hz(hz).is_err(), "{hz} was accepted");
+    }
+    let slowest = UpdateFrequency::from_hz(1e-15).unwrap();
+    assert!(slowest.period() > Duration::from_secs(1_000_000));
+    let fastest = UpdateFrequency::from_hz(1e8).unwrap();
+    assert!(fastest.period() >= Duration::from_nanos(1));
+    assert!(fastest.period() < Duration::from_micros(1));
+}
+
+#[test]
+fn converts_between_hz_and_period() {
+    let frequency = UpdateFrequency::from_hz(4.0).unwrap();
+    assert_eq!(frequency.period(), Duration::from_millis(250));
+    let frequency = UpdateFrequency::from_period(Duration::from_millis(500)).unwrap();
+    assert_eq!(frequency.hz(), 2.0);
+}
+
+#[test]
+fn parses_with_and_without_unit() {
+    for text in ["2.5", " 2.5 Hz", "2.5hz"] {
+        let frequency: UpdateFrequency = text.parse().unwrap();
+        assert_eq!(frequency.hz(), 2.5, "{text}");
+    }
+    assert_eq!(
+        "fast".parse::<UpdateFrequency>(),
+        Err(InvalidFrequency::Parse("fast".to_owned()))
+    );
+    assert_eq!(
+        "-3".parse::<UpdateFrequency>(),
+        Err(InvalidFrequency::OutOfRange(-3.0))
+    );
+}
+
+#[test]
+fn display_round_trips_through_from_str() {
+    let frequency = UpdateFrequency::from_hz(0.5).unwrap();
+    assert_eq!(frequency.to_string(), "0.5 Hz");
+    assert_eq!(frequency.to_string().parse(), Ok(frequency));
+}
+
+#[test]
+fn converts_to_and_from_sensor_configuration() {
+    let frequency = UpdateFrequency::from_hz(1.5).unwrap();
+    let configuration = SensorConfiguration::from(frequency);
+    assert_eq!(configuration.update_frequency_hz, 1.5);
+    assert_eq!(UpdateFrequency::try_from(&configuration), Ok(frequency));
+    let invalid = SensorConfiguration {
+        update_frequency_hz: 0.0,
+    };
+    assert!(UpdateFrequency::try_from(&invalid).is_err());
+}
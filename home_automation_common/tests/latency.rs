@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use home_automation_common::latency::{
+    correct_timestamp_ms, estimate_clock_offset_ms, unix_time_ms, LatencyWindow,
+};
+
+#[test]
+fn computes_nearest_rank_percentiles() {
+    let mut window = LatencyWindow::default();
+    for ms in (1..=100).rev() {
+        window.record(Duration::from_millis(ms));
+    }
+    assert_eq!(window.percentile(0.5), Some(Duration::from_millis(50)));
+    assert_eq!(window.percentile(0.99), Some(Duration::from_millis(99)));
+    assert_eq!(window.percentile(1.0), Some(Duration::from_millis(100)));
+
+    let summary = window.summary();
+    assert_eq!(summary.samples, 100);
+    assert_eq!(summary.p90_ms, 90.0);
+}
+
+#[test]
+fn ignores_unknown_timestamps_and_clamps_future_ones() {
+    let mut window = LatencyWindow::default();
+    assert_eq!(window.percentile(0.5), None);
+    window.record_since(0);
+    assert_eq!(window.summary().samples, 0);
+
+    window.record_since(unix_time_ms() + 60_000);
+    assert_eq!(window.percentile(0.5), Some(Duration::ZERO));
+}
+
+#[test]
+fn keeps_only_the_most_recent_samples() {
+    let mut window = LatencyWindow::default();
+    for _ in 0..2000 {
+        window.record(Duration::from_secs(1));
+    }
+    for _ in 0..1000 {
+        window.record(Duration::from_millis(1));
+    }
+    assert_eq!(window.summary().samples, 1000);
+    assert_eq!(window.percentile(1.0), Some(Duration::from_millis(1)));
+}
+
+#[test]
+fn estimates_clock_offset_from_round_trip() {
+    // local clock is 5 s ahead, each direction takes 10 ms
+    assert_eq!(estimate_clock_offset_ms(105_000, 100_010, 105_020), 5000);
+    // remote clock is 2 s ahead
+    assert_eq!(estimate_clock_offset_ms(100_000, 102_050, 100_100), -2000);
+}
+
+#[test]
+fn corrects_timestamps_by_clock_offset() {
+    assert_eq!(correct_timestamp_ms(105_000, 5000), 100_000);
+    assert_eq!(correct_timestamp_ms(100_000, -2000), 102_000);
+    assert_eq!(correct_timestamp_ms(0, 5000), 0);
+    assert_eq!(correct_timestamp_ms(1000, 5000), 1);
+}
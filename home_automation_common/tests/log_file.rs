@@ -0,0 +1,65 @@
+use std::{io::Write as _, path::PathBuf};
+
+use home_automation_common::log_file::{LogFileConfiguration, RotatingLogFile};
+
+fn empty_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    directory
+}
+
+fn file_names(configuration: &LogFileConfiguration) -> Vec<String> {
+    let mut names: Vec<_> = std::fs::read_dir(&configuration.directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn rotates_full_file_and_keeps_max_files() {
+    let configuration = LogFileConfiguration {
+        directory: empty_directory("rotating-log-file"),
+        template: "test.log".to_owned(),
+        max_files: 3,
+        max_size: Some(4),
+    };
+    let mut file = RotatingLogFile::create(&configuration).unwrap();
+    for line in ["aaaa", "bbbb", "cccc", "dddd"] {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+    file.flush().unwrap();
+
+    assert_eq!(
+        file_names(&configuration),
+        ["test.log", "test.log.1", "test.log.2"]
+    );
+    let read = |name: &str| std::fs::read_to_string(configuration.directory.join(name)).unwrap();
+    assert_eq!(read("test.log"), "dddd");
+    assert_eq!(read("test.log.1"), "cccc");
+    assert_eq!(read("test.log.2"), "bbbb");
+}
+
+#[test]
+fn removes_oldest_files_of_the_same_scheme() {
+    let configuration = LogFileConfiguration {
+        directory: empty_directory("log-file-cleanup"),
+        template: "run-{time}.log".to_owned(),
+        max_files: 2,
+        max_size: None,
+    };
+    std::fs::create_dir_all(&configuration.directory).unwrap();
+    for name in ["run-1.log", "run-2.log.1", "run-3.log", "other.log"] {
+        std::fs::write(configuration.directory.join(name), name).unwrap();
+        // modification times must differ for a deterministic order
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    configuration.remove_old_files();
+
+    assert_eq!(
+        file_names(&configuration),
+        ["other.log", "run-2.log.1", "run-3.log"]
+    );
+}
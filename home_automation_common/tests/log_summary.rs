@@ -0,0 +1,44 @@
+use std::collections::{BTreeMap, HashMap};
+
+use home_automation_common::{
+    log_summary::{Abbreviated, Summarize as _, MAX_DEBUG_LEN},
+    protobuf::{ActuatorState, SensorMeasurement, SystemState},
+    EntityState,
+};
+
+// HOME_AUTOMATION_LOG_FULL_MESSAGES is not set in the tests
+
+#[test]
+fn summarizes_system_state_with_counts() {
+    let state = SystemState {
+        sensors: (0..8)
+            .map(|i| (format!("sen_{i}"), SensorMeasurement::default()))
+            .collect(),
+        actuators: BTreeMap::from([("act_a".to_owned(), ActuatorState::light(5.0))]),
+        new_sensors: vec!["sen_new".to_owned()],
+        generation: 3,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        state.summary().to_string(),
+        "SystemState generation 3: 8 sensors (sen_0, sen_1, sen_2, sen_3, sen_4, … 3 more), \
+         1 actuators (act_a), 1 new (sen_new)"
+    );
+}
+
+#[test]
+fn summarizes_empty_client_state() {
+    let state: HashMap<String, EntityState> = HashMap::new();
+    assert_eq!(state.summary().to_string(), "0 sensors, 0 actuators");
+}
+
+#[test]
+fn abbreviates_long_debug_output() {
+    let long = "x".repeat(2 * MAX_DEBUG_LEN);
+    let abbreviated = Abbreviated(&long).to_string();
+
+    assert!(abbreviated.starts_with(&format!("\"{}", "x".repeat(MAX_DEBUG_LEN - 1))));
+    assert!(abbreviated.ends_with("… (302 bytes omitted)"));
+    assert_eq!(Abbreviated(&"short").to_string(), "\"short\"");
+}
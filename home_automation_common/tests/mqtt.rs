@@ -0,0 +1,195 @@
+use std::{
+    io::{Read as _, Write as _},
+    net::TcpListener,
+    time::Duration,
+};
+
+use home_automation_common::mqtt::{
+    Connect, MqttClient, MqttError, Packet, PacketDecoder, Publish,
+};
+
+fn publish(topic: &str, payload: &[u8], retain: bool) -> Publish {
+    Publish {
+        topic: topic.to_owned(),
+        payload: payload.to_vec(),
+        retain,
+    }
+}
+
+#[test]
+fn decodes_packets_split_across_reads() {
+    let packets = [
+        Packet::Connect(Connect {
+            client_id: "bridge".to_owned(),
+            username: Some("user".to_owned()),
+            password: Some("secret".to_owned()),
+            keep_alive_secs: 30,
+        }),
+        Packet::ConnAck {
+            session_present: false,
+            return_code: 0,
+        },
+        // needs two bytes for the remaining length
+        Packet::Publish(publish("home/measurement/sen_a", &[7; 300], true)),
+        Packet::Subscribe {
+            packet_id: 1,
+            filters: vec!["home/command/+".to_owned()],
+        },
+        Packet::SubAck {
+            packet_id: 1,
+            return_codes: vec![0],
+        },
+        Packet::PingReq,
+        Packet::PingResp,
+        Packet::Disconnect,
+    ];
+    let bytes: Vec<u8> = packets
+        .iter()
+        .flat_map(|packet| packet.encode().unwrap())
+        .collect();
+
+    let mut decoder = PacketDecoder::default();
+    let mut decoded = Vec::new();
+    for chunk in bytes.chunks(3) {
+        decoder.push(chunk);
+        while let Some(packet) = decoder.next_packet() {
+            decoded.push(packet.unwrap());
+        }
+    }
+    assert_eq!(decoded, packets);
+}
+
+#[test]
+fn encodes_packets_as_specified() {
+    assert_eq!(Packet::PingReq.encode().unwrap(), [0xC0, 0x00]);
+    assert_eq!(
+        Packet::Publish(publish("a/b", b"1", true))
+            .encode()
+            .unwrap(),
+        [0x31, 0x06, 0x00, 0x03, b'a', b'/', b'b', b'1']
+    );
+    assert_eq!(
+        Packet::Subscribe {
+            packet_id: 10,
+            filters: vec!["a".to_owned()],
+        }
+        .encode()
+        .unwrap(),
+        [0x82, 0x06, 0x00, 0x0A, 0x00, 0x01, b'a', 0x00]
+    );
+}
+
+#[test]
+fn rejects_malformed_packets() {
+    let mut decoder = PacketDecoder::default();
+    // publication whose topic is longer than the packet
+    decoder.push(&[0x30, 0x02, 0x00, 0x05]);
+    decoder.push(&Packet::PingResp.encode().unwrap());
+
+    assert!(matches!(
+        decoder.next_packet(),
+        Some(Err(MqttError::Malformed(_)))
+    ));
+    assert_eq!(decoder.next_packet(), Some(Ok(Packet::PingResp)));
+    assert_eq!(decoder.next_packet(), None);
+
+    let topic = "t".repeat(usize::from(u16::MAX) + 1);
+    assert_eq!(
+        Packet::Publish(publish(&topic, b"", false)).encode(),
+        Err(MqttError::TooLong(topic.len()))
+    );
+}
+
+#[test]
+fn client_publishes_and_receives() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let broker = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut decoder = PacketDecoder::default();
+        let mut received = Vec::new();
+        let mut buffer = [0; 256];
+        while !received.contains(&Packet::Disconnect) {
+            let read = stream.read(&mut buffer).unwrap();
+            assert_ne!(read, 0, "client closed the connection");
+            decoder.push(&buffer[..read]);
+            while let Some(packet) = decoder.next_packet() {
+                let packet = packet.unwrap();
+                let answers = match &packet {
+                    Packet::Connect(_) => vec![Packet::ConnAck {
+                        session_present: false,
+                        return_code: 0,
+                    }],
+                    Packet::Subscribe { packet_id, .. } => vec![
+                        Packet::SubAck {
+                            packet_id: *packet_id,
+                            return_codes: vec![0],
+                        },
+                        Packet::Publish(publish("home/command/act_a", b"on", false)),
+                    ],
+                    _ => Vec::new(),
+                };
+                for answer in answers {
+                    stream.write_all(&answer.encode().unwrap()).unwrap();
+                }
+                received.push(packet);
+            }
+        }
+        received
+    });
+
+    let mut client = MqttClient::connect(
+        &address,
+        Connect {
+            client_id: "bridge".to_owned(),
+            keep_alive_secs: 30,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    client.subscribe("home/command/+").unwrap();
+    let command = client.receive(Duration::from_secs(5)).unwrap();
+    client
+        .publish(publish("home/measurement/sen_a", b"21.5", true))
+        .unwrap();
+    assert_eq!(client.receive(Duration::from_millis(50)).unwrap(), None);
+    client.disconnect().unwrap();
+
+    assert_eq!(command, Some(publish("home/command/act_a", b"on", false)));
+    let received = broker.join().unwrap();
+    assert_eq!(
+        received[1..],
+        [
+            Packet::Subscribe {
+                packet_id: 1,
+                filters: vec!["home/command/+".to_owned()],
+            },
+            Packet::Publish(publish("home/measurement/sen_a", b"21.5", true)),
+            Packet::Disconnect,
+        ]
+    );
+}
+
+#[test]
+fn client_reports_refused_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let broker = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        // read the connection request, so closing the stream does not reset it
+        assert_ne!(stream.read(&mut [0; 256]).unwrap(), 0);
+        let refusal = Packet::ConnAck {
+            session_present: false,
+            return_code: 4,
+        };
+        stream.write_all(&refusal.encode().unwrap()).unwrap();
+    });
+
+    let error = MqttClient::connect(&address, Connect::default()).unwrap_err();
+    broker.join().unwrap();
+
+    assert!(
+        format!("{error:#}").contains("bad user name or password"),
+        "{error:#}"
+    );
+}
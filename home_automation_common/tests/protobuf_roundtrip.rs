@@ -0,0 +1,606 @@
+//! Conformance tests for the wire format.
+//!
+//! Every protobuf message is round-tripped through plain encode/decode and through the
+//! [`PayloadEnvelope`] path that the sockets use. The expected type URLs and the golden byte
+//! sequences are spelled out explicitly so that proto edits or prost upgrades which change the
+//! wire format make these tests fail.
+
+use std::collections::BTreeMap;
+
+use home_automation_common::{envelope::EnvelopeError, protobuf::*, UpdateFrequency};
+use prost::{Message, Name};
+
+fn hz(hz: f32) -> UpdateFrequency {
+    UpdateFrequency::from_hz(hz).expect("valid frequency")
+}
+
+fn assert_round_trip<M>(message: M, type_url: &str)
+where
+    M: Message + Name + Default + PartialEq + std::fmt::Debug,
+{
+    let decoded = M::decode(&*message.encode_to_vec()).expect("failed to decode message");
+    assert_eq!(decoded, message);
+
+    assert_eq!(M::type_url(), type_url);
+    let any = prost_types::Any::from_msg(&message).expect("failed to pack message");
+    assert_eq!(any.type_url, type_url);
+
+    let envelope = PayloadEnvelope {
+        headers: BTreeMap::from([(
+            "traceparent".to_owned(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_owned(),
+        )]),
+        payload: Some(any),
+    };
+    let decoded_envelope =
+        PayloadEnvelope::decode(&*envelope.encode_to_vec()).expect("failed to decode envelope");
+    assert_eq!(decoded_envelope, envelope);
+
+    let payload: M = decoded_envelope
+        .payload
+        .expect("missing payload")
+        .to_msg()
+        .expect("failed to unpack payload");
+    assert_eq!(payload, message);
+
+    let packed = PayloadEnvelope::pack(&message, BTreeMap::new()).expect("failed to pack message");
+    let unpacked: M = PayloadEnvelope::from_bytes(&packed.encode_to_vec())
+        .expect("failed to decode envelope")
+        .unpack()
+        .expect("failed to unpack payload");
+    assert_eq!(unpacked, message);
+}
+
+macro_rules! round_trip_tests {
+    ($($test:ident: $type_url:literal => $message:expr;)+) => {
+        $(
+            #[test]
+            fn $test() {
+                assert_round_trip($message, $type_url);
+            }
+        )+
+    };
+}
+
+fn temperature() -> SensorMeasurement {
+    SensorMeasurement {
+        value: Some(sensor_measurement::Value::Temperature(
+            TemperatureSensorMeasurement { temperature: 21.5 },
+        )),
+        unit: "°C".to_owned(),
+    }
+}
+
+fn humidity() -> SensorMeasurement {
+    SensorMeasurement {
+        value: Some(sensor_measurement::Value::Humidity(
+            HumiditySensorMeasurement { humidity: 45.0 },
+        )),
+        unit: "%".to_owned(),
+    }
+}
+
+fn light_description() -> EntityDescription {
+    EntityDescription {
+        kind: "brightness".to_owned(),
+        unit: "%".to_owned(),
+        min: 0.0,
+        max: 100.0,
+        capabilities: vec![
+            home_automation_common::capabilities::UPDATE_FREQUENCY.to_owned(),
+            home_automation_common::capabilities::LIGHT.to_owned(),
+        ],
+    }
+}
+
+fn discovery(command: entity_discovery_command::Command) -> EntityDiscoveryCommand {
+    EntityDiscoveryCommand {
+        command: Some(command),
+        entity_type: entity_discovery_command::EntityType::Actuator.into(),
+        entity_name: "act_test".to_owned(),
+        session_id: 0,
+        auth_token: String::new(),
+    }
+}
+
+round_trip_tests! {
+    registration: "/wipmate.EntityDiscoveryCommand.Registration" =>
+        entity_discovery_command::Registration {
+            port: 4242,
+            tags: vec!["outdoor".to_owned()],
+            max_batch_size: 0,
+            heartbeat_interval_ms: 0,
+        };
+    registration_with_batching: "/wipmate.EntityDiscoveryCommand.Registration" =>
+        entity_discovery_command::Registration {
+            port: 1,
+            tags: Vec::new(),
+            max_batch_size: 20,
+            heartbeat_interval_ms: 0,
+        };
+    registration_with_heartbeat_interval: "/wipmate.EntityDiscoveryCommand.Registration" =>
+        entity_discovery_command::Registration {
+            port: 1,
+            tags: Vec::new(),
+            max_batch_size: 0,
+            heartbeat_interval_ms: 2500,
+        };
+    discovery_register: "/wipmate.EntityDiscoveryCommand" =>
+        discovery(entity_discovery_command::Command::Register(
+            entity_discovery_command::Registration {
+                port: 4242,
+                tags: Vec::new(),
+                max_batch_size: 0,
+                heartbeat_interval_ms: 0,
+            },
+        ));
+    discovery_unregister: "/wipmate.EntityDiscoveryCommand" =>
+        discovery(entity_discovery_command::Command::Unregister(()));
+    discovery_with_session: "/wipmate.EntityDiscoveryCommand" =>
+        EntityDiscoveryCommand {
+            session_id: u64::MAX,
+            ..discovery(entity_discovery_command::Command::Unregister(()))
+        };
+    heartbeat: "/wipmate.EntityDiscoveryCommand.Heartbeat" =>
+        entity_discovery_command::Heartbeat { sent_at_ms: 1_700_000_000_000, clock_offset_ms: -250 };
+    discovery_with_auth_token: "/wipmate.EntityDiscoveryCommand" =>
+        EntityDiscoveryCommand {
+            auth_token: "secret".to_owned(),
+            ..discovery(entity_discovery_command::Command::Unregister(()))
+        };
+    discovery_heartbeat: "/wipmate.EntityDiscoveryCommand" =>
+        discovery(entity_discovery_command::Command::Heartbeat(
+            entity_discovery_command::Heartbeat { sent_at_ms: 5, clock_offset_ms: 1200 },
+        ));
+    temperature_measurement: "/wipmate.SensorMeasurement" => temperature();
+    humidity_measurement: "/wipmate.SensorMeasurement" => humidity();
+    temperature_value: "/wipmate.TemperatureSensorMeasurement" =>
+        TemperatureSensorMeasurement { temperature: -3.25 };
+    humidity_value: "/wipmate.HumiditySensorMeasurement" =>
+        HumiditySensorMeasurement { humidity: 99.0 };
+    sensor_configuration: "/wipmate.SensorConfiguration" =>
+        SensorConfiguration { update_frequency_hz: 2.5 };
+    publish_measurement: "/wipmate.PublishData" => PublishData::from(temperature());
+    publish_actuator_state: "/wipmate.PublishData" => PublishData::from(ActuatorState::light(80.0));
+    publish_with_timestamp: "/wipmate.PublishData" => PublishData {
+        published_at_ms: 1_700_000_000_123,
+        ..PublishData::from(humidity())
+    };
+    entity_description: "/wipmate.EntityDescription" => light_description();
+    publish_description: "/wipmate.PublishData" => PublishData::from(light_description());
+    publish_batch: "/wipmate.PublishData" => PublishData {
+        published_at_ms: 1_700_000_001_000,
+        ..PublishData::from(MeasurementBatch {
+            measurements: vec![
+                TimestampedMeasurement {
+                    measurement: Some(temperature()),
+                    measured_at_ms: 1_700_000_000_900,
+                },
+                TimestampedMeasurement {
+                    measurement: Some(humidity()),
+                    measured_at_ms: 1_700_000_000_950,
+                },
+            ],
+        })
+    };
+    response_ok: "/wipmate.ResponseCode" => ResponseCode::ok();
+    response_error: "/wipmate.ResponseCode" =>
+        ResponseCode::from(Err::<(), _>("Entity limit reached"));
+    response_heartbeat: "/wipmate.ResponseCode" => ResponseCode {
+        controller_time_ms: 1_700_000_000_000,
+        ..ResponseCode::ok()
+    };
+    response_batch_size: "/wipmate.ResponseCode" => ResponseCode {
+        batch_size: 20,
+        ..ResponseCode::ok()
+    };
+    response_heartbeat_interval: "/wipmate.ResponseCode" => ResponseCode {
+        heartbeat_interval_ms: 60_000,
+        ..ResponseCode::ok()
+    };
+    light_state: "/wipmate.ActuatorState" => ActuatorState::light(12.5);
+    air_conditioning_state: "/wipmate.ActuatorState" => ActuatorState::air_conditioning(true);
+    light_value: "/wipmate.LightActuatorState" => LightActuatorState { brightness: 100.0 };
+    air_conditioning_value: "/wipmate.AirConditioningActuatorState" =>
+        AirConditioningActuatorState { on: true };
+    system_state_query: "/wipmate.SystemStateQuery" => SystemStateQuery {
+        tag: "outdoor".to_owned(),
+        wait_timeout_ms: 500,
+        known_generation: 3,
+    };
+    entity_tags: "/wipmate.EntityTags" => EntityTags {
+        entity_name: "sen_a".to_owned(),
+        tags: vec!["outdoor".to_owned(), "garden".to_owned()],
+    };
+    tagged_command: "/wipmate.TaggedCommand" => TaggedCommand {
+        tag: "outdoor".to_owned(),
+        command: Some(NamedEntityState::frequency("", hz(2.0))),
+    };
+    client_tagged_query: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::tagged_system_state_query("outdoor");
+    client_wait_for_change: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::wait_for_change(42, std::time::Duration::from_secs(5));
+    client_set_tags: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::set_tags("act_c", vec!["kitchen".to_owned()]);
+    client_tagged_action: "/wipmate.ClientApiCommand" => ClientApiCommand::tagged_action(
+        "outdoor",
+        NamedEntityState::actuator("", ActuatorState::light(10.0)),
+    );
+    system_state: "/wipmate.SystemState" => SystemState {
+        sensors: BTreeMap::from([
+            ("sen_a".to_owned(), temperature()),
+            ("sen_b".to_owned(), humidity()),
+        ]),
+        actuators: BTreeMap::from([("act_c".to_owned(), ActuatorState::light(1.0))]),
+        new_sensors: vec!["sen_d".to_owned()],
+        new_actuators: vec!["act_e".to_owned()],
+        generation: 7,
+        published_at_ms: BTreeMap::from([("sen_a".to_owned(), 1_700_000_000_000)]),
+        heartbeat_age_seconds: BTreeMap::from([("act_c".to_owned(), 2.5)]),
+        metadata: BTreeMap::from([(
+            "act_c".to_owned(),
+            EntityMetadata {
+                rooms: vec!["kitchen".to_owned()],
+                tags: vec!["demo".to_owned()],
+                back_channel_healthy: true,
+                heartbeat_interval_ms: 10_000,
+                description: Some(light_description()),
+            },
+        )]),
+    };
+    named_actuator_state: "/wipmate.NamedEntityState" =>
+        NamedEntityState::actuator("act_c", ActuatorState::air_conditioning(false));
+    named_frequency: "/wipmate.NamedEntityState" => NamedEntityState::frequency("sen_a", hz(0.5));
+    configuration_export: "/wipmate.ConfigurationExport" => ConfigurationExport {
+        admin_token: "s3cret".to_owned(),
+    };
+    configuration_import: "/wipmate.ConfigurationImport" => ConfigurationImport {
+        configuration_json: r#"{ "rooms": {} }"#.to_owned(),
+        admin_token: "s3cret".to_owned(),
+    };
+    configuration_document: "/wipmate.ConfigurationDocument" => ConfigurationDocument {
+        configuration_json: "{}".to_owned(),
+    };
+    planned_command: "/wipmate.PlannedCommand" => PlannedCommand {
+        origin: "scene evening".to_owned(),
+        command: Some(NamedEntityState::actuator("act_c", ActuatorState::light(30.0))),
+    };
+    dry_run_report: "/wipmate.DryRunReport" => DryRunReport {
+        commands: vec![PlannedCommand {
+            origin: "rule hot".to_owned(),
+            command: Some(NamedEntityState::actuator("act_ac", ActuatorState::air_conditioning(true))),
+        }],
+        error: "Unknown scene".to_owned(),
+    };
+    client_query: "/wipmate.ClientApiCommand" => ClientApiCommand::system_state_query();
+    client_action: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::named_entity_state(NamedEntityState::frequency("sen_a", hz(1.0)));
+    client_export_configuration: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::export_configuration("s3cret");
+    client_import_configuration: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::import_configuration("{}", "s3cret");
+    client_dry_run_scene: "/wipmate.ClientApiCommand" => ClientApiCommand::dry_run_scene("evening");
+    client_dry_run_rules: "/wipmate.ClientApiCommand" => ClientApiCommand::dry_run_rules();
+    dry_run_target: "/wipmate.AutomationDryRun" => AutomationDryRun {
+        target: Some(automation_dry_run::Target::Scene("evening".to_owned())),
+    };
+    named_ping: "/wipmate.NamedEntityState" => NamedEntityState::ping("act_c");
+    named_lifecycle: "/wipmate.NamedEntityState" =>
+        NamedEntityState::lifecycle("sen_a", lifecycle_command::Action::Restart);
+    lifecycle_command: "/wipmate.LifecycleCommand" => LifecycleCommand {
+        action: lifecycle_command::Action::Shutdown.into(),
+    };
+    admin_query: "/wipmate.AdminQuery" => AdminQuery {};
+    task_health: "/wipmate.TaskHealth" => TaskHealth {
+        name: "Subscriber".to_owned(),
+        status: task_health::Status::Failed.into(),
+        error: "Address in use".to_owned(),
+    };
+    entity_health: "/wipmate.EntityHealth" => EntityHealth {
+        name: "sen_a".to_owned(),
+        entity_type: entity_discovery_command::EntityType::Sensor.into(),
+        heartbeat_age_seconds: 4.5,
+        back_channel_healthy: true,
+        flapping: true,
+        ingest_latency: Some(Latency {
+            samples: 10,
+            p50_ms: 1.5,
+            p90_ms: 4.0,
+            p99_ms: 12.0,
+        }),
+        clock_offset_ms: 1500,
+        suspected: true,
+    };
+    latency: "/wipmate.Latency" => Latency {
+        samples: 1000,
+        p50_ms: 0.5,
+        p90_ms: 2.0,
+        p99_ms: 30.0,
+    };
+    error_report: "/wipmate.ErrorReport" => ErrorReport {
+        message: "Unknown entity".to_owned(),
+        age_seconds: 12.0,
+    };
+    admin_state: "/wipmate.AdminState" => AdminState {
+        tasks: vec![TaskHealth {
+            name: "Timeout".to_owned(),
+            status: task_health::Status::Running.into(),
+            error: String::new(),
+        }],
+        entities: vec![EntityHealth {
+            name: "act_c".to_owned(),
+            entity_type: entity_discovery_command::EntityType::Actuator.into(),
+            heartbeat_age_seconds: 0.5,
+            back_channel_healthy: false,
+            flapping: false,
+            ingest_latency: None,
+            clock_offset_ms: -3000,
+            suspected: false,
+        }],
+        recent_errors: vec![ErrorReport {
+            message: "Heartbeat from unknown entity".to_owned(),
+            age_seconds: 1.0,
+        }],
+        rejected_requests: 3,
+        ingest_latency: Some(Latency::default()),
+        payload_mismatches: 2,
+    };
+    admin_command: "/wipmate.AdminCommand" => AdminCommand {
+        token: "secret".to_owned(),
+        command: Some(admin_command::Command::Ping("act_c".to_owned())),
+    };
+    client_admin_query: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::admin("", admin_command::Command::Query(AdminQuery {}));
+    client_admin_unregister: "/wipmate.ClientApiCommand" => ClientApiCommand::admin(
+        "secret",
+        admin_command::Command::ForceUnregister("sen_a".to_owned()),
+    );
+    client_admin_restart: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::admin("secret", admin_command::Command::Restart("sen_a".to_owned()));
+    client_admin_performance: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::admin("", admin_command::Command::Performance(PerformanceQuery {}));
+    performance_counters: "/wipmate.PerformanceCounters" => PerformanceCounters {
+        uptime_ms: 61_500,
+        sockets: vec![SocketStatistics {
+            socket: "SUB tcp://*:5556".to_owned(),
+            messages_sent: 0,
+            bytes_sent: 0,
+            messages_received: 1200,
+            bytes_received: 96_000,
+            messages_dropped: 4,
+        }],
+        commands: vec![CommandPerformance {
+            command: "SystemStateQuery".to_owned(),
+            requests: 40,
+            failures: 2,
+            latency: Some(Latency {
+                samples: 40,
+                p50_ms: 0.5,
+                p90_ms: 2.0,
+                p99_ms: 8.0,
+            }),
+        }],
+        pending_queries: 3,
+        chunked_responses: 1,
+    };
+    hello: "/wipmate.Hello" => Hello { protocol_version: 1 };
+    welcome: "/wipmate.Welcome" => Welcome::current();
+    client_hello: "/wipmate.ClientApiCommand" => ClientApiCommand::hello();
+    tombstone_query: "/wipmate.TombstoneQuery" => TombstoneQuery {
+        entity_name: "sen_a".to_owned(),
+        include_history: true,
+    };
+    tombstone: "/wipmate.Tombstone" => Tombstone {
+        name: "sen_a".to_owned(),
+        entity_type: entity_discovery_command::EntityType::Sensor.into(),
+        last_state: Some(tombstone::LastState::Measurement(temperature())),
+        reason: "missed heartbeats".to_owned(),
+        age_seconds: 30.5,
+        rejoins: 2,
+        tags: vec!["lab".to_owned()],
+        address: "192.168.0.17".to_owned(),
+        history: vec![
+            ArchivedState {
+                published_at_ms: 1_700_000_000_000,
+                state: Some(archived_state::State::Measurement(temperature())),
+            },
+            ArchivedState::default(),
+        ],
+    };
+    tombstone_list: "/wipmate.TombstoneList" => TombstoneList {
+        tombstones: vec![Tombstone {
+            name: "act_c".to_owned(),
+            entity_type: entity_discovery_command::EntityType::Actuator.into(),
+            last_state: None,
+            reason: "disconnect request".to_owned(),
+            age_seconds: 1.0,
+            rejoins: 0,
+            ..Default::default()
+        }],
+    };
+    client_tombstone_query: "/wipmate.ClientApiCommand" => ClientApiCommand::tombstone_query();
+    client_archive_query: "/wipmate.ClientApiCommand" => ClientApiCommand::archive_query("act_c");
+    ghost_query: "/wipmate.GhostQuery" => GhostQuery {};
+    ghost: "/wipmate.Ghost" => Ghost {
+        name: "sen_typo".to_owned(),
+        entity_type: entity_discovery_command::EntityType::Sensor.into(),
+        publications: 42,
+        first_seen_seconds: 120.0,
+        last_seen_seconds: 0.5,
+        last_state: Some(ghost::LastState::Measurement(temperature())),
+    };
+    ghost_list: "/wipmate.GhostList" => GhostList {
+        ghosts: vec![Ghost {
+            name: "act_typo".to_owned(),
+            entity_type: entity_discovery_command::EntityType::Actuator.into(),
+            publications: 1,
+            ..Default::default()
+        }],
+        strict: true,
+    };
+    client_ghost_query: "/wipmate.ClientApiCommand" => ClientApiCommand::ghost_query();
+    client_next_chunk: "/wipmate.ClientApiCommand" => ClientApiCommand::next_chunk(3, 1);
+    client_limited_query: "/wipmate.ClientApiCommand" => ClientApiCommand {
+        max_response_size: 65536,
+        ..ClientApiCommand::system_state_query()
+    };
+    response_chunk: "/wipmate.ResponseChunk" => ResponseChunk {
+        response_id: 3,
+        sequence: 1,
+        total: 2,
+        more: false,
+        data: vec![0x0a, 0x01],
+    };
+    client_capture_snapshot: "/wipmate.ClientApiCommand" => ClientApiCommand::capture_snapshot("s3cret");
+    client_restore_snapshot: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::restore_snapshot("{}", "s3cret");
+    snapshot_document: "/wipmate.SnapshotDocument" => SnapshotDocument {
+        snapshot_json: "{}".to_owned(),
+    };
+    snapshot_restore_report: "/wipmate.SnapshotRestoreReport" => SnapshotRestoreReport {
+        restored: vec!["act_c".to_owned()],
+        missing: vec!["sen_a".to_owned()],
+        failed: BTreeMap::from([("act_ac".to_owned(), "timeout".to_owned())]),
+        error: String::new(),
+    };
+    named_measurement: "/wipmate.NamedEntityState" =>
+        NamedEntityState::measurement("sen_a", temperature());
+    automation_rule: "/wipmate.AutomationRule" => AutomationRule {
+        name: "cool down".to_owned(),
+        entity: "sen_kitchen".to_owned(),
+        comparison: automation_rule::Comparison::Above.into(),
+        value: 30.0,
+        actions: vec![NamedEntityState::actuator("act_ac", ActuatorState::air_conditioning(true))],
+    };
+    rule_list: "/wipmate.RuleList" => RuleList {
+        rules: vec![AutomationRule::default()],
+        error: "Unknown rule hot".to_owned(),
+    };
+    client_list_rules: "/wipmate.ClientApiCommand" => ClientApiCommand::list_rules();
+    client_add_rule: "/wipmate.ClientApiCommand" =>
+        ClientApiCommand::add_rule(AutomationRule::default());
+    client_remove_rule: "/wipmate.ClientApiCommand" => ClientApiCommand::remove_rule("hot");
+    empty_envelope: "/wipmate.PayloadEnvelope" => PayloadEnvelope::default();
+}
+
+#[test]
+fn payload_type_mismatch_is_rejected() {
+    let envelope = PayloadEnvelope::pack(
+        &SensorConfiguration {
+            update_frequency_hz: 1.0,
+        },
+        BTreeMap::new(),
+    )
+    .expect("failed to pack message");
+    let error = envelope.unpack::<LightActuatorState>().unwrap_err();
+    assert!(
+        matches!(&error, EnvelopeError::TypeMismatch { expected, actual }
+            if expected == "/wipmate.LightActuatorState" && actual == "/wipmate.SensorConfiguration"),
+        "unexpected error {error:?}"
+    );
+}
+
+#[test]
+fn payload_with_type_url_domain_is_accepted() {
+    let mut envelope =
+        PayloadEnvelope::pack(&LightActuatorState { brightness: 3.0 }, BTreeMap::new())
+            .expect("failed to pack message");
+    if let Some(payload) = &mut envelope.payload {
+        payload.type_url = format!("type.googleapis.com{}", payload.type_url);
+    }
+    let state: LightActuatorState = envelope.unpack().expect("failed to unpack payload");
+    assert_eq!(state.brightness, 3.0);
+}
+
+#[test]
+fn missing_payload_is_rejected() {
+    let error = PayloadEnvelope::default()
+        .unpack::<SystemState>()
+        .unwrap_err();
+    assert!(matches!(error, EnvelopeError::MissingPayload));
+}
+
+#[test]
+fn garbage_envelope_is_rejected() {
+    let error = PayloadEnvelope::from_bytes(&[0xff, 0xff, 0xff]).unwrap_err();
+    assert!(matches!(error, EnvelopeError::DecodeEnvelope(_)));
+}
+
+/// Golden byte sequences guard the field numbers of the messages exchanged between
+/// independently deployed binaries.
+#[test]
+fn golden_discovery_command() {
+    let bytes = [0x08, 0x01, 0x12, 0x01, b'a', 0x1a, 0x02, 0x08, 0x05];
+    let expected = EntityDiscoveryCommand {
+        command: Some(entity_discovery_command::Command::Register(
+            entity_discovery_command::Registration {
+                port: 5,
+                tags: Vec::new(),
+                max_batch_size: 0,
+                heartbeat_interval_ms: 0,
+            },
+        )),
+        entity_type: entity_discovery_command::EntityType::Actuator.into(),
+        entity_name: "a".to_owned(),
+        session_id: 0,
+        auth_token: String::new(),
+    };
+    assert_eq!(
+        EntityDiscoveryCommand::decode(&bytes[..]).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn golden_publish_data() {
+    let bytes = [
+        0x0a, 0x0a, 0x0a, 0x05, 0x0d, 0x00, 0x00, 0x80, 0x3f, 0x1a, 0x01, b'C',
+    ];
+    let expected = PublishData::from(SensorMeasurement {
+        value: Some(sensor_measurement::Value::Temperature(
+            TemperatureSensorMeasurement { temperature: 1.0 },
+        )),
+        unit: "C".to_owned(),
+    });
+    assert_eq!(PublishData::decode(&bytes[..]).unwrap(), expected);
+}
+
+#[test]
+fn golden_named_entity_state() {
+    let bytes = [0x0a, 0x01, b'b', 0x1a, 0x04, 0x12, 0x02, 0x08, 0x01];
+    let expected = NamedEntityState::actuator("b", ActuatorState::air_conditioning(true));
+    assert_eq!(NamedEntityState::decode(&bytes[..]).unwrap(), expected);
+}
+
+#[test]
+fn builders_fill_the_oneofs() {
+    let heartbeat = entity_discovery_command::Heartbeat {
+        sent_at_ms: 5,
+        clock_offset_ms: 1200,
+    };
+    assert_eq!(
+        EntityDiscoveryCommand::new(
+            entity_discovery_command::EntityType::Actuator,
+            "act_test",
+            heartbeat.clone(),
+        )
+        .with_session_id(7)
+        .with_auth_token("secret"),
+        EntityDiscoveryCommand {
+            session_id: 7,
+            auth_token: "secret".to_owned(),
+            ..discovery(entity_discovery_command::Command::Heartbeat(heartbeat))
+        }
+    );
+    assert_eq!(SensorMeasurement::temperature(21.5), temperature());
+    assert_eq!(SensorMeasurement::humidity(45.0), humidity());
+
+    let signed = NamedEntityState {
+        signature: Some(CommandSignature::default()),
+        ..NamedEntityState::actuator("act_a", ActuatorState::light(5.0))
+    };
+    assert_eq!(
+        signed.addressed_to("act_b"),
+        NamedEntityState::actuator("act_b", ActuatorState::light(5.0))
+    );
+}
@@ -0,0 +1,32 @@
+use home_automation_common::registration_auth::{entity_token, verify_entity_token};
+
+const KEY: [u8; 32] = [7; 32];
+const OTHER_KEY: [u8; 32] = [8; 32];
+
+#[test]
+fn accepts_token_of_entity() {
+    let token = entity_token(&KEY, "sen_kitchen");
+
+    assert_eq!(token.len(), 64);
+    assert!(verify_entity_token(&KEY, "sen_kitchen", &token));
+}
+
+#[test]
+fn rejects_token_of_other_entity() {
+    let token = entity_token(&KEY, "sen_kitchen");
+
+    assert!(!verify_entity_token(&KEY, "act_kitchen", &token));
+}
+
+#[test]
+fn rejects_token_of_other_key() {
+    let token = entity_token(&OTHER_KEY, "sen_kitchen");
+
+    assert!(!verify_entity_token(&KEY, "sen_kitchen", &token));
+}
+
+#[test]
+fn rejects_malformed_token() {
+    assert!(!verify_entity_token(&KEY, "sen_kitchen", ""));
+    assert!(!verify_entity_token(&KEY, "sen_kitchen", "not a token"));
+}
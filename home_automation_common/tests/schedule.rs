@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+use home_automation_common::schedule::{MissedTickPolicy, PublishSchedule};
+
+const PERIOD: Duration = Duration::from_millis(100);
+
+fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+}
+
+#[test]
+fn first_tick_is_due_immediately() {
+    let start = Instant::now();
+    let mut schedule = PublishSchedule::new(MissedTickPolicy::Skip);
+    assert_eq!(schedule.next(start), None);
+
+    schedule.set_period("sen_a", PERIOD);
+    assert_eq!(schedule.next(start), Some(("sen_a", start)));
+}
+
+#[test]
+fn deadlines_do_not_drift_with_publishing_time() {
+    let start = Instant::now();
+    let mut schedule = PublishSchedule::new(MissedTickPolicy::Skip);
+    schedule.set_period("sen_a", PERIOD);
+
+    schedule.advance("sen_a", start);
+    assert_eq!(schedule.next(start), Some(("sen_a", start + PERIOD)));
+    // publishing took 30 ms
+    schedule.advance("sen_a", start + PERIOD + ms(30));
+    assert_eq!(schedule.next(start), Some(("sen_a", start + 2 * PERIOD)));
+    schedule.advance("sen_a", start + 2 * PERIOD + ms(30));
+    assert_eq!(schedule.next(start), Some(("sen_a", start + 3 * PERIOD)));
+}
+
+#[test]
+fn topics_are_scheduled_independently() {
+    let start = Instant::now();
+    let mut schedule = PublishSchedule::new(MissedTickPolicy::Skip);
+    schedule.set_period("sen_fast", PERIOD);
+    schedule.set_period("sen_slow", ms(250));
+    schedule.advance("sen_fast", start);
+    schedule.advance("sen_slow", start);
+
+    let mut ticks = Vec::new();
+    for _ in 0..4 {
+        let (topic, deadline) = schedule.next(start).unwrap();
+        let topic = topic.to_owned();
+        ticks.push((topic.clone(), deadline.duration_since(start)));
+        schedule.advance(&topic, deadline);
+    }
+    assert_eq!(
+        ticks,
+        [
+            ("sen_fast".to_owned(), ms(100)),
+            ("sen_fast".to_owned(), ms(200)),
+            ("sen_slow".to_owned(), ms(250)),
+            ("sen_fast".to_owned(), ms(300)),
+        ]
+    );
+}
+
+#[test]
+fn skip_continues_with_the_next_tick_in_the_future() {
+    let start = Instant::now();
+    let mut schedule = PublishSchedule::new(MissedTickPolicy::Skip);
+    schedule.set_period("sen_a", PERIOD);
+    schedule.advance("sen_a", start);
+
+    // the tick at 100 ms is handled at 350 ms, so the ticks at 200 and 300 ms are missed
+    schedule.advance("sen_a", start + ms(350));
+    assert_eq!(schedule.next(start), Some(("sen_a", start + ms(400))));
+}
+
+#[test]
+fn burst_publishes_every_missed_tick() {
+    let start = Instant::now();
+    let mut schedule = PublishSchedule::new(MissedTickPolicy::Burst);
+    schedule.set_period("sen_a", PERIOD);
+    schedule.advance("sen_a", start);
+
+    schedule.advance("sen_a", start + ms(350));
+    assert_eq!(schedule.next(start), Some(("sen_a", start + ms(200))));
+}
+
+#[test]
+fn changed_period_applies_from_the_previous_tick() {
+    let start = Instant::now();
+    let mut schedule = PublishSchedule::new(MissedTickPolicy::Skip);
+    schedule.set_period("sen_a", PERIOD);
+    schedule.advance("sen_a", start);
+
+    schedule.set_period("sen_a", ms(40));
+    assert_eq!(schedule.next(start), Some(("sen_a", start + ms(40))));
+}
+
+#[test]
+fn removed_and_unknown_topics_are_not_scheduled() {
+    let start = Instant::now();
+    let mut schedule = PublishSchedule::new(MissedTickPolicy::Skip);
+    schedule.set_period("sen_a", PERIOD);
+    schedule.advance("sen_b", start);
+    assert_eq!(schedule.next(start), Some(("sen_a", start)));
+
+    schedule.remove("sen_a");
+    assert_eq!(schedule.next(start), None);
+}
@@ -0,0 +1,93 @@
+use home_automation_common::{
+    protobuf::{ActuatorState, NamedEntityState},
+    signing::{CommandSigner, CommandVerifier, SignatureError},
+};
+
+const KEY: [u8; 32] = [7; 32];
+const OTHER_KEY: [u8; 32] = [8; 32];
+
+fn command() -> NamedEntityState {
+    NamedEntityState::actuator("act_lamp", ActuatorState::light(42.0))
+}
+
+fn signed(signer: &CommandSigner) -> NamedEntityState {
+    let mut command = command();
+    signer.sign(&mut command);
+    command
+}
+
+#[test]
+fn accepts_signed_command() {
+    let signer = CommandSigner::new("client", KEY);
+    let verifier = CommandVerifier::new([("client".to_owned(), KEY)]);
+
+    assert_eq!(verifier.verify(&signed(&signer)), Ok(()));
+    assert_eq!(verifier.verify(&signed(&signer)), Ok(()));
+}
+
+#[test]
+fn rejects_replayed_command() {
+    let signer = CommandSigner::new("client", KEY);
+    let verifier = CommandVerifier::new([("client".to_owned(), KEY)]);
+    let first = signed(&signer);
+    let second = signed(&signer);
+
+    assert_eq!(verifier.verify(&second), Ok(()));
+    assert!(matches!(
+        verifier.verify(&second),
+        Err(SignatureError::Replay { .. })
+    ));
+    // an older command of the same signer is a replay as well
+    assert!(matches!(
+        verifier.verify(&first),
+        Err(SignatureError::Replay { .. })
+    ));
+}
+
+#[test]
+fn rejects_modified_command() {
+    let signer = CommandSigner::new("client", KEY);
+    let verifier = CommandVerifier::new([("client".to_owned(), KEY)]);
+    let mut command = signed(&signer);
+    command.entity_name = "act_heater".to_owned();
+
+    assert_eq!(
+        verifier.verify(&command),
+        Err(SignatureError::InvalidSignature("client".to_owned()))
+    );
+}
+
+#[test]
+fn rejects_wrong_or_unknown_keys() {
+    let verifier = CommandVerifier::new([("client".to_owned(), KEY)]);
+
+    let forged = signed(&CommandSigner::new("client", OTHER_KEY));
+    assert_eq!(
+        verifier.verify(&forged),
+        Err(SignatureError::InvalidSignature("client".to_owned()))
+    );
+    let unknown = signed(&CommandSigner::new("intruder", KEY));
+    assert_eq!(
+        verifier.verify(&unknown),
+        Err(SignatureError::UnknownKey("intruder".to_owned()))
+    );
+}
+
+#[test]
+fn rejects_unsigned_command_only_with_keys() {
+    let verifier = CommandVerifier::new([("client".to_owned(), KEY)]);
+    assert_eq!(verifier.verify(&command()), Err(SignatureError::Unsigned));
+
+    let disabled = CommandVerifier::default();
+    assert_eq!(disabled.verify(&command()), Ok(()));
+}
+
+#[test]
+fn nonces_increase_strictly() {
+    let signer = CommandSigner::new("client", KEY);
+    let nonces: Vec<_> = (0..100)
+        .map(|_| signed(&signer).signature.unwrap().nonce)
+        .collect();
+
+    assert!(nonces.windows(2).all(|pair| pair[0] < pair[1]));
+}
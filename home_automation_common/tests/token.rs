@@ -0,0 +1,20 @@
+use home_automation_common::token_matches;
+
+#[test]
+fn accepts_configured_token() {
+    assert!(token_matches("s3cret", "s3cret"));
+}
+
+#[test]
+fn rejects_other_tokens() {
+    assert!(!token_matches("s3cret", "s3cre"));
+    assert!(!token_matches("s3cret", "s3cret!"));
+    assert!(!token_matches("s3cret", "S3CRET"));
+    assert!(!token_matches("s3cret", ""));
+}
+
+#[test]
+fn empty_configured_token_matches_nothing() {
+    assert!(!token_matches("", ""));
+    assert!(!token_matches("", "s3cret"));
+}
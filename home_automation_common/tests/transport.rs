@@ -0,0 +1,288 @@
+use std::time::Duration;
+
+use home_automation_common::{
+    protobuf::{EntityDiscoveryCommand, ResponseCode},
+    transport::{
+        Channel, MemoryTransport, Pattern, TcpTransport, Transport, TransportKind, ZmqTransport,
+        PEER_ADDRESS_HEADER, TOPIC_HEADER,
+    },
+    zmq_sockets,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn discovery_command(name: &str) -> EntityDiscoveryCommand {
+    EntityDiscoveryCommand {
+        entity_name: name.to_owned(),
+        ..Default::default()
+    }
+}
+
+/// Answers a single request with the name of the requesting entity.
+fn reply_once(server: &dyn Channel) -> String {
+    let (request, headers) = server
+        .receive_message::<EntityDiscoveryCommand>(Some(TIMEOUT))
+        .unwrap()
+        .expect("request before the timeout");
+    server
+        .send_message(None, &ResponseCode::ok())
+        .expect("reply is sent");
+    assert_eq!(headers[PEER_ADDRESS_HEADER], "127.0.0.1");
+    request.entity_name
+}
+
+#[test]
+fn parses_transport_kind() {
+    assert_eq!("zmq".parse::<TransportKind>().unwrap(), TransportKind::Zmq);
+    assert_eq!(
+        " TCP ".parse::<TransportKind>().unwrap(),
+        TransportKind::Tcp
+    );
+    assert_eq!(
+        "memory".parse::<TransportKind>().unwrap(),
+        TransportKind::Memory
+    );
+    assert!("carrier pigeon".parse::<TransportKind>().is_err());
+}
+
+#[test]
+fn memory_publications_carry_topic() {
+    let subscriber = MemoryTransport
+        .bind(Pattern::Subscribe, "tcp://*:40001")
+        .unwrap();
+    let publisher = MemoryTransport
+        .connect(Pattern::Publish, "tcp://localhost:40001")
+        .unwrap();
+
+    publisher
+        .send_message(Some("sen/kitchen"), &discovery_command("kitchen"))
+        .unwrap();
+    let (message, headers) = subscriber
+        .receive_message::<EntityDiscoveryCommand>(Some(TIMEOUT))
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(message, discovery_command("kitchen"));
+    assert_eq!(headers[TOPIC_HEADER], "sen/kitchen");
+}
+
+#[test]
+fn memory_request_is_answered() {
+    let server = MemoryTransport.bind(Pattern::Reply, "tcp://*:*").unwrap();
+    let port = server.local_port().unwrap();
+    let client = MemoryTransport
+        .connect(Pattern::Request, &format!("tcp://127.0.0.1:{port}"))
+        .unwrap();
+
+    std::thread::scope(|s| {
+        let replier = s.spawn(move || reply_once(&*server));
+        let response: ResponseCode = client
+            .request(&discovery_command("lamp"), Some(TIMEOUT))
+            .unwrap();
+        assert_eq!(response, ResponseCode::ok());
+        assert_eq!(replier.join().unwrap(), "lamp");
+    });
+}
+
+#[test]
+fn memory_request_without_replier_fails() {
+    let client = MemoryTransport
+        .connect(Pattern::Request, "tcp://127.0.0.1:40002")
+        .unwrap();
+
+    let result = client.request::<_, ResponseCode>(&discovery_command("lamp"), Some(TIMEOUT));
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn memory_endpoint_is_bound_once() {
+    let _bound = MemoryTransport
+        .bind(Pattern::Reply, "tcp://*:40003")
+        .unwrap();
+
+    assert!(MemoryTransport
+        .bind(Pattern::Reply, "tcp://*:40003")
+        .is_err());
+}
+
+#[test]
+fn memory_receive_times_out() {
+    let subscriber = MemoryTransport
+        .bind(Pattern::Subscribe, "tcp://*:*")
+        .unwrap();
+
+    let received = subscriber
+        .receive_message::<EntityDiscoveryCommand>(Some(Duration::from_millis(10)))
+        .unwrap();
+
+    assert!(received.is_none());
+}
+
+#[test]
+fn tcp_publications_carry_topic() {
+    let subscriber = TcpTransport
+        .bind(Pattern::Subscribe, "tcp://127.0.0.1:*")
+        .unwrap();
+    let port = subscriber.local_port().unwrap();
+    let publisher = TcpTransport
+        .connect(Pattern::Publish, &format!("tcp://127.0.0.1:{port}"))
+        .unwrap();
+
+    for name in ["kitchen", "hallway"] {
+        publisher
+            .send_message(Some(&format!("sen/{name}")), &discovery_command(name))
+            .unwrap();
+    }
+    for name in ["kitchen", "hallway"] {
+        let (message, headers) = subscriber
+            .receive_message::<EntityDiscoveryCommand>(Some(TIMEOUT))
+            .unwrap()
+            .unwrap();
+        assert_eq!(message, discovery_command(name));
+        assert_eq!(headers[TOPIC_HEADER], format!("sen/{name}"));
+        assert_eq!(headers[PEER_ADDRESS_HEADER], "127.0.0.1");
+    }
+}
+
+#[test]
+fn tcp_request_is_answered() {
+    let server = TcpTransport
+        .bind(Pattern::Reply, "tcp://127.0.0.1:*")
+        .unwrap();
+    let port = server.local_port().unwrap();
+    let client = TcpTransport
+        .connect(Pattern::Request, &format!("tcp://127.0.0.1:{port}"))
+        .unwrap();
+
+    std::thread::scope(|s| {
+        let replier = s.spawn(move || reply_once(&*server));
+        let response: ResponseCode = client
+            .request(&discovery_command("lamp"), Some(TIMEOUT))
+            .unwrap();
+        assert_eq!(response, ResponseCode::ok());
+        assert_eq!(replier.join().unwrap(), "lamp");
+    });
+}
+
+#[test]
+fn tcp_requests_cannot_be_bound() {
+    assert!(TcpTransport
+        .bind(Pattern::Request, "tcp://127.0.0.1:*")
+        .is_err());
+}
+
+#[cfg(feature = "nng")]
+#[test]
+fn nng_request_is_answered() {
+    use home_automation_common::nng_transport::NngTransport;
+
+    let server = NngTransport
+        .bind(Pattern::Reply, "tcp://127.0.0.1:*")
+        .unwrap();
+    let port = server.local_port().unwrap();
+    let client = NngTransport
+        .connect(Pattern::Request, &format!("tcp://127.0.0.1:{port}"))
+        .unwrap();
+
+    std::thread::scope(|s| {
+        let replier = s.spawn(move || reply_once(&*server));
+        let response: ResponseCode = client
+            .request(&discovery_command("lamp"), Some(TIMEOUT))
+            .unwrap();
+        assert_eq!(response, ResponseCode::ok());
+        assert_eq!(replier.join().unwrap(), "lamp");
+    });
+}
+
+#[test]
+fn zmq_publications_in_order_are_not_dropped() {
+    let context = zmq_sockets::Context::new();
+    let subscriber = ZmqTransport::new(context.clone())
+        .bind(Pattern::Subscribe, "tcp://127.0.0.1:*")
+        .unwrap();
+    let port = subscriber.local_port().unwrap();
+    let publisher = ZmqTransport::new(context)
+        .connect(Pattern::Publish, &format!("tcp://127.0.0.1:{port}"))
+        .unwrap();
+
+    // publications are lost until the subscription reached the publisher, the numbering
+    // starts with the first received one
+    let received = (0..50).find_map(|_| {
+        publisher
+            .send_message(Some("sen/kitchen"), &discovery_command("kitchen"))
+            .unwrap();
+        subscriber
+            .receive_message::<EntityDiscoveryCommand>(Some(Duration::from_millis(100)))
+            .unwrap()
+    });
+    assert!(received.is_some(), "publication within 5 seconds");
+    for _ in 0..10 {
+        publisher
+            .send_message(Some("sen/kitchen"), &discovery_command("kitchen"))
+            .unwrap();
+        let (_, headers) = subscriber
+            .receive_message::<EntityDiscoveryCommand>(Some(TIMEOUT))
+            .unwrap()
+            .expect("publication before the timeout");
+        assert!(!headers.contains_key("sequence"), "{headers:?}");
+    }
+
+    let dropped: u64 = zmq_sockets::all_statistics()
+        .iter()
+        .map(|(_, statistics)| statistics.messages_dropped)
+        .sum();
+    assert_eq!(dropped, 0);
+}
+
+#[test]
+fn zmq_inproc_publications_are_received() {
+    // inproc messages have no peer address, like those of the internal subscriber of the proxy
+    let context = zmq_sockets::Context::new();
+    let subscriber = ZmqTransport::new(context.clone())
+        .bind(Pattern::Subscribe, "inproc://transport-test")
+        .unwrap();
+    let publisher = ZmqTransport::new(context)
+        .connect(Pattern::Publish, "inproc://transport-test")
+        .unwrap();
+
+    let received = (0..50).find_map(|_| {
+        publisher
+            .send_message(Some("sen/kitchen"), &discovery_command("kitchen"))
+            .unwrap();
+        subscriber
+            .receive_message::<EntityDiscoveryCommand>(Some(Duration::from_millis(100)))
+            .unwrap()
+    });
+    let (message, headers) = received.expect("publication within 5 seconds");
+    assert_eq!(message, discovery_command("kitchen"));
+    assert_eq!(headers[TOPIC_HEADER], "sen/kitchen");
+}
+
+#[cfg(feature = "nng")]
+#[test]
+fn nng_publications_carry_topic() {
+    use home_automation_common::nng_transport::NngTransport;
+
+    let subscriber = NngTransport
+        .bind(Pattern::Subscribe, "tcp://127.0.0.1:*")
+        .unwrap();
+    let port = subscriber.local_port().unwrap();
+    let publisher = NngTransport
+        .connect(Pattern::Publish, &format!("tcp://127.0.0.1:{port}"))
+        .unwrap();
+
+    // publications are lost until the connection is established
+    let received = (0..50).find_map(|_| {
+        publisher
+            .send_message(Some("sen/kitchen"), &discovery_command("kitchen"))
+            .unwrap();
+        subscriber
+            .receive_message::<EntityDiscoveryCommand>(Some(Duration::from_millis(100)))
+            .unwrap()
+    });
+    let (message, headers) = received.expect("publication within 5 seconds");
+
+    assert_eq!(message, discovery_command("kitchen"));
+    assert_eq!(headers[TOPIC_HEADER], "sen/kitchen");
+}
@@ -0,0 +1,59 @@
+use home_automation_common::{
+    protobuf::{lifecycle_command::Action, ActuatorState, NamedEntityState},
+    value_range::{self, OutOfRange, ValueKind},
+    UpdateFrequency,
+};
+
+#[test]
+fn ranges_are_well_formed() {
+    for &kind in ValueKind::ALL {
+        let range = kind.range();
+        assert!(range.min < range.max, "{kind}");
+        assert!(!range.unit.is_empty(), "{kind}");
+    }
+}
+
+#[test]
+fn checks_bounds_inclusively() {
+    assert_eq!(ValueKind::Brightness.check(0.0), Ok(0.0));
+    assert_eq!(ValueKind::Brightness.check(100.0), Ok(100.0));
+    assert_eq!(
+        ValueKind::Brightness.check(100.5),
+        Err(OutOfRange {
+            kind: ValueKind::Brightness,
+            value: 100.5,
+            range: ValueKind::Brightness.range(),
+        })
+    );
+    assert!(ValueKind::Brightness.check(f32::NAN).is_err());
+    assert!(ValueKind::UpdateFrequency.check(0.05).is_err());
+}
+
+#[test]
+fn clamps_and_maps_to_ratio() {
+    let range = ValueKind::UpdateFrequency.range();
+    assert_eq!(range.clamp(20.0), 10.0);
+    assert_eq!(range.clamp(0.0), 0.1);
+    assert_eq!(ValueKind::Brightness.range().ratio(25.0), 0.25);
+    assert_eq!(ValueKind::Brightness.range().ratio(-5.0), 0.0);
+}
+
+#[test]
+fn validates_commands() {
+    let light =
+        |brightness| NamedEntityState::actuator("act_lamp", ActuatorState::light(brightness));
+    assert_eq!(value_range::validate(&light(50.0)), Ok(()));
+    assert!(value_range::validate(&light(150.0)).is_err());
+
+    let too_fast = NamedEntityState::frequency("sen_a", UpdateFrequency::from_hz(50.0).unwrap());
+    let error = value_range::validate(&too_fast).unwrap_err();
+    assert_eq!(error.kind, ValueKind::UpdateFrequency);
+
+    for command in [
+        NamedEntityState::ping("act_lamp"),
+        NamedEntityState::lifecycle("act_lamp", Action::Restart),
+        NamedEntityState::actuator("act_ac", ActuatorState::air_conditioning(true)),
+    ] {
+        assert_eq!(value_range::validate(&command), Ok(()));
+    }
+}
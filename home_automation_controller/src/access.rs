@@ -0,0 +1,205 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Source address filters of the endpoints of the controller.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessControl {
+    pub discovery: AddressFilter,
+    pub client_api: AddressFilter,
+}
+
+/// Allow and deny lists of networks, e.g. `["192.168.0.0/24", "10.0.0.7"]`.
+///
+/// Denied networks take precedence. An empty allow list allows every address that is not denied.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AddressFilter {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl AddressFilter {
+    /// Returns whether requests from the address are accepted.
+    ///
+    /// Addresses that are not IP addresses are only accepted if the filter is empty.
+    pub fn permits(&self, address: &str) -> bool {
+        let Ok(ip) = address.parse::<IpAddr>() else {
+            return self.allow.is_empty() && self.deny.is_empty();
+        };
+        !self.deny.iter().any(|network| network.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip)))
+    }
+}
+
+/// IP network in CIDR notation, a plain IP address is a network with a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u32,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 peers of dual-stack sockets are reported as IPv4-mapped IPv6 addresses
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => Self::prefix_matches(
+                u32::from(network).into(),
+                u32::from(ip).into(),
+                32 - self.prefix_len,
+            ),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                Self::prefix_matches(network.into(), ip.into(), 128 - self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+
+    fn prefix_matches(network: u128, ip: u128, host_bits: u32) -> bool {
+        host_bits >= u128::BITS || (network ^ ip) >> host_bits == 0
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "Invalid network {s:?}, expected an IP address with optional prefix length, e.g. 192.168.0.0/24"
+            )
+        };
+        let (network, prefix_len) = match s.trim().split_once('/') {
+            Some((network, prefix_len)) => (network, Some(prefix_len)),
+            None => (s.trim(), None),
+        };
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse().map_err(|_| invalid())?,
+            None => max_prefix_len,
+        };
+        if prefix_len > max_prefix_len {
+            return Err(invalid());
+        }
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> AddressFilter {
+        let parse = |networks: &[&str]| networks.iter().map(|n| n.parse().unwrap()).collect();
+        AddressFilter {
+            allow: parse(allow),
+            deny: parse(deny),
+        }
+    }
+
+    fn contains(network: &str, address: &str) -> bool {
+        network
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(address.parse().unwrap())
+    }
+
+    #[test]
+    fn zero_prefix_contains_every_address_of_its_family() {
+        assert!(contains("0.0.0.0/0", "203.0.113.9"));
+        assert!(contains("::/0", "2001:db8::1"));
+        assert!(!contains("0.0.0.0/0", "2001:db8::1"));
+        assert!(!contains("::/0", "203.0.113.9"));
+    }
+
+    #[test]
+    fn full_prefix_contains_only_the_address() {
+        assert!(contains("10.0.0.7/32", "10.0.0.7"));
+        assert!(!contains("10.0.0.7/32", "10.0.0.8"));
+        assert!(contains("2001:db8::7/128", "2001:db8::7"));
+        assert!(!contains("2001:db8::7/128", "2001:db8::8"));
+        assert_eq!(
+            "10.0.0.7".parse::<Cidr>().unwrap().to_string(),
+            "10.0.0.7/32"
+        );
+    }
+
+    #[test]
+    fn prefix_matches_the_network_bits() {
+        assert!(contains("192.168.0.0/24", "192.168.0.255"));
+        assert!(!contains("192.168.0.0/24", "192.168.1.0"));
+        assert!(contains("2001:db8::/32", "2001:db8:ffff::1"));
+        assert!(!contains("2001:db8::/32", "2001:db9::1"));
+    }
+
+    #[test]
+    fn ipv4_mapped_ipv6_addresses_match_ipv4_networks() {
+        assert!(contains("192.168.0.0/24", "::ffff:192.168.0.10"));
+        assert!(!contains("192.168.0.0/24", "::ffff:192.168.1.10"));
+        assert!(!filter(&[], &["10.0.0.0/8"]).permits("::ffff:10.1.2.3"));
+    }
+
+    #[test]
+    fn rejects_invalid_networks() {
+        for network in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "10.0.0/8",
+            "host",
+            "10.0.0.0/-1",
+        ] {
+            assert!(network.parse::<Cidr>().is_err(), "{network} was accepted");
+        }
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let filter = filter(&["10.0.0.0/8"], &["10.0.0.7"]);
+        assert!(filter.permits("10.0.0.6"));
+        assert!(!filter.permits("10.0.0.7"));
+        assert!(!filter.permits("192.168.0.1"));
+    }
+
+    #[test]
+    fn empty_allow_list_allows_everything_not_denied() {
+        assert!(AddressFilter::default().permits("203.0.113.9"));
+        let filter = filter(&[], &["203.0.113.0/24"]);
+        assert!(!filter.permits("203.0.113.9"));
+        assert!(filter.permits("198.51.100.1"));
+    }
+
+    #[test]
+    fn non_ip_peers_pass_only_an_empty_filter() {
+        assert!(AddressFilter::default().permits("inproc"));
+        assert!(AddressFilter::default().permits(""));
+        assert!(!filter(&["127.0.0.1"], &[]).permits("inproc"));
+        assert!(!filter(&[], &["10.0.0.0/8"]).permits("inproc"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Authentication of the discovery commands of the entities, see
+//! [`registration_auth`](home_automation_common::registration_auth).
+
+use std::collections::HashSet;
+
+use home_automation_common::registration_auth::{
+    self, Key, ENV_REGISTRATION_KEY, ENV_REGISTRATION_TOKENS,
+};
+
+/// Decides whether a discovery command really comes from the entity it names.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, entity_name: &str, token: &str) -> anyhow::Result<()>;
+
+    /// Short description for the log.
+    fn describe(&self) -> String;
+}
+
+/// Accepts every entity, used if no authentication is configured.
+pub struct AllowAll;
+
+impl Authenticator for AllowAll {
+    fn authenticate(&self, _entity_name: &str, _token: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        "all entities are accepted".to_owned()
+    }
+}
+
+/// Accepts the entities that know one of the tokens.
+pub struct StaticTokens(HashSet<String>);
+
+impl Authenticator for StaticTokens {
+    fn authenticate(&self, entity_name: &str, token: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.0.contains(token),
+            "Entity {entity_name} sent an unknown registration token"
+        );
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        format!("{} registration tokens are accepted", self.0.len())
+    }
+}
+
+/// Accepts the entities that send the HMAC of their name, so a token only works for one name.
+pub struct HmacOfName(Key);
+
+impl Authenticator for HmacOfName {
+    fn authenticate(&self, entity_name: &str, token: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            registration_auth::verify_entity_token(&self.0, entity_name, token),
+            "Entity {entity_name} sent an invalid registration token"
+        );
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        "the HMAC of the entity name is required".to_owned()
+    }
+}
+
+/// Selects the authenticator by [`ENV_REGISTRATION_KEY`] or [`ENV_REGISTRATION_TOKENS`].
+pub fn from_env() -> anyhow::Result<Box<dyn Authenticator>> {
+    let key = registration_auth::key_from_env()?;
+    let tokens: HashSet<_> = std::env::var(ENV_REGISTRATION_TOKENS)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(ToOwned::to_owned)
+        .collect();
+    match key {
+        Some(_) if !tokens.is_empty() => anyhow::bail!(
+            "Only one of {ENV_REGISTRATION_KEY} and {ENV_REGISTRATION_TOKENS} can be set"
+        ),
+        Some(key) => Ok(Box::new(HmacOfName(key))),
+        None if !tokens.is_empty() => Ok(Box::new(StaticTokens(tokens))),
+        None => Ok(Box::new(AllowAll)),
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::HashMap,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use btleplug::{
+    api::{
+        bleuuid::uuid_from_u16, Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter,
+    },
+    platform::Manager,
+};
+use futures::StreamExt as _;
+use home_automation_common::{
+    latency, load_env,
+    protobuf::{
+        entity_discovery_command::{Command, EntityType, Heartbeat, Registration},
+        response_code::Code,
+        EntityDiscoveryCommand, NamedEntityState, PublishData, ResponseCode, SensorMeasurement,
+    },
+    registration_auth, sensor_measurement_topic,
+    transport::{Channel, Pattern, Transport},
+    ShutdownToken, DEFAULT_HEARTBEAT_INTERVAL,
+};
+
+use crate::{serial_gateway::connect_discovery, state::AppState};
+
+/// Comma separated list of the addresses of the beacons to forward, e.g. `A4:C1:38:12:34:56`,
+/// or `*` for all beacons in range.
+pub const ENV_BLE_BEACONS: &str = "HOME_AUTOMATION_BLE_BEACONS";
+
+/// Interval in which the gateway checks for readings, updates and shutdown requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the gateway waits for the answers of the entity discovery.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Beacons that were not received for this long are unregistered.
+const BEACON_TIMEOUT: Duration = Duration::from_secs(120);
+/// Tag of all entities registered by the gateway.
+const TAG: &str = "ble";
+
+/// Service data of the ATC and pvvx firmwares of Xiaomi thermometers.
+const ENVIRONMENTAL_SENSING_SERVICE: u16 = 0x181A;
+/// Manufacturer data of Govee H5072/H5075 thermometers.
+const GOVEE_COMPANY_ID: u16 = 0xEC88;
+
+/// Returns the beacons to forward if the gateway is enabled.
+pub fn allowed_beacons() -> Option<AllowedBeacons> {
+    let beacons = load_env(ENV_BLE_BEACONS).ok()?;
+    if beacons.trim() == "*" {
+        return Some(AllowedBeacons::All);
+    }
+    Some(AllowedBeacons::Listed(
+        beacons
+            .split(',')
+            .map(|address| address.trim().to_uppercase())
+            .filter(|address| !address.is_empty())
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Clone)]
+pub enum AllowedBeacons {
+    All,
+    Listed(Vec<String>),
+}
+
+impl AllowedBeacons {
+    fn contains(&self, address: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Listed(addresses) => addresses.iter().any(|a| a.eq_ignore_ascii_case(address)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Reading {
+    temperature: f32,
+    humidity: f32,
+}
+
+/// Decodes the ATC (13 bytes, big endian) and pvvx (15 bytes, little endian) formats.
+fn decode_environmental_sensing(data: &[u8]) -> Option<Reading> {
+    match data.len() {
+        13 => Some(Reading {
+            temperature: f32::from(i16::from_be_bytes([data[6], data[7]])) / 10.0,
+            humidity: f32::from(data[8]),
+        }),
+        15 => Some(Reading {
+            temperature: f32::from(i16::from_le_bytes([data[6], data[7]])) / 100.0,
+            humidity: f32::from(u16::from_le_bytes([data[8], data[9]])) / 100.0,
+        }),
+        _ => None,
+    }
+}
+
+/// Decodes the temperature and humidity packed into three bytes by Govee thermometers.
+fn decode_govee(data: &[u8]) -> Option<Reading> {
+    let &[_, high, middle, low, ..] = data else {
+        return None;
+    };
+    let packed = u32::from_be_bytes([0, high, middle, low]);
+    // the highest bit is the sign of the temperature
+    let value = (packed & 0x7F_FFFF) as f32;
+    let sign = if packed & 0x80_0000 == 0 { 1.0 } else { -1.0 };
+    Some(Reading {
+        temperature: sign * (value / 1000.0).trunc() / 10.0,
+        humidity: (value % 1000.0) / 10.0,
+    })
+}
+
+/// Scans for advertisements of thermometer beacons until the shutdown is requested.
+async fn scan(
+    shutdown: &ShutdownToken,
+    readings: mpsc::Sender<(String, Reading)>,
+) -> anyhow::Result<()> {
+    let manager = Manager::new().await?;
+    let central = manager
+        .adapters()
+        .await?
+        .into_iter()
+        .next()
+        .context("No Bluetooth adapter found")?;
+    let mut events = central.events().await?;
+    central.start_scan(ScanFilter::default()).await?;
+    tracing::info!("Scanning for BLE thermometer beacons");
+
+    while !shutdown.is_requested() {
+        let event = match tokio::time::timeout(POLL_INTERVAL, events.next()).await {
+            Err(_) => continue,
+            Ok(None) => anyhow::bail!("Bluetooth adapter stopped reporting advertisements"),
+            Ok(Some(event)) => event,
+        };
+        let (id, reading) = match event {
+            CentralEvent::ServiceDataAdvertisement { id, service_data } => {
+                let data = service_data.get(&uuid_from_u16(ENVIRONMENTAL_SENSING_SERVICE));
+                (id, data.and_then(|data| decode_environmental_sensing(data)))
+            }
+            CentralEvent::ManufacturerDataAdvertisement {
+                id,
+                manufacturer_data,
+            } => {
+                let data = manufacturer_data.get(&GOVEE_COMPANY_ID);
+                (id, data.and_then(|data| decode_govee(data)))
+            }
+            _ => continue,
+        };
+        let Some(reading) = reading else {
+            continue;
+        };
+        let Ok(peripheral) = central.peripheral(&id).await else {
+            continue;
+        };
+        let address = peripheral.address().to_string();
+        if readings.send((address, reading)).is_err() {
+            break;
+        }
+    }
+    central.stop_scan().await?;
+    Ok(())
+}
+
+/// Registers thermometer beacons as sensors and publishes their advertised readings.
+pub struct BleGatewayTask {
+    transport: Box<dyn Transport>,
+    discovery_endpoint: String,
+    discovery: Box<dyn Channel>,
+    publisher: Box<dyn Channel>,
+    updates: Box<dyn Channel>,
+    update_port: u16,
+    allowed: AllowedBeacons,
+    /// Registered entities and when their beacon was received last.
+    entities: HashMap<String, Instant>,
+    /// Session of the registered entities, the start time is unique for each run of the gateway.
+    session_id: u64,
+    shutdown: ShutdownToken,
+}
+
+impl BleGatewayTask {
+    pub fn new(app_state: &AppState, allowed: AllowedBeacons) -> anyhow::Result<Self> {
+        let transport = app_state.transport();
+        let discovery_endpoint = load_env(home_automation_common::ENV_DISCOVERY_ENDPOINT)?;
+        let discovery = connect_discovery(&*transport, &discovery_endpoint)?;
+        let publisher = transport.connect(
+            Pattern::Publish,
+            &load_env(home_automation_common::ENV_ENTITY_DATA_ENDPOINT)?,
+        )?;
+        let updates = transport.bind(Pattern::Reply, "tcp://*:*")?;
+        let update_port = updates.local_port()?;
+        Ok(Self {
+            transport,
+            discovery_endpoint,
+            discovery,
+            publisher,
+            updates,
+            update_port,
+            allowed,
+            entities: HashMap::new(),
+            session_id: latency::unix_time_ms(),
+            shutdown: app_state.shutdown.clone(),
+        })
+    }
+
+    #[tracing::instrument(name = "BLE gateway", skip(self))]
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        tracing::info!(allowed = ?self.allowed, "Starting BLE gateway.");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to create runtime for Bluetooth")?;
+        // stops the scanner if forwarding fails
+        let scanning = self.shutdown.child();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::scope(|s| {
+            let scanner = s.spawn(|| runtime.block_on(scan(&scanning, sender)));
+            let result = self.forward(&receiver);
+            scanning.request();
+            // the entity discovery does not answer during the shutdown
+            if !self.shutdown.is_requested() {
+                self.unregister_all();
+            }
+            scanner
+                .join()
+                .map_err(|e| anyhow::anyhow!("BLE scanner panicked: {e:?}"))??;
+            result
+        })
+    }
+
+    fn forward(&mut self, readings: &mpsc::Receiver<(String, Reading)>) -> anyhow::Result<()> {
+        let mut heartbeat = Instant::now() + DEFAULT_HEARTBEAT_INTERVAL;
+        while !self.shutdown.is_requested() {
+            match readings.recv_timeout(POLL_INTERVAL) {
+                Ok((address, reading)) => self.handle_reading(&address, reading),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                // the scanner failed, its error is returned when it is joined
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            self.answer_updates()?;
+            if Instant::now() >= heartbeat {
+                self.send_heartbeats();
+                heartbeat += DEFAULT_HEARTBEAT_INTERVAL;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_reading(&mut self, address: &str, reading: Reading) {
+        if !self.allowed.contains(address) {
+            return;
+        }
+        let base_name = format!("sen_ble_{}", address.replace(':', "").to_lowercase());
+        let measurements = [
+            (
+                format!("{base_name}_temperature"),
+                SensorMeasurement::temperature(reading.temperature),
+            ),
+            (
+                format!("{base_name}_humidity"),
+                SensorMeasurement::humidity(reading.humidity),
+            ),
+        ];
+        for (name, measurement) in measurements {
+            if !self.entities.contains_key(&name) {
+                let registration = Command::Register(Registration {
+                    port: self.update_port.into(),
+                    tags: vec![TAG.to_owned()],
+                    max_batch_size: 0,
+                    heartbeat_interval_ms: 0,
+                });
+                if let Err(e) = self.request(&name, registration) {
+                    tracing::warn!("Failed to register BLE beacon {address} as {name}: {e:#}");
+                    continue;
+                }
+                tracing::info!("Registered BLE beacon {address} as {name}");
+            }
+            self.entities.insert(name.clone(), Instant::now());
+
+            let data = PublishData {
+                published_at_ms: latency::unix_time_ms(),
+                ..measurement.into()
+            };
+            let topic = sensor_measurement_topic(&name);
+            if let Err(e) = self.publisher.send_message(Some(&topic), &data) {
+                tracing::error!("Failed to publish reading of {name}: {e:#}");
+            }
+        }
+    }
+
+    /// Answers the pings of the controller, beacons cannot be configured.
+    fn answer_updates(&self) -> anyhow::Result<()> {
+        // only polled between the readings
+        while let Some((update, _)) = self
+            .updates
+            .receive_message::<NamedEntityState>(Some(Duration::ZERO))?
+        {
+            let response = match update.state {
+                None => ResponseCode::ok(),
+                Some(_) => Err::<(), _>(anyhow::anyhow!(
+                    "BLE beacon {} cannot be configured",
+                    update.entity_name
+                ))
+                .into(),
+            };
+            self.updates.send_message(None, &response)?;
+        }
+        Ok(())
+    }
+
+    fn send_heartbeats(&mut self) {
+        let (stale, active): (Vec<_>, Vec<_>) = self
+            .entities
+            .iter()
+            .map(|(name, last_seen)| (name.clone(), last_seen.elapsed() >= BEACON_TIMEOUT))
+            .partition(|(_, stale)| *stale);
+        for (name, _) in stale {
+            tracing::info!("Unregistering {name} because its beacon is out of range");
+            self.entities.remove(&name);
+            if let Err(e) = self.request(&name, Command::Unregister(())) {
+                tracing::warn!("Failed to unregister {name}: {e:#}");
+            }
+        }
+        for (name, _) in active {
+            let heartbeat = Command::Heartbeat(Heartbeat {
+                sent_at_ms: latency::unix_time_ms(),
+                clock_offset_ms: 0,
+            });
+            if let Err(e) = self.request(&name, heartbeat) {
+                tracing::warn!("Heartbeat of {name} failed, registering it again: {e:#}");
+                self.entities.remove(&name);
+            }
+        }
+    }
+
+    fn unregister_all(&mut self) {
+        let names: Vec<_> = self.entities.drain().map(|(name, _)| name).collect();
+        for name in names {
+            if let Err(e) = self.request(&name, Command::Unregister(())) {
+                tracing::warn!("Failed to unregister {name}: {e:#}");
+            }
+        }
+    }
+
+    /// Sends a discovery request on behalf of the entity.
+    fn request(&mut self, name: &str, command: Command) -> anyhow::Result<()> {
+        let request = EntityDiscoveryCommand::new(EntityType::Sensor, name, command)
+            .with_session_id(self.session_id)
+            .with_auth_token(registration_auth::token_from_env(name)?);
+        let result = self
+            .discovery
+            .request::<_, ResponseCode>(&request, Some(RESPONSE_TIMEOUT));
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                // the requester cannot send again before it received a reply
+                self.discovery = connect_discovery(&*self.transport, &self.discovery_endpoint)?;
+                return Err(e);
+            }
+        };
+        anyhow::ensure!(
+            matches!(response.code(), Code::Ok),
+            "Controller rejected request: {}",
+            response.message
+        );
+        Ok(())
+    }
+}
@@ -1,33 +1,131 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
 use anyhow::Context as _;
+use home_automation_api::{command_name, SystemStateBuilder, MAX_WAIT_FOR_CHANGE};
 use home_automation_common::{
+    chunk,
+    envelope::PackedMessage,
     load_env,
+    log_event::LogEvent,
+    log_summary::Summarize as _,
     protobuf::{
-        client_api_command::CommandType, entity_discovery_command::EntityType, ClientApiCommand,
-        NamedEntityState, ResponseCode, SystemState,
+        admin_command, automation_dry_run::Target, client_api_command::CommandType,
+        lifecycle_command::Action, task_health, AdminCommand, AdminState, AutomationDryRun,
+        ChunkRequest, ClientApiCommand, ConfigurationDocument, ConfigurationImport, DryRunReport,
+        EntityHealth, EntityMetadata, EntityTags, ErrorReport, NamedEntityState,
+        PerformanceCounters, ResponseChunk, ResponseCode, RuleList, SnapshotDocument,
+        SnapshotRestoreReport, SocketStatistics, SystemState, SystemStateQuery, TaggedCommand,
+        TaskHealth, TombstoneQuery, Welcome,
     },
-    shutdown_requested,
-    zmq_sockets::{self, markers::Linked, termination_is_ok},
+    token_matches,
+    zmq_sockets::{self, markers::Linked, termination_is_ok, RoutingEnvelope},
+};
+
+use crate::{
+    config::Configuration,
+    request_log::{Outcome, RequestLog},
+    rules,
+    snapshot::Snapshot,
+    state::{AppState, TaskStatus},
 };
 
-use crate::state::AppState;
+/// Maximum delay until a change of the state is reported to waiting state queries.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Time a client has to request the chunks of a split response.
+const CHUNK_EXPIRY: Duration = Duration::from_secs(30);
+/// Split responses kept at most for all clients together, the oldest one is dropped first.
+const MAX_CHUNKED_RESPONSES: usize = 64;
+/// A cached system state is collected again after this time, so the heartbeat ages stay current.
+const SYSTEM_STATE_CACHE_MAX_AGE: Duration = Duration::from_secs(1);
+/// Smallest chunk that is sent, so tiny limits of the clients do not result in countless chunks.
+const MIN_CHUNK_SIZE: usize = 1024;
+
+/// State query that is answered once the state changed or its deadline passed.
+#[derive(Debug)]
+struct PendingQuery {
+    client: RoutingEnvelope,
+    tag: String,
+    known_generation: u64,
+    deadline: Instant,
+    max_response_size: usize,
+}
+
+/// Chunks of a split response that the client requests one after another.
+#[derive(Debug)]
+struct ChunkedResponse {
+    chunks: Vec<ResponseChunk>,
+    expires: Instant,
+}
+
+/// Encoded system states of a single state generation, keyed by the tag of the query.
+#[derive(Debug, Default)]
+struct SystemStateCache {
+    generation: u64,
+    by_tag: HashMap<String, CachedState>,
+}
+
+#[derive(Debug)]
+struct CachedState {
+    collected_at: Instant,
+    packed: PackedMessage,
+}
 
+/// Serves the client API with a `ROUTER` socket, so that state queries waiting for a change
+/// can be answered later without blocking the other clients.
 pub struct ClientApiTask<'a> {
     app_state: &'a AppState,
-    server: zmq_sockets::Replier<Linked>,
+    server: zmq_sockets::Router<Linked>,
+    admin_token: Option<String>,
+    pending: RefCell<Vec<PendingQuery>>,
+    system_state_cache: RefCell<SystemStateCache>,
+    /// Keyed by the increasing response id, so the first entry is the oldest.
+    chunked_responses: RefCell<BTreeMap<u64, ChunkedResponse>>,
+    last_response_id: Cell<u64>,
+    request_log: RequestLog,
+    started: Instant,
 }
 
 impl<'a> ClientApiTask<'a> {
     pub fn new(app_state: &'a AppState) -> anyhow::Result<Self> {
         let address = load_env(home_automation_common::ENV_CLIENT_API_ENDPOINT)?;
-        let server = zmq_sockets::Replier::new(&app_state.context)?.bind(&address)?;
-        Ok(Self { app_state, server })
+        let (server, bound) = zmq_sockets::Router::new(&app_state.context)?
+            .bind_with_retry(&address, &app_state.bind_retry())?;
+        tracing::info!(%bound, "Clients can connect to {bound}");
+        let admin_token = load_env(home_automation_common::ENV_ADMIN_TOKEN).ok();
+        anyhow::ensure!(
+            admin_token.as_ref().map_or(true, |token| !token.is_empty()),
+            "{} must not be empty",
+            home_automation_common::ENV_ADMIN_TOKEN
+        );
+        if admin_token.is_none() {
+            tracing::warn!(
+                "Admin commands are disabled because {} is not set",
+                home_automation_common::ENV_ADMIN_TOKEN
+            );
+        }
+        Ok(Self {
+            app_state,
+            server,
+            admin_token,
+            pending: RefCell::default(),
+            system_state_cache: RefCell::default(),
+            chunked_responses: RefCell::default(),
+            last_response_id: Cell::default(),
+            request_log: RequestLog::new()?,
+            started: Instant::now(),
+        })
     }
 
     #[tracing::instrument(name = "Client Api", skip(self))]
     pub fn run(&self) -> anyhow::Result<()> {
         tracing::info!("Starting Client API.");
-        while !shutdown_requested() {
-            let Err(e) = self.handle_client() else {
+        while !self.app_state.shutdown.is_requested() {
+            let Err(e) = self.poll_clients() else {
                 continue;
             };
             return Err(e).or_else(termination_is_ok);
@@ -35,94 +133,808 @@ impl<'a> ClientApiTask<'a> {
         Ok(())
     }
 
+    fn poll_clients(&self) -> anyhow::Result<()> {
+        let readable = {
+            let mut items = [self.server.poll_item()];
+            zmq_sockets::poll(&mut items, Some(POLL_INTERVAL))?;
+            items[0].is_readable()
+        };
+        if readable {
+            self.handle_client()?;
+        }
+        self.request_log.report_periodically();
+        let now = Instant::now();
+        self.chunked_responses
+            .borrow_mut()
+            .retain(|_, response| response.expires > now);
+        self.answer_pending_queries()
+    }
+
     #[tracing::instrument(skip(self))]
     fn handle_client(&self) -> anyhow::Result<()> {
-        let request: ClientApiCommand = self.server.receive()?;
-        match request.command_type {
-            Some(CommandType::Query(_)) => {
-                self.handle_system_state_query()?;
+        let (client, request): (_, ClientApiCommand) = self.server.receive()?;
+        let started = Instant::now();
+        let command = command_name(request.command_type.as_ref());
+        let outcome = self.handle_request(&client, request)?;
+        self.request_log
+            .record(client.peer_address(), command, started, outcome);
+        Ok(())
+    }
+
+    /// Answers the request unless it is deferred, failed requests are answered with an error.
+    fn handle_request(
+        &self,
+        client: &RoutingEnvelope,
+        request: ClientApiCommand,
+    ) -> anyhow::Result<Outcome> {
+        if let Err(e) = self
+            .app_state
+            .check_access(|access| &access.client_api, client.peer_address())
+        {
+            let response_code: ResponseCode = Err::<(), _>(e).into();
+            self.server.send(client, response_code)?;
+            return Ok(Outcome::Rejected);
+        }
+
+        let command = command_name(request.command_type.as_ref());
+        let max_response_size = request.max_response_size as usize;
+        let outcome = match request.command_type {
+            Some(CommandType::Query(query)) => {
+                self.handle_system_state_query(client, query, max_response_size)?
             }
             Some(CommandType::Action(entity_state)) => {
+                let entity_name = entity_state.entity_name.clone();
                 let result = self.handle_entity_state_command(entity_state);
-                tracing::info!(
-                    ?result,
-                    "Handled NamedEntityState command with result: {result:?}"
-                );
+                LogEvent::command(command, Some(&entity_name), &result).emit();
+                if let Err(e) = &result {
+                    self.app_state.record_entity_error(
+                        &entity_name,
+                        format!("Failed to handle entity command: {e:#}"),
+                    );
+                }
+                let outcome = Outcome::of(&result);
+                let response_code: ResponseCode = result.into();
+                self.server.send(client, response_code)?;
+                outcome
+            }
+            Some(CommandType::ExportConfiguration(export))
+                if !self.is_admin(&export.admin_token) =>
+            {
+                self.reject_unauthorized(client, command)?
+            }
+            Some(CommandType::ExportConfiguration(_)) => {
+                self.handle_configuration_export(client)?;
+                Outcome::Succeeded
+            }
+            Some(CommandType::ImportConfiguration(import)) => {
+                let result = self.handle_configuration_import(import);
+                LogEvent::command(command, None, &result).emit();
+                let outcome = Outcome::of(&result);
+                let response_code: ResponseCode = result.into();
+                self.server.send(client, response_code)?;
+                outcome
+            }
+            Some(CommandType::DryRun(dry_run)) => self.handle_dry_run(client, dry_run)?,
+            Some(CommandType::Admin(admin)) => {
+                self.handle_admin_command(client, admin, max_response_size)?
+            }
+            Some(CommandType::SetTags(entity_tags)) => {
+                let entity_name = entity_tags.entity_name.clone();
+                let result = self.handle_set_tags(entity_tags);
+                LogEvent::command(command, Some(&entity_name), &result).emit();
+                let outcome = Outcome::of(&result);
                 let response_code: ResponseCode = result.into();
-                self.server.send(response_code)?;
+                self.server.send(client, response_code)?;
+                outcome
+            }
+            Some(CommandType::TaggedAction(tagged_command)) => {
+                let result = self.handle_tagged_command(tagged_command);
+                LogEvent::command(command, None, &result).emit();
+                if let Err(e) = &result {
+                    self.app_state
+                        .record_error(format!("Failed to handle tagged command: {e:#}"));
+                }
+                let outcome = Outcome::of(&result);
+                let response_code: ResponseCode = result.into();
+                self.server.send(client, response_code)?;
+                outcome
+            }
+            Some(CommandType::Tombstones(query)) => {
+                self.handle_tombstone_query(client, &query, max_response_size)?;
+                Outcome::Succeeded
+            }
+            Some(CommandType::Ghosts(_)) => {
+                self.handle_ghost_query(client, max_response_size)?;
+                Outcome::Succeeded
+            }
+            Some(CommandType::NextChunk(request)) => self.handle_chunk_request(client, &request)?,
+            Some(CommandType::CaptureSnapshot(capture)) if !self.is_admin(&capture.admin_token) => {
+                self.reject_unauthorized(client, command)?
+            }
+            Some(CommandType::CaptureSnapshot(_)) => {
+                self.handle_snapshot_capture(client, max_response_size)?;
+                Outcome::Succeeded
+            }
+            Some(CommandType::RestoreSnapshot(restore)) => {
+                let result = if self.is_admin(&restore.admin_token) {
+                    Snapshot::from_json(&restore.snapshot_json)
+                        .and_then(|snapshot| snapshot.restore(self.app_state))
+                } else {
+                    Err(anyhow::anyhow!(
+                        "Rejected snapshot restore with invalid admin token"
+                    ))
+                };
+                LogEvent::command(command, None, &result).emit();
+                let outcome = Outcome::of(&result);
+                let report = result.unwrap_or_else(|e| SnapshotRestoreReport {
+                    error: format!("{e:#}"),
+                    ..Default::default()
+                });
+                self.server
+                    .send(client, report)
+                    .context("Failed to send snapshot restore report")?;
+                outcome
+            }
+            Some(CommandType::Rules(rule_command)) => {
+                let result = rules::apply(self.app_state, rule_command);
+                LogEvent::command(command, None, &result).emit();
+                let outcome = Outcome::of(&result);
+                let rules = rules::list(
+                    &self
+                        .app_state
+                        .configuration
+                        .read()
+                        .expect("non-poisoned RwLock"),
+                );
+                let rule_list = RuleList {
+                    rules,
+                    error: result.err().map_or_else(String::new, |e| format!("{e:#}")),
+                };
+                self.server
+                    .send(client, rule_list)
+                    .context("Failed to send rule list")?;
+                outcome
+            }
+            Some(CommandType::Hello(hello)) => {
+                let welcome = Welcome::current();
+                if hello.protocol_version == welcome.protocol_version {
+                    tracing::info!(
+                        "Client connected with protocol version {}",
+                        hello.protocol_version
+                    );
+                } else {
+                    tracing::warn!(
+                        "Client uses protocol version {} but controller uses {}",
+                        hello.protocol_version,
+                        welcome.protocol_version
+                    );
+                }
+                self.server.send(client, welcome)?;
+                Outcome::Succeeded
             }
             None => {
                 tracing::error!("Failed to handle request: Missing command in ClientApiCommand.");
                 let response_code: ResponseCode =
                     Err::<(), _>(anyhow::anyhow!("Missing command in ClientApiCommand")).into();
-                self.server.send(response_code)?;
+                self.server.send(client, response_code)?;
+                Outcome::Failed
             }
+        };
+
+        Ok(outcome)
+    }
+
+    /// Replies immediately unless the query waits for a change of the known state.
+    fn handle_system_state_query(
+        &self,
+        client: &RoutingEnvelope,
+        query: SystemStateQuery,
+        max_response_size: usize,
+    ) -> anyhow::Result<Outcome> {
+        if query.wait_timeout_ms > 0 && query.known_generation == self.app_state.generation() {
+            let timeout =
+                Duration::from_millis(query.wait_timeout_ms.into()).min(MAX_WAIT_FOR_CHANGE);
+            tracing::debug!(?timeout, "Deferring state query until the state changes");
+            self.pending.borrow_mut().push(PendingQuery {
+                client: client.clone(),
+                tag: query.tag,
+                known_generation: query.known_generation,
+                deadline: Instant::now() + timeout,
+                max_response_size,
+            });
+            return Ok(Outcome::Deferred);
         }
+        self.send_system_state(client, &query.tag, max_response_size)?;
+        Ok(Outcome::Succeeded)
+    }
 
+    /// Answers the waiting state queries whose state changed or whose timeout expired.
+    fn answer_pending_queries(&self) -> anyhow::Result<()> {
+        let generation = self.app_state.generation();
+        let now = Instant::now();
+        let due: Vec<_> = {
+            let mut pending = self.pending.borrow_mut();
+            let (due, waiting) = std::mem::take(&mut *pending)
+                .into_iter()
+                .partition(|query| query.known_generation != generation || query.deadline <= now);
+            *pending = waiting;
+            due
+        };
+        for query in due {
+            self.send_system_state(&query.client, &query.tag, query.max_response_size)?;
+        }
         Ok(())
     }
 
-    fn handle_system_state_query(&self) -> anyhow::Result<()> {
-        let system_state = {
-            use home_automation_common::EntityState;
-            use std::collections::HashMap;
-
-            let mut sensors = HashMap::new();
-            let mut actuators = HashMap::new();
-            let mut new_sensors = Vec::new();
-            let mut new_actuators = Vec::new();
-
-            for entity_entry in &self.app_state.entities {
-                let (name, state) = entity_entry.pair();
-                match &state.state {
-                    EntityState::Sensor(measurement) => {
-                        sensors.insert(name.to_owned(), measurement.clone());
-                    }
-                    EntityState::Actuator(state) => {
-                        actuators.insert(name.to_owned(), state.clone());
-                    }
-                    EntityState::New(EntityType::Sensor) => new_sensors.push(name.to_owned()),
-                    EntityState::New(EntityType::Actuator) => new_actuators.push(name.to_owned()),
-                }
+    /// Replies with the state of all entities or only of those with the tag if it is not empty.
+    ///
+    /// The encoded state is reused for all queries until the state changes, but at most for
+    /// [`SYSTEM_STATE_CACHE_MAX_AGE`].
+    fn send_system_state(
+        &self,
+        client: &RoutingEnvelope,
+        tag: &str,
+        max_response_size: usize,
+    ) -> anyhow::Result<()> {
+        // read before collecting so that concurrent changes are reported by the next query
+        let generation = self.app_state.generation();
+        let mut cache = self.system_state_cache.borrow_mut();
+        if cache.generation != generation {
+            cache.generation = generation;
+            cache.by_tag.clear();
+        }
+        let packed = match cache.by_tag.entry(tag.to_owned()) {
+            Entry::Occupied(o) if o.get().collected_at.elapsed() < SYSTEM_STATE_CACHE_MAX_AGE => {
+                tracing::debug!(generation, "Reusing cached system state.");
+                &o.into_mut().packed
+            }
+            entry => {
+                let system_state = self.collect_system_state(tag, generation);
+                tracing::debug!(
+                    system_state = %system_state.summary(),
+                    "Prepared system state response for sending."
+                );
+                let cached = CachedState {
+                    collected_at: Instant::now(),
+                    packed: PackedMessage::new(&system_state)?,
+                };
+                &entry.insert_entry(cached).into_mut().packed
             }
+        };
 
-            SystemState {
-                sensors,
-                actuators,
-                new_sensors,
-                new_actuators,
+        self.send_large(client, packed, max_response_size)
+            .context("Failed to send system state response")
+    }
+
+    /// Sends the response, or only its first chunk if it is larger than the client accepts.
+    fn send_large(
+        &self,
+        client: &RoutingEnvelope,
+        packed: &PackedMessage,
+        max_response_size: usize,
+    ) -> anyhow::Result<()> {
+        if max_response_size == 0 || packed.encoded_len() <= max_response_size {
+            self.server.send_packed(client, packed)?;
+            return Ok(());
+        }
+        let response_id = self.last_response_id.get() + 1;
+        self.last_response_id.set(response_id);
+        let chunks = chunk::split(packed, response_id, max_response_size.max(MIN_CHUNK_SIZE));
+        tracing::debug!(
+            response_id,
+            chunks = chunks.len(),
+            "Splitting {} into chunks",
+            packed.type_url()
+        );
+        let first = chunks[0].clone();
+        let mut responses = self.chunked_responses.borrow_mut();
+        while responses.len() >= MAX_CHUNKED_RESPONSES {
+            if let Some((dropped, _)) = responses.pop_first() {
+                tracing::warn!(
+                    dropped,
+                    "Too many split responses, dropping response {dropped}"
+                );
             }
+        }
+        responses.insert(
+            response_id,
+            ChunkedResponse {
+                chunks,
+                expires: Instant::now() + CHUNK_EXPIRY,
+            },
+        );
+        drop(responses);
+        self.server.send(client, first)?;
+        Ok(())
+    }
+
+    fn handle_chunk_request(
+        &self,
+        client: &RoutingEnvelope,
+        request: &ChunkRequest,
+    ) -> anyhow::Result<Outcome> {
+        let chunk = {
+            let mut responses = self.chunked_responses.borrow_mut();
+            let response = responses.get(&request.response_id);
+            let chunk = response
+                .and_then(|response| response.chunks.get(request.sequence as usize))
+                .cloned();
+            // the client requests the chunks in order, so the last one completes the response
+            if response
+                .is_some_and(|response| response.chunks.len() == request.sequence as usize + 1)
+            {
+                responses.remove(&request.response_id);
+            }
+            chunk
+        };
+        let Some(chunk) = chunk else {
+            let response_code: ResponseCode = Err::<(), _>(anyhow::anyhow!(
+                "Unknown chunk {} of response {}, it may have expired",
+                request.sequence,
+                request.response_id
+            ))
+            .into();
+            self.server.send(client, response_code)?;
+            return Ok(Outcome::Failed);
         };
+        self.server
+            .send(client, chunk)
+            .context("Failed to send response chunk")?;
+        Ok(Outcome::Succeeded)
+    }
+
+    fn collect_system_state(&self, tag: &str, generation: u64) -> SystemState {
+        let rooms = self
+            .app_state
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock")
+            .rooms
+            .clone();
+        let mut builder = SystemStateBuilder::new(generation);
+        for entity_entry in &self.app_state.entities {
+            let (name, entity) = entity_entry.pair();
+            if tag.is_empty() || entity.tags.contains(tag) {
+                builder.add(name, &entity.state, entity.published_at_ms);
+                builder.heartbeat(name, entity.last_heartbeat_pulse.elapsed());
+                builder.metadata(
+                    name,
+                    EntityMetadata {
+                        rooms: rooms
+                            .iter()
+                            .filter(|(_, entities)| entities.contains(name))
+                            .map(|(room, _)| room.clone())
+                            .collect(),
+                        tags: entity.tags.iter().cloned().collect(),
+                        back_channel_healthy: entity.back_channel_healthy.load(Ordering::SeqCst),
+                        heartbeat_interval_ms: entity
+                            .heartbeat_interval
+                            .as_millis()
+                            .try_into()
+                            .unwrap_or(u32::MAX),
+                        description: entity.description.clone(),
+                    },
+                );
+            }
+        }
+        builder.build()
+    }
+
+    /// Answers a command that requires the admin token with an error code.
+    fn reject_unauthorized(
+        &self,
+        client: &RoutingEnvelope,
+        command: &str,
+    ) -> anyhow::Result<Outcome> {
+        let result: anyhow::Result<()> = Err(anyhow::anyhow!(
+            "Rejected {command} command with invalid admin token"
+        ));
+        LogEvent::command(command, None, &result).emit();
+        let response_code: ResponseCode = result.into();
+        self.server.send(client, response_code)?;
+        Ok(Outcome::Failed)
+    }
 
-        tracing::debug!(?system_state, "Prepared system state response for sending.");
+    fn handle_configuration_export(&self, client: &RoutingEnvelope) -> anyhow::Result<()> {
+        let configuration_json = self
+            .app_state
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock")
+            .redacted()
+            .to_json()?;
 
         self.server
-            .send(system_state)
-            .context("Failed to send system state response")
+            .send(client, ConfigurationDocument { configuration_json })
+            .context("Failed to send configuration document")
     }
 
-    fn handle_entity_state_command(&self, entity_state: NamedEntityState) -> anyhow::Result<()> {
-        use home_automation_common::protobuf::response_code::Code;
-        let entity_name = entity_state.entity_name.clone();
-
-        let entity = self.app_state.entities.get(&entity_name).with_context(|| {
-            anyhow::anyhow!(
-                "Unknown entity {} in NamedEntityState command",
-                &entity_state.entity_name
-            )
-        })?;
-
-        let response_code: ResponseCode = {
-            tracing::debug!(?entity_state, "Forwarding command via back-channel.");
-            let connection = entity.connection.lock().expect("poisoned mutex");
-
-            connection.send(entity_state)?;
-            connection.receive()?
+    fn handle_configuration_import(&self, import: ConfigurationImport) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.is_admin(&import.admin_token),
+            "Rejected configuration import with invalid admin token"
+        );
+        let mut configuration = Configuration::from_json(&import.configuration_json)?;
+        configuration.keep_secrets(
+            &self
+                .app_state
+                .configuration
+                .read()
+                .expect("non-poisoned RwLock"),
+        )?;
+        tracing::debug!(?configuration, "Replacing controller configuration.");
+        self.app_state
+            .replace_configuration(configuration, "configuration import");
+        Ok(())
+    }
+
+    fn handle_snapshot_capture(
+        &self,
+        client: &RoutingEnvelope,
+        max_response_size: usize,
+    ) -> anyhow::Result<()> {
+        let snapshot_json = Snapshot::capture(self.app_state).to_json()?;
+        let packed = PackedMessage::new(&SnapshotDocument { snapshot_json })?;
+        self.send_large(client, &packed, max_response_size)
+            .context("Failed to send snapshot document")
+    }
+
+    fn handle_dry_run(
+        &self,
+        client: &RoutingEnvelope,
+        dry_run: AutomationDryRun,
+    ) -> anyhow::Result<Outcome> {
+        let report = {
+            let configuration = self
+                .app_state
+                .configuration
+                .read()
+                .expect("non-poisoned RwLock");
+            let planned = match dry_run.target {
+                Some(Target::Scene(scene)) => rules::plan_scene(&configuration, &scene),
+                Some(Target::Rules(())) => Ok(rules::plan_rules(&configuration, self.app_state)),
+                None => Err(anyhow::anyhow!("Missing target in AutomationDryRun")),
+            };
+            match planned {
+                Ok(commands) => DryRunReport {
+                    commands,
+                    error: String::new(),
+                },
+                Err(e) => DryRunReport {
+                    commands: Vec::new(),
+                    error: format!("{e:#}"),
+                },
+            }
         };
 
-        match response_code.code() {
-            Code::Ok => Ok(()),
-            Code::Error => Err(anyhow::anyhow!("Failed to update entity {entity_name}")),
+        tracing::debug!(report = %report.summary(), "Prepared dry run report for sending.");
+
+        let outcome = if report.error.is_empty() {
+            Outcome::Succeeded
+        } else {
+            Outcome::Failed
+        };
+        self.server
+            .send(client, report)
+            .context("Failed to send dry run report")?;
+        Ok(outcome)
+    }
+
+    fn handle_set_tags(&self, entity_tags: EntityTags) -> anyhow::Result<()> {
+        let mut entity = self
+            .app_state
+            .entities
+            .get_mut(&entity_tags.entity_name)
+            .with_context(|| {
+                anyhow::anyhow!(
+                    "Unknown entity {} in EntityTags command",
+                    entity_tags.entity_name
+                )
+            })?;
+        tracing::debug!(?entity_tags, "Replacing tags of entity.");
+        entity.tags = entity_tags.tags.into_iter().collect();
+        drop(entity);
+        self.app_state.state_changed();
+        Ok(())
+    }
+
+    /// Forwards the command to every entity with the tag and fails if any of them failed.
+    fn handle_tagged_command(&self, tagged_command: TaggedCommand) -> anyhow::Result<()> {
+        let command = tagged_command
+            .command
+            .context("Missing command in TaggedCommand")?;
+        let entity_names: Vec<_> = self
+            .app_state
+            .entities
+            .iter()
+            .filter(|entity| entity.tags.contains(&tagged_command.tag))
+            .map(|entity| entity.key().clone())
+            .collect();
+        anyhow::ensure!(
+            !entity_names.is_empty(),
+            "No entity with tag {}",
+            tagged_command.tag
+        );
+
+        let failed: Vec<_> = entity_names
+            .into_iter()
+            .filter(|entity_name| {
+                // the signature covers the entity name, so the controller signs the copies
+                self.handle_entity_state_command(command.addressed_to(entity_name.as_str()))
+                    .inspect_err(|e| tracing::warn!(%e, "Tagged command failed: {e:#}"))
+                    .is_err()
+            })
+            .collect();
+        anyhow::ensure!(
+            failed.is_empty(),
+            "Failed to update entities {}",
+            failed.join(", ")
+        );
+        Ok(())
+    }
+
+    /// Admin commands are only accepted with the configured admin token, without one they are
+    /// disabled.
+    fn is_admin(&self, token: &str) -> bool {
+        self.admin_token
+            .as_deref()
+            .is_some_and(|configured| token_matches(configured, token))
+    }
+
+    fn handle_admin_command(
+        &self,
+        client: &RoutingEnvelope,
+        admin: AdminCommand,
+        max_response_size: usize,
+    ) -> anyhow::Result<Outcome> {
+        use admin_command::Command;
+        let authorized = self.is_admin(&admin.token);
+        let result = match admin.command {
+            _ if !authorized => Err(anyhow::anyhow!("Rejected admin command with invalid token")),
+            Some(Command::Query(_)) => {
+                self.handle_admin_query(client, max_response_size)?;
+                return Ok(Outcome::Succeeded);
+            }
+            Some(Command::Performance(_)) => {
+                self.handle_performance_query(client, max_response_size)?;
+                return Ok(Outcome::Succeeded);
+            }
+            Some(Command::ForceUnregister(entity_name)) => {
+                tracing::info!("Unregistering entity {entity_name} because of admin request");
+                self.app_state
+                    .unregister(&entity_name, "forced by administrator")
+            }
+            Some(Command::Ping(entity_name)) => self.app_state.ping(&entity_name),
+            Some(Command::Shutdown(entity_name)) => {
+                tracing::info!("Shutting down entity {entity_name} because of admin request");
+                self.app_state
+                    .forward_to_entity(NamedEntityState::lifecycle(entity_name, Action::Shutdown))
+            }
+            Some(Command::Restart(entity_name)) => {
+                tracing::info!("Restarting entity {entity_name} because of admin request");
+                self.app_state
+                    .forward_to_entity(NamedEntityState::lifecycle(entity_name, Action::Restart))
+            }
+            None => Err(anyhow::anyhow!("Missing command in AdminCommand")),
+        };
+        LogEvent::command("Admin", None, &result).emit();
+        if let Err(e) = &result {
+            self.app_state
+                .record_error(format!("Failed to handle admin command: {e:#}"));
         }
+        let outcome = Outcome::of(&result);
+        let response_code: ResponseCode = result.into();
+        self.server.send(client, response_code)?;
+        Ok(outcome)
+    }
+
+    fn handle_tombstone_query(
+        &self,
+        client: &RoutingEnvelope,
+        query: &TombstoneQuery,
+        max_response_size: usize,
+    ) -> anyhow::Result<()> {
+        use home_automation_common::protobuf::{
+            archived_state, tombstone::LastState, ArchivedState, Tombstone, TombstoneList,
+        };
+        self.app_state.purge_archive();
+        let tombstones = self
+            .app_state
+            .tombstones
+            .lock()
+            .expect("non-poisoned Mutex")
+            .iter()
+            .filter(|tombstone| query.entity_name.is_empty() || tombstone.name == query.entity_name)
+            .map(|tombstone| Tombstone {
+                name: tombstone.name.clone(),
+                entity_type: tombstone.last_state.entity_type().into(),
+                last_state: tombstone
+                    .last_state
+                    .published(LastState::Measurement, LastState::ActuatorState),
+                reason: tombstone.reason.clone(),
+                age_seconds: tombstone.removed_at.elapsed().as_secs_f32(),
+                rejoins: tombstone.rejoins,
+                tags: tombstone.tags.iter().cloned().collect(),
+                address: tombstone.address.clone(),
+                history: if query.include_history {
+                    tombstone
+                        .history
+                        .iter()
+                        .map(|entry| ArchivedState {
+                            published_at_ms: entry.published_at_ms,
+                            state: entry.state.published(
+                                archived_state::State::Measurement,
+                                archived_state::State::ActuatorState,
+                            ),
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+            })
+            .collect();
+        let packed = PackedMessage::new(&TombstoneList { tombstones })?;
+        self.send_large(client, &packed, max_response_size)
+            .context("Failed to send tombstone list")
+    }
+
+    fn handle_ghost_query(
+        &self,
+        client: &RoutingEnvelope,
+        max_response_size: usize,
+    ) -> anyhow::Result<()> {
+        use home_automation_common::protobuf::{ghost::LastState, Ghost, GhostList};
+        let strict = self
+            .app_state
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock")
+            .strict
+            .enabled;
+        let ghosts = self
+            .app_state
+            .ghosts
+            .lock()
+            .expect("non-poisoned Mutex")
+            .iter()
+            .map(|(name, ghost)| Ghost {
+                name: name.clone(),
+                entity_type: ghost.entity_type.into(),
+                publications: ghost.publications,
+                first_seen_seconds: ghost.first_seen.elapsed().as_secs_f32(),
+                last_seen_seconds: ghost.last_seen.elapsed().as_secs_f32(),
+                last_state: ghost.last_state.as_ref().and_then(|state| {
+                    state.published(LastState::Measurement, LastState::ActuatorState)
+                }),
+            })
+            .collect();
+        let packed = PackedMessage::new(&GhostList { ghosts, strict })?;
+        self.send_large(client, &packed, max_response_size)
+            .context("Failed to send ghost list")
+    }
+
+    fn handle_admin_query(
+        &self,
+        client: &RoutingEnvelope,
+        max_response_size: usize,
+    ) -> anyhow::Result<()> {
+        let mut tasks: Vec<_> = self
+            .app_state
+            .tasks
+            .iter()
+            .map(|task| {
+                let (status, error) = match task.value() {
+                    TaskStatus::Running => (task_health::Status::Running, String::new()),
+                    TaskStatus::Stopped => (task_health::Status::Stopped, String::new()),
+                    TaskStatus::Failed(e) => (task_health::Status::Failed, e.clone()),
+                };
+                TaskHealth {
+                    name: task.key().to_string(),
+                    status: status.into(),
+                    error,
+                }
+            })
+            .collect();
+        tasks.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut entities: Vec<_> = self
+            .app_state
+            .entities
+            .iter()
+            .map(|entity| EntityHealth {
+                name: entity.key().clone(),
+                entity_type: entity.state.entity_type().into(),
+                heartbeat_age_seconds: entity.last_heartbeat_pulse.elapsed().as_secs_f32(),
+                back_channel_healthy: entity.back_channel_healthy.load(Ordering::SeqCst),
+                flapping: self.app_state.is_flapping(entity.key()),
+                suspected: entity.suspected.load(Ordering::SeqCst),
+                ingest_latency: Some(entity.ingest_latency.summary()),
+                clock_offset_ms: entity.clock_offset_ms,
+            })
+            .collect();
+        entities.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let recent_errors = self
+            .app_state
+            .recent_errors
+            .lock()
+            .expect("non-poisoned Mutex")
+            .iter()
+            .map(|(time, message)| ErrorReport {
+                message: message.clone(),
+                age_seconds: time.elapsed().as_secs_f32(),
+            })
+            .collect();
+
+        let admin_state = AdminState {
+            tasks,
+            entities,
+            recent_errors,
+            rejected_requests: self.app_state.rejected_requests.load(Ordering::SeqCst),
+            payload_mismatches: self.app_state.payload_mismatches.load(Ordering::SeqCst),
+            ingest_latency: Some(
+                self.app_state
+                    .ingest_latency
+                    .lock()
+                    .expect("non-poisoned Mutex")
+                    .summary(),
+            ),
+        };
+        tracing::debug!(
+            admin_state = %admin_state.summary(),
+            "Prepared admin state response for sending."
+        );
+
+        let packed = PackedMessage::new(&admin_state)?;
+        self.send_large(client, &packed, max_response_size)
+            .context("Failed to send admin state response")
+    }
+
+    fn handle_performance_query(
+        &self,
+        client: &RoutingEnvelope,
+        max_response_size: usize,
+    ) -> anyhow::Result<()> {
+        let sockets = zmq_sockets::all_statistics()
+            .into_iter()
+            .map(|(socket, statistics)| SocketStatistics {
+                socket,
+                messages_sent: statistics.messages_sent,
+                bytes_sent: statistics.bytes_sent,
+                messages_received: statistics.messages_received,
+                bytes_received: statistics.bytes_received,
+                messages_dropped: statistics.messages_dropped,
+            })
+            .collect();
+        let counters = PerformanceCounters {
+            uptime_ms: self
+                .started
+                .elapsed()
+                .as_millis()
+                .try_into()
+                .unwrap_or(u64::MAX),
+            sockets,
+            commands: self.request_log.performance(),
+            pending_queries: self.pending.borrow().len().try_into().unwrap_or(u32::MAX),
+            chunked_responses: self
+                .chunked_responses
+                .borrow()
+                .len()
+                .try_into()
+                .unwrap_or(u32::MAX),
+        };
+        let packed = PackedMessage::new(&counters)?;
+        self.send_large(client, &packed, max_response_size)
+            .context("Failed to send performance counters")
+    }
+
+    fn handle_entity_state_command(&self, entity_state: NamedEntityState) -> anyhow::Result<()> {
+        use home_automation_common::protobuf::named_entity_state::State;
+        anyhow::ensure!(
+            !matches!(entity_state.state, Some(State::Lifecycle(_))),
+            "Lifecycle commands for entity {} require an admin command",
+            entity_state.entity_name
+        );
+        self.app_state.forward_to_entity(entity_state)
     }
 }
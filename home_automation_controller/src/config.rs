@@ -0,0 +1,531 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use home_automation_common::{
+    log_file::LogFileConfiguration,
+    protobuf::{sensor_measurement::Value, ActuatorState, NamedEntityState, SensorMeasurement},
+    zmq_sockets::BindRetry,
+    UpdateFrequency,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::access::AccessControl;
+
+/// Optional path to a JSON file with the initial controller configuration.
+pub const ENV_CONTROLLER_CONFIG: &str = "HOME_AUTOMATION_CONTROLLER_CONFIG";
+/// Replaces the tokens in exported configurations.
+pub const REDACTED: &str = "<redacted>";
+
+/// User-defined controller configuration that is not tied to the lifetime of a single entity.
+///
+/// The whole document can be exported and imported via the client API as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Configuration {
+    /// Room name mapped to the names of the entities located in the room.
+    pub rooms: BTreeMap<String, BTreeSet<String>>,
+    /// Sensor name mapped to an offset that is added to every received measurement.
+    pub calibration: BTreeMap<String, f32>,
+    /// Named sets of commands that are sent together.
+    pub scenes: BTreeMap<String, Vec<Command>>,
+    pub rules: Vec<Rule>,
+    pub limits: Limits,
+    pub access: AccessControl,
+    /// Additionally logs to rotated files if set, only applied on startup.
+    pub log_file: Option<LogFile>,
+    pub binding: Binding,
+    /// Directory with Rhai scripts (`*.rhai`) that handle events, only loaded on startup.
+    pub scripts: Option<PathBuf>,
+    pub webhooks: Option<Webhooks>,
+    pub http_api: Option<HttpApi>,
+    pub notifications: Notifications,
+    pub flapping: Flapping,
+    pub archive: Archive,
+    pub strict: Strict,
+}
+
+/// Detection of entities that are removed and register again repeatedly.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Flapping {
+    /// Number of removals within the window that mark an entity as flapping, `0` disables it.
+    pub threshold: usize,
+    pub window_secs: u64,
+    /// Time after its last removal in which registrations of a flapping entity are rejected.
+    pub quarantine_secs: u64,
+}
+
+impl Default for Flapping {
+    fn default() -> Self {
+        Self {
+            threshold: 3,
+            window_secs: 600,
+            quarantine_secs: 0,
+        }
+    }
+}
+
+/// Archive of the removed entities with their history, see [`Tombstone`](crate::state::Tombstone).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Archive {
+    /// Number of states kept per entity and archived with it, `0` disables the history.
+    pub history_len: usize,
+    /// Number of archived entities, the oldest are purged first.
+    pub max_entries: usize,
+    /// Age after which archived entities are purged, `0` disables the age limit.
+    pub max_age_secs: u64,
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Self {
+            history_len: 100,
+            max_entries: 50,
+            max_age_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// Tracking of the sources that publish without being registered, see
+/// [`Ghost`](crate::state::Ghost).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Strict {
+    /// Counts the rejected publications per source instead of logging each as error.
+    pub enabled: bool,
+    /// Keeps the last state the ghosts published, without affecting rules, scripts or events.
+    pub quarantine_ghosts: bool,
+    /// Number of tracked ghosts, the least recently seen is dropped first.
+    pub max_ghosts: usize,
+}
+
+impl Default for Strict {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            quarantine_ghosts: false,
+            max_ghosts: 50,
+        }
+    }
+}
+
+/// Chat bots notified about alerts and with a periodic summary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifications {
+    pub telegram: Option<TelegramBot>,
+    pub matrix: Option<MatrixBot>,
+    /// Interval of the summary, `0` disables it.
+    pub summary_interval_hours: u64,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            telegram: None,
+            matrix: None,
+            summary_interval_hours: 24,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelegramBot {
+    pub bot_token: String,
+    pub chat_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatrixBot {
+    /// Base URL of the homeserver, e.g. `https://matrix.org`.
+    pub homeserver: String,
+    pub access_token: String,
+    pub room_id: String,
+}
+
+/// Inbound HTTP endpoint that external systems call via `POST /hooks/<name>` with the token as
+/// bearer authorization. Changing the `endpoint` is only applied on startup.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhooks {
+    /// Address of the HTTP server, e.g. `127.0.0.1:8080`.
+    pub endpoint: String,
+    pub token: String,
+    /// Webhook name mapped to the action it triggers.
+    #[serde(default)]
+    pub hooks: BTreeMap<String, WebhookAction>,
+}
+
+/// HTTP/JSON variant of the client API for dashboards and scripts, e.g. `GET /api/state`.
+/// Changing the `endpoint` is only applied on startup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpApi {
+    /// Address of the HTTP server, e.g. `127.0.0.1:8081`.
+    pub endpoint: String,
+    /// Bearer authorization of every request, the API does not start without one.
+    #[serde(default)]
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum WebhookAction {
+    Scene(String),
+    Command(Command),
+    /// Sets the state of a registered sensor to the `value` of the request body.
+    Measurement(VirtualMeasurement),
+}
+
+/// E.g. `{ "entity": "sen_garden", "kind": "temperature" }` for `{ "value": 21.5 }` as body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualMeasurement {
+    pub entity: String,
+    pub kind: MeasurementKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeasurementKind {
+    Temperature,
+    Humidity,
+}
+
+impl MeasurementKind {
+    pub fn measurement(self, value: f32) -> SensorMeasurement {
+        match self {
+            MeasurementKind::Temperature => SensorMeasurement::temperature(value),
+            MeasurementKind::Humidity => SensorMeasurement::humidity(value),
+        }
+    }
+}
+
+/// Retries binding the discovery and client API endpoints while they are in use, e.g. by a
+/// controller that is still shutting down.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Binding {
+    pub retries: u32,
+    /// Wait time before the first retry, doubled for every further retry.
+    pub backoff_ms: u64,
+    /// Number of ports following the configured one that are tried if it is in use.
+    pub port_fallback: u16,
+}
+
+impl Default for Binding {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            backoff_ms: 200,
+            port_fallback: 0,
+        }
+    }
+}
+
+impl From<&Binding> for BindRetry {
+    fn from(binding: &Binding) -> Self {
+        Self {
+            retries: binding.retries,
+            initial_backoff: Duration::from_millis(binding.backoff_ms),
+            port_fallback: binding.port_fallback,
+        }
+    }
+}
+
+/// Log files of the controller, e.g. `{ "directory": "logs" }` for the defaults in `logs`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogFile {
+    pub directory: PathBuf,
+    /// File name, `{time}` is replaced by the start time of the controller. A name without it
+    /// results in a single log file for all runs.
+    pub name: String,
+    /// Number of log files that are kept, including the rotated ones.
+    pub max_files: usize,
+    /// Size after which the log file is rotated, `0` disables the rotation.
+    pub max_size_mib: u64,
+}
+
+impl Default for LogFile {
+    fn default() -> Self {
+        let defaults = LogFileConfiguration::new("controller");
+        Self {
+            directory: defaults.directory,
+            name: defaults.template,
+            max_files: defaults.max_files,
+            max_size_mib: defaults.max_size.unwrap_or_default() / 1024 / 1024,
+        }
+    }
+}
+
+impl From<&LogFile> for LogFileConfiguration {
+    fn from(log_file: &LogFile) -> Self {
+        Self {
+            directory: log_file.directory.clone(),
+            template: log_file.name.clone(),
+            max_files: log_file.max_files,
+            max_size: (log_file.max_size_mib > 0).then(|| log_file.max_size_mib * 1024 * 1024),
+        }
+    }
+}
+
+/// Caps enforced when entities register, protecting the controller from misconfigured scripts.
+///
+/// Unset limits are not enforced.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_entities: Option<usize>,
+    pub max_sensors: Option<usize>,
+    pub max_actuators: Option<usize>,
+    /// Maximum number of entities registered from the same IP address.
+    pub max_entities_per_address: Option<usize>,
+}
+
+/// Sends the `actions` whenever the `condition` is met.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    pub condition: Condition,
+    pub actions: Vec<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    pub entity: String,
+    pub comparison: Comparison,
+    pub value: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+/// A single state update for an entity, e.g. `{ "entity": "act_light", "brightness": 50.0 }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Command {
+    pub entity: String,
+    #[serde(flatten)]
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    Brightness(f32),
+    AirConditioning(bool),
+    UpdateFrequency(#[serde(with = "update_frequency_hz")] UpdateFrequency),
+}
+
+/// Stores an [`UpdateFrequency`] as plain number of Hz.
+mod update_frequency_hz {
+    use home_automation_common::UpdateFrequency;
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        frequency: &UpdateFrequency,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(frequency.hz())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<UpdateFrequency, D::Error> {
+        UpdateFrequency::from_hz(f32::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+impl From<&Command> for NamedEntityState {
+    fn from(command: &Command) -> Self {
+        let name = command.entity.clone();
+        match command.target {
+            Target::Brightness(brightness) => {
+                NamedEntityState::actuator(name, ActuatorState::light(brightness))
+            }
+            Target::AirConditioning(on) => {
+                NamedEntityState::actuator(name, ActuatorState::air_conditioning(on))
+            }
+            Target::UpdateFrequency(frequency) => NamedEntityState::frequency(name, frequency),
+        }
+    }
+}
+
+impl TryFrom<&NamedEntityState> for Command {
+    type Error = anyhow::Error;
+
+    fn try_from(entity_state: &NamedEntityState) -> anyhow::Result<Self> {
+        use home_automation_common::protobuf::{actuator_state, named_entity_state::State};
+        let target = match &entity_state.state {
+            Some(State::SensorConfiguration(configuration)) => Target::UpdateFrequency(
+                UpdateFrequency::from_hz(configuration.update_frequency_hz)?,
+            ),
+            Some(State::ActuatorState(ActuatorState {
+                state: Some(actuator_state::State::Light(light)),
+            })) => Target::Brightness(light.brightness),
+            Some(State::ActuatorState(ActuatorState {
+                state: Some(actuator_state::State::AirConditioning(ac)),
+            })) => Target::AirConditioning(ac.on),
+            _ => anyhow::bail!(
+                "Unsupported command for {}, only actuator states and update frequencies",
+                entity_state.entity_name
+            ),
+        };
+        Ok(Self {
+            entity: entity_state.entity_name.clone(),
+            target,
+        })
+    }
+}
+
+impl Configuration {
+    /// Loads the configuration from the file given in [`ENV_CONTROLLER_CONFIG`] or
+    /// returns an empty configuration if the variable is not set.
+    pub fn load() -> anyhow::Result<Self> {
+        // called before the logger is set up because it may log to a file, so nothing is logged
+        let Ok(path) = std::env::var(ENV_CONTROLLER_CONFIG) else {
+            return Ok(Self::default());
+        };
+        let json = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read configuration file {path}"))?;
+        Self::from_json(&json).with_context(|| format!("Invalid configuration file {path}"))
+    }
+
+    /// Returns the names of the sections that differ in the other configuration.
+    pub fn changed_sections(&self, other: &Self) -> Vec<&'static str> {
+        let Self {
+            rooms,
+            calibration,
+            scenes,
+            rules,
+            limits,
+            access,
+            log_file,
+            binding,
+            scripts,
+            webhooks,
+            http_api,
+            notifications,
+            flapping,
+            archive,
+            strict,
+        } = self;
+        [
+            ("rooms", *rooms != other.rooms),
+            ("calibration", *calibration != other.calibration),
+            ("scenes", *scenes != other.scenes),
+            ("rules", *rules != other.rules),
+            ("limits", *limits != other.limits),
+            ("access", *access != other.access),
+            ("log_file", *log_file != other.log_file),
+            ("binding", *binding != other.binding),
+            ("scripts", *scripts != other.scripts),
+            ("webhooks", *webhooks != other.webhooks),
+            ("http_api", *http_api != other.http_api),
+            ("notifications", *notifications != other.notifications),
+            ("flapping", *flapping != other.flapping),
+            ("archive", *archive != other.archive),
+            ("strict", *strict != other.strict),
+        ]
+        .into_iter()
+        .filter_map(|(section, changed)| changed.then_some(section))
+        .collect()
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let configuration: Self =
+            serde_json::from_str(json).context("Failed to parse configuration")?;
+        if let Some(webhooks) = &configuration.webhooks {
+            // would authorize `Authorization: Bearer ` otherwise
+            anyhow::ensure!(
+                !webhooks.token.is_empty(),
+                "webhooks.token must not be empty"
+            );
+        }
+        Ok(configuration)
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize configuration")
+    }
+
+    /// Returns a copy with the tokens replaced by [`REDACTED`], e.g. for an export.
+    pub fn redacted(&self) -> Self {
+        let mut redacted = self.clone();
+        for (_, secret) in redacted.secrets_mut() {
+            if let Some(secret) = secret.filter(|secret| !secret.is_empty()) {
+                *secret = REDACTED.to_owned();
+            }
+        }
+        redacted
+    }
+
+    /// Takes the tokens that are [`REDACTED`] from the current configuration, so a redacted
+    /// export can be imported again.
+    pub fn keep_secrets(&mut self, current: &Self) -> anyhow::Result<()> {
+        let mut current = current.clone();
+        for ((section, secret), (_, current_secret)) in
+            self.secrets_mut().into_iter().zip(current.secrets_mut())
+        {
+            let Some(secret) = secret.filter(|secret| secret.as_str() == REDACTED) else {
+                continue;
+            };
+            *secret = current_secret
+                .with_context(|| format!("{section} is redacted, but not configured yet"))?
+                .clone();
+        }
+        Ok(())
+    }
+
+    fn secrets_mut(&mut self) -> [(&'static str, Option<&mut String>); 4] {
+        [
+            (
+                "webhooks.token",
+                self.webhooks.as_mut().map(|w| &mut w.token),
+            ),
+            (
+                "http_api.token",
+                self.http_api.as_mut().map(|h| &mut h.token),
+            ),
+            (
+                "notifications.telegram.bot_token",
+                self.notifications
+                    .telegram
+                    .as_mut()
+                    .map(|t| &mut t.bot_token),
+            ),
+            (
+                "notifications.matrix.access_token",
+                self.notifications
+                    .matrix
+                    .as_mut()
+                    .map(|m| &mut m.access_token),
+            ),
+        ]
+    }
+
+    /// Applies the configured calibration offset of the sensor to the measurement.
+    pub fn calibrate(&self, sensor_name: &str, measurement: &mut SensorMeasurement) {
+        let Some(offset) = self.calibration.get(sensor_name) else {
+            return;
+        };
+        match &mut measurement.value {
+            Some(Value::Temperature(t)) => t.temperature += offset,
+            Some(Value::Humidity(h)) => h.humidity += offset,
+            None => {}
+        }
+    }
+}
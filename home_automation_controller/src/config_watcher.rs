@@ -0,0 +1,60 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{config::Configuration, state::AppState};
+
+/// Interval in which the configuration file is checked for modifications.
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reloads the configuration file whenever it is modified.
+pub struct ConfigWatcherTask<'a> {
+    app_state: &'a AppState,
+    path: PathBuf,
+}
+
+impl<'a> ConfigWatcherTask<'a> {
+    pub fn new(app_state: &'a AppState, path: PathBuf) -> Self {
+        Self { app_state, path }
+    }
+
+    #[tracing::instrument(name = "Configuration watcher", skip(self), fields(path = %self.path.display()))]
+    pub fn run(&self) -> anyhow::Result<()> {
+        tracing::info!("Watching configuration file for changes.");
+        let mut last_modified = self.modified();
+        let mut deadline = Instant::now() + CHECK_INTERVAL;
+        while !self.app_state.shutdown.sleep_until_or_shutdown(deadline) {
+            deadline += CHECK_INTERVAL;
+            let modified = self.modified();
+            if modified != last_modified {
+                last_modified = modified;
+                reload(self.app_state, &self.path);
+            }
+        }
+        Ok(())
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
+
+/// Replaces the configuration by the file, an invalid file keeps the current one.
+pub fn reload(app_state: &AppState, path: &Path) {
+    let result = std::fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| Configuration::from_json(&json));
+    match result {
+        Ok(configuration) => app_state.replace_configuration(configuration, "configuration file"),
+        Err(e) => {
+            tracing::warn!("Keeping current configuration: {e:#}");
+            app_state.record_error(format!(
+                "Failed to reload configuration file {}: {e:#}",
+                path.display()
+            ));
+        }
+    }
+}
@@ -1,30 +1,90 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
 use anyhow::Context as _;
 use home_automation_common::{
+    envelope::Headers,
+    latency::{self, SUSPICIOUS_CLOCK_OFFSET_MS},
     load_env,
-    protobuf::{entity_discovery_command, EntityDiscoveryCommand, ResponseCode},
-    shutdown_requested,
-    zmq_sockets::{self, markers::Linked, termination_is_ok},
+    log_event::LogEvent,
+    protobuf::{
+        entity_discovery_command::{self, EntityType},
+        response_code::Code,
+        EntityDiscoveryCommand, ResponseCode,
+    },
+    transport::{self, Channel, Pattern, PEER_ADDRESS_HEADER},
+    zmq_sockets::termination_is_ok,
+    DEFAULT_HEARTBEAT_INTERVAL,
+};
+
+use crate::{
+    authenticator::{self, Authenticator},
+    events::Event,
+    registry,
+    state::{AppState, Entity},
 };
 
-use crate::state::{AppState, Entity};
+/// Interval in which the task checks for shutdown requests while waiting for requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Optional number of measurements a sensor may publish at once, 0 disables batching.
+pub const ENV_MAX_BATCH_SIZE: &str = "HOME_AUTOMATION_MAX_BATCH_SIZE";
+const DEFAULT_MAX_BATCH_SIZE: u32 = 50;
+
+/// Bounds of the heartbeat interval an entity can propose, others are overridden.
+const MIN_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How a registration under the name of a registered entity is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Takeover {
+    /// No entity of this name is registered.
+    Vacant,
+    /// The registered instance registers again, e.g. after the answer to its registration was lost.
+    SameInstance,
+    /// The registered entity is suspected or does not answer anymore and is replaced.
+    Replace,
+}
 
 pub struct EntityDiscoveryTask<'a> {
     app_state: &'a AppState,
-    server: zmq_sockets::Replier<Linked>,
+    server: Box<dyn Channel>,
+    max_batch_size: u32,
+    authenticator: Box<dyn Authenticator>,
 }
 
 impl<'a> EntityDiscoveryTask<'a> {
     pub fn new(app_state: &'a AppState) -> anyhow::Result<Self> {
         let address = load_env(home_automation_common::ENV_DISCOVERY_ENDPOINT)?;
-        let server = zmq_sockets::Replier::new(&app_state.context)?.bind(&address)?;
-        Ok(Self { app_state, server })
+        let server = app_state.transport().bind(Pattern::Reply, &address)?;
+        tracing::info!(transport = %app_state.transport, "Entities can register at {address}");
+        let max_batch_size = match load_env(ENV_MAX_BATCH_SIZE) {
+            Ok(size) => size
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid {ENV_MAX_BATCH_SIZE} {size:?}: {e}"))?,
+            Err(_) => DEFAULT_MAX_BATCH_SIZE,
+        };
+        let authenticator = authenticator::from_env()?;
+        tracing::info!("Registration authentication: {}", authenticator.describe());
+        Ok(Self {
+            app_state,
+            server,
+            max_batch_size,
+            authenticator,
+        })
     }
 
     #[tracing::instrument(name = "entity discovery", skip(self))]
     pub fn run(&self) -> anyhow::Result<()> {
         tracing::info!("Starting entity discovery task");
-        while !shutdown_requested() {
-            let Err(e) = self.accept_entity() else {
+        while !self.app_state.shutdown.is_requested() {
+            let result = self
+                .server
+                .receive_message(Some(POLL_INTERVAL))
+                .and_then(|request| match request {
+                    Some((request, headers)) => self.accept_entity(request, headers),
+                    None => Ok(()),
+                });
+            let Err(e) = result else {
                 continue;
             };
             return Err(e)
@@ -34,15 +94,81 @@ impl<'a> EntityDiscoveryTask<'a> {
         Ok(())
     }
 
-    #[tracing::instrument(skip(self))]
-    fn accept_entity(&self) -> anyhow::Result<()> {
-        let (request, ip): (EntityDiscoveryCommand, _) = self.server.receive_with_ip()?;
+    #[tracing::instrument(skip(self, headers))]
+    fn accept_entity(
+        &self,
+        request: EntityDiscoveryCommand,
+        headers: Headers,
+    ) -> anyhow::Result<()> {
+        transport::follow_remote_span(&headers);
+        let ip = headers
+            .get(PEER_ADDRESS_HEADER)
+            .cloned()
+            .context("Missing address of the entity")?;
+
+        if let Err(e) = self.app_state.check_access(|access| &access.discovery, &ip) {
+            let response: ResponseCode = Err::<(), _>(e).into();
+            self.server.send_message(None, &response)?;
+            return Ok(());
+        }
+        if let Err(e) = self
+            .authenticator
+            .authenticate(&request.entity_name, &request.auth_token)
+        {
+            tracing::warn!(address = %ip, "Rejected discovery command: {e:#}");
+            self.app_state
+                .record_error(format!("Rejected discovery command from {ip}: {e:#}"));
+            let response: ResponseCode = Err::<(), _>(e).into();
+            self.server.send_message(None, &response)?;
+            return Ok(());
+        }
 
+        let entity_name = request.entity_name.clone();
+        let is_heartbeat = matches!(
+            request.command,
+            Some(entity_discovery_command::Command::Heartbeat(_))
+        );
+        let requested_batch_size = match &request.command {
+            Some(entity_discovery_command::Command::Register(registration))
+                if request.entity_type() == EntityType::Sensor =>
+            {
+                registration.max_batch_size
+            }
+            _ => 0,
+        };
+        let heartbeat_interval = match &request.command {
+            Some(entity_discovery_command::Command::Register(registration)) => Some(
+                negotiate_heartbeat_interval(registration.heartbeat_interval_ms),
+            ),
+            _ => None,
+        };
         let result = self.handle_command(request, ip);
-        tracing::info!(?result, "Finished handling command with result {result:?}");
+        LogEvent::command("EntityDiscovery", Some(&entity_name), &result).emit();
+        if let Err(e) = &result {
+            self.app_state.record_entity_error(
+                &entity_name,
+                format!("Failed to handle entity discovery command: {e:#}"),
+            );
+        }
 
-        let response: ResponseCode = result.into();
-        self.server.send(response)?;
+        let mut response: ResponseCode = result.into();
+        if is_heartbeat {
+            // lets the entity estimate the offset of its clock
+            response.controller_time_ms = latency::unix_time_ms();
+        }
+        if matches!(response.code(), Code::Ok) {
+            let batch_size = requested_batch_size.min(self.max_batch_size);
+            // a single measurement is published without batch
+            if batch_size > 1 {
+                tracing::info!("Entity {entity_name} publishes {batch_size} measurements at once");
+                response.batch_size = batch_size;
+            }
+            if let Some(interval) = heartbeat_interval {
+                response.heartbeat_interval_ms =
+                    interval.as_millis().try_into().unwrap_or(u32::MAX);
+            }
+        }
+        self.server.send_message(None, &response)?;
 
         Ok(())
     }
@@ -55,6 +181,55 @@ impl<'a> EntityDiscoveryTask<'a> {
         match request.command {
             Some(Command::Register(registration)) => {
                 tracing::info!("Trying to register entity {}", request.entity_name);
+                if let Some(remaining) = self.app_state.quarantine(&request.entity_name) {
+                    anyhow::bail!(
+                        "Entity {} is quarantined for flapping for another {}s",
+                        request.entity_name,
+                        remaining.as_secs()
+                    );
+                }
+                let tags = registration.tags.into_iter().collect();
+                let heartbeat_interval =
+                    negotiate_heartbeat_interval(registration.heartbeat_interval_ms);
+                match self.takeover(&request.entity_name, request.session_id)? {
+                    Takeover::Vacant => {}
+                    Takeover::SameInstance => {
+                        tracing::info!(
+                            "Entity {} registered again with the same session",
+                            request.entity_name
+                        );
+                        let requester = self
+                            .open_back_channel(&ip, registration.port)
+                            .context("Failed to create back-channel")?;
+                        let mut entity = self
+                            .app_state
+                            .entities
+                            .get_mut(&request.entity_name)
+                            .with_context(|| {
+                                anyhow::anyhow!("Entity {} was removed", request.entity_name)
+                            })?;
+                        *entity.connection.get_mut().expect("non-poisoned Mutex") = requester;
+                        entity.back_channel_healthy.store(true, Ordering::SeqCst);
+                        entity.suspected.store(false, Ordering::SeqCst);
+                        entity.last_heartbeat_pulse = std::time::Instant::now();
+                        entity.heartbeat_interval = heartbeat_interval;
+                        entity.tags = tags;
+                        entity.address = ip;
+                        entity.port = registration.port;
+                        drop(entity);
+                        registry::save(self.app_state);
+                        return Ok(());
+                    }
+                    Takeover::Replace => {
+                        tracing::info!(
+                            "Replacing entity {} by a new instance",
+                            request.entity_name
+                        );
+                        self.app_state
+                            .unregister(&request.entity_name, "replaced by a new instance")?;
+                    }
+                }
+                self.check_limits(entity_type, &ip)?;
                 match self.app_state.entities.entry(request.entity_name.clone()) {
                     Entry::Occupied(o) => {
                         anyhow::bail!("Entity {} already registered", o.key());
@@ -62,20 +237,46 @@ impl<'a> EntityDiscoveryTask<'a> {
                     Entry::Vacant(v) => {
                         tracing::info!("Registering entity {}", v.key());
                         let requester = self
-                            .open_back_channel(ip, registration.port)
+                            .open_back_channel(&ip, registration.port)
                             .context("Failed to create back-channel")?;
-                        v.insert(Entity::new(requester, entity_type));
+                        let mut entity = Entity::new(
+                            requester,
+                            entity_type,
+                            tags,
+                            ip,
+                            registration.port,
+                            request.session_id,
+                        );
+                        entity.heartbeat_interval = heartbeat_interval;
+                        v.insert(entity);
                     }
                 }
+                self.app_state.state_changed();
+                registry::save(self.app_state);
+                self.app_state.release_ghost(&request.entity_name);
+                let rejoined = self.app_state.rejoin(&request.entity_name);
+                self.app_state.publish(Event::EntityRegistered {
+                    name: request.entity_name,
+                    entity_type,
+                    rejoined,
+                });
             }
             Some(Command::Unregister(())) => {
+                if let Some(entity) = self.app_state.entities.get(&request.entity_name) {
+                    anyhow::ensure!(
+                        !entity.is_other_instance(request.session_id),
+                        "Ignoring disconnect request of a replaced instance of entity {}",
+                        request.entity_name
+                    );
+                }
                 tracing::info!(
                     "Unregistering entity {} because of disconnect request",
                     request.entity_name
                 );
-                self.app_state.unregister(&request.entity_name)?;
+                self.app_state
+                    .unregister(&request.entity_name, "disconnect request")?;
             }
-            Some(Command::Heartbeat(())) => {
+            Some(Command::Heartbeat(heartbeat)) => {
                 let mut entity = self
                     .app_state
                     .entities
@@ -83,25 +284,117 @@ impl<'a> EntityDiscoveryTask<'a> {
                     .with_context(|| {
                         anyhow::anyhow!("Heartbeat from unknown entity {}", request.entity_name)
                     })?;
+                // makes the replaced instance shut down
+                anyhow::ensure!(
+                    !entity.is_other_instance(request.session_id),
+                    "Heartbeat from a replaced instance of entity {}",
+                    request.entity_name
+                );
                 tracing::info!(
                     "Updating timestamp of entity {} because of heartbeat reception",
                     request.entity_name
                 );
                 entity.last_heartbeat_pulse = std::time::Instant::now();
+                if entity.suspected.swap(false, Ordering::SeqCst) {
+                    tracing::info!("Restored entity {} is alive", request.entity_name);
+                }
+                let offset = heartbeat.clock_offset_ms;
+                if offset.abs() >= SUSPICIOUS_CLOCK_OFFSET_MS
+                    && entity.clock_offset_ms.abs() < SUSPICIOUS_CLOCK_OFFSET_MS
+                {
+                    tracing::warn!(
+                        offset_ms = offset,
+                        "Clock of entity {} is {offset} ms ahead of the controller",
+                        request.entity_name
+                    );
+                }
+                entity.clock_offset_ms = offset;
             }
             None => anyhow::bail!("EntityDiscoveryCommand is missing the command"),
         }
         Ok(())
     }
 
-    fn open_back_channel(
-        &self,
-        ip: String,
-        port: u32,
-    ) -> anyhow::Result<zmq_sockets::Requester<Linked>> {
-        zmq_sockets::Requester::new(&self.app_state.context)
-            .context("Failed to create back-channel socket")?
-            .connect(&format!("tcp://{ip}:{port}"))
-            .context("Failed to connect back-channel socket")
+    /// Decides how to handle a registration under the name of a registered entity.
+    fn takeover(&self, entity_name: &str, session_id: u64) -> anyhow::Result<Takeover> {
+        let Some(entity) = self.app_state.entities.get(entity_name) else {
+            return Ok(Takeover::Vacant);
+        };
+        if entity.session_id != 0 && entity.session_id == session_id {
+            return Ok(Takeover::SameInstance);
+        }
+        if entity.suspected.load(Ordering::SeqCst) {
+            return Ok(Takeover::Replace);
+        }
+        anyhow::ensure!(
+            entity.is_other_instance(session_id),
+            "Entity {entity_name} already registered"
+        );
+        drop(entity);
+        // usually the previous instance crashed and was restarted before its heartbeats timed out
+        match self.app_state.ping(entity_name) {
+            Ok(()) => {
+                anyhow::bail!("Entity {entity_name} is already registered by a running instance")
+            }
+            Err(_) => Ok(Takeover::Replace),
+        }
+    }
+
+    /// Rejects the registration if it would exceed one of the configured limits.
+    fn check_limits(&self, entity_type: EntityType, ip: &str) -> anyhow::Result<()> {
+        let limits = self
+            .app_state
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock")
+            .limits
+            .clone();
+        let (mut total, mut same_type, mut same_address) = (0, 0, 0);
+        for entity in &self.app_state.entities {
+            total += 1;
+            if entity.state.entity_type() == entity_type {
+                same_type += 1;
+            }
+            if entity.address == ip {
+                same_address += 1;
+            }
+        }
+
+        if let Some(limit) = limits.max_entities {
+            anyhow::ensure!(total < limit, "Limit of {limit} entities reached");
+        }
+        let type_limit = match entity_type {
+            EntityType::Sensor => limits.max_sensors,
+            EntityType::Actuator => limits.max_actuators,
+        };
+        if let Some(limit) = type_limit {
+            anyhow::ensure!(
+                same_type < limit,
+                "Limit of {limit} entities of type {entity_type} reached"
+            );
+        }
+        if let Some(limit) = limits.max_entities_per_address {
+            anyhow::ensure!(
+                same_address < limit,
+                "Limit of {limit} entities from address {ip} reached"
+            );
+        }
+        Ok(())
+    }
+
+    fn open_back_channel(&self, ip: &str, port: u32) -> anyhow::Result<Box<dyn Channel>> {
+        self.app_state
+            .transport()
+            .connect(Pattern::Request, &format!("tcp://{ip}:{port}"))
+            .context("Failed to connect back-channel")
+    }
+}
+
+/// Confirms the heartbeat interval proposed by an entity, or overrides it with the default if it
+/// proposed none or the nearest bound if it is out of bounds.
+fn negotiate_heartbeat_interval(proposed_ms: u32) -> Duration {
+    if proposed_ms == 0 {
+        return DEFAULT_HEARTBEAT_INTERVAL;
     }
+    Duration::from_millis(proposed_ms.into()).clamp(MIN_HEARTBEAT_INTERVAL, MAX_HEARTBEAT_INTERVAL)
 }
@@ -0,0 +1,122 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use home_automation_common::protobuf::entity_discovery_command::EntityType;
+
+/// Something that happened in the controller that automations can react to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    EntityRegistered {
+        name: String,
+        entity_type: EntityType,
+        /// Whether an entity of the same name was removed before, see [`Tombstone`].
+        ///
+        /// [`Tombstone`]: crate::state::Tombstone
+        rejoined: bool,
+    },
+    MeasurementReceived {
+        name: String,
+        value: f32,
+    },
+    /// An error was recorded for the administrators, see [`AppState::record_error`].
+    ///
+    /// [`AppState::record_error`]: crate::state::AppState::record_error
+    Alert {
+        message: String,
+    },
+    /// A publication was dropped because its payload contradicts the topic or the registered
+    /// type of the entity, see [`AppState::record_payload_mismatch`].
+    ///
+    /// [`AppState::record_payload_mismatch`]: crate::state::AppState::record_payload_mismatch
+    PayloadMismatch {
+        topic: String,
+        /// Type of entity the topic or the registration expects.
+        expected: EntityType,
+        /// Type of entity the payload belongs to.
+        published: EntityType,
+    },
+}
+
+impl Event {
+    /// Name of the entity the event is about.
+    pub fn entity(&self) -> Option<&str> {
+        match self {
+            Self::EntityRegistered { name, .. } | Self::MeasurementReceived { name, .. } => {
+                Some(name)
+            }
+            Self::Alert { .. } | Self::PayloadMismatch { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    name: &'static str,
+    sender: mpsc::Sender<Event>,
+    /// Events sent but not received yet.
+    backlog: Arc<AtomicUsize>,
+}
+
+/// Delivers every published event to all subscribers.
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl EventBus {
+    /// The name identifies the subscriber in the [backlogs][Self::backlogs].
+    pub fn subscribe(&self, name: &'static str) -> EventReceiver {
+        let (sender, receiver) = mpsc::channel();
+        let backlog = Arc::default();
+        self.subscribers
+            .lock()
+            .expect("non-poisoned Mutex")
+            .push(Subscriber {
+                name,
+                sender,
+                backlog: Arc::clone(&backlog),
+            });
+        EventReceiver { receiver, backlog }
+    }
+
+    /// Sends the event to the subscribers, dropped receivers are unsubscribed.
+    pub fn publish(&self, event: Event) {
+        self.subscribers
+            .lock()
+            .expect("non-poisoned Mutex")
+            .retain(|subscriber| {
+                subscriber.backlog.fetch_add(1, Ordering::Relaxed);
+                subscriber.sender.send(event.clone()).is_ok()
+            });
+    }
+
+    /// Number of events every subscriber has not received yet.
+    pub fn backlogs(&self) -> Vec<(&'static str, usize)> {
+        self.subscribers
+            .lock()
+            .expect("non-poisoned Mutex")
+            .iter()
+            .map(|subscriber| (subscriber.name, subscriber.backlog.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+/// Receiving end of a subscription to the [`EventBus`].
+#[derive(Debug)]
+pub struct EventReceiver {
+    receiver: mpsc::Receiver<Event>,
+    backlog: Arc<AtomicUsize>,
+}
+
+impl EventReceiver {
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Event, mpsc::RecvTimeoutError> {
+        let event = self.receiver.recv_timeout(timeout)?;
+        self.backlog.fetch_sub(1, Ordering::Relaxed);
+        Ok(event)
+    }
+}
@@ -0,0 +1,81 @@
+//! Parts shared by the HTTP servers of the [webhooks](crate::webhook) and the
+//! [HTTP API](crate::http_api).
+
+use std::{io::Read as _, time::Duration};
+
+use anyhow::Context as _;
+use home_automation_common::{token_matches, ShutdownToken};
+use tiny_http::{Request, Server};
+
+/// Interval in which the servers check whether shutdown was requested.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Rejected request with the HTTP status code of the answer.
+#[derive(Debug)]
+pub struct Rejection {
+    pub status: u16,
+    pub message: String,
+}
+
+impl Rejection {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn failed(e: &anyhow::Error) -> Self {
+        Self::new(500, format!("{e:#}"))
+    }
+}
+
+/// Passes every request to `handle` until the shutdown is requested.
+pub fn serve(
+    server: &Server,
+    shutdown: &ShutdownToken,
+    mut handle: impl FnMut(Request),
+) -> anyhow::Result<()> {
+    while !shutdown.is_requested() {
+        let Some(request) = server
+            .recv_timeout(SHUTDOWN_CHECK_INTERVAL)
+            .context("Failed to receive HTTP request")?
+        else {
+            continue;
+        };
+        handle(request);
+    }
+    Ok(())
+}
+
+/// Checks the bearer authorization of the request, an empty token authorizes nothing.
+pub fn is_authorized(request: &Request, token: &str) -> bool {
+    request.headers().iter().any(|header| {
+        header.field.equiv("Authorization")
+            && header
+                .value
+                .as_str()
+                .strip_prefix("Bearer ")
+                .is_some_and(|presented| token_matches(token, presented))
+    })
+}
+
+/// Reads up to `max_size` bytes of the body.
+pub fn read_body(request: &mut Request, max_size: u64) -> Result<String, Rejection> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(max_size)
+        .read_to_string(&mut body)
+        .map_err(|e| Rejection::new(400, format!("Failed to read body: {e}")))?;
+    Ok(body)
+}
+
+/// Reads up to `max_size` bytes of the body and parses them as JSON.
+pub fn read_json<T: serde::de::DeserializeOwned>(
+    request: &mut Request,
+    max_size: u64,
+) -> Result<T, Rejection> {
+    let body = read_body(request, max_size)?;
+    serde_json::from_str(&body).map_err(|e| Rejection::new(400, format!("Invalid body: {e}")))
+}
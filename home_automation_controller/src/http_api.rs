@@ -0,0 +1,433 @@
+//! HTTP/JSON variant of the client API, so dashboards and `curl` can use the system without
+//! ZeroMQ and protobuf.
+//!
+//! | Request                              | Answer                                          |
+//! |--------------------------------------|-------------------------------------------------|
+//! | `GET /api/state[?tag=<tag>]`         | registered entities, optionally only the tagged |
+//! | `GET /api/entities/<name>`           | single entity                                   |
+//! | `GET /api/entities/<name>/history`   | published states of the entity, oldest first    |
+//! | `POST /api/entities/<name>`          | sends the command of the body to the entity     |
+//! | `POST /api/tags/<tag>`               | sends the command of the body to the tagged     |
+//! | `POST /api/scenes/<name>`            | activates the scene                             |
+//! | `GET /api/tombstones`                | removed entities                                |
+//! | `GET /api/ghosts`                    | publishers that are not registered              |
+//! | `GET`/`PUT /api/configuration`       | exports or replaces the configuration           |
+//!
+//! Commands are bodies like `{ "brightness": 50.0 }`, `{ "air_conditioning": true }` or
+//! `{ "update_frequency": 2.0 }`, see [`Target`].
+
+use std::sync::atomic::Ordering;
+
+use home_automation_common::{
+    log_event::LogEvent,
+    protobuf::{EntityDescription, NamedEntityState},
+};
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    config::{Command, Configuration, Target},
+    http::{self, is_authorized, read_json, Rejection},
+    rules,
+    snapshot::State,
+    state::{AppState, Entity, HistoryEntry},
+};
+
+/// Upper bound of the request body, large enough for an imported configuration.
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Successful answer of a request.
+#[derive(Debug)]
+enum Answer {
+    Json(String),
+    NoContent,
+}
+
+impl Answer {
+    fn json(value: &impl Serialize) -> Result<Self, Rejection> {
+        serde_json::to_string_pretty(value)
+            .map(Self::Json)
+            .map_err(|e| Rejection::new(500, format!("Failed to serialize answer: {e}")))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EntityView {
+    name: String,
+    /// e.g. `SENSOR`
+    entity_type: &'static str,
+    /// `None` if the entity did not publish a state yet.
+    state: Option<State>,
+    /// Unix time in milliseconds, 0 if unknown.
+    published_at_ms: u64,
+    heartbeat_age_ms: u64,
+    rooms: Vec<String>,
+    tags: Vec<String>,
+    back_channel_healthy: bool,
+    description: Option<DescriptionView>,
+}
+
+#[derive(Debug, Serialize)]
+struct DescriptionView {
+    kind: String,
+    unit: String,
+    /// `None` if the value is unbounded.
+    range: Option<(f32, f32)>,
+    capabilities: Vec<String>,
+}
+
+impl From<&EntityDescription> for DescriptionView {
+    fn from(description: &EntityDescription) -> Self {
+        Self {
+            kind: description.kind.clone(),
+            unit: description.unit.clone(),
+            range: description.range(),
+            capabilities: description.capabilities.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryView {
+    published_at_ms: u64,
+    state: Option<State>,
+}
+
+impl From<&HistoryEntry> for HistoryView {
+    fn from(entry: &HistoryEntry) -> Self {
+        Self {
+            published_at_ms: entry.published_at_ms,
+            state: State::of(&entry.state),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TombstoneView {
+    name: String,
+    entity_type: &'static str,
+    last_state: Option<State>,
+    reason: String,
+    age_secs: u64,
+    rejoins: u32,
+    tags: Vec<String>,
+    address: String,
+}
+
+#[derive(Debug, Serialize)]
+struct GhostView {
+    name: String,
+    entity_type: &'static str,
+    publications: u64,
+    first_seen_secs: u64,
+    last_seen_secs: u64,
+    last_state: Option<State>,
+}
+
+/// Serves the [HTTP API](self) on the configured endpoint.
+pub struct HttpApiTask<'a> {
+    app_state: &'a AppState,
+    server: Server,
+}
+
+impl<'a> HttpApiTask<'a> {
+    /// Fails if no token is configured, the configuration is available via the API.
+    pub fn new(app_state: &'a AppState, endpoint: &str) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            app_state
+                .configuration
+                .read()
+                .expect("non-poisoned RwLock")
+                .http_api
+                .as_ref()
+                .is_some_and(|http_api| !http_api.token.is_empty()),
+            "The HTTP API requires a non-empty http_api.token"
+        );
+        let server = Server::http(endpoint)
+            .map_err(|e| anyhow::anyhow!("Failed to start HTTP API server on {endpoint}: {e}"))?;
+        tracing::info!(%endpoint, "HTTP API is available at http://{endpoint}/api/state");
+        Ok(Self { app_state, server })
+    }
+
+    #[tracing::instrument(name = "HTTP API", skip(self))]
+    pub fn run(&self) -> anyhow::Result<()> {
+        tracing::info!("Starting HTTP API server.");
+        http::serve(&self.server, &self.app_state.shutdown, |request| {
+            self.handle_request(request)
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(method = %request.method(), url = request.url(), peer = ?request.remote_addr()))]
+    fn handle_request(&self, mut request: Request) {
+        let response = match self.answer(&mut request) {
+            Ok(Answer::Json(json)) => Response::from_string(json).with_header(
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                    .expect("valid header"),
+            ),
+            Ok(Answer::NoContent) => Response::from_string("").with_status_code(204),
+            Err(rejection) => {
+                tracing::warn!(
+                    status = rejection.status,
+                    "Rejected HTTP API request: {}",
+                    rejection.message
+                );
+                Response::from_string(rejection.message).with_status_code(rejection.status)
+            }
+        };
+        if let Err(e) = request.respond(response) {
+            tracing::warn!("Failed to answer HTTP API request: {e}");
+        }
+    }
+
+    fn answer(&self, request: &mut Request) -> Result<Answer, Rejection> {
+        let address = request
+            .remote_addr()
+            .map(|address| address.ip().to_string())
+            .unwrap_or_default();
+        self.app_state
+            .check_access(|access| &access.client_api, &address)
+            .map_err(|e| Rejection::new(403, format!("{e:#}")))?;
+        {
+            let configuration = self
+                .app_state
+                .configuration
+                .read()
+                .expect("non-poisoned RwLock");
+            let http_api = configuration
+                .http_api
+                .as_ref()
+                .ok_or_else(|| Rejection::new(404, "HTTP API is disabled"))?;
+            if !is_authorized(request, &http_api.token) {
+                return Err(Rejection::new(401, "Missing or wrong bearer token"));
+            }
+        }
+
+        let method = request.method().clone();
+        let url = request.url().to_owned();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+        match (&method, segments.as_slice()) {
+            (Method::Get, ["api", "state"]) => {
+                let tag = query_parameter(query, "tag").unwrap_or_default();
+                Answer::json(
+                    &self.entities(|_, entity| tag.is_empty() || entity.tags.contains(tag)),
+                )
+            }
+            (Method::Get, ["api", "entities", name]) => {
+                let entity = self
+                    .entities(|entity_name, _| entity_name == *name)
+                    .pop()
+                    .ok_or_else(|| unknown_entity(name))?;
+                Answer::json(&entity)
+            }
+            (Method::Get, ["api", "entities", name, "history"]) => {
+                let history: Vec<HistoryView> = self
+                    .app_state
+                    .entities
+                    .get(*name)
+                    .ok_or_else(|| unknown_entity(name))?
+                    .history
+                    .iter()
+                    .map(HistoryView::from)
+                    .collect();
+                Answer::json(&history)
+            }
+            (Method::Post, ["api", "entities", name]) => {
+                let name = (*name).to_owned();
+                let target = read_json(request, MAX_BODY_SIZE)?;
+                self.send_command(&name, target)?;
+                Ok(Answer::NoContent)
+            }
+            (Method::Post, ["api", "tags", tag]) => {
+                let tag = (*tag).to_owned();
+                let target = read_json(request, MAX_BODY_SIZE)?;
+                self.send_tagged_command(&tag, target)?;
+                Ok(Answer::NoContent)
+            }
+            (Method::Post, ["api", "scenes", scene]) => {
+                let result = rules::activate_scene(self.app_state, scene);
+                LogEvent::command("Scene", None, &result).emit();
+                result.map_err(|e| Rejection::failed(&e))?;
+                Ok(Answer::NoContent)
+            }
+            (Method::Get, ["api", "tombstones"]) => Answer::json(&self.tombstones()),
+            (Method::Get, ["api", "ghosts"]) => Answer::json(&self.ghosts()),
+            (Method::Get, ["api", "configuration"]) => Answer::json(
+                &self
+                    .app_state
+                    .configuration
+                    .read()
+                    .expect("non-poisoned RwLock")
+                    .redacted(),
+            ),
+            (Method::Put, ["api", "configuration"]) => {
+                let mut configuration: Configuration = read_json(request, MAX_BODY_SIZE)?;
+                configuration
+                    .keep_secrets(
+                        &self
+                            .app_state
+                            .configuration
+                            .read()
+                            .expect("non-poisoned RwLock"),
+                    )
+                    .map_err(|e| Rejection::new(400, format!("{e:#}")))?;
+                tracing::debug!(?configuration, "Replacing controller configuration.");
+                self.app_state
+                    .replace_configuration(configuration, "HTTP API");
+                Ok(Answer::NoContent)
+            }
+            _ => Err(Rejection::new(
+                404,
+                format!("Unknown request {method} {path}"),
+            )),
+        }
+    }
+
+    fn entities(&self, filter: impl Fn(&str, &Entity) -> bool) -> Vec<EntityView> {
+        let rooms = self
+            .app_state
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock")
+            .rooms
+            .clone();
+        let mut entities: Vec<_> = self
+            .app_state
+            .entities
+            .iter()
+            .filter(|entry| filter(entry.key(), entry.value()))
+            .map(|entry| {
+                let (name, entity) = entry.pair();
+                EntityView {
+                    name: name.clone(),
+                    entity_type: entity.state.entity_type().as_str_name(),
+                    state: State::of(&entity.state),
+                    published_at_ms: entity.published_at_ms,
+                    heartbeat_age_ms: entity
+                        .last_heartbeat_pulse
+                        .elapsed()
+                        .as_millis()
+                        .try_into()
+                        .unwrap_or(u64::MAX),
+                    rooms: rooms
+                        .iter()
+                        .filter(|(_, entities)| entities.contains(name))
+                        .map(|(room, _)| room.clone())
+                        .collect(),
+                    tags: entity.tags.iter().cloned().collect(),
+                    back_channel_healthy: entity.back_channel_healthy.load(Ordering::SeqCst),
+                    description: entity.description.as_ref().map(DescriptionView::from),
+                }
+            })
+            .collect();
+        entities.sort_by(|a, b| a.name.cmp(&b.name));
+        entities
+    }
+
+    fn send_command(&self, name: &str, target: Target) -> Result<(), Rejection> {
+        if !self.app_state.entities.contains_key(name) {
+            return Err(unknown_entity(name));
+        }
+        let command = NamedEntityState::from(&Command {
+            entity: name.to_owned(),
+            target,
+        });
+        let result = self.app_state.forward_to_entity(command);
+        LogEvent::command("Action", Some(name), &result).emit();
+        result.map_err(|e| {
+            self.app_state
+                .record_entity_error(name, format!("Failed to handle entity command: {e:#}"));
+            Rejection::failed(&e)
+        })
+    }
+
+    /// Sends the command to every entity with the tag and fails if any of them failed.
+    fn send_tagged_command(&self, tag: &str, target: Target) -> Result<(), Rejection> {
+        let entity_names: Vec<_> = self
+            .app_state
+            .entities
+            .iter()
+            .filter(|entity| entity.tags.contains(tag))
+            .map(|entity| entity.key().clone())
+            .collect();
+        if entity_names.is_empty() {
+            return Err(Rejection::new(404, format!("No entity with tag {tag}")));
+        }
+        let failed: Vec<_> = entity_names
+            .into_iter()
+            .filter(|name| {
+                let command = NamedEntityState::from(&Command {
+                    entity: name.clone(),
+                    target,
+                });
+                self.app_state
+                    .forward_to_entity(command)
+                    .inspect_err(|e| tracing::warn!(%e, "Tagged command failed: {e:#}"))
+                    .is_err()
+            })
+            .collect();
+        let result = if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "Failed to update entities {}",
+                failed.join(", ")
+            ))
+        };
+        LogEvent::command("TaggedAction", None, &result).emit();
+        result.map_err(|e| {
+            self.app_state
+                .record_error(format!("Failed to handle tagged command: {e:#}"));
+            Rejection::failed(&e)
+        })
+    }
+
+    fn tombstones(&self) -> Vec<TombstoneView> {
+        self.app_state.purge_archive();
+        self.app_state
+            .tombstones
+            .lock()
+            .expect("non-poisoned Mutex")
+            .iter()
+            .map(|tombstone| TombstoneView {
+                name: tombstone.name.clone(),
+                entity_type: tombstone.last_state.entity_type().as_str_name(),
+                last_state: State::of(&tombstone.last_state),
+                reason: tombstone.reason.clone(),
+                age_secs: tombstone.removed_at.elapsed().as_secs(),
+                rejoins: tombstone.rejoins,
+                tags: tombstone.tags.iter().cloned().collect(),
+                address: tombstone.address.clone(),
+            })
+            .collect()
+    }
+
+    fn ghosts(&self) -> Vec<GhostView> {
+        self.app_state
+            .ghosts
+            .lock()
+            .expect("non-poisoned Mutex")
+            .iter()
+            .map(|(name, ghost)| GhostView {
+                name: name.clone(),
+                entity_type: ghost.entity_type.as_str_name(),
+                publications: ghost.publications,
+                first_seen_secs: ghost.first_seen.elapsed().as_secs(),
+                last_seen_secs: ghost.last_seen.elapsed().as_secs(),
+                last_state: ghost.last_state.as_ref().and_then(State::of),
+            })
+            .collect()
+    }
+}
+
+fn unknown_entity(name: &str) -> Rejection {
+    Rejection::new(404, format!("Unknown entity {name}"))
+}
+
+/// Value of the parameter in the query string, without percent-decoding.
+fn query_parameter<'q>(query: &'q str, name: &str) -> Option<&'q str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
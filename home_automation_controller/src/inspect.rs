@@ -0,0 +1,72 @@
+//! `inspect` subcommand that prints the persisted registry of the controller without starting it,
+//! e.g. to see which entities were registered while the system is down.
+//!
+//! The registry with the last states is the only persisted state, there is no command history.
+
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+
+use crate::registry;
+
+/// First argument that selects the subcommand.
+pub const INSPECT_COMMAND: &str = "inspect";
+
+/// Returns the registry file to inspect if the controller was started with [`INSPECT_COMMAND`].
+///
+/// The file is the second argument or the one configured for the controller.
+pub fn requested() -> Option<Option<PathBuf>> {
+    let mut args = std::env::args_os().skip(1);
+    if args.next()? != INSPECT_COMMAND {
+        return None;
+    }
+    Some(args.next().map(PathBuf::from).or_else(registry::path))
+}
+
+pub fn run(path: Option<PathBuf>) -> anyhow::Result<()> {
+    let path = path.with_context(|| {
+        format!(
+            "No registry file given, pass it after `{INSPECT_COMMAND}` or set {}",
+            registry::ENV_CONTROLLER_REGISTRY
+        )
+    })?;
+    let Some(registry::Loaded {
+        version,
+        registrations,
+    }) = registry::load(&path)?
+    else {
+        println!("No registry at {}", path.display());
+        return Ok(());
+    };
+    println!(
+        "Registry {} version {version} ({} entities)",
+        path.display(),
+        registrations.len()
+    );
+    let width = registrations
+        .iter()
+        .map(|registration| registration.name.len())
+        .max()
+        .unwrap_or_default();
+    for registration in &registrations {
+        let tags: Vec<_> = registration.tags.iter().map(String::as_str).collect();
+        println!(
+            "  {:width$}  {:8}  {}:{}  session {}{}{}",
+            registration.name,
+            registration.entity_type,
+            registration.address,
+            registration.port,
+            registration.session_id,
+            registration.state.map_or(String::new(), |state| format!(
+                "  last state: {state:?} at {} ms",
+                registration.published_at_ms
+            )),
+            if tags.is_empty() {
+                String::new()
+            } else {
+                format!("  tags: {}", tags.join(", "))
+            }
+        );
+    }
+    Ok(())
+}
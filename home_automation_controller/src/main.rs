@@ -1,25 +1,230 @@
 use anyhow::Context;
 use client_api::ClientApiTask;
+use config::Configuration;
+use config_watcher::ConfigWatcherTask;
 use entity_discovery::EntityDiscoveryTask;
+use home_automation_common::{
+    doctor::{self, Report},
+    encryption::PayloadCipher,
+    log_file::{LogFileConfiguration, RotatingLogFile},
+    signals::Signal,
+    signing::CommandSigner,
+    transport::TransportKind,
+    zmq_sockets, STATISTICS_LOG_INTERVAL,
+};
+use http_api::HttpApiTask;
+use mock::MockTask;
+use notifications::NotificationTask;
+use proxy::ProxyTask;
+use scripting::ScriptTask;
+use serial_gateway::SerialGatewayTask;
+use slow_consumer::SlowConsumerTask;
 use state::AppState;
 use subscriber::SubscriberTask;
 use timeout::TimeoutTask;
+use webhook::WebhookTask;
 
+mod access;
+mod authenticator;
+#[cfg(feature = "ble")]
+mod ble_gateway;
 mod client_api;
+mod config;
+mod config_watcher;
 mod entity_discovery;
+mod events;
+mod http;
+mod http_api;
+mod inspect;
+mod migrate;
+mod mock;
+mod notifications;
+mod pid_file;
+mod proxy;
+mod registry;
+mod request_log;
+mod rules;
+mod scripting;
+mod serial_gateway;
+mod slow_consumer;
+mod snapshot;
 mod state;
 mod subscriber;
 mod timeout;
+mod webhook;
 
 fn main() -> anyhow::Result<()> {
-    let _config = home_automation_common::OpenTelemetryConfiguration::new("controller")?;
-    let app_state = AppState::default();
-    home_automation_common::install_signal_handler(app_state.context.clone())?;
+    if doctor::requested() {
+        return run_doctor();
+    }
+    if let Some(registry) = inspect::requested() {
+        return inspect::run(registry);
+    }
+    if let Some(arguments) = migrate::requested() {
+        return migrate::run(arguments);
+    }
+    let mock_entities = mock::requested().transpose()?;
+    let configuration = Configuration::load()?;
+    // fails on an invalid key instead of on the first message
+    PayloadCipher::global()?;
+    let log_file = configuration
+        .log_file
+        .as_ref()
+        .map(LogFileConfiguration::from);
+    let _config = home_automation_common::OpenTelemetryConfiguration::with_optional_log_file(
+        "controller",
+        log_file.as_ref().map(RotatingLogFile::create).transpose()?,
+    )?;
+    if std::env::var(config::ENV_CONTROLLER_CONFIG).is_err() {
+        tracing::info!("No configuration file given, starting with empty configuration");
+    }
+    if let Some(log_file) = &log_file {
+        log_file.remove_old_files();
+    }
+    let _pid_file = pid_file::PidFile::acquire()?;
+    let scripts = configuration.scripts.clone();
+    let webhook_endpoint = configuration
+        .webhooks
+        .as_ref()
+        .map(|webhooks| webhooks.endpoint.clone());
+    let http_api_endpoint = configuration
+        .http_api
+        .as_ref()
+        .map(|http_api| http_api.endpoint.clone());
+    let app_state = AppState {
+        configuration: configuration.into(),
+        transport: TransportKind::from_env()?,
+        command_signer: CommandSigner::from_env()?,
+        ..Default::default()
+    };
+    let restored = registry::restore(&app_state)?;
+    let signals = home_automation_common::install_signal_handler(
+        app_state.context.clone(),
+        app_state.shutdown.clone(),
+    )?;
+    // subscribe to the events before the other tasks start so no event is missed
+    let script_task = scripts
+        .map(|directory| ScriptTask::new(&app_state, &directory))
+        .transpose()?;
+    let notification_task = NotificationTask::new(&app_state);
     std::thread::scope(|s| {
-        let discovery = s.spawn(|| EntityDiscoveryTask::new(&app_state)?.run());
-        let client_api = s.spawn(|| ClientApiTask::new(&app_state)?.run());
-        let subscriber = s.spawn(|| SubscriberTask::new(&app_state)?.run());
-        let timeout = s.spawn(|| TimeoutTask::new(&app_state).run());
+        let discovery = s.spawn(|| {
+            app_state.supervise("Entity discovery", || {
+                EntityDiscoveryTask::new(&app_state)?.run()
+            })
+        });
+        let client_api =
+            s.spawn(|| app_state.supervise("Client API", || ClientApiTask::new(&app_state)?.run()));
+        let data_stream_endpoint = proxy::data_stream_endpoint();
+        let proxy_enabled = data_stream_endpoint.is_some();
+        let proxy = data_stream_endpoint.map(|endpoint| {
+            s.spawn({
+                let app_state = &app_state;
+                move || app_state.supervise("Proxy", || ProxyTask::new(app_state, &endpoint)?.run())
+            })
+        });
+        let subscriber = s.spawn(|| {
+            app_state.supervise("Subscriber", || {
+                SubscriberTask::new(&app_state, proxy_enabled)?.run()
+            })
+        });
+        let timeout =
+            s.spawn(|| app_state.supervise("Timeout", || TimeoutTask::new(&app_state).run()));
+        let slow_consumers = s.spawn(|| {
+            app_state.supervise("Slow consumers", || {
+                SlowConsumerTask::new(&app_state)?.run()
+            })
+        });
+        let reconciliation = (!restored.is_empty()).then(|| {
+            s.spawn(|| {
+                app_state.supervise("Reconciliation", || {
+                    registry::reconcile(&app_state, &restored);
+                    Ok(())
+                })
+            })
+        });
+        let registry =
+            registry::path().map(|_| s.spawn(|| registry::save_periodically(&app_state)));
+        let serial_ports = serial_gateway::serial_ports();
+        let serial_gateway = (!serial_ports.is_empty()).then(|| {
+            s.spawn({
+                let app_state = &app_state;
+                move || {
+                    app_state.supervise("Serial gateway", || {
+                        SerialGatewayTask::new(app_state, &serial_ports)?.run()
+                    })
+                }
+            })
+        });
+        let mock = mock_entities.map(|entities| {
+            s.spawn({
+                let app_state = &app_state;
+                move || {
+                    app_state.supervise("Mock entities", || {
+                        MockTask::new(app_state, entities)?.run()
+                    })
+                }
+            })
+        });
+        #[cfg(feature = "ble")]
+        let ble_gateway = ble_gateway::allowed_beacons().map(|allowed| {
+            s.spawn({
+                let app_state = &app_state;
+                move || {
+                    app_state.supervise("BLE gateway", || {
+                        ble_gateway::BleGatewayTask::new(app_state, allowed)?.run()
+                    })
+                }
+            })
+        });
+        let scripts = script_task.map(|task| {
+            let app_state = &app_state;
+            s.spawn(move || app_state.supervise("Scripts", || task.run()))
+        });
+        let notifications = {
+            let app_state = &app_state;
+            s.spawn(move || app_state.supervise("Notifications", || notification_task.run()))
+        };
+        let webhooks = webhook_endpoint.map(|endpoint| {
+            s.spawn({
+                let app_state = &app_state;
+                move || {
+                    app_state
+                        .supervise("Webhooks", || WebhookTask::new(app_state, &endpoint)?.run())
+                }
+            })
+        });
+        let http_api = http_api_endpoint.map(|endpoint| {
+            s.spawn({
+                let app_state = &app_state;
+                move || {
+                    app_state
+                        .supervise("HTTP API", || HttpApiTask::new(app_state, &endpoint)?.run())
+                }
+            })
+        });
+        let config_watcher = std::env::var_os(config::ENV_CONTROLLER_CONFIG).map(|path| {
+            s.spawn({
+                let app_state = &app_state;
+                move || {
+                    app_state.supervise("Configuration watcher", || {
+                        ConfigWatcherTask::new(app_state, path.into()).run()
+                    })
+                }
+            })
+        });
+        let statistics = s.spawn(|| {
+            zmq_sockets::log_statistics_periodically(STATISTICS_LOG_INTERVAL, &app_state.shutdown)
+        });
+        let signals = s.spawn(|| {
+            signals.run(&app_state.shutdown, |signal| match signal {
+                Signal::Reload => match std::env::var_os(config::ENV_CONTROLLER_CONFIG) {
+                    Some(path) => config_watcher::reload(&app_state, path.as_ref()),
+                    None => tracing::warn!("Ignoring reload signal without configuration file"),
+                },
+                Signal::DumpState => app_state.log_state(),
+            })
+        });
 
         discovery
             .join()
@@ -37,6 +242,144 @@ fn main() -> anyhow::Result<()> {
             .join()
             .map_err(|e| anyhow::anyhow!("Timeout task panicked: {e:?}"))?
             .context("Timeout task failed")?;
+        slow_consumers
+            .join()
+            .map_err(|e| anyhow::anyhow!("Slow consumer task panicked: {e:?}"))?
+            .context("Slow consumer task failed")?;
+        if let Some(reconciliation) = reconciliation {
+            reconciliation
+                .join()
+                .map_err(|e| anyhow::anyhow!("Reconciliation task panicked: {e:?}"))?
+                .context("Reconciliation task failed")?;
+        }
+        if let Some(registry) = registry {
+            registry
+                .join()
+                .map_err(|e| anyhow::anyhow!("Registry task panicked: {e:?}"))?;
+        }
+        if let Some(serial_gateway) = serial_gateway {
+            serial_gateway
+                .join()
+                .map_err(|e| anyhow::anyhow!("Serial gateway task panicked: {e:?}"))?
+                .context("Serial gateway task failed")?;
+        }
+        if let Some(mock) = mock {
+            mock.join()
+                .map_err(|e| anyhow::anyhow!("Mock entities task panicked: {e:?}"))?
+                .context("Mock entities task failed")?;
+        }
+        #[cfg(feature = "ble")]
+        if let Some(ble_gateway) = ble_gateway {
+            ble_gateway
+                .join()
+                .map_err(|e| anyhow::anyhow!("BLE gateway task panicked: {e:?}"))?
+                .context("BLE gateway task failed")?;
+        }
+        if let Some(scripts) = scripts {
+            scripts
+                .join()
+                .map_err(|e| anyhow::anyhow!("Scripts task panicked: {e:?}"))?
+                .context("Scripts task failed")?;
+        }
+        notifications
+            .join()
+            .map_err(|e| anyhow::anyhow!("Notification task panicked: {e:?}"))?
+            .context("Notification task failed")?;
+        if let Some(webhooks) = webhooks {
+            webhooks
+                .join()
+                .map_err(|e| anyhow::anyhow!("Webhook task panicked: {e:?}"))?
+                .context("Webhook task failed")?;
+        }
+        if let Some(http_api) = http_api {
+            http_api
+                .join()
+                .map_err(|e| anyhow::anyhow!("HTTP API task panicked: {e:?}"))?
+                .context("HTTP API task failed")?;
+        }
+        if let Some(config_watcher) = config_watcher {
+            config_watcher
+                .join()
+                .map_err(|e| anyhow::anyhow!("Configuration watcher task panicked: {e:?}"))?
+                .context("Configuration watcher task failed")?;
+        }
+        if let Some(proxy) = proxy {
+            proxy
+                .join()
+                .map_err(|e| anyhow::anyhow!("Proxy task panicked: {e:?}"))?
+                .context("Proxy task failed")?;
+        }
+        statistics
+            .join()
+            .map_err(|e| anyhow::anyhow!("Statistics task panicked: {e:?}"))?;
+        signals
+            .join()
+            .map_err(|e| anyhow::anyhow!("Signal task panicked: {e:?}"))?;
         Ok(())
     })
 }
+
+/// Checks the environment of the controller instead of starting it.
+fn run_doctor() -> anyhow::Result<()> {
+    use home_automation_common::{
+        ENV_ADMIN_TOKEN, ENV_CLIENT_API_ENDPOINT, ENV_DATA_STREAM_ENDPOINT, ENV_DISCOVERY_ENDPOINT,
+        ENV_ENTITY_DATA_ENDPOINT, ENV_LAST_VALUE_ENDPOINT, ENV_TOPIC_PREFIX, PROTOCOL_VERSION,
+    };
+    let mut report = Report::new("home_automation_controller");
+    for var in [
+        ENV_DISCOVERY_ENDPOINT,
+        ENV_ENTITY_DATA_ENDPOINT,
+        ENV_CLIENT_API_ENDPOINT,
+    ] {
+        report.env_var(var, true);
+    }
+    for var in [
+        ENV_DATA_STREAM_ENDPOINT,
+        ENV_ADMIN_TOKEN,
+        ENV_TOPIC_PREFIX,
+        config::ENV_CONTROLLER_CONFIG,
+        pid_file::ENV_CONTROLLER_PID_FILE,
+        registry::ENV_CONTROLLER_REGISTRY,
+        ENV_LAST_VALUE_ENDPOINT,
+        proxy::ENV_LAST_VALUE_CACHE,
+        request_log::ENV_SLOW_REQUEST_THRESHOLD,
+        entity_discovery::ENV_MAX_BATCH_SIZE,
+        home_automation_common::registration_auth::ENV_REGISTRATION_KEY,
+        home_automation_common::registration_auth::ENV_REGISTRATION_TOKENS,
+        home_automation_common::registration_auth::ENV_REGISTRATION_TOKEN,
+        serial_gateway::ENV_SERIAL_GATEWAY_PORTS,
+        home_automation_common::serial::ENV_SERIAL_BAUD_RATE,
+        home_automation_common::udp::ENV_UDP_MULTICAST_GROUP,
+        home_automation_common::transport::ENV_TRANSPORT,
+    ] {
+        report.env_var(var, false);
+    }
+    #[cfg(feature = "ble")]
+    report.env_var(ble_gateway::ENV_BLE_BEACONS, false);
+    report.add_result(
+        "registration authentication",
+        authenticator::from_env().map(|authenticator| authenticator.describe()),
+    );
+    report.add_result(
+        "configuration",
+        Configuration::load().map(|_| "valid".to_owned()),
+    );
+    for var in [
+        ENV_DISCOVERY_ENDPOINT,
+        ENV_ENTITY_DATA_ENDPOINT,
+        ENV_CLIENT_API_ENDPOINT,
+        ENV_DATA_STREAM_ENDPOINT,
+        ENV_LAST_VALUE_ENDPOINT,
+    ] {
+        report.bind_probe(var);
+    }
+    report.payload_encryption();
+    report.command_signing(true, true);
+    report.zipkin();
+    report.add(
+        "protocol",
+        doctor::Status::Ok,
+        format!("speaks protocol version {PROTOCOL_VERSION}"),
+    );
+    report.finish()
+}
@@ -0,0 +1,84 @@
+//! `migrate` subcommand that converts the registry file to the current format without starting
+//! the controller, e.g. after an upgrade of a lab machine.
+
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+
+use crate::registry::{self, Loaded, MIGRATIONS, VERSION};
+
+/// First argument that selects the subcommand.
+pub const MIGRATE_COMMAND: &str = "migrate";
+/// Only prints the migrations instead of writing the file.
+pub const DRY_RUN_FLAG: &str = "--dry-run";
+
+pub struct Arguments {
+    path: Option<PathBuf>,
+    dry_run: bool,
+}
+
+/// Returns the arguments if the controller was started with [`MIGRATE_COMMAND`].
+///
+/// The file is the argument after the command or the one configured for the controller.
+pub fn requested() -> Option<Arguments> {
+    let mut args = std::env::args_os().skip(1);
+    if args.next()? != MIGRATE_COMMAND {
+        return None;
+    }
+    let mut arguments = Arguments {
+        path: None,
+        dry_run: false,
+    };
+    for arg in args {
+        if arg == DRY_RUN_FLAG {
+            arguments.dry_run = true;
+        } else {
+            arguments.path = Some(arg.into());
+        }
+    }
+    arguments.path = arguments.path.or_else(registry::path);
+    Some(arguments)
+}
+
+pub fn run(Arguments { path, dry_run }: Arguments) -> anyhow::Result<()> {
+    let path = path.with_context(|| {
+        format!(
+            "No registry file given, pass it after `{MIGRATE_COMMAND}` or set {}",
+            registry::ENV_CONTROLLER_REGISTRY
+        )
+    })?;
+    let Some(Loaded {
+        version,
+        registrations,
+    }) = registry::load(&path)?
+    else {
+        println!("No registry at {}, nothing to migrate", path.display());
+        return Ok(());
+    };
+    if version == VERSION {
+        println!(
+            "Registry {} is already at version {VERSION}",
+            path.display()
+        );
+        return Ok(());
+    }
+    println!(
+        "Registry {} with {} entities is at version {version}:",
+        path.display(),
+        registrations.len()
+    );
+    for (from, migration) in (version..).zip(&MIGRATIONS[version as usize - 1..]) {
+        println!("  {from} -> {}: {}", from + 1, migration.description);
+    }
+    if dry_run {
+        println!("Dry run, the file was not changed");
+        return Ok(());
+    }
+    let backup = registry::backup(&path, version)?;
+    registry::write(&path, registrations)?;
+    println!(
+        "Migrated to version {VERSION}, kept the old file as {}",
+        backup.display()
+    );
+    Ok(())
+}
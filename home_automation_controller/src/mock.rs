@@ -0,0 +1,405 @@
+//! `--mock` flag that simulates entities with changing values, so the client can be developed
+//! without starting any sensors or actuators.
+//!
+//! The simulated entities register, send heartbeats and publish through the sockets of the
+//! controller like real entities, so the rest of the controller treats them as such.
+
+use std::{
+    f64::consts::TAU,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use home_automation_common::{
+    capabilities, entity_topic,
+    frequency::UpdateFrequency,
+    latency, load_env, meta_topic,
+    protobuf::{
+        actuator_state,
+        entity_discovery_command::{Command, EntityType, Heartbeat, Registration},
+        named_entity_state::State,
+        publish_data,
+        response_code::Code,
+        ActuatorState, EntityDescription, EntityDiscoveryCommand, NamedEntityState, PublishData,
+        ResponseCode, SensorMeasurement,
+    },
+    registration_auth,
+    signing::CommandVerifier,
+    transport::{Channel, Pattern, Transport},
+    zmq_sockets::termination_is_ok,
+    ShutdownToken, DEFAULT_HEARTBEAT_INTERVAL,
+};
+
+use crate::{serial_gateway::connect_discovery, state::AppState};
+
+/// Flag that starts the simulation, optionally followed by the entities as `<name>=<kind>,...`.
+pub const MOCK_FLAG: &str = "--mock";
+
+const DEFAULT_ENTITIES: &str = "sen_mock_temperature=temperature,sen_mock_humidity=humidity,\
+    act_mock_light=light,act_mock_ac=air_conditioning";
+/// Tag of the simulated entities, so they can be told apart from real ones.
+const MOCK_TAG: &str = "mock";
+
+/// Interval in which the task checks for commands and shutdown requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the requests to the entity discovery wait for the answer.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Publication period until the controller configures a different one.
+const DEFAULT_PERIOD: Duration = Duration::from_millis(1500);
+/// Period of the oscillation of the simulated measurements.
+const WAVE_PERIOD: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Temperature,
+    Humidity,
+    Light,
+    AirConditioning,
+}
+
+impl Kind {
+    fn entity_type(self) -> EntityType {
+        match self {
+            Kind::Temperature | Kind::Humidity => EntityType::Sensor,
+            Kind::Light | Kind::AirConditioning => EntityType::Actuator,
+        }
+    }
+
+    /// Description with the range of the simulated values, published after the registration.
+    fn description(self) -> EntityDescription {
+        let (kind, unit, (min, max), capability) = match self {
+            Kind::Temperature => ("temperature", "°C", (18., 24.), None),
+            Kind::Humidity => ("humidity", "%", (35., 55.), None),
+            Kind::Light => ("brightness", "%", (0., 100.), Some(capabilities::LIGHT)),
+            Kind::AirConditioning => (
+                "air_conditioning",
+                "",
+                (0., 0.),
+                Some(capabilities::AIR_CONDITIONING),
+            ),
+        };
+        EntityDescription {
+            kind: kind.to_owned(),
+            unit: unit.to_owned(),
+            min,
+            max,
+            capabilities: std::iter::once(capabilities::UPDATE_FREQUENCY)
+                .chain(capability)
+                .map(ToOwned::to_owned)
+                .collect(),
+        }
+    }
+
+    fn initial_state(self) -> Option<ActuatorState> {
+        match self {
+            Kind::Temperature | Kind::Humidity => None,
+            Kind::Light => Some(ActuatorState::light(50.)),
+            Kind::AirConditioning => Some(ActuatorState::air_conditioning(false)),
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "temperature" => Ok(Kind::Temperature),
+            "humidity" => Ok(Kind::Humidity),
+            "light" => Ok(Kind::Light),
+            "air_conditioning" => Ok(Kind::AirConditioning),
+            _ => anyhow::bail!(
+                "Invalid mock entity kind {s:?}, expected temperature, humidity, light or \
+                air_conditioning"
+            ),
+        }
+    }
+}
+
+/// Simulated entity, given as `<name>=<kind>` on the command line.
+#[derive(Debug, Clone)]
+pub struct MockEntity {
+    name: String,
+    kind: Kind,
+    /// State set by the commands of the controller, only for actuators.
+    state: Option<ActuatorState>,
+    period: Duration,
+    next_publication: Instant,
+    registered: bool,
+    /// Sent with the discovery commands, see [`registration_auth`].
+    auth_token: String,
+}
+
+impl FromStr for MockEntity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, kind) = s
+            .split_once('=')
+            .with_context(|| format!("Invalid mock entity {s:?}, expected <name>=<kind>"))?;
+        anyhow::ensure!(!name.is_empty(), "Mock entity {s:?} has no name");
+        let kind: Kind = kind.parse()?;
+        Ok(Self {
+            name: name.to_owned(),
+            kind,
+            state: kind.initial_state(),
+            period: DEFAULT_PERIOD,
+            next_publication: Instant::now(),
+            registered: false,
+            auth_token: String::new(),
+        })
+    }
+}
+
+impl MockEntity {
+    /// Value that oscillates around a plausible level, shifted by the index of the entity so
+    /// several sensors of the same kind differ.
+    fn measurement(&self, elapsed: Duration, index: usize) -> SensorMeasurement {
+        let wave = (TAU * elapsed.as_secs_f64() / WAVE_PERIOD.as_secs_f64() + index as f64).sin();
+        match self.kind {
+            Kind::Humidity => SensorMeasurement::humidity((45. + 10. * wave) as f32),
+            _ => SensorMeasurement::temperature((21. + 3. * wave) as f32),
+        }
+    }
+
+    fn data(&self, elapsed: Duration, index: usize) -> PublishData {
+        let value = match &self.state {
+            Some(state) => publish_data::Value::ActuatorState(state.clone()),
+            None => publish_data::Value::Measurement(self.measurement(elapsed, index)),
+        };
+        PublishData {
+            value: Some(value),
+            published_at_ms: latency::unix_time_ms(),
+        }
+    }
+
+    /// Applies a command of the controller like the real entity of the kind would.
+    fn apply(&mut self, state: Option<State>) -> anyhow::Result<()> {
+        match state {
+            // ping
+            None => Ok(()),
+            Some(State::SensorConfiguration(configuration)) => {
+                self.period = UpdateFrequency::try_from(&configuration)?.period();
+                self.next_publication = Instant::now();
+                Ok(())
+            }
+            Some(State::ActuatorState(state)) => {
+                let matches = matches!(
+                    (self.kind, &state.state),
+                    (Kind::Light, Some(actuator_state::State::Light(_)))
+                        | (
+                            Kind::AirConditioning,
+                            Some(actuator_state::State::AirConditioning(_))
+                        )
+                );
+                anyhow::ensure!(matches, "Invalid state {state:?} for {}", self.name);
+                self.state = Some(state);
+                // publishes the new state right away like the real actuators
+                self.next_publication = Instant::now();
+                Ok(())
+            }
+            Some(other) => anyhow::bail!("Mock entity {} does not support {other:?}", self.name),
+        }
+    }
+}
+
+/// Returns the simulated entities if the controller was started with [`MOCK_FLAG`].
+pub fn requested() -> Option<anyhow::Result<Vec<MockEntity>>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let position = args.iter().position(|arg| arg == MOCK_FLAG)?;
+    let entities = args
+        .get(position + 1)
+        .filter(|arg| !arg.starts_with('-'))
+        .map_or(DEFAULT_ENTITIES, String::as_str);
+    Some(parse(entities))
+}
+
+fn parse(entities: &str) -> anyhow::Result<Vec<MockEntity>> {
+    let entities: Vec<MockEntity> = entities
+        .split(',')
+        .map(str::trim)
+        .filter(|entity| !entity.is_empty())
+        .map(str::parse)
+        .collect::<anyhow::Result<_>>()?;
+    anyhow::ensure!(!entities.is_empty(), "No mock entities given");
+    for (i, entity) in entities.iter().enumerate() {
+        anyhow::ensure!(
+            entities[..i].iter().all(|other| other.name != entity.name),
+            "Mock entity {} is given twice",
+            entity.name
+        );
+    }
+    Ok(entities)
+}
+
+/// Registers the simulated entities and publishes their values through the controller's sockets.
+pub struct MockTask {
+    entities: Vec<MockEntity>,
+    transport: Box<dyn Transport>,
+    discovery_endpoint: String,
+    discovery: Box<dyn Channel>,
+    publisher: Box<dyn Channel>,
+    /// Shared back-channel of all simulated entities.
+    updates: Box<dyn Channel>,
+    update_port: u16,
+    verifier: CommandVerifier,
+    shutdown: ShutdownToken,
+}
+
+impl MockTask {
+    pub fn new(app_state: &AppState, mut entities: Vec<MockEntity>) -> anyhow::Result<Self> {
+        for entity in &mut entities {
+            entity.auth_token = registration_auth::token_from_env(&entity.name)?;
+        }
+        let transport = app_state.transport();
+        let discovery_endpoint = load_env(home_automation_common::ENV_DISCOVERY_ENDPOINT)?;
+        let discovery = connect_discovery(&*transport, &discovery_endpoint)?;
+        let publisher = transport.connect(
+            Pattern::Publish,
+            &load_env(home_automation_common::ENV_ENTITY_DATA_ENDPOINT)?,
+        )?;
+        let updates = transport.bind(Pattern::Reply, "tcp://*:*")?;
+        let update_port = updates.local_port()?;
+        Ok(Self {
+            entities,
+            transport,
+            discovery_endpoint,
+            discovery,
+            publisher,
+            updates,
+            update_port,
+            verifier: CommandVerifier::from_env()?,
+            shutdown: app_state.shutdown.clone(),
+        })
+    }
+
+    #[tracing::instrument(name = "Mock entities", skip(self))]
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        tracing::info!(
+            "Simulating {} entities, receiving their updates on port {}",
+            self.entities.len(),
+            self.update_port
+        );
+        let started = Instant::now();
+        let mut next_heartbeat = Instant::now();
+        while !self.shutdown.is_requested() {
+            if Instant::now() >= next_heartbeat {
+                self.keep_alive();
+                next_heartbeat = Instant::now() + DEFAULT_HEARTBEAT_INTERVAL;
+            }
+            self.publish_due(started.elapsed())?;
+            match self
+                .updates
+                .receive_message::<NamedEntityState>(Some(POLL_INTERVAL))
+            {
+                Ok(Some((update, _))) => {
+                    let response = ResponseCode::from(self.handle_update(update));
+                    self.updates.send_message(None, &response)?;
+                }
+                Ok(None) => {}
+                Err(e) => return Err(e).or_else(termination_is_ok),
+            }
+        }
+        // the registrations are not withdrawn: the entity discovery may already be stopped and
+        // persisted registrations time out after a restart without the flag like stale ones
+        Ok(())
+    }
+
+    /// Registers the entities that are not registered yet and sends heartbeats for the others.
+    fn keep_alive(&mut self) {
+        for i in 0..self.entities.len() {
+            let entity = &self.entities[i];
+            let command = if entity.registered {
+                Command::Heartbeat(Heartbeat {
+                    sent_at_ms: latency::unix_time_ms(),
+                    clock_offset_ms: 0,
+                })
+            } else {
+                Command::Register(Registration {
+                    port: self.update_port.into(),
+                    tags: vec![MOCK_TAG.to_owned()],
+                    max_batch_size: 0,
+                    heartbeat_interval_ms: 0,
+                })
+            };
+            let request =
+                EntityDiscoveryCommand::new(entity.kind.entity_type(), &entity.name, command)
+                    .with_auth_token(entity.auth_token.clone());
+            let result = self.discovery_request(&request);
+            let entity = &mut self.entities[i];
+            match result {
+                Ok(()) if !entity.registered => {
+                    tracing::info!("Registered mock entity {}", entity.name);
+                    entity.registered = true;
+                    let description = PublishData::from(entity.kind.description());
+                    if let Err(e) = self
+                        .publisher
+                        .send_message(Some(&meta_topic(&entity.name)), &description)
+                    {
+                        tracing::warn!("Failed to describe mock entity {}: {e:#}", entity.name);
+                    }
+                }
+                Ok(()) => {}
+                Err(e) => {
+                    // e.g. unregistered by an administrator, it registers again in the next round
+                    tracing::warn!(
+                        "Discovery request for mock entity {} failed: {e:#}",
+                        entity.name
+                    );
+                    entity.registered = false;
+                }
+            }
+        }
+    }
+
+    fn discovery_request(&mut self, request: &EntityDiscoveryCommand) -> anyhow::Result<()> {
+        let result = self
+            .discovery
+            .request::<_, ResponseCode>(request, Some(RESPONSE_TIMEOUT));
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                // the requester cannot send again before it received a reply
+                match connect_discovery(&*self.transport, &self.discovery_endpoint) {
+                    Ok(discovery) => self.discovery = discovery,
+                    Err(e) => tracing::error!("Failed to reconnect to entity discovery: {e:#}"),
+                }
+                return Err(e);
+            }
+        };
+        anyhow::ensure!(matches!(response.code(), Code::Ok), "{}", response.message);
+        Ok(())
+    }
+
+    fn publish_due(&mut self, elapsed: Duration) -> anyhow::Result<()> {
+        let now = Instant::now();
+        for (i, entity) in self.entities.iter_mut().enumerate() {
+            if !entity.registered || now < entity.next_publication {
+                continue;
+            }
+            let topic = entity_topic(&entity.name, entity.kind.entity_type());
+            self.publisher
+                .send_message(Some(&topic), &entity.data(elapsed, i))
+                .with_context(|| {
+                    format!("Failed to publish data of mock entity {}", entity.name)
+                })?;
+            entity.next_publication = now + entity.period;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn handle_update(&mut self, update: NamedEntityState) -> anyhow::Result<()> {
+        // pings change nothing, so they are not signed
+        if update.state.is_some() {
+            self.verifier.verify(&update)?;
+        }
+        let entity = self
+            .entities
+            .iter_mut()
+            .find(|entity| entity.name == update.entity_name)
+            .with_context(|| format!("Unknown mock entity {}", update.entity_name))?;
+        entity.apply(update.state)
+    }
+}
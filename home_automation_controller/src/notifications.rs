@@ -0,0 +1,187 @@
+use std::{
+    sync::{atomic::Ordering, mpsc},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use home_automation_common::protobuf::entity_discovery_command::EntityType;
+
+use crate::{
+    config::{MatrixBot, Notifications, TelegramBot},
+    events::{Event, EventReceiver},
+    state::AppState,
+};
+
+/// Interval in which the task checks whether shutdown was requested.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+/// Alerts raised within this interval after a notification are sent together.
+const ALERT_BATCH_INTERVAL: Duration = Duration::from_secs(60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Channel that delivers notifications to the administrators.
+pub trait NotificationSink {
+    fn name(&self) -> &'static str;
+    fn send(&self, text: &str) -> anyhow::Result<()>;
+}
+
+impl NotificationSink for TelegramBot {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    fn send(&self, text: &str) -> anyhow::Result<()> {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+        let body = serde_json::json!({ "chat_id": self.chat_id, "text": text });
+        ureq::post(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .context("Failed to send Telegram message")?;
+        Ok(())
+    }
+}
+
+impl NotificationSink for MatrixBot {
+    fn name(&self) -> &'static str {
+        "Matrix"
+    }
+
+    fn send(&self, text: &str) -> anyhow::Result<()> {
+        // the transaction ID makes retries of the same message idempotent
+        let transaction = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{transaction}",
+            self.homeserver.trim_end_matches('/'),
+            percent_encode(&self.room_id)
+        );
+        let body = serde_json::json!({ "msgtype": "m.text", "body": text });
+        ureq::put(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .set("Authorization", &format!("Bearer {}", self.access_token))
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .context("Failed to send Matrix message")?;
+        Ok(())
+    }
+}
+
+impl Notifications {
+    pub fn sinks(&self) -> Vec<&dyn NotificationSink> {
+        let telegram = self.telegram.iter().map(|bot| bot as &dyn NotificationSink);
+        let matrix = self.matrix.iter().map(|bot| bot as &dyn NotificationSink);
+        telegram.chain(matrix).collect()
+    }
+}
+
+/// Notifies the configured sinks about alerts and periodically sends a summary.
+pub struct NotificationTask<'a> {
+    app_state: &'a AppState,
+    events: EventReceiver,
+}
+
+impl<'a> NotificationTask<'a> {
+    pub fn new(app_state: &'a AppState) -> Self {
+        Self {
+            app_state,
+            events: app_state.events.subscribe("Notifications"),
+        }
+    }
+
+    #[tracing::instrument(name = "Notifications", skip(self))]
+    pub fn run(&self) -> anyhow::Result<()> {
+        tracing::info!("Starting notification task.");
+        let mut pending_alerts = Vec::new();
+        let mut alerts_since_summary = 0;
+        let mut next_alert = Instant::now();
+        let mut last_summary = Instant::now();
+        while !self.app_state.shutdown.is_requested() {
+            match self.events.recv_timeout(SHUTDOWN_CHECK_INTERVAL) {
+                Ok(Event::Alert { message }) => {
+                    pending_alerts.push(message);
+                    alerts_since_summary += 1;
+                }
+                Ok(_) | Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            let now = Instant::now();
+            if !pending_alerts.is_empty() && now >= next_alert {
+                let text = format!("Alert:\n{}", pending_alerts.join("\n"));
+                pending_alerts.clear();
+                self.notify(&text);
+                next_alert = now + ALERT_BATCH_INTERVAL;
+            }
+            if self
+                .summary_interval()
+                .is_some_and(|interval| now >= last_summary + interval)
+            {
+                self.notify(&self.summary(alerts_since_summary));
+                alerts_since_summary = 0;
+                last_summary = now;
+            }
+        }
+        Ok(())
+    }
+
+    fn summary_interval(&self) -> Option<Duration> {
+        let configuration = self
+            .app_state
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock");
+        let hours = configuration.notifications.summary_interval_hours;
+        (hours > 0).then(|| Duration::from_secs(hours * 60 * 60))
+    }
+
+    fn summary(&self, alerts: usize) -> String {
+        let (mut sensors, mut actuators, mut unhealthy) = (0, 0, 0);
+        for entity in &self.app_state.entities {
+            match entity.state.entity_type() {
+                EntityType::Sensor => sensors += 1,
+                EntityType::Actuator => actuators += 1,
+            }
+            if !entity.back_channel_healthy.load(Ordering::Relaxed) {
+                unhealthy += 1;
+            }
+        }
+        let rejected = self.app_state.rejected_requests.load(Ordering::Relaxed);
+        let mismatches = self.app_state.payload_mismatches.load(Ordering::Relaxed);
+        format!(
+            "Summary: {sensors} sensors and {actuators} actuators registered, \
+             {unhealthy} with failed back-channel, {alerts} alerts, {rejected} rejected requests \
+             and {mismatches} payload mismatches in total"
+        )
+    }
+
+    /// Sends the text to every sink, failures are only logged so they cannot raise alerts.
+    fn notify(&self, text: &str) {
+        let notifications = self
+            .app_state
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock")
+            .notifications
+            .clone();
+        for sink in notifications.sinks() {
+            match sink.send(text) {
+                Ok(()) => tracing::debug!(sink = sink.name(), "Sent notification"),
+                Err(e) => tracing::warn!(sink = sink.name(), "Failed to send notification: {e:#}"),
+            }
+        }
+    }
+}
+
+/// Encodes everything except the unreserved characters of RFC 3986 for use in a URL path.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,69 @@
+use std::{
+    fs::File,
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+
+/// Optional path to a file that holds the process ID of the running controller, e.g. for
+/// `kill -HUP $(cat controller.pid)`.
+pub const ENV_CONTROLLER_PID_FILE: &str = "HOME_AUTOMATION_CONTROLLER_PID_FILE";
+
+/// Locked file with the process ID of the controller, removed when dropped.
+///
+/// The lock is held until the process exits, so a second controller using the same file fails
+/// instead of racing for the endpoints. A file left behind by a crashed controller is not locked
+/// and is reused.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    _file: File,
+}
+
+impl PidFile {
+    /// Locks the file given in [`ENV_CONTROLLER_PID_FILE`] and writes the process ID to it, returns
+    /// `None` if the variable is not set.
+    pub fn acquire() -> anyhow::Result<Option<Self>> {
+        let Some(path) = std::env::var_os(ENV_CONTROLLER_PID_FILE) else {
+            return Ok(None);
+        };
+        Self::create(path.into()).map(Some)
+    }
+
+    fn create(path: PathBuf) -> anyhow::Result<Self> {
+        let mut file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open PID file {}", path.display()))?;
+        file.try_lock().map_err(|_| {
+            anyhow::anyhow!(
+                "Another controller is running with PID {}, it holds the lock of {}",
+                read_pid(&path).as_deref().unwrap_or("unknown"),
+                path.display()
+            )
+        })?;
+        file.set_len(0)
+            .and_then(|()| writeln!(file, "{}", std::process::id()))
+            .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+        tracing::info!(path = %path.display(), "Wrote PID file {}", path.display());
+        Ok(Self { path, _file: file })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove PID file {}: {e}", self.path.display());
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|pid| pid.trim().to_owned())
+        .filter(|pid| !pid.is_empty())
+}
@@ -0,0 +1,154 @@
+use std::collections::{BTreeSet, HashMap};
+
+use home_automation_common::{
+    load_env,
+    protobuf::{LastValue, LastValueQuery, LastValueSnapshot},
+    topic_class,
+    transport::{TransportKind, ENV_TRANSPORT},
+    zmq_sockets::{self, markers::Linked, termination_is_ok, RawMessage},
+    ErrorKindExt as _, ShutdownToken, ENV_DATA_STREAM_ENDPOINT, ENV_LAST_VALUE_ENDPOINT,
+    META_TOPIC_CLASS,
+};
+
+use crate::state::AppState;
+
+/// In-process endpoint the [`SubscriberTask`][crate::subscriber::SubscriberTask] connects to
+/// if the proxy is running.
+pub const INTERNAL_DATA_ENDPOINT: &str = "inproc://entity-data";
+
+/// Comma separated list of topic classes, e.g. `actuator_state,measurement`, whose last message
+/// is served at [`ENV_LAST_VALUE_ENDPOINT`].
+pub const ENV_LAST_VALUE_CACHE: &str = "HOME_AUTOMATION_LAST_VALUE_CACHE";
+
+/// Returns the endpoint for external subscribers of the entity data if the proxy is enabled.
+pub fn data_stream_endpoint() -> Option<String> {
+    load_env(ENV_DATA_STREAM_ENDPOINT).ok()
+}
+
+/// Forwards the publications of the entities to the controller and to external subscribers.
+pub struct ProxyTask {
+    frontend: zmq_sockets::XSubscriber<Linked>,
+    backend: zmq_sockets::XPublisher<Linked>,
+    /// Serves the cached values to subscribers that just joined, the XPUB socket cannot
+    /// address a single subscriber.
+    last_value_server: Option<zmq_sockets::Replier<Linked>>,
+    cached_classes: BTreeSet<String>,
+    last_values: HashMap<Vec<u8>, RawMessage>,
+    shutdown: ShutdownToken,
+}
+
+impl ProxyTask {
+    pub fn new(app_state: &AppState, data_stream_endpoint: &str) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            app_state.transport == TransportKind::Zmq,
+            "The data stream proxy requires the zmq transport, but {ENV_TRANSPORT} is {}",
+            app_state.transport
+        );
+        let address = load_env(home_automation_common::ENV_ENTITY_DATA_ENDPOINT)?;
+        let frontend = zmq_sockets::XSubscriber::new(&app_state.context)?.bind(&address)?;
+        let backend =
+            zmq_sockets::XPublisher::new(&app_state.context)?.bind(INTERNAL_DATA_ENDPOINT)?;
+        backend.bind_additional(data_stream_endpoint)?;
+        backend.set_verbose(true)?;
+        let last_value_server = load_env(ENV_LAST_VALUE_ENDPOINT)
+            .ok()
+            .map(|endpoint| zmq_sockets::Replier::new(&app_state.context)?.bind(&endpoint))
+            .transpose()?;
+
+        let cached_classes = std::env::var(ENV_LAST_VALUE_CACHE)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|class| !class.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+        Ok(Self {
+            frontend,
+            backend,
+            last_value_server,
+            cached_classes,
+            last_values: HashMap::new(),
+            shutdown: app_state.shutdown.child(),
+        })
+    }
+
+    #[tracing::instrument(name = "Proxy", skip(self))]
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        tracing::info!(
+            cached_classes = ?self.cached_classes,
+            "Starting entity data proxy."
+        );
+        while !self.shutdown.is_requested() {
+            let Err(e) = self.forward() else {
+                continue;
+            };
+            return Err(e).or_else(termination_is_ok).map_err(Into::into);
+        }
+        Ok(())
+    }
+
+    fn forward(&mut self) -> home_automation_common::Result<()> {
+        let (publication, subscription, query) = {
+            let mut items = vec![self.frontend.poll_item(), self.backend.poll_item()];
+            items.extend(self.last_value_server.as_ref().map(|s| s.poll_item()));
+            zmq_sockets::poll(&mut items, Some(std::time::Duration::from_millis(100)))?;
+            (
+                items[0].is_readable(),
+                items[1].is_readable(),
+                items.get(2).is_some_and(|item| item.is_readable()),
+            )
+        };
+
+        if publication {
+            let message = self.frontend.receive_raw()?;
+            self.backend.send_raw(&message)?;
+            if self.last_value_server.is_some() && self.is_cached(message.topic()) {
+                self.last_values.insert(message.topic().to_owned(), message);
+            }
+        }
+
+        if subscription {
+            let event = self.backend.receive_subscription()?;
+            tracing::info!(%event, "Subscription changed: {event}");
+            self.frontend.send_subscription(&event)?;
+        }
+
+        if let Some(server) = self.last_value_server.as_ref().filter(|_| query) {
+            // a reply is due for every request, even an invalid one
+            let snapshot = match server.receive::<LastValueQuery>() {
+                Ok(query) => self.last_values(query.topic_prefix.as_bytes()),
+                Err(e) if e.is_termination() => return Err(e),
+                Err(e) => {
+                    tracing::warn!("Invalid last value query: {e}");
+                    LastValueSnapshot::default()
+                }
+            };
+            server.send(snapshot)?;
+        }
+        Ok(())
+    }
+
+    /// The descriptions of the entities are always retained, they are only published once.
+    fn is_cached(&self, topic: &[u8]) -> bool {
+        std::str::from_utf8(topic)
+            .ok()
+            .and_then(topic_class)
+            .is_some_and(|class| class == META_TOPIC_CLASS || self.cached_classes.contains(class))
+    }
+
+    /// Returns the cached message of every topic matching the prefix.
+    fn last_values(&self, prefix: &[u8]) -> LastValueSnapshot {
+        let values = self
+            .last_values
+            .iter()
+            .filter(|(topic, _)| topic.starts_with(prefix))
+            .filter_map(|(topic, message)| {
+                Some(LastValue {
+                    topic: String::from_utf8(topic.clone()).ok()?,
+                    envelope: message.0.get(1)?.clone(),
+                })
+            })
+            .collect();
+        LastValueSnapshot { values }
+    }
+}
@@ -0,0 +1,422 @@
+//! Optional file with the registered entities, so that a restarted controller still knows them
+//! and accepts their heartbeats instead of making them register again.
+//!
+//! The last states are kept as well, at most every [`SAVE_INTERVAL`]. They are restored with
+//! their publication time and the entities are suspected until they answer, so the states are
+//! not mistaken as current although the entities may have changed in the meantime.
+//!
+//! The file carries the [`VERSION`] of its format. Older files are migrated when they are read
+//! and written in the current format with the next save, or at once with the `migrate`
+//! subcommand.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use home_automation_common::{protobuf::entity_discovery_command::EntityType, transport::Pattern};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    snapshot::State,
+    state::{AppState, Entity},
+};
+
+/// Optional path to a JSON file in which the controller keeps the registered entities.
+pub const ENV_CONTROLLER_REGISTRY: &str = "HOME_AUTOMATION_CONTROLLER_REGISTRY_FILE";
+
+/// Minimum time between two saves because of changed states.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Steps from one version of the file format to the next, the first one migrates version 1.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "wrap the entities in a versioned object and add their session IDs",
+        apply: wrap_entities,
+    },
+    Migration {
+        description: "keep the last states of the entities",
+        apply: add_states,
+    },
+    Migration {
+        description: "keep the negotiated heartbeat intervals of the entities",
+        apply: add_heartbeat_intervals,
+    },
+];
+
+/// Version of the file format written by this controller.
+pub const VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+pub struct Migration {
+    pub description: &'static str,
+    apply: fn(Value) -> anyhow::Result<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Registration {
+    pub name: String,
+    /// e.g. `SENSOR`
+    pub entity_type: String,
+    pub tags: BTreeSet<String>,
+    pub address: String,
+    pub port: u32,
+    pub session_id: u64,
+    /// `None` if the entity did not publish a state yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<State>,
+    /// Unix time in milliseconds of the publication of the state, 0 if unknown.
+    #[serde(default)]
+    pub published_at_ms: u64,
+    /// Negotiated interval between the heartbeats, 0 for the default.
+    #[serde(default)]
+    pub heartbeat_interval_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    version: u32,
+    entities: Vec<Registration>,
+}
+
+/// Content of a registry file together with the version it was written in.
+#[derive(Debug)]
+pub struct Loaded {
+    pub version: u32,
+    pub registrations: Vec<Registration>,
+}
+
+pub fn path() -> Option<PathBuf> {
+    std::env::var_os(ENV_CONTROLLER_REGISTRY).map(PathBuf::from)
+}
+
+/// Writes the registered entities to the registry file if one is configured.
+///
+/// Failures are only logged because the entities still work without the registry.
+pub fn save(app_state: &AppState) {
+    // tasks save concurrently, e.g. on a registration and on changed states
+    static SAVING: Mutex<()> = Mutex::new(());
+
+    let Some(path) = path() else {
+        return;
+    };
+    let _saving = SAVING.lock().expect("non-poisoned Mutex");
+    let mut registrations: Vec<_> = app_state
+        .entities
+        .iter()
+        .map(|entity| Registration {
+            name: entity.key().clone(),
+            entity_type: entity.state.entity_type().as_str_name().to_owned(),
+            tags: entity.tags.clone(),
+            address: entity.address.clone(),
+            port: entity.port,
+            session_id: entity.session_id,
+            state: State::of(&entity.state),
+            published_at_ms: entity.published_at_ms,
+            heartbeat_interval_ms: entity
+                .heartbeat_interval
+                .as_millis()
+                .try_into()
+                .unwrap_or(u64::MAX),
+        })
+        .collect();
+    registrations.sort_by(|a, b| a.name.cmp(&b.name));
+    if let Err(e) = write(&path, registrations) {
+        tracing::warn!(error = %e, "Failed to save the registry: {e:#}");
+    }
+}
+
+/// Writes the registrations in the current format.
+pub fn write(path: &Path, registrations: Vec<Registration>) -> anyhow::Result<()> {
+    let json = serde_json::to_string_pretty(&RegistryFile {
+        version: VERSION,
+        entities: registrations,
+    })?;
+    // replaced at once so a crash cannot leave a truncated registry behind
+    let temporary = path.with_extension("tmp");
+    std::fs::write(&temporary, json)
+        .with_context(|| format!("Failed to write {}", temporary.display()))?;
+    std::fs::rename(&temporary, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Reads the registry file and migrates it to the current format, `None` if it does not exist.
+pub fn load(path: &Path) -> anyhow::Result<Option<Loaded>> {
+    let json = match std::fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read registry {}", path.display()))
+        }
+    };
+    let invalid = || format!("Invalid registry {}", path.display());
+    let mut value: Value = serde_json::from_str(&json).with_context(invalid)?;
+    let version = version(&value).with_context(invalid)?;
+    for (from, migration) in (version..).zip(&MIGRATIONS[version as usize - 1..]) {
+        value = (migration.apply)(value).with_context(|| {
+            format!(
+                "Failed to migrate registry {} from version {from}",
+                path.display()
+            )
+        })?;
+    }
+    let file: RegistryFile = serde_json::from_value(value).with_context(invalid)?;
+    Ok(Some(Loaded {
+        version,
+        registrations: file.entities,
+    }))
+}
+
+/// Copies the file in the given version next to it, e.g. to `registry.json.v1`.
+pub fn backup(path: &Path, version: u32) -> anyhow::Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{version}"));
+    let backup = PathBuf::from(backup);
+    std::fs::copy(path, &backup)
+        .with_context(|| format!("Failed to back up registry to {}", backup.display()))?;
+    Ok(backup)
+}
+
+/// Version of the format of the file content, version 1 was a plain list of entities.
+fn version(value: &Value) -> anyhow::Result<u32> {
+    if value.is_array() {
+        return Ok(1);
+    }
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .context("Missing version")?;
+    match u32::try_from(version) {
+        Ok(version @ 1..=VERSION) => Ok(version),
+        _ => anyhow::bail!(
+            "Unsupported version {version}, this controller reads up to version {VERSION}"
+        ),
+    }
+}
+
+/// Version 1 to 2.
+fn wrap_entities(value: Value) -> anyhow::Result<Value> {
+    let Value::Array(mut entities) = value else {
+        anyhow::bail!("Expected a list of entities");
+    };
+    for entity in &mut entities {
+        entity
+            .as_object_mut()
+            .context("Expected an entity object")?
+            .entry("session_id")
+            .or_insert(Value::from(0));
+    }
+    Ok(serde_json::json!({ "version": 2, "entities": entities }))
+}
+
+/// Version 2 to 3, the entities of older files have no state.
+fn add_states(mut value: Value) -> anyhow::Result<Value> {
+    *value.get_mut("version").context("Missing version")? = Value::from(3);
+    Ok(value)
+}
+
+/// Version 3 to 4, the entities of older files keep the default heartbeat interval.
+fn add_heartbeat_intervals(mut value: Value) -> anyhow::Result<Value> {
+    *value.get_mut("version").context("Missing version")? = Value::from(4);
+    Ok(value)
+}
+
+/// Registers the entities of the registry file as suspected, returns their names.
+pub fn restore(app_state: &AppState) -> anyhow::Result<Vec<String>> {
+    let Some(path) = path() else {
+        return Ok(Vec::new());
+    };
+    let Some(Loaded {
+        version,
+        registrations,
+    }) = load(&path)?
+    else {
+        return Ok(Vec::new());
+    };
+    let migrated = version < VERSION;
+    if migrated {
+        let backup = backup(&path, version)?;
+        tracing::info!(
+            "Migrated registry {} from version {version} to {VERSION}, kept the old file as {}",
+            path.display(),
+            backup.display()
+        );
+    }
+    let mut names = Vec::with_capacity(registrations.len());
+    for registration in registrations {
+        let entity_type = EntityType::from_str_name(&registration.entity_type)
+            .with_context(|| format!("Unknown entity type {}", registration.entity_type))?;
+        let connection = app_state
+            .transport()
+            .connect(
+                Pattern::Request,
+                &format!("tcp://{}:{}", registration.address, registration.port),
+            )
+            .with_context(|| format!("Failed to restore back-channel of {}", registration.name))?;
+        let mut entity = Entity::new(
+            connection,
+            entity_type,
+            registration.tags,
+            registration.address,
+            registration.port,
+            registration.session_id,
+        );
+        if let Some(state) = registration.state {
+            entity.state = state.entity_state();
+            entity.published_at_ms = registration.published_at_ms;
+        }
+        if registration.heartbeat_interval_ms > 0 {
+            entity.heartbeat_interval = Duration::from_millis(registration.heartbeat_interval_ms);
+        }
+        entity.suspected.store(true, Ordering::SeqCst);
+        names.push(registration.name.clone());
+        app_state.entities.insert(registration.name, entity);
+    }
+    if migrated {
+        save(app_state);
+    }
+    if !names.is_empty() {
+        app_state.state_changed();
+        tracing::info!(
+            ?names,
+            "Restored {} entities from the registry",
+            names.len()
+        );
+    }
+    Ok(names)
+}
+
+/// Saves the registry whenever the states changed, at most every [`SAVE_INTERVAL`], and once
+/// more on shutdown.
+#[tracing::instrument(name = "Registry", skip(app_state))]
+pub fn save_periodically(app_state: &AppState) {
+    let mut saved_generation = app_state.generation();
+    let mut deadline = Instant::now() + SAVE_INTERVAL;
+    while !app_state.shutdown.sleep_until_or_shutdown(deadline) {
+        let generation = app_state.generation();
+        if generation != saved_generation {
+            save(app_state);
+            saved_generation = generation;
+        }
+        deadline += SAVE_INTERVAL;
+    }
+    save(app_state);
+}
+
+/// Pings the restored entities, the ones that answer are no longer suspected.
+///
+/// The others stay suspected until their next heartbeat or registration, or are removed when
+/// their heartbeats are missing.
+pub fn reconcile(app_state: &AppState, names: &[String]) {
+    for name in names {
+        if app_state.shutdown.is_requested() {
+            return;
+        }
+        match app_state.ping(name) {
+            Ok(()) => {
+                if let Some(entity) = app_state.entities.get(name) {
+                    entity.suspected.store(false, Ordering::SeqCst);
+                }
+                tracing::info!("Restored entity {name} is reachable");
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Restored entity {name} is suspected: {e:#}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: &str = r#"[
+        { "name": "sen_kitchen", "entity_type": "SENSOR", "tags": ["indoor"], "address": "10.0.0.7", "port": 5600 },
+        { "name": "act_light", "entity_type": "ACTUATOR", "tags": [], "address": "10.0.0.8", "port": 5601 }
+    ]"#;
+    const V2: &str = r#"{
+        "version": 2,
+        "entities": [
+            { "name": "sen_kitchen", "entity_type": "SENSOR", "tags": ["indoor"], "address": "10.0.0.7", "port": 5600, "session_id": 0 },
+            { "name": "act_light", "entity_type": "ACTUATOR", "tags": [], "address": "10.0.0.8", "port": 5601, "session_id": 0 }
+        ]
+    }"#;
+
+    /// File in a directory of its own that is removed again when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> Self {
+            let directory =
+                std::env::temp_dir().join(format!("registry-test-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&directory).unwrap();
+            let path = directory.join("registry.json");
+            std::fs::write(&path, content).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(self.0.parent().unwrap());
+        }
+    }
+
+    /// Loads the file and writes its registrations in the current format, returns the version it
+    /// was loaded in and the written file.
+    fn migrate(name: &str, content: &str) -> (u32, String) {
+        let file = TempFile::new(name, content);
+        let loaded = load(&file.0).unwrap().unwrap();
+        write(&file.0, loaded.registrations).unwrap();
+        (loaded.version, std::fs::read_to_string(&file.0).unwrap())
+    }
+
+    #[test]
+    fn migrates_old_versions_to_the_current_format() {
+        let (version, from_v1) = migrate("v1", V1);
+        assert_eq!(version, 1);
+        let (version, from_v2) = migrate("v2", V2);
+        assert_eq!(version, 2);
+        assert_eq!(from_v1, from_v2);
+
+        // writing at the current version is stable
+        let (version, rewritten) = migrate("current", &from_v1);
+        assert_eq!(version, VERSION);
+        assert_eq!(rewritten, from_v1);
+
+        let file = TempFile::new("check", &from_v1);
+        let registrations = load(&file.0).unwrap().unwrap().registrations;
+        let names: Vec<_> = registrations.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["sen_kitchen", "act_light"]);
+        assert_eq!(registrations[0].session_id, 0);
+        assert_eq!(registrations[0].tags, BTreeSet::from(["indoor".to_owned()]));
+        assert_eq!(registrations[1].port, 5601);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        for version in [0, VERSION + 1] {
+            let file = TempFile::new(
+                &format!("version-{version}"),
+                &format!(r#"{{ "version": {version}, "entities": [] }}"#),
+            );
+            let error = load(&file.0).unwrap_err();
+            assert!(
+                format!("{error:#}").contains(&format!("Unsupported version {version}")),
+                "{error:#}"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_file_is_no_registry() {
+        let path =
+            std::env::temp_dir().join(format!("registry-test-{}-missing.json", std::process::id()));
+        assert!(load(&path).unwrap().is_none());
+    }
+}
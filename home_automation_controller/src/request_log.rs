@@ -0,0 +1,170 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use home_automation_common::{
+    latency::LatencyWindow, load_env, protobuf::CommandPerformance, STATISTICS_LOG_INTERVAL,
+};
+
+/// Optional number of milliseconds after which a client request is reported as slow.
+pub const ENV_SLOW_REQUEST_THRESHOLD: &str = "HOME_AUTOMATION_SLOW_REQUEST_THRESHOLD_MS";
+
+const DEFAULT_SLOW_REQUEST_THRESHOLD: Duration = Duration::from_millis(500);
+
+/// How a client request was answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+    /// Refused because of the source address of the client.
+    Rejected,
+    /// Answered later, e.g. a state query waiting for a change.
+    Deferred,
+}
+
+impl Outcome {
+    pub fn of<T, E>(result: &Result<T, E>) -> Self {
+        match result {
+            Ok(_) => Self::Succeeded,
+            Err(_) => Self::Failed,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self {
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Rejected => "rejected",
+            Self::Deferred => "deferred",
+        };
+        f.write_str(outcome)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct CommandStatistics {
+    requests: u64,
+    failures: u64,
+    slow: u64,
+    total_duration: Duration,
+    max_duration: Duration,
+    latency: LatencyWindow,
+}
+
+impl fmt::Display for CommandStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mean = self.total_duration / u32::try_from(self.requests.max(1)).unwrap_or(u32::MAX);
+        write!(
+            f,
+            "{} requests, {} failed, {} slow, mean {mean:?}, max {:?}",
+            self.requests, self.failures, self.slow, self.max_duration
+        )
+    }
+}
+
+/// Logs every request of the client API and keeps statistics per command type.
+#[derive(Debug)]
+pub struct RequestLog {
+    slow_request_threshold: Duration,
+    statistics: RefCell<BTreeMap<&'static str, CommandStatistics>>,
+    next_report: Cell<Instant>,
+}
+
+impl RequestLog {
+    pub fn new() -> anyhow::Result<Self> {
+        let slow_request_threshold = match load_env(ENV_SLOW_REQUEST_THRESHOLD) {
+            Ok(millis) => Duration::from_millis(millis.parse().map_err(|e| {
+                anyhow::anyhow!("Invalid {ENV_SLOW_REQUEST_THRESHOLD} {millis:?}: {e}")
+            })?),
+            Err(_) => DEFAULT_SLOW_REQUEST_THRESHOLD,
+        };
+        Ok(Self {
+            slow_request_threshold,
+            statistics: RefCell::default(),
+            next_report: Cell::new(Instant::now() + STATISTICS_LOG_INTERVAL),
+        })
+    }
+
+    /// Logs the finished request and adds it to the statistics of its command type.
+    pub fn record(&self, peer: &str, command: &'static str, started: Instant, outcome: Outcome) {
+        let duration = started.elapsed();
+        let duration_ms = duration.as_secs_f64() * 1000.;
+        let slow = duration > self.slow_request_threshold;
+        if slow {
+            tracing::warn!(
+                peer,
+                command,
+                duration_ms,
+                %outcome,
+                "Slow {command} request from {peer} {outcome} after {duration:?}"
+            );
+        } else if outcome == Outcome::Succeeded || outcome == Outcome::Deferred {
+            tracing::debug!(
+                peer,
+                command,
+                duration_ms,
+                %outcome,
+                "{command} request from {peer} {outcome} after {duration:?}"
+            );
+        } else {
+            tracing::info!(
+                peer,
+                command,
+                duration_ms,
+                %outcome,
+                "{command} request from {peer} {outcome} after {duration:?}"
+            );
+        }
+
+        let mut statistics = self.statistics.borrow_mut();
+        let statistics = statistics.entry(command).or_default();
+        statistics.requests += 1;
+        if matches!(outcome, Outcome::Failed | Outcome::Rejected) {
+            statistics.failures += 1;
+        }
+        if slow {
+            statistics.slow += 1;
+        }
+        statistics.total_duration += duration;
+        statistics.max_duration = statistics.max_duration.max(duration);
+        statistics.latency.record(duration);
+    }
+
+    /// Requests and latency percentiles per command type for the performance view.
+    pub fn performance(&self) -> Vec<CommandPerformance> {
+        self.statistics
+            .borrow()
+            .iter()
+            .map(|(command, statistics)| CommandPerformance {
+                command: (*command).to_owned(),
+                requests: statistics.requests,
+                failures: statistics.failures,
+                latency: Some(statistics.latency.summary()),
+            })
+            .collect()
+    }
+
+    /// Logs the statistics of all command types once per [`STATISTICS_LOG_INTERVAL`].
+    pub fn report_periodically(&self) {
+        let now = Instant::now();
+        if now < self.next_report.get() {
+            return;
+        }
+        self.next_report.set(now + STATISTICS_LOG_INTERVAL);
+        for (command, statistics) in self.statistics.borrow().iter() {
+            tracing::info!(
+                command,
+                requests = statistics.requests,
+                failures = statistics.failures,
+                slow = statistics.slow,
+                max_duration_ms = statistics.max_duration.as_secs_f64() * 1000.,
+                "Request statistics of {command}: {statistics}"
+            );
+        }
+    }
+}
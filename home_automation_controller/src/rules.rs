@@ -0,0 +1,238 @@
+use anyhow::Context as _;
+use home_automation_common::{
+    protobuf::{
+        actuator_state::State, automation_rule, rule_command, sensor_measurement::Value,
+        ActuatorState, AutomationRule, NamedEntityState, PlannedCommand, RuleCommand,
+        SensorMeasurement,
+    },
+    EntityState,
+};
+
+use crate::{
+    config::{Command, Comparison, Condition, Configuration, Rule},
+    state::AppState,
+};
+
+/// Determines the commands that would be sent when activating the given scene.
+pub fn plan_scene(
+    configuration: &Configuration,
+    scene: &str,
+) -> anyhow::Result<Vec<PlannedCommand>> {
+    let commands = configuration
+        .scenes
+        .get(scene)
+        .with_context(|| anyhow::anyhow!("Unknown scene {scene}"))?;
+    Ok(commands
+        .iter()
+        .map(|command| PlannedCommand {
+            origin: format!("scene {scene}"),
+            command: Some(command.into()),
+        })
+        .collect())
+}
+
+/// Sends the commands of the scene to the entities.
+pub fn activate_scene(app_state: &AppState, scene: &str) -> anyhow::Result<()> {
+    let planned = {
+        let configuration = app_state.configuration.read().expect("non-poisoned RwLock");
+        plan_scene(&configuration, scene)?
+    };
+    for command in planned.into_iter().filter_map(|planned| planned.command) {
+        app_state.forward_to_entity(command)?;
+    }
+    Ok(())
+}
+
+/// Determines the commands of all rules whose condition is met by the current state.
+pub fn plan_rules(configuration: &Configuration, app_state: &AppState) -> Vec<PlannedCommand> {
+    configuration
+        .rules
+        .iter()
+        .filter(|rule| rule.condition.is_met(app_state))
+        .flat_map(|rule| {
+            rule.actions.iter().map(|command| PlannedCommand {
+                origin: format!("rule {}", rule.name),
+                command: Some(command.into()),
+            })
+        })
+        .collect()
+}
+
+/// Sends the actions of the rules whose condition became met by the new state of the entity.
+///
+/// Called for every sample, so a rule fires once when its condition changes from unmet to met
+/// instead of with every sample beyond the threshold.
+pub fn evaluate(app_state: &AppState, entity: &str) {
+    if app_state.is_flapping(entity) {
+        return;
+    }
+    let fired: Vec<Rule> = {
+        let configuration = app_state.configuration.read().expect("non-poisoned RwLock");
+        let mut active = app_state.active_rules.lock().expect("non-poisoned Mutex");
+        configuration
+            .rules
+            .iter()
+            .filter(|rule| rule.condition.entity == entity)
+            .filter(|rule| {
+                if rule.condition.is_met(app_state) {
+                    active.insert(rule.name.clone())
+                } else {
+                    active.remove(&rule.name);
+                    false
+                }
+            })
+            .cloned()
+            .collect()
+    };
+    // the locks are released because the back-channels block until the entity answers
+    for rule in fired {
+        tracing::info!(rule = rule.name, "Rule {} fired", rule.name);
+        for command in &rule.actions {
+            if let Err(e) = app_state.forward_to_entity(NamedEntityState::from(command)) {
+                app_state.record_error(format!(
+                    "Rule {} failed to send {command:?}: {e:#}",
+                    rule.name
+                ));
+            }
+        }
+    }
+}
+
+/// Adds or removes a rule at runtime, listing the rules changes nothing.
+pub fn apply(app_state: &AppState, command: RuleCommand) -> anyhow::Result<()> {
+    match command.command {
+        Some(rule_command::Command::List(())) => Ok(()),
+        Some(rule_command::Command::Add(rule)) => {
+            let rule = Rule::try_from(rule)?;
+            let mut configuration = app_state
+                .configuration
+                .write()
+                .expect("non-poisoned RwLock");
+            anyhow::ensure!(
+                configuration.rules.iter().all(|r| r.name != rule.name),
+                "Rule {} already exists",
+                rule.name
+            );
+            tracing::info!(?rule, "Adding rule {}", rule.name);
+            configuration.rules.push(rule);
+            Ok(())
+        }
+        Some(rule_command::Command::Remove(name)) => {
+            let mut configuration = app_state
+                .configuration
+                .write()
+                .expect("non-poisoned RwLock");
+            let count = configuration.rules.len();
+            configuration.rules.retain(|rule| rule.name != name);
+            anyhow::ensure!(configuration.rules.len() < count, "Unknown rule {name}");
+            drop(configuration);
+            tracing::info!("Removed rule {name}");
+            app_state
+                .active_rules
+                .lock()
+                .expect("non-poisoned Mutex")
+                .remove(&name);
+            Ok(())
+        }
+        None => Err(anyhow::anyhow!("Missing command in RuleCommand")),
+    }
+}
+
+pub fn list(configuration: &Configuration) -> Vec<AutomationRule> {
+    configuration
+        .rules
+        .iter()
+        .map(AutomationRule::from)
+        .collect()
+}
+
+impl From<&Rule> for AutomationRule {
+    fn from(rule: &Rule) -> Self {
+        let comparison = match rule.condition.comparison {
+            Comparison::Above => automation_rule::Comparison::Above,
+            Comparison::Below => automation_rule::Comparison::Below,
+        };
+        Self {
+            name: rule.name.clone(),
+            entity: rule.condition.entity.clone(),
+            comparison: comparison.into(),
+            value: rule.condition.value,
+            actions: rule.actions.iter().map(NamedEntityState::from).collect(),
+        }
+    }
+}
+
+impl TryFrom<AutomationRule> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(rule: AutomationRule) -> anyhow::Result<Self> {
+        anyhow::ensure!(!rule.name.is_empty(), "Missing name of the rule");
+        anyhow::ensure!(
+            !rule.entity.is_empty(),
+            "Missing entity in the condition of rule {}",
+            rule.name
+        );
+        anyhow::ensure!(
+            rule.value.is_finite(),
+            "Invalid value {} in the condition of rule {}",
+            rule.value,
+            rule.name
+        );
+        let comparison = match rule.comparison() {
+            automation_rule::Comparison::Above => Comparison::Above,
+            automation_rule::Comparison::Below => Comparison::Below,
+        };
+        let actions = rule
+            .actions
+            .iter()
+            .map(Command::try_from)
+            .collect::<anyhow::Result<_>>()
+            .with_context(|| format!("Invalid action of rule {}", rule.name))?;
+        Ok(Self {
+            condition: Condition {
+                entity: rule.entity,
+                comparison,
+                value: rule.value,
+            },
+            actions,
+            name: rule.name,
+        })
+    }
+}
+
+impl Condition {
+    fn is_met(&self, app_state: &AppState) -> bool {
+        let Some(value) = app_state
+            .entities
+            .get(&self.entity)
+            .and_then(|entity| numeric_value(&entity.state))
+        else {
+            return false;
+        };
+        match self.comparison {
+            Comparison::Above => value > self.value,
+            Comparison::Below => value < self.value,
+        }
+    }
+}
+
+/// Maps the entity state to a number that conditions can be compared against.
+pub fn numeric_value(state: &EntityState) -> Option<f32> {
+    match state {
+        EntityState::Sensor(SensorMeasurement {
+            value: Some(Value::Temperature(t)),
+            ..
+        }) => Some(t.temperature),
+        EntityState::Sensor(SensorMeasurement {
+            value: Some(Value::Humidity(h)),
+            ..
+        }) => Some(h.humidity),
+        EntityState::Actuator(ActuatorState {
+            state: Some(State::Light(l)),
+        }) => Some(l.brightness),
+        EntityState::Actuator(ActuatorState {
+            state: Some(State::AirConditioning(ac)),
+        }) => Some(if ac.on { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use home_automation_common::protobuf::NamedEntityState;
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+
+use crate::{
+    config::{Command, Target},
+    events::{Event, EventReceiver},
+    rules,
+    state::AppState,
+};
+
+/// Interval in which the task checks whether shutdown was requested.
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+/// Upper bound of the operations of a single handler so a script cannot stall the controller.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Commands requested by a script, sent once its handler returned.
+#[derive(Debug)]
+enum Request {
+    Command(Command),
+    Scene(String),
+}
+
+type Requests = Arc<Mutex<Vec<Request>>>;
+
+#[derive(Debug)]
+struct Script {
+    path: PathBuf,
+    ast: AST,
+}
+
+/// Runs the event handlers of the Rhai scripts of a directory.
+///
+/// A script handles an event by defining the function of the same name:
+/// `on_entity_registered(name, entity_type)`, `on_measurement(name, value)` and
+/// `on_alert(message)`. The constant `state` maps the entity names to their current value (`()`
+/// if there is none), and `set_brightness(entity, brightness)`,
+/// `set_air_conditioning(entity, on)` and `activate_scene(scene)` send commands to the entities.
+pub struct ScriptTask<'a> {
+    app_state: &'a AppState,
+    engine: Engine,
+    scripts: Vec<Script>,
+    requests: Requests,
+    events: EventReceiver,
+}
+
+impl<'a> ScriptTask<'a> {
+    pub fn new(app_state: &'a AppState, directory: &Path) -> anyhow::Result<Self> {
+        let requests = Requests::default();
+        let engine = sandboxed_engine(&requests);
+        let mut paths: Vec<_> = std::fs::read_dir(directory)
+            .with_context(|| format!("Failed to read script directory {}", directory.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Failed to read script directory {}", directory.display()))?;
+        paths.retain(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "rhai")
+        });
+        paths.sort();
+        let scripts = paths
+            .into_iter()
+            .map(|path| {
+                let ast = engine.compile_file(path.clone()).map_err(|e| {
+                    anyhow::anyhow!("Failed to compile script {}: {e}", path.display())
+                })?;
+                Ok(Script { path, ast })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            app_state,
+            engine,
+            scripts,
+            requests,
+            events: app_state.events.subscribe("Scripts"),
+        })
+    }
+
+    #[tracing::instrument(name = "Scripts", skip(self))]
+    pub fn run(&self) -> anyhow::Result<()> {
+        tracing::info!("Running {} scripts.", self.scripts.len());
+        while !self.app_state.shutdown.is_requested() {
+            match self.events.recv_timeout(SHUTDOWN_CHECK_INTERVAL) {
+                Ok(event) => self.handle_event(&event),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_event(&self, event: &Event) {
+        let (handler, args) = match event {
+            Event::EntityRegistered {
+                name, entity_type, ..
+            } => (
+                "on_entity_registered",
+                vec![
+                    Dynamic::from(name.clone()),
+                    Dynamic::from(entity_type.as_str_name().to_owned()),
+                ],
+            ),
+            Event::MeasurementReceived { name, value } => (
+                "on_measurement",
+                vec![
+                    Dynamic::from(name.clone()),
+                    Dynamic::from(f64::from(*value)),
+                ],
+            ),
+            Event::Alert { message } => ("on_alert", vec![Dynamic::from(message.clone())]),
+            Event::PayloadMismatch {
+                topic,
+                expected,
+                published,
+            } => (
+                "on_payload_mismatch",
+                vec![
+                    Dynamic::from(topic.clone()),
+                    Dynamic::from(expected.as_str_name().to_owned()),
+                    Dynamic::from(published.as_str_name().to_owned()),
+                ],
+            ),
+        };
+        for script in &self.scripts {
+            if !script.ast.iter_functions().any(|f| f.name == handler) {
+                continue;
+            }
+            let mut scope = Scope::new();
+            scope.push_constant("state", self.state());
+            let options = CallFnOptions::new().eval_ast(false);
+            let result = match self.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut scope,
+                &script.ast,
+                handler,
+                args.clone(),
+            ) {
+                Ok(_) => self.send_requests(),
+                Err(e) => {
+                    // the requests of a failed handler must not be sent by the next one
+                    self.requests.lock().expect("non-poisoned Mutex").clear();
+                    Err(anyhow::anyhow!("{e}"))
+                }
+            };
+            if let Err(e) = result {
+                let message = format!(
+                    "Script {} failed in {handler}: {e:#}",
+                    script.path.display()
+                );
+                tracing::warn!("{message}");
+                // a failing alert handler must not raise the next alert
+                if !matches!(event, Event::Alert { .. }) {
+                    self.app_state.record_error(message);
+                }
+            }
+        }
+    }
+
+    /// Current value of every entity, see [`rules::numeric_value`].
+    fn state(&self) -> Map {
+        self.app_state
+            .entities
+            .iter()
+            .map(|entity| {
+                let value = rules::numeric_value(&entity.state)
+                    .map_or(Dynamic::UNIT, |value| Dynamic::from(f64::from(value)));
+                (entity.key().as_str().into(), value)
+            })
+            .collect()
+    }
+
+    fn send_requests(&self) -> anyhow::Result<()> {
+        let requests = std::mem::take(&mut *self.requests.lock().expect("non-poisoned Mutex"));
+        for request in requests {
+            let commands = match request {
+                Request::Command(command) => vec![command],
+                Request::Scene(scene) => {
+                    let configuration = self
+                        .app_state
+                        .configuration
+                        .read()
+                        .expect("non-poisoned RwLock");
+                    configuration
+                        .scenes
+                        .get(&scene)
+                        .cloned()
+                        .with_context(|| anyhow::anyhow!("Unknown scene {scene}"))?
+                }
+            };
+            for command in &commands {
+                tracing::info!(?command, "Script sends {command:?}");
+                self.app_state
+                    .forward_to_entity(NamedEntityState::from(command))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates an engine whose scripts can only queue requests and print to the log.
+///
+/// Rhai has no access to files, the network or the process unless it is registered explicitly.
+fn sandboxed_engine(requests: &Requests) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(32)
+        .set_max_string_size(10_000)
+        .set_max_array_size(1_000)
+        .set_max_map_size(1_000)
+        .on_print(|text| tracing::info!(target: "script", "{text}"))
+        .on_debug(|text, source, position| {
+            tracing::debug!(target: "script", ?source, %position, "{text}")
+        });
+
+    let queue = |requests: &Requests| {
+        let requests = Arc::clone(requests);
+        move |request: Request| requests.lock().expect("non-poisoned Mutex").push(request)
+    };
+    let push = queue(requests);
+    engine.register_fn("set_brightness", move |entity: &str, brightness: f64| {
+        push(Request::Command(Command {
+            entity: entity.to_owned(),
+            target: Target::Brightness(brightness as f32),
+        }));
+    });
+    let push = queue(requests);
+    engine.register_fn("set_air_conditioning", move |entity: &str, on: bool| {
+        push(Request::Command(Command {
+            entity: entity.to_owned(),
+            target: Target::AirConditioning(on),
+        }));
+    });
+    let push = queue(requests);
+    engine.register_fn("activate_scene", move |scene: &str| {
+        push(Request::Scene(scene.to_owned()));
+    });
+    engine
+}
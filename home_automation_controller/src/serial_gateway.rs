@@ -0,0 +1,235 @@
+use std::time::Duration;
+
+use anyhow::Context as _;
+use home_automation_common::{
+    entity_topic, load_env, meta_topic,
+    protobuf::{
+        entity_discovery_command::{Command, EntityType},
+        publish_data,
+        response_code::Code,
+        EntityDiscoveryCommand, NamedEntityState, PayloadEnvelope, PublishData, ResponseCode,
+    },
+    serial::{self, SerialLink},
+    signing::CommandVerifier,
+    transport::{Channel, Pattern, Transport},
+    zmq_sockets::termination_is_ok,
+    ShutdownToken,
+};
+
+use crate::state::AppState;
+
+/// Comma separated list of serial ports with an entity each, e.g. `/dev/ttyUSB0,/dev/ttyACM0`.
+pub const ENV_SERIAL_GATEWAY_PORTS: &str = "HOME_AUTOMATION_SERIAL_GATEWAY_PORTS";
+
+/// Interval in which the gateway checks for messages of the entity and shutdown requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the controller's requests wait for the answer of the entity.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Returns the serial ports of the gateway, empty if it is disabled.
+pub fn serial_ports() -> Vec<String> {
+    std::env::var(ENV_SERIAL_GATEWAY_PORTS)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|port| !port.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Lets entities on serial ports participate as if they were connected through the sockets.
+pub struct SerialGatewayTask {
+    bridges: Vec<Bridge>,
+}
+
+impl SerialGatewayTask {
+    pub fn new(app_state: &AppState, ports: &[String]) -> anyhow::Result<Self> {
+        let baud_rate = serial::baud_rate()?;
+        let bridges = ports
+            .iter()
+            .map(|port| Bridge::new(app_state, port, baud_rate))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { bridges })
+    }
+
+    #[tracing::instrument(name = "Serial gateway", skip(self))]
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        tracing::info!("Starting serial gateway.");
+        std::thread::scope(|s| {
+            let bridges: Vec<_> = self
+                .bridges
+                .iter_mut()
+                .map(|bridge| s.spawn(move || bridge.run()))
+                .collect();
+            for bridge in bridges {
+                bridge
+                    .join()
+                    .map_err(|e| anyhow::anyhow!("Serial bridge panicked: {e:?}"))??;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Translates between the serial link of a single entity and the sockets of the controller.
+struct Bridge {
+    link: SerialLink,
+    transport: Box<dyn Transport>,
+    discovery_endpoint: String,
+    discovery: Box<dyn Channel>,
+    publisher: Box<dyn Channel>,
+    updates: Box<dyn Channel>,
+    update_port: u16,
+    /// Verifies the commands on behalf of the entity, which cannot do it itself.
+    verifier: CommandVerifier,
+    /// Entity registered through the link, its publications are forwarded to its topic.
+    entity: Option<(String, EntityType)>,
+    shutdown: ShutdownToken,
+}
+
+impl Bridge {
+    fn new(app_state: &AppState, port: &str, baud_rate: u32) -> anyhow::Result<Self> {
+        let link = SerialLink::open(port, baud_rate)?;
+        let transport = app_state.transport();
+        let discovery_endpoint = load_env(home_automation_common::ENV_DISCOVERY_ENDPOINT)?;
+        let discovery = connect_discovery(&*transport, &discovery_endpoint)?;
+        let publisher = transport.connect(
+            Pattern::Publish,
+            &load_env(home_automation_common::ENV_ENTITY_DATA_ENDPOINT)?,
+        )?;
+        let updates = transport.bind(Pattern::Reply, "tcp://*:*")?;
+        let update_port = updates.local_port()?;
+        tracing::info!("Serial gateway for {port} receives updates on port {update_port}");
+        Ok(Self {
+            link,
+            transport,
+            discovery_endpoint,
+            discovery,
+            publisher,
+            updates,
+            update_port,
+            verifier: CommandVerifier::from_env()?,
+            entity: None,
+            shutdown: app_state.shutdown.clone(),
+        })
+    }
+
+    #[tracing::instrument(skip(self), fields(port = self.link.path()))]
+    fn run(&mut self) -> anyhow::Result<()> {
+        while !self.shutdown.is_requested() {
+            if let Some(envelope) = self.link.receive_envelope(POLL_INTERVAL)? {
+                self.handle_entity_message(envelope)?;
+            }
+            match self
+                .updates
+                .receive_message::<NamedEntityState>(Some(POLL_INTERVAL))
+            {
+                Ok(Some((update, _))) => self.forward_update(update)?,
+                Ok(None) => {}
+                Err(e) => return Err(e).or_else(termination_is_ok),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_entity_message(&mut self, envelope: PayloadEnvelope) -> anyhow::Result<()> {
+        if envelope.contains::<EntityDiscoveryCommand>() {
+            let response = self.forward_discovery(envelope.unpack()?);
+            self.link.send(&response)
+        } else if envelope.contains::<PublishData>() {
+            let data: PublishData = envelope.unpack()?;
+            let Some((name, entity_type)) = &self.entity else {
+                tracing::warn!(
+                    "Dropping data of unregistered entity on {}",
+                    self.link.path()
+                );
+                return Ok(());
+            };
+            let topic = match data.value {
+                Some(publish_data::Value::Description(_)) => meta_topic(name),
+                _ => entity_topic(name, *entity_type),
+            };
+            self.publisher
+                .send_message(Some(&topic), &data)
+                .context("Failed to publish data of serial entity")
+        } else {
+            let type_url = envelope.payload.map(|payload| payload.type_url);
+            tracing::warn!(
+                ?type_url,
+                "Dropping unexpected message from {}",
+                self.link.path()
+            );
+            Ok(())
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn forward_discovery(&mut self, mut request: EntityDiscoveryCommand) -> ResponseCode {
+        let entity = (request.entity_name.clone(), request.entity_type());
+        let registered = match &mut request.command {
+            Some(Command::Register(registration)) => {
+                // the controller sends the updates to the gateway instead of the entity
+                registration.port = self.update_port.into();
+                Some(true)
+            }
+            Some(Command::Unregister(())) => Some(false),
+            _ => None,
+        };
+        let result = self
+            .discovery
+            .request::<_, ResponseCode>(&request, Some(RESPONSE_TIMEOUT));
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::error!("Failed to forward discovery request: {e:#}");
+                // the requester cannot send again before it received a reply
+                match connect_discovery(&*self.transport, &self.discovery_endpoint) {
+                    Ok(discovery) => self.discovery = discovery,
+                    Err(e) => tracing::error!("Failed to reconnect to entity discovery: {e:#}"),
+                }
+                return Err::<(), _>(e).into();
+            }
+        };
+        if matches!(response.code(), Code::Ok) {
+            match registered {
+                Some(true) => self.entity = Some(entity),
+                Some(false) => self.entity = None,
+                None => {}
+            }
+        }
+        response
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn forward_update(&mut self, update: NamedEntityState) -> anyhow::Result<()> {
+        // pings change nothing, so they are not signed
+        if update.state.is_some() {
+            if let Err(e) = self.verifier.verify(&update) {
+                tracing::warn!("Rejected command for serial entity: {e}");
+                return self
+                    .updates
+                    .send_message(None, &ResponseCode::from(Err::<(), _>(e)));
+            }
+        }
+        self.link.send(&update)?;
+        let response = self
+            .link
+            .receive::<ResponseCode>(RESPONSE_TIMEOUT)?
+            .unwrap_or_else(|| {
+                Err::<(), _>(anyhow::anyhow!(
+                    "Serial entity on {} did not answer",
+                    self.link.path()
+                ))
+                .into()
+            });
+        self.updates.send_message(None, &response)
+    }
+}
+
+/// Connects a requester to the entity discovery to register entities on their behalf.
+pub fn connect_discovery(
+    transport: &dyn Transport,
+    endpoint: &str,
+) -> anyhow::Result<Box<dyn Channel>> {
+    transport.connect(Pattern::Request, endpoint)
+}
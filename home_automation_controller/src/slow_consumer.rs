@@ -0,0 +1,112 @@
+//! Detection of consumers that do not keep up with the data, e.g. a subscriber whose queue
+//! overflows or an automation that is stuck, so performance problems in the lab show up as
+//! alerts instead of silently missing data.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use home_automation_common::{load_env, zmq_sockets};
+
+use crate::state::AppState;
+
+/// Optional number of milliseconds between the publication and the reception of entity data
+/// above which the subscriber is reported as falling behind.
+pub const ENV_SLOW_CONSUMER_LAG: &str = "HOME_AUTOMATION_SLOW_CONSUMER_LAG_MS";
+
+const DEFAULT_MAX_LAG: Duration = Duration::from_secs(1);
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Events a subscriber of the event bus may have not received before it is reported.
+const MAX_EVENT_BACKLOG: usize = 1000;
+
+pub struct SlowConsumerTask<'a> {
+    app_state: &'a AppState,
+    max_lag: Duration,
+    /// Dropped messages per socket at the last check.
+    dropped: HashMap<String, u64>,
+    subscriber_behind: bool,
+    /// Subscribers of the event bus that are reported as slow.
+    slow_subscribers: BTreeSet<&'static str>,
+}
+
+impl<'a> SlowConsumerTask<'a> {
+    pub fn new(app_state: &'a AppState) -> anyhow::Result<Self> {
+        let max_lag = match load_env(ENV_SLOW_CONSUMER_LAG) {
+            Ok(millis) => millis
+                .parse()
+                .map(Duration::from_millis)
+                .map_err(|e| anyhow::anyhow!("Invalid {ENV_SLOW_CONSUMER_LAG} {millis:?}: {e}"))?,
+            Err(_) => DEFAULT_MAX_LAG,
+        };
+        Ok(Self {
+            app_state,
+            max_lag,
+            dropped: HashMap::new(),
+            subscriber_behind: false,
+            slow_subscribers: BTreeSet::new(),
+        })
+    }
+
+    #[tracing::instrument(name = "Slow consumers", skip(self))]
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        tracing::info!(max_lag = ?self.max_lag, "Starting slow consumer detection.");
+        let mut deadline = Instant::now() + CHECK_INTERVAL;
+        while !self.app_state.shutdown.sleep_until_or_shutdown(deadline) {
+            self.check_dropped_messages();
+            self.check_subscriber_lag();
+            self.check_event_backlogs();
+            deadline += CHECK_INTERVAL;
+        }
+        Ok(())
+    }
+
+    fn check_dropped_messages(&mut self) {
+        for (socket, statistics) in zmq_sockets::all_statistics() {
+            let total = statistics.messages_dropped;
+            let previous = self
+                .dropped
+                .insert(socket.clone(), total)
+                .unwrap_or_default();
+            let dropped = total.saturating_sub(previous);
+            if dropped > 0 {
+                self.app_state.record_error(format!(
+                    "{socket} dropped {dropped} messages, a queue reached its high water mark"
+                ));
+            }
+        }
+    }
+
+    fn check_subscriber_lag(&mut self) {
+        let lag =
+            Duration::from_millis(self.app_state.max_ingest_lag_ms.swap(0, Ordering::Relaxed));
+        let behind = lag > self.max_lag;
+        if behind && !self.subscriber_behind {
+            self.app_state.record_error(format!(
+                "Subscriber is falling behind, entity data arrived up to {lag:?} late"
+            ));
+        } else if !behind && self.subscriber_behind {
+            tracing::info!(?lag, "Subscriber caught up again");
+        }
+        self.subscriber_behind = behind;
+    }
+
+    fn check_event_backlogs(&mut self) {
+        for (subscriber, backlog) in self.app_state.events.backlogs() {
+            if backlog >= MAX_EVENT_BACKLOG {
+                if self.slow_subscribers.insert(subscriber) {
+                    self.app_state.record_error(format!(
+                        "{subscriber} stopped reading events, {backlog} events are waiting"
+                    ));
+                }
+            } else if self.slow_subscribers.remove(subscriber) {
+                tracing::info!(
+                    subscriber,
+                    backlog,
+                    "{subscriber} caught up with the events again"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Snapshot of the whole system as JSON document, so that a known scenario, e.g. for a demo, can
+//! be established again later.
+//!
+//! The snapshot holds the configuration with its rules and scenes, and the registered entities
+//! with their tags and last states. Restoring it replaces the configuration and the tags and
+//! sends the states to the entities. Sensors only adopt their measurement if they are simulated.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Context as _;
+use home_automation_common::{
+    latency::unix_time_ms,
+    protobuf::{
+        actuator_state, sensor_measurement::Value, ActuatorState, NamedEntityState,
+        SnapshotRestoreReport,
+    },
+    EntityState,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Configuration, MeasurementKind},
+    state::AppState,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Snapshot {
+    /// Unix time in milliseconds.
+    taken_at_ms: u64,
+    configuration: Configuration,
+    entities: BTreeMap<String, SnapshotEntity>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SnapshotEntity {
+    /// e.g. `SENSOR`
+    entity_type: String,
+    tags: BTreeSet<String>,
+    /// `None` if the entity did not publish a state yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<State>,
+}
+
+/// Last state of an entity, also kept in the [registry][crate::registry].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Temperature(f32),
+    Humidity(f32),
+    Brightness(f32),
+    AirConditioning(bool),
+}
+
+impl State {
+    pub fn of(state: &EntityState) -> Option<Self> {
+        match state {
+            EntityState::Sensor(measurement) => match measurement.value.as_ref()? {
+                Value::Temperature(t) => Some(Self::Temperature(t.temperature)),
+                Value::Humidity(h) => Some(Self::Humidity(h.humidity)),
+            },
+            EntityState::Actuator(actuator) => match actuator.state.as_ref()? {
+                actuator_state::State::Light(light) => Some(Self::Brightness(light.brightness)),
+                actuator_state::State::AirConditioning(ac) => Some(Self::AirConditioning(ac.on)),
+            },
+            EntityState::New(_) => None,
+        }
+    }
+
+    pub fn entity_state(self) -> EntityState {
+        match self {
+            Self::Temperature(value) => {
+                EntityState::Sensor(MeasurementKind::Temperature.measurement(value))
+            }
+            Self::Humidity(value) => {
+                EntityState::Sensor(MeasurementKind::Humidity.measurement(value))
+            }
+            Self::Brightness(brightness) => EntityState::Actuator(ActuatorState::light(brightness)),
+            Self::AirConditioning(on) => EntityState::Actuator(ActuatorState::air_conditioning(on)),
+        }
+    }
+
+    /// Removes the calibration offset of a sensor, which the controller adds again when the
+    /// sensor publishes the measurement.
+    fn uncalibrated(self, offset: f32) -> Self {
+        match self {
+            Self::Temperature(value) => Self::Temperature(value - offset),
+            Self::Humidity(value) => Self::Humidity(value - offset),
+            Self::Brightness(_) | Self::AirConditioning(_) => self,
+        }
+    }
+
+    fn command(self, entity_name: &str) -> NamedEntityState {
+        match self {
+            Self::Temperature(value) => NamedEntityState::measurement(
+                entity_name,
+                MeasurementKind::Temperature.measurement(value),
+            ),
+            Self::Humidity(value) => NamedEntityState::measurement(
+                entity_name,
+                MeasurementKind::Humidity.measurement(value),
+            ),
+            Self::Brightness(brightness) => {
+                NamedEntityState::actuator(entity_name, ActuatorState::light(brightness))
+            }
+            Self::AirConditioning(on) => {
+                NamedEntityState::actuator(entity_name, ActuatorState::air_conditioning(on))
+            }
+        }
+    }
+}
+
+impl Snapshot {
+    /// The tokens of the configuration are [redacted](Configuration::redacted).
+    pub fn capture(app_state: &AppState) -> Self {
+        let configuration = app_state
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock")
+            .redacted();
+        let entities = app_state
+            .entities
+            .iter()
+            .map(|entity| {
+                let snapshot = SnapshotEntity {
+                    entity_type: entity.state.entity_type().as_str_name().to_owned(),
+                    tags: entity.tags.clone(),
+                    state: State::of(&entity.state),
+                };
+                (entity.key().clone(), snapshot)
+            })
+            .collect();
+        Self {
+            taken_at_ms: unix_time_ms(),
+            configuration,
+            entities,
+        }
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        serde_json::from_str(json).context("Failed to parse snapshot")
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize snapshot")
+    }
+
+    /// Replaces the configuration and the tags and sends the states to the registered entities.
+    ///
+    /// Entities that are not registered are reported as missing, registered entities that are
+    /// not part of the snapshot are left alone. Fails without changes if the snapshot has
+    /// redacted tokens that are not configured.
+    pub fn restore(mut self, app_state: &AppState) -> anyhow::Result<SnapshotRestoreReport> {
+        self.configuration
+            .keep_secrets(&app_state.configuration.read().expect("non-poisoned RwLock"))?;
+        app_state.replace_configuration(self.configuration, "snapshot restore");
+        let mut report = SnapshotRestoreReport::default();
+        for (name, snapshot) in self.entities {
+            let Some(mut entity) = app_state.entities.get_mut(&name) else {
+                report.missing.push(name);
+                continue;
+            };
+            let entity_type = entity.state.entity_type().as_str_name();
+            if entity_type != snapshot.entity_type {
+                report.failed.insert(
+                    name,
+                    format!("Is a {entity_type} but was a {}", snapshot.entity_type),
+                );
+                continue;
+            }
+            entity.tags = snapshot.tags;
+            // not held while waiting for the entity
+            drop(entity);
+            let offset = app_state
+                .configuration
+                .read()
+                .expect("non-poisoned RwLock")
+                .calibration
+                .get(&name)
+                .copied()
+                .unwrap_or_default();
+            let result = snapshot.state.map_or(Ok(()), |state| {
+                app_state.forward_to_entity(state.uncalibrated(offset).command(&name))
+            });
+            match result {
+                Ok(()) => report.restored.push(name),
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to restore state of {name}: {e:#}");
+                    report.failed.insert(name, format!("{e:#}"));
+                }
+            }
+        }
+        app_state.state_changed();
+        tracing::info!(
+            restored = report.restored.len(),
+            missing = ?report.missing,
+            failed = report.failed.len(),
+            "Restored snapshot taken at {} ms",
+            self.taken_at_ms
+        );
+        Ok(report)
+    }
+}
@@ -1,41 +1,626 @@
-use std::{sync::Mutex, time::Instant};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context as _, Result};
 use dashmap::DashMap;
 use home_automation_common::{
-    protobuf::entity_discovery_command::EntityType,
-    zmq_sockets::{self, markers::Linked},
-    EntityState,
+    latency::LatencyWindow,
+    protobuf::{
+        entity_discovery_command::EntityType, EntityDescription, NamedEntityState, ResponseCode,
+    },
+    signing::CommandSigner,
+    transport::{Channel, Pattern, Transport, TransportKind, ZmqTransport},
+    value_range,
+    zmq_sockets::{self, BindRetry},
+    EntityState, ShutdownToken, DEFAULT_HEARTBEAT_INTERVAL,
+};
+
+use crate::{
+    access::{AccessControl, AddressFilter},
+    config::Configuration,
+    events::{Event, EventBus},
+    registry,
 };
 
+/// Number of errors kept for the admin API.
+const RECENT_ERRORS_CAPACITY: usize = 20;
+/// Maximum time to wait for the answer of an entity to a ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+/// Maximum time to wait for the answer of an entity to a forwarded command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Default)]
 pub struct AppState {
     pub entities: DashMap<String, Entity>,
     pub context: zmq_sockets::Context,
+    /// Transport the entities are connected through.
+    pub transport: TransportKind,
+    /// Signs the commands that are not signed by a client yet.
+    pub command_signer: Option<CommandSigner>,
+    pub configuration: RwLock<Configuration>,
+    pub tasks: DashMap<&'static str, TaskStatus>,
+    pub recent_errors: Mutex<VecDeque<(Instant, String)>>,
+    pub shutdown: ShutdownToken,
+    /// Number of requests rejected because of their source address.
+    pub rejected_requests: AtomicU64,
+    /// Number of publications dropped because their payload contradicts their topic, see
+    /// [`AppState::record_payload_mismatch`].
+    pub payload_mismatches: AtomicU64,
+    pub events: EventBus,
+    /// Archive of the removed entities, most recently removed entity first.
+    ///
+    /// Purged according to the archive configuration, see [`AppState::purge_archive`].
+    pub tombstones: Mutex<VecDeque<Tombstone>>,
+    /// Time between the publication and the reception of the data of all entities.
+    pub ingest_latency: Mutex<LatencyWindow>,
+    /// Largest ingest latency since the last check, see [`SlowConsumerTask`].
+    ///
+    /// [`SlowConsumerTask`]: crate::slow_consumer::SlowConsumerTask
+    pub max_ingest_lag_ms: AtomicU64,
+    /// Names of the rules whose condition was met by the last state of their entity, see
+    /// [`rules::evaluate`](crate::rules::evaluate).
+    pub active_rules: Mutex<HashSet<String>>,
+    /// Sources that published without being registered, only tracked in strict mode.
+    pub ghosts: Mutex<BTreeMap<String, Ghost>>,
+    /// Incremented on every change of the entities, see [`AppState::state_changed`].
+    generation: AtomicU64,
+}
+
+/// Archived remains of a removed entity, also used to detect entities that register again.
+#[derive(Debug, Clone)]
+pub struct Tombstone {
+    pub name: String,
+    pub last_state: EntityState,
+    pub tags: BTreeSet<String>,
+    pub address: String,
+    pub history: VecDeque<HistoryEntry>,
+    pub reason: String,
+    pub removed_at: Instant,
+    /// Number of registrations of the same name since the removal.
+    pub rejoins: u32,
+}
+
+/// Source that published without being registered, e.g. a misconfigured publisher.
+#[derive(Debug, Clone)]
+pub struct Ghost {
+    pub entity_type: EntityType,
+    /// Number of rejected publications.
+    pub publications: u64,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    /// Last published state, only kept if the ghosts are quarantined.
+    pub last_state: Option<EntityState>,
+}
+
+/// A published state with its publication time, 0 if unknown.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub published_at_ms: u64,
+    pub state: EntityState,
+}
+
+/// Flapping section of the configuration with durations.
+struct FlappingConfiguration {
+    threshold: usize,
+    window: Duration,
+    quarantine: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub enum TaskStatus {
+    Running,
+    Stopped,
+    Failed(String),
 }
 
 impl AppState {
-    pub fn unregister(&self, entity_name: &str) -> Result<()> {
-        self.entities
+    /// Runs the task while keeping track of its status for the admin API.
+    pub fn supervise(&self, name: &'static str, task: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.tasks.insert(name, TaskStatus::Running);
+        let result = task();
+        let status = match &result {
+            Ok(()) => TaskStatus::Stopped,
+            Err(e) => {
+                self.record_error(format!("{name} task failed: {e:#}"));
+                TaskStatus::Failed(format!("{e:#}"))
+            }
+        };
+        self.tasks.insert(name, status);
+        result
+    }
+
+    /// Atomically replaces the configuration and logs which sections changed.
+    pub fn replace_configuration(&self, configuration: Configuration, source: &str) {
+        let mut current = self.configuration.write().expect("non-poisoned RwLock");
+        let changed = current.changed_sections(&configuration);
+        *current = configuration;
+        drop(current);
+        if changed.is_empty() {
+            tracing::info!(source, "Configuration from {source} is unchanged");
+        } else {
+            let changed = changed.join(", ");
+            tracing::info!(source, %changed, "Configuration changed by {source}: {changed}");
+        }
+    }
+
+    /// Logs the registered entities and the status of the tasks.
+    pub fn log_state(&self) {
+        for entry in &self.entities {
+            let (name, entity) = entry.pair();
+            tracing::info!(
+                %name,
+                state = ?entity.state,
+                address = %entity.address,
+                tags = ?entity.tags,
+                back_channel_healthy = entity.back_channel_healthy.load(Ordering::Relaxed),
+                clock_offset_ms = entity.clock_offset_ms,
+                "Entity {name}: {:?}",
+                entity.state
+            );
+        }
+        for entry in &self.tasks {
+            let (name, status) = entry.pair();
+            tracing::info!(%name, ?status, "Task {name}: {status:?}");
+        }
+        tracing::info!(
+            entities = self.entities.len(),
+            generation = self.generation(),
+            recent_errors = self.recent_errors.lock().expect("non-poisoned Mutex").len(),
+            rejected_requests = self.rejected_requests.load(Ordering::Relaxed),
+            payload_mismatches = self.payload_mismatches.load(Ordering::Relaxed),
+            ingest_latency = ?self.ingest_latency.lock().expect("non-poisoned Mutex").summary(),
+            "Controller state dumped"
+        );
+    }
+
+    /// Retry policy for binding the endpoints of the tasks.
+    pub fn bind_retry(&self) -> BindRetry {
+        let configuration = self.configuration.read().expect("non-poisoned RwLock");
+        BindRetry::from(&configuration.binding)
+    }
+
+    /// Creates the transport for the channels to the entities.
+    pub fn transport(&self) -> Box<dyn Transport> {
+        match self.transport {
+            TransportKind::Zmq => {
+                Box::new(ZmqTransport::new(self.context.clone()).with_bind_retry(self.bind_retry()))
+            }
+            kind => kind.transport(&self.context),
+        }
+    }
+
+    /// Remembers the error so administrators can inspect it later, and raises an alert.
+    pub fn record_error(&self, message: String) {
+        let mut errors = self.recent_errors.lock().expect("non-poisoned Mutex");
+        if errors.len() == RECENT_ERRORS_CAPACITY {
+            errors.pop_back();
+        }
+        errors.push_front((Instant::now(), message.clone()));
+        drop(errors);
+        self.events.publish(Event::Alert { message });
+    }
+
+    /// Fails if the access control of the endpoint does not permit requests from the address.
+    ///
+    /// Rejected requests are logged and counted for the admin API.
+    pub fn check_access(
+        &self,
+        endpoint: impl FnOnce(&AccessControl) -> &AddressFilter,
+        address: &str,
+    ) -> Result<()> {
+        let configuration = self.configuration.read().expect("non-poisoned RwLock");
+        if endpoint(&configuration.access).permits(address) {
+            return Ok(());
+        }
+        self.rejected_requests.fetch_add(1, Ordering::SeqCst);
+        tracing::warn!(address, "Rejected request from address {address}");
+        anyhow::bail!("Access denied for address {address}")
+    }
+
+    /// Counts and reports a publication whose payload belongs to another type of entity than the
+    /// topic or the registered entity, so it is not stored as inconsistent state.
+    pub fn record_payload_mismatch(
+        &self,
+        topic: &str,
+        expected: EntityType,
+        published: EntityType,
+    ) {
+        self.payload_mismatches.fetch_add(1, Ordering::SeqCst);
+        tracing::warn!(
+            topic,
+            ?expected,
+            ?published,
+            "Dropped {published:?} payload published on topic {topic} of {expected:?}"
+        );
+        self.events.publish(Event::PayloadMismatch {
+            topic: topic.to_owned(),
+            expected,
+            published,
+        });
+    }
+
+    /// Publishes the event unless it is about a flapping entity.
+    pub fn publish(&self, event: Event) {
+        if let Some(name) = event.entity().filter(|name| self.is_flapping(name)) {
+            tracing::debug!(?event, "Suppressed event of flapping entity {name}");
+            return;
+        }
+        self.events.publish(event);
+    }
+
+    /// Records the error about the entity unless it is flapping, see [`AppState::record_error`].
+    pub fn record_entity_error(&self, entity_name: &str, message: String) {
+        if self.is_flapping(entity_name) {
+            tracing::warn!("Suppressed alert of flapping entity {entity_name}: {message}");
+        } else {
+            self.record_error(message);
+        }
+    }
+
+    /// Whether the entity was removed as often as the flapping threshold within the window.
+    pub fn is_flapping(&self, entity_name: &str) -> bool {
+        let flapping = self.flapping_configuration();
+        flapping.threshold > 0
+            && self.recent_removals(entity_name, flapping.window).len() >= flapping.threshold
+    }
+
+    /// Remaining time in which registrations of the flapping entity are rejected.
+    pub fn quarantine(&self, entity_name: &str) -> Option<Duration> {
+        let flapping = self.flapping_configuration();
+        if flapping.threshold == 0 || flapping.quarantine.is_zero() {
+            return None;
+        }
+        let removals = self.recent_removals(entity_name, flapping.window);
+        if removals.len() < flapping.threshold {
+            return None;
+        }
+        let last_removal = removals.into_iter().max()?;
+        flapping.quarantine.checked_sub(last_removal.elapsed())
+    }
+
+    fn flapping_configuration(&self) -> FlappingConfiguration {
+        let configuration = self.configuration.read().expect("non-poisoned RwLock");
+        FlappingConfiguration {
+            threshold: configuration.flapping.threshold,
+            window: Duration::from_secs(configuration.flapping.window_secs),
+            quarantine: Duration::from_secs(configuration.flapping.quarantine_secs),
+        }
+    }
+
+    /// Times of the removals of the entity within the window, taken from the tombstones.
+    fn recent_removals(&self, entity_name: &str, window: Duration) -> Vec<Instant> {
+        self.tombstones
+            .lock()
+            .expect("non-poisoned Mutex")
+            .iter()
+            .filter(|t| t.name == entity_name && t.removed_at.elapsed() < window)
+            .map(|t| t.removed_at)
+            .collect()
+    }
+
+    /// Removes the entity and archives it with its history and the reason of the removal.
+    ///
+    /// Raises an alert once the removal makes the entity flapping.
+    pub fn unregister(&self, entity_name: &str, reason: &str) -> Result<()> {
+        let (name, entity) = self
+            .entities
             .remove(entity_name)
             .with_context(|| anyhow::anyhow!("Failed to remove unknown entity {entity_name}"))?;
+        self.state_changed();
+        self.tombstones
+            .lock()
+            .expect("non-poisoned Mutex")
+            .push_front(Tombstone {
+                name,
+                last_state: entity.state,
+                tags: entity.tags,
+                address: entity.address,
+                history: entity.history,
+                reason: reason.to_owned(),
+                removed_at: Instant::now(),
+                rejoins: 0,
+            });
+        self.purge_archive();
+        registry::save(self);
+
+        let flapping = self.flapping_configuration();
+        let removals = self.recent_removals(entity_name, flapping.window).len();
+        if flapping.threshold > 0 && removals == flapping.threshold {
+            self.record_error(format!(
+                "Entity {entity_name} is flapping, it was removed {removals} times within {:?}",
+                flapping.window
+            ));
+        }
+        Ok(())
+    }
+
+    /// Drops the tombstones that exceed the age or the number of entries of the archive.
+    pub fn purge_archive(&self) {
+        let archive = self
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock")
+            .archive
+            .clone();
+        let max_age = Duration::from_secs(archive.max_age_secs);
+        let mut tombstones = self.tombstones.lock().expect("non-poisoned Mutex");
+        let count = tombstones.len();
+        if !max_age.is_zero() {
+            tombstones.retain(|t| t.removed_at.elapsed() < max_age);
+        }
+        tombstones.truncate(archive.max_entries);
+        let purged = count - tombstones.len();
+        if purged > 0 {
+            tracing::debug!(purged, "Purged {purged} entities from the archive");
+        }
+    }
+
+    /// Counts the registration of a previously removed entity, returns whether it rejoined.
+    pub fn rejoin(&self, entity_name: &str) -> bool {
+        let mut tombstones = self.tombstones.lock().expect("non-poisoned Mutex");
+        let Some(tombstone) = tombstones.iter_mut().find(|t| t.name == entity_name) else {
+            return false;
+        };
+        tombstone.rejoins += 1;
+        tracing::info!(
+            rejoins = tombstone.rejoins,
+            "Entity {entity_name} rejoined {:?} after it was removed because of {}",
+            tombstone.removed_at.elapsed(),
+            tombstone.reason
+        );
+        true
+    }
+
+    /// Handles a publication of an entity that is not registered, fails unless in strict mode.
+    ///
+    /// In strict mode, the publication is counted for the ghost of its source instead.
+    pub fn record_ghost(&self, entity_name: &str, state: EntityState) -> Result<()> {
+        let strict = self
+            .configuration
+            .read()
+            .expect("non-poisoned RwLock")
+            .strict
+            .clone();
+        anyhow::ensure!(
+            strict.enabled,
+            "Payload {state:?} received for unknown entity {entity_name}"
+        );
+        let mut ghosts = self.ghosts.lock().expect("non-poisoned Mutex");
+        let now = Instant::now();
+        if !ghosts.contains_key(entity_name) {
+            tracing::warn!("Entity {entity_name} publishes without being registered");
+            if strict.max_ghosts == 0 {
+                return Ok(());
+            }
+            if ghosts.len() >= strict.max_ghosts {
+                let oldest = ghosts
+                    .iter()
+                    .min_by_key(|(_, ghost)| ghost.last_seen)
+                    .map(|(name, _)| name.clone());
+                if let Some(oldest) = oldest {
+                    tracing::debug!("Dropping ghost {oldest} to make room for {entity_name}");
+                    ghosts.remove(&oldest);
+                }
+            }
+        }
+        let ghost = ghosts
+            .entry(entity_name.to_owned())
+            .or_insert_with(|| Ghost {
+                entity_type: state.entity_type(),
+                publications: 0,
+                first_seen: now,
+                last_seen: now,
+                last_state: None,
+            });
+        ghost.entity_type = state.entity_type();
+        ghost.publications += 1;
+        ghost.last_seen = now;
+        ghost.last_state = strict.quarantine_ghosts.then_some(state);
         Ok(())
     }
+
+    /// Forgets the ghost of an entity that registered.
+    pub fn release_ghost(&self, entity_name: &str) {
+        let ghost = self
+            .ghosts
+            .lock()
+            .expect("non-poisoned Mutex")
+            .remove(entity_name);
+        if let Some(ghost) = ghost {
+            tracing::info!(
+                publications = ghost.publications,
+                "Entity {entity_name} registered after {} publications as ghost",
+                ghost.publications
+            );
+        }
+    }
+
+    /// Signs the command on behalf of the controller unless a client signed it already.
+    ///
+    /// Only called while holding the connection of the entity, so the commands are sent in the
+    /// order of their nonces, which the entity requires to increase.
+    fn sign(&self, mut command: NamedEntityState) -> NamedEntityState {
+        if let (None, Some(signer)) = (&command.signature, &self.command_signer) {
+            signer.sign(&mut command);
+        }
+        command
+    }
+
+    /// Checks whether the entity answers via its back-channel.
+    pub fn ping(&self, entity_name: &str) -> Result<()> {
+        let entity = self
+            .entities
+            .get(entity_name)
+            .with_context(|| anyhow::anyhow!("Unknown entity {entity_name} in ping command"))?;
+
+        self.exchange(&entity, NamedEntityState::ping(entity_name), PING_TIMEOUT)
+            .map(|_| ())
+            .with_context(|| anyhow::anyhow!("Entity {entity_name} did not answer the ping"))
+    }
+
+    /// Sends the command to the entity via its back-channel and waits for the answer.
+    ///
+    /// Values outside of the [allowed ranges](value_range) are rejected before they are sent.
+    pub fn forward_to_entity(&self, entity_state: NamedEntityState) -> anyhow::Result<()> {
+        use home_automation_common::protobuf::response_code::Code;
+        value_range::validate(&entity_state)?;
+        let entity_name = entity_state.entity_name.clone();
+
+        let entity = self.entities.get(&entity_name).with_context(|| {
+            anyhow::anyhow!(
+                "Unknown entity {} in NamedEntityState command",
+                &entity_state.entity_name
+            )
+        })?;
+
+        tracing::debug!(?entity_state, "Forwarding command via back-channel.");
+        let response_code = self.exchange(&entity, entity_state, COMMAND_TIMEOUT)?;
+
+        match response_code.code() {
+            Code::Ok => Ok(()),
+            Code::Error => Err(anyhow::anyhow!("Failed to update entity {entity_name}")),
+        }
+    }
+
+    /// Signs the request and sends it via the back-channel of the entity and waits up to
+    /// `timeout` for the answer.
+    ///
+    /// A REQ socket whose request was not answered refuses further requests, so the back-channel
+    /// is connected again after every failed exchange (lazy pirate pattern).
+    fn exchange(
+        &self,
+        entity: &Entity,
+        request: NamedEntityState,
+        timeout: Duration,
+    ) -> Result<ResponseCode> {
+        let mut connection = entity.connection.lock().expect("poisoned mutex");
+        let request = self.sign(request);
+        let result = connection.request(&request, Some(timeout));
+        entity
+            .back_channel_healthy
+            .store(result.is_ok(), Ordering::SeqCst);
+        if result.is_err() {
+            match self
+                .transport()
+                .connect(Pattern::Request, &entity.back_channel_endpoint())
+            {
+                Ok(reconnected) => *connection = reconnected,
+                Err(e) => tracing::warn!(
+                    "Failed to reconnect the back-channel of {}: {e:#}",
+                    request.entity_name
+                ),
+            }
+        }
+        result
+    }
+
+    /// Must be called after modifying the entities so that waiting state queries are answered.
+    pub fn state_changed(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
 }
 
 #[derive(Debug)]
 pub struct Entity {
     pub state: EntityState,
     pub last_heartbeat_pulse: Instant,
-    pub connection: Mutex<zmq_sockets::Requester<Linked>>,
+    /// Negotiated at the registration.
+    pub heartbeat_interval: Duration,
+    pub connection: Mutex<Box<dyn Channel>>,
+    /// Whether the last message exchange via the back-channel succeeded.
+    pub back_channel_healthy: AtomicBool,
+    pub tags: BTreeSet<String>,
+    /// IP address the entity registered from.
+    pub address: String,
+    /// Port of the back-channel of the entity at its address.
+    pub port: u32,
+    /// Random ID of the running instance of the entity, 0 if the entity has no session.
+    pub session_id: u64,
+    /// Restored from the registry after a restart and not confirmed by a ping or heartbeat yet.
+    pub suspected: AtomicBool,
+    /// Unix time in milliseconds of the publication of the current state, 0 if unknown.
+    ///
+    /// Already corrected by the clock offset.
+    pub published_at_ms: u64,
+    /// How far the clock of the entity is ahead, as reported with its heartbeats.
+    pub clock_offset_ms: i64,
+    pub ingest_latency: LatencyWindow,
+    /// Published states, oldest first and including the current one, archived on removal.
+    pub history: VecDeque<HistoryEntry>,
+    /// Published by the entity after its registration, not kept in the registry.
+    pub description: Option<EntityDescription>,
 }
 
 impl Entity {
-    pub fn new(connection: zmq_sockets::Requester<Linked>, entity_type: EntityType) -> Self {
+    pub fn new(
+        connection: Box<dyn Channel>,
+        entity_type: EntityType,
+        tags: BTreeSet<String>,
+        address: String,
+        port: u32,
+        session_id: u64,
+    ) -> Self {
         Self {
             state: EntityState::New(entity_type),
             last_heartbeat_pulse: Instant::now(),
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
             connection: connection.into(),
+            back_channel_healthy: AtomicBool::new(true),
+            tags,
+            address,
+            port,
+            session_id,
+            suspected: AtomicBool::new(false),
+            published_at_ms: 0,
+            clock_offset_ms: 0,
+            ingest_latency: LatencyWindow::default(),
+            history: VecDeque::new(),
+            description: None,
+        }
+    }
+
+    /// Endpoint of the back-channel of the entity.
+    pub fn back_channel_endpoint(&self) -> String {
+        format!("tcp://{}:{}", self.address, self.port)
+    }
+
+    /// The entity is removed once its last heartbeat is older than this, i.e. after it missed
+    /// two heartbeats.
+    pub fn heartbeat_timeout(&self) -> Duration {
+        self.heartbeat_interval * 2
+    }
+
+    /// Whether the command was sent by another instance of the entity.
+    ///
+    /// Commands of entities without session cannot be told apart and are never rejected.
+    pub fn is_other_instance(&self, session_id: u64) -> bool {
+        self.session_id != 0 && session_id != 0 && self.session_id != session_id
+    }
+
+    /// Replaces the state and keeps at most `history_len` states in the history.
+    pub fn update_state(&mut self, state: EntityState, published_at_ms: u64, history_len: usize) {
+        if history_len > 0 {
+            if self.history.len() >= history_len {
+                self.history.drain(..=self.history.len() - history_len);
+            }
+            self.history.push_back(HistoryEntry {
+                published_at_ms,
+                state: state.clone(),
+            });
+        } else {
+            self.history.clear();
         }
+        self.state = state;
+        self.published_at_ms = published_at_ms;
     }
 }
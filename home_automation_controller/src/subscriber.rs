@@ -1,44 +1,86 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
 use anyhow::Context as _;
 use home_automation_common::{
-    load_env,
-    protobuf::{publish_data, PublishData},
-    shutdown_requested,
-    zmq_sockets::{self, markers::Linked},
-    AnyhowZmq, EntityState,
+    envelope::Headers,
+    latency, load_env,
+    protobuf::{
+        entity_discovery_command::EntityType, publish_data, EntityDescription, PublishData,
+        SensorMeasurement,
+    },
+    transport::{self, Channel, Pattern, Transport as _, ZmqTransport, TOPIC_HEADER},
+    udp::{self, UdpSubscriber},
+    EntityState, ErrorKindExt, STATISTICS_LOG_INTERVAL,
 };
 
-use crate::state::AppState;
+use crate::{events::Event, proxy::INTERNAL_DATA_ENDPOINT, rules, state::AppState};
+
+/// Interval in which the subscriber and the UDP listener check for shutdown requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 pub struct SubscriberTask<'a> {
     app_state: &'a AppState,
-    subscriber: zmq_sockets::Subscriber<Linked>,
+    subscriber: Box<dyn Channel>,
+    /// Receives the data of the entities that publish over UDP multicast.
+    udp: Option<UdpSubscriber>,
 }
 
 impl<'a> SubscriberTask<'a> {
-    pub fn new(app_state: &'a AppState) -> anyhow::Result<Self> {
-        let address = load_env(home_automation_common::ENV_ENTITY_DATA_ENDPOINT)?;
-        let subscriber = zmq_sockets::Subscriber::new(&app_state.context)?.bind(&address)?;
-        subscriber.subscribe("")?;
+    /// If the proxy is running, the entity data is received from it instead of the entities.
+    pub fn new(app_state: &'a AppState, proxy_enabled: bool) -> anyhow::Result<Self> {
+        let subscriber = if proxy_enabled {
+            ZmqTransport::new(app_state.context.clone())
+                .connect(Pattern::Subscribe, INTERNAL_DATA_ENDPOINT)?
+        } else {
+            let address = load_env(home_automation_common::ENV_ENTITY_DATA_ENDPOINT)?;
+            app_state.transport().bind(Pattern::Subscribe, &address)?
+        };
+        let udp = udp::multicast_group()?
+            .map(|group| {
+                tracing::info!("Receiving entity data from multicast group {group}");
+                UdpSubscriber::join(group, POLL_INTERVAL)
+            })
+            .transpose()?;
         Ok(Self {
             app_state,
             subscriber,
+            udp,
         })
     }
 
     #[tracing::instrument(name = "Subscriber", skip(self))]
     pub fn run(&self) -> anyhow::Result<()> {
         tracing::info!("Starting Subscriber.");
-        while !shutdown_requested() {
-            self.handle_client();
-        }
-        Ok(())
+        let app_state = self.app_state;
+        std::thread::scope(|s| {
+            let udp = self
+                .udp
+                .as_ref()
+                .map(|udp| s.spawn(move || run_udp_listener(app_state, udp)));
+            while !app_state.shutdown.is_requested() {
+                self.handle_client();
+            }
+            if let Some(udp) = udp {
+                udp.join()
+                    .map_err(|e| anyhow::anyhow!("UDP listener panicked: {e:?}"))?;
+            }
+            Ok(())
+        })
     }
 
-    #[tracing::instrument(name = "receive sample", skip(self))]
     fn handle_client(&self) {
-        let result = self.inner_handle_client();
+        let result = self
+            .subscriber
+            .receive_message(Some(POLL_INTERVAL))
+            .and_then(|publication| match publication {
+                Some((payload, headers)) => self.handle_sample(payload, headers),
+                None => Ok(()),
+            });
         if let Err(e) = result {
-            if !e.is_zmq_termination() {
+            if !e.is_termination() {
                 tracing::error!("Failed handle client publication: {e:#}");
             } else {
                 tracing::info!("Cannot handle client publication because shutdown is in progress.");
@@ -46,29 +88,258 @@ impl<'a> SubscriberTask<'a> {
         }
     }
 
-    fn inner_handle_client(&self) -> anyhow::Result<()> {
-        let (topic, payload): (String, PublishData) = self.subscriber.receive()?;
+    #[tracing::instrument(name = "receive sample", skip(self, headers))]
+    fn handle_sample(&self, payload: PublishData, headers: Headers) -> anyhow::Result<()> {
+        transport::follow_remote_span(&headers);
+        let topic = headers
+            .get(TOPIC_HEADER)
+            .cloned()
+            .context("Missing topic of publication")?;
+        // only the ZMQ subscription filters the topics of other deployments
+        if !topic.starts_with(home_automation_common::topic_prefix()) {
+            return Ok(());
+        }
+        handle_publication(self.app_state, topic, payload)
+    }
+}
 
-        let update_state = |name, state| -> anyhow::Result<()> {
-            let mut entry = self.app_state.entities.get_mut(&name).with_context(|| {
-                anyhow::anyhow!("Payload {state:?} received for unknown entity {name}")
-            })?;
-            tracing::info!("Updating entity {name} with new state {state:?}");
-            entry.state = state;
-            Ok(())
-        };
+/// Receives the publications over UDP multicast until the shutdown.
+#[tracing::instrument(name = "UDP listener", skip_all)]
+fn run_udp_listener(app_state: &AppState, udp: &UdpSubscriber) {
+    let mut next_statistics = Instant::now() + STATISTICS_LOG_INTERVAL;
+    while !app_state.shutdown.is_requested() {
+        let result = udp
+            .receive::<PublishData>()
+            .and_then(|publication| match publication {
+                // the ZMQ subscription filters the topics of other deployments
+                Some((topic, payload))
+                    if topic.starts_with(home_automation_common::topic_prefix()) =>
+                {
+                    handle_publication(app_state, topic, payload)
+                }
+                _ => Ok(()),
+            });
+        if let Err(e) = result {
+            tracing::error!("Failed to handle UDP publication: {e:#}");
+        }
+        if Instant::now() >= next_statistics {
+            let statistics = udp.statistics();
+            tracing::info!(?statistics, "UDP data plane: {statistics}");
+            next_statistics += STATISTICS_LOG_INTERVAL;
+        }
+    }
+}
 
-        match payload.value {
-            None => anyhow::bail!("Missing payload in {payload:?} for topic {topic}"),
-            Some(publish_data::Value::Measurement(m)) => {
-                let name = home_automation_common::sensor_name(&topic)?;
-                update_state(name, EntityState::Sensor(m))?;
-            }
-            Some(publish_data::Value::ActuatorState(s)) => {
-                let name = home_automation_common::actuator_name(&topic)?;
-                update_state(name, EntityState::Actuator(s))?;
-            }
+fn handle_publication(
+    app_state: &AppState,
+    topic: String,
+    payload: PublishData,
+) -> anyhow::Result<()> {
+    let published_at_ms = payload.published_at_ms;
+    let Some(value) = payload.value else {
+        anyhow::bail!("Missing payload in {payload:?} for topic {topic}")
+    };
+    let published = match value {
+        publish_data::Value::Description(description) => {
+            return update_description(app_state, &topic, description);
+        }
+        publish_data::Value::ActuatorState(_) => EntityType::Actuator,
+        publish_data::Value::Measurement(_) | publish_data::Value::Batch(_) => EntityType::Sensor,
+    };
+    if let Some(expected) =
+        home_automation_common::topic_entity_type(&topic).filter(|&expected| expected != published)
+    {
+        app_state.record_payload_mismatch(&topic, expected, published);
+        return Ok(());
+    }
+    let publication = Publication {
+        topic: &topic,
+        published_at_ms,
+        origin: Origin::Entity,
+    };
+
+    let measurements = match value {
+        publish_data::Value::ActuatorState(s) => {
+            let name = home_automation_common::actuator_name(&topic)?;
+            update_states(
+                app_state,
+                &publication,
+                &name,
+                vec![(EntityState::Actuator(s), published_at_ms)],
+            )?;
+            return Ok(());
+        }
+        publish_data::Value::Measurement(m) => vec![(m, published_at_ms)],
+        publish_data::Value::Batch(batch) => {
+            anyhow::ensure!(
+                !batch.measurements.is_empty(),
+                "Empty batch for topic {topic}"
+            );
+            batch
+                .measurements
+                .into_iter()
+                .map(|m| {
+                    let measurement = m
+                        .measurement
+                        .with_context(|| format!("Missing measurement in batch for {topic}"))?;
+                    Ok((measurement, m.measured_at_ms))
+                })
+                .collect::<anyhow::Result<_>>()?
+        }
+    };
+    let name = home_automation_common::sensor_name(&topic)?;
+    update_measurements(app_state, &publication, &name, measurements)
+}
+
+/// Stores a measurement of the sensor that the controller received in another way, e.g. by a
+/// webhook, like a publication of the sensor itself.
+pub fn inject_measurement(
+    app_state: &AppState,
+    name: &str,
+    measurement: SensorMeasurement,
+) -> anyhow::Result<()> {
+    let entity_type = app_state
+        .entities
+        .get(name)
+        .with_context(|| anyhow::anyhow!("Unknown sensor {name}"))?
+        .state
+        .entity_type();
+    anyhow::ensure!(
+        entity_type == EntityType::Sensor,
+        "Entity {name} is not a sensor"
+    );
+    let now = latency::unix_time_ms();
+    let topic = home_automation_common::entity_topic(name, EntityType::Sensor);
+    let publication = Publication {
+        topic: &topic,
+        published_at_ms: now,
+        origin: Origin::Controller,
+    };
+    update_measurements(app_state, &publication, name, vec![(measurement, now)])
+}
+
+/// Clock that took the timestamps of a publication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    /// Corrected by the clock offset of the entity and counted in the ingest latency.
+    Entity,
+    Controller,
+}
+
+#[derive(Debug)]
+struct Publication<'a> {
+    topic: &'a str,
+    published_at_ms: u64,
+    origin: Origin,
+}
+
+/// Calibrates the measurements of the sensor, stores them and publishes their events.
+fn update_measurements(
+    app_state: &AppState,
+    publication: &Publication,
+    name: &str,
+    measurements: Vec<(SensorMeasurement, u64)>,
+) -> anyhow::Result<()> {
+    let mut values = Vec::new();
+    let mut states = Vec::new();
+    {
+        let configuration = app_state.configuration.read().expect("non-poisoned RwLock");
+        for (mut m, measured_at_ms) in measurements {
+            configuration.calibrate(name, &mut m);
+            values.extend(rules::numeric_value(&EntityState::Sensor(m.clone())));
+            states.push((EntityState::Sensor(m), measured_at_ms));
         }
-        Ok(())
     }
+    if !update_states(app_state, publication, name, states)? {
+        return Ok(());
+    }
+    for value in values {
+        app_state.publish(Event::MeasurementReceived {
+            name: name.to_owned(),
+            value,
+        });
+    }
+    Ok(())
+}
+
+/// Stores the states of the entity, a batch holds several states, each with the time it was
+/// measured.
+///
+/// Returns whether the states were stored, ghosts and mismatches must not trigger any events.
+fn update_states(
+    app_state: &AppState,
+    publication: &Publication,
+    name: &str,
+    states: Vec<(EntityState, u64)>,
+) -> anyhow::Result<bool> {
+    let topic = publication.topic;
+    let published = states.first().context("Empty publication")?.0.entity_type();
+    let history_len = app_state
+        .configuration
+        .read()
+        .expect("non-poisoned RwLock")
+        .archive
+        .history_len;
+    let Some(mut entry) = app_state.entities.get_mut(name) else {
+        // the last state of a batch is the most recent one
+        let (state, _) = states.into_iter().last().context("Empty publication")?;
+        app_state.record_ghost(name, state)?;
+        return Ok(false);
+    };
+    // e.g. an actuator that publishes measurements under its name
+    let expected = entry.state.entity_type();
+    if expected != published {
+        drop(entry);
+        app_state.record_payload_mismatch(topic, expected, published);
+        return Ok(false);
+    }
+    let clock_offset_ms = match publication.origin {
+        Origin::Entity => entry.clock_offset_ms,
+        Origin::Controller => 0,
+    };
+    for (state, measured_at_ms) in states {
+        tracing::info!("Updating entity {name} with new state {state:?}");
+        let measured_at_ms = latency::correct_timestamp_ms(measured_at_ms, clock_offset_ms);
+        entry.update_state(state, measured_at_ms, history_len);
+    }
+    if publication.origin == Origin::Entity {
+        let published_at_ms =
+            latency::correct_timestamp_ms(publication.published_at_ms, clock_offset_ms);
+        entry.ingest_latency.record_since(published_at_ms);
+        drop(entry);
+        app_state
+            .ingest_latency
+            .lock()
+            .expect("non-poisoned Mutex")
+            .record_since(published_at_ms);
+        if published_at_ms != 0 {
+            let lag_ms = latency::unix_time_ms().saturating_sub(published_at_ms);
+            app_state
+                .max_ingest_lag_ms
+                .fetch_max(lag_ms, Ordering::Relaxed);
+        }
+    } else {
+        drop(entry);
+    }
+    app_state.state_changed();
+    rules::evaluate(app_state, name);
+    Ok(true)
+}
+
+/// Stores the self-description of a registered entity, which replaces the previous one.
+fn update_description(
+    app_state: &AppState,
+    topic: &str,
+    description: EntityDescription,
+) -> anyhow::Result<()> {
+    let name = home_automation_common::meta_name(topic)?;
+    let mut entity = app_state
+        .entities
+        .get_mut(&name)
+        .with_context(|| format!("Description received for unknown entity {name}"))?;
+    tracing::info!("Entity {name} describes itself as {description:?}");
+    entity.description = Some(description);
+    drop(entity);
+    app_state.state_changed();
+    Ok(())
 }
@@ -1,9 +1,11 @@
 use std::time::{Duration, Instant};
 
-use home_automation_common::{shutdown_requested, HEARTBEAT_FREQUENCY};
-
 use crate::state::AppState;
 
+/// Interval in which the task looks for dead entities, short enough for the shortest heartbeat
+/// interval an entity can negotiate.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct TimeoutTask<'a> {
     app_state: &'a AppState,
 }
@@ -16,13 +18,10 @@ impl<'a> TimeoutTask<'a> {
     #[tracing::instrument(name = "Timeout for un-registration", skip(self))]
     pub fn run(&self) -> anyhow::Result<()> {
         tracing::info!("Running Timeout task.");
-        let mut last_run = Instant::now();
-        while !shutdown_requested() {
-            std::thread::sleep(Duration::from_millis(100));
-            if last_run.elapsed() > HEARTBEAT_FREQUENCY {
-                self.unregister_dead_entities();
-                last_run = Instant::now();
-            }
+        let mut deadline = Instant::now() + CHECK_INTERVAL;
+        while !self.app_state.shutdown.sleep_until_or_shutdown(deadline) {
+            self.unregister_dead_entities();
+            deadline += CHECK_INTERVAL;
         }
         Ok(())
     }
@@ -30,13 +29,21 @@ impl<'a> TimeoutTask<'a> {
     #[tracing::instrument(skip(self))]
     fn unregister_dead_entities(&self) {
         let now = Instant::now();
-        self.app_state.entities.retain(|name, entity| {
-            if now.duration_since(entity.last_heartbeat_pulse) < HEARTBEAT_FREQUENCY * 2 {
-                true
-            } else {
-                tracing::info!("Unregistering entity {name} because of missed heartbeats");
-                false
+        // collected first because removing entries while iterating would deadlock
+        let dead: Vec<_> = self
+            .app_state
+            .entities
+            .iter()
+            .filter(|entity| {
+                now.duration_since(entity.last_heartbeat_pulse) >= entity.heartbeat_timeout()
+            })
+            .map(|entity| entity.key().clone())
+            .collect();
+        for name in dead {
+            tracing::info!("Unregistering entity {name} because of missed heartbeats");
+            if let Err(e) = self.app_state.unregister(&name, "missed heartbeats") {
+                tracing::debug!("Entity {name} is already gone: {e:#}");
             }
-        });
+        }
     }
 }
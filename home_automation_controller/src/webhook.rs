@@ -0,0 +1,125 @@
+use home_automation_common::protobuf::NamedEntityState;
+use serde::Deserialize;
+use tiny_http::{Method, Request, Response, Server};
+
+use crate::{
+    config::WebhookAction,
+    http::{self, is_authorized, read_body, Rejection},
+    rules,
+    state::AppState,
+    subscriber,
+};
+
+const HOOK_PATH_PREFIX: &str = "/hooks/";
+/// Upper bound of the request body, the bodies only contain a single value.
+const MAX_BODY_SIZE: u64 = 4096;
+
+/// Body of a webhook request for a virtual measurement.
+#[derive(Debug, Deserialize)]
+struct Body {
+    value: f32,
+}
+
+/// Triggers the actions of the configured webhooks for inbound HTTP requests.
+pub struct WebhookTask<'a> {
+    app_state: &'a AppState,
+    server: Server,
+}
+
+impl<'a> WebhookTask<'a> {
+    pub fn new(app_state: &'a AppState, endpoint: &str) -> anyhow::Result<Self> {
+        let server = Server::http(endpoint)
+            .map_err(|e| anyhow::anyhow!("Failed to start webhook server on {endpoint}: {e}"))?;
+        tracing::info!(%endpoint, "Webhooks can be called at http://{endpoint}{HOOK_PATH_PREFIX}<name>");
+        Ok(Self { app_state, server })
+    }
+
+    #[tracing::instrument(name = "Webhooks", skip(self))]
+    pub fn run(&self) -> anyhow::Result<()> {
+        tracing::info!("Starting webhook server.");
+        http::serve(&self.server, &self.app_state.shutdown, |request| {
+            self.handle_request(request)
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(url = request.url(), peer = ?request.remote_addr()))]
+    fn handle_request(&self, mut request: Request) {
+        let response = match self.trigger(&mut request) {
+            Ok(()) => Response::from_string("").with_status_code(204),
+            Err(rejection) => {
+                tracing::warn!(
+                    status = rejection.status,
+                    "Rejected webhook request: {}",
+                    rejection.message
+                );
+                Response::from_string(rejection.message).with_status_code(rejection.status)
+            }
+        };
+        if let Err(e) = request.respond(response) {
+            tracing::warn!("Failed to answer webhook request: {e}");
+        }
+    }
+
+    fn trigger(&self, request: &mut Request) -> Result<(), Rejection> {
+        let address = request
+            .remote_addr()
+            .map(|address| address.ip().to_string())
+            .unwrap_or_default();
+        self.app_state
+            .check_access(|access| &access.client_api, &address)
+            .map_err(|e| Rejection::new(403, format!("{e:#}")))?;
+        if *request.method() != Method::Post {
+            return Err(Rejection::new(405, "Webhooks must be called with POST"));
+        }
+        let name = request
+            .url()
+            .strip_prefix(HOOK_PATH_PREFIX)
+            .ok_or_else(|| Rejection::new(404, "Unknown path"))?
+            .to_owned();
+        let action = {
+            let configuration = self
+                .app_state
+                .configuration
+                .read()
+                .expect("non-poisoned RwLock");
+            let webhooks = configuration
+                .webhooks
+                .as_ref()
+                .ok_or_else(|| Rejection::new(404, "Webhooks are disabled"))?;
+            if !is_authorized(request, &webhooks.token) {
+                return Err(Rejection::new(401, "Missing or wrong bearer token"));
+            }
+            webhooks
+                .hooks
+                .get(&name)
+                .cloned()
+                .ok_or_else(|| Rejection::new(404, format!("Unknown webhook {name}")))?
+        };
+        tracing::info!(?action, "Webhook {name} triggers {action:?}");
+        let result = match action {
+            WebhookAction::Scene(scene) => rules::activate_scene(self.app_state, &scene),
+            WebhookAction::Command(command) => self
+                .app_state
+                .forward_to_entity(NamedEntityState::from(&command)),
+            WebhookAction::Measurement(measurement) => {
+                let body = read_value(request)?;
+                subscriber::inject_measurement(
+                    self.app_state,
+                    &measurement.entity,
+                    measurement.kind.measurement(body.value),
+                )
+            }
+        };
+        result.map_err(|e| {
+            self.app_state
+                .record_error(format!("Webhook {name} failed: {e:#}"));
+            Rejection::failed(&e)
+        })
+    }
+}
+
+fn read_value(request: &mut Request) -> Result<Body, Rejection> {
+    let body = read_body(request, MAX_BODY_SIZE)?;
+    serde_json::from_str(&body)
+        .map_err(|e| Rejection::new(400, format!("Expected {{ \"value\": <number> }}: {e}")))
+}
@@ -0,0 +1,55 @@
+/* C ABI of the home automation entities, see home_automation_entity/src/ffi.rs. */
+#ifndef HOME_AUTOMATION_ENTITY_H
+#define HOME_AUTOMATION_ENTITY_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum ha_entity_type {
+    HA_ENTITY_TYPE_SENSOR = 0,
+    HA_ENTITY_TYPE_ACTUATOR = 1,
+} ha_entity_type;
+
+typedef enum ha_update_kind {
+    /* brightness in percent */
+    HA_UPDATE_KIND_BRIGHTNESS = 0,
+    /* 1.0 to switch the air conditioning on, 0.0 to switch it off */
+    HA_UPDATE_KIND_AIR_CONDITIONING = 1,
+} ha_update_kind;
+
+typedef struct ha_entity ha_entity;
+
+/* The enums are passed as int, other values are rejected. */
+
+/* Called from a background thread for every state the controller requests with an ha_update_kind,
+ * return 0 if applied. */
+typedef int (*ha_update_callback)(void *user_data, int kind, float value);
+
+/* Functions returning int return 0 on success and -1 on failure, the error is logged. */
+
+int ha_init_tracing(const char *service_name);
+void ha_shutdown_tracing(void);
+
+/* Returns NULL on failure. The name gets the prefix sen_ or act_. No signal handler is installed,
+ * stop the entity with ha_entity_shutdown. */
+ha_entity *ha_entity_create(const char *name, int entity_type);
+/* Must not be called while ha_entity_run is running. */
+void ha_entity_destroy(ha_entity *entity);
+
+/* NULL removes the callback. */
+int ha_entity_set_update_callback(const ha_entity *entity, ha_update_callback callback,
+                                  void *user_data);
+int ha_entity_push_temperature(const ha_entity *entity, float temperature);
+int ha_entity_push_humidity(const ha_entity *entity, float humidity);
+int ha_entity_push_state(const ha_entity *entity, int kind, float value);
+
+/* Blocks until the entity is shut down by ha_entity_shutdown or the controller. */
+int ha_entity_run(const ha_entity *entity);
+void ha_entity_shutdown(const ha_entity *entity);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HOME_AUTOMATION_ENTITY_H */
@@ -1,58 +1,48 @@
-use std::{str::FromStr, sync::RwLock, time::Duration};
+use std::{sync::RwLock, time::Duration};
 
-use anyhow::{Context as _, Result};
+use anyhow::Result;
 use home_automation_common::{
-    actuator_state_topic,
+    capabilities,
     protobuf::{
         actuator_state::State, entity_discovery_command::EntityType,
         named_entity_state::State as NState, ActuatorState, AirConditioningActuatorState,
-        LightActuatorState, NamedEntityState, PublishData,
+        EntityDescription, LightActuatorState, NamedEntityState, PublishData,
     },
+    value_range::ValueKind,
+    UpdateFrequency,
 };
-use home_automation_entity::{App, Entity};
+use home_automation_entity::{define_entity_kind, kind::EntityKind, App, Entity};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum ActuatorKind {
-    AirConditioning,
-    Light,
+define_entity_kind! {
+    enum ActuatorKind: Actuator {
+        AirConditioning,
+        Light,
+    }
 }
 
 impl ActuatorKind {
-    const ALL: [ActuatorKind; 2] = [Self::AirConditioning, Self::Light];
-
-    fn list_allowed() -> impl std::fmt::Display {
-        struct Printer;
-        impl std::fmt::Display for Printer {
-            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-                write!(f, "Allowed values: ")?;
-                match &ActuatorKind::ALL[..] {
-                    [] => {}
-                    [one] => write!(f, "{one}")?,
-                    [many @ .., last] => {
-                        for kind in many {
-                            write!(f, "{kind}, ")?;
-                        }
-                        write!(f, "{last}")?;
-                    }
-                }
-
-                Ok(())
+    fn description(self) -> EntityDescription {
+        let (kind, unit, range, capability) = match self {
+            ActuatorKind::AirConditioning => {
+                ("air_conditioning", "", None, capabilities::AIR_CONDITIONING)
             }
+            ActuatorKind::Light => (
+                "brightness",
+                "%",
+                Some(ValueKind::Brightness.range()),
+                capabilities::LIGHT,
+            ),
+        };
+        EntityDescription {
+            kind: kind.to_owned(),
+            unit: unit.to_owned(),
+            min: range.map_or(0.0, |range| range.min),
+            max: range.map_or(0.0, |range| range.max),
+            capabilities: vec![
+                capabilities::UPDATE_FREQUENCY.to_owned(),
+                capability.to_owned(),
+            ],
         }
-        Printer
-    }
-}
-
-impl FromStr for ActuatorKind {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> anyhow::Result<Self> {
-        let pos = Self::ALL
-            .map(|kind| kind.to_string())
-            .iter()
-            .position(|kind| kind == s)
-            .with_context(|| format!("Unknown actuator kind {s}. {}", Self::list_allowed()))?;
-        Ok(Self::ALL[pos])
     }
 }
 
@@ -76,15 +66,6 @@ impl From<&State> for ActuatorKind {
     }
 }
 
-impl std::fmt::Display for ActuatorKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            ActuatorKind::AirConditioning => f.write_str("AirConditioning"),
-            ActuatorKind::Light => f.write_str("Light"),
-        }
-    }
-}
-
 #[derive(Debug)]
 struct Actuator {
     topic: String,
@@ -96,14 +77,11 @@ impl Entity for Actuator {
     const ENTITY_TYPE: EntityType = EntityType::Actuator;
 
     fn new(base_name: String) -> Result<Self> {
-        let kind: ActuatorKind = std::env::args()
-            .nth(2)
-            .with_context(|| format!("Missing actuator kind. {} ", ActuatorKind::list_allowed()))?
-            .parse()?;
-        let name = format!("act_{base_name}");
+        let kind = ActuatorKind::from_args()?;
+        let name = ActuatorKind::entity_name(&base_name);
 
         Ok(Self {
-            topic: actuator_state_topic(&name),
+            topic: ActuatorKind::topic(&name),
             name,
             data: RwLock::new(kind.into()),
         })
@@ -117,6 +95,11 @@ impl Entity for Actuator {
         &self.topic
     }
 
+    fn description(&self) -> Option<EntityDescription> {
+        let kind = ActuatorKind::from(&*self.data.read().expect("non-poisoned RwLock"));
+        Some(kind.description())
+    }
+
     fn retrieve_publish_data(&self) -> PublishData {
         let state = self.data.read().expect("non-poisoned RwLock").clone();
         ActuatorState { state: Some(state) }.into()
@@ -146,17 +129,26 @@ impl Entity for Actuator {
                 *old_state = new_state;
                 Ok(None)
             }
-            Some(NState::SensorConfiguration(config)) => Ok(Some(Duration::from_secs_f32(
-                1. / config.update_frequency_hz,
-            ))),
+            Some(NState::SensorConfiguration(config)) => {
+                Ok(Some(UpdateFrequency::try_from(&config)?.period()))
+            }
+            Some(NState::Lifecycle(_)) => {
+                Err(anyhow::anyhow!("Lifecycle commands are handled by the app"))
+            }
+            Some(NState::Measurement(_)) => {
+                Err(anyhow::anyhow!("Measurements are only adopted by sensors"))
+            }
         }
     }
 }
 
 fn main() -> Result<()> {
+    if home_automation_common::doctor::requested() {
+        return home_automation_entity::run_doctor("actuator");
+    }
     let app = App::<Actuator>::new()?;
     let _config = home_automation_common::OpenTelemetryConfiguration::new(app.entity.name())?;
 
-    let sockets = app.connect()?;
-    app.run(sockets)
+    let connection = app.connect()?;
+    app.run(connection)
 }
@@ -1,85 +1,69 @@
-use std::{str::FromStr, time::Duration};
+use std::{
+    ops::Range,
+    sync::{Mutex, RwLock},
+    time::Duration,
+};
 
-use anyhow::{Context as _, Result};
+use anyhow::Result;
 use home_automation_common::{
+    capabilities,
     protobuf::{
         entity_discovery_command::EntityType, named_entity_state::State as NState,
-        sensor_measurement::Value, HumiditySensorMeasurement, NamedEntityState, PublishData,
-        SensorMeasurement, TemperatureSensorMeasurement,
+        sensor_measurement::Value, EntityDescription, NamedEntityState, PublishData,
+        SensorMeasurement,
     },
-    sensor_measurement_topic,
+    UpdateFrequency,
 };
-use home_automation_entity::{App, Entity};
-use rand::Rng;
+use home_automation_entity::{define_entity_kind, kind::EntityKind, App, Entity};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Ranges of the simulated values.
+const HUMIDITY_RANGE: Range<f32> = 0.0..100.0;
+const TEMPERATURE_RANGE: Range<f32> = -40.0..45.0;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum SensorKind {
-    Humidity,
-    Temperature,
+define_entity_kind! {
+    enum SensorKind: Sensor {
+        Humidity,
+        Temperature,
+    }
 }
 
 impl SensorKind {
-    const ALL: [SensorKind; 2] = [Self::Humidity, Self::Temperature];
-
-    fn list_allowed() -> impl std::fmt::Display {
-        struct Printer;
-        impl std::fmt::Display for Printer {
-            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-                write!(f, "Allowed values: ")?;
-                match &SensorKind::ALL[..] {
-                    [] => {}
-                    [one] => write!(f, "{one}")?,
-                    [many @ .., last] => {
-                        for kind in many {
-                            write!(f, "{kind}, ")?;
-                        }
-                        write!(f, "{last}")?;
-                    }
-                }
-
-                Ok(())
-            }
+    fn description(self) -> EntityDescription {
+        let (kind, unit, range) = match self {
+            SensorKind::Humidity => ("humidity", "%", HUMIDITY_RANGE),
+            SensorKind::Temperature => ("temperature", "°C", TEMPERATURE_RANGE),
+        };
+        EntityDescription {
+            kind: kind.to_owned(),
+            unit: unit.to_owned(),
+            min: range.start,
+            max: range.end,
+            capabilities: vec![
+                capabilities::UPDATE_FREQUENCY.to_owned(),
+                capabilities::MEASUREMENT.to_owned(),
+            ],
         }
-        Printer
     }
 
-    fn random(self) -> SensorMeasurement {
-        let mut rng = rand::thread_rng();
+    fn random(self, rng: &mut impl Rng) -> SensorMeasurement {
         match self {
-            SensorKind::Humidity => SensorMeasurement {
-                unit: "%".to_owned(),
-                value: Some(Value::Humidity(HumiditySensorMeasurement {
-                    humidity: rng.gen_range(0.0..100.0),
-                })),
-            },
-            SensorKind::Temperature => SensorMeasurement {
-                unit: "°C".to_owned(),
-                value: Some(Value::Temperature(TemperatureSensorMeasurement {
-                    temperature: rng.gen_range(-40.0..45.0),
-                })),
-            },
+            SensorKind::Humidity => SensorMeasurement::humidity(rng.gen_range(HUMIDITY_RANGE)),
+            SensorKind::Temperature => {
+                SensorMeasurement::temperature(rng.gen_range(TEMPERATURE_RANGE))
+            }
         }
     }
 }
 
-impl FromStr for SensorKind {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> anyhow::Result<Self> {
-        let pos = Self::ALL
-            .map(|kind| kind.to_string())
-            .iter()
-            .position(|kind| kind == s)
-            .with_context(|| format!("Unknown sensor kind {s}. {}", Self::list_allowed()))?;
-        Ok(Self::ALL[pos])
-    }
-}
+impl TryFrom<&SensorMeasurement> for SensorKind {
+    type Error = anyhow::Error;
 
-impl std::fmt::Display for SensorKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            SensorKind::Humidity => f.write_str("Humidity"),
-            SensorKind::Temperature => f.write_str("Temperature"),
+    fn try_from(measurement: &SensorMeasurement) -> anyhow::Result<Self> {
+        match measurement.value {
+            Some(Value::Humidity(_)) => Ok(Self::Humidity),
+            Some(Value::Temperature(_)) => Ok(Self::Temperature),
+            None => Err(anyhow::anyhow!("Missing value in {measurement:?}")),
         }
     }
 }
@@ -89,23 +73,39 @@ struct Sensor {
     topic: String,
     name: String,
     data_kind: SensorKind,
+    /// Published instead of random values once the controller sent a measurement.
+    adopted: RwLock<Option<SensorMeasurement>>,
+    rng: Mutex<StdRng>,
+}
+
+/// Seed of the simulated values, mixed with the name so that sensors started with the same seed
+/// still publish different values.
+fn seeded_rng(seed: u64, name: &str) -> StdRng {
+    // FNV-1a, unlike the std hasher stable across builds
+    let name_hash = name.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    StdRng::seed_from_u64(seed ^ name_hash)
 }
 
 impl Entity for Sensor {
     const ENTITY_TYPE: EntityType = EntityType::Sensor;
 
     fn new(base_name: String) -> Result<Self> {
-        let kind: SensorKind = std::env::args()
-            .nth(2)
-            .with_context(|| format!("Missing sensor kind. {} ", SensorKind::list_allowed()))?
-            .parse()?;
+        let kind = SensorKind::from_args()?;
 
-        let name = format!("sen_{base_name}");
+        let name = SensorKind::entity_name(&base_name);
+        let rng = match home_automation_entity::seed()? {
+            Some(seed) => seeded_rng(seed, &name),
+            None => StdRng::from_entropy(),
+        };
 
         Ok(Self {
-            topic: sensor_measurement_topic(&name),
+            topic: SensorKind::topic(&name),
             name,
             data_kind: kind,
+            adopted: RwLock::default(),
+            rng: Mutex::new(rng),
         })
     }
 
@@ -117,8 +117,20 @@ impl Entity for Sensor {
         &self.topic
     }
 
+    fn description(&self) -> Option<EntityDescription> {
+        Some(self.data_kind.description())
+    }
+
     fn retrieve_publish_data(&self) -> PublishData {
-        self.data_kind.random().into()
+        self.adopted
+            .read()
+            .expect("non-poisoned RwLock")
+            .clone()
+            .unwrap_or_else(|| {
+                self.data_kind
+                    .random(&mut *self.rng.lock().expect("non-poisoned Mutex"))
+            })
+            .into()
     }
 
     fn handle_incoming_data(&self, data: NamedEntityState) -> Result<Option<Duration>> {
@@ -129,9 +141,19 @@ impl Entity for Sensor {
             self.name
         );
         match data.state {
-            Some(NState::SensorConfiguration(config)) => Ok(Some(Duration::from_secs_f32(
-                1. / config.update_frequency_hz,
-            ))),
+            Some(NState::SensorConfiguration(config)) => {
+                Ok(Some(UpdateFrequency::try_from(&config)?.period()))
+            }
+            Some(NState::Measurement(measurement)) => {
+                let kind = SensorKind::try_from(&measurement)?;
+                anyhow::ensure!(
+                    kind == self.data_kind,
+                    "Incompatible measurement kind {kind} received for {}",
+                    self.data_kind
+                );
+                *self.adopted.write().expect("non-poisoned RwLock") = Some(measurement);
+                Ok(None)
+            }
             None => Err(anyhow::anyhow!("Missing payload data in {:?}", data.state)),
             Some(other) => Err(anyhow::anyhow!("Invalid payload for sensor: {other:?}",)),
         }
@@ -139,9 +161,12 @@ impl Entity for Sensor {
 }
 
 fn main() -> Result<()> {
+    if home_automation_common::doctor::requested() {
+        return home_automation_entity::run_doctor("sensor");
+    }
     let app = App::<Sensor>::new()?;
     let _config = home_automation_common::OpenTelemetryConfiguration::new(app.entity.name())?;
 
-    let sockets = app.connect()?;
-    app.run(sockets)
+    let connection = app.connect()?;
+    app.run(connection)
 }
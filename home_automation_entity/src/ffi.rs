@@ -0,0 +1,440 @@
+//! C ABI around [`App`] so that firmware written in C or C++ can join the system.
+//!
+//! The declarations are in `include/home_automation_entity.h`. Every function catches panics and
+//! reports failures by its return value, the details are logged.
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr},
+    marker::PhantomData,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{Mutex, RwLock},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use home_automation_common::{
+    entity_topic,
+    protobuf::{
+        actuator_state, entity_discovery_command::EntityType, named_entity_state::State,
+        publish_data, ActuatorState, NamedEntityState, PublishData, SensorMeasurement,
+    },
+    OpenTelemetryConfiguration, UpdateFrequency,
+};
+
+use crate::{kind, App, Entity};
+
+static TELEMETRY: Mutex<Option<OpenTelemetryConfiguration>> = Mutex::new(None);
+
+/// Type of the entity created by [`ha_entity_create`].
+///
+/// Passed as `c_int`, because an unknown value would be undefined behavior for a Rust enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaEntityType {
+    Sensor = 0,
+    Actuator = 1,
+}
+
+impl TryFrom<c_int> for HaEntityType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: c_int) -> Result<Self> {
+        [Self::Sensor, Self::Actuator]
+            .into_iter()
+            .find(|entity_type| *entity_type as c_int == value)
+            .with_context(|| format!("Invalid entity type {value}"))
+    }
+}
+
+/// Target state requested by the controller for an actuator, passed as `c_int` like
+/// [`HaEntityType`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaUpdateKind {
+    /// Brightness in percent.
+    Brightness = 0,
+    /// `1.0` to switch the air conditioning on, `0.0` to switch it off.
+    AirConditioning = 1,
+}
+
+impl TryFrom<c_int> for HaUpdateKind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: c_int) -> Result<Self> {
+        [Self::Brightness, Self::AirConditioning]
+            .into_iter()
+            .find(|kind| *kind as c_int == value)
+            .with_context(|| format!("Invalid update kind {value}"))
+    }
+}
+
+/// Called for every state requested by the controller with a [`HaUpdateKind`], returns `0` if
+/// the state was applied.
+pub type HaUpdateCallback =
+    Option<extern "C" fn(user_data: *mut c_void, kind: c_int, value: f32) -> c_int>;
+
+#[derive(Debug, Clone, Copy)]
+struct Callback {
+    function: extern "C" fn(*mut c_void, c_int, f32) -> c_int,
+    user_data: *mut c_void,
+}
+
+// SAFETY: the caller of `ha_entity_set_update_callback` guarantees that the callback and its user
+// data can be used from any thread.
+unsafe impl Send for Callback {}
+unsafe impl Sync for Callback {}
+
+trait Kind: Send + Sync {
+    const ENTITY_TYPE: EntityType;
+
+    fn initial_data() -> PublishData;
+}
+
+#[derive(Debug)]
+struct SensorKind;
+
+impl Kind for SensorKind {
+    const ENTITY_TYPE: EntityType = EntityType::Sensor;
+
+    fn initial_data() -> PublishData {
+        SensorMeasurement::default().into()
+    }
+}
+
+#[derive(Debug)]
+struct ActuatorKind;
+
+impl Kind for ActuatorKind {
+    const ENTITY_TYPE: EntityType = EntityType::Actuator;
+
+    fn initial_data() -> PublishData {
+        ActuatorState::default().into()
+    }
+}
+
+/// Entity whose data is pushed by the C code and whose updates are handled by a C callback.
+#[derive(Debug)]
+struct FfiEntity<K> {
+    name: String,
+    topic: String,
+    data: RwLock<PublishData>,
+    callback: RwLock<Option<Callback>>,
+    kind: PhantomData<K>,
+}
+
+impl<K: Kind> FfiEntity<K> {
+    fn apply(&self, state: actuator_state::State) -> Result<()> {
+        let (kind, value) = match state {
+            actuator_state::State::Light(light) => (HaUpdateKind::Brightness, light.brightness),
+            actuator_state::State::AirConditioning(ac) => {
+                (HaUpdateKind::AirConditioning, if ac.on { 1.0 } else { 0.0 })
+            }
+        };
+        if let Some(callback) = *self.callback.read().expect("non-poisoned RwLock") {
+            let code = (callback.function)(callback.user_data, kind as c_int, value);
+            anyhow::ensure!(
+                code == 0,
+                "Update callback rejected {kind:?} {value} with {code}"
+            );
+        }
+        *self.data.write().expect("non-poisoned RwLock") =
+            ActuatorState { state: Some(state) }.into();
+        Ok(())
+    }
+}
+
+impl<K: Kind> Entity for FfiEntity<K> {
+    const ENTITY_TYPE: EntityType = K::ENTITY_TYPE;
+
+    fn new(base_name: String) -> Result<Self> {
+        let name = kind::entity_name(K::ENTITY_TYPE, &base_name);
+        Ok(Self {
+            topic: entity_topic(&name, K::ENTITY_TYPE),
+            name,
+            data: RwLock::new(K::initial_data()),
+            callback: RwLock::new(None),
+            kind: PhantomData,
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn topic_name(&self) -> &str {
+        &self.topic
+    }
+
+    fn retrieve_publish_data(&self) -> PublishData {
+        self.data.read().expect("non-poisoned RwLock").clone()
+    }
+
+    fn handle_incoming_data(&self, data: NamedEntityState) -> Result<Option<Duration>> {
+        anyhow::ensure!(
+            data.entity_name == self.name,
+            "Message arrived at wrong entity. Expected {} but got {}",
+            data.entity_name,
+            self.name
+        );
+        match data.state {
+            Some(State::SensorConfiguration(config)) => {
+                Ok(Some(UpdateFrequency::try_from(&config)?.period()))
+            }
+            Some(State::ActuatorState(ActuatorState { state: Some(state) }))
+                if K::ENTITY_TYPE == EntityType::Actuator =>
+            {
+                self.apply(state).map(|()| None)
+            }
+            state => Err(anyhow::anyhow!(
+                "Unsupported update {state:?} for {}",
+                self.name
+            )),
+        }
+    }
+}
+
+enum EntityApp {
+    Sensor(App<FfiEntity<SensorKind>>),
+    Actuator(App<FfiEntity<ActuatorKind>>),
+}
+
+/// Opaque handle of an entity for the C code.
+pub struct HaEntity(EntityApp);
+
+impl HaEntity {
+    fn name(&self) -> &str {
+        match &self.0 {
+            EntityApp::Sensor(app) => app.entity.name(),
+            EntityApp::Actuator(app) => app.entity.name(),
+        }
+    }
+
+    fn push(&self, data: PublishData) -> Result<()> {
+        let target = match (&self.0, &data.value) {
+            (EntityApp::Sensor(app), Some(publish_data::Value::Measurement(_))) => &app.entity.data,
+            (EntityApp::Actuator(app), Some(publish_data::Value::ActuatorState(_))) => {
+                &app.entity.data
+            }
+            _ => anyhow::bail!("Data {data:?} does not match entity {}", self.name()),
+        };
+        *target.write().expect("non-poisoned RwLock") = data;
+        Ok(())
+    }
+
+    fn run(&self) -> Result<()> {
+        match &self.0 {
+            EntityApp::Sensor(app) => app.run(app.connect()?),
+            EntityApp::Actuator(app) => app.run(app.connect()?),
+        }
+    }
+}
+
+/// Runs the function, logs its error and converts the result to `0` or `-1`.
+fn status(name: &str, f: impl FnOnce() -> Result<()>) -> c_int {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => {
+            tracing::error!("{name} failed: {e:#}");
+            -1
+        }
+        Err(_) => {
+            tracing::error!("{name} panicked");
+            -1
+        }
+    }
+}
+
+/// # Safety
+///
+/// `text` must be null or point to a null-terminated string.
+unsafe fn string(text: *const c_char) -> Result<String> {
+    anyhow::ensure!(!text.is_null(), "Missing string");
+    // SAFETY: checked for null, the caller guarantees the termination
+    let text = unsafe { CStr::from_ptr(text) };
+    Ok(text.to_str().context("String is not UTF-8")?.to_owned())
+}
+
+/// Logs to stderr and sends traces to Zipkin, must be called at most once before creating entities.
+///
+/// # Safety
+///
+/// `service_name` must point to a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ha_init_tracing(service_name: *const c_char) -> c_int {
+    status("ha_init_tracing", || {
+        // SAFETY: guaranteed by the caller
+        let service_name = unsafe { string(service_name) }?;
+        let mut telemetry = TELEMETRY.lock().expect("non-poisoned Mutex");
+        anyhow::ensure!(telemetry.is_none(), "Tracing is already initialized");
+        *telemetry = Some(OpenTelemetryConfiguration::new(service_name)?);
+        Ok(())
+    })
+}
+
+/// Flushes the traces, no further entities may run afterwards.
+#[no_mangle]
+pub extern "C" fn ha_shutdown_tracing() {
+    let _ = catch_unwind(|| {
+        if let Ok(mut telemetry) = TELEMETRY.lock() {
+            telemetry.take();
+        }
+    });
+}
+
+/// Creates the entity configured by the same environment variables as the Rust entities.
+///
+/// Returns null on failure, e.g. for an invalid [`HaEntityType`]. The name gets the prefix `sen_`
+/// or `act_` like the Rust entities. Signals are left to the caller, who stops the entity with
+/// [`ha_entity_shutdown`].
+///
+/// # Safety
+///
+/// `name` must point to a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ha_entity_create(
+    name: *const c_char,
+    entity_type: c_int,
+) -> *mut HaEntity {
+    let mut entity = None;
+    status("ha_entity_create", || {
+        // SAFETY: guaranteed by the caller
+        let name = unsafe { string(name) }?;
+        let app = match HaEntityType::try_from(entity_type)? {
+            HaEntityType::Sensor => EntityApp::Sensor(App::with_entity(FfiEntity::new(name)?)?),
+            HaEntityType::Actuator => EntityApp::Actuator(App::with_entity(FfiEntity::new(name)?)?),
+        };
+        entity = Some(Box::new(HaEntity(app)));
+        Ok(())
+    });
+    entity.map_or(std::ptr::null_mut(), Box::into_raw)
+}
+
+/// Frees the entity, it must not be running anymore.
+///
+/// # Safety
+///
+/// `entity` must be null or created by [`ha_entity_create`] and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn ha_entity_destroy(entity: *mut HaEntity) {
+    if !entity.is_null() {
+        // SAFETY: guaranteed by the caller
+        drop(unsafe { Box::from_raw(entity) });
+    }
+}
+
+/// Registers the callback for the states the controller requests for an actuator.
+///
+/// Without a callback, or after registering null, every requested state is applied. The callback
+/// is called from another thread than [`ha_entity_run`]'s caller.
+///
+/// # Safety
+///
+/// `entity` must be valid, and the callback and `user_data` must be usable from any thread until
+/// the entity is destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn ha_entity_set_update_callback(
+    entity: *const HaEntity,
+    callback: HaUpdateCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    status("ha_entity_set_update_callback", || {
+        // SAFETY: guaranteed by the caller
+        let entity = unsafe { entity.as_ref() }.context("Missing entity")?;
+        let EntityApp::Actuator(app) = &entity.0 else {
+            anyhow::bail!("Only actuators receive state updates");
+        };
+        *app.entity.callback.write().expect("non-poisoned RwLock") =
+            callback.map(|function| Callback {
+                function,
+                user_data,
+            });
+        Ok(())
+    })
+}
+
+/// Pushes the temperature in °C that the sensor publishes from now on.
+///
+/// # Safety
+///
+/// `entity` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn ha_entity_push_temperature(
+    entity: *const HaEntity,
+    temperature: f32,
+) -> c_int {
+    status("ha_entity_push_temperature", || {
+        // SAFETY: guaranteed by the caller
+        let entity = unsafe { entity.as_ref() }.context("Missing entity")?;
+        entity.push(PublishData::from(SensorMeasurement::temperature(
+            temperature,
+        )))
+    })
+}
+
+/// Pushes the humidity in percent that the sensor publishes from now on.
+///
+/// # Safety
+///
+/// `entity` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn ha_entity_push_humidity(entity: *const HaEntity, humidity: f32) -> c_int {
+    status("ha_entity_push_humidity", || {
+        // SAFETY: guaranteed by the caller
+        let entity = unsafe { entity.as_ref() }.context("Missing entity")?;
+        entity.push(PublishData::from(SensorMeasurement::humidity(humidity)))
+    })
+}
+
+/// Pushes the state of a light or air conditioning actuator, e.g. after a manual change.
+///
+/// `kind` is a [`HaUpdateKind`], other values fail.
+///
+/// # Safety
+///
+/// `entity` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn ha_entity_push_state(
+    entity: *const HaEntity,
+    kind: c_int,
+    value: f32,
+) -> c_int {
+    status("ha_entity_push_state", || {
+        // SAFETY: guaranteed by the caller
+        let entity = unsafe { entity.as_ref() }.context("Missing entity")?;
+        let state = match HaUpdateKind::try_from(kind)? {
+            HaUpdateKind::Brightness => ActuatorState::light(value),
+            HaUpdateKind::AirConditioning => ActuatorState::air_conditioning(value != 0.0),
+        };
+        entity.push(state.into())
+    })
+}
+
+/// Registers the entity and runs it until it is shut down, blocks the calling thread.
+///
+/// A restart requested by the controller starts the executable again with the same arguments.
+///
+/// # Safety
+///
+/// `entity` must be valid until the function returns.
+#[no_mangle]
+pub unsafe extern "C" fn ha_entity_run(entity: *const HaEntity) -> c_int {
+    status("ha_entity_run", || {
+        // SAFETY: guaranteed by the caller
+        let entity = unsafe { entity.as_ref() }.context("Missing entity")?;
+        entity.run()
+    })
+}
+
+/// Requests the shutdown of the running entity, [`ha_entity_run`] returns once it unregistered.
+///
+/// # Safety
+///
+/// `entity` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn ha_entity_shutdown(entity: *const HaEntity) {
+    // SAFETY: guaranteed by the caller
+    if let Some(entity) = unsafe { entity.as_ref() } {
+        match &entity.0 {
+            EntityApp::Sensor(app) => app.shutdown.request(),
+            EntityApp::Actuator(app) => app.shutdown.request(),
+        }
+    }
+}
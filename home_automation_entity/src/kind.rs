@@ -0,0 +1,117 @@
+//! What an entity binary simulates, selected on its command line after the name.
+
+use std::fmt;
+
+use anyhow::{Context as _, Result};
+use home_automation_common::entity_topic;
+// for `define_entity_kind!`
+#[doc(hidden)]
+pub use home_automation_common::protobuf::entity_discovery_command::EntityType;
+
+/// Kinds of an entity binary, implemented by [`define_entity_kind!`](crate::define_entity_kind).
+pub trait EntityKind: Copy + fmt::Display + 'static {
+    const ENTITY_TYPE: EntityType;
+    const ALL: &'static [Self];
+
+    /// Parses the kind from its name, e.g. `Temperature`.
+    fn parse(s: &str) -> Result<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.to_string() == s)
+            .with_context(|| {
+                format!(
+                    "Unknown {} kind {s}. {}",
+                    type_name(Self::ENTITY_TYPE),
+                    list_allowed::<Self>()
+                )
+            })
+    }
+
+    /// Parses the second command line argument.
+    fn from_args() -> Result<Self> {
+        let arg = std::env::args().nth(2).with_context(|| {
+            format!(
+                "Missing {} kind. {}",
+                type_name(Self::ENTITY_TYPE),
+                list_allowed::<Self>()
+            )
+        })?;
+        Self::parse(&arg)
+    }
+
+    /// Name of an entity of the kind, see [`entity_name`].
+    fn entity_name(base_name: &str) -> String {
+        entity_name(Self::ENTITY_TYPE, base_name)
+    }
+
+    /// Topic the entity publishes its data on.
+    fn topic(entity_name: &str) -> String {
+        entity_topic(entity_name, Self::ENTITY_TYPE)
+    }
+}
+
+/// Prefixes the name given on the command line with the type, e.g. `sen_kitchen`.
+pub fn entity_name(entity_type: EntityType, base_name: &str) -> String {
+    let prefix = match entity_type {
+        EntityType::Sensor => "sen_",
+        EntityType::Actuator => "act_",
+    };
+    format!("{prefix}{base_name}")
+}
+
+fn type_name(entity_type: EntityType) -> String {
+    entity_type.as_str_name().to_lowercase()
+}
+
+fn list_allowed<K: EntityKind>() -> String {
+    let kinds: Vec<_> = K::ALL.iter().map(ToString::to_string).collect();
+    format!("Allowed values: {}", kinds.join(", "))
+}
+
+/// Generates the enum of the kinds with [`EntityKind`] and [`Display`](fmt::Display), which
+/// prints the name of the variant.
+///
+/// ```
+/// use home_automation_entity::kind::EntityKind;
+///
+/// home_automation_entity::define_entity_kind! {
+///     /// Value a sensor measures.
+///     enum SensorKind: Sensor {
+///         Humidity,
+///         Temperature,
+///     }
+/// }
+///
+/// assert_eq!(SensorKind::parse("Humidity")?, SensorKind::Humidity);
+/// assert_eq!(SensorKind::entity_name("kitchen"), "sen_kitchen");
+/// # anyhow::Ok(())
+/// ```
+#[macro_export]
+macro_rules! define_entity_kind {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident: $entity_type:ident {
+            $($(#[$variant_meta:meta])* $variant:ident),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        $vis enum $name {
+            $($(#[$variant_meta])* $variant,)+
+        }
+
+        impl $crate::kind::EntityKind for $name {
+            const ENTITY_TYPE: $crate::kind::EntityType = $crate::kind::EntityType::$entity_type;
+            const ALL: &'static [Self] = &[$(Self::$variant,)+];
+        }
+
+        impl ::std::fmt::Display for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.write_str(match self {
+                    $(Self::$variant => stringify!($variant),)+
+                })
+            }
+        }
+    };
+}
@@ -1,20 +1,45 @@
 use std::{
-    sync::RwLock,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU32, Ordering},
+        Mutex, RwLock,
+    },
     time::{Duration, Instant},
 };
 
 use anyhow::{Context as _, Result};
 use home_automation_common::{
+    encryption::PayloadCipher,
+    latency::{self, SUSPICIOUS_CLOCK_OFFSET_MS},
     load_env,
+    log_event::LogEvent,
     protobuf::{
-        entity_discovery_command::{Command, EntityType, Registration},
+        entity_discovery_command::{Command, EntityType, Heartbeat, Registration},
+        lifecycle_command::Action,
+        named_entity_state::State,
+        publish_data,
         response_code::Code,
-        EntityDiscoveryCommand, NamedEntityState, PublishData, ResponseCode,
+        EntityDescription, EntityDiscoveryCommand, MeasurementBatch, NamedEntityState, PublishData,
+        ResponseCode, TimestampedMeasurement,
     },
-    zmq_sockets::{self, markers::Linked, termination_is_ok},
-    AnyhowZmq, HEARTBEAT_FREQUENCY,
+    registration_auth,
+    serial::{self, SerialLink},
+    signals::{Signal, SignalReceiver},
+    signing::CommandVerifier,
+    transport::{Pattern, TransportKind},
+    udp::{self, UdpPublisher},
+    value_range,
+    zmq_sockets::{self, termination_is_ok},
+    ErrorKindExt, ShutdownToken, UpdateFrequency, DEFAULT_HEARTBEAT_INTERVAL,
+    STATISTICS_LOG_INTERVAL,
 };
 
+pub mod ffi;
+pub mod kind;
+pub mod transport;
+
+pub use home_automation_common::schedule::{MissedTickPolicy, PublishSchedule};
+use transport::{ChannelDiscovery, Connection, Discovery, Publish, SerialTransport, Updates};
+
 pub trait Entity: Sync {
     const ENTITY_TYPE: EntityType;
 
@@ -26,42 +51,210 @@ pub trait Entity: Sync {
 
     fn retrieve_publish_data(&self) -> PublishData;
     fn handle_incoming_data(&self, data: NamedEntityState) -> Result<Option<Duration>>;
+
+    /// Published on the meta topic right after the registration, `None` if the entity does not
+    /// describe itself.
+    fn description(&self) -> Option<EntityDescription> {
+        None
+    }
+
+    /// How the publish loop catches up after it fell behind its schedule.
+    fn missed_tick_policy(&self) -> MissedTickPolicy {
+        MissedTickPolicy::default()
+    }
+}
+
+/// Sensors that publish faster than this ask the controller to batch their measurements.
+const BATCH_THRESHOLD: Duration = Duration::from_millis(100);
+/// How long a measurement waits in a batch at most.
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Optional interval between the heartbeats in milliseconds that the entity proposes to the
+/// controller.
+pub const ENV_HEARTBEAT_INTERVAL: &str = "HOME_AUTOMATION_HEARTBEAT_INTERVAL_MS";
+
+/// Command line flag with the seed of the simulated values, e.g. `--seed 42`.
+pub const SEED_FLAG: &str = "--seed";
+
+/// Returns the seed given with [`SEED_FLAG`], `None` if the simulated values are not
+/// reproducible.
+pub fn seed() -> Result<Option<u64>> {
+    let args: Vec<_> = std::env::args().collect();
+    let Some(position) = args.iter().position(|arg| arg == SEED_FLAG) else {
+        return Ok(None);
+    };
+    let value = args
+        .get(position + 1)
+        .with_context(|| format!("Missing value of {SEED_FLAG}"))?;
+    value
+        .parse()
+        .map(Some)
+        .with_context(|| format!("Invalid {SEED_FLAG} {value}"))
 }
 
-pub struct Sockets {
-    pub publisher: zmq_sockets::Publisher<Linked>,
-    pub replier: zmq_sockets::Replier<Linked>,
-    pub heartbeat: zmq_sockets::Requester<Linked>,
+/// Reads the tags of the entity from [`ENV_ENTITY_TAGS`][home_automation_common::ENV_ENTITY_TAGS].
+fn entity_tags() -> Vec<String> {
+    std::env::var(home_automation_common::ENV_ENTITY_TAGS)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Reads the initial refresh rate from [`ENV_UPDATE_FREQUENCY`][home_automation_common::ENV_UPDATE_FREQUENCY].
+fn initial_refresh_rate() -> Result<Duration> {
+    let var = home_automation_common::ENV_UPDATE_FREQUENCY;
+    let Ok(frequency) = std::env::var(var) else {
+        return Ok(Duration::from_millis(1500));
+    };
+    let frequency: UpdateFrequency = frequency
+        .parse()
+        .with_context(|| anyhow::anyhow!("Invalid {var} value {frequency}"))?;
+    Ok(frequency.period())
+}
+
+/// Reads the proposed heartbeat interval from [`ENV_HEARTBEAT_INTERVAL`].
+fn proposed_heartbeat_interval() -> Result<Duration> {
+    let Ok(interval) = std::env::var(ENV_HEARTBEAT_INTERVAL) else {
+        return Ok(DEFAULT_HEARTBEAT_INTERVAL);
+    };
+    let ms: u32 = interval
+        .parse()
+        .with_context(|| format!("Invalid {ENV_HEARTBEAT_INTERVAL} value {interval}"))?;
+    anyhow::ensure!(ms > 0, "{ENV_HEARTBEAT_INTERVAL} must be positive");
+    Ok(Duration::from_millis(ms.into()))
+}
+
+/// Checks the environment of an entity instead of starting it.
+pub fn run_doctor(program: &str) -> Result<()> {
+    use home_automation_common::{
+        doctor::Report, ENV_CLIENT_API_ENDPOINT, ENV_DISCOVERY_ENDPOINT, ENV_ENTITY_DATA_ENDPOINT,
+        ENV_ENTITY_TAGS, ENV_TOPIC_PREFIX, ENV_UPDATE_FREQUENCY,
+    };
+    let mut report = Report::new(program);
+    report.env_var(ENV_DISCOVERY_ENDPOINT, true);
+    report.env_var(ENV_ENTITY_DATA_ENDPOINT, true);
+    for var in [
+        ENV_UPDATE_FREQUENCY,
+        ENV_HEARTBEAT_INTERVAL,
+        ENV_ENTITY_TAGS,
+        ENV_TOPIC_PREFIX,
+        home_automation_common::transport::ENV_TRANSPORT,
+        serial::ENV_SERIAL_PORT,
+        serial::ENV_SERIAL_BAUD_RATE,
+        udp::ENV_UDP_MULTICAST_GROUP,
+        registration_auth::ENV_REGISTRATION_KEY,
+        registration_auth::ENV_REGISTRATION_TOKEN,
+    ] {
+        report.env_var(var, false);
+    }
+    report.add_result(
+        "update frequency",
+        initial_refresh_rate().map(|period| format!("publishes every {period:?}")),
+    );
+    report.add_result(
+        "heartbeat interval",
+        proposed_heartbeat_interval().map(|interval| format!("proposes {interval:?}")),
+    );
+    report.connect_probe(ENV_DISCOVERY_ENDPOINT);
+    report.connect_probe(ENV_ENTITY_DATA_ENDPOINT);
+    report.payload_encryption();
+    report.command_signing(false, true);
+    report.zipkin();
+    // the discovery has no version handshake, so ask the client API of the controller if known
+    if report.env_var(ENV_CLIENT_API_ENDPOINT, false).is_some() {
+        report.protocol_handshake(ENV_CLIENT_API_ENDPOINT);
+    }
+    report.finish()
 }
 
 pub struct App<E: Entity> {
     context: zmq_sockets::Context,
-    data_endpoint: String,
-    discovery_endpoint: String,
     pub entity: E,
     pub refresh_rate: RwLock<Duration>,
+    /// Requested when the refresh rate changes to interrupt the publisher's current sleep.
+    /// It is replaced by a fresh child of `shutdown` once the publisher woke up.
+    refresh_rate_changed: Mutex<ShutdownToken>,
+    /// Proposed at the registration and replaced by the interval the controller expects.
+    heartbeat_interval: RwLock<Duration>,
+    restart_requested: AtomicBool,
+    /// How far the clock is ahead of the controller, estimated during the last heartbeat.
+    clock_offset_ms: AtomicI64,
+    /// Measurements per publication granted by the controller, 0 if the entity does not batch.
+    batch_size: AtomicU32,
+    /// Rejects commands without a valid signature if keys are configured.
+    verifier: CommandVerifier,
+    /// Random ID of this instance, sent with every discovery command.
+    session_id: u64,
+    /// Sent with every discovery command, see [`registration_auth`].
+    auth_token: String,
+    /// Only for entities started from the command line, see [`App::new`].
+    signals: Option<SignalReceiver>,
+    pub shutdown: ShutdownToken,
 }
 
 impl<E: Entity> App<E> {
     pub fn new() -> Result<Self> {
         let name = std::env::args().nth(1).context("Missing name.")?;
+        let mut app = Self::with_entity(E::new(name).context("Failed to create entity")?)?;
+        // the context stays alive until the entity unregistered in `run`
+        app.signals = Some(home_automation_common::install_graceful_signal_handler(
+            app.shutdown.clone(),
+        )?);
+        Ok(app)
+    }
+
+    /// Creates the app for an entity that is not configured by the command line arguments.
+    ///
+    /// The signals are left to the embedding program, which stops the entity via
+    /// [`shutdown`](Self::shutdown).
+    pub fn with_entity(entity: E) -> Result<Self> {
+        // fails on an invalid key instead of on the first message
+        PayloadCipher::global()?;
+        let verifier = CommandVerifier::from_env()?;
+        let auth_token = registration_auth::token_from_env(entity.name())?;
         let context = zmq_sockets::Context::new();
-        home_automation_common::install_signal_handler(context.clone())?;
+        let shutdown = ShutdownToken::new();
         Ok(Self {
             context,
-            data_endpoint: load_env(home_automation_common::ENV_ENTITY_DATA_ENDPOINT)?,
-            discovery_endpoint: load_env(home_automation_common::ENV_DISCOVERY_ENDPOINT)?,
-            entity: E::new(name).context("Failed to create entity")?,
-            refresh_rate: RwLock::new(Duration::from_millis(1500)),
+            entity,
+            refresh_rate: RwLock::new(initial_refresh_rate()?),
+            refresh_rate_changed: Mutex::new(shutdown.child()),
+            heartbeat_interval: RwLock::new(proposed_heartbeat_interval()?),
+            restart_requested: AtomicBool::new(false),
+            clock_offset_ms: AtomicI64::new(0),
+            batch_size: AtomicU32::new(0),
+            verifier,
+            // 0 means that the entity has no session
+            session_id: rand::random::<u64>().max(1),
+            auth_token,
+            signals: None,
+            shutdown,
         })
     }
 
-    pub fn run(&self, sockets: Sockets) -> Result<()> {
-        std::thread::scope(|s| {
-            let publisher = s.spawn(move || self.run_publish_data(sockets.publisher));
-            let updater = s.spawn(move || self.run_updater(sockets.replier));
+    /// Runs the entity until it is shut down, then unregisters it while the sockets still work.
+    pub fn run(&self, connection: Connection) -> Result<()> {
+        let Connection {
+            publisher,
+            updates,
+            discovery,
+        } = connection;
+        let result = std::thread::scope(|s| {
+            let publisher = s.spawn(move || self.run_publish_data(&*publisher));
+            let updater = s.spawn(move || self.run_updater(&*updates));
+            let statistics = s.spawn(|| {
+                zmq_sockets::log_statistics_periodically(STATISTICS_LOG_INTERVAL, &self.shutdown)
+            });
+            let signals = s.spawn(|| {
+                if let Some(signals) = &self.signals {
+                    signals.run(&self.shutdown, |signal| self.handle_signal(signal));
+                }
+            });
 
-            self.run_heartbeat(sockets.heartbeat)?;
+            let heartbeat = self.run_heartbeat(&*discovery);
             publisher
                 .join()
                 .map_err(|e| anyhow::anyhow!("Publisher task panicked: {e:?}"))?
@@ -70,121 +263,252 @@ impl<E: Entity> App<E> {
                 .join()
                 .map_err(|e| anyhow::anyhow!("Updater task panicked: {e:?}"))?
                 .context("Updater task failed")?;
-            Ok(())
-        })
+            statistics
+                .join()
+                .map_err(|e| anyhow::anyhow!("Statistics task panicked: {e:?}"))?;
+            signals
+                .join()
+                .map_err(|e| anyhow::anyhow!("Signal task panicked: {e:?}"))?;
+            heartbeat
+        });
+        // all tasks stopped, so the unregister request is the last message of the entity
+        self.disconnect(&*discovery);
+        result?;
+
+        if self.restart_requested.load(Ordering::SeqCst) {
+            self.restart()?;
+        }
+        Ok(())
     }
 
-    fn discovery_command(&self, command: Command) -> EntityDiscoveryCommand {
-        EntityDiscoveryCommand {
-            command: Some(command),
-            entity_name: self.entity.name().to_owned(),
-            entity_type: E::ENTITY_TYPE.into(),
+    fn handle_signal(&self, signal: Signal) {
+        match signal {
+            Signal::Reload => {
+                tracing::warn!("Ignoring reload signal, entities are only configured on startup");
+            }
+            Signal::DumpState => {
+                let refresh_rate = *self.refresh_rate.read().expect("non-poisoned RwLock");
+                let data = self.entity.retrieve_publish_data();
+                tracing::info!(
+                    name = self.entity.name(),
+                    ?refresh_rate,
+                    ?data,
+                    clock_offset_ms = self.clock_offset_ms.load(Ordering::SeqCst),
+                    "State of {:?} {}: publishing {data:?} every {refresh_rate:?}",
+                    E::ENTITY_TYPE,
+                    self.entity.name()
+                );
+            }
         }
     }
 
-    #[tracing::instrument(parent=None, skip(self))]
-    pub fn connect(&self) -> Result<Sockets> {
-        let replier = zmq_sockets::Replier::new(&self.context)?.bind("tcp://*:*")?;
-        let update_port = replier.get_last_endpoint()?.port();
-        let publisher = zmq_sockets::Publisher::new(&self.context)?.connect(&self.data_endpoint)?;
+    /// Starts a new instance of the entity with the same command line arguments.
+    fn restart(&self) -> Result<()> {
+        let executable = std::env::current_exe().context("Failed to determine executable")?;
+        tracing::info!("Restarting entity {}", self.entity.name());
+        std::process::Command::new(executable)
+            .args(std::env::args_os().skip(1))
+            .spawn()
+            .context("Failed to restart entity")?;
+        Ok(())
+    }
 
-        let requester =
-            zmq_sockets::Requester::new(&self.context)?.connect(&self.discovery_endpoint)?;
+    fn discovery_command(&self, command: impl Into<Command>) -> EntityDiscoveryCommand {
+        EntityDiscoveryCommand::new(E::ENTITY_TYPE, self.entity.name(), command)
+            .with_session_id(self.session_id)
+            .with_auth_token(self.auth_token.clone())
+    }
+
+    /// Connects through the serial port in [`ENV_SERIAL_PORT`][serial::ENV_SERIAL_PORT] if set,
+    /// otherwise through the selected [`TransportKind`], and registers the entity.
+    #[tracing::instrument(parent=None, skip(self))]
+    pub fn connect(&self) -> Result<Connection> {
+        let (connection, update_port) = match std::env::var(serial::ENV_SERIAL_PORT) {
+            Ok(path) => {
+                let link = SerialLink::open(&path, serial::baud_rate()?)?;
+                tracing::info!("Connecting through serial port {path}");
+                // the serial gateway receives the updates on its own port
+                (SerialTransport(link.into()).connection(), 0)
+            }
+            Err(_) => self.connect_transport()?,
+        };
 
-        let request = self.discovery_command(Command::Register(Registration {
+        let proposed_interval = *self.heartbeat_interval.read().expect("non-poisoned RwLock");
+        let request = self.discovery_command(Registration {
             port: update_port.into(),
-        }));
+            tags: entity_tags(),
+            max_batch_size: self.requested_batch_size(),
+            heartbeat_interval_ms: proposed_interval.as_millis().try_into().unwrap_or(u32::MAX),
+        });
 
         tracing::info!("Sending connect request {request:?}");
-        requester.send(request)?;
-
-        let response_code: ResponseCode = requester.receive()?;
+        let response_code = connection.discovery.request(request)?;
         tracing::debug!("Received {response_code:?}");
 
         anyhow::ensure!(
             matches!(response_code.code(), Code::Ok),
-            "Failed to register with controller"
+            "Failed to register with controller: {}",
+            response_code.message
         );
+        if response_code.batch_size > 1 {
+            tracing::info!(
+                "Publishing {} measurements at once",
+                response_code.batch_size
+            );
+        }
+        self.batch_size
+            .store(response_code.batch_size, Ordering::SeqCst);
+        // controllers that do not negotiate the interval expect the default one
+        let interval = match response_code.heartbeat_interval_ms {
+            0 => DEFAULT_HEARTBEAT_INTERVAL,
+            ms => Duration::from_millis(ms.into()),
+        };
+        if interval != proposed_interval {
+            tracing::info!("Controller overrode the heartbeat interval with {interval:?}");
+        }
+        *self
+            .heartbeat_interval
+            .write()
+            .expect("non-poisoned RwLock") = interval;
 
-        Ok(Sockets {
-            publisher,
-            replier,
-            heartbeat: requester,
-        })
+        Ok(connection)
     }
 
-    pub fn run_heartbeat(&self, requester: zmq_sockets::Requester<Linked>) -> Result<()> {
-        struct Dropper<'a> {
-            endpoint: &'a str,
-            request: EntityDiscoveryCommand,
-        }
-        impl Drop for Dropper<'_> {
-            fn drop(&mut self) {
-                let _span = tracing::info_span!("disconnect").entered();
-
-                // Ugly workaround
-                tracing::debug!("Recreating context and requester socket because the one used everywhere else is already closed.");
-                let context = zmq_sockets::Context::new();
-
-                let inner = || -> anyhow::Result<()> {
-                    let mut requester =
-                        zmq_sockets::Requester::new(&context)?.connect(self.endpoint)?;
-                    requester.set_message_exchange_timeout(Some(Duration::from_millis(800)))?;
-                    let request = self.request.clone();
-                    tracing::info!("Sending disconnect request {request:?}");
-                    requester.send(request)?;
-                    tracing::info!("Requested disconnection successfully.");
-                    requester.receive::<ResponseCode>()?;
-                    tracing::info!("Successfully disconnected.");
-                    Ok(())
-                };
-
-                if let Err(error) = inner() {
-                    tracing::error!(%error, "Failed to properly disconnect: {error:#}");
-                }
-                // Workaround: Seems to block forever when properly destroying the context.
-                std::mem::forget(context);
-            }
+    /// Sensors above the [`BATCH_THRESHOLD`] rate batch the measurements of up to
+    /// [`MAX_BATCH_DELAY`].
+    fn requested_batch_size(&self) -> u32 {
+        let refresh_rate = *self.refresh_rate.read().expect("non-poisoned RwLock");
+        if E::ENTITY_TYPE != EntityType::Sensor || refresh_rate >= BATCH_THRESHOLD {
+            return 0;
         }
+        let size = MAX_BATCH_DELAY.as_nanos() / refresh_rate.as_nanos().max(1);
+        size.try_into().unwrap_or(u32::MAX)
+    }
 
-        let _dropper = Dropper {
-            endpoint: &self.discovery_endpoint,
-            request: self.discovery_command(Command::Unregister(())),
+    fn connect_transport(&self) -> Result<(Connection, u16)> {
+        let data_endpoint = load_env(home_automation_common::ENV_ENTITY_DATA_ENDPOINT)?;
+        let discovery_endpoint = load_env(home_automation_common::ENV_DISCOVERY_ENDPOINT)?;
+        let kind = TransportKind::from_env()?;
+        tracing::info!("Connecting through the {kind} transport");
+        let transport = kind.transport(&self.context);
+        let updates = transport.bind(Pattern::Reply, "tcp://*:*")?;
+        let update_port = updates.local_port()?;
+        let publisher: Box<dyn Publish + Send> = match udp::multicast_group()? {
+            Some(group) => {
+                tracing::info!("Publishing data to multicast group {group}");
+                Box::new(UdpPublisher::new(group)?)
+            }
+            None => Box::new(transport.connect(Pattern::Publish, &data_endpoint)?),
+        };
+        let connection = Connection {
+            publisher,
+            updates: Box::new(updates),
+            discovery: Box::new(ChannelDiscovery::connect(transport, discovery_endpoint)?),
         };
+        Ok((connection, update_port))
+    }
 
-        let mut last = Instant::now();
-        while !home_automation_common::shutdown_requested() {
-            std::thread::sleep(Duration::from_millis(100));
-            if last.elapsed() >= HEARTBEAT_FREQUENCY {
-                if let Err(e) = self.heartbeat(&requester) {
-                    return Err(e).or_else(termination_is_ok).inspect_err(|_| {
-                        home_automation_common::request_shutdown();
-                    });
-                }
-                last = Instant::now();
+    pub fn run_heartbeat(&self, discovery: &dyn Discovery) -> Result<()> {
+        let interval = *self.heartbeat_interval.read().expect("non-poisoned RwLock");
+        let mut deadline = Instant::now() + interval;
+        while !self.shutdown.sleep_until_or_shutdown(deadline) {
+            if let Err(e) = self.heartbeat(discovery) {
+                return Err(e)
+                    .or_else(termination_is_ok)
+                    .inspect_err(|_| self.shutdown.request());
             }
+            deadline += interval;
         }
         Ok(())
     }
 
+    /// Sends the unregister request, a failure is only logged because the entity stops anyway.
+    fn disconnect(&self, discovery: &dyn Discovery) {
+        let _span = tracing::info_span!("disconnect").entered();
+        match discovery.disconnect(self.discovery_command(Command::Unregister(()))) {
+            Ok(()) => tracing::info!("Successfully disconnected."),
+            Err(error) => tracing::error!(%error, "Failed to properly disconnect: {error:#}"),
+        }
+    }
+
     /// Sends a single heartbeat and waits for the answer.
     #[tracing::instrument(parent=None, skip_all)]
-    fn heartbeat(&self, requester: &zmq_sockets::Requester<Linked>) -> Result<()> {
-        let request = self.discovery_command(Command::Heartbeat(()));
+    fn heartbeat(&self, discovery: &dyn Discovery) -> Result<()> {
+        let sent_at_ms = latency::unix_time_ms();
+        let request = self.discovery_command(Heartbeat {
+            sent_at_ms,
+            clock_offset_ms: self.clock_offset_ms.load(Ordering::SeqCst),
+        });
         tracing::info!("Sending heartbeat request {request:?}");
-        requester.send(request)?;
-        let response: ResponseCode = requester.receive()?;
+        let response = discovery.request(request)?;
         match response.code() {
-            Code::Ok => Ok(()),
+            Code::Ok => {
+                self.update_clock_offset(sent_at_ms, response.controller_time_ms);
+                Ok(())
+            }
             Code::Error => anyhow::bail!("Heartbeat failed"),
         }
     }
 
-    pub fn run_publish_data(&self, publisher: zmq_sockets::Publisher<Linked>) -> Result<()> {
+    /// Estimates the clock offset from the controller time in the answer to a heartbeat.
+    fn update_clock_offset(&self, sent_at_ms: u64, controller_time_ms: u64) {
+        // controllers without clock sync do not send their time
+        if controller_time_ms == 0 {
+            return;
+        }
+        let offset = latency::estimate_clock_offset_ms(
+            sent_at_ms,
+            controller_time_ms,
+            latency::unix_time_ms(),
+        );
+        let previous = self.clock_offset_ms.swap(offset, Ordering::SeqCst);
+        if offset.abs() >= SUSPICIOUS_CLOCK_OFFSET_MS && previous.abs() < SUSPICIOUS_CLOCK_OFFSET_MS
+        {
+            tracing::warn!(
+                offset_ms = offset,
+                "Clock is {offset} ms ahead of the controller, check the time synchronization"
+            );
+        } else {
+            tracing::debug!(offset_ms = offset, "Estimated clock offset of {offset} ms");
+        }
+    }
+
+    pub fn run_publish_data(&self, publisher: &dyn Publish) -> Result<()> {
+        self.publish_description(publisher);
         let mut error_counter = 0;
+        let mut batch = Vec::new();
+        let topic = self.entity.topic_name();
+        let mut schedule = PublishSchedule::new(self.entity.missed_tick_policy());
         loop {
-            match self.publish_data(&publisher) {
-                Err(e) if e.is_zmq_termination() => return Ok(()),
+            let wakeup = {
+                let mut wakeup = self
+                    .refresh_rate_changed
+                    .lock()
+                    .expect("non-poisoned Mutex");
+                if wakeup.is_requested() {
+                    *wakeup = self.shutdown.child();
+                }
+                wakeup.clone()
+            };
+            // read after renewing the token so that no change of the refresh rate is missed
+            schedule.set_period(
+                topic,
+                *self.refresh_rate.read().expect("non-poisoned RwLock"),
+            );
+            let (_, deadline) = schedule
+                .next(Instant::now())
+                .expect("the data topic is scheduled");
+            if wakeup.sleep_until_or_shutdown(deadline) {
+                if self.shutdown.is_requested() {
+                    break;
+                }
+                tracing::debug!("Refresh rate changed, recomputing the publish deadline");
+                continue;
+            }
+
+            schedule.advance(topic, Instant::now());
+            match self.publish_data(publisher, &mut batch) {
+                Err(e) if e.is_termination() => return Ok(()),
                 Err(e) if error_counter > 3 => return Err(e),
                 Err(e) => {
                     tracing::error!(error=%e, "Failed to publish data: {e:#}");
@@ -194,22 +518,81 @@ impl<E: Entity> App<E> {
                     error_counter = 0;
                 }
             }
-            std::thread::sleep(*self.refresh_rate.read().expect("non-poisoned RwLock"));
         }
+        self.publish_batch(publisher, &mut batch)
+            .or_else(termination_is_ok)
     }
 
-    /// Publishes a single sample.
+    /// Publishes the description once, a failure is only logged because the entity works without.
+    fn publish_description(&self, publisher: &dyn Publish) {
+        let Some(description) = self.entity.description() else {
+            return;
+        };
+        let topic = home_automation_common::meta_topic(self.entity.name());
+        let data = PublishData {
+            published_at_ms: latency::unix_time_ms(),
+            ..description.into()
+        };
+        if let Err(error) = publisher.publish(&topic, data) {
+            tracing::error!(%error, "Failed to publish description: {error:#}");
+        }
+    }
+
+    /// Publishes a single sample, or adds it to the batch if the controller granted batching.
     #[tracing::instrument(parent=None, skip_all)]
-    fn publish_data(&self, publisher: &zmq_sockets::Publisher<Linked>) -> Result<()> {
+    fn publish_data(
+        &self,
+        publisher: &dyn Publish,
+        batch: &mut Vec<TimestampedMeasurement>,
+    ) -> Result<()> {
+        let published_at_ms = latency::unix_time_ms();
         let data = self.entity.retrieve_publish_data();
-        publisher
-            .send(self.entity.topic_name(), data)
-            .context("Failed to publish data")
+        let batch_size = self.batch_size.load(Ordering::SeqCst) as usize;
+        match data.value {
+            Some(publish_data::Value::Measurement(measurement)) if batch_size > 1 => {
+                batch.push(TimestampedMeasurement {
+                    measurement: Some(measurement),
+                    measured_at_ms: published_at_ms,
+                });
+                let waiting_ms = published_at_ms.saturating_sub(batch[0].measured_at_ms);
+                if batch.len() < batch_size && u128::from(waiting_ms) < MAX_BATCH_DELAY.as_millis()
+                {
+                    return Ok(());
+                }
+                self.publish_batch(publisher, batch)
+            }
+            value => publisher.publish(
+                self.entity.topic_name(),
+                PublishData {
+                    value,
+                    published_at_ms,
+                },
+            ),
+        }
+    }
+
+    /// Publishes the batched measurements, if any.
+    fn publish_batch(
+        &self,
+        publisher: &dyn Publish,
+        batch: &mut Vec<TimestampedMeasurement>,
+    ) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let data = PublishData {
+            published_at_ms: latency::unix_time_ms(),
+            ..MeasurementBatch {
+                measurements: std::mem::take(batch),
+            }
+            .into()
+        };
+        publisher.publish(self.entity.topic_name(), data)
     }
 
-    fn run_updater(&self, updater: zmq_sockets::Replier<Linked>) -> Result<()> {
-        while !home_automation_common::shutdown_requested() {
-            let Err(e) = self.update(&updater) else {
+    fn run_updater(&self, updates: &dyn Updates) -> Result<()> {
+        while !self.shutdown.is_requested() {
+            let Err(e) = self.update(updates) else {
                 continue;
             };
             return Err(e).or_else(termination_is_ok);
@@ -219,25 +602,69 @@ impl<E: Entity> App<E> {
 
     /// Read an incoming configuration update and apply it to the entity.
     #[tracing::instrument(parent=None, skip_all)]
-    fn update(&self, updater: &zmq_sockets::Replier<Linked>) -> Result<()> {
-        let data: NamedEntityState = updater
+    fn update(&self, updates: &dyn Updates) -> Result<()> {
+        let Some(data) = updates
             .receive()
-            .context("Failed to receive config update")?;
+            .context("Failed to receive config update")?
+        else {
+            return Ok(());
+        };
 
+        if data.state.is_none() {
+            tracing::debug!("Answering ping of the controller");
+            updates.reply(ResponseCode::ok())?;
+            return Ok(());
+        }
+        let accepted = self
+            .verifier
+            .verify(&data)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| value_range::validate(&data).map_err(Into::into));
+        if let Err(e) = accepted {
+            LogEvent::CommandFailed {
+                kind: "NamedEntityState",
+                entity: Some(&data.entity_name),
+                error: &e,
+            }
+            .emit();
+            updates.reply(Err::<(), _>(e).into())?;
+            return Ok(());
+        }
+
+        if let Some(State::Lifecycle(lifecycle)) = &data.state {
+            let action = lifecycle.action();
+            tracing::info!(?action, "Received lifecycle command {action:?}");
+            self.restart_requested
+                .store(action == Action::Restart, Ordering::SeqCst);
+            updates.reply(ResponseCode::ok())?;
+            self.shutdown.request();
+            return Ok(());
+        }
+
+        let entity_name = data.entity_name.clone();
         let result = self.entity.handle_incoming_data(data);
 
         match &result {
-            Err(e) => tracing::error!(error=%e, "Failed to apply configuration update: {e:#}"),
+            Err(e) => LogEvent::CommandFailed {
+                kind: "NamedEntityState",
+                entity: Some(&entity_name),
+                error: e,
+            }
+            .emit(),
             Ok(None) => {
                 tracing::info!("Successfully applied configuration update without new refresh rate")
             }
             &Ok(Some(new_refresh_rate)) => {
                 *self.refresh_rate.write().expect("non-poisoned RwLock") = new_refresh_rate;
+                self.refresh_rate_changed
+                    .lock()
+                    .expect("non-poisoned Mutex")
+                    .request();
                 tracing::info!("Successfully applied configuration update with new refresh rate {new_refresh_rate:?}");
             }
         }
 
-        let code: ResponseCode = result.into();
-        updater.send(code)
+        updates.reply(result.into())?;
+        Ok(())
     }
 }
@@ -0,0 +1,166 @@
+//! Channels of an entity to the controller, either the channels of a
+//! [`Transport`][home_automation_common::transport::Transport] or a serial link to the serial
+//! gateway of the controller. With a transport, the data can be published over UDP multicast.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use home_automation_common::{
+    protobuf::{EntityDiscoveryCommand, NamedEntityState, PublishData, ResponseCode},
+    serial::SerialLink,
+    transport::{Channel, Pattern, Transport},
+};
+
+/// How long a serial entity waits for the controller to answer its discovery requests.
+const SERIAL_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval in which an entity checks whether it is shut down while waiting for updates.
+const UPDATE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the discovery requests wait for the answer, so an unresponsive controller cannot
+/// block the shutdown.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the final unregister request waits for the answer.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_millis(800);
+
+pub trait Publish {
+    fn publish(&self, topic: &str, data: PublishData) -> Result<()>;
+}
+
+pub trait Updates {
+    /// Waits for the next update, `None` if none arrived in time.
+    fn receive(&self) -> Result<Option<NamedEntityState>>;
+    fn reply(&self, response: ResponseCode) -> Result<()>;
+}
+
+pub trait Discovery {
+    fn request(&self, command: EntityDiscoveryCommand) -> Result<ResponseCode>;
+    /// Sends the final unregister request while the app shuts down.
+    fn disconnect(&self, command: EntityDiscoveryCommand) -> Result<()>;
+}
+
+/// Channels of a connected entity, see [`App::connect`][crate::App::connect].
+pub struct Connection {
+    pub publisher: Box<dyn Publish + Send>,
+    pub updates: Box<dyn Updates + Send>,
+    pub discovery: Box<dyn Discovery + Send>,
+}
+
+impl<C: Channel> Publish for C {
+    fn publish(&self, topic: &str, data: PublishData) -> Result<()> {
+        let channel: &dyn Channel = self;
+        channel
+            .send_message(Some(topic), &data)
+            .context("Failed to publish data")
+    }
+}
+
+impl<C: Channel> Updates for C {
+    fn receive(&self) -> Result<Option<NamedEntityState>> {
+        let channel: &dyn Channel = self;
+        let update = channel
+            .receive_message(Some(UPDATE_POLL_INTERVAL))
+            .context("Failed to receive update")?;
+        Ok(update.map(|(update, _)| update))
+    }
+
+    fn reply(&self, response: ResponseCode) -> Result<()> {
+        let channel: &dyn Channel = self;
+        channel.send_message(None, &response)
+    }
+}
+
+/// Discovery requests through a request channel to the endpoint.
+#[derive(Debug)]
+pub struct ChannelDiscovery {
+    transport: Box<dyn Transport>,
+    endpoint: String,
+    /// Replaced after a failed request because a requester cannot send again before it received
+    /// a reply.
+    channel: Mutex<Box<dyn Channel>>,
+}
+
+impl ChannelDiscovery {
+    pub fn connect(transport: Box<dyn Transport>, endpoint: String) -> Result<Self> {
+        let channel = transport.connect(Pattern::Request, &endpoint)?;
+        Ok(Self {
+            transport,
+            endpoint,
+            channel: Mutex::new(channel),
+        })
+    }
+
+    fn request_within(
+        &self,
+        command: &EntityDiscoveryCommand,
+        timeout: Duration,
+    ) -> Result<ResponseCode> {
+        let mut channel = self.channel.lock().expect("non-poisoned Mutex");
+        let result = channel.request(command, Some(timeout));
+        if result.is_err() {
+            match self.transport.connect(Pattern::Request, &self.endpoint) {
+                Ok(fresh) => *channel = fresh,
+                Err(e) => tracing::error!("Failed to reconnect to entity discovery: {e:#}"),
+            }
+        }
+        result
+    }
+}
+
+impl Discovery for ChannelDiscovery {
+    fn request(&self, command: EntityDiscoveryCommand) -> Result<ResponseCode> {
+        self.request_within(&command, REQUEST_TIMEOUT)
+    }
+
+    fn disconnect(&self, command: EntityDiscoveryCommand) -> Result<()> {
+        tracing::info!("Sending disconnect request {command:?}");
+        self.request_within(&command, DISCONNECT_TIMEOUT)
+            .map(|_| tracing::info!("Requested disconnection successfully."))
+    }
+}
+
+/// Publications, updates and discovery requests share the serial link.
+#[derive(Debug, Clone)]
+pub struct SerialTransport(pub Arc<SerialLink>);
+
+impl SerialTransport {
+    pub fn connection(self) -> Connection {
+        Connection {
+            publisher: Box::new(self.clone()),
+            updates: Box::new(self.clone()),
+            discovery: Box::new(self),
+        }
+    }
+}
+
+impl Publish for SerialTransport {
+    fn publish(&self, _topic: &str, data: PublishData) -> Result<()> {
+        // the gateway publishes on the topics of the registered entity
+        self.0.send(&data)
+    }
+}
+
+impl Updates for SerialTransport {
+    fn receive(&self) -> Result<Option<NamedEntityState>> {
+        self.0.receive(UPDATE_POLL_INTERVAL)
+    }
+
+    fn reply(&self, response: ResponseCode) -> Result<()> {
+        self.0.send(&response)
+    }
+}
+
+impl Discovery for SerialTransport {
+    fn request(&self, command: EntityDiscoveryCommand) -> Result<ResponseCode> {
+        self.0.send(&command)?;
+        self.0
+            .receive(SERIAL_RESPONSE_TIMEOUT)?
+            .with_context(|| format!("No response from serial gateway on {}", self.0.path()))
+    }
+
+    fn disconnect(&self, command: EntityDiscoveryCommand) -> Result<()> {
+        tracing::info!("Sending disconnect request {command:?}");
+        self.request(command).map(drop)
+    }
+}
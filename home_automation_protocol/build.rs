@@ -0,0 +1,10 @@
+use std::io::Result;
+
+fn main() -> Result<()> {
+    let mut config = prost_build::Config::new();
+    config.enable_type_names();
+    // `HashMap` is not available without std, and the generated types must not depend on the
+    // features
+    config.btree_map(["."]);
+    config.compile_protos(&["protobuf/wipmate.proto"], &["protobuf/"])
+}
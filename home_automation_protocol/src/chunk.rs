@@ -0,0 +1,118 @@
+//! Splitting of large responses into [`ResponseChunk`]s and their reassembly.
+//!
+//! The chunks carry the encoded `google.protobuf.Any` of the response, so the reassembled
+//! response is unpacked and type checked like any other [`PayloadEnvelope`].
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{
+    envelope::{Headers, PackedMessage},
+    protobuf::{PayloadEnvelope, ResponseChunk},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkError {
+    /// The chunk belongs to another response or does not follow the previous chunk.
+    Unexpected {
+        response_id: u64,
+        sequence: u32,
+    },
+    Incomplete {
+        received: u32,
+        total: u32,
+    },
+    Decode(prost::DecodeError),
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unexpected {
+                response_id,
+                sequence,
+            } => write!(f, "Unexpected chunk {sequence} of response {response_id}"),
+            Self::Incomplete { received, total } => {
+                write!(f, "Received only {received} of {total} chunks")
+            }
+            Self::Decode(_) => f.write_str("Failed to decode reassembled response"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ChunkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode(source) => Some(source),
+            Self::Unexpected { .. } | Self::Incomplete { .. } => None,
+        }
+    }
+}
+
+/// Splits the message into chunks with at most `chunk_size` bytes of data each.
+pub fn split(message: &PackedMessage, response_id: u64, chunk_size: usize) -> Vec<ResponseChunk> {
+    let data = message.encode_to_vec();
+    let chunk_size = chunk_size.max(1);
+    let total = u32::try_from(data.len().div_ceil(chunk_size)).expect("fewer than 2^32 chunks");
+    data.chunks(chunk_size)
+        .zip(0..)
+        .map(|(data, sequence)| ResponseChunk {
+            response_id,
+            sequence,
+            total,
+            more: sequence + 1 < total,
+            data: data.to_vec(),
+        })
+        .collect()
+}
+
+/// Collects the chunks of a single response in the order of their sequence.
+#[derive(Debug, Default)]
+pub struct ChunkAssembler {
+    response_id: u64,
+    received: u32,
+    total: u32,
+    more: bool,
+    data: Vec<u8>,
+}
+
+impl ChunkAssembler {
+    pub fn push(&mut self, chunk: ResponseChunk) -> Result<(), ChunkError> {
+        let first = self.received == 0;
+        if chunk.sequence != self.received
+            || (!first && (chunk.response_id != self.response_id || chunk.total != self.total))
+        {
+            return Err(ChunkError::Unexpected {
+                response_id: chunk.response_id,
+                sequence: chunk.sequence,
+            });
+        }
+        self.response_id = chunk.response_id;
+        self.total = chunk.total;
+        self.more = chunk.more;
+        self.received += 1;
+        self.data.extend_from_slice(&chunk.data);
+        Ok(())
+    }
+
+    /// Response id and sequence of the chunk to request next, `None` once the last one arrived.
+    pub fn next_chunk(&self) -> Option<(u64, u32)> {
+        self.more.then_some((self.response_id, self.received))
+    }
+
+    /// Returns the reassembled response in an envelope without headers.
+    pub fn finish(self) -> Result<PayloadEnvelope, ChunkError> {
+        if self.more || self.received == 0 || self.received != self.total {
+            return Err(ChunkError::Incomplete {
+                received: self.received,
+                total: self.total,
+            });
+        }
+        let payload = prost::Message::decode(&*self.data).map_err(ChunkError::Decode)?;
+        Ok(PayloadEnvelope {
+            headers: Headers::default(),
+            payload: Some(payload),
+        })
+    }
+}
@@ -0,0 +1,145 @@
+use alloc::string::String;
+use core::fmt;
+
+use crate::protobuf::PayloadEnvelope;
+
+/// Headers of a [`PayloadEnvelope`].
+pub type Headers = alloc::collections::BTreeMap<String, String>;
+
+/// Errors that occur while wrapping a message into a [`PayloadEnvelope`] or unwrapping it again.
+#[derive(Debug)]
+pub enum EnvelopeError {
+    Encode {
+        type_name: &'static str,
+        source: prost::EncodeError,
+    },
+    DecodeEnvelope(prost::DecodeError),
+    MissingPayload,
+    TypeMismatch {
+        expected: String,
+        actual: String,
+    },
+    DecodePayload {
+        type_name: &'static str,
+        source: prost::DecodeError,
+    },
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Encode { type_name, .. } => write!(f, "Failed to encode payload {type_name}"),
+            Self::DecodeEnvelope(_) => f.write_str("Failed to decode envelope"),
+            Self::MissingPayload => f.write_str("Missing payload in envelope"),
+            Self::TypeMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "Payload type mismatch: expected {expected} but got {actual}"
+                )
+            }
+            Self::DecodePayload { type_name, .. } => {
+                write!(f, "Failed to decode payload {type_name}")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EnvelopeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Encode { source, .. } => Some(source),
+            Self::DecodeEnvelope(source) | Self::DecodePayload { source, .. } => Some(source),
+            Self::MissingPayload | Self::TypeMismatch { .. } => None,
+        }
+    }
+}
+
+/// A payload that is encoded once and can then be sent any number of times, e.g. a reply that is
+/// shared by many clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedMessage(prost_types::Any);
+
+impl PackedMessage {
+    pub fn new<M>(message: &M) -> Result<Self, EnvelopeError>
+    where
+        M: prost::Name,
+    {
+        prost_types::Any::from_msg(message)
+            .map(Self)
+            .map_err(|source| EnvelopeError::Encode {
+                type_name: core::any::type_name::<M>(),
+                source,
+            })
+    }
+
+    pub fn type_url(&self) -> &str {
+        &self.0.type_url
+    }
+
+    /// Size of the encoded message, e.g. to decide whether it is split into chunks.
+    pub fn encoded_len(&self) -> usize {
+        prost::Message::encoded_len(&self.0)
+    }
+
+    pub(crate) fn encode_to_vec(&self) -> alloc::vec::Vec<u8> {
+        prost::Message::encode_to_vec(&self.0)
+    }
+}
+
+impl PayloadEnvelope {
+    /// Wraps the message into a new envelope with the given headers.
+    pub fn pack<M>(message: &M, headers: Headers) -> Result<Self, EnvelopeError>
+    where
+        M: prost::Name,
+    {
+        Ok(Self::pack_encoded(PackedMessage::new(message)?, headers))
+    }
+
+    /// Wraps the already encoded message into a new envelope with the given headers.
+    pub fn pack_encoded(message: PackedMessage, headers: Headers) -> Self {
+        Self {
+            headers,
+            payload: Some(message.0),
+        }
+    }
+
+    /// Decodes an envelope from its wire representation.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EnvelopeError> {
+        use prost::Message as _;
+        Self::decode(bytes).map_err(EnvelopeError::DecodeEnvelope)
+    }
+
+    /// Checks whether the envelope contains a message of the given type.
+    pub fn contains<M: prost::Name>(&self) -> bool {
+        self.payload.as_ref().is_some_and(|payload| {
+            // the type URL may be prefixed with an arbitrary domain, only the full name must match
+            let actual_name = payload
+                .type_url
+                .rsplit_once('/')
+                .map_or(&*payload.type_url, |(_, name)| name);
+            actual_name == M::full_name()
+        })
+    }
+
+    /// Extracts the contained message, checking that it has the expected type.
+    pub fn unpack<M>(self) -> Result<M, EnvelopeError>
+    where
+        M: prost::Name + Default,
+    {
+        let expected_type = self.contains::<M>();
+        let payload = self.payload.ok_or(EnvelopeError::MissingPayload)?;
+        if !expected_type {
+            return Err(EnvelopeError::TypeMismatch {
+                expected: M::type_url(),
+                actual: payload.type_url,
+            });
+        }
+        payload
+            .to_msg()
+            .map_err(|source| EnvelopeError::DecodePayload {
+                type_name: core::any::type_name::<M>(),
+                source,
+            })
+    }
+}
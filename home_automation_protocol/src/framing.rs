@@ -0,0 +1,112 @@
+//! Frames to send encoded [`PayloadEnvelope`][crate::protobuf::PayloadEnvelope]s over a byte
+//! stream like a serial port.
+//!
+//! A frame consists of the start byte [`START`], the length of the payload as little endian
+//! `u16`, the payload and its CRC-8 checksum. The receiver resynchronizes on the next start byte
+//! after a corrupted frame.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// First byte of every frame.
+pub const START: u8 = 0xA5;
+/// Longest payload of a frame, longer lengths indicate a corrupted frame.
+pub const MAX_PAYLOAD_LEN: usize = 1024;
+const HEADER_LEN: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    TooLong(usize),
+    Checksum { expected: u8, actual: u8 },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooLong(len) => {
+                write!(
+                    f,
+                    "Frame of {len} bytes exceeds the maximum of {MAX_PAYLOAD_LEN} bytes"
+                )
+            }
+            Self::Checksum { expected, actual } => {
+                write!(
+                    f,
+                    "Frame checksum mismatch: expected {expected:#04x} but got {actual:#04x}"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+/// CRC-8 with polynomial `0x07`.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x07
+            }
+        })
+    })
+}
+
+/// Wraps the payload into a frame.
+pub fn encode_frame(payload: &[u8]) -> Result<Vec<u8>, FrameError> {
+    let len = u16::try_from(payload.len())
+        .ok()
+        .filter(|&len| usize::from(len) <= MAX_PAYLOAD_LEN)
+        .ok_or(FrameError::TooLong(payload.len()))?;
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len() + 1);
+    frame.push(START);
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame.push(crc8(payload));
+    Ok(frame)
+}
+
+/// Collects the received bytes and splits them into the payloads of the frames.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the payload of the next complete frame, or `None` if more bytes are needed.
+    ///
+    /// After an error, the decoder skips to the next start byte, so it can be called again.
+    pub fn next_frame(&mut self) -> Option<Result<Vec<u8>, FrameError>> {
+        // drop the garbage before the start of the frame
+        let start = self
+            .buffer
+            .iter()
+            .position(|&byte| byte == START)
+            .unwrap_or(self.buffer.len());
+        self.buffer.drain(..start);
+
+        let header = self.buffer.get(..HEADER_LEN)?;
+        let len = usize::from(u16::from_le_bytes([header[1], header[2]]));
+        if len > MAX_PAYLOAD_LEN {
+            self.buffer.remove(0);
+            return Some(Err(FrameError::TooLong(len)));
+        }
+        let &actual = self.buffer.get(HEADER_LEN + len)?;
+        let expected = crc8(&self.buffer[HEADER_LEN..HEADER_LEN + len]);
+        if expected != actual {
+            // the start byte might have been part of the payload of a lost frame
+            self.buffer.remove(0);
+            return Some(Err(FrameError::Checksum { expected, actual }));
+        }
+        let payload = self.buffer[HEADER_LEN..HEADER_LEN + len].to_vec();
+        self.buffer.drain(..HEADER_LEN + len + 1);
+        Some(Ok(payload))
+    }
+}
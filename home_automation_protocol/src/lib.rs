@@ -0,0 +1,532 @@
+//! Messages of the home automation system without the transport.
+//!
+//! Besides the generated [`protobuf`] messages and their builders, it contains the [`topic`] names,
+//! the [`envelope`] the messages are sent in, the [`chunk`]s of large responses and the
+//! [`framing`] for serial links. Without the default `std` feature, it only needs
+//! `alloc`, so firmware using a different transport can still produce wire-compatible messages.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod chunk;
+pub mod envelope;
+pub mod framing;
+pub mod topic;
+
+pub mod protobuf {
+    use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+
+    include!(concat!(env!("OUT_DIR"), "/wipmate.rs"));
+
+    impl<T, E: core::fmt::Display> From<Result<T, E>> for ResponseCode {
+        fn from(value: Result<T, E>) -> Self {
+            match value {
+                Ok(_) => Self::ok(),
+                Err(e) => ResponseCode {
+                    code: response_code::Code::Error.into(),
+                    message: format!("{e:#}"),
+                    controller_time_ms: 0,
+                    batch_size: 0,
+                    heartbeat_interval_ms: 0,
+                },
+            }
+        }
+    }
+
+    impl ResponseCode {
+        pub fn ok() -> Self {
+            ResponseCode {
+                code: response_code::Code::Ok.into(),
+                message: String::new(),
+                controller_time_ms: 0,
+                batch_size: 0,
+                heartbeat_interval_ms: 0,
+            }
+        }
+    }
+
+    impl From<SensorMeasurement> for PublishData {
+        fn from(m: SensorMeasurement) -> Self {
+            Self {
+                value: Some(publish_data::Value::Measurement(m)),
+                published_at_ms: 0,
+            }
+        }
+    }
+
+    impl From<MeasurementBatch> for PublishData {
+        fn from(batch: MeasurementBatch) -> Self {
+            Self {
+                value: Some(publish_data::Value::Batch(batch)),
+                published_at_ms: 0,
+            }
+        }
+    }
+
+    impl From<ActuatorState> for PublishData {
+        fn from(m: ActuatorState) -> Self {
+            Self {
+                value: Some(publish_data::Value::ActuatorState(m)),
+                published_at_ms: 0,
+            }
+        }
+    }
+
+    impl From<EntityDescription> for PublishData {
+        fn from(description: EntityDescription) -> Self {
+            Self {
+                value: Some(publish_data::Value::Description(description)),
+                published_at_ms: 0,
+            }
+        }
+    }
+
+    impl EntityDescription {
+        /// Range of the value, `None` if it is unbounded.
+        pub fn range(&self) -> Option<(f32, f32)> {
+            (self.min != 0.0 || self.max != 0.0).then_some((self.min, self.max))
+        }
+
+        /// Whether the entity accepts the payload, see [`capabilities`](crate::capabilities).
+        pub fn accepts(&self, capability: &str) -> bool {
+            self.capabilities.iter().any(|c| c == capability)
+        }
+    }
+
+    impl SensorMeasurement {
+        pub fn temperature(celsius: f32) -> Self {
+            Self {
+                value: Some(sensor_measurement::Value::Temperature(
+                    TemperatureSensorMeasurement {
+                        temperature: celsius,
+                    },
+                )),
+                unit: "°C".to_owned(),
+            }
+        }
+
+        pub fn humidity(percent: f32) -> Self {
+            Self {
+                value: Some(sensor_measurement::Value::Humidity(
+                    HumiditySensorMeasurement { humidity: percent },
+                )),
+                unit: "%".to_owned(),
+            }
+        }
+    }
+
+    impl ActuatorState {
+        pub fn light(brightness: f32) -> Self {
+            Self {
+                state: Some(actuator_state::State::Light(LightActuatorState {
+                    brightness,
+                })),
+            }
+        }
+
+        pub fn air_conditioning(on: bool) -> Self {
+            Self {
+                state: Some(actuator_state::State::AirConditioning(
+                    AirConditioningActuatorState { on },
+                )),
+            }
+        }
+    }
+
+    impl NamedEntityState {
+        pub fn actuator(entity_name: impl Into<String>, value: ActuatorState) -> Self {
+            Self {
+                entity_name: entity_name.into(),
+                signature: None,
+                state: Some(named_entity_state::State::ActuatorState(value)),
+            }
+        }
+
+        /// Message without payload to check whether the back-channel of an entity is alive.
+        pub fn ping(entity_name: impl Into<String>) -> Self {
+            Self {
+                entity_name: entity_name.into(),
+                signature: None,
+                state: None,
+            }
+        }
+
+        pub fn lifecycle(
+            entity_name: impl Into<String>,
+            action: lifecycle_command::Action,
+        ) -> Self {
+            Self {
+                entity_name: entity_name.into(),
+                signature: None,
+                state: Some(named_entity_state::State::Lifecycle(LifecycleCommand {
+                    action: action.into(),
+                })),
+            }
+        }
+
+        /// Measurement a simulated sensor publishes instead of random values.
+        pub fn measurement(entity_name: impl Into<String>, measurement: SensorMeasurement) -> Self {
+            Self {
+                entity_name: entity_name.into(),
+                signature: None,
+                state: Some(named_entity_state::State::Measurement(measurement)),
+            }
+        }
+
+        pub fn frequency(
+            entity_name: impl Into<String>,
+            frequency: impl Into<SensorConfiguration>,
+        ) -> Self {
+            Self {
+                entity_name: entity_name.into(),
+                signature: None,
+                state: Some(named_entity_state::State::SensorConfiguration(
+                    frequency.into(),
+                )),
+            }
+        }
+
+        /// Copy of the command for another entity.
+        ///
+        /// The signature covers the entity name, so the copy is unsigned.
+        pub fn addressed_to(&self, entity_name: impl Into<String>) -> Self {
+            Self {
+                entity_name: entity_name.into(),
+                signature: None,
+                ..self.clone()
+            }
+        }
+    }
+
+    impl EntityDiscoveryCommand {
+        /// Command of an entity without session and authentication, see
+        /// [`with_session_id`](Self::with_session_id) and
+        /// [`with_auth_token`](Self::with_auth_token).
+        pub fn new(
+            entity_type: entity_discovery_command::EntityType,
+            entity_name: impl Into<String>,
+            command: impl Into<entity_discovery_command::Command>,
+        ) -> Self {
+            Self {
+                entity_type: entity_type.into(),
+                entity_name: entity_name.into(),
+                session_id: 0,
+                auth_token: String::new(),
+                command: Some(command.into()),
+            }
+        }
+
+        pub fn with_session_id(mut self, session_id: u64) -> Self {
+            self.session_id = session_id;
+            self
+        }
+
+        pub fn with_auth_token(mut self, auth_token: impl Into<String>) -> Self {
+            self.auth_token = auth_token.into();
+            self
+        }
+    }
+
+    impl From<entity_discovery_command::Registration> for entity_discovery_command::Command {
+        fn from(registration: entity_discovery_command::Registration) -> Self {
+            Self::Register(registration)
+        }
+    }
+
+    impl From<entity_discovery_command::Heartbeat> for entity_discovery_command::Command {
+        fn from(heartbeat: entity_discovery_command::Heartbeat) -> Self {
+            Self::Heartbeat(heartbeat)
+        }
+    }
+
+    impl core::fmt::Display for entity_discovery_command::EntityType {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            f.write_str(match self {
+                Self::Actuator => "Actuator",
+                Self::Sensor => "Sensor",
+            })
+        }
+    }
+
+    impl ClientApiCommand {
+        pub fn system_state_query() -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Query(SystemStateQuery::default())),
+                ..Default::default()
+            }
+        }
+
+        /// Query the state of the entities with the given tag only.
+        pub fn tagged_system_state_query(tag: impl Into<String>) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Query(SystemStateQuery {
+                    tag: tag.into(),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }
+        }
+
+        /// Query that is answered once the state differs from the known generation or the
+        /// timeout expired.
+        pub fn wait_for_change(known_generation: u64, timeout: core::time::Duration) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Query(SystemStateQuery {
+                    wait_timeout_ms: timeout.as_millis().try_into().unwrap_or(u32::MAX),
+                    known_generation,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn set_tags(entity_name: impl Into<String>, tags: Vec<String>) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::SetTags(EntityTags {
+                    entity_name: entity_name.into(),
+                    tags,
+                })),
+                ..Default::default()
+            }
+        }
+
+        /// Send the command to all entities with the given tag.
+        pub fn tagged_action(tag: impl Into<String>, command: NamedEntityState) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::TaggedAction(TaggedCommand {
+                    tag: tag.into(),
+                    command: Some(command),
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn named_entity_state(named_entity_state: NamedEntityState) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Action(named_entity_state)),
+                ..Default::default()
+            }
+        }
+
+        pub fn export_configuration(admin_token: impl Into<String>) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::ExportConfiguration(ConfigurationExport {
+                    admin_token: admin_token.into(),
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn import_configuration(
+            configuration_json: impl Into<String>,
+            admin_token: impl Into<String>,
+        ) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::ImportConfiguration(ConfigurationImport {
+                    configuration_json: configuration_json.into(),
+                    admin_token: admin_token.into(),
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn dry_run_scene(scene: impl Into<String>) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::DryRun(AutomationDryRun {
+                    target: Some(automation_dry_run::Target::Scene(scene.into())),
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn dry_run_rules() -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::DryRun(AutomationDryRun {
+                    target: Some(automation_dry_run::Target::Rules(())),
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn admin(token: impl Into<String>, command: admin_command::Command) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Admin(AdminCommand {
+                    token: token.into(),
+                    command: Some(command),
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn tombstone_query() -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Tombstones(TombstoneQuery::default())),
+                ..Default::default()
+            }
+        }
+
+        /// Queries the archived tombstones of the entity including its history.
+        pub fn archive_query(entity_name: impl Into<String>) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Tombstones(TombstoneQuery {
+                    entity_name: entity_name.into(),
+                    include_history: true,
+                })),
+                ..Default::default()
+            }
+        }
+
+        /// Queries the sources that published without being registered.
+        pub fn ghost_query() -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Ghosts(GhostQuery {})),
+                ..Default::default()
+            }
+        }
+
+        /// Requests the chunk of a response that was split into chunks.
+        pub fn next_chunk(response_id: u64, sequence: u32) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::NextChunk(ChunkRequest {
+                    response_id,
+                    sequence,
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn capture_snapshot(admin_token: impl Into<String>) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::CaptureSnapshot(SnapshotCapture {
+                    admin_token: admin_token.into(),
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn restore_snapshot(
+            snapshot_json: impl Into<String>,
+            admin_token: impl Into<String>,
+        ) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::RestoreSnapshot(SnapshotRestore {
+                    snapshot_json: snapshot_json.into(),
+                    admin_token: admin_token.into(),
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn list_rules() -> Self {
+            Self::rule_command(rule_command::Command::List(()))
+        }
+
+        pub fn add_rule(rule: AutomationRule) -> Self {
+            Self::rule_command(rule_command::Command::Add(rule))
+        }
+
+        pub fn remove_rule(name: impl Into<String>) -> Self {
+            Self::rule_command(rule_command::Command::Remove(name.into()))
+        }
+
+        fn rule_command(command: rule_command::Command) -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Rules(RuleCommand {
+                    command: Some(command),
+                })),
+                ..Default::default()
+            }
+        }
+
+        pub fn hello() -> Self {
+            use client_api_command::CommandType;
+            ClientApiCommand {
+                command_type: Some(CommandType::Hello(Hello {
+                    protocol_version: crate::PROTOCOL_VERSION,
+                })),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl Welcome {
+        /// Protocol version and features of this build.
+        pub fn current() -> Self {
+            Self {
+                protocol_version: crate::PROTOCOL_VERSION,
+                features: crate::features::ALL.map(ToOwned::to_owned).to_vec(),
+            }
+        }
+
+        /// Assumed for controllers that predate the handshake and do not understand [`Hello`].
+        pub fn legacy() -> Self {
+            Self {
+                protocol_version: 0,
+                features: Vec::new(),
+            }
+        }
+
+        pub fn supports(&self, feature: &str) -> bool {
+            self.features.iter().any(|f| f == feature)
+        }
+
+        pub fn is_compatible(&self) -> bool {
+            self.protocol_version == crate::PROTOCOL_VERSION
+        }
+    }
+}
+
+/// Version of the client API, incremented on every incompatible protocol change.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional parts of the client API announced by the controller in its
+/// [`Welcome`][protobuf::Welcome].
+pub mod features {
+    pub const CONFIGURATION: &str = "configuration";
+    pub const DRY_RUN: &str = "dry_run";
+    pub const ADMIN: &str = "admin";
+    pub const TAGS: &str = "tags";
+    pub const TOMBSTONES: &str = "tombstones";
+    pub const PERFORMANCE: &str = "performance";
+    pub const RULES: &str = "rules";
+    pub const GHOSTS: &str = "ghosts";
+
+    pub const ALL: [&str; 8] = [
+        CONFIGURATION,
+        DRY_RUN,
+        ADMIN,
+        TAGS,
+        TOMBSTONES,
+        PERFORMANCE,
+        RULES,
+        GHOSTS,
+    ];
+}
+
+/// Payloads an entity accepts on its back-channel according to its
+/// [`EntityDescription`][protobuf::EntityDescription].
+pub mod capabilities {
+    pub const UPDATE_FREQUENCY: &str = "update_frequency";
+    /// Measurements the sensor publishes instead of its own.
+    pub const MEASUREMENT: &str = "measurement";
+    pub const LIGHT: &str = "light";
+    pub const AIR_CONDITIONING: &str = "air_conditioning";
+}
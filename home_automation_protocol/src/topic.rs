@@ -0,0 +1,72 @@
+//! Topics the entities publish their data on.
+//!
+//! The `prefix` separates deployments sharing a network, e.g. `/lab42`, and is empty by default.
+//! It must not end with `/`.
+
+use alloc::{format, string::String};
+
+use crate::protobuf::entity_discovery_command::EntityType;
+
+const ACTUATOR_STATE: &str = "actuator_state";
+const MEASUREMENT: &str = "measurement";
+/// Class of the topics the entities describe themselves on, see
+/// [`EntityDescription`](crate::protobuf::EntityDescription).
+pub const META: &str = "meta";
+
+/// Returns the class of the topic, e.g. `measurement` for `/lab42/measurement/sen_a`.
+pub fn topic_class<'a>(prefix: &str, topic: &'a str) -> Option<&'a str> {
+    let (class, _) = topic
+        .strip_prefix(prefix)?
+        .strip_prefix('/')?
+        .split_once('/')?;
+    Some(class)
+}
+
+/// Returns the type of the entities that publish on the topic, e.g. `Sensor` for
+/// `/lab42/measurement/sen_a`.
+pub fn topic_entity_type(prefix: &str, topic: &str) -> Option<EntityType> {
+    match topic_class(prefix, topic)? {
+        ACTUATOR_STATE => Some(EntityType::Actuator),
+        MEASUREMENT => Some(EntityType::Sensor),
+        _ => None,
+    }
+}
+
+fn entity_name<'a>(prefix: &str, topic: &'a str, class: &str) -> Option<&'a str> {
+    topic
+        .strip_prefix(prefix)?
+        .strip_prefix('/')?
+        .strip_prefix(class)?
+        .strip_prefix('/')
+}
+
+pub fn actuator_name<'a>(prefix: &str, topic: &'a str) -> Option<&'a str> {
+    entity_name(prefix, topic, ACTUATOR_STATE)
+}
+
+pub fn actuator_state_topic(prefix: &str, name: &str) -> String {
+    format!("{prefix}/{ACTUATOR_STATE}/{name}")
+}
+
+pub fn sensor_name<'a>(prefix: &str, topic: &'a str) -> Option<&'a str> {
+    entity_name(prefix, topic, MEASUREMENT)
+}
+
+pub fn sensor_measurement_topic(prefix: &str, name: &str) -> String {
+    format!("{prefix}/{MEASUREMENT}/{name}")
+}
+
+pub fn meta_name<'a>(prefix: &str, topic: &'a str) -> Option<&'a str> {
+    entity_name(prefix, topic, META)
+}
+
+pub fn meta_topic(prefix: &str, name: &str) -> String {
+    format!("{prefix}/{META}/{name}")
+}
+
+pub fn entity_topic(prefix: &str, name: &str, entity_type: EntityType) -> String {
+    match entity_type {
+        EntityType::Actuator => actuator_state_topic(prefix, name),
+        EntityType::Sensor => sensor_measurement_topic(prefix, name),
+    }
+}
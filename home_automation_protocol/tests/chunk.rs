@@ -0,0 +1,77 @@
+use home_automation_protocol::{
+    chunk::{split, ChunkAssembler, ChunkError},
+    envelope::PackedMessage,
+    protobuf::{SensorMeasurement, SystemState},
+};
+
+fn large_state() -> SystemState {
+    SystemState {
+        sensors: (0..100)
+            .map(|i| (format!("sen_{i}"), SensorMeasurement::default()))
+            .collect(),
+        generation: 7,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn reassembles_split_response() {
+    let packed = PackedMessage::new(&large_state()).unwrap();
+    let chunks = split(&packed, 3, 100);
+    assert!(chunks.len() > 1);
+    assert!(chunks.iter().all(|chunk| chunk.data.len() <= 100));
+    assert!(!chunks.last().unwrap().more);
+
+    let mut assembler = ChunkAssembler::default();
+    for chunk in chunks {
+        assembler.push(chunk).unwrap();
+    }
+    assert_eq!(assembler.next_chunk(), None);
+    let state: SystemState = assembler.finish().unwrap().unpack().unwrap();
+    assert_eq!(state, large_state());
+}
+
+#[test]
+fn requests_chunks_in_order() {
+    let packed = PackedMessage::new(&large_state()).unwrap();
+    let mut chunks = split(&packed, 3, 100).into_iter();
+
+    let mut assembler = ChunkAssembler::default();
+    assembler.push(chunks.next().unwrap()).unwrap();
+    assert_eq!(assembler.next_chunk(), Some((3, 1)));
+
+    let skipped = chunks.nth(1).unwrap();
+    assert_eq!(
+        assembler.push(skipped),
+        Err(ChunkError::Unexpected {
+            response_id: 3,
+            sequence: 2
+        })
+    );
+}
+
+#[test]
+fn rejects_chunks_of_other_responses() {
+    let packed = PackedMessage::new(&large_state()).unwrap();
+    let mut assembler = ChunkAssembler::default();
+    assembler.push(split(&packed, 1, 100).remove(0)).unwrap();
+
+    let other = split(&packed, 2, 100).remove(1);
+    assert!(matches!(
+        assembler.push(other),
+        Err(ChunkError::Unexpected { response_id: 2, .. })
+    ));
+    assert!(matches!(
+        assembler.finish(),
+        Err(ChunkError::Incomplete { received: 1, .. })
+    ));
+}
+
+#[test]
+fn small_response_is_a_single_chunk() {
+    let packed = PackedMessage::new(&SystemState::default()).unwrap();
+    let chunks = split(&packed, 1, packed.encoded_len());
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].total, 1);
+    assert!(!chunks[0].more);
+}
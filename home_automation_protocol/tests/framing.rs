@@ -0,0 +1,50 @@
+use home_automation_protocol::framing::{encode_frame, FrameDecoder, FrameError, MAX_PAYLOAD_LEN};
+
+#[test]
+fn decodes_frames_split_across_reads() {
+    let mut bytes = encode_frame(b"hello").unwrap();
+    bytes.extend(encode_frame(b"").unwrap());
+    bytes.extend(encode_frame(&[0xA5; 3]).unwrap());
+
+    let mut decoder = FrameDecoder::default();
+    let mut frames = Vec::new();
+    for chunk in bytes.chunks(2) {
+        decoder.push(chunk);
+        while let Some(frame) = decoder.next_frame() {
+            frames.push(frame.unwrap());
+        }
+    }
+    assert_eq!(frames, [b"hello".to_vec(), Vec::new(), vec![0xA5; 3]]);
+}
+
+#[test]
+fn resynchronizes_after_garbage_and_corruption() {
+    let mut corrupted = encode_frame(b"lost").unwrap();
+    *corrupted.last_mut().unwrap() ^= 0xFF;
+
+    let mut decoder = FrameDecoder::default();
+    decoder.push(&[0x00, 0x42]);
+    decoder.push(&corrupted);
+    decoder.push(&encode_frame(b"kept").unwrap());
+
+    assert!(matches!(
+        decoder.next_frame(),
+        Some(Err(FrameError::Checksum { .. }))
+    ));
+    assert_eq!(decoder.next_frame(), Some(Ok(b"kept".to_vec())));
+    assert_eq!(decoder.next_frame(), None);
+}
+
+#[test]
+fn rejects_oversized_frames() {
+    let payload = vec![0; MAX_PAYLOAD_LEN + 1];
+    assert_eq!(
+        encode_frame(&payload),
+        Err(FrameError::TooLong(MAX_PAYLOAD_LEN + 1))
+    );
+
+    let mut decoder = FrameDecoder::default();
+    decoder.push(&[0xA5, 0xFF, 0xFF]);
+    assert_eq!(decoder.next_frame(), Some(Err(FrameError::TooLong(0xFFFF))));
+    assert_eq!(decoder.next_frame(), None);
+}
@@ -0,0 +1,53 @@
+use home_automation_protocol::{
+    protobuf::entity_discovery_command::EntityType,
+    topic::{
+        actuator_name, actuator_state_topic, entity_topic, meta_name, meta_topic,
+        sensor_measurement_topic, sensor_name, topic_class, topic_entity_type,
+    },
+};
+
+#[test]
+fn builds_and_parses_topics_without_prefix() {
+    let topic = sensor_measurement_topic("", "sen_a");
+    assert_eq!(topic, "/measurement/sen_a");
+    assert_eq!(topic_class("", &topic), Some("measurement"));
+    assert_eq!(sensor_name("", &topic), Some("sen_a"));
+    assert_eq!(actuator_name("", &topic), None);
+}
+
+#[test]
+fn builds_and_parses_topics_with_prefix() {
+    let topic = actuator_state_topic("/lab42", "act_b");
+    assert_eq!(topic, "/lab42/actuator_state/act_b");
+    assert_eq!(entity_topic("/lab42", "act_b", EntityType::Actuator), topic);
+    assert_eq!(topic_class("/lab42", &topic), Some("actuator_state"));
+    assert_eq!(actuator_name("/lab42", &topic), Some("act_b"));
+    assert_eq!(actuator_name("/lab43", &topic), None);
+    assert_eq!(topic_class("", &topic), Some("lab42"));
+}
+
+#[test]
+fn derives_entity_type_from_topic() {
+    assert_eq!(
+        topic_entity_type("/lab42", "/lab42/measurement/sen_a"),
+        Some(EntityType::Sensor)
+    );
+    assert_eq!(
+        topic_entity_type("/lab42", "/lab42/actuator_state/act_b"),
+        Some(EntityType::Actuator)
+    );
+    assert_eq!(topic_entity_type("/lab42", "/lab42/heartbeat/act_b"), None);
+    assert_eq!(
+        topic_entity_type("/lab42", "/lab43/measurement/sen_a"),
+        None
+    );
+}
+
+#[test]
+fn builds_and_parses_meta_topics() {
+    let topic = meta_topic("/lab42", "sen_a");
+    assert_eq!(topic, "/lab42/meta/sen_a");
+    assert_eq!(meta_name("/lab42", &topic), Some("sen_a"));
+    assert_eq!(sensor_name("/lab42", &topic), None);
+    assert_eq!(topic_entity_type("/lab42", &topic), None);
+}